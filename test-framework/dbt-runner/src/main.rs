@@ -0,0 +1,150 @@
+//! The host program for tests of dynamic libraries (`cdylib` and `dylib` crates). It loads
+//! the library given via the `DBT_LIBRARY` environment variable at runtime, like
+//! `dlopen`/`LoadLibrary` based plugin systems do, and calls the function the library exports
+//! as `dbt_main`:
+//!
+//! ```ignore
+//! #[no_mangle]
+//! pub extern "C" fn dbt_main() {
+//!     let v = vec![1, 2, 3];
+//!     dbt_support::zzz(); // #break
+//! }
+//! ```
+//!
+//! dbt compiles this file on its own, so it must not use any other crates.
+
+use std::{ffi::OsString, process::exit};
+
+const LIBRARY_VARIABLE: &str = "DBT_LIBRARY";
+const ENTRY_POINT: &str = "dbt_main";
+
+fn main() {
+    let library = match std::env::var_os(LIBRARY_VARIABLE) {
+        Some(library) => library,
+        None => {
+            eprintln!("{} is not set, is the test run via dbt?", LIBRARY_VARIABLE);
+            exit(1);
+        }
+    };
+
+    let entry_point = match load(&library) {
+        Ok(entry_point) => entry_point,
+        Err(e) => {
+            eprintln!("Could not load {}: {}", library.to_string_lossy(), e);
+            exit(1);
+        }
+    };
+
+    entry_point();
+}
+
+fn load(library: &OsString) -> Result<extern "C" fn(), String> {
+    let handle = platform::load_library(library)?;
+    let symbol = platform::find_symbol(handle, ENTRY_POINT)?;
+
+    // SAFETY: the test library is expected to export `dbt_main` as `extern "C" fn()`
+    Ok(unsafe { std::mem::transmute::<*mut std::ffi::c_void, extern "C" fn()>(symbol) })
+}
+
+#[cfg(unix)]
+mod platform {
+    use std::{
+        ffi::{c_char, c_int, c_void, CStr, CString, OsString},
+        os::unix::ffi::OsStrExt,
+    };
+
+    const RTLD_NOW: c_int = 2;
+
+    #[cfg_attr(target_os = "linux", link(name = "dl"))]
+    extern "C" {
+        fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+        fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+        fn dlerror() -> *const c_char;
+    }
+
+    pub fn load_library(path: &OsString) -> Result<*mut c_void, String> {
+        let path = CString::new(path.as_bytes()).map_err(|e| e.to_string())?;
+
+        // SAFETY: `path` is a valid C string
+        let handle = unsafe { dlopen(path.as_ptr(), RTLD_NOW) };
+
+        if handle.is_null() {
+            Err(last_error())
+        } else {
+            Ok(handle)
+        }
+    }
+
+    pub fn find_symbol(handle: *mut c_void, name: &str) -> Result<*mut c_void, String> {
+        let name = CString::new(name).map_err(|e| e.to_string())?;
+
+        // SAFETY: `handle` was returned by `dlopen` and `name` is a valid C string
+        let symbol = unsafe { dlsym(handle, name.as_ptr()) };
+
+        if symbol.is_null() {
+            Err(last_error())
+        } else {
+            Ok(symbol)
+        }
+    }
+
+    fn last_error() -> String {
+        // SAFETY: `dlerror` returns null or a valid C string
+        let error = unsafe { dlerror() };
+
+        if error.is_null() {
+            "unknown error".to_string()
+        } else {
+            // SAFETY: see above
+            unsafe { CStr::from_ptr(error) }
+                .to_string_lossy()
+                .into_owned()
+        }
+    }
+}
+
+#[cfg(windows)]
+mod platform {
+    use std::{
+        ffi::{c_char, c_void, CString, OsString},
+        os::windows::ffi::OsStrExt,
+    };
+
+    #[link(name = "kernel32")]
+    extern "system" {
+        fn LoadLibraryW(filename: *const u16) -> *mut c_void;
+        fn GetProcAddress(module: *mut c_void, name: *const c_char) -> *mut c_void;
+        fn GetLastError() -> u32;
+    }
+
+    pub fn load_library(path: &OsString) -> Result<*mut c_void, String> {
+        let path: Vec<u16> = path.encode_wide().chain(Some(0)).collect();
+
+        // SAFETY: `path` is a null-terminated wide string
+        let module = unsafe { LoadLibraryW(path.as_ptr()) };
+
+        if module.is_null() {
+            Err(last_error())
+        } else {
+            Ok(module)
+        }
+    }
+
+    pub fn find_symbol(module: *mut c_void, name: &str) -> Result<*mut c_void, String> {
+        let name = CString::new(name).map_err(|e| e.to_string())?;
+
+        // SAFETY: `module` was returned by `LoadLibraryW` and `name` is a valid C string
+        let symbol = unsafe { GetProcAddress(module, name.as_ptr()) };
+
+        if symbol.is_null() {
+            Err(last_error())
+        } else {
+            Ok(symbol)
+        }
+    }
+
+    fn last_error() -> String {
+        // SAFETY: always safe to call
+        format!("error code {}", unsafe { GetLastError() })
+    }
+}
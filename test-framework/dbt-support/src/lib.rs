@@ -0,0 +1,87 @@
+//! Helpers for dbt test programs, available in every test program as `dbt_support` without
+//! declaring a dependency on it:
+//!
+//! ```ignore
+//! use dbt_support::{zzz, Point};
+//!
+//! fn main() {
+//!     let p = Point { x: 1, y: 2 };
+//!     zzz(); // #break
+//! }
+//! ```
+//!
+//! dbt compiles this file on its own, so it must not use any other crates.
+
+use std::str::FromStr;
+
+/// A function to put breakpoints on, via `zzz(); // #break`. It is never inlined, so the
+/// caller's frame (and the variables in it) is still around when the breakpoint is hit.
+#[inline(never)]
+pub fn zzz() {}
+
+/// Like [zzz], but also keeps `value` alive (and in memory) until the breakpoint is hit, so
+/// that it is not optimized out in release builds.
+#[inline(never)]
+pub fn break_with<T: ?Sized>(value: &T) {
+    black_box(value);
+}
+
+/// Keeps the compiler from optimizing `value` away
+#[inline(never)]
+pub fn black_box<T>(value: T) -> T {
+    std::hint::black_box(value)
+}
+
+/// The value of the `#params` parameter `name` for the current instantiation of the test,
+/// passed by dbt via the `DBT_PARAM_<NAME>` environment variable.
+///
+/// Panics if the parameter is not set or can't be parsed, as the test could not do anything
+/// meaningful then.
+pub fn param<T: FromStr>(name: &str) -> T {
+    let variable = format!("DBT_PARAM_{}", name.to_ascii_uppercase());
+
+    match std::env::var(&variable) {
+        Ok(value) => match value.parse() {
+            Ok(value) => value,
+            Err(_) => panic!("could not parse `{}` from {}", value, variable),
+        },
+        Err(_) => panic!("{} is not set, is the test run via dbt?", variable),
+    }
+}
+
+/// A plain struct with named fields
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Point {
+    pub x: i32,
+    pub y: i32,
+}
+
+/// A tuple struct
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Pair(pub u8, pub u16);
+
+/// A zero-sized struct
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Unit;
+
+/// An enum without data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    Red,
+    Green,
+    Blue,
+}
+
+/// An enum with a variant of each kind
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Shape {
+    Empty,
+    Circle(u32),
+    Rectangle { width: u32, height: u32 },
+    Named(String),
+}
+
+/// `len` consecutive numbers starting at 0, e.g. for testing how collections are displayed
+pub fn numbers(len: usize) -> Vec<u32> {
+    (0..len as u32).collect()
+}
@@ -0,0 +1,275 @@
+//! C programs as behavioral baselines for Rust tests. A `.c` file next to the `.rs` file of a
+//! test with `#c-baseline` (e.g. `src/main.c` next to `src/main.rs`) is compiled with the
+//! system's C compiler and run as a test of its own, with the Rust test's script minus its checks. The values
+//! the two print via `#print` are then compared, which shows whether a debugger renders the
+//! Rust program differently from its C equivalent.
+
+use std::{ffi::OsString, path::Path, process::Command};
+
+use anyhow::{bail, Context};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::{
+    cargo_test_directory::BuildConfig, consistency, prettify_path, test_result::TestResult,
+};
+
+/// The environment variable naming the C compiler, `cc` if not set
+const COMPILER_VARIABLE: &str = "CC";
+
+/// The name of the executable the baseline of the test program `executable_name` is
+/// compiled to, e.g. `t1-baseline` for `t1`
+pub fn executable_name(executable_name: &OsString) -> OsString {
+    let executable_name = Path::new(executable_name);
+    let mut baseline_name = executable_name.file_stem().unwrap().to_owned();
+    baseline_name.push("-baseline");
+
+    match executable_name.extension() {
+        Some(extension) => {
+            baseline_name.push(".");
+            baseline_name.push(extension);
+            baseline_name
+        }
+        None => baseline_name,
+    }
+}
+
+/// Compiles the C program at `source_path` with debuginfo and `opt_level` (the one of the
+/// Cargo profile) to `executable_path`. Compilers that take GCC's arguments are supported,
+/// so on Windows `CC` has to be e.g. `clang`.
+pub fn compile(
+    source_path: &Path,
+    executable_path: &Path,
+    build: &BuildConfig,
+    opt_level: &str,
+) -> anyhow::Result<()> {
+    let compiler = std::env::var_os(COMPILER_VARIABLE).unwrap_or_else(|| "cc".into());
+    let mut command = Command::new(&compiler);
+
+    let opt_level = match opt_level {
+        // GCC has no `-Oz` before version 12
+        "z" => "s".to_string(),
+        opt_level => opt_level.to_string(),
+    };
+
+    command
+        .arg("-g")
+        .arg(format!("-O{}", opt_level))
+        .arg("-o")
+        .arg(executable_path)
+        .arg(source_path);
+
+    if let Some(target) = &build.target {
+        // Only understood by Clang
+        command.arg(format!("--target={}", target));
+    }
+
+    let output = command.output().with_context(|| {
+        format!(
+            "Could not run `{}` to compile the C baseline {} (set {} to use another compiler)",
+            compiler.to_string_lossy(),
+            prettify_path(source_path),
+            COMPILER_VARIABLE
+        )
+    })?;
+
+    if !output.status.success() {
+        bail!(
+            "Could not compile the C baseline {}:\n{}",
+            prettify_path(source_path),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(())
+}
+
+/// A `#print` statement whose value was rendered differently for the Rust test and its C
+/// baseline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Difference {
+    /// See [TestResult::label_with_debugger]
+    pub test: String,
+    pub location: String,
+    pub expression: String,
+    pub rust_output: String,
+    pub baseline_output: String,
+}
+
+/// Compares the values printed by each Rust test with the ones printed by its baseline with
+/// the same debugger, Cargo profile and phase. `baselines` are the pairs of the names of the
+/// baseline and the Rust test. Values are compared like with `--consistency`, by the
+/// literals they contain, see [comparable_literals].
+pub fn differences(test_results: &[TestResult], baselines: &[(&str, &str)]) -> Vec<Difference> {
+    let mut differences = vec![];
+
+    for baseline_result in test_results {
+        let rust_test_name = match baselines
+            .iter()
+            .find(|(baseline, _)| *baseline == &baseline_result.test_name[..])
+        {
+            Some((_, rust_test_name)) => rust_test_name,
+            None => continue,
+        };
+
+        let rust_result = test_results.iter().find(|rust_result| {
+            &rust_result.test_name[..] == *rust_test_name
+                && rust_result.debugger_kind == baseline_result.debugger_kind
+                && rust_result.debugger_version == baseline_result.debugger_version
+                && rust_result.cargo_profile == baseline_result.cargo_profile
+                && rust_result.build == baseline_result.build
+                && rust_result.phase == baseline_result.phase
+        });
+
+        let rust_result = match rust_result {
+            Some(rust_result) => rust_result,
+            None => continue,
+        };
+
+        for rust_value in &rust_result.printed_values {
+            let baseline_value = baseline_result
+                .printed_values
+                .iter()
+                .find(|value| value.line_number == rust_value.line_number);
+
+            if let Some(baseline_value) = baseline_value {
+                if comparable_literals(&rust_value.output)
+                    != comparable_literals(&baseline_value.output)
+                {
+                    differences.push(Difference {
+                        test: rust_result.label_with_debugger(),
+                        location: format!(
+                            "{}:{}",
+                            prettify_path(&rust_result.source_path),
+                            rust_value.line_number.0
+                        ),
+                        expression: rust_value.expression.to_string(),
+                        rust_output: rust_value.output.clone(),
+                        baseline_output: baseline_value.output.clone(),
+                    });
+                }
+            }
+        }
+    }
+
+    differences
+}
+
+/// The literals of a printed value (see [consistency::value_literals]) without the sizes
+/// pretty-printers annotate Rust collections with, e.g. GDB's `Vec(size=3)` or LLDB's
+/// `size=3`, which C arrays don't have
+fn comparable_literals(output: &str) -> Vec<String> {
+    lazy_static! {
+        static ref SIZE_ANNOTATION: Regex =
+            Regex::new(r"\b(?:size|len|length|capacity)\s*=\s*\d+").unwrap();
+    }
+
+    consistency::value_literals(&SIZE_ANNOTATION.replace_all(output, ""))
+}
+
+pub fn print_baseline_report(differences: &[Difference]) {
+    println!();
+
+    if differences.is_empty() {
+        println!("All tests printed the same values as their C baselines.");
+        return;
+    }
+
+    println!("Values printed differently for the Rust tests and their C baselines:");
+
+    for difference in differences {
+        println!();
+        println!(
+            "  `#print {}` at {} in {}",
+            difference.expression, difference.location, difference.test
+        );
+
+        let single_line = |output: &str| output.split_whitespace().collect::<Vec<_>>().join(" ");
+        println!("      Rust: {}", single_line(&difference.rust_output));
+        println!("      C:    {}", single_line(&difference.baseline_output));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsString, sync::Arc};
+
+    use crate::{
+        cargo_test_directory::BuildConfig,
+        debugger::{DebuggerKind, PrintedValue},
+        script::{LineNumber, PhaseConfig},
+        test_result::{Status, TestResult},
+    };
+
+    #[test]
+    fn executable_name() {
+        assert_eq!(
+            super::executable_name(&OsString::from("t1")),
+            OsString::from("t1-baseline")
+        );
+        assert_eq!(
+            super::executable_name(&OsString::from("t1.exe")),
+            OsString::from("t1-baseline.exe")
+        );
+    }
+
+    #[test]
+    fn differences() {
+        let test_result = |test_name: &str, output: &str| TestResult {
+            test_name: test_name.into(),
+            phase: PhaseConfig::Live,
+            debugger_kind: DebuggerKind::Gdb,
+            debugger_version: "12.1".into(),
+            cargo_profile: Arc::from("debug"),
+            build: BuildConfig::default(),
+            status: Box::new(Status::Passed),
+            source_path: test_name.into(),
+            debugger_output_stdout_path: None,
+            debugger_output_stderr_path: None,
+            command_timings: vec![],
+            resource_usage: None,
+            debugger_duration: None,
+            printed_values: vec![PrintedValue {
+                expression: "a".into(),
+                line_number: LineNumber(3),
+                output: output.to_string(),
+            }],
+            matched_checks: vec![],
+            diagnostics: vec![],
+            build_output: String::new(),
+        };
+
+        let baselines = [("ws/t1/src/main.c", "ws/t1/src/main.rs")];
+
+        let same = [
+            test_result("ws/t1/src/main.rs", "$1 = [1, 2]"),
+            test_result("ws/t1/src/main.c", "$1 = {1, 2}"),
+        ];
+        assert!(super::differences(&same, &baselines).is_empty());
+
+        // The sizes pretty-printers add to Rust collections are no difference
+        let annotated = [
+            test_result("ws/t1/src/main.rs", "$1 = Vec(size=2) = {1, 2}"),
+            test_result("ws/t1/src/main.c", "$1 = {1, 2}"),
+        ];
+        assert!(super::differences(&annotated, &baselines).is_empty());
+
+        let lldb_annotated = [
+            test_result(
+                "ws/t1/src/main.rs",
+                "(Vec<i32>) v = size=2 { [0] = 1 [1] = 2 }",
+            ),
+            test_result("ws/t1/src/main.c", "(int[2]) v = ([0] = 1, [1] = 2)"),
+        ];
+        assert!(super::differences(&lldb_annotated, &baselines).is_empty());
+
+        let different = [
+            test_result("ws/t1/src/main.rs", "$1 = [1, 2]"),
+            test_result("ws/t1/src/main.c", "$1 = {1, 3}"),
+        ];
+        let differences = super::differences(&different, &baselines);
+        assert_eq!(differences.len(), 1);
+        assert_eq!(differences[0].location, "ws/t1/src/main.rs:3");
+        assert_eq!(differences[0].baseline_output, "$1 = {1, 3}");
+    }
+}
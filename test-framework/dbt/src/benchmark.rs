@@ -0,0 +1,226 @@
+//! How long the debuggers took for each command in previous runs, persisted via
+//! `--benchmark`. This is used to catch performance regressions, e.g. in pretty printers,
+//! by reporting commands that took much longer than they usually do.
+//!
+//! The commands are timed via the fences placed around each of them in the debuggers'
+//! output, see [crate::debugger::command_timings].
+
+use std::{path::Path, time::Duration};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{prettify_path, test_result::TestResult};
+
+/// The number of samples kept per command
+const MAX_SAMPLES: usize = 20;
+
+/// The number of previous samples needed before a command can be reported as slow
+const MIN_SAMPLES: usize = 3;
+
+/// A command is reported as slow if it took this many times as long as usual...
+const OUTLIER_FACTOR: u64 = 2;
+
+/// ... and at least this many milliseconds longer, so that fast commands aren't reported
+/// because of noise.
+const OUTLIER_MIN_DIFFERENCE_MS: u64 = 50;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Benchmark {
+    #[serde(default)]
+    pub commands: Vec<CommandSamples>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CommandSamples {
+    /// See [TestResult::label_with_debugger]
+    pub test: String,
+    pub line: u32,
+    /// The command as sent to the debugger
+    pub command: String,
+    /// The durations of the most recent runs of the command in milliseconds, oldest first
+    pub samples_ms: Vec<u64>,
+}
+
+/// A command that took much longer than it did in previous runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlowCommand {
+    pub test: String,
+    pub line: u32,
+    pub command: String,
+    pub duration_ms: u64,
+    pub median_ms: u64,
+}
+
+impl Benchmark {
+    /// Loads the samples from `path`, returning an empty benchmark if it does not exist yet.
+    pub fn load(path: &Path) -> anyhow::Result<Benchmark> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Benchmark::default()),
+            Err(e) => bail!(e),
+        };
+
+        toml::from_str(&contents)
+            .with_context(|| format!("while reading benchmark file {}", prettify_path(path)))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Adds the command timings of the given test results and returns the commands that
+    /// took much longer than usual.
+    pub fn add_results(&mut self, test_results: &[TestResult]) -> Vec<SlowCommand> {
+        let mut slow_commands = vec![];
+
+        for test_result in test_results {
+            let test = test_result.label_with_debugger();
+
+            for timing in &test_result.command_timings {
+                slow_commands.extend(self.add_sample(
+                    &test,
+                    timing.line_number.0,
+                    &timing.command,
+                    timing.duration,
+                ));
+            }
+        }
+
+        slow_commands
+    }
+
+    fn add_sample(
+        &mut self,
+        test: &str,
+        line: u32,
+        command: &str,
+        duration: Duration,
+    ) -> Option<SlowCommand> {
+        let index = match self
+            .commands
+            .iter()
+            .position(|c| c.test == test && c.line == line && c.command == command)
+        {
+            Some(index) => index,
+            None => {
+                self.commands.push(CommandSamples {
+                    test: test.to_string(),
+                    line,
+                    command: command.to_string(),
+                    samples_ms: vec![],
+                });
+                self.commands.len() - 1
+            }
+        };
+
+        let samples = &mut self.commands[index].samples_ms;
+        let duration_ms = duration.as_millis() as u64;
+
+        let slow_command = if samples.len() >= MIN_SAMPLES {
+            let mut sorted = samples.clone();
+            sorted.sort_unstable();
+            let median_ms = sorted[sorted.len() / 2];
+
+            if duration_ms >= median_ms * OUTLIER_FACTOR
+                && duration_ms >= median_ms + OUTLIER_MIN_DIFFERENCE_MS
+            {
+                Some(SlowCommand {
+                    test: test.to_string(),
+                    line,
+                    command: command.to_string(),
+                    duration_ms,
+                    median_ms,
+                })
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+
+        samples.push(duration_ms);
+
+        if samples.len() > MAX_SAMPLES {
+            samples.drain(..samples.len() - MAX_SAMPLES);
+        }
+
+        slow_command
+    }
+}
+
+pub fn print_slow_commands(slow_commands: &[SlowCommand]) {
+    if slow_commands.is_empty() {
+        return;
+    }
+
+    println!();
+    println!("Slow commands (compared to previous runs):");
+    println!();
+
+    for slow_command in slow_commands {
+        println!(
+            "  {} ms (usually {} ms): `{}` at line {} of {}",
+            slow_command.duration_ms,
+            slow_command.median_ms,
+            slow_command.command,
+            slow_command.line,
+            slow_command.test
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Benchmark, SlowCommand};
+
+    #[test]
+    fn slow_commands() {
+        let mut benchmark = Benchmark::default();
+
+        let mut add = |command: &str, ms: u64| {
+            benchmark.add_sample("test", 3, command, Duration::from_millis(ms))
+        };
+
+        // Not enough samples yet
+        assert_eq!(add("print v", 100), None);
+        assert_eq!(add("print v", 500), None);
+        assert_eq!(add("print v", 120), None);
+
+        // Twice as slow but only by a few milliseconds
+        assert_eq!(add("print x", 10), None);
+        assert_eq!(add("print x", 10), None);
+        assert_eq!(add("print x", 10), None);
+        assert_eq!(add("print x", 25), None);
+
+        assert_eq!(add("print v", 200), None);
+        assert_eq!(
+            add("print v", 400),
+            Some(SlowCommand {
+                test: "test".to_string(),
+                line: 3,
+                command: "print v".to_string(),
+                duration_ms: 400,
+                median_ms: 200,
+            })
+        );
+
+        assert_eq!(benchmark.commands.len(), 2);
+        assert_eq!(
+            benchmark.commands[0].samples_ms,
+            vec![100, 500, 120, 200, 400]
+        );
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut benchmark = Benchmark::default();
+        benchmark.add_sample("test", 3, "print v", Duration::from_millis(100));
+
+        let toml = toml::to_string(&benchmark).unwrap();
+        assert_eq!(toml::from_str::<Benchmark>(&toml).unwrap(), benchmark);
+    }
+}
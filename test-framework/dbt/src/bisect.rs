@@ -0,0 +1,200 @@
+//! Finding the first nightly toolchain with which a test fails (`dbt bisect`).
+
+use anyhow::bail;
+
+const NIGHTLY_PREFIX: &str = "nightly-";
+
+/// The result of running a test with a given toolchain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Failed,
+    /// The toolchain could not be installed or the test could not be built with it
+    Unusable,
+}
+
+/// Returns the names of all nightly toolchains from `from` to `to` (inclusive), which
+/// both have to be of the form `nightly-YYYY-MM-DD`.
+pub fn nightly_toolchains(from: &str, to: &str) -> anyhow::Result<Vec<String>> {
+    let from = parse_nightly(from)?;
+    let to = parse_nightly(to)?;
+
+    if from >= to {
+        bail!("The toolchain to bisect from must be older than the one to bisect to");
+    }
+
+    Ok((from..=to)
+        .map(|day| {
+            let (year, month, day) = civil_from_days(day);
+            format!("{}{:04}-{:02}-{:02}", NIGHTLY_PREFIX, year, month, day)
+        })
+        .collect())
+}
+
+/// Parses `nightly-YYYY-MM-DD` into the number of days since 1970-01-01
+fn parse_nightly(toolchain: &str) -> anyhow::Result<i64> {
+    let date = toolchain.strip_prefix(NIGHTLY_PREFIX).and_then(|date| {
+        let mut components = date.splitn(3, '-').map(|c| c.parse::<i64>().ok());
+        Some((
+            components.next()??,
+            components.next()??,
+            components.next()??,
+        ))
+    });
+
+    match date {
+        Some((year, month, day)) if (1..=12).contains(&month) && (1..=31).contains(&day) => {
+            let days = days_from_civil(year, month, day);
+
+            // Catches e.g. February 30th
+            if civil_from_days(days) != (year, month, day) {
+                bail!("`{}` is not a valid date", toolchain);
+            }
+
+            Ok(days)
+        }
+        _ => bail!(
+            "Expected a toolchain of the form `nightly-YYYY-MM-DD`, found `{}`",
+            toolchain
+        ),
+    }
+}
+
+// The following two functions convert between dates and days since 1970-01-01.
+// See http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Binary-searches `toolchains` (ordered from oldest to newest) for the first one for
+/// which `test` fails. The test has to pass with the first usable toolchain and fail with
+/// the last one. Returns the last passing and the first failing toolchain.
+pub fn bisect(
+    toolchains: &[String],
+    mut test: impl FnMut(&str) -> anyhow::Result<Outcome>,
+) -> anyhow::Result<(&str, &str)> {
+    let mut candidates: Vec<&str> = toolchains.iter().map(|t| &t[..]).collect();
+
+    loop {
+        let first = match candidates.first() {
+            Some(&first) => first,
+            None => bail!("None of the toolchains could be used"),
+        };
+
+        match test(first)? {
+            Outcome::Passed => break,
+            Outcome::Failed => bail!("The test already fails with `{}`", first),
+            Outcome::Unusable => {
+                candidates.remove(0);
+            }
+        }
+    }
+
+    loop {
+        if candidates.len() < 2 {
+            bail!("The test passes with all usable toolchains");
+        }
+
+        let last = candidates[candidates.len() - 1];
+
+        match test(last)? {
+            Outcome::Failed => break,
+            Outcome::Passed => bail!("The test still passes with `{}`", last),
+            Outcome::Unusable => {
+                candidates.pop();
+            }
+        }
+    }
+
+    // The test passes with `candidates[good]` and fails with `candidates[bad]`
+    let (mut good, mut bad) = (0, candidates.len() - 1);
+
+    while bad - good > 1 {
+        let middle = (good + bad) / 2;
+
+        match test(candidates[middle])? {
+            Outcome::Passed => good = middle,
+            Outcome::Failed => bad = middle,
+            Outcome::Unusable => {
+                candidates.remove(middle);
+                bad -= 1;
+            }
+        }
+    }
+
+    Ok((candidates[good], candidates[bad]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bisect, nightly_toolchains, Outcome};
+
+    #[test]
+    fn nightly_toolchain_range() {
+        assert_eq!(
+            nightly_toolchains("nightly-2024-02-27", "nightly-2024-03-02").unwrap(),
+            vec![
+                "nightly-2024-02-27",
+                "nightly-2024-02-28",
+                "nightly-2024-02-29",
+                "nightly-2024-03-01",
+                "nightly-2024-03-02",
+            ]
+        );
+
+        assert_eq!(
+            nightly_toolchains("nightly-2023-12-31", "nightly-2024-06-01")
+                .unwrap()
+                .len(),
+            154
+        );
+
+        assert!(nightly_toolchains("nightly-2024-02-30", "nightly-2024-03-02").is_err());
+        assert!(nightly_toolchains("stable", "nightly-2024-03-02").is_err());
+        assert!(nightly_toolchains("nightly-2024-03-02", "nightly-2024-03-01").is_err());
+    }
+
+    #[test]
+    fn bisect_toolchains() {
+        let toolchains = nightly_toolchains("nightly-2024-01-01", "nightly-2024-01-31").unwrap();
+
+        let mut tested = vec![];
+        let result = bisect(&toolchains, |toolchain| {
+            tested.push(toolchain.to_string());
+            Ok(match toolchain {
+                "nightly-2024-01-01" | "nightly-2024-01-12" | "nightly-2024-01-13" => {
+                    Outcome::Unusable
+                }
+                _ if toolchain < "nightly-2024-01-13" => Outcome::Passed,
+                _ => Outcome::Failed,
+            })
+        })
+        .unwrap();
+
+        assert_eq!(result, ("nightly-2024-01-11", "nightly-2024-01-14"));
+        assert!(tested.len() < 10);
+
+        assert!(bisect(&toolchains, |_| Ok(Outcome::Passed)).is_err());
+        assert!(bisect(&toolchains, |_| Ok(Outcome::Failed)).is_err());
+        assert!(bisect(&toolchains, |_| Ok(Outcome::Unusable)).is_err());
+    }
+}
@@ -1,19 +1,141 @@
-#[derive(Debug, PartialEq, Eq, Hash)]
+use std::sync::Arc;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct BreakPoint {
     pub line_index: usize,
+    /// The name given via `// #break: name`. Named breakpoints are only set when
+    /// referenced by a `#break name` statement in the test script.
+    pub name: Option<Arc<str>>,
+    /// Only stop on the N-th time the breakpoint is hit, given via `// #break hit N`
+    pub hit_count: Option<u32>,
+    /// Created via `// #tbreak`. The breakpoint is deleted after it has been hit once.
+    pub temporary: bool,
 }
 
-/// Return 0-based line numbers of all lines containing the string "#break"
+const TOKEN_BREAK: &str = "#break";
+const TOKEN_TBREAK: &str = "#tbreak";
+const TOKEN_COMMENT: &str = "//";
+const TOKEN_HIT: &str = "hit";
+
+/// Return 0-based line numbers of all lines containing a `// #break` (or `// #tbreak`)
+/// comment, which can be placed on a line of its own or at the end of a line of code.
 pub fn find(file_contents: &str) -> Vec<BreakPoint> {
     file_contents
         .lines()
         .enumerate()
         .filter_map(|(line_index, line)| {
-            if line.contains("#break") {
-                Some(BreakPoint { line_index })
-            } else {
-                None
-            }
+            let (name, hit_count, temporary) = parse_marker(line)?;
+
+            Some(BreakPoint {
+                line_index,
+                name,
+                hit_count,
+                temporary,
+            })
         })
         .collect()
 }
+
+/// Returns `Some` if `line` contains a `// #break` or `// #tbreak` comment. The inner
+/// value is the name of the breakpoint, its hit count, and whether it is temporary.
+fn parse_marker(line: &str) -> Option<(Option<Arc<str>>, Option<u32>, bool)> {
+    line.match_indices(TOKEN_COMMENT).find_map(|(index, _)| {
+        let comment = line[index + TOKEN_COMMENT.len()..].trim_start();
+
+        let (rest, temporary) = if let Some(rest) = comment.strip_prefix(TOKEN_BREAK) {
+            (rest, false)
+        } else {
+            (comment.strip_prefix(TOKEN_TBREAK)?, true)
+        };
+
+        let (name, rest) = if let Some(rest) = rest.strip_prefix(':') {
+            let mut tokens = rest.split_whitespace();
+            let name: Arc<str> = tokens.next()?.into();
+            (Some(name), tokens)
+        } else if rest.is_empty() || rest.starts_with(char::is_whitespace) {
+            (None, rest.split_whitespace())
+        } else {
+            // Something like `#breakfast`
+            return None;
+        };
+
+        Some((name, parse_hit_count(rest), temporary))
+    })
+}
+
+/// Parses `hit N` at the start of `tokens`. Anything else is treated as free-form text.
+fn parse_hit_count<'a>(mut tokens: impl Iterator<Item = &'a str>) -> Option<u32> {
+    if tokens.next()? != TOKEN_HIT {
+        return None;
+    }
+
+    tokens.next()?.parse().ok().filter(|&count| count > 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BreakPoint;
+
+    #[test]
+    fn find() {
+        let source = "\
+fn main() {
+    // #break
+    foo(); // #break - this is the second breakpoint
+    let url = \"http://example.com\"; // #break: loop-body
+    let s = \"#break\"; // #breakfast
+    bar(); //#break
+    baz(); // #break hit 3
+    quux(); // #break: nth hit 2
+    once(); // #tbreak: once
+}";
+
+        assert_eq!(
+            super::find(source),
+            vec![
+                BreakPoint {
+                    line_index: 1,
+                    name: None,
+                    hit_count: None,
+                    temporary: false,
+                },
+                BreakPoint {
+                    line_index: 2,
+                    name: None,
+                    hit_count: None,
+                    temporary: false,
+                },
+                BreakPoint {
+                    line_index: 3,
+                    name: Some("loop-body".into()),
+                    hit_count: None,
+                    temporary: false,
+                },
+                BreakPoint {
+                    line_index: 5,
+                    name: None,
+                    hit_count: None,
+                    temporary: false,
+                },
+                BreakPoint {
+                    line_index: 6,
+                    name: None,
+                    hit_count: Some(3),
+                    temporary: false,
+                },
+                BreakPoint {
+                    line_index: 7,
+                    name: Some("nth".into()),
+                    hit_count: Some(2),
+                    temporary: false,
+                },
+                BreakPoint {
+                    line_index: 8,
+                    name: Some("once".into()),
+                    hit_count: None,
+                    temporary: true,
+                },
+            ]
+        );
+    }
+}
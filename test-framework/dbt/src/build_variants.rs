@@ -0,0 +1,90 @@
+//! Build variants with reduced debuginfo, for testing that debuggers degrade gracefully
+//! (e.g. backtraces still work, but variables are reported as unavailable). They can be
+//! given like Cargo profiles via `--cargo-profile` and are compiled as custom Cargo
+//! profiles inheriting from `dev`, which dbt defines on the command line via `--config`, so
+//! test workspaces don't have to declare them.
+
+pub struct BuildVariant {
+    pub name: &'static str,
+    /// The settings of the Cargo profile, in addition to `inherits = "dev"`
+    profile_settings: &'static [(&'static str, &'static str)],
+    /// The value of `-Cdebuginfo` the support crate is compiled with
+    rustc_debuginfo: &'static str,
+}
+
+pub const BUILD_VARIANTS: &[BuildVariant] = &[
+    BuildVariant {
+        name: "line-tables-only",
+        profile_settings: &[("debug", "line-tables-only")],
+        rustc_debuginfo: "line-tables-only",
+    },
+    BuildVariant {
+        name: "strip-debuginfo",
+        profile_settings: &[("strip", "debuginfo")],
+        rustc_debuginfo: "2",
+    },
+    BuildVariant {
+        name: "strip-symbols",
+        profile_settings: &[("strip", "symbols")],
+        rustc_debuginfo: "2",
+    },
+];
+
+/// The build variant `cargo_profile` refers to, `None` for `debug` and `release`
+pub fn find(cargo_profile: &str) -> Option<&'static BuildVariant> {
+    BUILD_VARIANTS
+        .iter()
+        .find(|variant| variant.name == cargo_profile)
+}
+
+/// The value of `@debuginfo` for tests compiled with `cargo_profile`
+pub fn debuginfo(cargo_profile: &str) -> &str {
+    match find(cargo_profile) {
+        Some(variant) => variant.name,
+        None => "full",
+    }
+}
+
+/// The value of `-Cdebuginfo` for code compiled by dbt itself with `cargo_profile`
+pub fn rustc_debuginfo(cargo_profile: &str) -> &'static str {
+    match find(cargo_profile) {
+        Some(variant) => variant.rustc_debuginfo,
+        None => "2",
+    }
+}
+
+impl BuildVariant {
+    /// The `--config` arguments for Cargo that define the profile of the variant
+    pub fn cargo_config_args(&self) -> Vec<String> {
+        let mut args = vec![
+            "--config".to_string(),
+            format!("profile.{}.inherits=\"dev\"", self.name),
+        ];
+
+        for (key, value) in self.profile_settings {
+            args.push("--config".to_string());
+            args.push(format!("profile.{}.{}=\"{}\"", self.name, key, value));
+        }
+
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn cargo_config_args() {
+        assert_eq!(
+            super::find("line-tables-only").unwrap().cargo_config_args(),
+            [
+                "--config",
+                "profile.line-tables-only.inherits=\"dev\"",
+                "--config",
+                "profile.line-tables-only.debug=\"line-tables-only\"",
+            ]
+        );
+        assert!(super::find("release").is_none());
+        assert_eq!(super::debuginfo("debug"), "full");
+        assert_eq!(super::debuginfo("strip-symbols"), "strip-symbols");
+    }
+}
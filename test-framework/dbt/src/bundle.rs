@@ -0,0 +1,462 @@
+//! Bundles of failing tests (`dbt bundle-failures`), so that failures on CI can be
+//! reproduced and analyzed locally without rerunning CI. A bundle is a `.tar.zst` archive
+//! with everything dbt has about the tests that didn't pass in a run, going by the run's
+//! JSON report:
+//!
+//! - `report.json`: the entries of the failing tests in the report, along with the run's
+//!   [crate::metadata::RunMetadata] if the report has it
+//! - `output/`: their output directories, i.e. the debugger scripts, the output of the
+//!   debuggers and debuggees, crashdumps and fault dumps (see `--output-dir`)
+//! - `sources/`: the Cargo packages of the tests, without their target directories, and the
+//!   files of their workspaces that affect how they are built: the workspace's `Cargo.toml`,
+//!   `Cargo.lock` and `.cargo/config.toml`, and the `dbt-test.toml` files of the suites
+//!   the packages are in
+//! - `binaries/`: the test programs with their debuginfo (e.g. `.pdb` files), in the layout
+//!   of the Cargo target directory, and the support crate (see `--support-crate`) they were
+//!   linked with, if any
+//! - `omitted.txt`: the files left out for exceeding the size limit, if any
+//!
+//! Files larger than the limit are left out, as core files can easily be larger than what
+//! CI systems accept as artifacts. Symlinked directories are left out as well, as they may
+//! lead to loops.
+
+use std::{
+    collections::HashSet,
+    fs::File,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use log::debug;
+
+use crate::{
+    cargo_test_directory::{BuildConfig, CargoPackage, CargoWorkspace, TestDefinition},
+    json_report::{JsonReport, JsonTestResult},
+    prettify_path, test_config, workflow,
+};
+
+/// The files of a workspace, relative to its root, that affect how its packages are built
+const WORKSPACE_FILES: &[&str] = &[
+    "Cargo.toml",
+    "Cargo.lock",
+    ".cargo/config.toml",
+    // The name Cargo used before `config.toml`
+    ".cargo/config",
+];
+
+/// The zstd level bundles are compressed with, a compromise between speed and size for
+/// binaries and dumps
+const COMPRESSION_LEVEL: i32 = 9;
+
+/// What went into a bundle
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BundleSummary {
+    /// The number of failing test results in the report
+    pub failures: usize,
+    /// The files left out for exceeding the size limit
+    pub omitted: Vec<PathBuf>,
+}
+
+/// Where the bundle of a run finds the artifacts of its tests
+pub struct RunDirectories<'a> {
+    /// See `--output-dir`
+    pub output_dir: &'a Path,
+    /// See `--cargo-target-directory`
+    pub cargo_target_directory: &'a Path,
+}
+
+/// Writes the bundle of the tests that failed according to `report` to `archive_path`,
+/// leaving out files larger than `max_file_size` bytes
+pub fn bundle_failures(
+    report: &JsonReport,
+    cargo_workspaces: &[CargoWorkspace],
+    directories: &RunDirectories,
+    archive_path: &Path,
+    max_file_size: u64,
+) -> anyhow::Result<BundleSummary> {
+    let failures: Vec<&JsonTestResult> = report
+        .tests
+        .iter()
+        .filter(|test_result| test_result.failed())
+        .collect();
+
+    let file = File::create(archive_path)
+        .with_context(|| format!("Could not create {}", prettify_path(archive_path)))?;
+    let encoder = zstd::Encoder::new(file, COMPRESSION_LEVEL)?;
+
+    let mut bundle = Bundle {
+        builder: tar::Builder::new(encoder),
+        added: HashSet::new(),
+        max_file_size,
+        omitted: vec![],
+    };
+
+    bundle.add_bytes(
+        Path::new("report.json"),
+        serde_json::to_string_pretty(&JsonReport {
+            tests: failures
+                .iter()
+                .map(|test_result| (*test_result).clone())
+                .collect(),
+            ..report.clone()
+        })?
+        .as_bytes(),
+    )?;
+
+    for test_result in &failures {
+        let (workspace, package, test_definition) =
+            match find_test(cargo_workspaces, &test_result.test) {
+                Some(test) => test,
+                None => {
+                    debug!("Test `{}` is not in the workspaces", test_result.test);
+                    continue;
+                }
+            };
+
+        let build = BuildConfig {
+            toolchain: test_result.toolchain.as_deref().map(Into::into),
+            target: test_result.target.as_deref().map(Into::into),
+        };
+
+        // See `output_dir_for_build` and `output_dir_for_test`
+        let mut build_path = PathBuf::new();
+        if !build.is_default() {
+            build_path.push(build.dir_name());
+        }
+        let output_path = build_path.join(format!(
+            "{}@{}",
+            test_definition.flat_name(),
+            test_result.cargo_profile
+        ));
+
+        bundle.add_directory(
+            &Path::new("output").join(&output_path),
+            &directories.output_dir.join(&output_path),
+        )?;
+
+        let package_path = Path::new("sources").join(CargoPackage::pretty_root_path(
+            &workspace.root_path,
+            &package.root_path,
+        ));
+        bundle.add_directory(&package_path, &package.root_path)?;
+
+        let workspace_path = Path::new("sources").join(CargoPackage::pretty_root_path(
+            &workspace.root_path,
+            &workspace.root_path,
+        ));
+
+        for file in WORKSPACE_FILES {
+            bundle.add_path(&workspace_path.join(file), &workspace.root_path.join(file))?;
+        }
+
+        // The `dbt-test.toml` files of the suites, which the package's settings inherit from
+        let mut suite_directory = package.root_path.as_path();
+
+        while let Some(parent) = suite_directory.parent() {
+            if !parent.starts_with(&workspace.root_path) {
+                break;
+            }
+
+            let suite_path = Path::new("sources")
+                .join(CargoPackage::pretty_root_path(&workspace.root_path, parent));
+            bundle.add_path(
+                &suite_path.join(test_config::FILE_NAME),
+                &parent.join(test_config::FILE_NAME),
+            )?;
+
+            suite_directory = parent;
+        }
+
+        let executables_directory = workflow::executables_directory(
+            &workflow::build_target_directory(directories.cargo_target_directory, &build),
+            &build,
+        );
+        let executable_directory = executables_directory.join(&test_result.cargo_profile);
+
+        let binaries_path = Path::new("binaries")
+            .join(&build_path)
+            .join(&test_result.cargo_profile);
+
+        let test_program = workflow::test_program_path(
+            &executable_directory,
+            Some(&workflow::load_test_programs(&executable_directory)?),
+            test_definition,
+        );
+
+        for path in test_program_files(&test_program) {
+            let file_name = path.file_name().unwrap();
+            bundle.add_path(&binaries_path.join(file_name), &path)?;
+        }
+
+        // See `compile_cargo_tests`
+        bundle.add_directory(
+            &Path::new("binaries")
+                .join(&build_path)
+                .join("dbt-support")
+                .join(&test_result.cargo_profile),
+            &executables_directory
+                .join("dbt-support")
+                .join(&test_result.cargo_profile),
+        )?;
+    }
+
+    if !bundle.omitted.is_empty() {
+        let omitted = bundle
+            .omitted
+            .iter()
+            .map(|path| format!("{}\n", path.display()))
+            .collect::<String>();
+
+        bundle.add_bytes(Path::new("omitted.txt"), omitted.as_bytes())?;
+    }
+
+    let omitted = std::mem::take(&mut bundle.omitted);
+    bundle.builder.into_inner()?.finish()?;
+
+    Ok(BundleSummary {
+        failures: failures.len(),
+        omitted,
+    })
+}
+
+struct Bundle {
+    builder: tar::Builder<zstd::Encoder<'static, File>>,
+    /// The paths in the archive, as several results can share a test's files
+    added: HashSet<PathBuf>,
+    max_file_size: u64,
+    omitted: Vec<PathBuf>,
+}
+
+impl Bundle {
+    fn add_bytes(&mut self, archive_path: &Path, bytes: &[u8]) -> anyhow::Result<()> {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(bytes.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(0);
+        header.set_cksum();
+
+        self.builder.append_data(&mut header, archive_path, bytes)?;
+        Ok(())
+    }
+
+    /// Adds the file or directory at `path`, if it exists
+    fn add_path(&mut self, archive_path: &Path, path: &Path) -> anyhow::Result<()> {
+        if path.is_dir() {
+            self.add_directory(archive_path, path)
+        } else if path.is_file() {
+            self.add_file(archive_path, path)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Adds the files in the directory at `path`, if it exists, except for target directories
+    /// and symlinked directories
+    fn add_directory(&mut self, archive_path: &Path, path: &Path) -> anyhow::Result<()> {
+        if !path.is_dir() || !self.added.insert(archive_path.to_path_buf()) {
+            return Ok(());
+        }
+
+        for entry in std::fs::read_dir(path)? {
+            let entry = entry?;
+
+            if entry.file_name() == "target"
+                || (entry.file_type()?.is_symlink() && entry.path().is_dir())
+            {
+                continue;
+            }
+
+            self.add_path(&archive_path.join(entry.file_name()), &entry.path())?;
+        }
+
+        Ok(())
+    }
+
+    fn add_file(&mut self, archive_path: &Path, path: &Path) -> anyhow::Result<()> {
+        if !self.added.insert(archive_path.to_path_buf()) {
+            return Ok(());
+        }
+
+        if std::fs::metadata(path)?.len() > self.max_file_size {
+            self.omitted.push(archive_path.to_path_buf());
+            return Ok(());
+        }
+
+        self.builder
+            .append_path_with_name(path, archive_path)
+            .with_context(|| format!("Could not add {} to the bundle", prettify_path(path)))?;
+
+        Ok(())
+    }
+}
+
+/// The test with the given name, along with its package and workspace
+fn find_test<'w>(
+    cargo_workspaces: &'w [CargoWorkspace],
+    test_name: &str,
+) -> Option<(&'w CargoWorkspace, &'w CargoPackage, &'w TestDefinition)> {
+    cargo_workspaces.iter().find_map(|workspace| {
+        workspace.cargo_packages.iter().find_map(|package| {
+            package
+                .test_definitions
+                .iter()
+                .find(|test_definition| &test_definition.name[..] == test_name)
+                .map(|test_definition| (workspace, package, test_definition))
+        })
+    })
+}
+
+/// `test_program` and the files next to it with its debuginfo, as far as they exist: `.pdb`
+/// files (named with underscores by rustc), split DWARF packages (`.dwp`) and `.dSYM` bundles
+fn test_program_files(test_program: &Path) -> Vec<PathBuf> {
+    let directory = test_program.parent().unwrap_or(Path::new(""));
+    let stem = test_program.with_extension("");
+    let stem = stem.file_name().unwrap_or_default().to_string_lossy();
+    let file_name = test_program
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+
+    [
+        test_program.to_path_buf(),
+        directory.join(format!("{}.pdb", stem)),
+        directory.join(format!("{}.pdb", stem.replace('-', "_"))),
+        directory.join(format!("{}.dwp", stem)),
+        directory.join(format!("{}.dSYM", file_name)),
+    ]
+    .into_iter()
+    .filter(|path| path.exists())
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, sync::Arc};
+
+    use crate::{
+        cargo_test_directory::{CargoPackage, CargoWorkspace, TestDefinition},
+        json_report::{JsonReport, JsonTestResult},
+        script::Script,
+    };
+
+    #[test]
+    fn bundle_failures() {
+        let root = std::env::temp_dir().join(format!("dbt-bundle-test-{}", std::process::id()));
+        let workspace = root.join("tests");
+        let package = workspace.join("pkg");
+        let output = root.join("output");
+        let target = root.join("target");
+        let archive = root.join("failures.tar.zst");
+
+        std::fs::create_dir_all(package.join("src")).unwrap();
+        std::fs::create_dir_all(package.join("target")).unwrap();
+        std::fs::create_dir_all(workspace.join(".cargo")).unwrap();
+        std::fs::create_dir_all(target.join("dbt-support/debug")).unwrap();
+        std::fs::write(workspace.join("Cargo.toml"), "[workspace]").unwrap();
+        std::fs::write(workspace.join(".cargo/config.toml"), "[build]").unwrap();
+        std::fs::write(workspace.join("dbt-test.toml"), "timeout = 5").unwrap();
+        std::fs::write(target.join("dbt-support/debug/libdbt_support.rlib"), "rlib").unwrap();
+        std::fs::create_dir_all(output.join("pkg~src~main.rs@debug")).unwrap();
+        std::fs::create_dir_all(target.join("debug/deps")).unwrap();
+        std::fs::write(package.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::write(package.join("target/junk"), "junk").unwrap();
+        std::fs::write(output.join("pkg~src~main.rs@debug/gdb.out"), "output").unwrap();
+        std::fs::write(target.join("debug/deps/pkg_main-1a2b"), "executable").unwrap();
+        std::fs::write(target.join("debug/deps/pkg_main-1a2b.dwp"), "a".repeat(100)).unwrap();
+        std::fs::write(
+            target
+                .join("debug")
+                .join(crate::workflow::TEST_PROGRAMS_FILE_NAME),
+            r#"{"pkg-main": "deps/pkg_main-1a2b"}"#,
+        )
+        .unwrap();
+
+        // A symlink back to the workspace must not make the bundle loop
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&workspace, package.join("src/loop")).unwrap();
+
+        let cargo_workspaces = [CargoWorkspace {
+            root_path: workspace.clone(),
+            cargo_packages: vec![CargoPackage {
+                root_path: package.clone(),
+                name: None,
+                config: Arc::default(),
+                test_definitions: vec![TestDefinition::new(
+                    Path::new("src/main.rs"),
+                    &package.join("src/main.rs"),
+                    "pkg",
+                    "pkg-main".into(),
+                    Script::new_empty(),
+                    vec![],
+                )],
+            }],
+        }];
+
+        let test_result = |test: &str, status: &str| JsonTestResult {
+            test: test.to_string(),
+            cargo_profile: "debug".to_string(),
+            status: status.to_string(),
+            ..Default::default()
+        };
+        let report = JsonReport {
+            schema_version: crate::json_report::SCHEMA_VERSION,
+            tests: vec![
+                test_result("pkg/src/main.rs", "failed"),
+                test_result("pkg/src/main.rs", "timed-out"),
+                test_result("pkg/src/other.rs", "passed"),
+            ],
+            time_breakdown: Default::default(),
+            metadata: None,
+        };
+
+        let summary = super::bundle_failures(
+            &report,
+            &cargo_workspaces,
+            &super::RunDirectories {
+                output_dir: &output,
+                cargo_target_directory: &target,
+            },
+            &archive,
+            20,
+        )
+        .unwrap();
+
+        assert_eq!(summary.failures, 2);
+        assert_eq!(
+            summary.omitted,
+            vec![Path::new("binaries/debug/pkg_main-1a2b.dwp")]
+        );
+
+        let decoder = zstd::Decoder::new(std::fs::File::open(&archive).unwrap()).unwrap();
+        let mut paths = tar::Archive::new(decoder)
+            .entries()
+            .unwrap()
+            .map(|entry| {
+                entry
+                    .unwrap()
+                    .path()
+                    .unwrap()
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect::<Vec<_>>();
+        paths.sort();
+
+        assert_eq!(
+            paths,
+            [
+                "binaries/dbt-support/debug/libdbt_support.rlib",
+                "binaries/debug/pkg_main-1a2b",
+                "omitted.txt",
+                "output/pkg~src~main.rs@debug/gdb.out",
+                "report.json",
+                "sources/tests/.cargo/config.toml",
+                "sources/tests/Cargo.toml",
+                "sources/tests/dbt-test.toml",
+                "sources/tests/pkg/src/main.rs",
+            ]
+        );
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,231 @@
+//! The packages and targets of a test workspace as `cargo metadata` reports them, so that
+//! they are passed to Cargo by the names Cargo knows them by, rather than by names guessed
+//! from the file system. Those differ e.g. for a package whose `name` isn't the name of its
+//! directory, or a `[[bin]]` with a `name` other than the stem of its source file.
+
+use std::{
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::bail;
+use serde::Deserialize;
+
+use crate::{
+    cargo_test_directory::{BuildConfig, CargoWorkspace},
+    prettify_path,
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+pub struct CargoMetadata {
+    packages: Vec<Package>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+struct Package {
+    name: String,
+    manifest_path: PathBuf,
+    targets: Vec<Target>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct Target {
+    pub name: String,
+    /// E.g. `bin`, `test`, `lib` or `cdylib`
+    pub kind: Vec<String>,
+    pub src_path: PathBuf,
+}
+
+impl Target {
+    /// The arguments selecting this target for `cargo rustc`
+    pub fn cargo_target_args(&self) -> Vec<String> {
+        let has_kind = |kind: &str| self.kind.iter().any(|k| k == kind);
+
+        if has_kind("bin") {
+            vec!["--bin".to_string(), self.name.clone()]
+        } else if has_kind("test") {
+            vec!["--test".to_string(), self.name.clone()]
+        } else if has_kind("example") {
+            vec!["--example".to_string(), self.name.clone()]
+        } else if has_kind("bench") {
+            vec!["--bench".to_string(), self.name.clone()]
+        } else {
+            vec!["--lib".to_string()]
+        }
+    }
+}
+
+impl CargoMetadata {
+    /// Runs `cargo metadata` for the workspace at `root_path` with the toolchain of `build`
+    pub fn load(root_path: &Path, build: &BuildConfig) -> anyhow::Result<CargoMetadata> {
+        let mut command = Command::new("cargo");
+
+        if let Some(toolchain) = &build.toolchain {
+            command.arg(format!("+{}", toolchain));
+        }
+
+        let output = command
+            .args(["metadata", "--format-version", "1", "--no-deps"])
+            .current_dir(root_path)
+            .output()?;
+
+        if !output.status.success() {
+            bail!(
+                "`cargo metadata` failed for {}:\n{}",
+                prettify_path(root_path),
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        Ok(serde_json::from_slice(&output.stdout)?)
+    }
+
+    /// Sets the [crate::cargo_test_directory::CargoPackage::name] of the packages of
+    /// `cargo_workspace` and the [crate::cargo_test_directory::TestDefinition::cargo_target]
+    /// of their tests, which is the one given via `#debuggee-target` if there is one. Fails
+    /// if the package has no such target.
+    pub fn apply(&self, cargo_workspace: &mut CargoWorkspace) -> anyhow::Result<()> {
+        for package in &mut cargo_workspace.cargo_packages {
+            let root_path = canonical(&package.root_path);
+
+            let metadata_package = self.packages.iter().find(|metadata_package| {
+                metadata_package
+                    .manifest_path
+                    .parent()
+                    .is_some_and(|directory| canonical(directory) == root_path)
+            });
+
+            let metadata_package = match metadata_package {
+                Some(metadata_package) => metadata_package,
+                None => continue,
+            };
+
+            package.name = Some(metadata_package.name.clone());
+
+            for test_definition in &mut package.test_definitions {
+                let source_path = canonical(&test_definition.absolute_source_path);
+
+                test_definition.cargo_target = match test_definition.script.debuggee_target() {
+                    Some(debuggee_target) => {
+                        let target = metadata_package.targets.iter().find(|target| {
+                            *target.name == *debuggee_target.name
+                                && target
+                                    .kind
+                                    .iter()
+                                    .any(|k| k == debuggee_target.kind.as_str())
+                        });
+
+                        if target.is_none() {
+                            bail!(
+                                "{}: package `{}` has no target `{}` (see #debuggee-target)",
+                                prettify_path(&test_definition.absolute_source_path),
+                                metadata_package.name,
+                                debuggee_target
+                            );
+                        }
+
+                        target.cloned()
+                    }
+                    None => metadata_package
+                        .targets
+                        .iter()
+                        .find(|target| canonical(&target.src_path) == source_path)
+                        .cloned(),
+                };
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// `path` with symlinks resolved, as Cargo reports paths that way, or `path` itself if it
+/// doesn't exist
+pub fn canonical(path: &Path) -> PathBuf {
+    path.canonicalize().unwrap_or_else(|_| path.to_path_buf())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::CargoMetadata;
+    use crate::{
+        cargo_test_directory::{CargoPackage, CargoWorkspace, TestDefinition},
+        script::{parse_script, Script},
+    };
+
+    #[test]
+    fn apply() {
+        let metadata: CargoMetadata = serde_json::from_str(
+            r#"{
+                "packages": [{
+                    "name": "vectors",
+                    "manifest_path": "/ws/vec-tests/Cargo.toml",
+                    "targets": [
+                        {"name": "show-vec", "kind": ["bin"], "src_path": "/ws/vec-tests/src/bin/a.rs"},
+                        {"name": "nested", "kind": ["test"], "src_path": "/ws/vec-tests/tests/nested.rs"},
+                        {"name": "vectors", "kind": ["cdylib"], "src_path": "/ws/vec-tests/src/lib.rs"},
+                        {"name": "demo", "kind": ["example"], "src_path": "/ws/vec-tests/examples/demo.rs"}
+                    ]
+                }],
+                "workspace_root": "/ws"
+            }"#,
+        )
+        .unwrap();
+
+        let test_definition_with_script = |source_path: &str, script: Script| {
+            TestDefinition::new(
+                Path::new(source_path)
+                    .strip_prefix("/ws/vec-tests")
+                    .unwrap(),
+                Path::new(source_path),
+                "ws/vec-tests",
+                "a".into(),
+                script,
+                vec![],
+            )
+        };
+        let test_definition =
+            |source_path: &str| test_definition_with_script(source_path, Script::new_empty());
+        let debuggee_target = |target: &str| {
+            let script = format!("/***\n#debuggee-target {}\n***/", target);
+            let script = parse_script(&script, None).unwrap();
+            test_definition_with_script("/ws/vec-tests/src/bin/c.rs", script)
+        };
+
+        let mut cargo_workspace = CargoWorkspace {
+            root_path: "/ws".into(),
+            cargo_packages: vec![CargoPackage {
+                root_path: "/ws/vec-tests".into(),
+                name: None,
+                config: Default::default(),
+                test_definitions: vec![
+                    test_definition("/ws/vec-tests/src/bin/a.rs"),
+                    test_definition("/ws/vec-tests/tests/nested.rs"),
+                    test_definition("/ws/vec-tests/src/lib.rs"),
+                    test_definition("/ws/vec-tests/src/bin/b.rs"),
+                    debuggee_target("example:demo"),
+                ],
+            }],
+        };
+
+        metadata.apply(&mut cargo_workspace).unwrap();
+
+        let package = &cargo_workspace.cargo_packages[0];
+        let args = |index: usize| package.test_definitions[index].cargo_target_args();
+
+        assert_eq!(package.package_name(), "vectors");
+        assert_eq!(args(0), ["--bin", "show-vec"]);
+        assert_eq!(args(1), ["--test", "nested"]);
+        assert_eq!(args(2), ["--lib"]);
+        // Not known to Cargo, so the name is derived from the source path
+        assert_eq!(args(3), ["--bin", "b"]);
+        assert_eq!(args(4), ["--example", "demo"]);
+
+        let package = &mut cargo_workspace.cargo_packages[0];
+        package.test_definitions = vec![debuggee_target("bench:demo")];
+
+        assert!(metadata.apply(&mut cargo_workspace).is_err());
+    }
+}
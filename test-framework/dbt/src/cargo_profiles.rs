@@ -0,0 +1,248 @@
+//! The Cargo profiles test cases can be compiled with (`--cargo-profile`): `debug` (also
+//! called `dev`), `release`, `test` and `bench`, the build variants (see
+//! [crate::build_variants]), and custom profiles declared in the `[profile]` tables of the
+//! test workspaces. Scripts can gate on the `opt-level` of the profile via `@opt_level`,
+//! which is resolved for each workspace when it is loaded (see [opt_levels]), following the
+//! `inherits` of custom profiles.
+
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::bail;
+
+use crate::{build_variants, prettify_path};
+
+/// The profiles Cargo has without a `[profile]` table for them, by the names dbt uses
+const BUILT_IN_PROFILES: [&str; 4] = ["debug", "release", "test", "bench"];
+
+/// The name dbt uses for `cargo_profile`, i.e. `debug` for Cargo's `dev` profile, which
+/// is also the name of the directory Cargo puts its executables into
+pub fn normalize(cargo_profile: &str) -> String {
+    match cargo_profile.trim() {
+        "dev" => "debug".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// The manifest of the workspace at `workspace_directory`
+fn load_manifest(workspace_directory: &Path) -> anyhow::Result<toml::Value> {
+    let manifest_path = workspace_directory.join("Cargo.toml");
+
+    match toml::from_str::<toml::Value>(&std::fs::read_to_string(&manifest_path)?) {
+        Ok(manifest) => Ok(manifest),
+        Err(e) => bail!("{}: {}", prettify_path(&manifest_path), e),
+    }
+}
+
+/// Checks that the given profiles exist for the workspace at `workspace_directory`
+pub fn declare(workspace_directory: &Path, cargo_profiles: &[String]) -> anyhow::Result<()> {
+    let manifest_path = workspace_directory.join("Cargo.toml");
+    let manifest = load_manifest(workspace_directory)?;
+    let profiles = manifest.get("profile").and_then(toml::Value::as_table);
+
+    for cargo_profile in cargo_profiles {
+        if let Err(e) = resolve_opt_level(profiles, cargo_profile) {
+            bail!(
+                "Cargo profile `{}` is not supported: {}. Use `debug`, `release`, `test`, \
+                 `bench`, a build variant ({}), or a profile declared in {}.",
+                cargo_profile,
+                e,
+                build_variants::BUILD_VARIANTS
+                    .iter()
+                    .map(|variant| format!("`{}`", variant.name))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                prettify_path(&manifest_path)
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// The `opt-level` of the built-in profiles, the build variants and the profiles declared
+/// in the `[profile]` tables of the workspace at `workspace_directory`, by profile name.
+/// Profiles whose `opt-level` can't be resolved are left out, as [declare] reports them.
+pub fn opt_levels(workspace_directory: &Path) -> anyhow::Result<BTreeMap<String, String>> {
+    let manifest = load_manifest(workspace_directory)?;
+    let profiles = manifest.get("profile").and_then(toml::Value::as_table);
+
+    let names = BUILT_IN_PROFILES
+        .iter()
+        .copied()
+        .chain(
+            build_variants::BUILD_VARIANTS
+                .iter()
+                .map(|variant| variant.name),
+        )
+        .chain(
+            profiles
+                .into_iter()
+                .flat_map(|profiles| profiles.keys().map(|name| &name[..])),
+        );
+
+    Ok(names
+        .filter_map(|name| {
+            let opt_level = resolve_opt_level(profiles, name).ok()?;
+            Some((normalize(name), opt_level))
+        })
+        .collect())
+}
+
+/// The `opt-level` of `cargo_profile` among `opt_levels` (see [opt_levels]), or Cargo's
+/// default if it isn't there
+pub fn opt_level(opt_levels: &BTreeMap<String, String>, cargo_profile: &str) -> String {
+    match opt_levels.get(cargo_profile) {
+        Some(opt_level) => opt_level.clone(),
+        None if cargo_profile == "release" || cargo_profile == "bench" => "3".to_string(),
+        None => "0".to_string(),
+    }
+}
+
+/// The arguments selecting `cargo_profile` for `cargo build` and the like
+pub fn cargo_args(cargo_profile: &str) -> Vec<String> {
+    match cargo_profile {
+        "debug" => vec![],
+        "release" => vec!["--release".to_string()],
+        other => {
+            let mut args = vec!["--profile".to_string(), other.to_string()];
+
+            if let Some(variant) = build_variants::find(other) {
+                args.extend(variant.cargo_config_args());
+            }
+
+            args
+        }
+    }
+}
+
+/// Follows the `inherits` of `cargo_profile` in the `[profile]` tables of the workspace
+/// manifest up to the first profile that sets `opt-level`, or to `dev` or `release`. The
+/// built-in `test` and `bench` profiles inherit from `dev` and `release`.
+fn resolve_opt_level(
+    profiles: Option<&toml::value::Table>,
+    cargo_profile: &str,
+) -> anyhow::Result<String> {
+    let mut name = match cargo_profile {
+        "debug" => "dev",
+        other if build_variants::find(other).is_some() => "dev",
+        other => other,
+    };
+    let mut visited = vec![];
+
+    loop {
+        if visited.contains(&name) {
+            bail!("the `inherits` of `{}` form a cycle", cargo_profile);
+        }
+        visited.push(name);
+
+        let profile = profiles.and_then(|profiles| profiles.get(name));
+
+        match profile.and_then(|profile| profile.get("opt-level")) {
+            Some(toml::Value::Integer(opt_level)) => return Ok(opt_level.to_string()),
+            Some(toml::Value::String(opt_level)) => return Ok(opt_level.clone()),
+            Some(other) => bail!("`profile.{}.opt-level` is `{}`", name, other),
+            None => {}
+        }
+
+        match name {
+            "dev" => return Ok("0".to_string()),
+            "release" => return Ok("3".to_string()),
+            _ => {}
+        }
+
+        name = match profile.and_then(|profile| profile.get("inherits")) {
+            Some(inherits) => match inherits.as_str() {
+                Some(inherits) => inherits,
+                None => bail!("`profile.{}.inherits` is `{}`", name, inherits),
+            },
+            None if name == "test" => "dev",
+            None if name == "bench" => "release",
+            None if profile.is_some() => bail!("`profile.{}` has no `inherits`", name),
+            None => bail!("there is no `[profile.{}]`", name),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn resolve_opt_level() {
+        let manifest: toml::Value = toml::from_str(
+            r#"
+[profile.release]
+opt-level = 2
+
+[profile.release-with-debug]
+inherits = "release"
+debug = true
+
+[profile.small]
+inherits = "dev"
+opt-level = "s"
+
+[profile.loop]
+inherits = "loop"
+
+[profile.test]
+debug = 1
+"#,
+        )
+        .unwrap();
+        let profiles = manifest.get("profile").and_then(toml::Value::as_table);
+
+        let resolve = |name: &str| super::resolve_opt_level(profiles, name);
+
+        assert_eq!(resolve("debug").unwrap(), "0");
+        assert_eq!(resolve("line-tables-only").unwrap(), "0");
+        assert_eq!(resolve("release").unwrap(), "2");
+        assert_eq!(resolve("release-with-debug").unwrap(), "2");
+        assert_eq!(resolve("small").unwrap(), "s");
+        assert_eq!(resolve("test").unwrap(), "0");
+        assert_eq!(resolve("bench").unwrap(), "2");
+        assert_eq!(
+            resolve("fast").unwrap_err().to_string(),
+            "there is no `[profile.fast]`"
+        );
+        assert_eq!(
+            resolve("loop").unwrap_err().to_string(),
+            "the `inherits` of `loop` form a cycle"
+        );
+
+        assert_eq!(super::resolve_opt_level(None, "release").unwrap(), "3");
+        assert_eq!(super::resolve_opt_level(None, "bench").unwrap(), "3");
+    }
+
+    #[test]
+    fn opt_levels() {
+        let workspace = std::env::temp_dir().join(format!("dbt-opt-levels-{}", std::process::id()));
+        std::fs::create_dir_all(&workspace).unwrap();
+        std::fs::write(
+            workspace.join("Cargo.toml"),
+            "[profile.dev]\nopt-level = 1\n\n[profile.loop]\ninherits = \"loop\"\n",
+        )
+        .unwrap();
+
+        let opt_levels = super::opt_levels(&workspace).unwrap();
+        assert_eq!(opt_levels["debug"], "1");
+        assert_eq!(opt_levels["test"], "1");
+        assert_eq!(opt_levels["line-tables-only"], "1");
+        assert_eq!(opt_levels["release"], "3");
+        assert!(!opt_levels.contains_key("loop"));
+
+        assert_eq!(super::opt_level(&opt_levels, "debug"), "1");
+        assert_eq!(super::opt_level(&Default::default(), "bench"), "3");
+
+        std::fs::remove_dir_all(&workspace).unwrap();
+    }
+
+    #[test]
+    fn cargo_args() {
+        assert!(super::cargo_args("debug").is_empty());
+        assert_eq!(super::cargo_args("release"), ["--release"]);
+        assert_eq!(
+            super::cargo_args("release-with-debug"),
+            ["--profile", "release-with-debug"]
+        );
+        assert_eq!(super::cargo_args("strip-symbols").len(), 6);
+    }
+}
@@ -1,8 +1,10 @@
 use regex::Regex;
 use serde::Deserialize;
 use std::{
+    borrow::Cow,
     collections::HashSet,
-    ffi::OsString,
+    ffi::{OsStr, OsString},
+    fmt::Display,
     path::{Path, PathBuf},
     sync::Arc,
 };
@@ -11,8 +13,12 @@
 use log::{info, warn};
 
 use crate::{
+    baseline,
     breakpoints::{self, BreakPoint},
-    script::{parse_script, Script},
+    cargo_metadata::Target,
+    library_runner,
+    script::{instantiate_params, parse_inline_script, parse_script, DebuggeeTarget, Script},
+    test_config::TestConfig,
 };
 
 #[derive(Debug, PartialEq, Eq)]
@@ -21,27 +27,88 @@ pub struct CargoWorkspace {
     pub cargo_packages: Vec<CargoPackage>,
 }
 
+/// The Rust toolchain and target triple that test cases are compiled with, in addition to
+/// the Cargo profile (see `--toolchain` and `--target`). `None` means the default toolchain
+/// and the host target respectively.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BuildConfig {
+    pub toolchain: Option<Arc<str>>,
+    pub target: Option<Arc<str>>,
+}
+
+impl BuildConfig {
+    pub fn is_default(&self) -> bool {
+        self.toolchain.is_none() && self.target.is_none()
+    }
+
+    /// A name for the build config that can be used as a directory name
+    pub fn dir_name(&self) -> String {
+        format!(
+            "{}-{}",
+            self.toolchain.as_deref().unwrap_or("default"),
+            self.target.as_deref().unwrap_or("host")
+        )
+    }
+}
+
+impl Display for BuildConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match (&self.toolchain, &self.target) {
+            (None, None) => write!(f, "default toolchain"),
+            (Some(toolchain), None) => write!(f, "toolchain `{}`", toolchain),
+            (None, Some(target)) => write!(f, "target `{}`", target),
+            (Some(toolchain), Some(target)) => {
+                write!(f, "toolchain `{}`, target `{}`", toolchain, target)
+            }
+        }
+    }
+}
+
 /// A single Cargo package within a workspace. Each ex
 #[derive(Debug, PartialEq, Eq)]
 pub struct CargoPackage {
     pub root_path: PathBuf,
 
+    /// The name of the package as Cargo knows it, if it was looked up via
+    /// [crate::cargo_metadata]
+    pub name: Option<String>,
+
+    /// The settings from the package's `dbt-test.toml`
+    pub config: Arc<TestConfig>,
+
     // There might be multiple test definitions
     pub test_definitions: Vec<TestDefinition>,
 }
 
 impl CargoPackage {
-    pub fn pretty_root_path(root_path: &Path) -> String {
+    pub fn pretty_root_path(workspace_directory: &Path, root_path: &Path) -> String {
         format!(
             "{}",
             root_path
                 // Make path start with the top-level test directory
-                .strip_prefix(root_path.parent().unwrap().parent().unwrap())
+                .strip_prefix(workspace_directory.parent().unwrap())
                 .unwrap()
                 .display()
         )
         .replace('\\', "/")
     }
+
+    /// Whether any of the package's tests has `#rustc-flags` or `#debuggee-target`, in which
+    /// case its test programs are compiled one by one via `cargo rustc`
+    pub fn builds_tests_separately(&self) -> bool {
+        self.test_definitions
+            .iter()
+            .any(|test_definition| test_definition.builds_separately())
+    }
+
+    /// The name to pass to `cargo build -p`, assumed to be the name of the package's directory
+    /// unless Cargo was asked for it, see [CargoPackage::name]
+    pub fn package_name(&self) -> Cow<'_, str> {
+        match &self.name {
+            Some(name) => Cow::Borrowed(name),
+            None => self.root_path.file_name().unwrap().to_string_lossy(),
+        }
+    }
 }
 
 /// A [TestDefinition] that has been successfully parsed from an `.rs` file. Each
@@ -63,6 +130,20 @@ pub struct TestDefinition {
 
     /// Breakpoints created via #break annotations
     pub breakpoints: Vec<BreakPoint>,
+
+    /// The parameter values of this instantiation of a test with #params directives
+    pub params: Vec<(String, String)>,
+
+    /// The settings from the `dbt-test.toml` of the containing package
+    pub config: Arc<TestConfig>,
+
+    /// For a C program that is the baseline of a Rust test (e.g. `src/main.c` next to
+    /// `src/main.rs`), the name of the Rust test
+    pub baseline_of: Option<Arc<str>>,
+
+    /// The Cargo target the test program is built from, if it was looked up via
+    /// [crate::cargo_metadata]
+    pub cargo_target: Option<Target>,
 }
 
 impl TestDefinition {
@@ -91,32 +172,180 @@ pub fn new(
             absolute_source_path: absolute_source_path.to_path_buf(),
             script,
             breakpoints,
+            params: vec![],
+            config: Default::default(),
+            baseline_of: None,
+            cargo_target: None,
+        }
+    }
+
+    /// Makes this the instantiation of a test with #params directives for `params`, which
+    /// are appended to the test's name, e.g. `ws/t1/src/main.rs[size=10]`
+    pub fn with_params(mut self, params: Vec<(String, String)>) -> TestDefinition {
+        if !params.is_empty() {
+            let values = params
+                .iter()
+                .map(|(name, value)| format!("{}={}", name, value))
+                .collect::<Vec<_>>()
+                .join(",");
+
+            self.name = format!("{}[{}]", self.name, values).into();
         }
+
+        self.params = params;
+        self
+    }
+
+    pub fn with_config(mut self, config: Arc<TestConfig>) -> TestDefinition {
+        self.config = config;
+        self
+    }
+
+    pub fn with_baseline_of(mut self, test_name: Arc<str>) -> TestDefinition {
+        self.baseline_of = Some(test_name);
+        self
+    }
+
+    /// The environment variables passing the parameter values to the test program, e.g.
+    /// `DBT_PARAM_SIZE=10` for `size`
+    pub fn param_env_vars(&self) -> Vec<(String, String)> {
+        self.params
+            .iter()
+            .map(|(name, value)| {
+                (
+                    format!("DBT_PARAM_{}", name.to_ascii_uppercase()),
+                    value.clone(),
+                )
+            })
+            .collect()
+    }
+
+    /// Whether the test program is a C program, compiled by [crate::baseline]
+    pub fn is_baseline(&self) -> bool {
+        self.baseline_of.is_some()
+    }
+
+    /// Whether the test is compiled and run for `target_triple`, see #targets
+    pub fn allows_target(&self, target_triple: &str) -> bool {
+        self.script
+            .targets()
+            .is_none_or(|targets| targets.iter().any(|target| &target[..] == target_triple))
+    }
+
+    /// Whether the test program is a dynamic library, run via the library runner
+    pub fn is_library(&self) -> bool {
+        self.absolute_source_path.file_name() == Some(OsStr::new("lib.rs"))
+            && self.script.debuggee_target().is_none()
+    }
+
+    /// Whether the test program has to be compiled on its own via `cargo rustc`, because of
+    /// `#rustc-flags` or because it is another target of the package (`#debuggee-target`)
+    pub fn builds_separately(&self) -> bool {
+        !self.script.rustc_flags().is_empty() || self.script.debuggee_target().is_some()
+    }
+
+    /// The name of the integration test (in the package's `tests` directory) if the test
+    /// program is its `#[test]` harness, built via `cargo test --no-run`
+    pub fn harness_test_name(&self) -> Option<Cow<'_, str>> {
+        if self.script.debuggee_target().is_some() {
+            return None;
+        }
+
+        if is_integration_test(&self.absolute_source_path) {
+            Some(self.absolute_source_path.file_stem()?.to_string_lossy())
+        } else {
+            None
+        }
+    }
+
+    /// The name Cargo knows the integration test by that is the test program, see
+    /// [TestDefinition::harness_test_name]
+    pub fn harness_target_name(&self) -> Option<Cow<'_, str>> {
+        let test_name = self.harness_test_name()?;
+
+        match &self.cargo_target {
+            Some(target) => Some(Cow::Borrowed(&target.name)),
+            None => Some(test_name),
+        }
+    }
+
+    /// The arguments selecting the target of the test program for `cargo rustc`
+    pub fn cargo_target_args(&self) -> Vec<String> {
+        if let Some(target) = &self.cargo_target {
+            return target.cargo_target_args();
+        }
+
+        if let Some(debuggee_target) = self.script.debuggee_target() {
+            return debuggee_target.cargo_target_args();
+        }
+
+        if self.is_library() {
+            return vec!["--lib".to_string()];
+        }
+
+        if let Some(test_name) = self.harness_test_name() {
+            return vec!["--test".to_string(), test_name.into_owned()];
+        }
+
+        // `src/main.rs` is the binary named after the package, `src/bin/x.rs` is `x`
+        let bin_name = if self.absolute_source_path.file_name() == Some(OsStr::new("main.rs")) {
+            self.absolute_source_path
+                .parent()
+                .and_then(Path::parent)
+                .and_then(Path::file_name)
+        } else {
+            self.absolute_source_path.file_stem()
+        };
+
+        vec![
+            "--bin".to_string(),
+            bin_name.unwrap().to_string_lossy().into_owned(),
+        ]
+    }
+
+    /// Returns the breakpoint defined via `// #break: name`, if any.
+    pub fn breakpoint(&self, name: &str) -> Option<&BreakPoint> {
+        self.breakpoints
+            .iter()
+            .find(|bp| bp.name.as_deref() == Some(name))
     }
 
     pub fn flat_name(&self) -> String {
-        self.name.replace(|c| c == '/' || c == '\\', "~")
+        self.name.replace(['/', '\\'], "~")
     }
 
+    /// Whether the name of the test or one of its tags, written as `tag:<tag>`, matches
+    /// `regex`
     pub fn matches(&self, regex: Option<&Regex>) -> bool {
         regex
-            .map(|regex| regex.is_match(&self.name[..]))
+            .map(|regex| {
+                regex.is_match(&self.name[..])
+                    || self
+                        .config
+                        .tags
+                        .iter()
+                        .any(|tag| regex.is_match(&format!("tag:{}", tag)))
+            })
             .unwrap_or(true)
     }
 }
 
-/// Finds and parses all [TestDefinitions]s in a Cargo package. Does not actually look
-/// at the package's Cargo.toml, just assumes that `src/main.rs` and each file in `src/bin`
-/// specifies a test case.
-// TODO: also support tests specified in `tests` directory
-fn analyze_cargo_package(project_directory: &Path) -> anyhow::Result<Vec<TestDefinition>> {
+/// Finds and parses all [TestDefinitions]s in a Cargo package. Assumes that `src/main.rs`
+/// and each file in `src/bin` and `tests` specifies a test case, as well as `src/lib.rs` if
+/// the package's Cargo.toml declares a `cdylib` or `dylib` crate type.
+fn analyze_cargo_package(
+    workspace_directory: &Path,
+    project_directory: &Path,
+    config: &Arc<TestConfig>,
+) -> anyhow::Result<Vec<TestDefinition>> {
     info!("Analyzing Cargo package `{}`", project_directory.display());
 
     assert!(project_directory.is_dir());
     assert!(project_directory.exists());
     assert!(project_directory.is_absolute());
 
-    let pretty_project_path = CargoPackage::pretty_root_path(project_directory);
+    let pretty_project_path =
+        CargoPackage::pretty_root_path(workspace_directory, project_directory);
 
     let cargo_toml_name = project_directory.join("Cargo.toml");
 
@@ -132,21 +361,104 @@ fn analyze_cargo_package(project_directory: &Path) -> anyhow::Result<Vec<TestDef
         |source_path: PathBuf, executable_name: OsString| -> anyhow::Result<()> {
             if source_path.exists() {
                 let contents = std::fs::read_to_string(&source_path)?;
-                let script = parse_script(&contents, Some(source_path.as_path()))?;
-                let breakpoints = breakpoints::find(&contents);
-
-                let test_definition = TestDefinition::new(
-                    source_path.strip_prefix(project_directory)?,
-                    source_path.as_path(),
-                    &pretty_project_path,
-                    executable_name,
-                    script,
-                    breakpoints,
-                );
 
-                info!(" - Found test case `{}`", test_definition.name);
+                // Only the harnesses of integration tests are built, see
+                // [TestDefinition::harness_test_name]
+                if !is_integration_test(&source_path) && defines_test_functions(&contents) {
+                    bail!(
+                        "{}: `#[test]` functions are only run for the files in the package's \
+                         `tests` directory, move them there to debug them",
+                        source_path.display()
+                    );
+                }
 
-                test_defs.push(test_definition);
+                for instantiation in instantiate_params(&contents)? {
+                    let breakpoints = breakpoints::find(&contents);
+                    let script = match parse_inline_script(
+                        &instantiation.source,
+                        &breakpoints,
+                        Some(source_path.as_path()),
+                    )? {
+                        Some(script) => script,
+                        None => parse_script(&instantiation.source, Some(source_path.as_path()))?,
+                    };
+                    check_breakpoint_references(&source_path, &script, &breakpoints)?;
+
+                    // Another target of the package gets a name of its own, so that it
+                    // doesn't take the place of the test program Cargo builds anyway
+                    let executable_name = match script.debuggee_target() {
+                        Some(debuggee_target) => {
+                            debuggee_executable_name(project_directory, debuggee_target)
+                        }
+                        None => executable_name.clone(),
+                    };
+
+                    let test_definition = TestDefinition::new(
+                        source_path.strip_prefix(project_directory)?,
+                        source_path.as_path(),
+                        &pretty_project_path,
+                        executable_name.clone(),
+                        script,
+                        breakpoints,
+                    )
+                    .with_params(instantiation.params)
+                    .with_config(config.clone());
+
+                    info!(" - Found test case `{}`", test_definition.name);
+
+                    // A C program next to a test with #c-baseline, e.g. `src/main.c`, is run
+                    // with the same script for comparison
+                    let baseline_path = source_path.with_extension("c");
+                    let baseline = if test_definition.script.has_c_baseline() {
+                        if !baseline_path.exists() {
+                            bail!(
+                                "{}: #c-baseline is given, but there is no C program at {}",
+                                source_path.display(),
+                                baseline_path.display()
+                            );
+                        }
+
+                        if test_definition.harness_test_name().is_some()
+                            || test_definition.is_library()
+                            || test_definition.script.debuggee_target().is_some()
+                        {
+                            bail!(
+                                "{}: #c-baseline is not supported for harnesses, libraries and \
+                                 tests with #debuggee-target",
+                                source_path.display()
+                            );
+                        }
+
+                        let baseline_contents = std::fs::read_to_string(&baseline_path)?;
+                        let baseline_breakpoints = breakpoints::find(&baseline_contents);
+                        check_breakpoint_references(
+                            &baseline_path,
+                            &test_definition.script,
+                            &baseline_breakpoints,
+                        )?;
+
+                        let baseline = TestDefinition::new(
+                            baseline_path.strip_prefix(project_directory)?,
+                            baseline_path.as_path(),
+                            &pretty_project_path,
+                            baseline::executable_name(&executable_name),
+                            test_definition.script.without_checks(),
+                            baseline_breakpoints,
+                        )
+                        .with_params(test_definition.params.clone())
+                        .with_config(config.clone())
+                        .with_baseline_of(test_definition.name.clone());
+
+                        info!(" - Found C baseline `{}`", baseline.name);
+
+                        Some(baseline)
+                    } else {
+                        None
+                    };
+
+                    test_defs.push(test_definition);
+                    test_defs.extend(baseline);
+                }
             }
 
             Ok(())
@@ -176,6 +488,33 @@ fn analyze_cargo_package(project_directory: &Path) -> anyhow::Result<Vec<TestDef
         }
     }
 
+    // Collect test harnesses from the tests directory, which are built via `cargo test`
+    let tests_directory = project_directory.join("tests");
+    if tests_directory.exists() {
+        let rs_ext = OsString::from("rs");
+        let package_name = project_directory.file_name().unwrap().to_string_lossy();
+
+        for dir_entry in std::fs::read_dir(&tests_directory)? {
+            let dir_entry = dir_entry?;
+            let source_path = dir_entry.path();
+
+            if source_path.extension() == Some(rs_ext.as_os_str()) {
+                let executable_name = executable_name(format!(
+                    "{}-harness-{}",
+                    package_name,
+                    source_path.file_stem().unwrap().to_string_lossy()
+                ));
+
+                collect_test_def(source_path, executable_name)?;
+            }
+        }
+    }
+
+    // A dynamic library, which is loaded by the library runner
+    if let Some(library_file_name) = library_runner::library_file_name(project_directory)? {
+        collect_test_def(src_directory.join("lib.rs"), library_file_name)?;
+    }
+
     test_defs.sort_by_cached_key(|td| td.executable_name.clone());
 
     Ok(test_defs)
@@ -197,33 +536,10 @@ pub fn load(directory: &Path) -> anyhow::Result<CargoWorkspace> {
             (ws_toml.workspace.members, ws_toml.workspace.exclude)
         };
 
-        // Read all the test files
+        // Find the packages, which may be grouped into suites, i.e. directories without a
+        // Cargo.toml
         let mut files = Vec::new();
-        for dir_entry in std::fs::read_dir(&directory)? {
-            let dir_entry = dir_entry?;
-
-            if dir_entry.file_type()?.is_dir() {
-                let test_directory = dir_entry.path();
-
-                let directory_name = test_directory.file_name().unwrap().to_string_lossy();
-
-                if exclude.contains(&directory_name[..]) {
-                    // Skip this directory if it is in the "exlude" list of the workspace Cargo.toml
-                    continue;
-                }
-
-                let mut cargo_toml_path = test_directory.clone();
-                cargo_toml_path.push("Cargo.toml");
-
-                if cargo_toml_path.exists() {
-                    info!(" - Found Cargo package `{}`", test_directory.display());
-                    members.remove(&directory_name[..]);
-                    files.push(test_directory);
-                } else {
-                    warn!(" - {} has no Cargo.toml", test_directory.display());
-                }
-            }
-        }
+        find_packages(&directory, &directory, &exclude, &mut members, &mut files)?;
 
         for member_not_found in members.iter().filter(|s| !s.contains('*')) {
             warn!(
@@ -237,9 +553,13 @@ pub fn load(directory: &Path) -> anyhow::Result<CargoWorkspace> {
         let mut test_project_defs = Vec::with_capacity(files.len());
 
         for cargo_project_directory in files {
-            let test_definitions = analyze_cargo_package(&cargo_project_directory)?;
+            let config = Arc::new(TestConfig::load(&directory, &cargo_project_directory)?);
+            let test_definitions =
+                analyze_cargo_package(&directory, &cargo_project_directory, &config)?;
             test_project_defs.push(CargoPackage {
                 root_path: cargo_project_directory,
+                name: None,
+                config,
                 test_definitions,
             });
         }
@@ -251,6 +571,108 @@ pub fn load(directory: &Path) -> anyhow::Result<CargoWorkspace> {
     }
 }
 
+/// Fails if the script references a breakpoint via `#break name` that has no marker in the
+/// source file at `source_path`
+fn check_breakpoint_references(
+    source_path: &Path,
+    script: &Script,
+    breakpoints: &[BreakPoint],
+) -> anyhow::Result<()> {
+    for (name, line_number) in script.breakpoint_references() {
+        if !breakpoints.iter().any(|bp| bp.name.as_ref() == Some(&name)) {
+            bail!(
+                "{}:{}: no `// #break: {}` marker found for `#break {}`",
+                source_path.display(),
+                line_number.0,
+                name,
+                name
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// Collects the package directories in `directory` and, recursively, in its subdirectories
+/// that are not packages themselves. `exclude` and `members` are the paths relative to the
+/// workspace directory from the workspace's Cargo.toml, found packages are removed from
+/// `members`.
+fn find_packages(
+    workspace_directory: &Path,
+    directory: &Path,
+    exclude: &HashSet<String>,
+    members: &mut HashSet<String>,
+    packages: &mut Vec<PathBuf>,
+) -> anyhow::Result<()> {
+    for dir_entry in std::fs::read_dir(directory)? {
+        let dir_entry = dir_entry?;
+
+        if !dir_entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        let test_directory = dir_entry.path();
+        let relative_path = test_directory
+            .strip_prefix(workspace_directory)?
+            .to_string_lossy()
+            .replace('\\', "/");
+
+        if exclude.contains(&relative_path)
+            || dir_entry.file_name().to_string_lossy().starts_with('.')
+        {
+            // Skip this directory if it is in the "exlude" list of the workspace Cargo.toml
+            continue;
+        }
+
+        if test_directory.join("Cargo.toml").exists() {
+            info!(" - Found Cargo package `{}`", test_directory.display());
+            members.remove(&relative_path);
+            packages.push(test_directory);
+        } else {
+            let found = packages.len();
+            find_packages(
+                workspace_directory,
+                &test_directory,
+                exclude,
+                members,
+                packages,
+            )?;
+
+            if packages.len() == found {
+                warn!(" - {} has no Cargo.toml", test_directory.display());
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Whether the file at `source_path` is an integration test, i.e. in the `tests` directory
+fn is_integration_test(source_path: &Path) -> bool {
+    source_path
+        .parent()
+        .is_some_and(|parent| parent.file_name() == Some(OsStr::new("tests")))
+}
+
+/// Whether a source file has `#[test]` functions, which are not part of the test program
+/// unless it is the harness of an integration test
+fn defines_test_functions(contents: &str) -> bool {
+    contents.lines().any(|line| line.trim() == "#[test]")
+}
+
+/// The name of the executable of a test with `#debuggee-target`, e.g. `t1-example-show-vec`
+fn debuggee_executable_name(
+    project_directory: &Path,
+    debuggee_target: &DebuggeeTarget,
+) -> OsString {
+    executable_name(format!(
+        "{}-{}-{}",
+        project_directory.file_name().unwrap().to_string_lossy(),
+        debuggee_target.kind.as_str(),
+        debuggee_target.name
+    ))
+}
+
 fn executable_name(name: impl Into<OsString>) -> OsString {
     let mut name: OsString = name.into();
 
@@ -282,8 +704,38 @@ mod tests {
 
     use super::WorkspaceToml;
 
+    #[test]
+    fn unit_tests_are_rejected() {
+        let package = std::env::temp_dir().join(format!("dbt-unit-tests-{}", std::process::id()));
+        std::fs::create_dir_all(package.join("src")).unwrap();
+        std::fs::create_dir_all(package.join("tests")).unwrap();
+        std::fs::write(package.join("Cargo.toml"), "[package]\nname = \"t1\"").unwrap();
+        std::fs::write(
+            package.join("tests/harness.rs"),
+            "/***\nrun\n***/\n#[test]\nfn t() {}",
+        )
+        .unwrap();
+
+        std::fs::write(package.join("src/main.rs"), "/***\nrun\n***/\nfn main() {}").unwrap();
+        super::analyze_cargo_package(&std::env::temp_dir(), &package, &Default::default()).unwrap();
+
+        std::fs::write(
+            package.join("src/main.rs"),
+            "/***\nrun\n***/\nfn main() {}\n#[cfg(test)]\nmod tests {\n    #[test]\n    fn t() {}\n}",
+        )
+        .unwrap();
+        let error =
+            super::analyze_cargo_package(&std::env::temp_dir(), &package, &Default::default())
+                .unwrap_err();
+        assert!(error
+            .to_string()
+            .contains("`#[test]` functions are only run"));
+
+        std::fs::remove_dir_all(&package).unwrap();
+    }
+
     fn hashset(items: &[&str]) -> HashSet<String> {
-        items.into_iter().map(|s| s.to_string()).collect()
+        items.iter().map(|s| s.to_string()).collect()
     }
 
     #[test]
@@ -0,0 +1,90 @@
+//! Whether dbt runs on CI, and on which provider, so that scripts can e.g. relax
+//! timing-sensitive checks there via `#if @ci`. Providers are told apart by the environment
+//! variables they set, and most of them also set `CI`, which is enough to tell that the run
+//! is on CI at all.
+
+use lazy_static::lazy_static;
+
+/// The variable that is defined when running on CI
+pub const VARIABLE: &str = "@ci";
+
+/// The variable holding the name of the CI provider, if it is known
+pub const PROVIDER_VARIABLE: &str = "@ci_provider";
+
+/// The environment variables identifying CI providers, and the names of the providers
+const PROVIDERS: &[(&str, &str)] = &[
+    ("GITHUB_ACTIONS", "github-actions"),
+    ("GITLAB_CI", "gitlab"),
+    ("TF_BUILD", "azure-pipelines"),
+    ("BUILDKITE", "buildkite"),
+    ("CIRCLECI", "circleci"),
+    ("TRAVIS", "travis"),
+    ("APPVEYOR", "appveyor"),
+    ("JENKINS_URL", "jenkins"),
+    ("TEAMCITY_VERSION", "teamcity"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ci {
+    /// The name of the provider (e.g. `github-actions`), if it is one of the known ones
+    pub provider: Option<&'static str>,
+}
+
+lazy_static! {
+    static ref CI: Option<Ci> = detect(|name| std::env::var(name).ok());
+}
+
+/// Whether dbt runs on CI, going by the environment
+pub fn ci() -> Option<Ci> {
+    *CI
+}
+
+/// Detects the CI from the environment variables given by `var`
+fn detect(var: impl Fn(&str) -> Option<String>) -> Option<Ci> {
+    let is_set = |name: &str| {
+        var(name).is_some_and(|value| !value.is_empty() && value != "false" && value != "0")
+    };
+
+    let provider = PROVIDERS
+        .iter()
+        .find(|(name, _)| is_set(name))
+        .map(|(_, provider)| *provider);
+
+    if provider.is_some() || is_set("CI") {
+        Some(Ci { provider })
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Ci;
+
+    #[test]
+    fn detect() {
+        let detect = |vars: &[(&str, &str)]| {
+            super::detect(|name| {
+                vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| value.to_string())
+            })
+        };
+
+        assert_eq!(detect(&[]), None);
+        assert_eq!(detect(&[("CI", "false")]), None);
+        assert_eq!(detect(&[("CI", "true")]), Some(Ci { provider: None }));
+        assert_eq!(
+            detect(&[("CI", "true"), ("GITHUB_ACTIONS", "true")]),
+            Some(Ci {
+                provider: Some("github-actions")
+            })
+        );
+        assert_eq!(
+            detect(&[("TF_BUILD", "True")]),
+            Some(Ci {
+                provider: Some("azure-pipelines")
+            })
+        );
+    }
+}
@@ -0,0 +1,156 @@
+//! Making test programs debuggable by LLDB on macOS (`--codesign`). macOS only lets a debugger
+//! attach to programs that are signed with the `com.apple.security.get-task-allow`
+//! entitlement, and otherwise LLDB fails with rather opaque errors, which
+//! [debugging_denied] turns into a hint at what to do about it.
+
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{bail, Context};
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::{debugger::DebuggerOutput, prettify_path};
+
+/// The ad-hoc signing identity, which doesn't need a certificate
+pub const AD_HOC_IDENTITY: &str = "-";
+
+const ENTITLEMENTS: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+    <key>com.apple.security.get-task-allow</key>
+    <true/>
+</dict>
+</plist>
+"#;
+
+/// Writes the entitlements debuggees are signed with to `directory`, returning the file's path
+pub fn write_entitlements(directory: &Path) -> anyhow::Result<PathBuf> {
+    let path = directory.join("dbt-entitlements.plist");
+    std::fs::write(&path, ENTITLEMENTS)?;
+    Ok(path)
+}
+
+/// Signs `debuggee` in place with `identity` and the entitlements at `entitlements`
+pub fn sign(debuggee: &Path, identity: &str, entitlements: &Path) -> anyhow::Result<()> {
+    if !cfg!(target_os = "macos") {
+        bail!("--codesign is only supported on macOS");
+    }
+
+    let output = sign_command(debuggee, identity, entitlements)
+        .output()
+        .context("Could not run `codesign`")?;
+
+    if !output.status.success() {
+        bail!(
+            "Could not sign {}:\n{}",
+            prettify_path(debuggee),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(())
+}
+
+fn sign_command(debuggee: &Path, identity: &str, entitlements: &Path) -> Command {
+    let mut command = Command::new("codesign");
+
+    command
+        .arg("--force")
+        .arg("--sign")
+        .arg(identity)
+        .arg("--entitlements")
+        .arg(entitlements)
+        .arg(OsStr::new(debuggee));
+
+    command
+}
+
+/// Returns an explanation if the output of LLDB shows that the OS did not let it debug the
+/// test program
+pub fn debugging_denied(debugger_output: &DebuggerOutput) -> Option<String> {
+    lazy_static! {
+        static ref DENIED_REGEX: Regex = Regex::new(concat!(
+            r"Not allowed to attach to process",
+            r"|'A' packet returned an error: 8",
+            r"|unable to start the exception thread",
+            r"|attach failed \(.*(?:denied|not permitted)",
+        ))
+        .unwrap();
+    }
+
+    let line = debugger_output
+        .stdout
+        .lines()
+        .chain(debugger_output.stderr.lines())
+        .find(|line| DENIED_REGEX.is_match(line))?;
+
+    Some(format!(
+        "The OS did not allow LLDB to debug the test program:\n  {}\n\
+         On macOS, the test program has to be signed with the \
+         `com.apple.security.get-task-allow` entitlement (see `--codesign`), and debugging has \
+         to be enabled via `DevToolsSecurity -enable` or Developer Mode.\n",
+        line.trim()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::debugger::{DebuggerExitStatus, DebuggerOutput};
+
+    #[test]
+    fn sign_command() {
+        let command = super::sign_command(
+            Path::new("target/debug/my-test"),
+            super::AD_HOC_IDENTITY,
+            Path::new("target/dbt-entitlements.plist"),
+        );
+
+        assert_eq!(command.get_program(), "codesign");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            vec![
+                "--force",
+                "--sign",
+                "-",
+                "--entitlements",
+                "target/dbt-entitlements.plist",
+                "target/debug/my-test"
+            ]
+        );
+    }
+
+    #[test]
+    fn debugging_denied() {
+        let output = |stdout: &str, stderr: &str| DebuggerOutput {
+            stdout: stdout.to_string(),
+            stderr: stderr.to_string(),
+            exit_status: DebuggerExitStatus::Success,
+            program_output: None,
+        };
+
+        let denied = output(
+            "(lldb) process launch\n",
+            "error: process exited with status -1 (attach failed (Not allowed to attach to process.  Look in the console messages (Console.app), near the debugserver entries, when the attach failed.))\n",
+        );
+        let message = super::debugging_denied(&denied).unwrap();
+        assert!(message.starts_with(
+            "The OS did not allow LLDB to debug the test program:\n  error: process exited with status -1"
+        ));
+
+        let packet_error = output(
+            "error: process launch failed: 'A' packet returned an error: 8\n",
+            "",
+        );
+        assert!(super::debugging_denied(&packet_error).is_some());
+
+        let other_error = output("(lldb) p x\n", "error: use of undeclared identifier 'x'\n");
+        assert!(super::debugging_denied(&other_error).is_none());
+    }
+}
@@ -0,0 +1,283 @@
+//! Comparing the values printed via `#print` by different debuggers (`--consistency`).
+//! Each debugger renders values in its own format, so the outputs are reduced to the
+//! literals they contain (numbers, strings, characters and booleans) before comparing them.
+//! This catches cases where e.g. GDB and LLDB disagree about the contents of a value.
+
+use std::collections::BTreeMap;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::{prettify_path, test_result::TestResult};
+
+/// A `#print` statement whose value was rendered differently by different debuggers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    /// See [TestResult::test_label]
+    pub test: String,
+    pub location: String,
+    pub expression: String,
+    /// The output of each debugger, labeled with its name and version
+    pub outputs: Vec<(String, String)>,
+}
+
+pub fn mismatches(test_results: &[TestResult]) -> Vec<Mismatch> {
+    let mut by_print: BTreeMap<(String, u32), Mismatch> = BTreeMap::new();
+
+    for test_result in test_results {
+        let debugger = format!(
+            "{} {}",
+            test_result.debugger_kind.name(),
+            test_result.debugger_version
+        );
+
+        for printed_value in &test_result.printed_values {
+            let mismatch = by_print
+                .entry((test_result.test_label(), printed_value.line_number.0))
+                .or_insert_with(|| Mismatch {
+                    test: test_result.test_label(),
+                    location: format!(
+                        "{}:{}",
+                        prettify_path(&test_result.source_path),
+                        printed_value.line_number.0
+                    ),
+                    expression: printed_value.expression.to_string(),
+                    outputs: vec![],
+                });
+
+            // With --stress each debugger prints the value several times
+            if !mismatch.outputs.iter().any(|(d, _)| *d == debugger) {
+                mismatch
+                    .outputs
+                    .push((debugger.clone(), printed_value.output.clone()));
+            }
+        }
+    }
+
+    by_print
+        .into_values()
+        .filter(|mismatch| {
+            let literals: Vec<Vec<String>> = mismatch
+                .outputs
+                .iter()
+                .map(|(_, output)| value_literals(output))
+                .collect();
+
+            literals.iter().any(|l| *l != literals[0])
+        })
+        .collect()
+}
+
+/// Returns the literals in a value as rendered by a debugger, e.g. `["3", "1", "2", "3"]`
+/// for GDB's `$1 = Vec(size=3) = {1, 2, 3}` as well as for LLDB's
+/// `(Vec<i32>) v = size=3 { [0] = 1 [1] = 2 [2] = 3 }`. Addresses are left out since they
+/// usually differ between debuggers.
+pub fn value_literals(output: &str) -> Vec<String> {
+    lazy_static! {
+        // CDB's `dx` appends the type of each value
+        static ref CDB_TYPE: Regex = Regex::new(r"\[Type: [^\]]*\]").unwrap();
+        // `$1 = `, `v = ` or `v : ` in front of the value
+        static ref NAME: Regex = Regex::new(r"^\s*(?:\$\d+|[A-Za-z_][\w.]*)\s*[=:]\s").unwrap();
+        static ref INDEX: Regex = Regex::new(r"\[\d+\]\s*[=:]").unwrap();
+        // LLDB labels the fields of tuples with their index, as in `(0 = 1, 1 = 2)`
+        static ref TUPLE_FIELD: Regex = Regex::new(r"(^|[({,])\s*\d+\s*=\s").unwrap();
+        static ref ADDRESS: Regex =
+            Regex::new(r"\b(?:0x)?[0-9a-fA-F]{8}`[0-9a-fA-F]{8}\b|\b0x[0-9a-fA-F]{6,}\b").unwrap();
+        // GDB prints characters as `97 'a'`
+        static ref CHAR_CODE: Regex = Regex::new(r"-?\d+\s+('(?:[^'\\]|\\.)+')").unwrap();
+        static ref LITERAL: Regex = Regex::new(
+            r#""(?:[^"\\]|\\.)*"|'(?:[^'\\]|\\.)+'|\b0x[0-9a-fA-F]+\b|-?\b\d+(?:\.\d+)?\b|\btrue\b|\bfalse\b"#
+        )
+        .unwrap();
+    }
+
+    let mut literals = vec![];
+
+    for line in output.lines() {
+        let line = CDB_TYPE.replace_all(line, "");
+        let line = strip_type_prefix(&line);
+        let line = NAME.replace(line, "");
+        let line = INDEX.replace_all(&line, "");
+        let line = TUPLE_FIELD.replace_all(&line, "$1");
+        let line = ADDRESS.replace_all(&line, "");
+        let line = CHAR_CODE.replace_all(&line, "$1");
+
+        for literal in LITERAL.find_iter(&line) {
+            let literal = literal.as_str();
+
+            // Debuggers differ in whether they print numbers as hex
+            let literal = match literal.strip_prefix("0x") {
+                Some(hex) => match u64::from_str_radix(hex, 16) {
+                    Ok(value) => value.to_string(),
+                    Err(_) => literal.to_string(),
+                },
+                None => literal.to_string(),
+            };
+
+            literals.push(literal);
+        }
+    }
+
+    literals
+}
+
+/// Strips the type LLDB prints in front of values, e.g. `(core::option::Option<(i32, i32)>)`
+fn strip_type_prefix(line: &str) -> &str {
+    let trimmed = line.trim_start();
+
+    if !trimmed.starts_with('(') {
+        return line;
+    }
+
+    let mut depth = 0;
+
+    for (index, c) in trimmed.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+
+                if depth == 0 {
+                    return &trimmed[index + 1..];
+                }
+            }
+            _ => {}
+        }
+    }
+
+    line
+}
+
+pub fn print_consistency_report(mismatches: &[Mismatch]) {
+    println!();
+
+    if mismatches.is_empty() {
+        println!("All debuggers printed consistent values.");
+        return;
+    }
+
+    println!("Values printed differently by different debuggers:");
+
+    for mismatch in mismatches {
+        println!();
+        println!(
+            "  `#print {}` at {} in {}",
+            mismatch.expression, mismatch.location, mismatch.test
+        );
+
+        for (debugger, output) in &mismatch.outputs {
+            let output = output.split_whitespace().collect::<Vec<_>>().join(" ");
+            println!("      {}: {}", debugger, output);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use crate::{
+        cargo_test_directory::BuildConfig,
+        debugger::{DebuggerKind, PrintedValue},
+        script::{LineNumber, PhaseConfig},
+        test_result::{Status, TestResult},
+    };
+
+    use super::value_literals;
+
+    #[test]
+    fn literals_of_rendered_values() {
+        let expected = vec!["3", "1", "2", "3"];
+
+        assert_eq!(value_literals("$1 = Vec(size=3) = {1, 2, 3}"), expected);
+        assert_eq!(
+            value_literals(
+                "(alloc::vec::Vec<i32, alloc::alloc::Global>) v = size=3 {\n  [0] = 1\n  [1] = 2\n  [2] = 3\n}"
+            ),
+            expected
+        );
+        assert_eq!(
+            value_literals(
+                "v : { len=3 } [Type: alloc::vec::Vec<i32>]\n    [0] : 1 [Type: int]\n    [1] : 2 [Type: int]\n    [2] : 0x3 [Type: int]"
+            ),
+            expected
+        );
+
+        assert_eq!(
+            value_literals("$2 = (97 'a', \"abc\", true, 0x7ffe5b2c1234)"),
+            vec!["'a'", "\"abc\"", "true"]
+        );
+        assert_eq!(
+            value_literals(
+                "((char, &str, bool, *const u8)) $0 = ('a', \"abc\", true, 0x00007ffe5b2c1234)"
+            ),
+            vec!["'a'", "\"abc\"", "true"]
+        );
+
+        assert_eq!(value_literals("$3 = (1, -2)"), vec!["1", "-2"]);
+        assert_eq!(
+            value_literals("((i32, i32)) t = (0 = 1, 1 = -2)"),
+            vec!["1", "-2"]
+        );
+        assert_eq!(
+            value_literals("((i32, i32)) t = {\n  0 = 1\n  1 = -2\n}"),
+            vec!["1", "-2"]
+        );
+    }
+
+    #[test]
+    fn mismatches() {
+        let test_result = |kind: DebuggerKind, output: &str| TestResult {
+            test_name: "t".into(),
+            phase: PhaseConfig::Live,
+            debugger_kind: kind,
+            debugger_version: "1.0".into(),
+            cargo_profile: Arc::from("debug"),
+            build: BuildConfig::default(),
+            status: Box::new(Status::Passed),
+            source_path: "src/main.rs".into(),
+            debugger_output_stdout_path: None,
+            debugger_output_stderr_path: None,
+            command_timings: vec![],
+            resource_usage: None,
+            debugger_duration: None,
+            printed_values: vec![PrintedValue {
+                expression: "v".into(),
+                line_number: LineNumber(3),
+                output: output.to_string(),
+            }],
+            matched_checks: vec![],
+            diagnostics: vec![],
+            build_output: String::new(),
+        };
+
+        let consistent = [
+            test_result(DebuggerKind::Gdb, "$1 = Vec(size=2) = {1, 2}"),
+            test_result(
+                DebuggerKind::Lldb,
+                "(Vec<i32>) v = size=2 { [0] = 1 [1] = 2 }",
+            ),
+        ];
+        assert!(super::mismatches(&consistent).is_empty());
+
+        let inconsistent = [
+            test_result(DebuggerKind::Gdb, "$1 = Vec(size=2) = {1, 2}"),
+            test_result(
+                DebuggerKind::Lldb,
+                "(Vec<i32>) v = size=2 { [0] = 1 [1] = 3 }",
+            ),
+        ];
+        let mismatches = super::mismatches(&inconsistent);
+        assert_eq!(mismatches.len(), 1);
+        assert_eq!(mismatches[0].expression, "v");
+        assert_eq!(mismatches[0].location, "src/main.rs:3");
+        assert_eq!(
+            mismatches[0]
+                .outputs
+                .iter()
+                .map(|(debugger, _)| &debugger[..])
+                .collect::<Vec<_>>(),
+            vec!["gdb 1.0", "lldb 1.0"]
+        );
+    }
+}
@@ -0,0 +1,486 @@
+//! Which variables declared in the test programs are actually inspected by the test
+//! scripts (`--coverage`). This makes visible which types, in particular which std
+//! collection types, the test suite does not check the debuginfo of.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+};
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::{
+    cargo_test_directory::TestDefinition,
+    debugger::Debugger,
+    script::{CommandCheck, EvaluationContext, PhaseConfig, Script, Statement},
+};
+
+/// The std types whose debuginfo the test suite should cover. These are reported even if
+/// no test declares a variable of that type.
+const STD_TYPES: &[&str] = &[
+    "Arc",
+    "BTreeMap",
+    "BTreeSet",
+    "BinaryHeap",
+    "Box",
+    "Cell",
+    "HashMap",
+    "HashSet",
+    "LinkedList",
+    "Mutex",
+    "Option",
+    "Rc",
+    "RefCell",
+    "Result",
+    "String",
+    "Vec",
+    "VecDeque",
+];
+
+/// A `let` binding in a test program
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Variable {
+    pub name: String,
+    /// The outermost type of the variable, e.g. `HashMap` for `HashMap<u32, String>`,
+    /// if it can be told from the declaration
+    pub type_name: Option<String>,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CoverageEntry {
+    pub test_name: Arc<str>,
+    pub variable: Variable,
+    pub inspected: bool,
+}
+
+/// Determines for each variable declared in the given tests whether it is inspected by
+/// any of the statements that are executed with one of the debuggers and Cargo profiles.
+pub fn coverage(
+    test_definitions: &[&TestDefinition],
+    debuggers: &[Debugger],
+    cargo_profiles: &[Arc<str>],
+) -> anyhow::Result<Vec<CoverageEntry>> {
+    let mut entries = vec![];
+
+    for test_definition in test_definitions {
+        let source = std::fs::read_to_string(&test_definition.absolute_source_path)?;
+
+        let mut inspected = HashSet::new();
+
+        for debugger in debuggers {
+            for cargo_profile in cargo_profiles {
+                let live_context = debugger.evaluation_context(
+                    &test_definition.config,
+                    cargo_profile,
+                    &PhaseConfig::Live,
+                );
+
+                for phase in test_definition.script.phases(&live_context) {
+                    let context =
+                        debugger.evaluation_context(&test_definition.config, cargo_profile, &phase);
+                    inspected.extend(inspected_variables(&test_definition.script, &context));
+                }
+            }
+        }
+
+        for variable in declared_variables(&source) {
+            entries.push(CoverageEntry {
+                test_name: test_definition.name.clone(),
+                inspected: inspected.contains(&variable.name),
+                variable,
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+/// Merges the entries of the same variable, which [coverage] returns once per build
+/// configuration. A variable counts as inspected if it is in any configuration.
+pub fn deduplicate(entries: Vec<CoverageEntry>) -> Vec<CoverageEntry> {
+    let mut merged: Vec<CoverageEntry> = vec![];
+    let mut indices: HashMap<(Arc<str>, usize, String), usize> = HashMap::new();
+
+    for entry in entries {
+        let key = (
+            entry.test_name.clone(),
+            entry.variable.line,
+            entry.variable.name.clone(),
+        );
+
+        match indices.get(&key) {
+            Some(&index) => merged[index].inspected |= entry.inspected,
+            None => {
+                indices.insert(key, merged.len());
+                merged.push(entry);
+            }
+        }
+    }
+
+    merged
+}
+
+/// Returns the `let` bindings in `source`, except for ones starting with an underscore.
+/// Bindings in tuple and struct patterns are included if the pattern is on a single line.
+pub fn declared_variables(source: &str) -> Vec<Variable> {
+    lazy_static! {
+        static ref LET: Regex = Regex::new(
+            r"\blet\s+(?:mut\s+)?([A-Za-z][A-Za-z0-9_]*)\s*(?::\s*([^=]+?))?\s*=\s*(.*)"
+        )
+        .unwrap();
+        // `let (a, b)`, `let Point { x, y }` or `let Wrapper(inner)`, up to the opening bracket
+        static ref LET_PATTERN: Regex =
+            Regex::new(r"\blet\s+(?:[A-Z][A-Za-z0-9_]*(?:::[A-Za-z0-9_]+)*\s*)?[({]").unwrap();
+    }
+
+    let mut variables = vec![];
+
+    for (index, line) in source.lines().enumerate() {
+        if line.trim_start().starts_with("//") {
+            continue;
+        }
+
+        for captures in LET.captures_iter(line) {
+            let type_name = match captures.get(2) {
+                Some(annotation) => annotated_type_name(annotation.as_str()),
+                None => inferred_type_name(&captures[3]),
+            };
+
+            variables.push(Variable {
+                name: captures[1].to_string(),
+                type_name,
+                line: index + 1,
+            });
+        }
+
+        for pattern_start in LET_PATTERN.find_iter(line) {
+            let open = pattern_start.end() - 1;
+
+            // The types of the bindings can't be told without resolving the pattern
+            variables.extend(
+                pattern_bindings(&line[open..])
+                    .into_iter()
+                    .map(|name| Variable {
+                        name: name.to_string(),
+                        type_name: None,
+                        line: index + 1,
+                    }),
+            );
+        }
+    }
+
+    variables
+}
+
+/// Returns the variables bound by the pattern at the start of `s`, which starts with an
+/// opening bracket, e.g. `x` and `renamed` for `{ x, y: renamed, .. } = point`. Returns
+/// nothing if the pattern doesn't end on the same line.
+fn pattern_bindings(s: &str) -> Vec<&str> {
+    lazy_static! {
+        static ref IDENTIFIER: Regex = Regex::new(r"([A-Za-z_][A-Za-z0-9_]*)\s*(::?)?").unwrap();
+    }
+
+    let mut depth = 0;
+    let mut end = None;
+
+    for (index, c) in s.char_indices() {
+        match c {
+            '(' | '{' | '[' => depth += 1,
+            ')' | '}' | ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    end = Some(index);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let pattern = match end {
+        Some(end) => &s[..end],
+        None => return vec![],
+    };
+
+    IDENTIFIER
+        .captures_iter(pattern)
+        // Field names and path segments are followed by colons
+        .filter(|captures| captures.get(2).is_none())
+        .map(|captures| captures.get(1).unwrap().as_str())
+        .filter(|name| {
+            !matches!(*name, "mut" | "ref")
+                && !name.starts_with('_')
+                && !name.starts_with(|c: char| c.is_ascii_uppercase())
+        })
+        .collect()
+}
+
+/// `&mut std::collections::HashMap<u32, String>` -> `HashMap`
+fn annotated_type_name(annotation: &str) -> Option<String> {
+    let mut annotation = annotation.trim();
+
+    while let Some(rest) = annotation
+        .strip_prefix('&')
+        .or_else(|| annotation.strip_prefix("mut "))
+    {
+        annotation = rest.trim_start();
+    }
+
+    if annotation.starts_with('[') {
+        return Some(
+            if annotation.contains(';') {
+                "array"
+            } else {
+                "slice"
+            }
+            .to_string(),
+        );
+    }
+
+    if annotation.starts_with('(') {
+        return Some("tuple".to_string());
+    }
+
+    let path = annotation.split('<').next().unwrap();
+    let name = path.rsplit("::").next().unwrap().trim();
+
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+/// Guesses the type of a variable from its initializer, e.g. `HashMap` for `HashMap::new()`
+fn inferred_type_name(initializer: &str) -> Option<String> {
+    lazy_static! {
+        static ref CONSTRUCTOR: Regex =
+            Regex::new(r"^(?:[a-z_][A-Za-z0-9_]*::)*([A-Z][A-Za-z0-9_]*)").unwrap();
+    }
+
+    let initializer = initializer.trim();
+
+    if initializer.starts_with("vec!") {
+        return Some("Vec".to_string());
+    }
+
+    if initializer.starts_with("format!")
+        || (initializer.starts_with('"')
+            && (initializer.contains(".to_string()") || initializer.contains(".to_owned()")))
+    {
+        return Some("String".to_string());
+    }
+
+    let name = CONSTRUCTOR.captures(initializer)?.get(1)?.as_str();
+
+    Some(
+        match name {
+            "Some" | "None" => "Option",
+            "Ok" | "Err" => "Result",
+            name => name,
+        }
+        .to_string(),
+    )
+}
+
+/// Returns all identifiers in expressions that are printed by the statements of `script`
+/// applicable in `context`, e.g. `map` and `len` for `print map.len()`.
+pub fn inspected_variables(script: &Script, context: &EvaluationContext) -> HashSet<String> {
+    lazy_static! {
+        static ref PRINT_COMMAND: Regex = Regex::new(
+            r"^\s*(?:print(?:/\w+)?|p(?:/\w+)?|dx(?:\s+-\w+)*|\?\?|frame\s+variable|v|expr(?:ession)?|display)\s+(.+)$"
+        )
+        .unwrap();
+        static ref IDENTIFIER: Regex = Regex::new(r"[A-Za-z_][A-Za-z0-9_]*").unwrap();
+    }
+
+    let mut identifiers = HashSet::new();
+
+    script.walk_applicable_leaves(context, &mut |statement| {
+        let expression = match statement {
+            Statement::Exec(command, _, _) => PRINT_COMMAND
+                .captures(command)
+                .map(|captures| captures[1].to_string()),
+            Statement::CommandCheck(CommandCheck::Memory(expression, _), _, _) => {
+                Some(expression.to_string())
+            }
+            _ => None,
+        };
+
+        if let Some(expression) = expression {
+            identifiers.extend(
+                IDENTIFIER
+                    .find_iter(&expression)
+                    .map(|identifier| identifier.as_str().to_string()),
+            );
+        }
+
+        true
+    });
+
+    identifiers
+}
+
+pub fn print_coverage_report(entries: &[CoverageEntry]) {
+    let mut by_type: BTreeMap<&str, Vec<&CoverageEntry>> = BTreeMap::new();
+
+    for entry in entries {
+        let type_name = entry
+            .variable
+            .type_name
+            .as_deref()
+            .unwrap_or("<unknown type>");
+        by_type.entry(type_name).or_default().push(entry);
+    }
+
+    println!();
+    println!("Debuginfo coverage:");
+    println!();
+
+    for (type_name, entries) in &by_type {
+        let inspected = entries.iter().filter(|entry| entry.inspected).count();
+
+        println!(
+            "  {}: {} of {} variables inspected",
+            type_name,
+            inspected,
+            entries.len()
+        );
+
+        for entry in entries.iter().filter(|entry| !entry.inspected) {
+            println!(
+                "      not inspected: `{}` in {}:{}",
+                entry.variable.name, entry.test_name, entry.variable.line
+            );
+        }
+    }
+
+    let undeclared: Vec<&str> = STD_TYPES
+        .iter()
+        .copied()
+        .filter(|std_type| !by_type.contains_key(std_type))
+        .collect();
+
+    if !undeclared.is_empty() {
+        if !by_type.is_empty() {
+            println!();
+        }
+        println!(
+            "  std types not declared in any test: {}",
+            undeclared.join(", ")
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use crate::script::{parse_script, EvaluationContext};
+
+    use super::{CoverageEntry, Variable};
+
+    fn variable(name: &str, type_name: Option<&str>, line: usize) -> Variable {
+        Variable {
+            name: name.to_string(),
+            type_name: type_name.map(|t| t.to_string()),
+            line,
+        }
+    }
+
+    #[test]
+    fn declared_variables() {
+        let source = r#"
+fn main() {
+    // let commented = 1;
+    let v = vec![1, 2, 3];
+    let mut map: std::collections::HashMap<u32, String> = HashMap::new();
+    let s = "abc".to_string();
+    let b = Box::new(5);
+    let maybe = Some(1);
+    let bytes: &[u8] = &[1, 2];
+    let _unused = 0;
+    let x = 1 + 2;
+    let (first, mut second): (u32, u32) = (1, 2);
+    let Point { x: px, y, .. } = point;
+    let Wrapper(ref inner, _) = wrapper;
+}
+"#;
+
+        assert_eq!(
+            super::declared_variables(source),
+            vec![
+                variable("v", Some("Vec"), 4),
+                variable("map", Some("HashMap"), 5),
+                variable("s", Some("String"), 6),
+                variable("b", Some("Box"), 7),
+                variable("maybe", Some("Option"), 8),
+                variable("bytes", Some("slice"), 9),
+                variable("x", None, 11),
+                variable("first", None, 12),
+                variable("second", None, 12),
+                variable("px", None, 13),
+                variable("y", None, 13),
+                variable("inner", None, 14),
+            ]
+        );
+    }
+
+    #[test]
+    fn deduplicate() {
+        let entry = |name: &str, line, inspected| CoverageEntry {
+            test_name: "t1".into(),
+            variable: variable(name, None, line),
+            inspected,
+        };
+
+        // One entry per build configuration
+        let entries = vec![
+            entry("a", 3, false),
+            entry("b", 4, false),
+            entry("a", 3, true),
+            entry("b", 4, false),
+        ];
+
+        assert_eq!(
+            super::deduplicate(entries),
+            vec![entry("a", 3, true), entry("b", 4, false)]
+        );
+    }
+
+    #[test]
+    fn inspected_variables() {
+        let script = parse_script(
+            r#"
+/***
+#if @gdb
+  print map.len()
+#if @cdb
+  dx -r2 deque
+frame variable v
+#check-memory &bytes 2 01 02
+continue
+***/
+"#,
+            None,
+        )
+        .unwrap();
+
+        let context = EvaluationContext {
+            values: Default::default(),
+        };
+
+        let expected: HashSet<String> = ["v", "bytes"].iter().map(|s| s.to_string()).collect();
+        assert_eq!(super::inspected_variables(&script, &context), expected);
+
+        let context = context.with_additional_values(vec![("@gdb".into(), "true".into())]);
+
+        let expected: HashSet<String> = ["v", "bytes", "map", "len"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        assert_eq!(super::inspected_variables(&script, &context), expected);
+    }
+}
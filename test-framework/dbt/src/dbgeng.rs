@@ -0,0 +1,310 @@
+//! A CDB backend that drives the debugger engine (`dbgeng.dll`) in-process via its COM API
+//! instead of running `cdb.exe` (`-d dbgeng`). It runs the same scripts as CDB, but gets
+//! the output of each command through an output callback, tagged with whether it is an
+//! error, instead of scraping a console. Only available on Windows, with the `dbgeng` feature.
+
+use std::{path::Path, time::Duration};
+
+use crate::debugger::{DebuggerOutput, RunMeasurements};
+
+/// The value to pass to `-d` for selecting this backend
+pub const COMMAND: &str = "dbgeng";
+
+/// Returns the version of the debugger engine, e.g. `10.0.22621.1`
+pub fn version() -> anyhow::Result<String> {
+    #[cfg(all(windows, feature = "dbgeng"))]
+    {
+        engine::version()
+    }
+
+    #[cfg(not(all(windows, feature = "dbgeng")))]
+    {
+        anyhow::bail!("{}", UNSUPPORTED)
+    }
+}
+
+/// Runs the CDB script at `script_file_path` against `debuggee`, or against `crashdump` if
+/// given, like [crate::debugger::Debugger::run] does with `cdb.exe`.
+pub fn run(
+    script_file_path: &Path,
+    debuggee: &Path,
+    crashdump: Option<&Path>,
+    timeout: Option<Duration>,
+) -> anyhow::Result<(DebuggerOutput, RunMeasurements)> {
+    #[cfg(all(windows, feature = "dbgeng"))]
+    {
+        engine::run(script_file_path, debuggee, crashdump, timeout)
+    }
+
+    #[cfg(not(all(windows, feature = "dbgeng")))]
+    {
+        let _ = (script_file_path, debuggee, crashdump, timeout);
+        anyhow::bail!("{}", UNSUPPORTED)
+    }
+}
+
+#[cfg(not(all(windows, feature = "dbgeng")))]
+const UNSUPPORTED: &str =
+    "The DbgEng backend requires a build of dbt for Windows with `--features dbgeng`";
+
+#[cfg(all(windows, feature = "dbgeng"))]
+mod engine {
+    use std::{
+        path::Path,
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    };
+
+    use anyhow::{bail, Context};
+    use lazy_static::lazy_static;
+    use regex::Regex;
+    use windows::{
+        core::{implement, Interface, HSTRING, PCWSTR},
+        Win32::System::Diagnostics::Debug::Extensions::{
+            DebugCreate, IDebugClient5, IDebugControl4, IDebugOutputCallbacksWide,
+            IDebugOutputCallbacksWide_Impl, IDebugSymbols3, DEBUG_ATTACH_DEFAULT,
+            DEBUG_END_ACTIVE_TERMINATE, DEBUG_EXECUTE_ECHO, DEBUG_EXECUTE_NOT_LOGGED,
+            DEBUG_INTERRUPT_ACTIVE, DEBUG_OUTCTL_THIS_CLIENT, DEBUG_OUTPUT_ERROR,
+            DEBUG_STATUS_BREAK, DEBUG_STATUS_NO_DEBUGGEE, DEBUG_WAIT_DEFAULT,
+        },
+    };
+
+    use crate::{
+        debugger::{DebuggerExitStatus, DebuggerOutput, OutputTimestamps, RunMeasurements},
+        hang::Hang,
+    };
+
+    /// `DEBUG_ONLY_THIS_PROCESS` from the process creation flags, as used by `cdb.exe`
+    const DEBUG_ONLY_THIS_PROCESS: u32 = 0x2;
+
+    const INFINITE: u32 = u32::MAX;
+
+    #[derive(Default)]
+    struct CollectedOutput {
+        stdout: String,
+        stderr: String,
+        stdout_timestamps: OutputTimestamps,
+        stderr_timestamps: OutputTimestamps,
+    }
+
+    /// Collects everything the engine outputs for our client. Errors go to stderr, the
+    /// rest (including the echoed commands) to stdout, as with `cdb.exe`.
+    #[implement(IDebugOutputCallbacksWide)]
+    struct OutputCollector {
+        start: Instant,
+        output: Arc<Mutex<CollectedOutput>>,
+    }
+
+    impl IDebugOutputCallbacksWide_Impl for OutputCollector_Impl {
+        fn Output(&self, mask: u32, text: &PCWSTR) -> windows::core::Result<()> {
+            // SAFETY: the engine passes a valid, null-terminated string
+            let text = unsafe { text.to_string() }.unwrap_or_default();
+            let output = &mut *self.output.lock().unwrap();
+
+            if mask & DEBUG_OUTPUT_ERROR != 0 {
+                output.stderr.push_str(&text);
+                output
+                    .stderr_timestamps
+                    .push((output.stderr.len(), self.start.elapsed()));
+            } else {
+                output.stdout.push_str(&text);
+                output
+                    .stdout_timestamps
+                    .push((output.stdout.len(), self.start.elapsed()));
+            }
+
+            Ok(())
+        }
+    }
+
+    struct Session {
+        client: IDebugClient5,
+        control: IDebugControl4,
+        output: Arc<Mutex<CollectedOutput>>,
+    }
+
+    impl Session {
+        fn new(start: Instant) -> anyhow::Result<Session> {
+            // SAFETY: plain COM calls on interfaces we own
+            unsafe {
+                let client: IDebugClient5 =
+                    DebugCreate().context("Could not create a DbgEng client")?;
+                let control: IDebugControl4 = client.cast()?;
+
+                let output = Arc::new(Mutex::new(CollectedOutput::default()));
+                let callbacks: IDebugOutputCallbacksWide = OutputCollector {
+                    start,
+                    output: output.clone(),
+                }
+                .into();
+                client.SetOutputCallbacksWide(&callbacks)?;
+
+                Ok(Session {
+                    client,
+                    control,
+                    output,
+                })
+            }
+        }
+
+        /// Runs `command` like `cdb.exe` does when reading it from a script, i.e. after
+        /// printing the prompt and echoing the command
+        fn execute(&self, command: &str) -> windows::core::Result<()> {
+            // SAFETY: plain COM calls on interfaces we own
+            unsafe {
+                self.control
+                    .OutputPromptWide(DEBUG_OUTCTL_THIS_CLIENT, PCWSTR::null())?;
+                self.control.ExecuteWide(
+                    DEBUG_OUTCTL_THIS_CLIENT,
+                    &HSTRING::from(command),
+                    DEBUG_EXECUTE_ECHO | DEBUG_EXECUTE_NOT_LOGGED,
+                )
+            }
+        }
+
+        fn execution_status(&self) -> u32 {
+            // SAFETY: plain COM call on an interface we own
+            unsafe { self.control.GetExecutionStatus() }.unwrap_or(DEBUG_STATUS_NO_DEBUGGEE)
+        }
+
+        /// Lets the debuggee run until the next event. Returns `false` if `deadline` passed
+        /// before that.
+        fn wait_for_event(&self, deadline: Option<Instant>) -> bool {
+            let timeout = match deadline {
+                Some(deadline) => deadline
+                    .saturating_duration_since(Instant::now())
+                    .as_millis()
+                    .min(INFINITE as u128 - 1) as u32,
+                None => INFINITE,
+            };
+
+            // SAFETY: plain COM call on an interface we own. It returns `S_FALSE`, which
+            // isn't an error, when timing out.
+            let hresult = unsafe {
+                (Interface::vtable(&self.control).WaitForEvent)(
+                    Interface::as_raw(&self.control),
+                    DEBUG_WAIT_DEFAULT,
+                    timeout,
+                )
+            };
+
+            hresult.0 == 0 || hresult.is_err()
+        }
+
+        fn into_output(self) -> CollectedOutput {
+            // SAFETY: plain COM calls on interfaces we own
+            unsafe {
+                let _ = self.client.EndSession(DEBUG_END_ACTIVE_TERMINATE);
+                let _ = self.client.SetOutputCallbacksWide(None);
+            }
+
+            std::mem::take(&mut *self.output.lock().unwrap())
+        }
+    }
+
+    pub fn version() -> anyhow::Result<String> {
+        lazy_static! {
+            // example: Microsoft (R) Windows Debugger Version 10.0.22621.1 AMD64
+            static ref VERSION_REGEX: Regex = Regex::new(r"Debugger Version ([\d\.]+)").unwrap();
+        }
+
+        let session = Session::new(Instant::now())?;
+        session.execute("version")?;
+        let output = session.into_output();
+
+        match VERSION_REGEX.captures(&output.stdout) {
+            Some(captures) => Ok(captures[1].to_string()),
+            None => bail!("Could not find the DbgEng version in:\n{}", output.stdout),
+        }
+    }
+
+    pub fn run(
+        script_file_path: &Path,
+        debuggee: &Path,
+        crashdump: Option<&Path>,
+        timeout: Option<Duration>,
+    ) -> anyhow::Result<(DebuggerOutput, RunMeasurements)> {
+        let script = std::fs::read_to_string(script_file_path)?;
+
+        let start = Instant::now();
+        let deadline = timeout.map(|timeout| start + timeout);
+        let session = Session::new(start)?;
+
+        // SAFETY: plain COM calls on interfaces we own
+        unsafe {
+            match crashdump {
+                Some(crashdump) => {
+                    // Like `cdb.exe -y`, look for the PDB next to the debuggee
+                    let symbols: IDebugSymbols3 = session.client.cast()?;
+                    symbols.AppendSymbolPathWide(&HSTRING::from(debuggee.parent().unwrap()))?;
+                    session
+                        .client
+                        .OpenDumpFileWide(&HSTRING::from(crashdump), 0)?;
+                }
+                None => {
+                    let command_line = format!("\"{}\"", debuggee.display());
+                    session.client.CreateProcessAndAttachWide(
+                        0,
+                        &HSTRING::from(command_line),
+                        DEBUG_ONLY_THIS_PROCESS,
+                        0,
+                        DEBUG_ATTACH_DEFAULT,
+                    )?;
+                }
+            }
+        }
+
+        // Wait for the initial break (or for the dump to be loaded), where `cdb.exe` starts
+        // reading its script
+        let mut timed_out = !session.wait_for_event(deadline);
+
+        for command in script
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+        {
+            if timed_out {
+                break;
+            }
+
+            // Failing commands are reported via the output, as with `cdb.exe`
+            let _ = session.execute(command);
+
+            match session.execution_status() {
+                DEBUG_STATUS_NO_DEBUGGEE => break,
+                DEBUG_STATUS_BREAK => {}
+                // Commands like `g` only resume the debuggee, the engine runs it while waiting
+                _ => timed_out = !session.wait_for_event(deadline),
+            }
+        }
+
+        if timed_out {
+            // SAFETY: plain COM calls on interfaces we own
+            unsafe {
+                let _ = session.control.SetInterrupt(DEBUG_INTERRUPT_ACTIVE);
+                let _ = session.client.TerminateProcesses();
+            }
+        }
+
+        let output = session.into_output();
+
+        Ok((
+            DebuggerOutput {
+                stdout: output.stdout,
+                stderr: output.stderr,
+                exit_status: if timed_out {
+                    DebuggerExitStatus::TimedOut(Hang::Unknown)
+                } else {
+                    DebuggerExitStatus::Success
+                },
+                program_output: None,
+            },
+            RunMeasurements {
+                stdout_timestamps: output.stdout_timestamps,
+                stderr_timestamps: output.stderr_timestamps,
+                resource_usage: None,
+                duration: Some(start.elapsed()),
+            },
+        ))
+    }
+}
@@ -1,23 +1,40 @@
 use std::collections::{BTreeMap, HashSet};
 use std::fmt::Write;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::{
     collections::HashMap,
     ffi::{OsStr, OsString},
     fmt::{Debug, Display},
+    io::Read,
     path::{Path, PathBuf},
-    process::Command,
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
 };
 
 use anyhow::{bail, Context};
 use lazy_static::lazy_static;
 use log::{info, warn};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 
+use crate::build_variants;
+use crate::ci;
+use crate::codesign;
+use crate::dbgeng;
+use crate::hang::{self, Hang};
 use crate::prettify_path;
-use crate::script::{PhaseConfig, Script, Statement, Value};
+use crate::recording;
+use crate::regex_check::{parse_number, PointerBindings, RegexCheck};
+use crate::reproducible;
+use crate::script::{
+    CommandCheck, DebuggerCommand, FieldLayout, ForkMode, LineNumber, PhaseConfig, Requirement,
+    Script, Statement, Value, WatchAccess,
+};
+use crate::test_config::TestConfig;
 use crate::test_result::Status;
+use crate::wine;
 use crate::{
+    breakpoints::BreakPoint,
     cargo_test_directory::TestDefinition,
     script::{self, CorrelationId, EvaluationContext},
     test_result::TestResult,
@@ -61,43 +78,110 @@ pub fn name(self) -> &'static str {
         }
     }
 
-    pub fn run(
+    /// The settings emitted at the beginning of every debugger script of this kind, unless
+    /// `--no-default-settings` is given, so that tests don't have to set them themselves and
+    /// get the same output format
+    pub fn default_settings(self) -> &'static [&'static str] {
+        match self {
+            DebuggerKind::Gdb => &[
+                "set print pretty on",
+                "set pagination off",
+                "set confirm off",
+            ],
+            DebuggerKind::Cdb => &[".prefer_dml 0"],
+            DebuggerKind::Lldb => &["settings set use-color false"],
+            DebuggerKind::Mock => &[],
+        }
+    }
+
+    /// The environment variables debuggers of this kind are run with, unless
+    /// `--no-forced-locale` is given, so that their messages are in English whatever the
+    /// locale of the system. Wine also picks the language of CDB's messages from them.
+    pub fn forced_locale_env_vars(self) -> &'static [(&'static str, &'static str)] {
+        match self {
+            // gettext prefers `LANGUAGE` over the locale, unless it is empty
+            DebuggerKind::Gdb | DebuggerKind::Lldb | DebuggerKind::Cdb => {
+                &[("LANG", "C.UTF-8"), ("LC_ALL", "C.UTF-8"), ("LANGUAGE", "")]
+            }
+            DebuggerKind::Mock => &[],
+        }
+    }
+
+    /// The commands emitted at the beginning of every debugger script of this kind, unless
+    /// `--no-forced-locale` is given, for debuggers that don't take their locale from the
+    /// environment
+    pub fn forced_locale_commands(self) -> &'static [&'static str] {
+        match self {
+            DebuggerKind::Cdb => &[".locale C"],
+            DebuggerKind::Gdb | DebuggerKind::Lldb | DebuggerKind::Mock => &[],
+        }
+    }
+
+    /// The directive of `command` if debuggers of this kind can't run it, in which case the
+    /// tests using it are skipped
+    pub fn unsupported_directive(self, command: &DebuggerCommand) -> Option<&'static str> {
+        match (command, self) {
+            // LLDB can only continue backwards
+            (DebuggerCommand::ReverseStep, DebuggerKind::Lldb) => Some(script::TOKEN_REVERSE_STEP),
+            (DebuggerCommand::ReverseStepInto, DebuggerKind::Lldb) => {
+                Some(script::TOKEN_REVERSE_STEP_INTO)
+            }
+            (DebuggerCommand::ReverseFinish, DebuggerKind::Lldb) => {
+                Some(script::TOKEN_REVERSE_FINISH)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether `command` (a line of a test script) starts the debuggee, which is where
+    /// `before-run` preludes go, see [PreludePlacement]
+    pub fn starts_debuggee(self, command: &str) -> bool {
+        let command = command.trim();
+        let first_word = command.split_whitespace().next().unwrap_or("");
+
+        match self {
+            DebuggerKind::Gdb => ["run", "r", "start", "starti"].contains(&first_word),
+            DebuggerKind::Cdb => first_word == "g",
+            DebuggerKind::Lldb => {
+                ["run", "r"].contains(&first_word) || command.starts_with("process launch")
+            }
+            DebuggerKind::Mock => first_word == "run",
+        }
+    }
+
+    /// Builds the command for running a debugger of this kind. Mock debuggers don't run a
+    /// command, see [Debugger::run].
+    pub fn command(
         self,
         debugger_executable: &OsStr,
-        script_file_path: &Path,
+        script_args: &[OsString],
         debuggee: &Path,
+        debuggee_args: &[String],
         crashdump: Option<&Path>,
         command_line_args: &[String],
-        env_vars: &[(String, String)],
         // TODO: add source path
-    ) -> anyhow::Result<DebuggerOutput> {
+    ) -> Command {
         let mut command = Command::new(debugger_executable);
 
-        command.envs(env_vars.iter().map(|(env_var_name, env_var_value)| {
-            (OsString::from(env_var_name), OsString::from(env_var_value))
-        }));
-
         command.args(command_line_args.iter().map(OsString::from));
 
         match self {
-            DebuggerKind::Mock => {
-                return Ok(create_mock_debugger_output(script_file_path));
-            }
+            DebuggerKind::Mock => unreachable!(),
             DebuggerKind::Gdb => {
-                command
-                    .arg("--batch")
-                    .arg("--quiet")
-                    .arg("--command")
-                    .arg(script_file_path);
+                command.arg("--batch").arg("--quiet").args(script_args);
 
                 if let Some(crashdump) = crashdump {
                     command.arg("--core").arg(crashdump);
                 }
 
-                command.arg(debuggee);
+                if !debuggee_args.is_empty() {
+                    command.arg("--args");
+                }
+
+                command.arg(debuggee).args(debuggee_args);
             }
             DebuggerKind::Cdb => {
-                command.arg("-cf").arg(script_file_path);
+                command.args(script_args);
 
                 if let Some(crashdump) = crashdump {
                     command.arg("-z").arg(crashdump);
@@ -105,68 +189,777 @@ pub fn run(
                     // where we expect find the accompanying PDB.
                     command.arg("-y").arg(debuggee.parent().unwrap());
                 } else {
-                    command.arg(debuggee);
+                    command.arg(debuggee).args(debuggee_args);
                 }
             }
             DebuggerKind::Lldb => {
                 command.arg("--batch");
-                command.arg("--source").arg(script_file_path);
+                command.args(script_args);
                 command.arg(debuggee);
+
+                if !debuggee_args.is_empty() {
+                    command.arg("--").args(debuggee_args);
+                }
+            }
+        }
+
+        command
+    }
+
+    /// The arguments that make a debugger of this kind run the script at `script_file_path`,
+    /// for [DebuggerKind::command]
+    pub fn script_args(
+        self,
+        script_file_path: &Path,
+        lldb_script_mode: LldbScriptMode,
+    ) -> anyhow::Result<Vec<OsString>> {
+        let file_args = |option: &str| vec![option.into(), script_file_path.into()];
+
+        Ok(match self {
+            DebuggerKind::Mock => unreachable!(),
+            DebuggerKind::Gdb => file_args("--command"),
+            DebuggerKind::Cdb => file_args("-cf"),
+            DebuggerKind::Lldb => match lldb_script_mode {
+                LldbScriptMode::Source => file_args("--source"),
+                LldbScriptMode::OneLiners => {
+                    let script = std::fs::read_to_string(script_file_path)?;
+                    let mut args = vec![];
+
+                    for line in script.lines().filter(|line| !line.trim().is_empty()) {
+                        args.push("-o".into());
+                        args.push(line.into());
+                    }
+
+                    args
+                }
+                LldbScriptMode::CommandSource => {
+                    // The path is part of an LLDB command, which splits its arguments at spaces
+                    let command_source = format!(
+                        "command source --stop-on-error false --stop-on-continue false {}",
+                        lldb_quote(&script_file_path.to_string_lossy())
+                    );
+
+                    vec!["-o".into(), command_source.into()]
+                }
+            },
+        })
+    }
+}
+
+/// Quotes `arg` for use as an argument of an LLDB command
+fn lldb_quote(arg: &str) -> String {
+    format!("\"{}\"", arg.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// How LLDB is given the debugger script (`--lldb-script-mode`), as `--source` behaves
+/// differently in batch mode across versions, e.g. for the exit code
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LldbScriptMode {
+    /// `--source <script>`
+    #[default]
+    Source,
+    /// `-o <command>` for each line of the script, which rules out commands spanning lines
+    OneLiners,
+    /// `-o "command source <script>"`
+    CommandSource,
+}
+
+impl LldbScriptMode {
+    /// Picks the mode for the host from the values of `--lldb-script-mode`, which are of the
+    /// form `[<os>:]<mode>`: the last one for the host's OS (as in [std::env::consts::OS]) or
+    /// for any OS, [LldbScriptMode::Source] if there is none
+    pub fn for_host(values: &[String]) -> anyhow::Result<LldbScriptMode> {
+        let mut host_mode = LldbScriptMode::default();
+
+        for value in values {
+            let (os, mode) = match value.split_once(':') {
+                Some((os, mode)) => (Some(os.trim()), mode),
+                None => (None, &value[..]),
+            };
+
+            let mode = match mode.trim() {
+                "source" => LldbScriptMode::Source,
+                "one-liners" => LldbScriptMode::OneLiners,
+                "command-source" => LldbScriptMode::CommandSource,
+                other => bail!(
+                    "Unknown LLDB script mode `{}`, expected `source`, `one-liners` or \
+                     `command-source`",
+                    other
+                ),
+            };
+
+            if os.is_none_or(|os| os == std::env::consts::OS) {
+                host_mode = mode;
             }
         }
 
-        let output = command.output()?;
+        Ok(host_mode)
+    }
+}
+
+/// GDB's `scheduler-locking` setting (`--gdb-scheduler-locking`), i.e. which threads run
+/// while the current one is stepped or resumed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SchedulerLocking {
+    /// All threads run
+    Off,
+    /// Only the current thread runs
+    On,
+    /// Only the current thread runs while stepping, all of them run when continuing
+    Step,
+    /// Like `Off`, but like `On` while replaying a recording
+    Replay,
+}
+
+impl SchedulerLocking {
+    pub fn parse(value: &str) -> anyhow::Result<SchedulerLocking> {
+        match value.trim() {
+            "off" => Ok(SchedulerLocking::Off),
+            "on" => Ok(SchedulerLocking::On),
+            "step" => Ok(SchedulerLocking::Step),
+            "replay" => Ok(SchedulerLocking::Replay),
+            other => bail!(
+                "Unknown scheduler-locking mode `{}`, expected `off`, `on`, `step` or `replay`",
+                other
+            ),
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SchedulerLocking::Off => "off",
+            SchedulerLocking::On => "on",
+            SchedulerLocking::Step => "step",
+            SchedulerLocking::Replay => "replay",
+        }
+    }
+}
+
+/// Read from the `gdb-scheduler-locking` of a `dbt-test.toml`
+impl<'de> Deserialize<'de> for SchedulerLocking {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        SchedulerLocking::parse(&value).map_err(serde::de::Error::custom)
+    }
+}
+
+/// When each part of a debugger's stdout or stderr arrived, as pairs of the length of the
+/// output so far and the time since the debugger was started. See [command_timings] and
+/// [stderr_by_correlation_id].
+pub type OutputTimestamps = Vec<(usize, Duration)>;
+
+/// The resources used by a debugger process
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResourceUsage {
+    /// The peak resident set size
+    pub peak_memory_bytes: u64,
+    /// User plus system CPU time
+    pub cpu_time: Duration,
+}
+
+/// What was measured while running a debugger, in addition to its output
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RunMeasurements {
+    pub stdout_timestamps: OutputTimestamps,
+    pub stderr_timestamps: OutputTimestamps,
+    /// `None` if not measured, which is currently the case on Windows
+    pub resource_usage: Option<ResourceUsage>,
+    /// How long the debugger ran, wall-clock. `None` for recordings.
+    pub duration: Option<Duration>,
+}
+
+/// How [Debugger::run] runs the debugger, apart from what it runs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionOptions {
+    /// Kill the debugger if it takes longer than this
+    pub timeout: Option<Duration>,
+    /// Kill the debugger if a single block of commands takes longer than this
+    /// (`--command-timeout`)
+    pub command_timeout: Option<Duration>,
+    /// Echo the debugger's stdout and stderr to the console as they arrive (`--verbose-test`)
+    pub stream_output: bool,
+}
 
-        Ok(DebuggerOutput {
-            stdout: String::from_utf8_lossy(&output.stdout).to_string(),
-            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
-            exit_status: if output.status.success() {
+/// Like [Child::try_wait] but also returns the resource usage of the process.
+#[cfg(unix)]
+fn try_wait_with_resource_usage(
+    child: &mut Child,
+) -> std::io::Result<Option<(DebuggerExitStatus, Option<ResourceUsage>)>> {
+    let mut status = 0;
+    // SAFETY: `rusage` is a plain C struct for which all zeroes is a valid value
+    let mut rusage: libc::rusage = unsafe { std::mem::zeroed() };
+
+    // SAFETY: `status` and `rusage` are valid for writes
+    let pid = unsafe {
+        libc::wait4(
+            child.id() as libc::pid_t,
+            &mut status,
+            libc::WNOHANG,
+            &mut rusage,
+        )
+    };
+
+    match pid {
+        0 => Ok(None),
+        -1 => {
+            let error = std::io::Error::last_os_error();
+
+            if error.kind() == std::io::ErrorKind::Interrupted {
+                Ok(None)
+            } else {
+                Err(error)
+            }
+        }
+        _ => {
+            let exit_status = if libc::WIFSIGNALED(status) {
+                DebuggerExitStatus::Crashed(DebuggerCrash::Signal(libc::WTERMSIG(status)))
+            } else if libc::WIFEXITED(status) && libc::WEXITSTATUS(status) == 0 {
                 DebuggerExitStatus::Success
             } else {
                 DebuggerExitStatus::Failure
-            },
-        })
+            };
+
+            // `ru_maxrss` is in kilobytes, except on macOS where it is in bytes
+            let peak_memory_bytes = if cfg!(target_os = "macos") {
+                rusage.ru_maxrss as u64
+            } else {
+                rusage.ru_maxrss as u64 * 1024
+            };
+
+            let to_duration = |time: libc::timeval| {
+                Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+            };
+
+            Ok(Some((
+                exit_status,
+                Some(ResourceUsage {
+                    peak_memory_bytes,
+                    cpu_time: to_duration(rusage.ru_utime) + to_duration(rusage.ru_stime),
+                }),
+            )))
+        }
+    }
+}
+
+#[cfg(not(unix))]
+fn try_wait_with_resource_usage(
+    child: &mut Child,
+) -> std::io::Result<Option<(DebuggerExitStatus, Option<ResourceUsage>)>> {
+    Ok(child.try_wait()?.map(|status| {
+        let exit_status = match status.code() {
+            _ if status.success() => DebuggerExitStatus::Success,
+            // Unhandled exceptions (like access violations) end the process with the
+            // exception's NTSTATUS code, all of which have the error severity bits set
+            Some(code) if code as u32 >= 0xC000_0000 => {
+                DebuggerExitStatus::Crashed(DebuggerCrash::Exception(code as u32))
+            }
+            _ => DebuggerExitStatus::Failure,
+        };
+
+        (exit_status, None)
+    }))
+}
+
+/// Tracks which block of commands the debugger is running, going by the correlation markers
+/// in its stdout as it arrives, to enforce the [SessionOptions::command_timeout]
+#[derive(Debug, Default)]
+struct BlockWatch {
+    /// How much of stdout was scanned for markers
+    scanned_len: usize,
+    /// The block whose begin marker arrived last, if its end marker didn't arrive yet, and
+    /// since when
+    running: Option<(CorrelationId, Instant)>,
+}
+
+impl BlockWatch {
+    /// Scans the complete lines of `stdout` that weren't scanned yet
+    fn update(&mut self, stdout: &[u8]) {
+        let new_len = match stdout[self.scanned_len..].iter().rposition(|&b| b == b'\n') {
+            Some(position) => self.scanned_len + position + 1,
+            None => return,
+        };
+
+        for line in String::from_utf8_lossy(&stdout[self.scanned_len..new_len]).lines() {
+            match parse_marker(line) {
+                // Keep the first of repeated markers, see debugger_output_by_correlation_id()
+                Some((true, cid)) if self.running.is_some_and(|(running, _)| running == cid) => {}
+                Some((true, cid)) => self.running = Some((cid, Instant::now())),
+                Some((false, _)) => self.running = None,
+                None => {}
+            }
+        }
+
+        self.scanned_len = new_len;
     }
+
+    fn exceeded(&self, command_timeout: Duration) -> bool {
+        self.running
+            .is_some_and(|(_, since)| since.elapsed() >= command_timeout)
+    }
+}
+
+/// Runs `command` to completion, killing it if it takes longer than the `timeout`, or a
+/// block of commands takes longer than the `command_timeout` of `session`. The output
+/// produced up to that point is still returned in that case, along with why the debugger
+/// hung, going by the state of the `debuggee` and the debugger. With `stream_output`, the
+/// output is also written to the console as it arrives.
+fn run_with_timeout(
+    mut command: Command,
+    debuggee: &Path,
+    session: SessionOptions,
+) -> anyhow::Result<(DebuggerOutput, RunMeasurements)> {
+    let SessionOptions {
+        timeout,
+        command_timeout,
+        stream_output,
+    } = session;
+
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let start = Instant::now();
+    let mut child = command.spawn()?;
+
+    // Read the pipes on separate threads so that the debugger doesn't block on a full pipe.
+    // The output is collected incrementally, so that it is available even if a process that
+    // inherited the pipes (like the debuggee) keeps them open after the debugger was killed.
+    let read_pipe = |mut pipe: Box<dyn Read + Send>,
+                     mut console: Option<Box<dyn std::io::Write + Send>>| {
+        let output = Arc::new(Mutex::new((vec![], OutputTimestamps::new())));
+        let thread_output = output.clone();
+
+        let thread = std::thread::spawn(move || {
+            use std::io::Write;
+
+            let mut buffer = [0; 4096];
+
+            loop {
+                match pipe.read(&mut buffer) {
+                    Ok(0) => return Ok(()),
+                    Ok(len) => {
+                        if let Some(console) = &mut console {
+                            // The console going away must not fail the test
+                            let _ = console.write_all(&buffer[..len]);
+                            let _ = console.flush();
+                        }
+
+                        let (output, timestamps) = &mut *thread_output.lock().unwrap();
+                        output.extend_from_slice(&buffer[..len]);
+                        timestamps.push((output.len(), start.elapsed()));
+                    }
+                    Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                    Err(e) => return Err(e),
+                }
+            }
+        });
+
+        (thread, output)
+    };
+    let console = |console: Box<dyn std::io::Write + Send>| stream_output.then_some(console);
+    let (stdout_thread, stdout) = read_pipe(
+        Box::new(child.stdout.take().unwrap()),
+        console(Box::new(std::io::stdout())),
+    );
+    let (stderr_thread, stderr) = read_pipe(
+        Box::new(child.stderr.take().unwrap()),
+        console(Box::new(std::io::stderr())),
+    );
+
+    let deadline = timeout.map(|timeout| Instant::now() + timeout);
+    let mut block_watch = BlockWatch::default();
+
+    let mut resource_usage = None;
+
+    let exit_status = loop {
+        if let Some((exit_status, usage)) = try_wait_with_resource_usage(&mut child)? {
+            stdout_thread.join().unwrap()?;
+            stderr_thread.join().unwrap()?;
+
+            resource_usage = usage;
+
+            break exit_status;
+        }
+
+        if command_timeout.is_some() {
+            block_watch.update(&stdout.lock().unwrap().0);
+        }
+
+        if deadline.is_some_and(|deadline| Instant::now() >= deadline)
+            || command_timeout.is_some_and(|command_timeout| block_watch.exceeded(command_timeout))
+        {
+            let hang = hang::inspect(child.id(), debuggee);
+
+            child.kill()?;
+            child.wait()?;
+
+            // Give the reader threads a moment to pick up any remaining output
+            std::thread::sleep(Duration::from_millis(100));
+            break DebuggerExitStatus::TimedOut(hang);
+        }
+
+        std::thread::sleep(Duration::from_millis(10));
+    };
+
+    let (stdout, stdout_timestamps) = std::mem::take(&mut *stdout.lock().unwrap());
+    let stdout = String::from_utf8_lossy(&stdout).to_string();
+    let (stderr, stderr_timestamps) = std::mem::take(&mut *stderr.lock().unwrap());
+    let stderr = String::from_utf8_lossy(&stderr).to_string();
+
+    Ok((
+        DebuggerOutput {
+            stdout,
+            stderr,
+            exit_status,
+            program_output: None,
+        },
+        RunMeasurements {
+            stdout_timestamps,
+            stderr_timestamps,
+            resource_usage,
+            duration: Some(start.elapsed()),
+        },
+    ))
+}
+
+/// Writes output that was collected in one go to the console, for debuggers that can't
+/// stream it as it arrives
+fn echo_output(output: &DebuggerOutput) {
+    print!("{}", output.stdout);
+    eprint!("{}", output.stderr);
+}
+
+fn create_mock_debugger_output(
+    script_file_path: &Path,
+    session: SessionOptions,
+) -> (DebuggerOutput, RunMeasurements) {
+    mock_debugger_output(&std::fs::read_to_string(script_file_path).unwrap(), session)
 }
 
-fn create_mock_debugger_output(script_file_path: &Path) -> DebuggerOutput {
-    DebuggerOutput {
-        stdout: std::fs::read_to_string(script_file_path).unwrap(),
-        stderr: "".to_string(),
+/// The mock debugger echoes its script, except for the following commands which allow
+/// simulating misbehaving debuggers:
+///
+/// - `mock_exit N` -- stops with exit code `N` (failure unless `N` is 0)
+/// - `mock_truncate` -- stops as if the debugger had crashed
+/// - `mock_signal N` -- stops as if the debugger had been killed by signal `N`
+/// - `mock_sleep MS [HANG]` -- waits for `MS` milliseconds, honoring the timeout and command
+///   timeout, and reports the given [Hang] (by name) if it times out
+/// - `mock_echo TEXT` / `mock_stderr TEXT` -- writes `TEXT` to stdout or stderr, which
+///   allows emitting malformed correlation markers
+/// - `mock_memory BYTES` -- reports `BYTES` as the peak memory usage of the debugger
+/// - `mock_program_output_path PATH` -- redirects the output of the (imaginary) debuggee to
+///   the file at `PATH`, see [Debugger::program_output_redirect]
+/// - `mock_program_output TEXT` -- writes `TEXT` as output of the debuggee, i.e. to the file
+///   given via `mock_program_output_path`, or else to stdout
+fn mock_debugger_output(
+    script: &str,
+    session: SessionOptions,
+) -> (DebuggerOutput, RunMeasurements) {
+    let start = Instant::now();
+    let deadline = session.timeout.map(|timeout| start + timeout);
+    // When the block of commands that is running began, see BlockWatch
+    let mut block_start = None;
+    let mut stdout_timestamps = vec![];
+    let mut stderr_timestamps = vec![];
+    let mut peak_memory_bytes = 0;
+    let mut program_output_path = None;
+
+    let mut output = DebuggerOutput {
+        stdout: String::new(),
+        stderr: String::new(),
         exit_status: DebuggerExitStatus::Success,
+        program_output: None,
+    };
+
+    for line in script.lines() {
+        let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+
+        match command {
+            "mock_exit" => {
+                if argument.trim() != "0" {
+                    output.exit_status = DebuggerExitStatus::Failure;
+                }
+                break;
+            }
+            "mock_truncate" => {
+                output.exit_status = DebuggerExitStatus::Failure;
+                break;
+            }
+            "mock_signal" => {
+                let signal = argument.trim().parse().unwrap_or(0);
+                output.exit_status = DebuggerExitStatus::Crashed(DebuggerCrash::Signal(signal));
+                break;
+            }
+            "mock_sleep" => {
+                let mut arguments = argument.split_whitespace();
+                let duration =
+                    Duration::from_millis(arguments.next().unwrap_or("0").parse().unwrap_or(0));
+                let hang = arguments
+                    .next()
+                    .and_then(Hang::from_name)
+                    .unwrap_or(Hang::Unknown);
+
+                let block_deadline = block_start
+                    .zip(session.command_timeout)
+                    .map(|(block_start, command_timeout)| block_start + command_timeout);
+
+                if let Some(deadline) = deadline.into_iter().chain(block_deadline).min() {
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+
+                    if duration >= remaining {
+                        std::thread::sleep(remaining);
+                        output.exit_status = DebuggerExitStatus::TimedOut(hang);
+                        break;
+                    }
+                }
+
+                std::thread::sleep(duration);
+                continue;
+            }
+            "mock_echo" => {
+                writeln!(output.stdout, "{}", argument).unwrap();
+                stdout_timestamps.push((output.stdout.len(), start.elapsed()));
+                continue;
+            }
+            "mock_stderr" => {
+                writeln!(output.stderr, "{}", argument).unwrap();
+                stderr_timestamps.push((output.stderr.len(), start.elapsed()));
+                continue;
+            }
+            "mock_memory" => {
+                peak_memory_bytes = argument.trim().parse().unwrap_or(0);
+                continue;
+            }
+            "mock_program_output_path" => {
+                program_output_path = Some(PathBuf::from(argument.trim()));
+                continue;
+            }
+            "mock_program_output" => {
+                match &program_output_path {
+                    Some(path) => {
+                        let mut file = std::fs::OpenOptions::new()
+                            .create(true)
+                            .append(true)
+                            .open(path)
+                            .unwrap();
+                        std::io::Write::write_all(&mut file, format!("{}\n", argument).as_bytes())
+                            .unwrap();
+                    }
+                    None => {
+                        writeln!(output.stdout, "{}", argument).unwrap();
+                        stdout_timestamps.push((output.stdout.len(), start.elapsed()));
+                    }
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        writeln!(output.stdout, "{}", line).unwrap();
+        stdout_timestamps.push((output.stdout.len(), start.elapsed()));
+
+        match parse_marker(line) {
+            Some((true, _)) => block_start = Some(Instant::now()),
+            Some((false, _)) => block_start = None,
+            None => {}
+        }
     }
+
+    let measurements = RunMeasurements {
+        stdout_timestamps,
+        stderr_timestamps,
+        resource_usage: Some(ResourceUsage {
+            peak_memory_bytes,
+            cpu_time: start.elapsed(),
+        }),
+        duration: Some(start.elapsed()),
+    };
+
+    (output, measurements)
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+/// Serialized as a string (`success`, `failure`, `timed-out`, `signal-11`,
+/// `exception-0xC0000005`), as TOML has no representation for enum variants with data
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(into = "String", try_from = "String")]
 pub enum DebuggerExitStatus {
     Success,
     Failure,
+    /// The debugger was killed because it did not finish within `--timeout`, and why it
+    /// hung
+    TimedOut(Hang),
+    /// The debugger itself crashed, as opposed to exiting with an error
+    Crashed(DebuggerCrash),
 }
 
 impl DebuggerExitStatus {
     pub fn success(self) -> bool {
         match self {
             DebuggerExitStatus::Success => true,
-            DebuggerExitStatus::Failure => false,
+            DebuggerExitStatus::Failure
+            | DebuggerExitStatus::TimedOut(_)
+            | DebuggerExitStatus::Crashed(_) => false,
+        }
+    }
+}
+
+impl From<DebuggerExitStatus> for String {
+    fn from(exit_status: DebuggerExitStatus) -> String {
+        match exit_status {
+            DebuggerExitStatus::Success => "success".to_string(),
+            DebuggerExitStatus::Failure => "failure".to_string(),
+            DebuggerExitStatus::TimedOut(Hang::Unknown) => "timed-out".to_string(),
+            DebuggerExitStatus::TimedOut(hang) => format!("timed-out-{}", hang.name()),
+            DebuggerExitStatus::Crashed(DebuggerCrash::Signal(signal)) => {
+                format!("signal-{}", signal)
+            }
+            DebuggerExitStatus::Crashed(DebuggerCrash::Exception(code)) => {
+                format!("exception-{:#010X}", code)
+            }
+        }
+    }
+}
+
+impl TryFrom<String> for DebuggerExitStatus {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<DebuggerExitStatus, String> {
+        let crash = if let Some(signal) = s.strip_prefix("signal-") {
+            signal.parse().ok().map(DebuggerCrash::Signal)
+        } else if let Some(hang) = s.strip_prefix("timed-out-") {
+            if let Some(hang) = Hang::from_name(hang) {
+                return Ok(DebuggerExitStatus::TimedOut(hang));
+            }
+
+            None
+        } else if let Some(code) = s.strip_prefix("exception-0x") {
+            u32::from_str_radix(code, 16)
+                .ok()
+                .map(DebuggerCrash::Exception)
+        } else {
+            match &s[..] {
+                "success" => return Ok(DebuggerExitStatus::Success),
+                "failure" => return Ok(DebuggerExitStatus::Failure),
+                "timed-out" => return Ok(DebuggerExitStatus::TimedOut(Hang::Unknown)),
+                _ => None,
+            }
+        };
+
+        match crash {
+            Some(crash) => Ok(DebuggerExitStatus::Crashed(crash)),
+            None => Err(format!("unknown debugger exit status `{}`", s)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DebuggerCrash {
+    /// The debugger was killed by the given signal (on Unix)
+    Signal(i32),
+    /// The debugger exited with the given unhandled exception code (on Windows)
+    Exception(u32),
+}
+
+impl DebuggerCrash {
+    /// Whether the crash points at a bug in the debugger, as opposed to the debugger being
+    /// killed from outside, e.g. by the OOM killer or a CI job that is cancelled
+    pub fn is_fault(&self) -> bool {
+        match *self {
+            DebuggerCrash::Signal(signal) => is_fault_signal(signal),
+            DebuggerCrash::Exception(_) => true,
+        }
+    }
+}
+
+impl std::fmt::Display for DebuggerCrash {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match *self {
+            DebuggerCrash::Signal(signal) => match signal_name(signal) {
+                Some(name) => write!(f, "killed by signal {} ({})", signal, name),
+                None => write!(f, "killed by signal {}", signal),
+            },
+            DebuggerCrash::Exception(code) => {
+                write!(f, "terminated by exception code {:#010X}", code)
+            }
         }
     }
 }
 
+#[cfg(unix)]
+fn signal_name(signal: i32) -> Option<&'static str> {
+    Some(match signal {
+        libc::SIGABRT => "SIGABRT",
+        libc::SIGBUS => "SIGBUS",
+        libc::SIGFPE => "SIGFPE",
+        libc::SIGILL => "SIGILL",
+        libc::SIGKILL => "SIGKILL",
+        libc::SIGSEGV => "SIGSEGV",
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGTRAP => "SIGTRAP",
+        _ => return None,
+    })
+}
+
+#[cfg(not(unix))]
+fn signal_name(_signal: i32) -> Option<&'static str> {
+    None
+}
+
+#[cfg(unix)]
+fn is_fault_signal(signal: i32) -> bool {
+    [libc::SIGSEGV, libc::SIGABRT, libc::SIGBUS, libc::SIGILL].contains(&signal)
+}
+
+#[cfg(not(unix))]
+fn is_fault_signal(_signal: i32) -> bool {
+    true
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct DebuggerOutput {
     pub stdout: String,
     pub stderr: String,
     pub exit_status: DebuggerExitStatus,
+    /// What the debuggee printed to its stdout, if it was captured apart from the debugger's
+    /// stdout, see [Debugger::program_output_redirect]
+    pub program_output: Option<String>,
 }
 
 pub struct Debugger {
     pub kind: DebuggerKind,
     pub version: Arc<str>,
     pub command: OsString,
-    prelude: Vec<String>,
+    prelude: Vec<(PreludePlacement, String)>,
     commandline_args: Vec<String>,
     env_vars: Vec<(String, String)>,
     defines: Arc<[Arc<str>]>,
+    /// Caches the results of (potentially expensive) requirement checks
+    requirements_met: Mutex<HashMap<Requirement, bool>>,
+    /// Set for debuggers that replay recorded sessions instead of running anything
+    replay_directory: Option<PathBuf>,
+    /// The Wine executable this debugger runs under (see `--wine`)
+    wine: Option<OsString>,
+    /// Whether the [DebuggerKind::default_settings] are emitted (see `--no-default-settings`)
+    default_settings: bool,
+    /// Whether the debuggee is dumped when it faults (see `--no-fault-dumps`)
+    fault_dumps: bool,
+    /// Whether the debugger runs with the C locale (see `--no-forced-locale`)
+    forced_locale: bool,
+    /// How the script is passed to LLDB (see `--lldb-script-mode`)
+    lldb_script_mode: LldbScriptMode,
+    /// Whether GDB runs the debuggee in non-stop mode (see `--gdb-non-stop`), unless the
+    /// test's `dbt-test.toml` says otherwise
+    gdb_non_stop: bool,
+    /// See `--gdb-scheduler-locking`, unless the test's `dbt-test.toml` says otherwise
+    gdb_scheduler_locking: Option<SchedulerLocking>,
+    /// Only the checks whose source contains this are evaluated (see `--check-filter`)
+    check_filter: Option<Arc<str>>,
+    /// The workspaces whose sources have remapped paths in the debuginfo (see
+    /// `--reproducible`)
+    remapped_workspaces: Vec<PathBuf>,
+    /// Whether each command is fenced with markers for timing it (see `--benchmark`)
+    command_fences: bool,
 }
 
 impl Debug for Debugger {
@@ -187,7 +980,7 @@ pub fn new(
         kind: DebuggerKind,
         version: Arc<str>,
         command: OsString,
-        prelude: Vec<String>,
+        prelude: Vec<(PreludePlacement, String)>,
         commandline_args: Vec<String>,
         env_vars: Vec<(String, String)>,
         defines: Arc<[Arc<str>]>,
@@ -200,73 +993,466 @@ pub fn new(
             commandline_args,
             env_vars,
             defines,
+            requirements_met: Default::default(),
+            replay_directory: None,
+            wine: None,
+            default_settings: true,
+            fault_dumps: true,
+            forced_locale: true,
+            lldb_script_mode: LldbScriptMode::default(),
+            gdb_non_stop: false,
+            gdb_scheduler_locking: None,
+            check_filter: None,
+            remapped_workspaces: vec![],
+            command_fences: false,
         }
     }
 
-    pub fn run(
-        &self,
-        script_file_path: &Path,
-        debuggee: &Path,
-        crashdump: Option<&Path>,
-    ) -> anyhow::Result<DebuggerOutput> {
-        if let Some(crashdump) = crashdump {
-            if !crashdump.exists() {
-                bail!(
-                    "Could not find crashdump file: {}",
-                    prettify_path(crashdump)
-                );
-            }
-        }
-        self.kind.run(
-            &self.command,
-            script_file_path,
-            debuggee,
-            crashdump,
-            &self.commandline_args,
-            &self.env_vars,
-        )
+    /// Leaves out the [DebuggerKind::default_settings] from the scripts of this debugger
+    pub fn disable_default_settings(&mut self) {
+        self.default_settings = false;
     }
 
-    pub fn mock() -> Debugger {
-        Debugger::new(
-            DebuggerKind::Mock,
-            "1.0".into(),
-            "mockdbg".into(),
-            vec![],
-            vec![],
-            vec![],
-            vec![].into(),
-        )
+    /// Doesn't dump the debuggee when it faults, see [Debugger::fault_dump_command]
+    pub fn disable_fault_dumps(&mut self) {
+        self.fault_dumps = false;
     }
 
-    pub fn ignore_test(
-        &self,
-        test_definition: &TestDefinition,
-        cargo_profile: &Arc<str>,
-        phase: &PhaseConfig,
-    ) -> bool {
-        let evaluation_context = self.evaluation_context(cargo_profile, phase);
-        test_definition.script.ignore_test(&evaluation_context)
+    /// Runs this debugger with the locale of the system, see
+    /// [DebuggerKind::forced_locale_env_vars]
+    pub fn disable_forced_locale(&mut self) {
+        self.forced_locale = false;
     }
 
-    pub fn has_active_checks(
+    /// Defines `@<define>` for this debugger only, unlike `--define`
+    pub fn add_define(&mut self, define: &str) {
+        let variable: Arc<str> = format!("@{}", define).into();
+
+        if !self.defines.contains(&variable) {
+            self.defines = self.defines.iter().cloned().chain([variable]).collect();
+        }
+    }
+
+    /// Runs the debuggee in GDB's non-stop mode, for tests that don't set `gdb-non-stop` in
+    /// their `dbt-test.toml`
+    pub fn enable_gdb_non_stop(&mut self) {
+        self.gdb_non_stop = true;
+    }
+
+    /// Sets GDB's `scheduler-locking`, for tests that don't set `gdb-scheduler-locking` in
+    /// their `dbt-test.toml`
+    pub fn set_gdb_scheduler_locking(&mut self, scheduler_locking: SchedulerLocking) {
+        self.gdb_scheduler_locking = Some(scheduler_locking);
+    }
+
+    /// Whether GDB runs the debuggee of a test with `test_config` in non-stop mode
+    fn gdb_non_stop(&self, test_config: &TestConfig) -> bool {
+        self.kind == DebuggerKind::Gdb && test_config.gdb_non_stop.unwrap_or(self.gdb_non_stop)
+    }
+
+    /// GDB's `scheduler-locking` for a test with `test_config`, if it is set at all
+    fn gdb_scheduler_locking(&self, test_config: &TestConfig) -> Option<SchedulerLocking> {
+        if self.kind != DebuggerKind::Gdb {
+            return None;
+        }
+
+        test_config
+            .gdb_scheduler_locking()
+            .or(self.gdb_scheduler_locking)
+    }
+
+    /// Sets how the script is passed to the debugger, if it is LLDB
+    pub fn set_lldb_script_mode(&mut self, lldb_script_mode: LldbScriptMode) {
+        self.lldb_script_mode = lldb_script_mode;
+    }
+
+    /// Only evaluates the checks whose line in the source contains `check_filter`. The
+    /// script is still run as a whole, the output the other checks would match is just not
+    /// checked.
+    pub fn set_check_filter(&mut self, check_filter: Arc<str>) {
+        self.check_filter = Some(check_filter);
+    }
+
+    /// Makes the debugger look up the sources of the workspace at `root_path`, which were
+    /// compiled with remapped paths, in the workspace, see [crate::reproducible]
+    pub fn add_remapped_workspace(&mut self, root_path: PathBuf) {
+        self.remapped_workspaces.push(root_path);
+    }
+
+    /// Places markers around each command of the scripts of this debugger, so that
+    /// [command_timings] can tell how long each of them took
+    pub fn enable_command_fences(&mut self) {
+        self.command_fences = true;
+    }
+
+    /// The command that redirects the stdout of the debuggee to the file at `path`, to be put
+    /// at the start of the script of a live test, so that the output of the program doesn't
+    /// mix with the output of the debugger. `None` for debuggers that don't support it (CDB).
+    /// GDB starts the debuggee via a shell, so the redirection is part of its arguments.
+    pub fn program_output_redirect(&self, path: &Path, debuggee_args: &[String]) -> Option<String> {
+        let path = path.to_string_lossy();
+
+        match self.kind {
+            DebuggerKind::Gdb => {
+                let shell_quote = |arg: &str| format!("'{}'", arg.replace('\'', "'\\''"));
+                let mut args: Vec<_> = debuggee_args.iter().map(|arg| shell_quote(arg)).collect();
+                args.push(format!("> {}", shell_quote(&path)));
+
+                Some(format!("set args {}", args.join(" ")))
+            }
+            DebuggerKind::Lldb => Some(format!(
+                "settings set target.output-path {}",
+                lldb_quote(&path)
+            )),
+            DebuggerKind::Mock => Some(format!("mock_program_output_path {}", path)),
+            DebuggerKind::Cdb => None,
+        }
+    }
+
+    /// The directory containing the recordings this debugger replays, if it is a
+    /// replaying debugger (see `--replay`).
+    pub fn replay_directory(&self) -> Option<&Path> {
+        self.replay_directory.as_deref()
+    }
+
+    /// Runs the debugger script at `script_file_path`. `env_vars` are set for the debugger in
+    /// addition to the ones given via `--debugger-env`, and are passed on to the debuggee.
+    pub fn run(
+        &self,
+        script_file_path: &Path,
+        debuggee: &Path,
+        debuggee_args: &[String],
+        crashdump: Option<&Path>,
+        env_vars: &[(String, String)],
+        session: SessionOptions,
+    ) -> anyhow::Result<(DebuggerOutput, RunMeasurements)> {
+        let SessionOptions {
+            timeout,
+            command_timeout,
+            stream_output,
+            ..
+        } = session;
+
+        if let Some(crashdump) = crashdump {
+            if !crashdump.exists() {
+                bail!(
+                    "Could not find crashdump file: {}",
+                    prettify_path(crashdump)
+                );
+            }
+        }
+
+        if self.kind == DebuggerKind::Mock {
+            let (output, measurements) = create_mock_debugger_output(script_file_path, session);
+
+            if stream_output {
+                echo_output(&output);
+            }
+
+            return Ok((output, measurements));
+        }
+
+        if self.command == dbgeng::COMMAND {
+            if !env_vars.is_empty() {
+                bail!("#params is not supported with the debugger engine (`-d dbgeng`)");
+            }
+
+            if !debuggee_args.is_empty() {
+                bail!("#args is not supported with the debugger engine (`-d dbgeng`)");
+            }
+
+            // The engine runs each command synchronously, so there is no way to interrupt one
+            // that takes too long
+            if command_timeout.is_some() {
+                bail!("--command-timeout is not supported with the debugger engine (`-d dbgeng`)");
+            }
+
+            // The engine delivers its output through callbacks, which aren't streamed yet
+            let (output, measurements) =
+                dbgeng::run(script_file_path, debuggee, crashdump, timeout)?;
+
+            if stream_output {
+                echo_output(&output);
+            }
+
+            return Ok((output, measurements));
+        }
+
+        let env_vars: Vec<(String, String)> = self
+            .configured_env_vars()
+            .into_iter()
+            .chain(env_vars.iter().cloned())
+            .collect();
+
+        if let Some(wine) = &self.wine {
+            let script_args = self
+                .kind
+                .script_args(&wine::windows_path(script_file_path), self.lldb_script_mode)?;
+            let mut command = self.kind.command(
+                &self.command,
+                &script_args,
+                &wine::windows_path(debuggee),
+                debuggee_args,
+                crashdump.map(wine::windows_path).as_deref(),
+                &self.commandline_args,
+            );
+            command.envs(env_vars);
+
+            return run_with_timeout(wine::wrap(&command, wine), debuggee, session);
+        }
+
+        let script_args = self
+            .kind
+            .script_args(script_file_path, self.lldb_script_mode)?;
+        let mut command = self.kind.command(
+            &self.command,
+            &script_args,
+            debuggee,
+            debuggee_args,
+            crashdump,
+            &self.commandline_args,
+        );
+        command.envs(env_vars);
+
+        run_with_timeout(command, debuggee, session)
+    }
+
+    /// Creates a debugger that is only used for processing output captured previously,
+    /// see `dbt recheck`. It needs the `--debugger-prelude` commands of the run, as they are
+    /// part of the debugger scripts.
+    pub fn offline(
+        kind: DebuggerKind,
+        version: Arc<str>,
+        preludes: &[OsString],
+        defines: &[String],
+    ) -> anyhow::Result<Debugger> {
+        Ok(Debugger::new(
+            kind,
+            version.clone(),
+            "offline".into(),
+            build_prelude_map(preludes)?.for_debugger(kind, &version),
+            vec![],
+            vec![],
+            define_variables(defines)?,
+        ))
+    }
+
+    pub fn mock() -> Debugger {
+        Debugger::new(
+            DebuggerKind::Mock,
+            "1.0".into(),
+            "mockdbg".into(),
+            vec![],
+            vec![],
+            vec![],
+            vec![].into(),
+        )
+    }
+
+    /// Returns `Some(reason)` if the test should be ignored for this debugger.
+    pub fn ignore_test(
+        &self,
+        test_definition: &TestDefinition,
+        cargo_profile: &Arc<str>,
+        phase: &PhaseConfig,
+    ) -> Option<Option<Arc<str>>> {
+        let evaluation_context =
+            self.evaluation_context(&test_definition.config, cargo_profile, phase);
+        test_definition.script.ignore_test(&evaluation_context)
+    }
+
+    pub fn has_active_checks(
         &self,
         test_definition: &TestDefinition,
         cargo_profile: &Arc<str>,
         phase: &PhaseConfig,
     ) -> bool {
-        let evaluation_context = self.evaluation_context(cargo_profile, phase);
+        let evaluation_context =
+            self.evaluation_context(&test_definition.config, cargo_profile, phase);
         test_definition
             .script
             .has_active_checks(&evaluation_context)
     }
 
+    /// The reason for skipping the test if `--check-filter` matches none of its checks
+    pub fn skipped_by_check_filter(
+        &self,
+        test_definition: &TestDefinition,
+        cargo_profile: &Arc<str>,
+        phase: &PhaseConfig,
+    ) -> Option<String> {
+        let filter = self.check_filter.as_ref()?;
+        let evaluation_context =
+            self.evaluation_context(&test_definition.config, cargo_profile, phase);
+        let check_filter = CheckFilter::new(self, &test_definition.script);
+        let mut selected = false;
+
+        test_definition
+            .script
+            .walk_applicable_leaves(&evaluation_context, &mut |statement| {
+                if let Statement::Check(..)
+                | Statement::CheckUnorderedBlock(..)
+                | Statement::CommandCheck(..)
+                | Statement::ProgramOutputCheck(..) = statement
+                {
+                    selected = check_filter.selects(statement);
+                }
+
+                !selected
+            });
+
+        if selected {
+            None
+        } else {
+            Some(format!("no check matches `--check-filter {}`", filter))
+        }
+    }
+
+    /// Returns the first directive of the test that this debugger can't run, see
+    /// [DebuggerKind::unsupported_directive]
+    pub fn unsupported_directive(
+        &self,
+        test_definition: &TestDefinition,
+        cargo_profile: &Arc<str>,
+        phase: &PhaseConfig,
+    ) -> Option<&'static str> {
+        let evaluation_context =
+            self.evaluation_context(&test_definition.config, cargo_profile, phase);
+        let mut unsupported = None;
+
+        test_definition
+            .script
+            .walk_applicable_leaves(&evaluation_context, &mut |statement| {
+                if let Statement::Command(command, _, _) = statement {
+                    unsupported = self.kind.unsupported_directive(command);
+                }
+
+                unsupported.is_none()
+            });
+
+        unsupported
+    }
+
+    /// Returns the requirements declared via #require that this debugger does not meet.
+    pub fn unmet_requirements(
+        &self,
+        test_definition: &TestDefinition,
+        cargo_profile: &Arc<str>,
+        phase: &PhaseConfig,
+    ) -> Vec<Requirement> {
+        let evaluation_context =
+            self.evaluation_context(&test_definition.config, cargo_profile, phase);
+        test_definition
+            .script
+            .requirements(&evaluation_context)
+            .into_iter()
+            .filter(|&requirement| !self.meets_requirement(requirement))
+            .collect()
+    }
+
+    fn meets_requirement(&self, requirement: Requirement) -> bool {
+        if let Some(&met) = self.requirements_met.lock().unwrap().get(&requirement) {
+            return met;
+        }
+
+        let met = match (requirement, self.kind) {
+            // Tests that were skipped when recording simply have no recording
+            _ if self.replay_directory.is_some() => true,
+            (_, DebuggerKind::Mock) => true,
+            (Requirement::Python, DebuggerKind::Cdb) => false,
+            (Requirement::Python, DebuggerKind::Gdb | DebuggerKind::Lldb) => {
+                self.probe_python_support()
+            }
+            (Requirement::Natvis, kind) => kind == DebuggerKind::Cdb,
+            (Requirement::SymbolServer, DebuggerKind::Cdb) => self
+                .env_var("_NT_SYMBOL_PATH")
+                .is_some_and(|path| path.to_lowercase().contains("srv*")),
+            (Requirement::SymbolServer, DebuggerKind::Gdb | DebuggerKind::Lldb) => self
+                .env_var("DEBUGINFOD_URLS")
+                .is_some_and(|urls| !urls.trim().is_empty()),
+        };
+
+        info!("{:?}: requirement `{}` met: {}", self, requirement, met);
+
+        self.requirements_met
+            .lock()
+            .unwrap()
+            .insert(requirement, met);
+
+        met
+    }
+
+    /// Runs the debugger with a trivial Python command to find out if it has been
+    /// built with Python support.
+    fn probe_python_support(&self) -> bool {
+        const PROBE: &str = "__dbt_python_probe__";
+
+        let mut command = Command::new(&self.command);
+        command.envs(self.configured_env_vars());
+
+        // E.g. gdb's `--data-directory` determines where it finds its Python modules
+        command.args(&self.commandline_args);
+
+        match self.kind {
+            DebuggerKind::Gdb => {
+                command
+                    .arg("--batch")
+                    .arg("-ex")
+                    .arg(format!("python print('{}')", PROBE));
+            }
+            DebuggerKind::Lldb => {
+                command
+                    .arg("--batch")
+                    .arg("-o")
+                    .arg(format!("script print('{}')", PROBE));
+            }
+            DebuggerKind::Cdb | DebuggerKind::Mock => unreachable!(),
+        }
+
+        match command.output() {
+            Ok(output) => {
+                output.status.success() && String::from_utf8_lossy(&output.stdout).contains(PROBE)
+            }
+            Err(e) => {
+                warn!("Could not probe {:?} for Python support: {}", self, e);
+                false
+            }
+        }
+    }
+
+    /// The environment variables forcing the locale, followed by those given via
+    /// `--debugger-env`, which take precedence
+    fn configured_env_vars(&self) -> Vec<(String, String)> {
+        let forced_locale = if self.forced_locale {
+            self.kind.forced_locale_env_vars()
+        } else {
+            &[]
+        };
+
+        forced_locale
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .chain(self.env_vars.iter().cloned())
+            .collect()
+    }
+
+    /// Looks up an environment variable as the debugger process would see it.
+    fn env_var(&self, name: &str) -> Option<String> {
+        self.configured_env_vars()
+            .into_iter()
+            .rev()
+            .find(|(var_name, _)| var_name == name)
+            .map(|(_, value)| value)
+            .or_else(|| std::env::var(name).ok())
+    }
+
     pub fn active_crashdump_tags(
         &self,
         test_definition: &TestDefinition,
         cargo_profile: &Arc<str>,
     ) -> Vec<Arc<str>> {
-        let evaluation_context = self.evaluation_context(cargo_profile, &PhaseConfig::Live);
+        let evaluation_context =
+            self.evaluation_context(&test_definition.config, cargo_profile, &PhaseConfig::Live);
         test_definition
             .script
             .active_crashdump_tags(&evaluation_context)
@@ -274,11 +1460,19 @@ pub fn active_crashdump_tags(
 
     /// Tries to create a Debugger object from its commandline command. Will invoke the command
     /// to get a version string.
-    fn infer_from_command(command: &Path) -> anyhow::Result<(DebuggerKind, Arc<str>)> {
+    fn infer_from_command(
+        command: &Path,
+        wine: Option<&OsStr>,
+    ) -> anyhow::Result<(DebuggerKind, Arc<str>)> {
         if command.to_string_lossy() == "mockdbg" {
             return Ok((DebuggerKind::Mock, "1.0".into()));
         }
 
+        // The DbgEng backend runs the same scripts as CDB
+        if command.to_string_lossy() == dbgeng::COMMAND {
+            return Ok((DebuggerKind::Cdb, dbgeng::version()?.into()));
+        }
+
         if let Some(file_name) = command.file_name() {
             let file_name = file_name.to_string_lossy().to_lowercase();
 
@@ -289,7 +1483,13 @@ fn infer_from_command(command: &Path) -> anyhow::Result<(DebuggerKind, Arc<str>)
                 "--version"
             };
 
-            let output = Command::new(command).arg(version_arg).output()?;
+            let mut version_command = Command::new(command);
+            version_command.arg(version_arg);
+
+            let output = match wine {
+                Some(wine) => wine::wrap(&version_command, wine).output()?,
+                None => version_command.output()?,
+            };
 
             if !output.status.success() {
                 bail!("failed to get debugger version from {}", command.display());
@@ -321,7 +1521,69 @@ fn infer_from_command(command: &Path) -> anyhow::Result<(DebuggerKind, Arc<str>)
         )
     }
 
+    /// The commands that make the debugger dump the debuggee to `path` when it faults, e.g.
+    /// because of a segfault or an access violation, to be put at the start of the script of
+    /// a live test. The dump is written when the fault occurs, as the debuggee is gone once
+    /// the script continues it. `None` for debuggers that can't write dumps (LLDB, see
+    /// [Debugger::emit_crashdump_command]) or if disabled.
+    pub fn fault_dump_command(&self, path: &Path) -> Option<String> {
+        if !self.fault_dumps {
+            return None;
+        }
+
+        let windows_path;
+        let path = if self.wine.is_some() {
+            windows_path = wine::windows_path(path);
+            &windows_path
+        } else {
+            path
+        };
+
+        match self.kind {
+            DebuggerKind::Gdb => Some(format!(
+                "python\n\
+                 def dbt_dump_on_fault(event):\n    \
+                     faults = ('SIGSEGV', 'SIGBUS', 'SIGILL', 'SIGFPE', 'SIGABRT')\n    \
+                     if isinstance(event, gdb.SignalEvent) and event.stop_signal in faults:\n        \
+                         try:\n            \
+                             gdb.execute('generate-core-file ' + {:?}, to_string=True)\n        \
+                         except gdb.error:\n            \
+                             pass\n\
+                 gdb.events.stop.connect(dbt_dump_on_fault)\n\
+                 end",
+                path.display().to_string()
+            )),
+            DebuggerKind::Cdb => {
+                // The command is a quoted string itself, so the quotes around the path and the
+                // backslashes in it are escaped
+                let command = format!(".dump /ma /o \"{}\"", path.display())
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"");
+
+                // Illegal instructions only break on the second chance by default, which is
+                // kept, so the command runs on the second chance for them
+                Some(
+                    ["av", "sov", "dz"]
+                        .iter()
+                        .map(|event| format!("sxe -c \"{}\" {}", command, event))
+                        .chain([format!("sxd -c2 \"{}\" ii", command)])
+                        .collect::<Vec<_>>()
+                        .join("\n"),
+                )
+            }
+            DebuggerKind::Lldb | DebuggerKind::Mock => None,
+        }
+    }
+
     fn emit_crashdump_command(&self, path: &Path, output: &mut String) {
+        let windows_path;
+        let path = if self.wine.is_some() {
+            windows_path = wine::windows_path(path);
+            &windows_path
+        } else {
+            path
+        };
+
         match self.kind {
             DebuggerKind::Cdb => {
                 writeln!(output, ".dump /ma {}", path.display()).unwrap();
@@ -338,852 +1600,5203 @@ fn emit_crashdump_command(&self, path: &Path, output: &mut String) {
         }
     }
 
-    fn maybe_emit_correlation_id_command(
-        &self,
-        begin: bool,
-        correlation_id: Option<CorrelationId>,
-        output: &mut String,
-    ) {
-        if let Some(correlation_id) = correlation_id {
-            let marker = if begin {
-                CORRELATION_ID_BEGIN_MARKER
-            } else {
-                CORRELATION_ID_END_MARKER
-            };
-
-            match self.kind {
-                DebuggerKind::Cdb => {
-                    writeln!(output, ".echo {}{}", marker, correlation_id.0).unwrap();
-                }
-                DebuggerKind::Gdb => {
-                    writeln!(output, "python print('{}{}')", marker, correlation_id.0).unwrap();
-                }
-                DebuggerKind::Mock => {
-                    writeln!(output, "{}{}", marker, correlation_id.0).unwrap();
-                }
-                DebuggerKind::Lldb => {
-                    writeln!(output, "script print('{}{}')", marker, correlation_id.0).unwrap();
-                }
+    /// Translates a debugger-agnostic command into this debugger's syntax.
+    fn emit_command(&self, command: &DebuggerCommand, output: &mut String) {
+        match (command, self.kind) {
+            (&DebuggerCommand::SelectThread(number), DebuggerKind::Cdb) => {
+                // CDB numbers threads starting at zero
+                writeln!(output, "~{}s", number - 1).unwrap();
             }
-        }
-    }
-
-    fn assign_correlation_ids(
-        &self,
-        script: &mut Script,
-        cargo_profile: &Arc<str>,
-        phase: &PhaseConfig,
-    ) {
-        let mut correlation_ids_checked = HashSet::new();
-        let mut next_correlation_id = 0;
-        let mut last_correlation_id_emitted = None;
-
-        let evaluation_context = self.evaluation_context(cargo_profile, phase);
-
-        // Assign correlation ids
-        script.walk_applicable_leaves_mut(&evaluation_context, &mut |statement| {
-            match statement {
-                Statement::Exec(_, correlation_id_slot, _) => {
-                    debug_assert_eq!(correlation_id_slot, &None);
-
-                    let last_id_has_been_checked = last_correlation_id_emitted
-                        .map_or(true, |id| correlation_ids_checked.contains(&id));
-
-                    let correlation_id = if last_id_has_been_checked {
-                        // Allocate a new one
-                        let correlation_id = CorrelationId(next_correlation_id);
-                        next_correlation_id += 1;
-                        correlation_id
-                    } else {
-                        // Re-use the existing one
-                        last_correlation_id_emitted.unwrap()
-                    };
-
-                    *correlation_id_slot = Some(correlation_id);
-                    last_correlation_id_emitted = Some(correlation_id);
-                }
-                Statement::Check(_, correlation_id_slot, _)
-                | Statement::CheckUnorderedBlock(_, correlation_id_slot, _) => {
-                    if let Some(last_correlation_id_emitted) = last_correlation_id_emitted {
-                        debug_assert_eq!(last_correlation_id_emitted.0, next_correlation_id - 1);
-                        *correlation_id_slot = Some(last_correlation_id_emitted);
-                        correlation_ids_checked.insert(last_correlation_id_emitted);
-                    } else {
-                        warn!("{:?} has no output to check", statement);
-                    }
-                }
-                // Add any statements here that unconditionally get wrapped in their own correlation ID
-                Statement::GenerateCrashDump(_, correlation_id_slot, _) => {
-                    debug_assert_eq!(correlation_id_slot, &None);
-                    let correlation_id = CorrelationId(next_correlation_id);
-                    next_correlation_id += 1;
-                    *correlation_id_slot = Some(correlation_id);
-                    assert!(correlation_ids_checked.insert(correlation_id));
-                    last_correlation_id_emitted = Some(correlation_id);
-                }
-                Statement::IfBlock(..) | Statement::IgnoreTest(_) | Statement::Phase(..) => {
-                    // Nothing to do
-                }
+            (&DebuggerCommand::SelectThread(number), DebuggerKind::Gdb) => {
+                writeln!(output, "thread {}", number).unwrap();
             }
-            true
-        });
-    }
-
-    /// Write a debugger (and version) specific prelude to the debugger script
-    /// Will be invoked before anything is written to the script.
-    fn emit_script_prelude(&self, script: &mut String) {
-        match self.kind {
-            DebuggerKind::Cdb => {
-                writeln!(script, ".lines -e").unwrap();
+            (&DebuggerCommand::SelectThread(number), DebuggerKind::Lldb) => {
+                writeln!(output, "thread select {}", number).unwrap();
             }
-            DebuggerKind::Gdb => {}
-            DebuggerKind::Lldb => {
-                // We want to actually stop at breakpoints
-                writeln!(script, "script lldb.debugger.SetAsync(False)").unwrap();
-                // Don't wait for user input
-                writeln!(script, "settings set auto-confirm true").unwrap();
+            (&DebuggerCommand::SelectThread(number), DebuggerKind::Mock) => {
+                writeln!(output, "select_thread {}", number).unwrap();
             }
-            DebuggerKind::Mock => {
-                // no prelude
+            (DebuggerCommand::ListThreads, DebuggerKind::Cdb) => {
+                writeln!(output, "~").unwrap();
             }
-        };
-
-        for command in &self.prelude {
-            writeln!(script, "{}", command).unwrap();
+            (DebuggerCommand::ListThreads, DebuggerKind::Gdb) => {
+                writeln!(output, "info threads").unwrap();
+            }
+            (DebuggerCommand::ListThreads, DebuggerKind::Lldb) => {
+                writeln!(output, "thread list").unwrap();
+            }
+            (DebuggerCommand::ListThreads, DebuggerKind::Mock) => {
+                writeln!(output, "list_threads").unwrap();
+            }
+            (DebuggerCommand::ListLocals, DebuggerKind::Cdb) => {
+                writeln!(output, "dv").unwrap();
+            }
+            (DebuggerCommand::ListLocals, DebuggerKind::Gdb) => {
+                writeln!(output, "info locals").unwrap();
+            }
+            (DebuggerCommand::ListLocals, DebuggerKind::Lldb) => {
+                writeln!(output, "frame variable").unwrap();
+            }
+            (DebuggerCommand::ListLocals, DebuggerKind::Mock) => {
+                writeln!(output, "list_locals").unwrap();
+            }
+            (DebuggerCommand::DescribeType(type_name), DebuggerKind::Cdb) => {
+                writeln!(output, "dt -v {}", type_name).unwrap();
+            }
+            (DebuggerCommand::DescribeType(type_name), DebuggerKind::Gdb) => {
+                writeln!(output, "ptype /o {}", type_name).unwrap();
+            }
+            (DebuggerCommand::DescribeType(type_name), DebuggerKind::Lldb) => {
+                writeln!(output, "type lookup {}", type_name).unwrap();
+            }
+            (DebuggerCommand::DescribeType(type_name), DebuggerKind::Mock) => {
+                writeln!(output, "describe_type {}", type_name).unwrap();
+            }
+            (&DebuggerCommand::SelectFrame(number), DebuggerKind::Cdb) => {
+                writeln!(output, ".frame {}", number).unwrap();
+            }
+            (&DebuggerCommand::SelectFrame(number), DebuggerKind::Gdb) => {
+                writeln!(output, "frame {}", number).unwrap();
+            }
+            (&DebuggerCommand::SelectFrame(number), DebuggerKind::Lldb) => {
+                writeln!(output, "frame select {}", number).unwrap();
+            }
+            (&DebuggerCommand::SelectFrame(number), DebuggerKind::Mock) => {
+                writeln!(output, "select_frame {}", number).unwrap();
+            }
+            (DebuggerCommand::FrameUp, DebuggerKind::Cdb) => {
+                // `.f+` moves to the caller, like `up` in GDB
+                writeln!(output, ".f+").unwrap();
+            }
+            (DebuggerCommand::FrameUp, DebuggerKind::Gdb) => {
+                writeln!(output, "up").unwrap();
+            }
+            (DebuggerCommand::FrameUp, DebuggerKind::Lldb) => {
+                writeln!(output, "frame select --relative 1").unwrap();
+            }
+            (DebuggerCommand::FrameUp, DebuggerKind::Mock) => {
+                writeln!(output, "frame_up").unwrap();
+            }
+            (DebuggerCommand::FrameDown, DebuggerKind::Cdb) => {
+                writeln!(output, ".f-").unwrap();
+            }
+            (DebuggerCommand::FrameDown, DebuggerKind::Gdb) => {
+                writeln!(output, "down").unwrap();
+            }
+            (DebuggerCommand::FrameDown, DebuggerKind::Lldb) => {
+                writeln!(output, "frame select --relative -1").unwrap();
+            }
+            (DebuggerCommand::FrameDown, DebuggerKind::Mock) => {
+                writeln!(output, "frame_down").unwrap();
+            }
+            (DebuggerCommand::Continue, DebuggerKind::Cdb) => {
+                writeln!(output, "g").unwrap();
+            }
+            (
+                DebuggerCommand::Continue,
+                DebuggerKind::Gdb | DebuggerKind::Lldb | DebuggerKind::Mock,
+            ) => {
+                writeln!(output, "continue").unwrap();
+            }
+            (DebuggerCommand::ContinueToExit, DebuggerKind::Cdb) => {
+                // CDB stops at the final breakpoint without printing the exit code, which
+                // `.lastevent` reports as e.g. `Exit process 0:1a2c, code 0`
+                writeln!(output, "g").unwrap();
+                writeln!(output, ".lastevent").unwrap();
+            }
+            (
+                DebuggerCommand::ContinueToExit,
+                DebuggerKind::Gdb | DebuggerKind::Lldb | DebuggerKind::Mock,
+            ) => {
+                writeln!(output, "continue").unwrap();
+            }
+            (DebuggerCommand::ReadRegister(name), DebuggerKind::Cdb) => {
+                writeln!(output, "r {}", name).unwrap();
+            }
+            (DebuggerCommand::ReadRegister(name), DebuggerKind::Gdb) => {
+                writeln!(output, "info registers {}", name).unwrap();
+            }
+            (DebuggerCommand::ReadRegister(name), DebuggerKind::Lldb) => {
+                writeln!(output, "register read {}", name).unwrap();
+            }
+            (DebuggerCommand::ReadRegister(name), DebuggerKind::Mock) => {
+                writeln!(output, "read_register {}", name).unwrap();
+            }
+            (DebuggerCommand::ReadMemory(expression, len), DebuggerKind::Cdb) => {
+                // `0n` makes CDB interpret the length as decimal
+                writeln!(output, "db {} L0n{}", expression, len).unwrap();
+            }
+            (DebuggerCommand::ReadMemory(expression, len), DebuggerKind::Gdb) => {
+                writeln!(output, "x/{}xb {}", len, expression).unwrap();
+            }
+            (DebuggerCommand::ReadMemory(expression, len), DebuggerKind::Lldb) => {
+                writeln!(
+                    output,
+                    "memory read --size 1 --format x --count {} {}",
+                    len, expression
+                )
+                .unwrap();
+            }
+            (DebuggerCommand::ReadMemory(expression, len), DebuggerKind::Mock) => {
+                writeln!(output, "read_memory {} {}", expression, len).unwrap();
+            }
+            (&DebuggerCommand::FollowFork(mode), DebuggerKind::Cdb) => {
+                // CDB attaches to child processes in addition to the parent
+                let enabled = mode == ForkMode::Child;
+                writeln!(output, ".childdbg {}", enabled as u8).unwrap();
+            }
+            (&DebuggerCommand::FollowFork(mode), DebuggerKind::Gdb) => {
+                writeln!(output, "set follow-fork-mode {}", mode).unwrap();
+                // Keep the other process around so that `#inferior` can switch to it
+                writeln!(output, "set detach-on-fork off").unwrap();
+            }
+            (&DebuggerCommand::FollowFork(mode), DebuggerKind::Lldb) => {
+                writeln!(
+                    output,
+                    "settings set target.process.follow-fork-mode {}",
+                    mode
+                )
+                .unwrap();
+            }
+            (&DebuggerCommand::FollowFork(mode), DebuggerKind::Mock) => {
+                writeln!(output, "follow_fork {}", mode).unwrap();
+            }
+            (&DebuggerCommand::SelectInferior(number), DebuggerKind::Cdb) => {
+                // CDB numbers processes starting at zero
+                writeln!(output, "|{}s", number - 1).unwrap();
+            }
+            (&DebuggerCommand::SelectInferior(number), DebuggerKind::Gdb) => {
+                writeln!(output, "inferior {}", number).unwrap();
+            }
+            (&DebuggerCommand::SelectInferior(number), DebuggerKind::Lldb) => {
+                // LLDB numbers targets starting at zero
+                writeln!(output, "target select {}", number - 1).unwrap();
+            }
+            (&DebuggerCommand::SelectInferior(number), DebuggerKind::Mock) => {
+                writeln!(output, "select_inferior {}", number).unwrap();
+            }
+            (DebuggerCommand::Print(expression), DebuggerKind::Cdb) => {
+                writeln!(output, "dx {}", expression).unwrap();
+            }
+            (DebuggerCommand::Print(expression), DebuggerKind::Gdb | DebuggerKind::Mock) => {
+                writeln!(output, "print {}", expression).unwrap();
+            }
+            (DebuggerCommand::Print(expression), DebuggerKind::Lldb) => {
+                writeln!(output, "expression -- {}", expression).unwrap();
+            }
+            (DebuggerCommand::ReverseStep, DebuggerKind::Cdb) => {
+                writeln!(output, "p-").unwrap();
+            }
+            (DebuggerCommand::ReverseStepInto, DebuggerKind::Cdb) => {
+                writeln!(output, "t-").unwrap();
+            }
+            (DebuggerCommand::ReverseContinue, DebuggerKind::Cdb) => {
+                writeln!(output, "g-").unwrap();
+            }
+            (DebuggerCommand::ReverseFinish, DebuggerKind::Cdb) => {
+                writeln!(output, "g-u").unwrap();
+            }
+            // GDB only supports these while recording, e.g. after `record full` or under rr
+            (DebuggerCommand::ReverseStep, DebuggerKind::Gdb) => {
+                writeln!(output, "reverse-next").unwrap();
+            }
+            (DebuggerCommand::ReverseStepInto, DebuggerKind::Gdb) => {
+                writeln!(output, "reverse-step").unwrap();
+            }
+            (DebuggerCommand::ReverseContinue, DebuggerKind::Gdb) => {
+                writeln!(output, "reverse-continue").unwrap();
+            }
+            (DebuggerCommand::ReverseFinish, DebuggerKind::Gdb) => {
+                writeln!(output, "reverse-finish").unwrap();
+            }
+            // Tests using these are skipped for LLDB, see [DebuggerKind::unsupported_directive]
+            (
+                DebuggerCommand::ReverseStep
+                | DebuggerCommand::ReverseStepInto
+                | DebuggerCommand::ReverseFinish,
+                DebuggerKind::Lldb,
+            ) => {
+                writeln!(output, "# reverse stepping is not supported by LLDB").unwrap();
+            }
+            // LLDB can only continue backwards with a remote that supports it, like rr
+            (DebuggerCommand::ReverseContinue, DebuggerKind::Lldb) => {
+                writeln!(output, "process continue --reverse").unwrap();
+            }
+            (DebuggerCommand::ReverseStep, DebuggerKind::Mock) => {
+                writeln!(output, "reverse_step").unwrap();
+            }
+            (DebuggerCommand::ReverseStepInto, DebuggerKind::Mock) => {
+                writeln!(output, "reverse_step_into").unwrap();
+            }
+            (DebuggerCommand::ReverseContinue, DebuggerKind::Mock) => {
+                writeln!(output, "reverse_continue").unwrap();
+            }
+            (DebuggerCommand::ReverseFinish, DebuggerKind::Mock) => {
+                writeln!(output, "reverse_finish").unwrap();
+            }
+            (DebuggerCommand::Watch(watchpoint), DebuggerKind::Cdb) => {
+                // `ba r` breaks on reads and writes alike
+                let access = match watchpoint.access {
+                    WatchAccess::Write => 'w',
+                    WatchAccess::Read | WatchAccess::Access => 'r',
+                };
+                writeln!(
+                    output,
+                    "ba {}{} @@c++(&{})",
+                    access,
+                    watchpoint.width.unwrap_or(4),
+                    watchpoint.place
+                )
+                .unwrap();
+            }
+            (DebuggerCommand::Watch(watchpoint), DebuggerKind::Gdb) => {
+                let command = match watchpoint.access {
+                    WatchAccess::Write => "watch",
+                    WatchAccess::Read => "rwatch",
+                    WatchAccess::Access => "awatch",
+                };
+                // GDB watches as many bytes as the type of the expression has
+                match watchpoint.width {
+                    Some(width) => writeln!(
+                        output,
+                        "{} -location *(&({}) as *const u{})",
+                        command,
+                        watchpoint.place,
+                        u32::from(width) * 8
+                    )
+                    .unwrap(),
+                    None => writeln!(output, "{} -location {}", command, watchpoint.place).unwrap(),
+                }
+            }
+            (DebuggerCommand::Watch(watchpoint), DebuggerKind::Lldb) => {
+                let access = match watchpoint.access {
+                    WatchAccess::Write => "write",
+                    WatchAccess::Read => "read",
+                    WatchAccess::Access => "read_write",
+                };
+                let size = match watchpoint.width {
+                    Some(width) => format!(" -s {}", width),
+                    None => String::new(),
+                };
+                writeln!(
+                    output,
+                    "watchpoint set expression -w {}{} -- &({})",
+                    access, size, watchpoint.place
+                )
+                .unwrap();
+            }
+            (DebuggerCommand::Watch(watchpoint), DebuggerKind::Mock) => match watchpoint.width {
+                Some(width) => writeln!(
+                    output,
+                    "watch {} {} {}",
+                    watchpoint.access.keyword(),
+                    width,
+                    watchpoint.place
+                )
+                .unwrap(),
+                None => writeln!(
+                    output,
+                    "watch {} {}",
+                    watchpoint.access.keyword(),
+                    watchpoint.place
+                )
+                .unwrap(),
+            },
         }
     }
 
-    /// Emit commands for setting breakpoints that have been specified via #break directives
-    fn emit_breakpoints(
-        &self,
-        test_definition: &TestDefinition,
-        phase: &PhaseConfig,
-        script: &mut String,
-    ) {
-        if *phase != PhaseConfig::Live {
-            return;
-        }
+    /// Checks the output of the command issued by a [CommandCheck]. Returns a
+    /// description of the problem if the check fails.
+    fn evaluate_command_check(&self, check: &CommandCheck, output: &[&str]) -> Result<(), String> {
+        match check {
+            &CommandCheck::ThreadCount(expected) => {
+                let actual = self.count_threads(output);
 
-        for bp in &test_definition.breakpoints {
-            match self.kind {
-                DebuggerKind::Cdb | DebuggerKind::Mock => {
-                    writeln!(
-                        script,
-                        "bp `{}:{}`",
-                        test_definition.name.rsplit_once('/').unwrap().1,
-                        bp.line_index + 1
-                    )
-                    .unwrap();
+                if actual != expected as usize {
+                    return Err(format!("Expected {} threads, found {}", expected, actual));
                 }
-                DebuggerKind::Gdb => {
-                    writeln!(
-                        script,
-                        "break '{}:{}'",
-                        test_definition.name.rsplit_once('/').unwrap().1,
-                        bp.line_index + 1
-                    )
-                    .unwrap();
+            }
+            CommandCheck::Register(name, constraint) => match self.register_value(name, output) {
+                Some(value) if constraint.is_satisfied_by(value) => {}
+                Some(value) => {
+                    return Err(format!(
+                        "Expected register {} to be {} {}, found {:#x}",
+                        name,
+                        constraint.op.symbol(),
+                        constraint.value,
+                        value
+                    ))
                 }
-                DebuggerKind::Lldb => {
-                    writeln!(
-                        script,
-                        "breakpoint set --file '{}' --line {}",
-                        test_definition.name.rsplit_once('/').unwrap().1,
-                        bp.line_index + 1
-                    )
-                    .unwrap();
+                None => return Err(format!("Could not find value of register {}", name)),
+            },
+            CommandCheck::Memory(expression, expected) => {
+                let actual = self.memory_bytes(output, expected.len());
+
+                let matches = actual.len() == expected.len()
+                    && expected
+                        .iter()
+                        .zip(&actual)
+                        .all(|(expected, actual)| expected.is_none_or(|byte| byte == *actual));
+
+                if !matches {
+                    let format_byte = |byte: &Option<u8>| match byte {
+                        Some(byte) => format!("{:02x}", byte),
+                        None => "??".to_string(),
+                    };
+
+                    return Err(format!(
+                        "Expected memory at `{}` to be `{}`, found `{}`",
+                        expression,
+                        expected
+                            .iter()
+                            .map(format_byte)
+                            .collect::<Vec<_>>()
+                            .join(" "),
+                        actual
+                            .iter()
+                            .map(|&byte| format_byte(&Some(byte)))
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    ));
+                }
+            }
+            CommandCheck::CleanExit => self.check_clean_exit(output)?,
+            CommandCheck::Locals(expected, exact) => {
+                let actual = self.locals(output);
+                let names = || {
+                    actual
+                        .iter()
+                        .map(|(name, _)| name.as_str())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                };
+
+                for local in expected {
+                    let value = match actual.iter().find(|(name, _)| **name == *local.name) {
+                        Some((_, value)) => value,
+                        None => {
+                            return Err(format!(
+                                "Expected a local variable `{}`, found only: {}",
+                                local.name,
+                                names()
+                            ))
+                        }
+                    };
+
+                    if let Some(check) = &local.value {
+                        if !check.check(value) {
+                            return Err(format!(
+                                "Expected local variable `{}` to match '{}', found `{}`",
+                                local.name, check, value
+                            ));
+                        }
+                    }
+                }
+
+                if *exact {
+                    let unexpected: Vec<_> = actual
+                        .iter()
+                        .filter(|(name, _)| !expected.iter().any(|local| *local.name == **name))
+                        .map(|(name, _)| name.as_str())
+                        .collect();
+
+                    if !unexpected.is_empty() {
+                        return Err(format!(
+                            "Found unexpected local variables: {}",
+                            unexpected.join(", ")
+                        ));
+                    }
+                }
+            }
+            CommandCheck::Str(expression, expected) => match self.string_value(output) {
+                Some(actual) if actual == **expected => {}
+                Some(actual) => {
+                    return Err(format!(
+                        "Expected `{}` to be {:?}, found {:?}",
+                        expression, expected, actual
+                    ))
+                }
+                None => {
+                    return Err(format!(
+                        "Could not find the contents of `{}` in the debugger output",
+                        expression
+                    ))
+                }
+            },
+            CommandCheck::Variant(expression, variant, payload) => {
+                self.check_variant(expression, variant, payload.as_deref(), output)?
+            }
+            CommandCheck::TypeLayout(expected) => {
+                let (size, fields) = self.type_layout(output);
+                let type_name = &expected.type_name;
+
+                match (expected.size, size) {
+                    (None, _) => {}
+                    (Some(expected), Some(actual)) if expected == actual => {}
+                    (Some(expected), Some(actual)) => {
+                        return Err(format!(
+                            "Expected `{}` to have size {}, found {}",
+                            type_name, expected, actual
+                        ))
+                    }
+                    (Some(_), None) => {
+                        return Err(format!(
+                            "Could not find the size of `{}` in the debugger output",
+                            type_name
+                        ))
+                    }
+                }
+
+                for field in &expected.fields {
+                    let actual = match fields.iter().find(|actual| actual.name == field.name) {
+                        Some(actual) => actual,
+                        None => {
+                            return Err(format!(
+                                "Expected `{}` to have a field `{}`, found: {}",
+                                type_name,
+                                field.name,
+                                fields
+                                    .iter()
+                                    .map(|field| &field.name[..])
+                                    .collect::<Vec<_>>()
+                                    .join(", ")
+                            ))
+                        }
+                    };
+
+                    for (what, expected, actual) in [
+                        ("offset", field.offset, actual.offset),
+                        ("size", field.size, actual.size),
+                    ] {
+                        match (expected, actual) {
+                            (None, _) => {}
+                            (Some(expected), Some(actual)) if expected == actual => {}
+                            (Some(expected), Some(actual)) => {
+                                return Err(format!(
+                                    "Expected field `{}` of `{}` to have {} {}, found {}",
+                                    field.name, type_name, what, expected, actual
+                                ))
+                            }
+                            (Some(_), None) => {
+                                return Err(format!(
+                                    "Could not find the {} of field `{}` of `{}` in the debugger \
+                                     output",
+                                    what, field.name, type_name
+                                ))
+                            }
+                        }
+                    }
                 }
             }
         }
+
+        Ok(())
     }
 
-    pub fn evaluation_context(
-        &self,
-        cargo_profile: &Arc<str>,
-        phase: &PhaseConfig,
-    ) -> EvaluationContext {
-        let default_value: Value = "true".into();
+    /// Checks that the output of continuing the debuggee for [CommandCheck::CleanExit] shows
+    /// it exiting with code 0, rather than stopping at a breakpoint or because of a fault:
+    ///
+    /// - GDB: `[Inferior 1 (process 42) exited normally]`, `exited with code 01`,
+    ///   `Breakpoint 2, main::f ()`, `Temporary breakpoint 2, main::f ()`,
+    ///   `Program received signal SIGSEGV`
+    /// - LLDB: `Process 42 exited with status = 0 (0x00000000)`,
+    ///   `stop reason = breakpoint 2.1`, `stop reason = one-shot breakpoint 2`,
+    ///   `stop reason = EXC_BAD_ACCESS`
+    /// - CDB: `Last event: 1a2c.3b4: Exit process 0:1a2c, code 1` (from `.lastevent` at the
+    ///   final breakpoint), `Breakpoint 1 hit`, `Access violation - code c0000005`
+    ///
+    /// The debuggee of the mock debugger exits normally unless the output says otherwise.
+    fn check_clean_exit(&self, output: &[&str]) -> Result<(), String> {
+        lazy_static! {
+            static ref BREAKPOINT: Regex = Regex::new(concat!(
+                r"(^|hit )(Temporary b|B)reakpoint \d+,|^Breakpoint \d+ hit",
+                r"|stop reason = (one-shot )?breakpoint"
+            ))
+            .unwrap();
+            static ref FAULT: Regex = Regex::new(
+                r"received signal \w+|stop reason = (signal \w+|EXC_\w+)|violation - code \w+"
+            )
+            .unwrap();
+            static ref EXIT_CODE: Regex =
+                Regex::new(r"(exited with (?:code|status =)|Exit process [\w:.]+, code) (-?\w+)")
+                    .unwrap();
+        }
 
-        let mut evaluation_context = HashMap::new();
-        evaluation_context.insert(format!("@{}", self.kind.name()), default_value.clone());
-        evaluation_context.insert("@debugger".into(), self.kind.name().into());
-        evaluation_context.insert("@version".into(), (&self.version).into());
-        evaluation_context.insert("@cargo_profile".into(), cargo_profile.into());
-        evaluation_context.insert("@phase".into(), phase.to_variable_value());
+        for line in output.iter().map(|line| line.trim()) {
+            if BREAKPOINT.is_match(line) {
+                return Err(format!(
+                    "Expected the debuggee to exit normally, but it hit a breakpoint: `{}`",
+                    line
+                ));
+            }
 
-        for define in &self.defines[..] {
-            if evaluation_context
-                .insert(define.to_string(), default_value.clone())
-                .is_some()
-            {
-                panic!("Duplicate define `{}`", define);
+            if FAULT.is_match(line) {
+                return Err(format!(
+                    "Expected the debuggee to exit normally, but it faulted: `{}`",
+                    line
+                ));
+            }
+
+            if line.contains("exited normally") {
+                return Ok(());
+            }
+
+            if let Some(captures) = EXIT_CODE.captures(line) {
+                return if captures[2].trim_start_matches('0').is_empty() {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "Expected the debuggee to exit normally, but it exited with an error: \
+                         `{}`",
+                        line
+                    ))
+                };
             }
         }
 
-        EvaluationContext {
-            values: evaluation_context,
+        if self.kind == DebuggerKind::Mock {
+            return Ok(());
         }
+
+        Err(
+            "Expected the debuggee to exit normally, but the debugger output doesn't show it \
+             exiting"
+                .to_string(),
+        )
     }
-}
 
-const CORRELATION_ID_BEGIN_MARKER: &str = "__correlation_id_begin__=";
-const CORRELATION_ID_END_MARKER: &str = "__correlation_id_end__=";
+    /// Extracts the value of a register from the output of [DebuggerCommand::ReadRegister]:
+    ///
+    /// - GDB: `rax            0x5                 5`
+    /// - LLDB: `     rax = 0x0000000000000005`
+    /// - CDB: `rax=0000000000000005`
+    fn register_value(&self, name: &str, output: &[&str]) -> Option<i128> {
+        lazy_static! {
+            static ref REGISTER: Regex =
+                Regex::new(r"(?i)\b([a-z_][a-z0-9_]*)\s*=?\s*(0x)?([0-9a-f`]+)\b").unwrap();
+        }
 
-fn extract_correlation_id(line: &str) -> CorrelationId {
-    debug_assert!(
-        line.contains(CORRELATION_ID_BEGIN_MARKER) || line.contains(CORRELATION_ID_BEGIN_MARKER)
-    );
-    let id_start = line.rfind('=').unwrap() + 1;
-    let id_str = &line[id_start..];
-    let id: u32 = id_str.parse().unwrap();
-    CorrelationId(id)
-}
+        // CDB prints several registers per line
+        output.iter().find_map(|line| {
+            let captures = REGISTER
+                .captures_iter(line)
+                .find(|captures| captures[1].eq_ignore_ascii_case(name))?;
+            let digits = captures[3].replace('`', "");
+            i128::from_str_radix(&digits, 16).ok()
+        })
+    }
 
-/// Generates the debugger script for the given combination of test definition and debugger.
-pub fn generate_debugger_script(
-    debugger: &Debugger,
-    test_definition: &TestDefinition,
-    cargo_profile: &Arc<str>,
-    phase: &PhaseConfig,
-    mk_crashdump_path: &mut dyn FnMut(/* tag */ &str) -> PathBuf,
-) -> String {
-    let mut debugger_script = String::new();
+    /// Extracts up to `len` bytes from the output of [DebuggerCommand::ReadMemory]:
+    ///
+    /// - GDB and LLDB: `0x7fffffffe3bc: 0x01 0x00 0x00 0x00` (GDB separates bytes by tabs)
+    /// - CDB: ``00000074`5f9cf6a0  01 00 00 00-02 00 00 00  ........``
+    fn memory_bytes(&self, output: &[&str], len: usize) -> Vec<u8> {
+        lazy_static! {
+            static ref GDB_ADDRESS: Regex = Regex::new(r"^\s*0x[0-9a-fA-F]+(\s+<.*>)?:").unwrap();
+            static ref CDB_ADDRESS: Regex = Regex::new(r"^\s*[0-9a-fA-F`]+\s").unwrap();
+        }
 
-    debugger.emit_script_prelude(&mut debugger_script);
-    debugger.emit_breakpoints(test_definition, phase, &mut debugger_script);
+        let address: &Regex = match self.kind {
+            DebuggerKind::Cdb => &CDB_ADDRESS,
+            DebuggerKind::Gdb | DebuggerKind::Lldb | DebuggerKind::Mock => &GDB_ADDRESS,
+        };
 
-    let mut script = test_definition.script.clone();
-    debugger.assign_correlation_ids(&mut script, cargo_profile, phase);
+        let mut bytes = vec![];
 
-    let evaluation_context = debugger.evaluation_context(cargo_profile, phase);
+        for line in output {
+            let address_end = match address.find(line) {
+                Some(m) => m.end(),
+                None => continue,
+            };
 
-    // Emit commands
-    let mut last_correlation_id = None;
+            // CDB appends an ASCII rendering of the bytes, so never read more than a
+            // full line of 16 bytes
+            let line_bytes = line[address_end..]
+                .split(|c: char| c.is_whitespace() || c == '-')
+                .filter(|token| !token.is_empty())
+                .map_while(|token| {
+                    let digits = token.strip_prefix("0x").unwrap_or(token);
 
-    script.walk_applicable_leaves(&evaluation_context, &mut |statement| {
-        // Emit new correlation id if necessary
-        match statement {
-            script::Statement::Exec(_, correlation_id, _)
-            | script::Statement::GenerateCrashDump(_, correlation_id, _) => {
-                if last_correlation_id != *correlation_id {
-                    debugger.maybe_emit_correlation_id_command(
-                        false,
-                        last_correlation_id,
-                        &mut debugger_script,
-                    );
-                    debugger.maybe_emit_correlation_id_command(
-                        true,
-                        *correlation_id,
-                        &mut debugger_script,
-                    );
-                    last_correlation_id = *correlation_id;
-                }
-            }
-            _ => {
-                // Other statements don't produce output, so no correlation id for them
-            }
-        };
+                    if digits.len() == 2 {
+                        u8::from_str_radix(digits, 16).ok()
+                    } else {
+                        None
+                    }
+                })
+                .take(16);
 
-        // Emit the actual command
-        match statement {
-            script::Statement::Exec(command, _, _) => {
-                writeln!(&mut debugger_script, "{}", command).unwrap();
-            }
-            script::Statement::GenerateCrashDump(tag, _, _) => {
-                if *phase != PhaseConfig::Live {
-                    warn!(
-                        "Encountered {} command in crashdump phase. Ignoring.",
-                        script::TOKEN_GENERATE_CRASHDUMP
-                    );
-                }
+            bytes.extend(line_bytes);
 
-                let crashdump_path = mk_crashdump_path(tag);
-                debugger.emit_crashdump_command(&crashdump_path, &mut debugger_script);
-            }
-            _ => {
-                // other statements don't have an effect here
+            if bytes.len() >= len {
+                bytes.truncate(len);
+                break;
             }
         }
 
-        true
-    });
+        bytes
+    }
 
-    debugger.maybe_emit_correlation_id_command(false, last_correlation_id, &mut debugger_script);
+    /// Extracts the contents of the first string literal in the printed value of a string for
+    /// [CommandCheck::Str], with its escapes resolved. Annotations around the literal, like
+    /// types, lengths or a `u8` prefix, are ignored:
+    ///
+    /// - GDB: `$1 = "hello"`
+    /// - LLDB: `(&str) $0 = "hello" { data_ptr = 0x... length = 5 }`
+    /// - CDB: `s : "hello" [Type: alloc::string::String]`
+    fn string_value(&self, output: &[&str]) -> Option<String> {
+        lazy_static! {
+            static ref STRING_LITERAL: Regex =
+                Regex::new(r#"[=:]\s*[a-zA-Z0-9]*"((?:[^"\\]|\\.)*)""#).unwrap();
+        }
 
-    debugger_script
-}
+        let literal = output
+            .iter()
+            .find_map(|line| STRING_LITERAL.captures(line))?;
 
-pub fn process_debugger_output(
-    debugger: &Debugger,
-    test_definition: &TestDefinition,
-    debugger_output: DebuggerOutput,
-    cargo_profile: &Arc<str>,
-    phase: &PhaseConfig,
-) -> TestResult {
-    let mut script = test_definition.script.clone();
-    debugger.assign_correlation_ids(&mut script, cargo_profile, phase);
+        let mut contents = String::new();
+        let mut chars = literal[1].chars();
 
-    let mut checks_by_correlation_id: BTreeMap<CorrelationId, Vec<Statement>> = BTreeMap::new();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                contents.push(c);
+                continue;
+            }
 
-    let evaluation_context = debugger.evaluation_context(cargo_profile, phase);
+            match chars.next() {
+                Some('n') => contents.push('\n'),
+                Some('t') => contents.push('\t'),
+                Some('r') => contents.push('\r'),
+                Some('0') => contents.push('\0'),
+                Some(c) => contents.push(c),
+                None => {}
+            }
+        }
 
-    script.walk_applicable_leaves(&evaluation_context, &mut |statement| {
-        match statement {
-            Statement::Check(_, cid, _) | Statement::CheckUnorderedBlock(_, cid, _) => {
-                checks_by_correlation_id
-                    .entry(cid.unwrap())
-                    .or_default()
-                    .push(statement.clone());
-            }
-            _ => {
-                // Only collecting check statements
+        Some(contents)
+    }
+
+    /// Checks the active variant and payload of an enum in the output of printing it for
+    /// [CommandCheck::Variant]. The variant is matched by the last segment of its path, and
+    /// the payload ignoring whitespace, either in parentheses or braces after the variant, or
+    /// as a field `0`/`__0` listed on a line of its own:
+    ///
+    /// - GDB: `$1 = core::option::Option<i32>::Some(42)`
+    /// - LLDB: `(core::option::Option<i32>) $0 = Some(42) {` followed by `  __0 = 42`
+    /// - CDB: `opt : Some [Type: enum2$<core::option::Option<i32> >]` followed by
+    ///   `    [+0x004] __0 : 42 [Type: int]`
+    fn check_variant(
+        &self,
+        expression: &str,
+        variant: &str,
+        payload: Option<&str>,
+        output: &[&str],
+    ) -> Result<(), String> {
+        lazy_static! {
+            static ref FIRST_FIELD: Regex =
+                Regex::new(r"^\s*(\[\+0x[0-9a-fA-F]+\]\s*)?(__)?0\s*[=:]\s*(.*?)(\s*\[Type: .*)?$")
+                    .unwrap();
+        }
+
+        let name = variant.rsplit("::").next().unwrap();
+        let separator = if self.kind == DebuggerKind::Cdb {
+            ':'
+        } else {
+            '='
+        };
+        let variant_regex = Regex::new(&format!(
+            r"{}\s*(.*::)?{}\b",
+            separator,
+            regex::escape(name)
+        ))
+        .unwrap();
+
+        let value = output
+            .iter()
+            .find(|line| !line.trim().is_empty())
+            .map_or("", |line| line.trim());
+
+        let expected = || match payload {
+            Some(payload) => format!("{}({})", variant, payload),
+            None => variant.to_string(),
+        };
+
+        if !variant_regex.is_match(value) {
+            return Err(format!(
+                "Expected `{}` to be `{}`, found `{}`",
+                expression,
+                expected(),
+                value
+            ));
+        }
+
+        if let Some(payload) = payload {
+            let compact = |text: &str| text.split_whitespace().collect::<String>();
+            let payload = compact(payload);
+            let output_compact = compact(&output.join(" "));
+
+            let matches = output_compact.contains(&format!("{}({})", name, payload))
+                || output_compact.contains(&format!("{}{{{}}}", name, payload))
+                || output.iter().any(|line| {
+                    FIRST_FIELD
+                        .captures(line)
+                        .is_some_and(|captures| compact(&captures[3]) == payload)
+                });
+
+            if !matches {
+                return Err(format!(
+                    "Expected `{}` to be `{}`, found `{}` with a different payload",
+                    expression,
+                    expected(),
+                    value
+                ));
             }
         }
 
-        true
-    });
+        Ok(())
+    }
 
-    let debugger_output_by_correlation_id = match debugger_output_by_correlation_id(
-        &debugger_output,
-        test_definition,
-        debugger,
-        cargo_profile,
-        phase,
-    ) {
-        Ok(x) => x,
-        Err(test_result) => {
-            return test_result;
+    /// Extracts the size of a type and its fields from the output of
+    /// [DebuggerCommand::DescribeType]. Only the outermost fields are returned, not the ones
+    /// of nested types GDB expands. LLDB shows neither sizes nor offsets, and CDB shows no
+    /// field sizes.
+    ///
+    /// - GDB: `/*      4      |       4 */    y: i32,` and `/* total size (bytes):    8 */`
+    /// - LLDB: `    int y;`
+    /// - CDB: `   +0x004 y                : Int4B` and `struct Point, 2 elements, 0x8 bytes`
+    fn type_layout(&self, output: &[&str]) -> (Option<u64>, Vec<FieldLayout>) {
+        lazy_static! {
+            static ref GDB_FIELD: Regex =
+                Regex::new(r"^/\*\s*(\d+)(:\s*\d+)?\s*\|\s*(\d+)\s*\*/(\s*)(.*)$").unwrap();
+            static ref GDB_SIZE: Regex = Regex::new(r"total size \(bytes\):\s*(\d+)").unwrap();
+            // `pub(crate) x: i32,` for Rust
+            static ref RUST_FIELD: Regex = Regex::new(r"^(pub(\(\w+\))? )?(\w+)\s*:").unwrap();
+            // `int x[4];` for C
+            static ref C_FIELD: Regex = Regex::new(r"(\w+)(\[[^\]]*\])*;$").unwrap();
+            static ref CDB_FIELD: Regex =
+                Regex::new(r"^(\s*)\+0x([0-9a-fA-F]+) (\w+)\s*:").unwrap();
+            static ref CDB_SIZE: Regex =
+                Regex::new(r"elements, (0x[0-9a-fA-F]+|\d+) bytes").unwrap();
         }
-    };
 
-    for (cid, checks) in checks_by_correlation_id {
-        let output = if let Some(output) = debugger_output_by_correlation_id.get(&cid) {
-            output
-        } else {
-            return TestResult::new(
-                test_definition,
-                debugger,
-                cargo_profile,
-                phase,
-                Status::Failed(format!("check {:?} failed", &checks[0]), debugger_output),
-            );
+        let field_name = |declaration: &str| {
+            RUST_FIELD
+                .captures(declaration)
+                .map(|captures| captures[3].to_string())
+                .or_else(|| {
+                    C_FIELD
+                        .captures(declaration)
+                        .map(|captures| captures[1].to_string())
+                })
         };
 
-        let mut check_index = 0;
-        for output_line in output {
-            match &checks[check_index] {
-                Statement::Check(check, _cid, _) => {
-                    debug_assert_eq!(_cid, &Some(cid));
-
-                    if check.check(output_line) {
-                        // we have a match
-                        check_index += 1;
+        // (indentation, field)
+        let mut fields: Vec<(usize, FieldLayout)> = vec![];
+        let mut size = None;
 
-                        if check_index == checks.len() {
-                            // We have found all the output we wanted
-                            break;
+        for line in output {
+            match self.kind {
+                DebuggerKind::Gdb | DebuggerKind::Mock => {
+                    if let Some(captures) = GDB_SIZE.captures(line) {
+                        // The outermost type's size comes last
+                        size = captures[1].parse().ok();
+                    } else if let Some(captures) = GDB_FIELD.captures(line) {
+                        if let Some(name) = field_name(captures[5].trim()) {
+                            fields.push((
+                                captures[4].len(),
+                                FieldLayout {
+                                    name: name.into(),
+                                    offset: captures[1].parse().ok(),
+                                    size: captures[3].parse().ok(),
+                                },
+                            ));
                         }
                     }
                 }
-                Statement::CheckUnorderedBlock(..) => {
-                    todo!()
+                DebuggerKind::Lldb => {
+                    if line.starts_with(char::is_whitespace) {
+                        if let Some(name) = field_name(line.trim()) {
+                            let indent = line.len() - line.trim_start().len();
+                            fields.push((
+                                indent,
+                                FieldLayout {
+                                    name: name.into(),
+                                    offset: None,
+                                    size: None,
+                                },
+                            ));
+                        }
+                    }
                 }
-                _ => {
-                    // only interested in check statements
+                DebuggerKind::Cdb => {
+                    if let Some(captures) = CDB_SIZE.captures(line) {
+                        size = size.or_else(|| parse_number(&captures[1]).map(|size| size as u64));
+                    } else if let Some(captures) = CDB_FIELD.captures(line) {
+                        fields.push((
+                            captures[1].len(),
+                            FieldLayout {
+                                name: captures[3].into(),
+                                offset: u64::from_str_radix(&captures[2], 16).ok(),
+                                size: None,
+                            },
+                        ));
+                    }
                 }
             }
         }
 
-        if check_index != checks.len() {
-            let (expected, line_number) = match &checks[check_index] {
-                Statement::Check(check, _, line_number) => (&check.source, line_number),
-                &Statement::CheckUnorderedBlock(..) => {
-                    todo!()
-                }
-                _ => {
-                    unreachable!()
-                }
-            };
-
-            let mut message = format!(
-                "Could not find '{}' in debugger output. Expected to find it \
-                 within the following lines:\n\n",
-                expected
-            );
-
-            for line in output.iter().filter(|x| {
-                !x.contains(CORRELATION_ID_BEGIN_MARKER) && !x.contains(CORRELATION_ID_END_MARKER)
-            }) {
-                writeln!(message, "> {}", line).unwrap();
-            }
-
-            writeln!(message).unwrap();
-            writeln!(
-                message,
-                "Check failed at: {}:{}",
-                prettify_path(&test_definition.absolute_source_path),
-                line_number.0,
-            )
-            .unwrap();
+        let outermost = fields.iter().map(|(indent, _)| *indent).min();
 
-            let status = Status::Failed(message, debugger_output);
+        (
+            size,
+            fields
+                .into_iter()
+                .filter(|(indent, _)| Some(*indent) == outermost)
+                .map(|(_, field)| field)
+                .collect(),
+        )
+    }
 
-            return TestResult::new(test_definition, debugger, cargo_profile, phase, status);
+    /// Extracts the names and values of the variables in the output of
+    /// [DebuggerCommand::ListLocals], with runs of whitespace in the values collapsed. Values
+    /// spanning several lines are cut off after the first.
+    ///
+    /// - GDB: `len = 3`
+    /// - LLDB: `(usize) len = 3`
+    /// - CDB: `            len = 0n3`
+    fn locals(&self, output: &[&str]) -> Vec<(String, String)> {
+        lazy_static! {
+            static ref GDB_LOCAL: Regex = Regex::new(r"^([\w$]+) = (.*)$").unwrap();
+            static ref LLDB_LOCAL: Regex = Regex::new(r"^\(.*?\) ([\w$]+) = (.*)$").unwrap();
+            static ref CDB_LOCAL: Regex = Regex::new(r"^\s*([\w$]+) = (.*)$").unwrap();
         }
-    }
 
-    TestResult::new(
-        test_definition,
-        debugger,
-        cargo_profile,
-        phase,
-        Status::Passed,
-    )
-}
+        let regex: &Regex = match self.kind {
+            DebuggerKind::Cdb => &CDB_LOCAL,
+            DebuggerKind::Gdb | DebuggerKind::Mock => &GDB_LOCAL,
+            DebuggerKind::Lldb => &LLDB_LOCAL,
+        };
 
-/// Splits debugger output into sections that correspond to a single correlation ID.
-fn debugger_output_by_correlation_id<'a>(
-    debugger_output: &'a DebuggerOutput,
-    test_definition: &TestDefinition,
-    debugger: &Debugger,
-    cargo_profile: &Arc<str>,
-    phase: &PhaseConfig,
-) -> Result<BTreeMap<CorrelationId, Vec<&'a str>>, TestResult> {
-    let mut result = BTreeMap::new();
+        let mut locals: Vec<(String, String)> = vec![];
 
-    let mut current_id = None;
-    let mut current_lines = vec![];
-    for line in debugger_output.stdout.lines() {
-        if line.starts_with(CORRELATION_ID_BEGIN_MARKER) {
-            if current_id.is_some() {
-                // malformed debugger output
-                return Err(TestResult::new(
-                    test_definition,
-                    debugger,
-                    cargo_profile,
-                    phase,
-                    Status::Errored(
-                        "Unexpected debugger output: correlation ID begin marker during active correlation section."
-                            .to_string(),
-                    ),
-                ));
-            }
-            assert!(current_lines.is_empty());
-            current_id = Some(extract_correlation_id(line));
-        } else if line.starts_with(CORRELATION_ID_END_MARKER) {
-            if let Some(current_id) = current_id {
-                result.insert(current_id, current_lines);
-                current_lines = vec![];
-            } else {
-                // malformed debugger output
-                return Err(TestResult::new(
-                    test_definition,
-                    debugger,
-                    cargo_profile,
-                    phase,
-                    Status::Errored(
-                        "Unexpected debugger output: unopened correlation ID end marker."
-                            .to_string(),
-                    ),
+        for captures in output.iter().filter_map(|line| regex.captures(line)) {
+            // Shadowed variables are listed once per scope, innermost first
+            if locals.iter().all(|(name, _)| *name != captures[1]) {
+                locals.push((
+                    captures[1].to_string(),
+                    captures[2].split_whitespace().collect::<Vec<_>>().join(" "),
                 ));
             }
-
-            current_id = None;
-        } else if current_id.is_some() {
-            current_lines.push(line);
         }
-    }
 
-    if let Some(current_id) = current_id {
-        // Always add the current ID even if it was not closed.
-        // This leads to better failure messages when the debugger scripts
-        // aborts prematurely.
-        result.insert(current_id, current_lines);
+        locals
     }
 
-    Ok(result)
-}
-
-/// Takes a set of debugger commandline commands and tries to create a [Debugger] object for each.
-pub fn init_debuggers(
-    commands: &[PathBuf],
-    preludes: &[OsString],
-    commandline_args: &[OsString],
-    env_vars: &[OsString],
-    defines: &[String],
-) -> anyhow::Result<Vec<Debugger>> {
-    let prelude_map = build_prelude_map(preludes)?;
-    let commandline_arg_map = build_commandline_arg_map(commandline_args)?;
-    let env_var_map = build_env_var_map(env_vars)?;
-
-    let defines: Arc<[Arc<str>]> = defines
-        .iter()
-        .map(|s| Arc::from(format!("@{}", s)))
-        .collect::<Vec<_>>()
-        .into();
-
-    info!("Setting up debuggers");
-    let mut debuggers = vec![];
-
-    for command in commands {
-        info!("Trying to set up debugger {}", command.display());
-        let (debugger_kind, version) = Debugger::infer_from_command(command)?;
+    /// Counts the threads in the output of [DebuggerCommand::ListThreads].
+    fn count_threads(&self, output: &[&str]) -> usize {
+        lazy_static! {
+            // `* 2    Thread 0x7ffff7d8a640 (LWP 1234) "worker" ...`
+            static ref GDB_THREAD: Regex = Regex::new(r"^\*?\s*\d+\s+(Thread|LWP|process) ").unwrap();
+            // `  thread #2: tid = 1234, 0x0000555555559a7b worker...`
+            static ref LLDB_THREAD: Regex = Regex::new(r"^\*?\s*thread #\d+:").unwrap();
+            // `.  1  Id: 1a2c.3b40 Suspend: 1 Teb: 00000074`5f9c5000 Unfrozen`
+            static ref CDB_THREAD: Regex = Regex::new(r"^[.#]?\s*\d+\s+Id: [0-9a-fA-F]+\.[0-9a-fA-F]+ ").unwrap();
+            // The mock debugger echoes the script, so it always reports a single thread
+            static ref MOCK_THREAD: Regex = Regex::new(r"^list_threads$").unwrap();
+        }
 
-        let debugger = Debugger::new(
-            debugger_kind,
-            version,
-            command.into(),
-            prelude_map.get(&debugger_kind).cloned().unwrap_or_default(),
-            commandline_arg_map
-                .get(&debugger_kind)
-                .cloned()
-                .unwrap_or_default(),
-            env_var_map.get(&debugger_kind).cloned().unwrap_or_default(),
-            defines.clone(),
-        );
+        let regex: &Regex = match self.kind {
+            DebuggerKind::Cdb => &CDB_THREAD,
+            DebuggerKind::Gdb => &GDB_THREAD,
+            DebuggerKind::Lldb => &LLDB_THREAD,
+            DebuggerKind::Mock => &MOCK_THREAD,
+        };
 
-        info!("Successfully set up debugger: {:?}", debugger);
-        debuggers.push(debugger);
+        output.iter().filter(|line| regex.is_match(line)).count()
     }
 
-    Ok(debuggers)
-}
-
-fn build_prelude_map(preludes: &[OsString]) -> anyhow::Result<HashMap<DebuggerKind, Vec<String>>> {
-    info!("Scanning debugger preludes");
-    partition_by_debugger_kind(preludes).context("while scanning debugger preludes")
-}
-
-fn build_commandline_arg_map(
-    preludes: &[OsString],
-) -> anyhow::Result<HashMap<DebuggerKind, Vec<String>>> {
-    info!("Scanning debugger commandline args");
-    partition_by_debugger_kind(preludes).context("while scanning debugger commandline args")
-}
-
-fn build_env_var_map(
-    env_vars: &[OsString],
-) -> anyhow::Result<HashMap<DebuggerKind, Vec<(String, String)>>> {
-    info!("Scanning debugger environment variables");
-    let by_debugger_kind =
-        partition_by_debugger_kind(env_vars).context("while scanning debugger env vars")?;
-
-    let mut env_vars = HashMap::with_capacity(by_debugger_kind.len());
-
-    for (debugger_kind, var_specs) in by_debugger_kind {
-        let mut split_var_specs = Vec::with_capacity(var_specs.len());
-
-        for var_spec in var_specs {
-            if let Some((var_name, var_value)) = var_spec.split_once('=') {
-                split_var_specs.push((var_name.trim().to_string(), var_value.trim().to_string()));
+    fn maybe_emit_correlation_id_command(
+        &self,
+        begin: bool,
+        correlation_id: Option<CorrelationId>,
+        output: &mut String,
+    ) {
+        if let Some(correlation_id) = correlation_id {
+            let marker = if begin {
+                CORRELATION_ID_BEGIN_MARKER
             } else {
-                bail!(
-                    "Could not parse env var spec: {}:{}",
-                    debugger_kind,
-                    var_spec
-                );
-            }
-        }
-
-        env_vars.insert(debugger_kind, split_var_specs);
-    }
-
-    Ok(env_vars)
-}
-
-fn partition_by_debugger_kind(
-    strings: &[OsString],
-) -> anyhow::Result<HashMap<DebuggerKind, Vec<String>>> {
-    let mut by_kind: HashMap<DebuggerKind, Vec<String>> = Default::default();
-    for s in strings {
-        let as_str = s.to_string_lossy();
-        if let Some((debugger, command)) = as_str.split_once(':') {
-            let debugger_kind = DebuggerKind::try_from(debugger.trim())?;
+                CORRELATION_ID_END_MARKER
+            };
 
-            by_kind
-                .entry(debugger_kind)
-                .or_default()
-                .push(command.trim().to_owned());
-        } else {
-            bail!("No debugger kind specified in {}", as_str);
+            self.emit_marker(marker, correlation_id.0, output);
         }
     }
-    Ok(by_kind)
-}
 
-fn extract_gdb_version(version_output: &str) -> Option<&str> {
-    lazy_static! {
-        // GNU gdb (Ubuntu 9.2-0ubuntu1~20.04) 9.2
-        // GNU gdb (GDB) 10.2
-        // GNU gdb (GDB) 8.2.1
-        static ref GDB_REGEX: Regex = Regex::new(r"GNU\s+gdb\s+\(.+\)\s+(.+)").unwrap();
+    /// Emits the command that prints `marker` followed by `id`
+    fn emit_marker(&self, marker: &str, id: u32, output: &mut String) {
+        match self.kind {
+            DebuggerKind::Cdb => {
+                writeln!(output, ".echo {}{}", marker, id).unwrap();
+            }
+            DebuggerKind::Gdb => {
+                writeln!(output, "python print('{}{}')", marker, id).unwrap();
+            }
+            DebuggerKind::Mock => {
+                writeln!(output, "{}{}", marker, id).unwrap();
+            }
+            DebuggerKind::Lldb => {
+                writeln!(output, "script print('{}{}')", marker, id).unwrap();
+            }
+        }
     }
 
-    GDB_REGEX
-        .captures(version_output)
-        .map(|captures| captures.get(1).unwrap().as_str())
-}
+    fn assign_correlation_ids(
+        &self,
+        script: &mut Script,
+        test_config: &TestConfig,
+        cargo_profile: &Arc<str>,
+        phase: &PhaseConfig,
+    ) {
+        let mut correlation_ids_checked = HashSet::new();
+        let mut next_correlation_id = 0;
+        let mut last_correlation_id_emitted = None;
+        let mut sequence_point = false;
 
-fn extract_cdb_version(version_output: &str) -> Option<&str> {
-    lazy_static! {
-        // example: cdb version 10.0.21349.1004
-        static ref CDB_REGEX: Regex = Regex::new(r"cdb\s+version\s+([\d\.]+)").unwrap();
-    }
+        let evaluation_context = self.evaluation_context(test_config, cargo_profile, phase);
 
-    CDB_REGEX
-        .captures(version_output)
-        .map(|captures| captures.get(1).unwrap().as_str())
-}
+        // Assign correlation ids
+        script.walk_applicable_leaves_mut(&evaluation_context, &mut |statement| {
+            match statement {
+                Statement::Command(DebuggerCommand::Print(_), correlation_id_slot, _) => {
+                    debug_assert_eq!(correlation_id_slot, &None);
 
-fn extract_lldb_version(version_output: &str) -> Option<&str> {
-    lazy_static! {
-        // example: lldb version 12.0.0
-        static ref LLDB_REGEX: Regex = Regex::new(r"lldb\s+version\s+([\d\.]+)").unwrap();
-    }
+                    // Always gets its own block, and so does whatever comes after it
+                    let correlation_id = CorrelationId(next_correlation_id);
+                    next_correlation_id += 1;
+                    *correlation_id_slot = Some(correlation_id);
+                    last_correlation_id_emitted = Some(correlation_id);
+                    sequence_point = true;
+                }
+                Statement::Exec(_, correlation_id_slot, _)
+                | Statement::Command(_, correlation_id_slot, _)
+                | Statement::SetBreakpoint(_, correlation_id_slot, _) => {
+                    debug_assert_eq!(correlation_id_slot, &None);
 
-    LLDB_REGEX
-        .captures(version_output)
-        .map(|captures| captures.get(1).unwrap().as_str())
-}
+                    let last_id_has_been_checked = last_correlation_id_emitted
+                        .is_none_or(|id| correlation_ids_checked.contains(&id));
 
-#[cfg(test)]
-mod tests {
-    use std::{
-        path::{Path, PathBuf},
-        sync::Arc,
-    };
+                    let correlation_id = if last_id_has_been_checked || sequence_point {
+                        // Allocate a new one
+                        let correlation_id = CorrelationId(next_correlation_id);
+                        next_correlation_id += 1;
+                        correlation_id
+                    } else {
+                        // Re-use the existing one
+                        last_correlation_id_emitted.unwrap()
+                    };
 
-    use crate::{
-        cargo_test_directory::TestDefinition,
-        debugger::Debugger,
-        script::{parse_script, CorrelationId, LineNumber, PhaseConfig, Statement},
+                    *correlation_id_slot = Some(correlation_id);
+                    last_correlation_id_emitted = Some(correlation_id);
+                    sequence_point = false;
+                }
+                Statement::Sequence(_) => {
+                    // Explicitly requested by the test author
+                    sequence_point = true;
+                }
+                Statement::Check(_, correlation_id_slot, _)
+                | Statement::CheckUnorderedBlock(_, correlation_id_slot, _) => {
+                    if let Some(last_correlation_id_emitted) = last_correlation_id_emitted {
+                        debug_assert_eq!(last_correlation_id_emitted.0, next_correlation_id - 1);
+                        *correlation_id_slot = Some(last_correlation_id_emitted);
+                        correlation_ids_checked.insert(last_correlation_id_emitted);
+                    } else {
+                        warn!("{:?} has no output to check", statement);
+                    }
+                }
+                // Add any statements here that unconditionally get wrapped in their own correlation ID
+                Statement::GenerateCrashDump(_, correlation_id_slot, _)
+                | Statement::CommandCheck(_, correlation_id_slot, _) => {
+                    debug_assert_eq!(correlation_id_slot, &None);
+                    let correlation_id = CorrelationId(next_correlation_id);
+                    next_correlation_id += 1;
+                    *correlation_id_slot = Some(correlation_id);
+                    assert!(correlation_ids_checked.insert(correlation_id));
+                    last_correlation_id_emitted = Some(correlation_id);
+                }
+                Statement::IfBlock(..)
+                | Statement::Group(..)
+                | Statement::EachHit(..)
+                | Statement::IgnoreTest(..)
+                | Statement::Require(..)
+                | Statement::MaxMemory(..)
+                | Statement::DependsOn(..)
+                | Statement::Dependency(..)
+                | Statement::Args(..)
+                | Statement::RustcFlags(..)
+                | Statement::Targets(..)
+                | Statement::DebuggeeTarget(..)
+                | Statement::CBaseline(..)
+                | Statement::StaticCheck(..)
+                | Statement::ObjectCheck(..)
+                | Statement::ProgramOutputCheck(..)
+                | Statement::Phase(..) => {
+                    // Nothing to do
+                }
+            }
+            true
+        });
+    }
+
+    /// Write a debugger (and version) specific prelude to the debugger script
+    /// Will be invoked before anything but `before-generated` preludes is written to the script.
+    fn emit_script_prelude(&self, test_config: &TestConfig, script: &mut String) {
+        match self.kind {
+            DebuggerKind::Cdb => {
+                writeln!(script, ".lines -e").unwrap();
+            }
+            DebuggerKind::Gdb => {}
+            DebuggerKind::Lldb => {
+                // We want to actually stop at breakpoints
+                writeln!(script, "script lldb.debugger.SetAsync(False)").unwrap();
+                // Don't wait for user input
+                writeln!(script, "settings set auto-confirm true").unwrap();
+            }
+            DebuggerKind::Mock => {
+                // no prelude
+            }
+        };
+
+        if self.default_settings {
+            for command in self.kind.default_settings() {
+                writeln!(script, "{}", command).unwrap();
+            }
+        }
+
+        if self.forced_locale {
+            for command in self.kind.forced_locale_commands() {
+                writeln!(script, "{}", command).unwrap();
+            }
+        }
+
+        for root_path in &self.remapped_workspaces {
+            if let Some(command) = reproducible::source_map_command(self.kind, root_path) {
+                writeln!(script, "{}", command).unwrap();
+            }
+        }
+
+        // Non-stop mode can only be changed before the debuggee runs
+        if self.gdb_non_stop(test_config) {
+            writeln!(script, "set non-stop on").unwrap();
+        }
+
+        if let Some(scheduler_locking) = self.gdb_scheduler_locking(test_config) {
+            writeln!(script, "set scheduler-locking {}", scheduler_locking.name()).unwrap();
+        }
+    }
+
+    /// Emit commands for setting breakpoints that have been specified via #break directives
+    fn emit_breakpoints(
+        &self,
+        test_definition: &TestDefinition,
+        phase: &PhaseConfig,
+        script: &mut String,
+    ) {
+        if !phase.executes_debuggee() {
+            return;
+        }
+
+        for bp in &test_definition.breakpoints {
+            // Named breakpoints are set via `#break name` statements in the script
+            if bp.name.is_none() {
+                self.emit_breakpoint(test_definition, bp, script);
+            }
+        }
+    }
+
+    fn emit_breakpoint(
+        &self,
+        test_definition: &TestDefinition,
+        bp: &BreakPoint,
+        script: &mut String,
+    ) {
+        let file_name = test_definition.name.rsplit_once('/').unwrap().1;
+        let line = bp.line_index + 1;
+
+        match self.kind {
+            DebuggerKind::Cdb | DebuggerKind::Mock => {
+                let one_shot = if bp.temporary { "/1 " } else { "" };
+                write!(script, "bp {}`{}:{}`", one_shot, file_name, line).unwrap();
+
+                if let Some(hit_count) = bp.hit_count {
+                    // CDB breaks on the given pass
+                    write!(script, " {}", hit_count).unwrap();
+                }
+
+                writeln!(script).unwrap();
+            }
+            DebuggerKind::Gdb => {
+                let command = if bp.temporary { "tbreak" } else { "break" };
+                writeln!(script, "{} '{}:{}'", command, file_name, line).unwrap();
+
+                if let Some(hit_count) = bp.hit_count {
+                    writeln!(script, "ignore $bpnum {}", hit_count - 1).unwrap();
+                }
+            }
+            DebuggerKind::Lldb => {
+                write!(
+                    script,
+                    "breakpoint set --file '{}' --line {}",
+                    file_name, line
+                )
+                .unwrap();
+
+                if let Some(hit_count) = bp.hit_count {
+                    write!(script, " --ignore-count {}", hit_count - 1).unwrap();
+                }
+
+                if bp.temporary {
+                    write!(script, " --one-shot true").unwrap();
+                }
+
+                writeln!(script).unwrap();
+            }
+        }
+    }
+
+    /// The variables `#if` directives are evaluated with, for a test with `test_config`
+    pub fn evaluation_context(
+        &self,
+        test_config: &TestConfig,
+        cargo_profile: &Arc<str>,
+        phase: &PhaseConfig,
+    ) -> EvaluationContext {
+        let default_value: Value = "true".into();
+
+        let mut evaluation_context = HashMap::new();
+        evaluation_context.insert(format!("@{}", self.kind.name()), default_value.clone());
+        evaluation_context.insert("@debugger".into(), self.kind.name().into());
+        evaluation_context.insert("@version".into(), (&self.version).into());
+        evaluation_context.insert("@cargo_profile".into(), cargo_profile.into());
+        evaluation_context.insert(
+            "@debuginfo".into(),
+            build_variants::debuginfo(cargo_profile).into(),
+        );
+        evaluation_context.insert(
+            "@opt_level".into(),
+            test_config.opt_level(cargo_profile).into(),
+        );
+        evaluation_context.insert("@phase".into(), phase.to_variable_value());
+
+        if self.wine.is_some() {
+            evaluation_context.insert(wine::VARIABLE.into(), default_value.clone());
+        }
+
+        if self.gdb_non_stop(test_config) {
+            evaluation_context.insert("@gdb_non_stop".into(), default_value.clone());
+        }
+
+        // Always defined, so that comparisons with it don't fail for other debuggers
+        let scheduler_locking = match self.gdb_scheduler_locking(test_config) {
+            Some(scheduler_locking) => scheduler_locking.name(),
+            // GDB's default
+            None if self.kind == DebuggerKind::Gdb => SchedulerLocking::Replay.name(),
+            None => "none",
+        };
+        evaluation_context.insert("@gdb_scheduler_locking".into(), scheduler_locking.into());
+
+        let ci = ci::ci();
+
+        if ci.is_some() {
+            evaluation_context.insert(ci::VARIABLE.into(), default_value.clone());
+        }
+
+        // Always defined, so that comparisons with it don't fail off CI
+        evaluation_context.insert(
+            ci::PROVIDER_VARIABLE.into(),
+            ci.and_then(|ci| ci.provider).unwrap_or("none").into(),
+        );
+
+        for define in &self.defines[..] {
+            if evaluation_context
+                .insert(define.to_string(), default_value.clone())
+                .is_some()
+            {
+                panic!("Duplicate define `{}`", define);
+            }
+        }
+
+        EvaluationContext {
+            values: evaluation_context,
+        }
+    }
+}
+
+const CORRELATION_ID_BEGIN_MARKER: &str = "__correlation_id_begin__=";
+const CORRELATION_ID_END_MARKER: &str = "__correlation_id_end__=";
+
+/// Fence each command with `--benchmark`, followed by the index of the command in the
+/// script. Unlike correlation markers, they don't affect which output the checks match.
+const COMMAND_BEGIN_MARKER: &str = "__command_begin__=";
+const COMMAND_END_MARKER: &str = "__command_end__=";
+
+fn extract_correlation_id(line: &str) -> CorrelationId {
+    debug_assert!(
+        line.contains(CORRELATION_ID_BEGIN_MARKER) || line.contains(CORRELATION_ID_END_MARKER)
+    );
+    let id_start = line.rfind('=').unwrap() + 1;
+    let id_str = &line[id_start..];
+    let id: u32 = id_str.parse().unwrap();
+    CorrelationId(id)
+}
+
+/// Generates the debugger script for the given combination of test definition and debugger.
+pub fn generate_debugger_script(
+    debugger: &Debugger,
+    test_definition: &TestDefinition,
+    cargo_profile: &Arc<str>,
+    phase: &PhaseConfig,
+    mk_crashdump_path: &mut dyn FnMut(/* tag */ &str) -> PathBuf,
+) -> String {
+    let mut debugger_script = String::new();
+
+    // The preludes given via --debugger-prelude, followed by the ones from the dbt-test.toml
+    // of the test's package and suites
+    let preludes: Vec<(PreludePlacement, &str)> = debugger
+        .prelude
+        .iter()
+        .map(|(placement, command)| (*placement, &command[..]))
+        .chain(
+            test_definition
+                .config
+                .prelude_for(debugger.kind, &debugger.version),
+        )
+        .collect();
+
+    let emit_preludes = |placement: PreludePlacement, debugger_script: &mut String| {
+        for (_, command) in preludes.iter().filter(|(p, _)| *p == placement) {
+            writeln!(debugger_script, "{}", command).unwrap();
+        }
     };
 
-    fn from_lines(lines: &[&str]) -> String {
-        let mut output = String::new();
+    emit_preludes(PreludePlacement::BeforeGenerated, &mut debugger_script);
+    debugger.emit_script_prelude(&test_definition.config, &mut debugger_script);
+    emit_preludes(PreludePlacement::AfterGenerated, &mut debugger_script);
+
+    debugger.emit_breakpoints(test_definition, phase, &mut debugger_script);
+    emit_preludes(PreludePlacement::AfterBreakpoints, &mut debugger_script);
+
+    let mut script = test_definition.script.clone();
+    debugger.assign_correlation_ids(&mut script, &test_definition.config, cargo_profile, phase);
+
+    let evaluation_context =
+        debugger.evaluation_context(&test_definition.config, cargo_profile, phase);
+
+    // Without a command that starts the debuggee (e.g. for crashdumps), the `before-run`
+    // preludes go before the commands of the script
+    let mut starts_debuggee = false;
+    script.walk_applicable_leaves(&evaluation_context, &mut |statement| {
+        if let script::Statement::Exec(command, _, _) = statement {
+            starts_debuggee |= debugger.kind.starts_debuggee(command);
+        }
+
+        !starts_debuggee
+    });
+
+    let mut before_run_pending = true;
+
+    if !starts_debuggee {
+        emit_preludes(PreludePlacement::BeforeRun, &mut debugger_script);
+        before_run_pending = false;
+    }
+
+    // Emit commands
+    let mut last_correlation_id = None;
+    let mut command_index = 0;
+
+    script.walk_applicable_leaves(&evaluation_context, &mut |statement| {
+        let is_run_command = matches!(statement, script::Statement::Exec(command, _, _)
+            if debugger.kind.starts_debuggee(command));
+        let mut emit_before_run = before_run_pending && is_run_command;
+
+        // Emit new correlation id if necessary
+        match statement {
+            script::Statement::Exec(_, correlation_id, _)
+            | script::Statement::Command(_, correlation_id, _)
+            | script::Statement::SetBreakpoint(_, correlation_id, _)
+            | script::Statement::CommandCheck(_, correlation_id, _)
+            | script::Statement::GenerateCrashDump(_, correlation_id, _)
+                if last_correlation_id != *correlation_id =>
+            {
+                debugger.maybe_emit_correlation_id_command(
+                    false,
+                    last_correlation_id,
+                    &mut debugger_script,
+                );
+
+                // Between the blocks, so that the output of the preludes isn't checked
+                if emit_before_run {
+                    emit_preludes(PreludePlacement::BeforeRun, &mut debugger_script);
+                    emit_before_run = false;
+                }
+
+                debugger.maybe_emit_correlation_id_command(
+                    true,
+                    *correlation_id,
+                    &mut debugger_script,
+                );
+                last_correlation_id = *correlation_id;
+            }
+            _ => {
+                // Other statements don't produce output, so no correlation id for them
+            }
+        };
+
+        // The command that starts the debuggee is in the same block as the ones before it
+        if emit_before_run {
+            emit_preludes(PreludePlacement::BeforeRun, &mut debugger_script);
+        }
+
+        before_run_pending &= !is_run_command;
+
+        let fenced = debugger.command_fences
+            && matches!(
+                statement,
+                script::Statement::Exec(..)
+                    | script::Statement::Command(..)
+                    | script::Statement::CommandCheck(..)
+            );
+
+        if fenced {
+            debugger.emit_marker(COMMAND_BEGIN_MARKER, command_index, &mut debugger_script);
+        }
+
+        // Emit the actual command
+        match statement {
+            script::Statement::Exec(command, _, _) => {
+                writeln!(&mut debugger_script, "{}", command).unwrap();
+            }
+            script::Statement::Command(command, _, _) => {
+                debugger.emit_command(command, &mut debugger_script);
+            }
+            script::Statement::CommandCheck(check, _, _) => {
+                debugger.emit_command(&check.command(), &mut debugger_script);
+            }
+            script::Statement::SetBreakpoint(name, _, _) => {
+                if !phase.executes_debuggee() {
+                    warn!("Encountered #break command in {} phase. Ignoring.", phase);
+                } else if let Some(bp) = test_definition.breakpoint(name) {
+                    debugger.emit_breakpoint(test_definition, bp, &mut debugger_script);
+                }
+            }
+            script::Statement::GenerateCrashDump(tag, _, _) => {
+                if *phase != PhaseConfig::Live {
+                    warn!(
+                        "Encountered {} command in {} phase. Ignoring.",
+                        script::TOKEN_GENERATE_CRASHDUMP,
+                        phase
+                    );
+                }
+
+                let crashdump_path = mk_crashdump_path(tag);
+                debugger.emit_crashdump_command(&crashdump_path, &mut debugger_script);
+            }
+            _ => {
+                // other statements don't have an effect here
+            }
+        }
+
+        if fenced {
+            debugger.emit_marker(COMMAND_END_MARKER, command_index, &mut debugger_script);
+            command_index += 1;
+        }
+
+        true
+    });
+
+    debugger.maybe_emit_correlation_id_command(false, last_correlation_id, &mut debugger_script);
+
+    debugger_script
+}
+
+/// How long the debugger took for a single command, measured between the arrival of the
+/// fences placed around it in the debugger's output (see [Debugger::enable_command_fences]).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandTiming {
+    /// The command as sent to the debugger
+    pub command: String,
+    pub line_number: LineNumber,
+    pub duration: Duration,
+}
+
+/// Returns the timing of each command in the debugger's output whose begin and end fences
+/// were both found. Commands run several times (e.g. by `#each-hit`) are timed each time.
+pub fn command_timings(
+    debugger: &Debugger,
+    test_definition: &TestDefinition,
+    cargo_profile: &Arc<str>,
+    phase: &PhaseConfig,
+    stdout: &str,
+    stdout_timestamps: &OutputTimestamps,
+) -> Vec<CommandTiming> {
+    let commands = fenced_commands(debugger, test_definition, cargo_profile, phase);
+    let mut begin_times = HashMap::new();
+    let mut timings = vec![];
+
+    for (is_begin, index, time) in marker_arrivals(stdout, stdout_timestamps, parse_fence) {
+        if is_begin {
+            begin_times.insert(index, time);
+        } else if let (Some(begin_time), Some((command, line_number))) =
+            (begin_times.remove(&index), commands.get(index as usize))
+        {
+            timings.push(CommandTiming {
+                command: command.clone(),
+                line_number: *line_number,
+                duration: time.saturating_sub(begin_time),
+            });
+        }
+    }
+
+    timings
+}
+
+/// The commands generate_debugger_script() fences, in the order of their indices, as sent
+/// to the debugger and with their line numbers
+fn fenced_commands(
+    debugger: &Debugger,
+    test_definition: &TestDefinition,
+    cargo_profile: &Arc<str>,
+    phase: &PhaseConfig,
+) -> Vec<(String, LineNumber)> {
+    let mut commands = vec![];
+
+    test_definition.script.walk_applicable_leaves(
+        &debugger.evaluation_context(&test_definition.config, cargo_profile, phase),
+        &mut |statement| {
+            let mut emitted = String::new();
+
+            let line_number = match statement {
+                Statement::Exec(command, _, line_number) => {
+                    emitted.push_str(command);
+                    line_number
+                }
+                Statement::Command(command, _, line_number) => {
+                    debugger.emit_command(command, &mut emitted);
+                    line_number
+                }
+                Statement::CommandCheck(check, _, line_number) => {
+                    debugger.emit_command(&check.command(), &mut emitted);
+                    line_number
+                }
+                _ => return true,
+            };
+
+            commands.push((emitted.trim().to_string(), *line_number));
+
+            true
+        },
+    );
+
+    commands
+}
+
+/// The first command of each block of commands sharing a correlation ID, as sent to the
+/// debugger, and its line number
+fn first_commands(
+    debugger: &Debugger,
+    test_definition: &TestDefinition,
+    cargo_profile: &Arc<str>,
+    phase: &PhaseConfig,
+) -> BTreeMap<CorrelationId, (String, LineNumber)> {
+    let mut script = test_definition.script.clone();
+    debugger.assign_correlation_ids(&mut script, &test_definition.config, cargo_profile, phase);
+
+    let mut first_commands: BTreeMap<CorrelationId, (String, LineNumber)> = BTreeMap::new();
+
+    script.walk_applicable_leaves(
+        &debugger.evaluation_context(&test_definition.config, cargo_profile, phase),
+        &mut |statement| {
+            let (command, cid, line_number) = match statement {
+                Statement::Exec(command, cid, line_number) => (command.clone(), cid, line_number),
+                Statement::Command(command, cid, line_number) => {
+                    let mut emitted = String::new();
+                    debugger.emit_command(command, &mut emitted);
+                    (emitted, cid, line_number)
+                }
+                Statement::CommandCheck(check, cid, line_number) => {
+                    let mut emitted = String::new();
+                    debugger.emit_command(&check.command(), &mut emitted);
+                    (emitted, cid, line_number)
+                }
+                _ => return true,
+            };
+
+            if let Some(cid) = cid {
+                first_commands
+                    .entry(*cid)
+                    .or_insert_with(|| (command.trim().to_string(), *line_number));
+            }
+
+            true
+        },
+    );
+
+    first_commands
+}
+
+/// When the end of the text up to `len` arrived, going by `timestamps`
+fn arrival_time(timestamps: &OutputTimestamps, len: usize) -> Option<Duration> {
+    timestamps
+        .iter()
+        .find(|&&(timestamp_len, _)| timestamp_len >= len)
+        .map(|&(_, time)| time)
+}
+
+/// The markers in `stdout` with known arrival times that `parse` recognizes (see
+/// [parse_marker] and [parse_fence]), as whether it is a begin marker, its ID and the time
+/// it arrived
+fn marker_arrivals<T>(
+    stdout: &str,
+    stdout_timestamps: &OutputTimestamps,
+    parse: fn(&str) -> Option<(bool, T)>,
+) -> Vec<(bool, T, Duration)> {
+    let mut markers = vec![];
+    let mut line_end = 0;
+
+    for line in stdout.split_inclusive('\n') {
+        line_end += line.len();
+
+        let (is_begin, id) = match parse(line) {
+            Some(marker) => marker,
+            None => continue,
+        };
+
+        if let Some(time) = arrival_time(stdout_timestamps, line_end) {
+            markers.push((is_begin, id, time));
+        }
+    }
+
+    markers
+}
+
+/// Whether `line` is a begin or end marker, and its correlation ID. Malformed markers are
+/// reported by process_debugger_output(), and left out here.
+fn parse_marker(line: &str) -> Option<(bool, CorrelationId)> {
+    let is_begin = line.starts_with(CORRELATION_ID_BEGIN_MARKER);
+
+    if !is_begin && !line.starts_with(CORRELATION_ID_END_MARKER) {
+        return None;
+    }
+
+    match line.trim_end().rsplit('=').next().map(str::parse) {
+        Some(Ok(id)) => Some((is_begin, CorrelationId(id))),
+        _ => None,
+    }
+}
+
+/// Whether `line` is a begin or end fence of a command, and the index of the command
+fn parse_fence(line: &str) -> Option<(bool, u32)> {
+    let is_begin = line.starts_with(COMMAND_BEGIN_MARKER);
+
+    if !is_begin && !line.starts_with(COMMAND_END_MARKER) {
+        return None;
+    }
+
+    match line.trim_end().rsplit('=').next().map(str::parse) {
+        Some(Ok(index)) => Some((is_begin, index)),
+        _ => None,
+    }
+}
+
+/// The block of commands the debugger was running when it stopped, i.e. the last one whose
+/// begin marker is in `stdout` but not its end marker
+fn unfinished_block(stdout: &str) -> Option<CorrelationId> {
+    let mut running = None;
+
+    for line in stdout.lines() {
+        match parse_marker(line) {
+            Some((true, cid)) => running = Some(cid),
+            Some((false, cid)) if running == Some(cid) => running = None,
+            _ => {}
+        }
+    }
+
+    running
+}
+
+/// Assigns the lines of the debugger's stderr to the correlation block that was active when
+/// they arrived, so that failures can show errors (e.g. Python tracebacks) next to the
+/// output of the commands that caused them. As stdout and stderr are read separately, this
+/// goes by the arrival times of both. Lines that arrived outside of any block, or whose
+/// arrival time is unknown (e.g. for replayed sessions), are left out.
+pub fn stderr_by_correlation_id(
+    debugger_output: &DebuggerOutput,
+    measurements: &RunMeasurements,
+) -> BTreeMap<CorrelationId, Vec<String>> {
+    // The time each block began and, if it did, ended
+    let mut blocks: Vec<(CorrelationId, Duration, Option<Duration>)> = vec![];
+
+    for (is_begin, cid, time) in marker_arrivals(
+        &debugger_output.stdout,
+        &measurements.stdout_timestamps,
+        parse_marker,
+    ) {
+        if is_begin {
+            // Repeated markers are tolerated, see debugger_output_by_correlation_id()
+            if !blocks.iter().any(|&(other, ..)| other == cid) {
+                blocks.push((cid, time, None));
+            }
+        } else if let Some(block) = blocks
+            .iter_mut()
+            .find(|(other, _, end)| *other == cid && end.is_none())
+        {
+            block.2 = Some(time);
+        }
+    }
+
+    let mut result: BTreeMap<CorrelationId, Vec<String>> = BTreeMap::new();
+    let mut line_end = 0;
+
+    for line in debugger_output.stderr.split_inclusive('\n') {
+        line_end += line.len();
+
+        let time = match arrival_time(&measurements.stderr_timestamps, line_end) {
+            Some(time) => time,
+            None => continue,
+        };
+
+        let block = blocks
+            .iter()
+            .find(|&&(_, begin, end)| begin <= time && end.is_none_or(|end| time <= end));
+
+        if let Some(&(cid, ..)) = block {
+            result
+                .entry(cid)
+                .or_default()
+                .push(line.trim_end().to_string());
+        }
+    }
+
+    result
+}
+
+/// The output of a `#print` statement
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PrintedValue {
+    pub expression: Arc<str>,
+    pub line_number: LineNumber,
+    pub output: String,
+}
+
+/// A check that matched a line of the debugger's stdout
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CheckMatch {
+    /// The line of the check in the test's source file
+    pub check_line: LineNumber,
+    /// The number of the matching line in the debugger's stdout, counting from 1
+    pub output_line: usize,
+    pub output: String,
+}
+
+/// Returns the output of each `#print` statement found in `debugger_output`
+pub fn printed_values(
+    debugger: &Debugger,
+    test_definition: &TestDefinition,
+    cargo_profile: &Arc<str>,
+    phase: &PhaseConfig,
+    debugger_output: &DebuggerOutput,
+) -> Vec<PrintedValue> {
+    // Malformed output is reported by process_debugger_output()
+    let output_by_correlation_id = match debugger_output_by_correlation_id(debugger_output) {
+        Ok(output_by_correlation_id) => output_by_correlation_id,
+        Err(_) => return vec![],
+    };
+
+    let mut script = test_definition.script.clone();
+    debugger.assign_correlation_ids(&mut script, &test_definition.config, cargo_profile, phase);
+
+    let mut printed_values = vec![];
+
+    script.walk_applicable_leaves(
+        &debugger.evaluation_context(&test_definition.config, cargo_profile, phase),
+        &mut |statement| {
+            if let Statement::Command(DebuggerCommand::Print(expression), Some(cid), line_number) =
+                statement
+            {
+                if let Some(lines) = output_by_correlation_id.get(cid) {
+                    printed_values.push(PrintedValue {
+                        expression: expression.clone(),
+                        line_number: *line_number,
+                        output: lines.join("\n"),
+                    });
+                }
+            }
+
+            true
+        },
+    );
+
+    printed_values
+}
+
+/// Returns the output of each command in `debugger_output`, keyed by the command's line
+/// number. `test_definition` has to be the one the debugger script was generated from, with
+/// [Script::with_sequence_points] applied to its script. Commands that ran more than once,
+/// e.g. within `#each-hit`, are mapped to the output of their first run.
+pub fn output_by_command<'a>(
+    debugger: &Debugger,
+    test_definition: &TestDefinition,
+    cargo_profile: &Arc<str>,
+    phase: &PhaseConfig,
+    debugger_output: &'a DebuggerOutput,
+) -> Result<BTreeMap<u32, Vec<&'a str>>, String> {
+    let mut script = test_definition.script.clone();
+    debugger.assign_correlation_ids(&mut script, &test_definition.config, cargo_profile, phase);
+
+    let mut line_numbers = BTreeMap::new();
+
+    script.walk_applicable_leaves(
+        &debugger.evaluation_context(&test_definition.config, cargo_profile, phase),
+        &mut |statement| {
+            if let Statement::Exec(_, Some(cid), line_number) = statement {
+                line_numbers.insert(*cid, line_number.0);
+            }
+
+            true
+        },
+    );
+
+    let mut result = BTreeMap::new();
+
+    for (cid, lines) in debugger_output_by_correlation_id(debugger_output)? {
+        if let Some(&line_number) = line_numbers.get(&cid) {
+            result.entry(line_number).or_insert(lines);
+        }
+    }
+
+    Ok(result)
+}
+
+pub fn process_debugger_output(
+    debugger: &Debugger,
+    test_definition: &TestDefinition,
+    debugger_output: DebuggerOutput,
+    stderr_by_correlation_id: &BTreeMap<CorrelationId, Vec<String>>,
+    cargo_profile: &Arc<str>,
+    phase: &PhaseConfig,
+) -> TestResult {
+    let mut script = test_definition.script.clone();
+    debugger.assign_correlation_ids(&mut script, &test_definition.config, cargo_profile, phase);
+
+    let mut checks_by_correlation_id: BTreeMap<CorrelationId, Vec<Statement>> = BTreeMap::new();
+
+    let evaluation_context =
+        debugger.evaluation_context(&test_definition.config, cargo_profile, phase);
+    let check_filter = CheckFilter::new(debugger, &script);
+
+    script.walk_applicable_leaves(&evaluation_context, &mut |statement| {
+        match statement {
+            Statement::Check(_, cid, _)
+            | Statement::CheckUnorderedBlock(_, cid, _)
+            | Statement::CommandCheck(_, cid, _)
+                if check_filter.selects(statement) =>
+            {
+                checks_by_correlation_id
+                    .entry(cid.unwrap())
+                    .or_default()
+                    .push(statement.clone());
+            }
+            _ => {
+                // Only collecting check statements
+            }
+        }
+
+        true
+    });
+
+    let program_output_checks: Vec<_> = script
+        .program_output_checks(&evaluation_context)
+        .into_iter()
+        .filter(|(_, line_number)| check_filter.selects_line(*line_number))
+        .collect();
+
+    // Tests are usually skipped before running the debugger already, but not on replay
+    if let Some(reason) = debugger.skipped_by_check_filter(test_definition, cargo_profile, phase) {
+        return TestResult::new(
+            test_definition,
+            debugger,
+            cargo_profile,
+            phase,
+            Status::Skipped(reason),
+        );
+    }
+
+    if let DebuggerExitStatus::TimedOut(hang) = debugger_output.exit_status {
+        let mut message = "The debugger was killed because it did not finish in time.".to_string();
+
+        let running = unfinished_block(&debugger_output.stdout).and_then(|cid| {
+            first_commands(debugger, test_definition, cargo_profile, phase).remove(&cid)
+        });
+
+        if let Some((command, line_number)) = running {
+            write!(
+                message,
+                " It was still running `{}` at line {}.",
+                command, line_number.0
+            )
+            .unwrap();
+        }
+
+        if let Some(description) = hang.description(&debugger_output.stdout) {
+            write!(message, " {}", description).unwrap();
+        }
+
+        message.push('\n');
+
+        return TestResult::new(
+            test_definition,
+            debugger,
+            cargo_profile,
+            phase,
+            Status::TimedOut(message),
+        );
+    }
+
+    if let DebuggerExitStatus::Crashed(crash) = debugger_output.exit_status {
+        return TestResult::new(
+            test_definition,
+            debugger,
+            cargo_profile,
+            phase,
+            Status::DebuggerCrashed(crash, debugger_output),
+        );
+    }
+
+    if debugger.kind == DebuggerKind::Lldb {
+        if let Some(message) = codesign::debugging_denied(&debugger_output) {
+            return TestResult::new(
+                test_definition,
+                debugger,
+                cargo_profile,
+                phase,
+                Status::Errored(message),
+            );
+        }
+    }
+
+    let debugger_output_by_correlation_id =
+        match debugger_output_by_correlation_id(&debugger_output) {
+            Ok(x) => x,
+            Err(message) => {
+                return TestResult::new(
+                    test_definition,
+                    debugger,
+                    cargo_profile,
+                    phase,
+                    Status::Errored(message),
+                );
+            }
+        };
+
+    // Addresses bound via `{{ptr:name}}` are shared by all checks of the test
+    let mut pointer_bindings = PointerBindings::default();
+    let mut matched_checks = vec![];
+
+    for (cid, checks) in checks_by_correlation_id {
+        // The checks of each `#each-hit` iteration share their line numbers with the other
+        // iterations, so only the matches in this block are looked at
+        let block_matches_start = matched_checks.len();
+        let output = if let Some(output) = debugger_output_by_correlation_id.get(&cid) {
+            output
+        } else {
+            return TestResult::new(
+                test_definition,
+                debugger,
+                cargo_profile,
+                phase,
+                Status::Failed(format!("check {:?} failed", &checks[0]), debugger_output),
+            );
+        };
+
+        // Command checks are evaluated against the output as a whole
+        for statement in &checks {
+            if let Statement::CommandCheck(check, _, line_number) = statement {
+                if let Err(failure) = debugger.evaluate_command_check(check, output) {
+                    let group_label = script.group_label_of(statement);
+
+                    let mut message = String::new();
+
+                    if let Some(group_label) = &group_label {
+                        write!(message, "[{}] ", group_label).unwrap();
+                    }
+
+                    writeln!(message, "{}\n", failure).unwrap();
+                    write_stderr_lines(&mut message, stderr_by_correlation_id.get(&cid));
+                    write_check_location(&mut message, test_definition, *line_number, &group_label);
+
+                    let status = Status::Failed(message, debugger_output);
+
+                    return TestResult::new(
+                        test_definition,
+                        debugger,
+                        cargo_profile,
+                        phase,
+                        status,
+                    );
+                }
+            }
+        }
+
+        let checks: Vec<_> = checks
+            .into_iter()
+            .filter(|statement| !matches!(statement, Statement::CommandCheck(..)))
+            .collect();
+
+        let mut check_index = 0;
+        let mut forbidden = take_negated_checks(&checks, &mut check_index);
+
+        for output_line in output {
+            match checks.get(check_index) {
+                Some(Statement::Check(check, _cid, _)) => {
+                    debug_assert_eq!(_cid, &Some(cid));
+
+                    // `#check-same` checks must match the same line as this check
+                    let same_line = same_line_checks(&checks[check_index + 1..]);
+                    let mut bindings = pointer_bindings.clone();
+
+                    if check.check_with_bindings(output_line, &mut bindings)
+                        && same_line
+                            .iter()
+                            .all(|check| check.check_with_bindings(output_line, &mut bindings))
+                    {
+                        // we have a match
+                        let output_line_number =
+                            stdout_line_number(&debugger_output.stdout, output_line);
+
+                        for statement in &checks[check_index..=check_index + same_line.len()] {
+                            matched_checks.push(CheckMatch {
+                                check_line: statement.line_number(),
+                                output_line: output_line_number,
+                                output: output_line.to_string(),
+                            });
+                        }
+
+                        pointer_bindings = bindings;
+                        check_index += 1 + same_line.len();
+                        forbidden = take_negated_checks(&checks, &mut check_index);
+                        continue;
+                    }
+                }
+                Some(Statement::CheckUnorderedBlock(..)) => {
+                    todo!()
+                }
+                Some(_) => {
+                    // only interested in check statements
+                }
+                None => {
+                    if forbidden.is_empty() {
+                        // We have found all the output we wanted
+                        break;
+                    }
+                }
+            }
+
+            if let Some((check, statement)) =
+                forbidden.iter().find(|(check, _)| check.check(output_line))
+            {
+                let line_number = statement.line_number();
+                let group_label = script.group_label_of(statement);
+
+                let mut message = String::new();
+
+                if let Some(group_label) = &group_label {
+                    write!(message, "[{}] ", group_label).unwrap();
+                }
+
+                writeln!(
+                    message,
+                    "Found '{}' in debugger output between the surrounding checks:\n",
+                    check
+                )
+                .unwrap();
+                writeln!(message, "> {}", output_line).unwrap();
+                writeln!(message).unwrap();
+                write_check_location(&mut message, test_definition, line_number, &group_label);
+
+                let status = Status::Failed(message, debugger_output);
+
+                return TestResult {
+                    matched_checks,
+                    ..TestResult::new(test_definition, debugger, cargo_profile, phase, status)
+                };
+            }
+        }
+
+        if check_index != checks.len() {
+            let (expected, line_number) = match &checks[check_index] {
+                Statement::Check(check, _, line_number) => {
+                    let mut expected = check.to_string();
+
+                    for same_line in same_line_checks(&checks[check_index + 1..]) {
+                        write!(expected, "' and '{}", same_line).unwrap();
+                    }
+
+                    (expected, line_number)
+                }
+                &Statement::CheckUnorderedBlock(..) => {
+                    todo!()
+                }
+                _ => {
+                    unreachable!()
+                }
+            };
+
+            let group_label = script.group_label_of(&checks[check_index]);
+
+            let mut message = String::new();
+
+            if let Some(group_label) = &group_label {
+                write!(message, "[{}] ", group_label).unwrap();
+            }
+
+            write!(
+                message,
+                "Could not find '{}' in debugger output. Expected to find it \
+                 within the following lines:\n\n",
+                expected
+            )
+            .unwrap();
+
+            for line in output.iter().filter(|x| {
+                !x.contains(CORRELATION_ID_BEGIN_MARKER) && !x.contains(CORRELATION_ID_END_MARKER)
+            }) {
+                writeln!(message, "> {}", line).unwrap();
+            }
+
+            writeln!(message).unwrap();
+            write_matched_checks(
+                &mut message,
+                &checks[..check_index],
+                &matched_checks[block_matches_start..],
+            );
+            write_stderr_lines(&mut message, stderr_by_correlation_id.get(&cid));
+            write_check_location(&mut message, test_definition, *line_number, &group_label);
+
+            let status = Status::Failed(message, debugger_output);
+
+            return TestResult {
+                matched_checks,
+                ..TestResult::new(test_definition, debugger, cargo_profile, phase, status)
+            };
+        }
+    }
+
+    if !program_output_checks.is_empty() {
+        let program_output = match &debugger_output.program_output {
+            Some(program_output) => program_output,
+            None => {
+                return TestResult::new(
+                    test_definition,
+                    debugger,
+                    cargo_profile,
+                    phase,
+                    Status::Errored(format!(
+                        "`#check-program-output` is not supported with {} in the {} phase, as \
+                         the output of the debuggee can't be captured separately.",
+                        debugger.kind.name(),
+                        phase
+                    )),
+                );
+            }
+        };
+
+        // Like `#check`, the checks have to match in order
+        let mut lines = program_output.lines();
+
+        for (check, line_number) in &program_output_checks {
+            if !lines.any(|line| check.check(line)) {
+                let mut message = String::new();
+
+                write!(
+                    message,
+                    "Could not find '{}' in the output of the debuggee. Expected to find it \
+                     within the following lines:\n\n",
+                    check
+                )
+                .unwrap();
+
+                for line in program_output.lines() {
+                    writeln!(message, "> {}", line).unwrap();
+                }
+
+                writeln!(message).unwrap();
+                write_check_location(&mut message, test_definition, *line_number, &None);
+
+                let status = Status::Failed(message, debugger_output);
+
+                return TestResult {
+                    matched_checks,
+                    ..TestResult::new(test_definition, debugger, cargo_profile, phase, status)
+                };
+            }
+        }
+    }
+
+    TestResult {
+        matched_checks,
+        ..TestResult::new(
+            test_definition,
+            debugger,
+            cargo_profile,
+            phase,
+            Status::Passed,
+        )
+    }
+}
+
+/// The number of the line of `stdout` that `line` is, counting from 1. `line` must be a
+/// slice of `stdout`, as returned by [debugger_output_by_correlation_id].
+fn stdout_line_number(stdout: &str, line: &str) -> usize {
+    let offset = line.as_ptr() as usize - stdout.as_ptr() as usize;
+    stdout[..offset].matches('\n').count() + 1
+}
+
+/// Collects the `#check-not-between` checks starting at `check_index` and advances
+/// `check_index` to the next regular check.
+fn take_negated_checks<'a>(
+    checks: &'a [Statement],
+    check_index: &mut usize,
+) -> Vec<(&'a RegexCheck, &'a Statement)> {
+    let mut negated = vec![];
+
+    while let Some(statement @ Statement::Check(check, ..)) = checks.get(*check_index) {
+        if !check.options.negated {
+            break;
+        }
+
+        negated.push((check, statement));
+        *check_index += 1;
+    }
+
+    negated
+}
+
+/// Returns the `#check-same` checks at the start of `checks`.
+fn same_line_checks(checks: &[Statement]) -> Vec<&RegexCheck> {
+    checks
+        .iter()
+        .map_while(|statement| match statement {
+            Statement::Check(check, ..) if check.options.same_line => Some(check),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Writes where the checks preceding a failed check in its block matched, so that it's clear
+/// where the output diverged from what the checks expect
+fn write_matched_checks(
+    message: &mut String,
+    preceding_checks: &[Statement],
+    matched_checks: &[CheckMatch],
+) {
+    let mut matched = preceding_checks
+        .iter()
+        .filter_map(|statement| match statement {
+            Statement::Check(check, _, line_number) if !check.options.negated => {
+                let check_match = matched_checks
+                    .iter()
+                    .find(|check_match| check_match.check_line == *line_number)?;
+
+                Some((check, line_number, check_match))
+            }
+            _ => None,
+        })
+        .peekable();
+
+    if matched.peek().is_none() {
+        return;
+    }
+
+    writeln!(message, "The checks before it in the same block matched:\n").unwrap();
+
+    for (check, line_number, check_match) in matched {
+        writeln!(
+            message,
+            "  '{}' (line {}) at line {} of stdout: {}",
+            check, line_number.0, check_match.output_line, check_match.output
+        )
+        .unwrap();
+    }
+
+    writeln!(message).unwrap();
+}
+
+/// Writes the stderr lines of the correlation block a check failed in, see
+/// [stderr_by_correlation_id]
+fn write_stderr_lines(message: &mut String, stderr_lines: Option<&Vec<String>>) {
+    if let Some(stderr_lines) = stderr_lines {
+        writeln!(message, "The debugger wrote to stderr meanwhile:\n").unwrap();
+
+        for line in stderr_lines {
+            writeln!(message, "! {}", line).unwrap();
+        }
+
+        writeln!(message).unwrap();
+    }
+}
+
+/// Selects the checks evaluated by [process_debugger_output] if `--check-filter` is given,
+/// going by the lines of the test's script
+struct CheckFilter<'a> {
+    filter: Option<&'a str>,
+    source_lines: &'a HashMap<u32, Arc<str>>,
+}
+
+impl<'a> CheckFilter<'a> {
+    fn new(debugger: &'a Debugger, script: &'a Script) -> CheckFilter<'a> {
+        CheckFilter {
+            filter: debugger.check_filter.as_deref(),
+            source_lines: &script.source_lines,
+        }
+    }
+
+    fn selects_line(&self, line_number: LineNumber) -> bool {
+        match self.filter {
+            Some(filter) => self
+                .source_lines
+                .get(&line_number.0)
+                .is_some_and(|line| line.contains(filter)),
+            None => true,
+        }
+    }
+
+    /// Unordered blocks are also selected if one of their lines matches
+    fn selects(&self, statement: &Statement) -> bool {
+        match (self.filter, statement) {
+            (Some(filter), Statement::CheckUnorderedBlock(lines, _, _))
+                if lines.iter().any(|line| line.contains(filter)) =>
+            {
+                true
+            }
+            _ => self.selects_line(statement.line_number()),
+        }
+    }
+}
+
+fn write_check_location(
+    message: &mut String,
+    test_definition: &TestDefinition,
+    line_number: LineNumber,
+    group_label: &Option<Arc<str>>,
+) {
+    write!(
+        message,
+        "Check failed at: {}:{}",
+        prettify_path(&test_definition.absolute_source_path),
+        line_number.0,
+    )
+    .unwrap();
+
+    if let Some(group_label) = group_label {
+        write!(message, " (group \"{}\")", group_label).unwrap();
+    }
+
+    writeln!(message).unwrap();
+}
+
+/// Splits debugger output into sections that correspond to a single correlation ID.
+/// Returns an error message if the output is malformed.
+///
+/// Debuggers that echo their commands (e.g. GDB with `set trace-commands on`) print the
+/// commands emitting the markers as well, as in `+python print('__correlation_id_end__=0')`.
+/// Such lines are dropped, as are markers repeating the one just seen and command fences.
+fn debugger_output_by_correlation_id(
+    debugger_output: &DebuggerOutput,
+) -> Result<BTreeMap<CorrelationId, Vec<&str>>, String> {
+    let mut result = BTreeMap::new();
+
+    let mut current_id = None;
+    let mut last_closed_id = None;
+    let mut current_lines = vec![];
+    for line in debugger_output.stdout.lines() {
+        if line.contains(COMMAND_BEGIN_MARKER) || line.contains(COMMAND_END_MARKER) {
+            // Command fences are only there for timing the commands
+            continue;
+        }
+
+        let is_marker = line.starts_with(CORRELATION_ID_BEGIN_MARKER)
+            || line.starts_with(CORRELATION_ID_END_MARKER);
+
+        if !is_marker
+            && (line.contains(CORRELATION_ID_BEGIN_MARKER)
+                || line.contains(CORRELATION_ID_END_MARKER))
+        {
+            // An echoed marker command
+            continue;
+        }
+
+        if line.starts_with(CORRELATION_ID_BEGIN_MARKER) {
+            let id = extract_correlation_id(line);
+
+            if current_id == Some(id) {
+                // The marker was printed twice
+                continue;
+            }
+
+            if current_id.is_some() {
+                // malformed debugger output
+                return Err(
+                    "Unexpected debugger output: correlation ID begin marker during active correlation section."
+                        .to_string(),
+                );
+            }
+            assert!(current_lines.is_empty());
+            current_id = Some(id);
+        } else if line.starts_with(CORRELATION_ID_END_MARKER) {
+            if let Some(current_id) = current_id {
+                result.insert(current_id, current_lines);
+                current_lines = vec![];
+                last_closed_id = Some(extract_correlation_id(line));
+            } else if last_closed_id == Some(extract_correlation_id(line)) {
+                // The marker was printed twice
+                continue;
+            } else {
+                // malformed debugger output
+                return Err(
+                    "Unexpected debugger output: unopened correlation ID end marker.".to_string(),
+                );
+            }
+
+            current_id = None;
+        } else if current_id.is_some() {
+            current_lines.push(line);
+        }
+    }
+
+    if let Some(current_id) = current_id {
+        // Always add the current ID even if it was not closed.
+        // This leads to better failure messages when the debugger scripts
+        // aborts prematurely.
+        result.insert(current_id, current_lines);
+    }
+
+    Ok(result)
+}
+
+/// A debugger command given via `-d`, with the kind and version it reported, see
+/// [probe_debuggers]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProbedDebugger {
+    command: PathBuf,
+    kind: DebuggerKind,
+    version: Arc<str>,
+    wine: Option<OsString>,
+}
+
+/// Asks each debugger command for its kind and version, leaving out the debuggers that `only`
+/// and `skip` exclude (see [select_debuggers]) before anything else is done with them.
+pub fn probe_debuggers(
+    commands: &[PathBuf],
+    wine: Option<&OsStr>,
+    only: &[DebuggerSelector],
+    skip: &[DebuggerSelector],
+) -> anyhow::Result<Vec<ProbedDebugger>> {
+    let mut probed: Vec<ProbedDebugger> = vec![];
+
+    for command in commands {
+        info!("Trying to set up debugger {}", command.display());
+        let wine = wine.filter(|_| wine::runs_under_wine(command));
+        let (kind, version) = Debugger::infer_from_command(command, wine)?;
+
+        if !is_selected(kind, &version, only, skip) {
+            info!("Leaving out {} {}", kind.name(), version);
+            continue;
+        }
+
+        // Results, output files and recordings are told apart by kind and version
+        if let Some(other) = probed
+            .iter()
+            .find(|other| other.kind == kind && other.version == version)
+        {
+            bail!(
+                "{} and {} are both {} {}",
+                command.display(),
+                other.command.display(),
+                kind.name(),
+                version
+            );
+        }
+
+        probed.push(ProbedDebugger {
+            command: command.clone(),
+            kind,
+            version,
+            wine: wine.map(OsString::from),
+        });
+    }
+
+    Ok(probed)
+}
+
+/// Creates a [Debugger] object for each of the `probed` debuggers and, with `replay_directory`,
+/// each recorded one.
+pub fn init_debuggers(
+    probed: &[ProbedDebugger],
+    preludes: &[OsString],
+    commandline_args: &[OsString],
+    env_vars: &[OsString],
+    defines: &[String],
+    replay_directory: Option<&Path>,
+) -> anyhow::Result<Vec<Debugger>> {
+    let prelude_map = build_prelude_map(preludes)?;
+    let commandline_arg_map = build_commandline_arg_map(commandline_args)?;
+    let env_var_map = build_env_var_map(env_vars)?;
+
+    let defines = define_variables(defines)?;
+
+    info!("Setting up debuggers");
+    let mut debuggers = vec![];
+
+    for ProbedDebugger {
+        command,
+        kind,
+        version,
+        wine,
+    } in probed
+    {
+        let mut debugger = Debugger::new(
+            *kind,
+            version.clone(),
+            command.into(),
+            prelude_map.for_debugger(*kind, version),
+            commandline_arg_map.for_debugger(*kind, version),
+            env_var_map.for_debugger(*kind, version),
+            defines.clone(),
+        );
+        debugger.wine = wine.clone();
+
+        info!("Successfully set up debugger: {:?}", debugger);
+        debuggers.push(debugger);
+    }
+
+    if let Some(replay_directory) = replay_directory {
+        for (debugger_kind, version) in recording::recorded_debuggers(replay_directory)? {
+            // Replayed debuggers are told apart from the others like in [probe_debuggers]
+            if let Some(other) = debuggers
+                .iter()
+                .find(|other| other.kind == debugger_kind && other.version == version)
+            {
+                bail!(
+                    "{} {} is both given via -d ({}) and replayed from {}",
+                    debugger_kind.name(),
+                    version,
+                    other.command.to_string_lossy(),
+                    prettify_path(replay_directory)
+                );
+            }
+
+            // The prelude is part of the debugger script, so it has to match the recording
+            let mut debugger = Debugger::new(
+                debugger_kind,
+                version.clone(),
+                "mockdbg".into(),
+                prelude_map.for_debugger(debugger_kind, &version),
+                vec![],
+                vec![],
+                defines.clone(),
+            );
+            debugger.replay_directory = Some(replay_directory.to_path_buf());
+
+            info!("Replaying recorded debugger: {:?}", debugger);
+            debuggers.push(debugger);
+        }
+    }
+
+    Ok(debuggers)
+}
+
+/// Turns `--define` values into the names of the variables they define. The variables dbt
+/// defines itself can't be defined this way, as some of them are only defined in some
+/// environments (e.g. `@ci`), where the same command line would fail otherwise.
+fn define_variables(defines: &[String]) -> anyhow::Result<Arc<[Arc<str>]>> {
+    let reserved = [
+        "@debugger",
+        "@version",
+        "@cargo_profile",
+        "@debuginfo",
+        "@opt_level",
+        "@phase",
+        "@gdb_non_stop",
+        "@gdb_scheduler_locking",
+        wine::VARIABLE,
+        ci::VARIABLE,
+        ci::PROVIDER_VARIABLE,
+    ];
+
+    let mut variables = vec![];
+
+    for define in defines {
+        let variable = format!("@{}", define);
+
+        if reserved.contains(&&variable[..])
+            || DebuggerKind::try_from(&define[..]).is_ok_and(|kind| kind.name() == define)
+        {
+            bail!(
+                "`--define {}` is not allowed, dbt defines `{}` itself",
+                define,
+                variable
+            );
+        }
+
+        variables.push(Arc::from(variable));
+    }
+
+    Ok(variables.into())
+}
+
+/// Which debuggers a per-debugger option (like `--debugger-prelude`) applies to: all debuggers
+/// of a kind (`gdb`), or only those whose version also starts with the given one (`gdb@14`)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebuggerSelector {
+    pub kind: DebuggerKind,
+    pub version: Option<String>,
+}
+
+impl DebuggerSelector {
+    pub fn parse(s: &str) -> anyhow::Result<DebuggerSelector> {
+        let (kind, version) = match s.trim().split_once('@') {
+            Some((kind, version)) => (kind, Some(version.trim().to_string())),
+            None => (s, None),
+        };
+
+        Ok(DebuggerSelector {
+            kind: DebuggerKind::try_from(kind)?,
+            version,
+        })
+    }
+
+    pub fn matches(&self, kind: DebuggerKind, version: &str) -> bool {
+        self.kind == kind
+            && self.version.as_deref().is_none_or(|prefix| {
+                version == prefix
+                    || version
+                        .strip_prefix(prefix)
+                        .is_some_and(|rest| rest.starts_with('.'))
+            })
+    }
+}
+
+/// The debuggers that match one of `only` (or all if it's empty) and none of `skip`, see
+/// `--only-debugger` and `--skip-debugger`. Fails for a selector in `only` that matches none
+/// of the debuggers, as that's most likely a typo.
+pub fn select_debuggers(
+    debuggers: Vec<Debugger>,
+    only: &[DebuggerSelector],
+    skip: &[DebuggerSelector],
+) -> anyhow::Result<Vec<Debugger>> {
+    for selector in only {
+        if !debuggers
+            .iter()
+            .any(|debugger| selector.matches(debugger.kind, &debugger.version))
+        {
+            match &selector.version {
+                Some(version) => bail!(
+                    "--only-debugger {}@{} does not match any of the debuggers given via -d",
+                    selector.kind.name(),
+                    version
+                ),
+                None => bail!(
+                    "--only-debugger {} does not match any of the debuggers given via -d",
+                    selector.kind.name()
+                ),
+            }
+        }
+    }
+
+    Ok(debuggers
+        .into_iter()
+        .filter(|debugger| is_selected(debugger.kind, &debugger.version, only, skip))
+        .collect())
+}
+
+fn is_selected(
+    kind: DebuggerKind,
+    version: &str,
+    only: &[DebuggerSelector],
+    skip: &[DebuggerSelector],
+) -> bool {
+    let matches = |selector: &DebuggerSelector| selector.matches(kind, version);
+
+    (only.is_empty() || only.iter().any(matches)) && !skip.iter().any(matches)
+}
+
+/// Where a prelude command goes in the debugger script, given after the debugger it is for,
+/// e.g. `cdb/before-generated:.sympath+ C:\symbols`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PreludePlacement {
+    /// `before-generated`: before the commands dbt generates, like the default settings, e.g.
+    /// for settings that have to be in place before modules are loaded
+    BeforeGenerated,
+    /// Without a placement: after the commands dbt generates
+    #[default]
+    AfterGenerated,
+    /// `after-breakpoints`: after the breakpoints for `#break` markers are set
+    AfterBreakpoints,
+    /// `before-run`: right before the command of the script that starts the debuggee, see
+    /// [DebuggerKind::starts_debuggee]
+    BeforeRun,
+}
+
+impl PreludePlacement {
+    fn parse(s: &str) -> anyhow::Result<PreludePlacement> {
+        match s.trim() {
+            "before-generated" => Ok(PreludePlacement::BeforeGenerated),
+            "after-breakpoints" => Ok(PreludePlacement::AfterBreakpoints),
+            "before-run" => Ok(PreludePlacement::BeforeRun),
+            other => bail!(
+                "Unknown prelude placement `{}`, expected `before-generated`, \
+                 `after-breakpoints` or `before-run`",
+                other
+            ),
+        }
+    }
+}
+
+/// Parses a prelude command of the form
+/// `<debugger-kind>[@<version>][/<placement>]:<command>`
+pub fn parse_prelude(s: &str) -> anyhow::Result<(DebuggerSelector, (PreludePlacement, String))> {
+    let (debugger, command) = match s.split_once(':') {
+        Some(split) => split,
+        None => bail!("No debugger kind specified in {}", s),
+    };
+
+    let (selector, placement) = match debugger.split_once('/') {
+        Some((selector, placement)) => (selector, PreludePlacement::parse(placement)?),
+        None => (debugger, PreludePlacement::default()),
+    };
+
+    Ok((
+        DebuggerSelector::parse(selector)?,
+        (placement, command.trim().to_owned()),
+    ))
+}
+
+/// Option values given per debugger, in the order they were given
+struct PerDebugger<T> {
+    values: Vec<(DebuggerSelector, T)>,
+}
+
+impl<T: Clone> PerDebugger<T> {
+    fn for_debugger(&self, kind: DebuggerKind, version: &str) -> Vec<T> {
+        self.values
+            .iter()
+            .filter(|(selector, _)| selector.matches(kind, version))
+            .map(|(_, value)| value.clone())
+            .collect()
+    }
+}
+
+fn build_prelude_map(
+    preludes: &[OsString],
+) -> anyhow::Result<PerDebugger<(PreludePlacement, String)>> {
+    info!("Scanning debugger preludes");
+    let mut values = vec![];
+
+    for prelude in preludes {
+        values.push(
+            parse_prelude(&prelude.to_string_lossy())
+                .context("while scanning debugger preludes")?,
+        );
+    }
+
+    Ok(PerDebugger { values })
+}
+
+fn build_commandline_arg_map(preludes: &[OsString]) -> anyhow::Result<PerDebugger<String>> {
+    info!("Scanning debugger commandline args");
+    partition_by_debugger_kind(preludes).context("while scanning debugger commandline args")
+}
+
+fn build_env_var_map(env_vars: &[OsString]) -> anyhow::Result<PerDebugger<(String, String)>> {
+    info!("Scanning debugger environment variables");
+    let by_debugger =
+        partition_by_debugger_kind(env_vars).context("while scanning debugger env vars")?;
+
+    let mut env_vars = Vec::with_capacity(by_debugger.values.len());
+
+    for (selector, var_spec) in by_debugger.values {
+        if let Some((var_name, var_value)) = var_spec.split_once('=') {
+            let var = (var_name.trim().to_string(), var_value.trim().to_string());
+            env_vars.push((selector, var));
+        } else {
+            bail!(
+                "Could not parse env var spec: {}:{}",
+                selector.kind,
+                var_spec
+            );
+        }
+    }
+
+    Ok(PerDebugger { values: env_vars })
+}
+
+/// Splits values of the form `<debugger-kind>[@<version>]:<value>`
+fn partition_by_debugger_kind(strings: &[OsString]) -> anyhow::Result<PerDebugger<String>> {
+    let mut values = vec![];
+    for s in strings {
+        let as_str = s.to_string_lossy();
+        if let Some((debugger, command)) = as_str.split_once(':') {
+            let selector = DebuggerSelector::parse(debugger)?;
+            values.push((selector, command.trim().to_owned()));
+        } else {
+            bail!("No debugger kind specified in {}", as_str);
+        }
+    }
+    Ok(PerDebugger { values })
+}
+
+fn extract_gdb_version(version_output: &str) -> Option<&str> {
+    lazy_static! {
+        // GNU gdb (Ubuntu 9.2-0ubuntu1~20.04) 9.2
+        // GNU gdb (GDB) 10.2
+        // GNU gdb (GDB) 8.2.1
+        static ref GDB_REGEX: Regex = Regex::new(r"GNU\s+gdb\s+\(.+\)\s+(.+)").unwrap();
+    }
+
+    GDB_REGEX
+        .captures(version_output)
+        .map(|captures| captures.get(1).unwrap().as_str())
+}
+
+fn extract_cdb_version(version_output: &str) -> Option<&str> {
+    lazy_static! {
+        // example: cdb version 10.0.21349.1004
+        static ref CDB_REGEX: Regex = Regex::new(r"cdb\s+version\s+([\d\.]+)").unwrap();
+    }
+
+    CDB_REGEX
+        .captures(version_output)
+        .map(|captures| captures.get(1).unwrap().as_str())
+}
+
+fn extract_lldb_version(version_output: &str) -> Option<&str> {
+    lazy_static! {
+        // example: lldb version 12.0.0
+        static ref LLDB_REGEX: Regex = Regex::new(r"lldb\s+version\s+([\d\.]+)").unwrap();
+    }
+
+    LLDB_REGEX
+        .captures(version_output)
+        .map(|captures| captures.get(1).unwrap().as_str())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        path::{Path, PathBuf},
+        process::Command,
+        sync::Arc,
+        time::Duration,
+    };
+
+    use crate::{
+        breakpoints::BreakPoint,
+        cargo_test_directory::TestDefinition,
+        debugger::{
+            Debugger, DebuggerCrash, DebuggerExitStatus, DebuggerKind, DebuggerOutput,
+            LldbScriptMode, SchedulerLocking, SessionOptions,
+        },
+        hang::Hang,
+        regex_check::RegexCheck,
+        script::{
+            parse_script, CommandCheck, CorrelationId, DebuggerCommand, ExpectedLocal, FieldLayout,
+            ForkMode, LineNumber, PhaseConfig, Statement, TypeLayout, WatchAccess, Watchpoint,
+        },
+        test_config::TestConfig,
+        test_result::Status,
+    };
+
+    fn session(timeout: Option<Duration>) -> SessionOptions {
+        SessionOptions {
+            timeout,
+            ..SessionOptions::default()
+        }
+    }
+
+    fn from_lines(lines: &[&str]) -> String {
+        let mut output = String::new();
+
+        for line in lines {
+            output.push_str(line);
+            output.push('\n');
+        }
+        output
+    }
+
+    fn mock_test_def(script: String) -> TestDefinition {
+        let script = parse_script(&script, None).unwrap();
+
+        let root_path = if cfg!(windows) {
+            Path::new("D:\\mock")
+        } else {
+            Path::new("/mock")
+        };
+
+        let relative_path = Path::new("src").join("main.rs");
+
+        TestDefinition::new(
+            relative_path.as_path(),
+            root_path.join(relative_path.as_path()).as_path(),
+            "project",
+            "main".into(),
+            script,
+            vec![],
+        )
+    }
+
+    /// Generates the script of `test_def` for the live phase with the `debug` profile
+    fn live_script(debugger: &Debugger, test_def: &TestDefinition) -> String {
+        super::generate_debugger_script(
+            debugger,
+            test_def,
+            &Arc::from("debug"),
+            &PhaseConfig::Live,
+            &mut |tag| PathBuf::from(tag),
+        )
+    }
+
+    /// Processes `stdout` as the output of a successful debugger run of [live_script]
+    fn process_stdout(debugger: &Debugger, test_def: &TestDefinition, stdout: String) -> Status {
+        *super::process_debugger_output(
+            debugger,
+            test_def,
+            DebuggerOutput {
+                stdout,
+                stderr: String::new(),
+                exit_status: DebuggerExitStatus::Success,
+                program_output: None,
+            },
+            &BTreeMap::new(),
+            &Arc::from("debug"),
+            &PhaseConfig::Live,
+        )
+        .status
+    }
+
+    /// The status of `test_def` with the mock debugger, which echoes the script
+    fn mock_status(test_def: &TestDefinition) -> Status {
+        let debugger = Debugger::mock();
+        process_stdout(&debugger, test_def, live_script(&debugger, test_def))
+    }
+
+    #[test]
+    fn debuggee_args() {
+        let args = |kind: DebuggerKind| {
+            let script_args = kind
+                .script_args(Path::new("script"), LldbScriptMode::Source)
+                .unwrap();
+            let command = kind.command(
+                "dbg".as_ref(),
+                &script_args,
+                Path::new("harness"),
+                &["my_test".to_string(), "--exact".to_string()],
+                None,
+                &[],
+            );
+
+            command
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            args(DebuggerKind::Gdb),
+            vec![
+                "--batch",
+                "--quiet",
+                "--command",
+                "script",
+                "--args",
+                "harness",
+                "my_test",
+                "--exact"
+            ]
+        );
+        assert_eq!(
+            args(DebuggerKind::Lldb),
+            vec!["--batch", "--source", "script", "harness", "--", "my_test", "--exact"]
+        );
+        assert_eq!(
+            args(DebuggerKind::Cdb),
+            vec!["-cf", "script", "harness", "my_test", "--exact"]
+        );
+    }
+
+    #[test]
+    fn lldb_script_mode() {
+        let script_file_path =
+            std::env::temp_dir().join(format!("dbt \"lldb\" script-{}", std::process::id()));
+        std::fs::write(&script_file_path, "b main\n\nrun\n").unwrap();
+
+        let args = |mode: LldbScriptMode| {
+            DebuggerKind::Lldb
+                .script_args(&script_file_path, mode)
+                .unwrap()
+                .into_iter()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            args(LldbScriptMode::OneLiners),
+            ["-o", "b main", "-o", "run"]
+        );
+        assert_eq!(
+            args(LldbScriptMode::CommandSource),
+            [
+                "-o".to_string(),
+                format!(
+                    "command source --stop-on-error false --stop-on-continue false \"{}\"",
+                    script_file_path
+                        .display()
+                        .to_string()
+                        .replace('\\', "\\\\")
+                        .replace('"', "\\\"")
+                )
+            ]
+        );
+
+        std::fs::remove_file(&script_file_path).unwrap();
+
+        let for_host = |values: &[&str]| {
+            let values: Vec<String> = values.iter().map(|value| value.to_string()).collect();
+            LldbScriptMode::for_host(&values)
+        };
+
+        assert_eq!(for_host(&[]).unwrap(), LldbScriptMode::Source);
+        assert_eq!(
+            for_host(&["one-liners", "no-such-os:command-source"]).unwrap(),
+            LldbScriptMode::OneLiners
+        );
+        assert_eq!(
+            for_host(&[&format!("{}:command-source", std::env::consts::OS)]).unwrap(),
+            LldbScriptMode::CommandSource
+        );
+        assert!(for_host(&["inline"]).is_err());
+    }
+
+    #[test]
+    fn generate_debugger_script() {
+        let test_def = mock_test_def(from_lines(&[
+            "/***",
+            "#if @mock",
+            "  print abc",
+            "  #check __abc__",
+            "  #generate-crashdump foo",
+            "  print xyz",
+            "  #check __xyz__",
+            "***/",
+        ]));
+
+        let script = super::generate_debugger_script(
+            &Debugger::mock(),
+            &test_def,
+            &Arc::from("debug"),
+            &PhaseConfig::Live,
+            &mut |tag| PathBuf::from(format!("base-dir/{}/crashdump.dmp", tag)),
+        );
+
+        assert_eq!(
+            script,
+            from_lines(&[
+                "__correlation_id_begin__=0",
+                "print abc",
+                "__correlation_id_end__=0",
+                "__correlation_id_begin__=1",
+                "generate_crashdump base-dir/foo/crashdump.dmp",
+                "__correlation_id_end__=1",
+                "__correlation_id_begin__=2",
+                "print xyz",
+                "__correlation_id_end__=2",
+            ])
+        );
+    }
+
+    #[test]
+    fn correlation_id_assignment_simple() {
+        let mut script = mock_test_def(from_lines(&[
+            "/***",
+            "foo",
+            "#check foo",
+            "bar",
+            "#check bar",
+            "baz",
+            "#check baz",
+            "***/",
+        ]))
+        .script;
+
+        Debugger::mock().assign_correlation_ids(
+            &mut script,
+            &TestConfig::default(),
+            &Arc::from("debug"),
+            &PhaseConfig::Live,
+        );
+
+        assert_eq!(
+            script.statements,
+            vec![
+                Statement::Exec("foo".into(), Some(CorrelationId(0)), LineNumber::ANY),
+                Statement::Check("foo".into(), Some(CorrelationId(0)), LineNumber::ANY),
+                Statement::Exec("bar".into(), Some(CorrelationId(1)), LineNumber::ANY),
+                Statement::Check("bar".into(), Some(CorrelationId(1)), LineNumber::ANY),
+                Statement::Exec("baz".into(), Some(CorrelationId(2)), LineNumber::ANY),
+                Statement::Check("baz".into(), Some(CorrelationId(2)), LineNumber::ANY),
+            ]
+        );
+    }
+
+    #[test]
+    fn correlation_id_assignment_multi_exec() {
+        let mut script = mock_test_def(from_lines(&[
+            "/***",
+            "foo",
+            "bar",
+            "baz",
+            "#check baz",
+            "***/",
+        ]))
+        .script;
+
+        Debugger::mock().assign_correlation_ids(
+            &mut script,
+            &TestConfig::default(),
+            &Arc::from("debug"),
+            &PhaseConfig::Live,
+        );
+
+        assert_eq!(
+            script.statements,
+            vec![
+                Statement::Exec("foo".into(), Some(CorrelationId(0)), LineNumber::ANY),
+                Statement::Exec("bar".into(), Some(CorrelationId(0)), LineNumber::ANY),
+                Statement::Exec("baz".into(), Some(CorrelationId(0)), LineNumber::ANY),
+                Statement::Check("baz".into(), Some(CorrelationId(0)), LineNumber::ANY),
+            ]
+        );
+    }
+
+    #[test]
+    fn correlation_id_assignment_multi_check() {
+        let mut script = mock_test_def(from_lines(&[
+            "/***",
+            "foo",
+            "#check foo",
+            "#check bar",
+            "#check baz",
+            "***/",
+        ]))
+        .script;
+
+        Debugger::mock().assign_correlation_ids(
+            &mut script,
+            &TestConfig::default(),
+            &Arc::from("debug"),
+            &PhaseConfig::Live,
+        );
+
+        assert_eq!(
+            script.statements,
+            vec![
+                Statement::Exec("foo".into(), Some(CorrelationId(0)), LineNumber::ANY),
+                Statement::Check("foo".into(), Some(CorrelationId(0)), LineNumber::ANY),
+                Statement::Check("bar".into(), Some(CorrelationId(0)), LineNumber::ANY),
+                Statement::Check("baz".into(), Some(CorrelationId(0)), LineNumber::ANY),
+            ]
+        );
+    }
+
+    #[test]
+    fn correlation_id_assignment_multiple_of_both() {
+        let mut script = mock_test_def(from_lines(&[
+            "/***",
+            "foo",
+            "bar",
+            "baz",
+            "#check foo",
+            "#check bar",
+            "#check baz",
+            "***/",
+        ]))
+        .script;
+
+        Debugger::mock().assign_correlation_ids(
+            &mut script,
+            &TestConfig::default(),
+            &Arc::from("debug"),
+            &PhaseConfig::Live,
+        );
+
+        assert_eq!(
+            script.statements,
+            vec![
+                Statement::Exec("foo".into(), Some(CorrelationId(0)), LineNumber::ANY),
+                Statement::Exec("bar".into(), Some(CorrelationId(0)), LineNumber::ANY),
+                Statement::Exec("baz".into(), Some(CorrelationId(0)), LineNumber::ANY),
+                Statement::Check("foo".into(), Some(CorrelationId(0)), LineNumber::ANY),
+                Statement::Check("bar".into(), Some(CorrelationId(0)), LineNumber::ANY),
+                Statement::Check("baz".into(), Some(CorrelationId(0)), LineNumber::ANY),
+            ]
+        );
+    }
+
+    #[test]
+    fn correlation_id_assignment_generate_crashdump() {
+        let mut script = mock_test_def(from_lines(&[
+            "/***",
+            "foo",
+            "bar",
+            "baz",
+            "#generate-crashdump tag",
+            "#check foo",
+            "#check bar",
+            "#check baz",
+            "***/",
+        ]))
+        .script;
+
+        Debugger::mock().assign_correlation_ids(
+            &mut script,
+            &TestConfig::default(),
+            &Arc::from("debug"),
+            &PhaseConfig::Live,
+        );
+
+        assert_eq!(
+            script.statements,
+            vec![
+                Statement::Exec("foo".into(), Some(CorrelationId(0)), LineNumber::ANY),
+                Statement::Exec("bar".into(), Some(CorrelationId(0)), LineNumber::ANY),
+                Statement::Exec("baz".into(), Some(CorrelationId(0)), LineNumber::ANY),
+                Statement::GenerateCrashDump("tag".into(), Some(CorrelationId(1)), LineNumber::ANY),
+                Statement::Check("foo".into(), Some(CorrelationId(1)), LineNumber::ANY),
+                Statement::Check("bar".into(), Some(CorrelationId(1)), LineNumber::ANY),
+                Statement::Check("baz".into(), Some(CorrelationId(1)), LineNumber::ANY),
+            ]
+        );
+    }
+
+    #[test]
+    fn correlation_id_assignment_sequence() {
+        let mut script = mock_test_def(from_lines(&[
+            "/***",
+            "foo",
+            "#sequence",
+            "bar",
+            "baz",
+            "#check bar",
+            "#sequence",
+            "#check baz",
+            "quux",
+            "***/",
+        ]))
+        .script;
+
+        Debugger::mock().assign_correlation_ids(
+            &mut script,
+            &TestConfig::default(),
+            &Arc::from("debug"),
+            &PhaseConfig::Live,
+        );
+
+        assert_eq!(
+            script.statements,
+            vec![
+                Statement::Exec("foo".into(), Some(CorrelationId(0)), LineNumber::ANY),
+                Statement::Sequence(LineNumber::ANY),
+                Statement::Exec("bar".into(), Some(CorrelationId(1)), LineNumber::ANY),
+                Statement::Exec("baz".into(), Some(CorrelationId(1)), LineNumber::ANY),
+                Statement::Check("bar".into(), Some(CorrelationId(1)), LineNumber::ANY),
+                Statement::Sequence(LineNumber::ANY),
+                Statement::Check("baz".into(), Some(CorrelationId(1)), LineNumber::ANY),
+                Statement::Exec("quux".into(), Some(CorrelationId(2)), LineNumber::ANY),
+            ]
+        );
+    }
+
+    #[test]
+    fn generate_debugger_script_sequence() {
+        let test_def = mock_test_def(from_lines(&[
+            "/***",
+            "print abc",
+            "#sequence",
+            "print xyz",
+            "#check __xyz__",
+            "***/",
+        ]));
+
+        let script = super::generate_debugger_script(
+            &Debugger::mock(),
+            &test_def,
+            &Arc::from("debug"),
+            &PhaseConfig::Live,
+            &mut |tag| PathBuf::from(format!("base-dir/{}/crashdump.dmp", tag)),
+        );
+
+        assert_eq!(
+            script,
+            from_lines(&[
+                "__correlation_id_begin__=0",
+                "print abc",
+                "__correlation_id_end__=0",
+                "__correlation_id_begin__=1",
+                "print xyz",
+                "__correlation_id_end__=1",
+            ])
+        );
+    }
+
+    #[test]
+    fn generate_debugger_script_named_breakpoint() {
+        let mut test_def = mock_test_def(from_lines(&[
+            "/***",
+            "run",
+            "#break loop-body",
+            "continue",
+            "***/",
+        ]));
+
+        test_def.breakpoints = vec![
+            BreakPoint {
+                line_index: 7,
+                name: None,
+                hit_count: Some(3),
+                temporary: false,
+            },
+            BreakPoint {
+                line_index: 9,
+                name: Some("loop-body".into()),
+                hit_count: None,
+                temporary: false,
+            },
+        ];
+
+        let script = super::generate_debugger_script(
+            &Debugger::mock(),
+            &test_def,
+            &Arc::from("debug"),
+            &PhaseConfig::Live,
+            &mut |tag| PathBuf::from(tag),
+        );
+
+        assert_eq!(
+            script,
+            from_lines(&[
+                "bp `main.rs:8` 3",
+                "__correlation_id_begin__=0",
+                "run",
+                "bp `main.rs:10`",
+                "continue",
+                "__correlation_id_end__=0",
+            ])
+        );
+    }
+
+    #[test]
+    fn generate_debugger_script_prelude_placement() {
+        let mut test_def = mock_test_def(from_lines(&[
+            "/***",
+            "print a",
+            "#check print a",
+            "run",
+            "***/",
+        ]));
+        test_def.breakpoints = vec![BreakPoint {
+            line_index: 7,
+            name: None,
+            hit_count: None,
+            temporary: false,
+        }];
+
+        let preludes = [
+            "mock/before-run:fourth",
+            "mock/after-breakpoints:third",
+            "mock:second",
+            "mock/before-generated:first",
+        ];
+        let debugger = Debugger::new(
+            DebuggerKind::Mock,
+            "1.0".into(),
+            "mockdbg".into(),
+            preludes
+                .iter()
+                .map(|prelude| super::parse_prelude(prelude).unwrap().1)
+                .collect(),
+            vec![],
+            vec![],
+            vec![].into(),
+        );
+
+        let script = |test_def: &TestDefinition| {
+            super::generate_debugger_script(
+                &debugger,
+                test_def,
+                &Arc::from("debug"),
+                &PhaseConfig::Live,
+                &mut |tag| PathBuf::from(tag),
+            )
+        };
+
+        assert_eq!(
+            script(&test_def),
+            from_lines(&[
+                "first",
+                "second",
+                "bp `main.rs:8`",
+                "third",
+                "__correlation_id_begin__=0",
+                "print a",
+                "__correlation_id_end__=0",
+                "fourth",
+                "__correlation_id_begin__=1",
+                "run",
+                "__correlation_id_end__=1",
+            ])
+        );
+
+        // Without a command starting the debuggee, they go before the script's commands
+        test_def.script = mock_test_def(from_lines(&["/***", "print a", "***/"])).script;
+        assert!(script(&test_def).starts_with("first\nsecond\nbp `main.rs:8`\nthird\nfourth\n"));
+
+        assert!(super::parse_prelude("gdb/before-load:set x").is_err());
+    }
+
+    #[test]
+    fn each_hit() {
+        let mut test_def = mock_test_def(from_lines(&[
+            "/***",
+            "run",
+            "print i",
+            "#each-hit loop-body 3",
+            "  print i",
+            "  #check print i",
+            "***/",
+        ]));
+
+        test_def.breakpoints = vec![BreakPoint {
+            line_index: 9,
+            name: Some("loop-body".into()),
+            hit_count: None,
+            temporary: false,
+        }];
+
+        let debugger = Debugger::mock();
+        let script = live_script(&debugger, &test_def);
+
+        assert_eq!(
+            script,
+            from_lines(&[
+                "__correlation_id_begin__=0",
+                "run",
+                "print i",
+                "bp `main.rs:10`",
+                "__correlation_id_end__=0",
+                "__correlation_id_begin__=1",
+                "continue",
+                "print i",
+                "__correlation_id_end__=1",
+                "__correlation_id_begin__=2",
+                "continue",
+                "print i",
+                "__correlation_id_end__=2",
+                "__correlation_id_begin__=3",
+                "continue",
+                "print i",
+                "__correlation_id_end__=3",
+            ])
+        );
+
+        // Failures are attributed to the iteration they occurred in, and the first one
+        // doesn't see the output from before the loop
+        let first_hit = script.match_indices("print i").nth(1).unwrap().0;
+        let mut stdout = script.clone();
+        stdout.replace_range(first_hit..first_hit + "print i".len(), "print j");
+
+        match process_stdout(&debugger, &test_def, stdout) {
+            Status::Failed(ref message, _) => {
+                assert!(message.starts_with("[loop-body hit 1] Could not find 'print i'"));
+                assert!(message.contains("main.rs:6 (group \"loop-body hit 1\")"));
+            }
+            ref status => panic!("unexpected status {:?}", status),
+        }
+
+        // The matches listed for a failed check are those of its own iteration
+        let test_def = mock_test_def(from_lines(&[
+            "/***",
+            "run",
+            "#each-hit loop-body 2",
+            "  print i",
+            "  print k",
+            "  #check print i",
+            "  #check print k",
+            "***/",
+        ]));
+        let stdout = live_script(&debugger, &test_def);
+        let second_hit = stdout.match_indices("print k").nth(1).unwrap().0;
+        let mut stdout = stdout.clone();
+        stdout.replace_range(second_hit..second_hit + "print k".len(), "print j");
+
+        match process_stdout(&debugger, &test_def, stdout) {
+            Status::Failed(ref message, _) => {
+                assert!(message.starts_with("[loop-body hit 2] Could not find 'print k'"));
+                assert!(
+                    message.contains("'print i' (line 6) at line 11 of stdout: print i"),
+                    "{}",
+                    message
+                );
+            }
+            ref status => panic!("unexpected status {:?}", status),
+        }
+    }
+
+    #[test]
+    fn emit_breakpoint_options() {
+        let test_def = mock_test_def(from_lines(&["/***", "run", "***/"]));
+        let mut bp = BreakPoint {
+            line_index: 9,
+            name: None,
+            hit_count: Some(3),
+            temporary: false,
+        };
+
+        let emit = |kind, bp: &BreakPoint| {
+            let debugger = Debugger {
+                kind,
+                ..Debugger::mock()
+            };
+
+            let mut output = String::new();
+            debugger.emit_breakpoint(&test_def, bp, &mut output);
+            output
+        };
+
+        assert_eq!(
+            emit(DebuggerKind::Gdb, &bp),
+            "break 'main.rs:10'\nignore $bpnum 2\n"
+        );
+        assert_eq!(
+            emit(DebuggerKind::Lldb, &bp),
+            "breakpoint set --file 'main.rs' --line 10 --ignore-count 2\n"
+        );
+        assert_eq!(emit(DebuggerKind::Cdb, &bp), "bp `main.rs:10` 3\n");
+
+        bp.hit_count = None;
+        bp.temporary = true;
+
+        assert_eq!(emit(DebuggerKind::Gdb, &bp), "tbreak 'main.rs:10'\n");
+        assert_eq!(
+            emit(DebuggerKind::Lldb, &bp),
+            "breakpoint set --file 'main.rs' --line 10 --one-shot true\n"
+        );
+        assert_eq!(emit(DebuggerKind::Cdb, &bp), "bp /1 `main.rs:10`\n");
+    }
+
+    #[test]
+    fn fault_dump_command() {
+        let command = |kind| {
+            Debugger {
+                kind,
+                ..Debugger::mock()
+            }
+            .fault_dump_command(Path::new("/out/t1/gdb-12.1.fault.core"))
+        };
+
+        let gdb = command(DebuggerKind::Gdb).unwrap();
+        assert!(gdb.starts_with("python\n"));
+        assert!(gdb.contains("gdb.execute('generate-core-file ' + \"/out/t1/gdb-12.1.fault.core\""));
+        assert!(gdb.ends_with("\nend"));
+
+        assert_eq!(
+            command(DebuggerKind::Cdb)
+                .unwrap()
+                .lines()
+                .collect::<Vec<_>>(),
+            [
+                "sxe -c \".dump /ma /o \\\"/out/t1/gdb-12.1.fault.core\\\"\" av",
+                "sxe -c \".dump /ma /o \\\"/out/t1/gdb-12.1.fault.core\\\"\" sov",
+                "sxe -c \".dump /ma /o \\\"/out/t1/gdb-12.1.fault.core\\\"\" dz",
+                "sxd -c2 \".dump /ma /o \\\"/out/t1/gdb-12.1.fault.core\\\"\" ii",
+            ]
+        );
+
+        let cdb = Debugger {
+            kind: DebuggerKind::Cdb,
+            ..Debugger::mock()
+        };
+        assert_eq!(
+            cdb.fault_dump_command(Path::new(r"C:\dumps\my tests\cdb.fault.dmp"))
+                .unwrap()
+                .lines()
+                .next(),
+            Some(r#"sxe -c ".dump /ma /o \"C:\\dumps\\my tests\\cdb.fault.dmp\"" av"#)
+        );
+        assert_eq!(command(DebuggerKind::Lldb), None);
+
+        let mut debugger = Debugger {
+            kind: DebuggerKind::Gdb,
+            ..Debugger::mock()
+        };
+        debugger.disable_fault_dumps();
+        assert_eq!(
+            debugger.fault_dump_command(Path::new("/out/fault.core")),
+            None
+        );
+    }
+
+    #[test]
+    fn forced_locale() {
+        let mut debugger = Debugger {
+            kind: DebuggerKind::Gdb,
+            env_vars: vec![("LANG".to_string(), "de_DE.UTF-8".to_string())],
+            ..Debugger::mock()
+        };
+
+        // `--debugger-env` comes last, so it takes precedence
+        assert_eq!(
+            debugger.configured_env_vars(),
+            [
+                ("LANG", "C.UTF-8"),
+                ("LC_ALL", "C.UTF-8"),
+                ("LANGUAGE", ""),
+                ("LANG", "de_DE.UTF-8"),
+            ]
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+        );
+        assert_eq!(debugger.env_var("LANG").as_deref(), Some("de_DE.UTF-8"));
+        assert_eq!(debugger.env_var("LC_ALL").as_deref(), Some("C.UTF-8"));
+
+        debugger.disable_forced_locale();
+        assert_eq!(
+            debugger.configured_env_vars(),
+            [("LANG".to_string(), "de_DE.UTF-8".to_string())]
+        );
+    }
+
+    #[test]
+    fn gdb_threading_settings() {
+        let mut debugger = Debugger {
+            kind: DebuggerKind::Gdb,
+            ..Debugger::mock()
+        };
+        debugger.enable_gdb_non_stop();
+
+        let prelude = |debugger: &Debugger, test_config: &TestConfig| {
+            let mut script = String::new();
+            debugger.emit_script_prelude(test_config, &mut script);
+            script
+        };
+        let context_from = |debugger: &Debugger, test_config: &TestConfig| {
+            debugger.evaluation_context(test_config, &Arc::from("debug"), &PhaseConfig::Live)
+        };
+        let context = |debugger: &Debugger, test_config: &TestConfig| {
+            context_from(debugger, test_config).values
+        };
+
+        let default_config = TestConfig::default();
+        assert!(prelude(&debugger, &default_config).ends_with("set non-stop on\n"));
+        assert!(context(&debugger, &default_config).contains_key("@gdb_non_stop"));
+        assert_eq!(
+            context(&debugger, &default_config).get("@gdb_scheduler_locking"),
+            Some(&"replay".into())
+        );
+
+        // The test's `dbt-test.toml` takes precedence
+        let test_config = TestConfig {
+            gdb_non_stop: Some(false),
+            gdb_scheduler_locking: Some(SchedulerLocking::Step),
+            ..TestConfig::default()
+        };
+        assert!(prelude(&debugger, &test_config).ends_with("\nset scheduler-locking step\n"));
+        assert!(!prelude(&debugger, &test_config).contains("non-stop"));
+        assert!(!context(&debugger, &test_config).contains_key("@gdb_non_stop"));
+        assert_eq!(
+            context(&debugger, &test_config).get("@gdb_scheduler_locking"),
+            Some(&"step".into())
+        );
+
+        // Only GDB has these settings
+        let mut debugger = Debugger::mock();
+        debugger.enable_gdb_non_stop();
+        debugger.set_gdb_scheduler_locking(SchedulerLocking::On);
+        assert!(!prelude(&debugger, &default_config).contains("non-stop"));
+        assert_eq!(
+            context(&debugger, &default_config).get("@gdb_scheduler_locking"),
+            Some(&"none".into())
+        );
+
+        // The condition from the README can be evaluated whatever the debugger and settings
+        let script = mock_test_def(from_lines(&[
+            "/***",
+            "#if @gdb_scheduler_locking == step",
+            "  #check stepped",
+            "***/",
+        ]))
+        .script;
+        let lldb = Debugger {
+            kind: DebuggerKind::Lldb,
+            ..Debugger::mock()
+        };
+        let gdb = Debugger {
+            kind: DebuggerKind::Gdb,
+            ..Debugger::mock()
+        };
+        assert!(!script.has_active_checks(&context_from(&lldb, &default_config)));
+        assert!(!script.has_active_checks(&context_from(&gdb, &default_config)));
+        assert!(script.has_active_checks(&context_from(&gdb, &test_config)));
+    }
+
+    #[test]
+    fn debugger_output_by_correlation_id() {
+        let output = DebuggerOutput {
+            stdout: from_lines(&[
+                "startup noise",
+                "__correlation_id_begin__=0",
+                "abc",
+                "def",
+                "__correlation_id_end__=0",
+                "between blocks",
+                "__correlation_id_begin__=1",
+                "xyz",
+            ]),
+            stderr: String::new(),
+            exit_status: DebuggerExitStatus::Success,
+            program_output: None,
+        };
+
+        let by_id = super::debugger_output_by_correlation_id(&output).unwrap();
+
+        assert_eq!(
+            by_id.into_iter().collect::<Vec<_>>(),
+            vec![
+                (CorrelationId(0), vec!["abc", "def"]),
+                // Unterminated blocks are still reported
+                (CorrelationId(1), vec!["xyz"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn debugger_output_by_correlation_id_echoed_markers() {
+        let output = DebuggerOutput {
+            stdout: from_lines(&[
+                "+python print('__correlation_id_begin__=0')",
+                "__correlation_id_begin__=0",
+                "+print a",
+                "$1 = 1",
+                "+python print('__correlation_id_end__=0')",
+                "__correlation_id_end__=0",
+                "0:000> .echo __correlation_id_begin__=1",
+                "__correlation_id_begin__=1",
+                "__correlation_id_begin__=1",
+                "xyz",
+                "__correlation_id_end__=1",
+                "__correlation_id_end__=1",
+            ]),
+            stderr: String::new(),
+            exit_status: DebuggerExitStatus::Success,
+            program_output: None,
+        };
+
+        let by_id = super::debugger_output_by_correlation_id(&output).unwrap();
+
+        assert_eq!(
+            by_id.into_iter().collect::<Vec<_>>(),
+            vec![
+                (CorrelationId(0), vec!["+print a", "$1 = 1"]),
+                (CorrelationId(1), vec!["xyz"]),
+            ]
+        );
+    }
+
+    #[test]
+    fn stderr_in_failure_messages() {
+        let test_def = mock_test_def(from_lines(&[
+            "/***",
+            "print a",
+            "mock_stderr Traceback (most recent call last):",
+            "#check a = 1",
+            "print b",
+            "#check b",
+            "***/",
+        ]));
+        let debugger = Debugger::mock();
+        let cargo_profile = Arc::from("debug");
+        let script = super::generate_debugger_script(
+            &debugger,
+            &test_def,
+            &cargo_profile,
+            &PhaseConfig::Live,
+            &mut |tag| PathBuf::from(tag),
+        );
+
+        let (output, measurements) =
+            super::mock_debugger_output(&script, SessionOptions::default());
+        let stderr_by_correlation_id = super::stderr_by_correlation_id(&output, &measurements);
+
+        assert_eq!(
+            stderr_by_correlation_id.into_iter().collect::<Vec<_>>(),
+            vec![(
+                CorrelationId(0),
+                vec!["Traceback (most recent call last):".to_string()]
+            )]
+        );
+
+        let result = super::process_debugger_output(
+            &debugger,
+            &test_def,
+            output.clone(),
+            &super::stderr_by_correlation_id(&output, &measurements),
+            &cargo_profile,
+            &PhaseConfig::Live,
+        );
+
+        match *result.status {
+            Status::Failed(message, _) => assert!(
+                message.contains("stderr meanwhile:\n\n! Traceback (most recent call last):\n"),
+                "{}",
+                message
+            ),
+            status => panic!("unexpected status {:?}", status),
+        }
+    }
+
+    #[test]
+    fn matched_checks() {
+        let test_def = mock_test_def(from_lines(&[
+            "/***",
+            "print a",
+            "#check print",
+            "#check-same a",
+            "print b",
+            "#check b",
+            "***/",
+        ]));
+        let debugger = Debugger::mock();
+        let cargo_profile = Arc::from("debug");
+        let script = super::generate_debugger_script(
+            &debugger,
+            &test_def,
+            &cargo_profile,
+            &PhaseConfig::Live,
+            &mut |tag| PathBuf::from(tag),
+        );
+
+        let result = super::process_debugger_output(
+            &debugger,
+            &test_def,
+            super::mock_debugger_output(&script, SessionOptions::default()).0,
+            &BTreeMap::new(),
+            &cargo_profile,
+            &PhaseConfig::Live,
+        );
+
+        assert_eq!(*result.status, Status::Passed);
+        assert_eq!(
+            result
+                .matched_checks
+                .iter()
+                .map(|m| (m.check_line.0, m.output_line, &m.output[..]))
+                .collect::<Vec<_>>(),
+            vec![(3, 2, "print a"), (4, 2, "print a"), (6, 5, "print b")]
+        );
+    }
+
+    #[test]
+    fn matched_checks_in_failure_messages() {
+        match mock_run(
+            &[
+                "/***",
+                "print a",
+                "mock_echo a = 1",
+                "mock_echo b = 2",
+                "#check a = 1",
+                "#check-not-between c",
+                "#check b = 3",
+                "***/",
+            ],
+            None,
+        ) {
+            Status::Failed(message, _) => assert!(
+                message.contains(
+                    "The checks before it in the same block matched:\n\n  \
+                     'a = 1' (line 5) at line 3 of stdout: a = 1\n\n"
+                ),
+                "{}",
+                message
+            ),
+            status => panic!("unexpected status {:?}", status),
+        }
+    }
+
+    #[test]
+    fn debugger_output_by_correlation_id_malformed() {
+        let output = |lines: &[&str]| DebuggerOutput {
+            stdout: from_lines(lines),
+            stderr: String::new(),
+            exit_status: DebuggerExitStatus::Success,
+            program_output: None,
+        };
+
+        assert!(super::debugger_output_by_correlation_id(&output(&[
+            "__correlation_id_begin__=0",
+            "__correlation_id_begin__=1",
+        ]))
+        .is_err());
+
+        assert!(super::debugger_output_by_correlation_id(&output(&[
+            "abc",
+            "__correlation_id_end__=0",
+        ]))
+        .is_err());
+    }
+
+    /// Runs `script_lines` through the mock debugger and returns the resulting status.
+    fn mock_run(script_lines: &[&str], timeout: Option<Duration>) -> Status {
+        mock_run_with_session(script_lines, session(timeout))
+    }
+
+    fn mock_run_with_session(script_lines: &[&str], session: SessionOptions) -> Status {
+        let test_def = mock_test_def(from_lines(script_lines));
+        let debugger = Debugger::mock();
+        let cargo_profile = Arc::from("debug");
+        let script = super::generate_debugger_script(
+            &debugger,
+            &test_def,
+            &cargo_profile,
+            &PhaseConfig::Live,
+            &mut |tag| PathBuf::from(tag),
+        );
+
+        let result = super::process_debugger_output(
+            &debugger,
+            &test_def,
+            super::mock_debugger_output(&script, session).0,
+            &BTreeMap::new(),
+            &cargo_profile,
+            &PhaseConfig::Live,
+        );
+
+        *result.status
+    }
+
+    #[test]
+    fn mock_fault_injection() {
+        let output = super::mock_debugger_output(
+            &from_lines(&[
+                "print a",
+                "mock_echo a = 1",
+                "mock_stderr oops",
+                "mock_exit 3",
+                "x",
+            ]),
+            SessionOptions::default(),
+        )
+        .0;
+
+        assert_eq!(
+            output,
+            DebuggerOutput {
+                stdout: from_lines(&["print a", "a = 1"]),
+                stderr: from_lines(&["oops"]),
+                exit_status: DebuggerExitStatus::Failure,
+                program_output: None,
+            }
+        );
+
+        assert_eq!(
+            super::mock_debugger_output(
+                &from_lines(&["a", "mock_exit 0", "b"]),
+                SessionOptions::default()
+            )
+            .0,
+            DebuggerOutput {
+                stdout: from_lines(&["a"]),
+                stderr: String::new(),
+                exit_status: DebuggerExitStatus::Success,
+                program_output: None,
+            }
+        );
+
+        assert_eq!(
+            super::mock_debugger_output(
+                &from_lines(&["a", "mock_sleep 10000", "b"]),
+                session(Some(Duration::from_millis(10)))
+            )
+            .0,
+            DebuggerOutput {
+                stdout: from_lines(&["a"]),
+                stderr: String::new(),
+                exit_status: DebuggerExitStatus::TimedOut(Hang::Unknown),
+                program_output: None,
+            }
+        );
+    }
+
+    #[test]
+    fn command_timings() {
+        let test_def = mock_test_def(from_lines(&[
+            "/***",
+            "print a",
+            "mock_sleep 60",
+            "#check print a",
+            "#thread 2",
+            "#check thread",
+            "***/",
+        ]));
+
+        let mut debugger = Debugger::mock();
+        debugger.enable_command_fences();
+        let cargo_profile = Arc::from("debug");
+        let script = super::generate_debugger_script(
+            &debugger,
+            &test_def,
+            &cargo_profile,
+            &PhaseConfig::Live,
+            &mut |tag| PathBuf::from(tag),
+        );
+
+        let (output, measurements) =
+            super::mock_debugger_output(&script, SessionOptions::default());
+
+        let timings = super::command_timings(
+            &debugger,
+            &test_def,
+            &cargo_profile,
+            &PhaseConfig::Live,
+            &output.stdout,
+            &measurements.stdout_timestamps,
+        );
+
+        let commands: Vec<_> = timings.iter().map(|t| &t.command[..]).collect();
+        assert_eq!(commands, ["print a", "mock_sleep 60", "select_thread 2"]);
+        assert_eq!(timings[0].line_number.0, 2);
+        assert!(timings[0].duration < Duration::from_millis(60));
+        assert_eq!(timings[1].line_number.0, 3);
+        assert!(timings[1].duration >= Duration::from_millis(60));
+        assert!(timings[2].duration < Duration::from_millis(60));
+
+        // The fences don't end up in the output the checks match
+        assert_eq!(
+            process_stdout(&debugger, &test_def, output.stdout),
+            Status::Passed
+        );
+    }
+
+    #[test]
+    fn mock_faults_in_test_results() {
+        // Truncated output is reported as a regular check failure
+        match mock_run(
+            &[
+                "/***",
+                "print a",
+                "mock_truncate",
+                "mock_echo a = 1",
+                "#check a = 1",
+                "***/",
+            ],
+            None,
+        ) {
+            Status::Failed(message, output) => {
+                assert!(message.starts_with("Could not find 'a = 1'"), "{}", message);
+                assert_eq!(output.exit_status, DebuggerExitStatus::Failure);
+            }
+            status => panic!("unexpected status {:?}", status),
+        }
+
+        match mock_run(
+            &[
+                "/***",
+                "mock_echo __correlation_id_end__=7",
+                "#check mock",
+                "***/",
+            ],
+            None,
+        ) {
+            Status::Errored(message) => {
+                assert!(message.contains("unopened correlation ID end marker"))
+            }
+            status => panic!("unexpected status {:?}", status),
+        }
+
+        assert!(matches!(
+            mock_run(
+                &["/***", "mock_sleep 10000", "#check mock", "***/"],
+                Some(Duration::from_millis(10))
+            ),
+            Status::TimedOut(..)
+        ));
+
+        match mock_run(
+            &[
+                "/***",
+                "print a",
+                "mock_signal 11",
+                "#check print a",
+                "***/",
+            ],
+            None,
+        ) {
+            Status::DebuggerCrashed(crash, output) => {
+                assert_eq!(crash, DebuggerCrash::Signal(11));
+                assert_eq!(crash.to_string(), "killed by signal 11 (SIGSEGV)");
+                assert!(output.stdout.contains("print a"));
+            }
+            status => panic!("unexpected status {:?}", status),
+        }
+
+        // A delay within the time limit is fine
+        assert_eq!(
+            mock_run(
+                &["/***", "mock_sleep 1", "print a", "#check print a", "***/"],
+                Some(Duration::from_secs(60))
+            ),
+            Status::Passed
+        );
+    }
+
+    #[test]
+    fn check_program_output() {
+        let test_def = mock_test_def(from_lines(&[
+            "/***",
+            "run",
+            "#check-program-output one",
+            "#check-program-output three",
+            "***/",
+        ]));
+        let debugger = Debugger::mock();
+        let cargo_profile = Arc::from("debug");
+
+        let status = |program_output: Option<&str>| {
+            let script = super::generate_debugger_script(
+                &debugger,
+                &test_def,
+                &cargo_profile,
+                &PhaseConfig::Live,
+                &mut |tag| PathBuf::from(tag),
+            );
+
+            let mut output = super::mock_debugger_output(&script, SessionOptions::default()).0;
+            output.program_output = program_output.map(str::to_string);
+
+            *super::process_debugger_output(
+                &debugger,
+                &test_def,
+                output,
+                &BTreeMap::new(),
+                &cargo_profile,
+                &PhaseConfig::Live,
+            )
+            .status
+        };
+
+        assert_eq!(status(Some("one\ntwo\nthree\n")), Status::Passed);
+
+        match status(Some("three\ntwo\none\n")) {
+            Status::Failed(message, _) => assert!(
+                message.starts_with("Could not find 'three' in the output of the debuggee"),
+                "{}",
+                message
+            ),
+            status => panic!("unexpected status {:?}", status),
+        }
+
+        match status(None) {
+            Status::Errored(message) => assert!(message.contains("not supported with mock")),
+            status => panic!("unexpected status {:?}", status),
+        }
+
+        let gdb = Debugger {
+            kind: DebuggerKind::Gdb,
+            ..Debugger::mock()
+        };
+        assert_eq!(
+            gdb.program_output_redirect(Path::new("/out/it's"), &["a b".to_string()])
+                .unwrap(),
+            "set args 'a b' > '/out/it'\\''s'"
+        );
+    }
+
+    #[test]
+    fn command_timeout() {
+        let session = SessionOptions {
+            command_timeout: Some(Duration::from_millis(100)),
+            ..SessionOptions::default()
+        };
+
+        match mock_run_with_session(
+            &[
+                "/***",
+                "print a",
+                "#check print a",
+                "print b",
+                "mock_sleep 10000",
+                "#check print b",
+                "***/",
+            ],
+            session,
+        ) {
+            Status::TimedOut(message) => assert_eq!(
+                message,
+                "The debugger was killed because it did not finish in time. It was still \
+                 running `print b` at line 4.\n"
+            ),
+            status => panic!("unexpected status {:?}", status),
+        }
+
+        match mock_run(
+            &[
+                "/***",
+                "print a",
+                "mock_sleep 10000 debuggee-running",
+                "#check print a",
+                "***/",
+            ],
+            Some(Duration::from_millis(10)),
+        ) {
+            Status::TimedOut(message) => assert!(
+                message.ends_with(
+                    "The debuggee was still running, so it probably never got to \
+                                   the next breakpoint, e.g. because it is stuck in a loop or \
+                                   waiting for input.\n"
+                ),
+                "{}",
+                message
+            ),
+            status => panic!("unexpected status {:?}", status),
+        }
+
+        // Each block gets the full time
+        assert_eq!(
+            mock_run_with_session(
+                &[
+                    "/***",
+                    "print a",
+                    "mock_sleep 60",
+                    "#check print a",
+                    "print b",
+                    "mock_sleep 60",
+                    "#check print b",
+                    "***/",
+                ],
+                session
+            ),
+            Status::Passed
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn run_with_timeout() {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("echo started; sleep 10");
+
+        let (output, _) = super::run_with_timeout(
+            command,
+            Path::new("/nonexistent"),
+            session(Some(Duration::from_millis(200))),
+        )
+        .unwrap();
+        assert_eq!(output.stdout, "started\n");
+        assert!(matches!(
+            output.exit_status,
+            DebuggerExitStatus::TimedOut(_)
+        ));
+
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("echo done; exit 2");
+
+        let (output, _) = super::run_with_timeout(
+            command,
+            Path::new("/nonexistent"),
+            session(Some(Duration::from_secs(60))),
+        )
+        .unwrap();
+        assert_eq!(output.stdout, "done\n");
+        assert_eq!(output.exit_status, DebuggerExitStatus::Failure);
+
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("echo crashing; kill -SEGV $$");
+
+        let (output, _) = super::run_with_timeout(
+            command,
+            Path::new("/nonexistent"),
+            session(Some(Duration::from_secs(60))),
+        )
+        .unwrap();
+        assert_eq!(output.stdout, "crashing\n");
+        assert_eq!(
+            output.exit_status,
+            DebuggerExitStatus::Crashed(DebuggerCrash::Signal(libc::SIGSEGV))
+        );
+
+        let mut command = Command::new("sh");
+        command
+            .arg("-c")
+            .arg("echo __correlation_id_begin__=3; sleep 10");
+        let session = SessionOptions {
+            command_timeout: Some(Duration::from_millis(200)),
+            ..SessionOptions::default()
+        };
+
+        let (output, _) =
+            super::run_with_timeout(command, Path::new("/nonexistent"), session).unwrap();
+        assert!(matches!(
+            output.exit_status,
+            DebuggerExitStatus::TimedOut(_)
+        ));
+        assert_eq!(
+            super::unfinished_block(&output.stdout),
+            Some(CorrelationId(3))
+        );
+
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("exit 0");
+
+        let (output, measurements) = super::run_with_timeout(
+            command,
+            Path::new("/nonexistent"),
+            SessionOptions::default(),
+        )
+        .unwrap();
+        assert_eq!(output.exit_status, DebuggerExitStatus::Success);
+        assert!(measurements.resource_usage.unwrap().peak_memory_bytes > 0);
+    }
+
+    #[test]
+    fn check_filter() {
+        let source = from_lines(&[
+            "/***",
+            "print abc",
+            "#check abc",
+            "#check not in output  // the second check",
+            "***/",
+        ]);
+        let test_def = mock_test_def(source);
+
+        let status = |check_filter: Option<&str>| {
+            let mut debugger = Debugger::mock();
+
+            if let Some(check_filter) = check_filter {
+                debugger.set_check_filter(check_filter.into());
+            }
+
+            process_stdout(&debugger, &test_def, live_script(&debugger, &test_def))
+        };
+
+        assert!(matches!(status(None), Status::Failed(..)));
+        assert_eq!(status(Some("#check abc")), Status::Passed);
+        assert_eq!(
+            status(Some("xyz")),
+            Status::Skipped("no check matches `--check-filter xyz`".to_string())
+        );
+        // Comments aren't part of a check
+        assert!(matches!(status(Some("second check")), Status::Skipped(..)));
+
+        let mut debugger = Debugger::mock();
+        let profile = Arc::from("debug");
+
+        debugger.set_check_filter("not in".into());
+        assert_eq!(
+            debugger.skipped_by_check_filter(&test_def, &profile, &PhaseConfig::Live),
+            None
+        );
+
+        debugger.set_check_filter("xyz".into());
+        assert_eq!(
+            debugger.skipped_by_check_filter(&test_def, &profile, &PhaseConfig::Live),
+            Some("no check matches `--check-filter xyz`".to_string())
+        );
+    }
+
+    #[test]
+    fn group_label_in_failure_message() {
+        let test_def = mock_test_def(from_lines(&[
+            "/***",
+            "#group \"Vec header\"",
+            "  print abc",
+            "  #check not in output",
+            "***/",
+        ]));
+
+        match mock_status(&test_def) {
+            Status::Failed(ref message, _) => {
+                assert!(message.starts_with("[Vec header] Could not find 'not in output'"));
+                assert!(message.contains("main.rs:4 (group \"Vec header\")"));
+            }
+            ref status => panic!("unexpected status {:?}", status),
+        }
+    }
+
+    #[test]
+    fn check_not_between() {
+        let run = |commands: &[&str]| {
+            let mut lines = vec!["/***"];
+            lines.extend_from_slice(commands);
+            lines.extend_from_slice(&["#check start", "#check-not-between noise", "#check end"]);
+            lines.push("***/");
+
+            mock_status(&mock_test_def(from_lines(&lines)))
+        };
+
+        assert_eq!(run(&["print start", "print end"]), Status::Passed);
+        assert_eq!(
+            run(&["print noise", "print start", "print end", "print noise"]),
+            Status::Passed
+        );
+
+        match run(&["print start", "print noise", "print end"]) {
+            Status::Failed(ref message, _) => {
+                assert!(message.starts_with("Found 'noise' in debugger output"));
+                assert!(message.contains("> print noise"));
+                assert!(message.contains("main.rs:6"));
+            }
+            ref status => panic!("unexpected status {:?}", status),
+        }
+    }
+
+    #[test]
+    fn check_same() {
+        let test_def = mock_test_def(from_lines(&[
+            "/***",
+            "print x: u32 = 1",
+            "print y: u64 = 2",
+            "#check y",
+            "#check-same u64",
+            "#check-same = 2",
+            "***/",
+        ]));
+
+        assert_eq!(mock_status(&test_def), Status::Passed);
+
+        // `u32` and `y` are both in the output, but not on the same line
+        let test_def = mock_test_def(from_lines(&[
+            "/***",
+            "print x: u32 = 1",
+            "print y: u64 = 2",
+            "#check y",
+            "#check-same u32",
+            "***/",
+        ]));
+
+        match mock_status(&test_def) {
+            Status::Failed(ref message, _) => {
+                assert!(message.starts_with("Could not find 'y' and 'u32' in debugger output"));
+            }
+            ref status => panic!("unexpected status {:?}", status),
+        }
+    }
+
+    #[test]
+    fn thread_directives() {
+        let test_def = mock_test_def(from_lines(&[
+            "/***",
+            "#thread 2",
+            "print x",
+            "#check x",
+            "#check-thread-count 1",
+            "***/",
+        ]));
+
+        let debugger = Debugger::mock();
+        let script = live_script(&debugger, &test_def);
+
+        assert_eq!(
+            script,
+            from_lines(&[
+                "__correlation_id_begin__=0",
+                "select_thread 2",
+                "print x",
+                "__correlation_id_end__=0",
+                "__correlation_id_begin__=1",
+                "list_threads",
+                "__correlation_id_end__=1",
+            ])
+        );
+
+        assert_eq!(process_stdout(&debugger, &test_def, script), Status::Passed);
+    }
+
+    #[test]
+    fn emit_frame_commands() {
+        let emit = |kind, command| {
+            let debugger = Debugger {
+                kind,
+                ..Debugger::mock()
+            };
+
+            let mut output = String::new();
+            debugger.emit_command(&command, &mut output);
+            output
+        };
+
+        assert_eq!(
+            emit(DebuggerKind::Gdb, DebuggerCommand::SelectFrame(1)),
+            "frame 1\n"
+        );
+        assert_eq!(
+            emit(DebuggerKind::Cdb, DebuggerCommand::SelectFrame(1)),
+            ".frame 1\n"
+        );
+        assert_eq!(
+            emit(DebuggerKind::Lldb, DebuggerCommand::SelectFrame(1)),
+            "frame select 1\n"
+        );
+        assert_eq!(emit(DebuggerKind::Gdb, DebuggerCommand::FrameUp), "up\n");
+        assert_eq!(emit(DebuggerKind::Cdb, DebuggerCommand::FrameUp), ".f+\n");
+        assert_eq!(emit(DebuggerKind::Cdb, DebuggerCommand::FrameDown), ".f-\n");
+        assert_eq!(
+            emit(DebuggerKind::Cdb, DebuggerCommand::ContinueToExit),
+            "g\n.lastevent\n"
+        );
+    }
+
+    #[test]
+    fn emit_reverse_commands() {
+        let emit = |kind, command| {
+            let debugger = Debugger {
+                kind,
+                ..Debugger::mock()
+            };
+
+            let mut output = String::new();
+            debugger.emit_command(&command, &mut output);
+            output
+        };
+
+        assert_eq!(
+            emit(DebuggerKind::Cdb, DebuggerCommand::ReverseStep),
+            "p-\n"
+        );
+        assert_eq!(
+            emit(DebuggerKind::Cdb, DebuggerCommand::ReverseStepInto),
+            "t-\n"
+        );
+        assert_eq!(
+            emit(DebuggerKind::Cdb, DebuggerCommand::ReverseContinue),
+            "g-\n"
+        );
+        assert_eq!(
+            emit(DebuggerKind::Gdb, DebuggerCommand::ReverseStep),
+            "reverse-next\n"
+        );
+        assert_eq!(
+            emit(DebuggerKind::Lldb, DebuggerCommand::ReverseContinue),
+            "process continue --reverse\n"
+        );
+        assert_eq!(
+            emit(DebuggerKind::Cdb, DebuggerCommand::ReverseFinish),
+            "g-u\n"
+        );
+        assert_eq!(
+            emit(DebuggerKind::Gdb, DebuggerCommand::ReverseFinish),
+            "reverse-finish\n"
+        );
+
+        // LLDB can't step backwards, so the tests are skipped
+        let test_def = mock_test_def(from_lines(&[
+            "/***",
+            "run",
+            "#if @lldb || @gdb",
+            "  #reverse-step-into",
+            "#check x",
+            "***/",
+        ]));
+        let unsupported = |kind| {
+            Debugger {
+                kind,
+                ..Debugger::mock()
+            }
+            .unsupported_directive(&test_def, &Arc::from("debug"), &PhaseConfig::Live)
+        };
+        assert_eq!(unsupported(DebuggerKind::Lldb), Some("#reverse-step-into"));
+        assert_eq!(unsupported(DebuggerKind::Gdb), None);
+        assert_eq!(unsupported(DebuggerKind::Cdb), None);
+    }
+
+    #[test]
+    fn emit_watchpoints() {
+        let emit = |kind, access, width| {
+            let debugger = Debugger {
+                kind,
+                ..Debugger::mock()
+            };
+
+            let mut output = String::new();
+            debugger.emit_command(
+                &DebuggerCommand::Watch(Watchpoint {
+                    access,
+                    width,
+                    place: "state.counter".into(),
+                }),
+                &mut output,
+            );
+            output
+        };
+
+        assert_eq!(
+            emit(DebuggerKind::Gdb, WatchAccess::Read, Some(4)),
+            "rwatch -location *(&(state.counter) as *const u32)\n"
+        );
+        assert_eq!(
+            emit(DebuggerKind::Gdb, WatchAccess::Write, None),
+            "watch -location state.counter\n"
+        );
+        assert_eq!(
+            emit(DebuggerKind::Cdb, WatchAccess::Read, Some(4)),
+            "ba r4 @@c++(&state.counter)\n"
+        );
+        assert_eq!(
+            emit(DebuggerKind::Cdb, WatchAccess::Write, None),
+            "ba w4 @@c++(&state.counter)\n"
+        );
+        assert_eq!(
+            emit(DebuggerKind::Lldb, WatchAccess::Access, Some(8)),
+            "watchpoint set expression -w read_write -s 8 -- &(state.counter)\n"
+        );
+        assert_eq!(
+            emit(DebuggerKind::Mock, WatchAccess::Access, Some(2)),
+            "watch access 2 state.counter\n"
+        );
+        assert_eq!(
+            emit(DebuggerKind::Mock, WatchAccess::Write, None),
+            "watch write state.counter\n"
+        );
+    }
+
+    #[test]
+    fn emit_multi_process_commands() {
+        let emit = |kind, command| {
+            let debugger = Debugger {
+                kind,
+                ..Debugger::mock()
+            };
+
+            let mut output = String::new();
+            debugger.emit_command(&command, &mut output);
+            output
+        };
+
+        let follow_child = DebuggerCommand::FollowFork(ForkMode::Child);
+
+        assert_eq!(
+            emit(DebuggerKind::Gdb, follow_child.clone()),
+            "set follow-fork-mode child\nset detach-on-fork off\n"
+        );
+        assert_eq!(
+            emit(DebuggerKind::Cdb, follow_child.clone()),
+            ".childdbg 1\n"
+        );
+        assert_eq!(
+            emit(DebuggerKind::Lldb, follow_child),
+            "settings set target.process.follow-fork-mode child\n"
+        );
+        assert_eq!(
+            emit(DebuggerKind::Gdb, DebuggerCommand::SelectInferior(2)),
+            "inferior 2\n"
+        );
+        assert_eq!(
+            emit(DebuggerKind::Cdb, DebuggerCommand::SelectInferior(2)),
+            "|1s\n"
+        );
+    }
+
+    #[test]
+    fn register_and_memory_output() {
+        let with_kind = |kind| Debugger {
+            kind,
+            ..Debugger::mock()
+        };
+
+        let gdb = with_kind(DebuggerKind::Gdb);
+        let lldb = with_kind(DebuggerKind::Lldb);
+        let cdb = with_kind(DebuggerKind::Cdb);
+
+        assert_eq!(
+            gdb.register_value("rax", &["rax            0x2a                42"]),
+            Some(42)
+        );
+        assert_eq!(
+            lldb.register_value("rax", &["     rax = 0x000000000000002a"]),
+            Some(42)
+        );
+        assert_eq!(
+            cdb.register_value("rax", &["rax=000000000000002a"]),
+            Some(42)
+        );
+        assert_eq!(gdb.register_value("rbx", &["rax 0x2a 42"]), None);
+        assert_eq!(
+            cdb.register_value("RBX", &["rax=0000000000000001 rbx=00000000`0000002a"]),
+            Some(42)
+        );
+
+        assert_eq!(
+            gdb.memory_bytes(
+                &[
+                    "0x555555558010 <main::FOO>:\t0x01\t0x02\t0x03\t0x04\t0x05\t0x06\t0x07\t0x08",
+                    "0x555555558018 <main::FOO+8>:\t0x09\t0x0a",
+                ],
+                10
+            ),
+            vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10]
+        );
+        assert_eq!(
+            lldb.memory_bytes(&["0x7fffffffe3bc: 0x01 0x00 0x00 0x00"], 4),
+            vec![1, 0, 0, 0]
+        );
+        let cdb_output = [
+            "00000074`5f9cf6a0  61 62 00 00-02 00 00 00-00 00 00 00-00 00 00 00  ab..............",
+            "00000074`5f9cf6b0  ff                                               .",
+        ];
+
+        assert_eq!(
+            cdb.memory_bytes(&cdb_output, 17),
+            vec![0x61, 0x62, 0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0xff]
+        );
+
+        let check = CommandCheck::Memory("&x".into(), vec![Some(1), None, Some(0)]);
+        assert!(lldb
+            .evaluate_command_check(&check, &["0x7fffffffe3bc: 0x01 0x7f 0x00"])
+            .is_ok());
+        assert_eq!(
+            lldb.evaluate_command_check(&check, &["0x7fffffffe3bc: 0x01 0x7f 0x01"]),
+            Err("Expected memory at `&x` to be `01 ?? 00`, found `01 7f 01`".to_string())
+        );
+    }
+
+    #[test]
+    fn check_locals() {
+        let debugger = |kind| Debugger {
+            kind,
+            ..Debugger::mock()
+        };
+        let expected = |locals: &[(&str, Option<&str>)]| {
+            locals
+                .iter()
+                .map(|&(name, value)| ExpectedLocal {
+                    name: name.into(),
+                    value: value.map(|value| RegexCheck::new(value).unwrap()),
+                })
+                .collect::<Vec<_>>()
+        };
+
+        let gdb_output = ["v = Vec(size=2) = {1, 2}", "len = 2", "len = 7"];
+        let lldb_output = [
+            "(alloc::vec::Vec<i32, alloc::alloc::Global>) v = size=2 {",
+            "  [0] = 1",
+            "  [1] = 2",
+            "}",
+            "(usize) len = 2",
+        ];
+        let cdb_output = ["              v = { len=0x2 }", "            len = 0n2"];
+
+        for (kind, output) in [
+            (DebuggerKind::Gdb, &gdb_output[..]),
+            (DebuggerKind::Lldb, &lldb_output[..]),
+            (DebuggerKind::Cdb, &cdb_output[..]),
+        ] {
+            let check = |locals: &[(&str, Option<&str>)], exact| {
+                debugger(kind)
+                    .evaluate_command_check(&CommandCheck::Locals(expected(locals), exact), output)
+            };
+
+            assert!(check(&[("len", Some("2"))], false).is_ok());
+            assert!(check(&[("len", None), ("v", None)], true).is_ok());
+            assert_eq!(
+                check(&[("x", None)], false),
+                Err("Expected a local variable `x`, found only: v, len".to_string())
+            );
+            assert_eq!(
+                check(&[("v", None)], true),
+                Err("Found unexpected local variables: len".to_string())
+            );
+        }
+
+        assert_eq!(
+            debugger(DebuggerKind::Gdb).evaluate_command_check(
+                &CommandCheck::Locals(expected(&[("len", Some("7"))]), false),
+                &gdb_output
+            ),
+            Err("Expected local variable `len` to match '7', found `2`".to_string())
+        );
+    }
+
+    #[test]
+    fn check_string() {
+        let debugger = |kind| Debugger {
+            kind,
+            ..Debugger::mock()
+        };
+        let check = |kind, output: &[&str], expected: &str| {
+            debugger(kind)
+                .evaluate_command_check(&CommandCheck::Str("s".into(), expected.into()), output)
+        };
 
-        for line in lines {
-            output.push_str(line);
-            output.push('\n');
+        let gdb_output = [r#"$1 = "say \"hi\"\n""#];
+        let lldb_output = [
+            r#"(alloc::string::String) $0 = "say \"hi\"\n" {"#,
+            "  vec = size=9 {",
+            "}",
+        ];
+        let cdb_output = [
+            r#"s                : "say \"hi\"\n" [Type: alloc::string::String]"#,
+            "    [len]            : 0x9 [Type: unsigned __int64]",
+        ];
+
+        for (kind, output) in [
+            (DebuggerKind::Gdb, &gdb_output[..]),
+            (DebuggerKind::Lldb, &lldb_output[..]),
+            (DebuggerKind::Cdb, &cdb_output[..]),
+        ] {
+            assert!(check(kind, output, "say \"hi\"\n").is_ok());
+            assert_eq!(
+                check(kind, output, "hi"),
+                Err(r#"Expected `s` to be "hi", found "say \"hi\"\n""#.to_string())
+            );
         }
-        output
-    }
 
-    fn mock_test_def(script: String) -> TestDefinition {
-        let script = parse_script(&script, None).unwrap();
+        assert_eq!(
+            check(DebuggerKind::Gdb, &["$1 = 0x0"], ""),
+            Err("Could not find the contents of `s` in the debugger output".to_string())
+        );
+        assert!(check(DebuggerKind::Cdb, &[r#"s : u8"a" [Type: str]"#], "a").is_ok());
+    }
 
-        let root_path = if cfg!(windows) {
-            Path::new("D:\\mock")
-        } else {
-            Path::new("/mock")
+    #[test]
+    fn check_variant() {
+        let debugger = |kind| Debugger {
+            kind,
+            ..Debugger::mock()
+        };
+        let check = |kind, output: &[&str], variant: &str, payload: Option<&str>| {
+            debugger(kind).evaluate_command_check(
+                &CommandCheck::Variant("opt".into(), variant.into(), payload.map(Arc::from)),
+                output,
+            )
         };
 
-        let relative_path = Path::new("src").join("main.rs");
+        let gdb_output = ["$1 = core::option::Option<i32>::Some(42)"];
+        let lldb_output = [
+            "(core::option::Option<i32>) $0 = Some(42) {",
+            "  __0 = 42",
+            "}",
+        ];
+        let cdb_output = [
+            "opt                 : Some [Type: enum2$<core::option::Option<i32> >]",
+            "    [+0x004] __0              : 42 [Type: int]",
+        ];
+
+        for (kind, output) in [
+            (DebuggerKind::Gdb, &gdb_output[..]),
+            (DebuggerKind::Lldb, &lldb_output[..]),
+            (DebuggerKind::Cdb, &cdb_output[..]),
+        ] {
+            assert!(check(kind, output, "Option::Some", Some("42")).is_ok());
+            assert!(check(kind, output, "Some", None).is_ok());
+            assert!(check(kind, output, "None", None)
+                .unwrap_err()
+                .starts_with("Expected `opt` to be `None`, found"));
+            assert!(check(kind, output, "Some", Some("7"))
+                .unwrap_err()
+                .ends_with("with a different payload"));
+        }
 
-        TestDefinition::new(
-            relative_path.as_path(),
-            root_path.join(relative_path.as_path()).as_path(),
-            "project",
-            "main".into(),
-            script,
-            vec![],
+        assert!(check(
+            DebuggerKind::Gdb,
+            &["$1 = my::Shape::Rect{w: 1, h: 2}"],
+            "Rect",
+            Some("w: 1, h: 2")
         )
+        .is_ok());
     }
 
     #[test]
-    fn generate_debugger_script() {
-        let test_def = mock_test_def(from_lines(&[
-            "/***",
-            "#if @mock",
-            "  print abc",
-            "  #check __abc__",
-            "  #generate-crashdump foo",
-            "  print xyz",
-            "  #check __xyz__",
-            "***/",
-        ]));
+    fn check_type_layout() {
+        let debugger = |kind| Debugger {
+            kind,
+            ..Debugger::mock()
+        };
+        let check = |kind, output: &[&str], size, fields: &[(&str, Option<u64>, Option<u64>)]| {
+            debugger(kind).evaluate_command_check(
+                &CommandCheck::TypeLayout(TypeLayout {
+                    type_name: "Outer".into(),
+                    size,
+                    fields: fields
+                        .iter()
+                        .map(|&(name, offset, size)| FieldLayout {
+                            name: name.into(),
+                            offset,
+                            size,
+                        })
+                        .collect(),
+                }),
+                output,
+            )
+        };
 
-        let script = super::generate_debugger_script(
-            &Debugger::mock(),
-            &test_def,
-            &Arc::from("debug"),
-            &PhaseConfig::Live,
-            &mut |tag| PathBuf::from(format!("base-dir/{}/crashdump.dmp", tag)),
-        );
+        let gdb_output = [
+            "/* offset      |    size */  type = struct Outer {",
+            "/*      0      |       8 */    inner: struct Inner {",
+            "/*      0      |       4 */        a: i32,",
+            "/*      4      |       4 */        b: i32,",
+            "",
+            "                                   /* total size (bytes):    8 */",
+            "                               },",
+            "/*      8      |       1 */    pub(crate) flag: bool,",
+            "/* XXX  7-byte padding     */",
+            "",
+            "                               /* total size (bytes):   16 */",
+            "                             }",
+        ];
+        let lldb_output = ["struct Outer {", "    Inner inner;", "    bool flag;", "}"];
+        let cdb_output = [
+            "test!Outer",
+            "struct Outer, 2 elements, 0x10 bytes",
+            "   +0x000 inner            : Inner",
+            "   +0x008 flag             : Bool",
+        ];
+
+        assert!(check(
+            DebuggerKind::Gdb,
+            &gdb_output,
+            Some(16),
+            &[("inner", Some(0), Some(8)), ("flag", Some(8), Some(1))]
+        )
+        .is_ok());
+        assert!(check(
+            DebuggerKind::Cdb,
+            &cdb_output,
+            Some(16),
+            &[("flag", Some(8), None)]
+        )
+        .is_ok());
+        assert!(check(
+            DebuggerKind::Lldb,
+            &lldb_output,
+            None,
+            &[("inner", None, None)]
+        )
+        .is_ok());
 
         assert_eq!(
-            script,
-            from_lines(&[
-                "__correlation_id_begin__=0",
-                "print abc",
-                "__correlation_id_end__=0",
-                "__correlation_id_begin__=1",
-                "generate_crashdump base-dir/foo/crashdump.dmp",
-                "__correlation_id_end__=1",
-                "__correlation_id_begin__=2",
-                "print xyz",
-                "__correlation_id_end__=2",
-            ])
+            check(DebuggerKind::Gdb, &gdb_output, None, &[("a", None, None)]),
+            Err("Expected `Outer` to have a field `a`, found: inner, flag".to_string())
+        );
+        assert_eq!(
+            check(DebuggerKind::Gdb, &gdb_output, Some(12), &[]),
+            Err("Expected `Outer` to have size 12, found 16".to_string())
+        );
+        assert_eq!(
+            check(
+                DebuggerKind::Cdb,
+                &cdb_output,
+                None,
+                &[("flag", Some(4), None)]
+            ),
+            Err("Expected field `flag` of `Outer` to have offset 4, found 8".to_string())
+        );
+        assert_eq!(
+            check(DebuggerKind::Lldb, &lldb_output, Some(16), &[]),
+            Err("Could not find the size of `Outer` in the debugger output".to_string())
         );
     }
 
     #[test]
-    fn correlation_id_assignment_simple() {
-        let mut script = mock_test_def(from_lines(&[
-            "/***",
-            "foo",
-            "#check foo",
-            "bar",
-            "#check bar",
-            "baz",
-            "#check baz",
-            "***/",
-        ]))
-        .script;
+    fn check_clean_exit() {
+        let debugger = |kind| Debugger {
+            kind,
+            ..Debugger::mock()
+        };
+        let check = |kind, output: &[&str]| {
+            debugger(kind).evaluate_command_check(&CommandCheck::CleanExit, output)
+        };
 
-        Debugger::mock().assign_correlation_ids(
-            &mut script,
-            &Arc::from("debug"),
-            &PhaseConfig::Live,
+        assert!(check(
+            DebuggerKind::Gdb,
+            &["Continuing.", "[Inferior 1 (process 42) exited normally]"]
+        )
+        .is_ok());
+        assert!(check(
+            DebuggerKind::Lldb,
+            &[
+                "Process 42 resuming",
+                "Process 42 exited with status = 0 (0x00000000) "
+            ]
+        )
+        .is_ok());
+        assert!(check(
+            DebuggerKind::Cdb,
+            &[
+                "ntdll!NtTerminateProcess+0x14:",
+                "Last event: 1a2c.3b4: Exit process 0:1a2c, code 0"
+            ]
+        )
+        .is_ok());
+        assert!(check(
+            DebuggerKind::Cdb,
+            &[
+                "ntdll!NtTerminateProcess+0x14:",
+                "Last event: 1a2c.3b4: Exit process 0:1a2c, code 65"
+            ]
+        )
+        .unwrap_err()
+        .contains("exited with an error"));
+        assert!(
+            check(DebuggerKind::Cdb, &["ntdll!NtTerminateProcess+0x14:"])
+                .unwrap_err()
+                .contains("doesn't show it exiting")
         );
+        assert!(check(DebuggerKind::Mock, &["continue"]).is_ok());
 
         assert_eq!(
-            script.statements,
-            vec![
-                Statement::Exec("foo".into(), Some(CorrelationId(0)), LineNumber::ANY),
-                Statement::Check("foo".into(), Some(CorrelationId(0)), LineNumber::ANY),
-                Statement::Exec("bar".into(), Some(CorrelationId(1)), LineNumber::ANY),
-                Statement::Check("bar".into(), Some(CorrelationId(1)), LineNumber::ANY),
-                Statement::Exec("baz".into(), Some(CorrelationId(2)), LineNumber::ANY),
-                Statement::Check("baz".into(), Some(CorrelationId(2)), LineNumber::ANY),
-            ]
+            check(
+                DebuggerKind::Gdb,
+                &[
+                    "Continuing.",
+                    "[Inferior 1 (process 42) exited with code 01]"
+                ]
+            ),
+            Err(
+                "Expected the debuggee to exit normally, but it exited with an error: \
+                 `[Inferior 1 (process 42) exited with code 01]`"
+                    .to_string()
+            )
+        );
+        assert_eq!(
+            check(
+                DebuggerKind::Gdb,
+                &[
+                    "Continuing.",
+                    "",
+                    "Breakpoint 2, main::f () at src/main.rs:5"
+                ]
+            ),
+            Err(
+                "Expected the debuggee to exit normally, but it hit a breakpoint: \
+                 `Breakpoint 2, main::f () at src/main.rs:5`"
+                    .to_string()
+            )
         );
+        assert!(check(
+            DebuggerKind::Lldb,
+            &["* thread #1, stop reason = EXC_BAD_ACCESS (code=1, address=0x0)"]
+        )
+        .unwrap_err()
+        .contains("it faulted"));
+        assert!(check(DebuggerKind::Cdb, &["Breakpoint 1 hit"])
+            .unwrap_err()
+            .contains("hit a breakpoint"));
+        assert!(check(DebuggerKind::Gdb, &["Continuing."])
+            .unwrap_err()
+            .contains("doesn't show it exiting"));
+        assert!(check(
+            DebuggerKind::Gdb,
+            &["Temporary breakpoint 2, main::f () at src/main.rs:5"]
+        )
+        .unwrap_err()
+        .contains("hit a breakpoint"));
+        assert!(check(
+            DebuggerKind::Lldb,
+            &["* thread #1, stop reason = one-shot breakpoint 2"]
+        )
+        .unwrap_err()
+        .contains("hit a breakpoint"));
     }
 
     #[test]
-    fn correlation_id_assignment_multi_exec() {
-        let mut script = mock_test_def(from_lines(&[
-            "/***",
-            "foo",
-            "bar",
-            "baz",
-            "#check baz",
-            "***/",
-        ]))
-        .script;
+    fn count_threads() {
+        let gdb = Debugger {
+            kind: DebuggerKind::Gdb,
+            ..Debugger::mock()
+        };
 
-        Debugger::mock().assign_correlation_ids(
-            &mut script,
-            &Arc::from("debug"),
-            &PhaseConfig::Live,
-        );
+        let output = [
+            "  Id   Target Id                                   Frame ",
+            "* 1    Thread 0x7ffff7d8b740 (LWP 4242) \"main\" main::main () at src/main.rs:10",
+            "  2    Thread 0x7ffff7b8a640 (LWP 4243) \"worker\" 0x00007ffff7e91e2e in ?? ()",
+        ];
 
-        assert_eq!(
-            script.statements,
-            vec![
-                Statement::Exec("foo".into(), Some(CorrelationId(0)), LineNumber::ANY),
-                Statement::Exec("bar".into(), Some(CorrelationId(0)), LineNumber::ANY),
-                Statement::Exec("baz".into(), Some(CorrelationId(0)), LineNumber::ANY),
-                Statement::Check("baz".into(), Some(CorrelationId(0)), LineNumber::ANY),
-            ]
-        );
+        assert_eq!(gdb.count_threads(&output), 2);
+
+        let cdb = Debugger {
+            kind: DebuggerKind::Cdb,
+            ..Debugger::mock()
+        };
+
+        let output = [
+            ".  0  Id: 1a2c.3b40 Suspend: 1 Teb: 00000074`5f9c5000 Unfrozen",
+            "   1  Id: 1a2c.2f10 Suspend: 1 Teb: 00000074`5f9c7000 Unfrozen \"worker\"",
+            "#  2  Id: 1a2c.0c84 Suspend: 1 Teb: 00000074`5f9c9000 Unfrozen",
+        ];
+
+        assert_eq!(cdb.count_threads(&output), 3);
     }
 
     #[test]
-    fn correlation_id_assignment_multi_check() {
-        let mut script = mock_test_def(from_lines(&[
-            "/***",
-            "foo",
-            "#check foo",
-            "#check bar",
-            "#check baz",
-            "***/",
-        ]))
-        .script;
+    fn partition_by_debugger_kind() {
+        let by_kind = super::partition_by_debugger_kind(&[
+            "gdb:--data-directory=/opt/gdb/share".into(),
+            "cdb:-y".into(),
+            "cdb: C:\\symbols ".into(),
+            "gdb@14:-nx".into(),
+        ])
+        .unwrap();
 
-        Debugger::mock().assign_correlation_ids(
-            &mut script,
-            &Arc::from("debug"),
-            &PhaseConfig::Live,
+        assert_eq!(
+            by_kind.for_debugger(DebuggerKind::Gdb, "14.2"),
+            vec!["--data-directory=/opt/gdb/share", "-nx"]
         );
-
         assert_eq!(
-            script.statements,
-            vec![
-                Statement::Exec("foo".into(), Some(CorrelationId(0)), LineNumber::ANY),
-                Statement::Check("foo".into(), Some(CorrelationId(0)), LineNumber::ANY),
-                Statement::Check("bar".into(), Some(CorrelationId(0)), LineNumber::ANY),
-                Statement::Check("baz".into(), Some(CorrelationId(0)), LineNumber::ANY),
-            ]
+            by_kind.for_debugger(DebuggerKind::Gdb, "10.2"),
+            vec!["--data-directory=/opt/gdb/share"]
+        );
+        assert_eq!(
+            by_kind.for_debugger(DebuggerKind::Cdb, "10.0.22621.1"),
+            vec!["-y", "C:\\symbols"]
         );
+        assert!(by_kind
+            .for_debugger(DebuggerKind::Lldb, "18.1.8")
+            .is_empty());
+
+        assert!(super::partition_by_debugger_kind(&["--quiet".into()]).is_err());
+        assert!(super::partition_by_debugger_kind(&["windbg:-y".into()]).is_err());
     }
 
     #[test]
-    fn correlation_id_assignment_multiple_of_both() {
-        let mut script = mock_test_def(from_lines(&[
-            "/***",
-            "foo",
-            "bar",
-            "baz",
-            "#check foo",
-            "#check bar",
-            "#check baz",
-            "***/",
-        ]))
-        .script;
+    fn debugger_selector() {
+        let gdb_14 = super::DebuggerSelector::parse("gdb@14").unwrap();
+        assert!(gdb_14.matches(DebuggerKind::Gdb, "14"));
+        assert!(gdb_14.matches(DebuggerKind::Gdb, "14.2"));
+        assert!(!gdb_14.matches(DebuggerKind::Gdb, "140.1"));
+        assert!(!gdb_14.matches(DebuggerKind::Gdb, "10.2"));
+        assert!(!gdb_14.matches(DebuggerKind::Lldb, "14.2"));
+
+        let gdb = super::DebuggerSelector::parse("gdb").unwrap();
+        assert!(gdb.matches(DebuggerKind::Gdb, "10.2"));
+        assert!(super::DebuggerSelector::parse("windbg@10").is_err());
+    }
 
-        Debugger::mock().assign_correlation_ids(
-            &mut script,
-            &Arc::from("debug"),
-            &PhaseConfig::Live,
+    #[test]
+    fn define_variables() {
+        assert_eq!(
+            &super::define_variables(&["nightly".into()]).unwrap()[..],
+            [Arc::from("@nightly")]
         );
 
+        for define in ["ci", "ci_provider", "gdb", "version"] {
+            assert_eq!(
+                super::define_variables(&[define.into()])
+                    .unwrap_err()
+                    .to_string(),
+                format!(
+                    "`--define {}` is not allowed, dbt defines `@{}` itself",
+                    define, define
+                )
+            );
+        }
+    }
+
+    #[test]
+    fn add_define() {
+        let mut debugger =
+            Debugger::offline(DebuggerKind::Gdb, "14.2".into(), &[], &["x".into()]).unwrap();
+        debugger.add_define("embedded");
+        debugger.add_define("embedded");
+
         assert_eq!(
-            script.statements,
-            vec![
-                Statement::Exec("foo".into(), Some(CorrelationId(0)), LineNumber::ANY),
-                Statement::Exec("bar".into(), Some(CorrelationId(0)), LineNumber::ANY),
-                Statement::Exec("baz".into(), Some(CorrelationId(0)), LineNumber::ANY),
-                Statement::Check("foo".into(), Some(CorrelationId(0)), LineNumber::ANY),
-                Statement::Check("bar".into(), Some(CorrelationId(0)), LineNumber::ANY),
-                Statement::Check("baz".into(), Some(CorrelationId(0)), LineNumber::ANY),
-            ]
+            &debugger.defines[..],
+            [Arc::from("@x"), Arc::from("@embedded")]
         );
+        assert!(debugger
+            .evaluation_context(
+                &TestConfig::default(),
+                &Arc::from("debug"),
+                &PhaseConfig::Live
+            )
+            .values
+            .contains_key("@embedded"));
     }
 
     #[test]
-    fn correlation_id_assignment_generate_crashdump() {
-        let mut script = mock_test_def(from_lines(&[
-            "/***",
-            "foo",
-            "bar",
-            "baz",
-            "#generate-crashdump tag",
-            "#check foo",
-            "#check bar",
-            "#check baz",
-            "***/",
-        ]))
-        .script;
+    fn select_debuggers() {
+        let debuggers = || {
+            vec![
+                Debugger::offline(DebuggerKind::Gdb, "12.1".into(), &[], &[]).unwrap(),
+                Debugger::offline(DebuggerKind::Gdb, "14.2".into(), &[], &[]).unwrap(),
+                Debugger::offline(DebuggerKind::Lldb, "18.1.8".into(), &[], &[]).unwrap(),
+            ]
+        };
+        let selectors = |selectors: &[&str]| -> Vec<super::DebuggerSelector> {
+            selectors
+                .iter()
+                .map(|selector| super::DebuggerSelector::parse(selector).unwrap())
+                .collect()
+        };
+        let versions = |debuggers: Vec<Debugger>| -> Vec<String> {
+            debuggers
+                .iter()
+                .map(|debugger| format!("{} {}", debugger.kind.name(), debugger.version))
+                .collect()
+        };
 
-        Debugger::mock().assign_correlation_ids(
-            &mut script,
-            &Arc::from("debug"),
-            &PhaseConfig::Live,
+        let selected = super::select_debuggers(debuggers(), &selectors(&["gdb"]), &[]).unwrap();
+        assert_eq!(versions(selected), ["gdb 12.1", "gdb 14.2"]);
+
+        let selected =
+            super::select_debuggers(debuggers(), &selectors(&["gdb"]), &selectors(&["gdb@14"]))
+                .unwrap();
+        assert_eq!(versions(selected), ["gdb 12.1"]);
+
+        let selected = super::select_debuggers(debuggers(), &[], &selectors(&["lldb"])).unwrap();
+        assert_eq!(versions(selected), ["gdb 12.1", "gdb 14.2"]);
+
+        assert_eq!(
+            super::select_debuggers(debuggers(), &selectors(&["cdb"]), &[])
+                .unwrap_err()
+                .to_string(),
+            "--only-debugger cdb does not match any of the debuggers given via -d"
         );
 
+        // Debuggers that are left out aren't set up, so they can't fail the run
+        let commands = [PathBuf::from("mockdbg"), PathBuf::from("mockdbg")];
+        assert!(super::probe_debuggers(&commands, None, &[], &[]).is_err());
         assert_eq!(
-            script.statements,
-            vec![
-                Statement::Exec("foo".into(), Some(CorrelationId(0)), LineNumber::ANY),
-                Statement::Exec("bar".into(), Some(CorrelationId(0)), LineNumber::ANY),
-                Statement::Exec("baz".into(), Some(CorrelationId(0)), LineNumber::ANY),
-                Statement::GenerateCrashDump("tag".into(), Some(CorrelationId(1)), LineNumber::ANY),
-                Statement::Check("foo".into(), Some(CorrelationId(1)), LineNumber::ANY),
-                Statement::Check("bar".into(), Some(CorrelationId(1)), LineNumber::ANY),
-                Statement::Check("baz".into(), Some(CorrelationId(1)), LineNumber::ANY),
-            ]
+            super::probe_debuggers(&commands, None, &selectors(&["gdb"]), &[]).unwrap(),
+            []
+        );
+        assert_eq!(
+            super::probe_debuggers(&commands[..1], None, &[], &selectors(&["mock"])).unwrap(),
+            []
+        );
+
+        // Neither can a debugger be given via -d and replayed
+        let replay_directory =
+            std::env::temp_dir().join(format!("dbt-replay-duplicates-{}", std::process::id()));
+        std::fs::create_dir_all(replay_directory.join("mock-1.0")).unwrap();
+
+        let probed = super::probe_debuggers(&commands[..1], None, &[], &[]).unwrap();
+        let init = |probed| {
+            super::init_debuggers(probed, &[], &[], &[], &[], Some(&replay_directory)).map(versions)
+        };
+        assert_eq!(
+            init(&probed).unwrap_err().to_string(),
+            format!(
+                "mock 1.0 is both given via -d (mockdbg) and replayed from {}",
+                replay_directory.display()
+            )
         );
+        assert_eq!(init(&[]).unwrap(), ["mock 1.0"]);
+
+        std::fs::remove_dir_all(replay_directory).unwrap();
     }
 
     #[test]
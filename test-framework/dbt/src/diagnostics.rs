@@ -0,0 +1,83 @@
+//! Warnings debuggers print about the environment rather than about the test, like missing
+//! debuginfo for system libraries or declined auto-loading of scripts. They are reported along
+//! with the test result, even if the test passed, as they often explain differences between
+//! machines.
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+use crate::debugger::DebuggerOutput;
+
+lazy_static! {
+    static ref WARNING_PATTERNS: Vec<Regex> = [
+        // GDB, for libraries without debuginfo
+        r"Missing separate debuginfos?",
+        // GDB, for `.debug_gdb_scripts` and other auto-loaded scripts
+        r"Skipping loading of",
+        r"auto-loading has been declined",
+        r"Unable to find libthread_db",
+        // LLDB, for modules at address 0 it could not read
+        r"warning: 0{8}",
+        // CDB
+        r"\*\*\* WARNING: Unable to verify checksum",
+        r"\*\*\* ERROR: Symbol file could not be found",
+        r"\*\*\* ERROR: Module load completed but symbols could not be loaded",
+    ]
+    .iter()
+    .map(|pattern| Regex::new(pattern).unwrap())
+    .collect();
+}
+
+/// The lines of the debugger's stdout and stderr that match one of the known warnings, each
+/// distinct line once
+pub fn debugger_warnings(debugger_output: &DebuggerOutput) -> Vec<String> {
+    let mut warnings: Vec<String> = vec![];
+
+    for line in debugger_output
+        .stdout
+        .lines()
+        .chain(debugger_output.stderr.lines())
+    {
+        let line = line.trim();
+
+        if WARNING_PATTERNS
+            .iter()
+            .any(|pattern| pattern.is_match(line))
+            && !warnings.iter().any(|warning| warning == line)
+        {
+            warnings.push(line.to_string());
+        }
+    }
+
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::debugger::{DebuggerExitStatus, DebuggerOutput};
+
+    #[test]
+    fn debugger_warnings() {
+        let output = DebuggerOutput {
+            stdout: "Breakpoint 1, main\n\
+                     Missing separate debuginfos, use: dnf debuginfo-install glibc\n\
+                     $1 = 1\n"
+                .to_string(),
+            stderr: "warning: Skipping loading of libthread_db\n\
+                     warning: 00000000 unknown module\n\
+                     Missing separate debuginfos, use: dnf debuginfo-install glibc\n"
+                .to_string(),
+            exit_status: DebuggerExitStatus::Success,
+            program_output: None,
+        };
+
+        assert_eq!(
+            super::debugger_warnings(&output),
+            [
+                "Missing separate debuginfos, use: dnf debuginfo-install glibc",
+                "warning: Skipping loading of libthread_db",
+                "warning: 00000000 unknown module",
+            ]
+        );
+    }
+}
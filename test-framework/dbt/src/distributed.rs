@@ -0,0 +1,620 @@
+//! Distributing test runs across machines. `dbt agent` runs on each worker machine (e.g. a
+//! Windows machine for CDB and a Mac for LLDB) with the debuggers available there, and runs
+//! tests on behalf of a coordinator, i.e. a regular run given `--agent` for each worker. The
+//! coordinator splits the tests among the agents offering each kind of debugger and reports
+//! their results along with its own.
+//!
+//! Each request is sent over a TCP connection of its own, as a TOML document that ends when
+//! the coordinator shuts down its side of the connection. The agent answers the same way.
+//!
+//! Agents don't authenticate coordinators: anyone who can connect can make an agent run the
+//! tests it was started with. Only run agents on trusted networks.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    io::{Read, Write},
+    net::{Shutdown, TcpListener, TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cargo_test_directory::{BuildConfig, TestDefinition},
+    debugger::{DebuggerExitStatus, DebuggerKind, DebuggerOutput},
+    script::PhaseConfig,
+    test_result::{Status, TestResult},
+};
+
+/// How long to wait for a connection to an agent
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a read or write may block while sending or receiving a message, except for reading
+/// the response to [Request::Run]
+const IO_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long to wait for an agent to run tests and respond
+const RUN_TIMEOUT: Duration = Duration::from_secs(4 * 60 * 60);
+
+/// Requests are small, so anything bigger is not from a coordinator
+const MAX_REQUEST_SIZE: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "request", rename_all = "kebab-case")]
+pub enum Request {
+    /// Asks for the debuggers the agent has
+    Debuggers,
+    /// Runs the given tests with all debuggers of the given kind the agent has
+    Run {
+        debugger: String,
+        tests: Vec<String>,
+    },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Response {
+    /// Set if the agent could not handle the request
+    #[serde(default)]
+    pub error: Option<String>,
+    // TOML can't have empty arrays after arrays of tables
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub debuggers: Vec<RemoteDebugger>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub results: Vec<RemoteTestResult>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteDebugger {
+    /// See [DebuggerKind::name]
+    pub kind: String,
+    pub version: String,
+}
+
+/// A [TestResult] as sent by an agent. Paths to the debugger's output are left out, as they
+/// refer to the agent's machine.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RemoteTestResult {
+    pub test_name: String,
+    /// See [PhaseConfig::from_display_string]
+    pub phase: String,
+    pub debugger_kind: String,
+    pub debugger_version: String,
+    pub cargo_profile: String,
+    pub toolchain: Option<String>,
+    pub target: Option<String>,
+    /// See [TestResult::diagnostics]
+    #[serde(default)]
+    pub diagnostics: Vec<String>,
+    /// See [TestResult::build_output]
+    #[serde(default)]
+    pub build_output: String,
+    /// See [TestResult::debugger_duration]
+    #[serde(default)]
+    pub debugger_duration: Option<Duration>,
+    pub status: RemoteStatus,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "kebab-case")]
+pub enum RemoteStatus {
+    Passed,
+    Failed {
+        message: String,
+        exit_status: DebuggerExitStatus,
+        stdout: String,
+        stderr: String,
+    },
+    DebuggerCrashed {
+        exit_status: DebuggerExitStatus,
+        stdout: String,
+        stderr: String,
+    },
+    Errored {
+        message: String,
+    },
+    TimedOut {
+        message: String,
+    },
+    DependencyFailed {
+        dependency: String,
+    },
+    Ignored {
+        reason: Option<String>,
+    },
+    Skipped {
+        message: String,
+    },
+    BuildFailed {
+        output: String,
+    },
+}
+
+impl RemoteTestResult {
+    pub fn new(test_result: &TestResult) -> RemoteTestResult {
+        let status = match &*test_result.status {
+            Status::Passed => RemoteStatus::Passed,
+            Status::Failed(message, debugger_output) => RemoteStatus::Failed {
+                message: message.clone(),
+                exit_status: debugger_output.exit_status,
+                stdout: debugger_output.stdout.clone(),
+                stderr: debugger_output.stderr.clone(),
+            },
+            Status::Errored(message) => RemoteStatus::Errored {
+                message: message.clone(),
+            },
+            Status::TimedOut(message) => RemoteStatus::TimedOut {
+                message: message.clone(),
+            },
+            Status::DependencyFailed(dependency) => RemoteStatus::DependencyFailed {
+                dependency: dependency.clone(),
+            },
+            Status::DebuggerCrashed(_, debugger_output) => RemoteStatus::DebuggerCrashed {
+                exit_status: debugger_output.exit_status,
+                stdout: debugger_output.stdout.clone(),
+                stderr: debugger_output.stderr.clone(),
+            },
+            Status::Ignored(reason) => RemoteStatus::Ignored {
+                reason: reason.clone(),
+            },
+            Status::Skipped(message) => RemoteStatus::Skipped {
+                message: message.clone(),
+            },
+            Status::BuildFailed(output) => RemoteStatus::BuildFailed {
+                output: output.clone(),
+            },
+        };
+
+        RemoteTestResult {
+            test_name: test_result.test_name.to_string(),
+            phase: test_result.phase.to_string(),
+            debugger_kind: test_result.debugger_kind.name().to_string(),
+            debugger_version: test_result.debugger_version.to_string(),
+            cargo_profile: test_result.cargo_profile.to_string(),
+            toolchain: test_result.build.toolchain.as_deref().map(String::from),
+            target: test_result.build.target.as_deref().map(String::from),
+            diagnostics: test_result.diagnostics.clone(),
+            build_output: test_result.build_output.clone(),
+            debugger_duration: test_result.debugger_duration,
+            status,
+        }
+    }
+
+    /// Turns this back into a [TestResult], for the test whose source is at `source_path`
+    pub fn into_test_result(self, source_path: PathBuf) -> anyhow::Result<TestResult> {
+        let phase = match PhaseConfig::from_display_string(&self.phase) {
+            Some(phase) => phase,
+            None => bail!("Unknown phase `{}`", self.phase),
+        };
+
+        let status = match self.status {
+            RemoteStatus::Passed => Status::Passed,
+            RemoteStatus::Failed {
+                message,
+                exit_status,
+                stdout,
+                stderr,
+            } => Status::Failed(
+                message,
+                DebuggerOutput {
+                    stdout,
+                    stderr,
+                    exit_status,
+                    program_output: None,
+                },
+            ),
+            RemoteStatus::Errored { message } => Status::Errored(message),
+            RemoteStatus::TimedOut { message } => Status::TimedOut(message),
+            RemoteStatus::DependencyFailed { dependency } => Status::DependencyFailed(dependency),
+            RemoteStatus::DebuggerCrashed {
+                exit_status,
+                stdout,
+                stderr,
+            } => match exit_status {
+                DebuggerExitStatus::Crashed(crash) => Status::DebuggerCrashed(
+                    crash,
+                    DebuggerOutput {
+                        stdout,
+                        stderr,
+                        exit_status,
+                        program_output: None,
+                    },
+                ),
+                _ => bail!(
+                    "Crashed test with exit status {}",
+                    String::from(exit_status)
+                ),
+            },
+            RemoteStatus::Ignored { reason } => Status::Ignored(reason),
+            RemoteStatus::Skipped { message } => Status::Skipped(message),
+            RemoteStatus::BuildFailed { output } => Status::BuildFailed(output),
+        };
+
+        Ok(TestResult {
+            test_name: self.test_name.into(),
+            phase,
+            debugger_kind: DebuggerKind::try_from(&self.debugger_kind[..])?,
+            debugger_version: self.debugger_version.into(),
+            cargo_profile: self.cargo_profile.into(),
+            build: BuildConfig {
+                toolchain: self.toolchain.map(Arc::from),
+                target: self.target.map(Arc::from),
+            },
+            status: Box::new(status),
+            source_path,
+            debugger_output_stdout_path: None,
+            debugger_output_stderr_path: None,
+            command_timings: vec![],
+            resource_usage: None,
+            debugger_duration: self.debugger_duration,
+            printed_values: vec![],
+            matched_checks: vec![],
+            diagnostics: self.diagnostics,
+            build_output: self.build_output,
+        })
+    }
+}
+
+/// Handles the requests of coordinators, one at a time, until the process is killed. The
+/// listener is unauthenticated, see the module documentation.
+pub fn serve(
+    listener: TcpListener,
+    handle: &mut dyn FnMut(Request) -> anyhow::Result<Response>,
+) -> anyhow::Result<()> {
+    for stream in listener.incoming() {
+        let mut stream = stream?;
+        let peer = stream.peer_addr()?;
+
+        // A stalled coordinator must not block the agent for everyone else
+        stream.set_read_timeout(Some(IO_TIMEOUT))?;
+        stream.set_write_timeout(Some(IO_TIMEOUT))?;
+
+        let response = match receive::<Request>(&mut stream, MAX_REQUEST_SIZE) {
+            Ok(request) => {
+                println!("Handling {:?} from {}", request, peer);
+                handle(request)
+            }
+            Err(e) => Err(e),
+        };
+
+        let response = response.unwrap_or_else(|e| Response {
+            error: Some(format!("{:#}", e)),
+            ..Response::default()
+        });
+
+        // A coordinator that went away doesn't stop the agent
+        if let Err(e) = send(&mut stream, &response) {
+            eprintln!("Could not send the response to {}: {:#}", peer, e);
+        }
+    }
+
+    Ok(())
+}
+
+/// Sends `request` to the agent listening at `agent` and returns its response
+pub fn request(agent: &str, request: &Request) -> anyhow::Result<Response> {
+    let mut stream =
+        connect(agent).with_context(|| format!("Could not connect to agent {}", agent))?;
+
+    stream.set_write_timeout(Some(IO_TIMEOUT))?;
+    stream.set_read_timeout(Some(match request {
+        Request::Debuggers => IO_TIMEOUT,
+        Request::Run { .. } => RUN_TIMEOUT,
+    }))?;
+
+    send(&mut stream, request)?;
+    let response: Response = receive(&mut stream, u64::MAX)
+        .with_context(|| format!("Could not receive the response of agent {}", agent))?;
+
+    if let Some(error) = response.error {
+        bail!("Agent {} failed: {}", agent, error);
+    }
+
+    Ok(response)
+}
+
+fn send<T: Serialize>(stream: &mut TcpStream, message: &T) -> anyhow::Result<()> {
+    stream.write_all(toml::to_string(message)?.as_bytes())?;
+    stream.shutdown(Shutdown::Write)?;
+    Ok(())
+}
+
+fn connect(agent: &str) -> anyhow::Result<TcpStream> {
+    let mut last_error = None;
+
+    for address in agent.to_socket_addrs()? {
+        match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+            Ok(stream) => return Ok(stream),
+            Err(e) => last_error = Some(e),
+        }
+    }
+
+    match last_error {
+        Some(e) => Err(e.into()),
+        None => bail!("No address found for {}", agent),
+    }
+}
+
+/// Reads a message of at most `limit` bytes
+fn receive<T: for<'de> Deserialize<'de>>(stream: &mut TcpStream, limit: u64) -> anyhow::Result<T> {
+    let mut message = String::new();
+    stream
+        .take(limit.saturating_add(1))
+        .read_to_string(&mut message)?;
+
+    if message.len() as u64 > limit {
+        bail!("Message is larger than {} bytes", limit);
+    }
+
+    Ok(toml::from_str(&message)?)
+}
+
+/// Splits `tests` among the agents offering each kind of debugger. Returns the requests to
+/// send to each agent, by the agent's index in `agent_debuggers`.
+pub fn assign(
+    tests: &[Arc<str>],
+    agent_debuggers: &[Vec<RemoteDebugger>],
+) -> BTreeMap<usize, Vec<Request>> {
+    let mut agents_by_kind: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+
+    for (agent, debuggers) in agent_debuggers.iter().enumerate() {
+        for debugger in debuggers {
+            let agents = agents_by_kind.entry(&debugger.kind).or_default();
+
+            // An agent with several debuggers of a kind runs the tests with all of them
+            if !agents.contains(&agent) {
+                agents.push(agent);
+            }
+        }
+    }
+
+    let mut requests: BTreeMap<usize, Vec<Request>> = BTreeMap::new();
+
+    for (kind, agents) in agents_by_kind {
+        let mut tests_by_agent = vec![vec![]; agents.len()];
+
+        for (index, test) in tests.iter().enumerate() {
+            tests_by_agent[index % agents.len()].push(test.to_string());
+        }
+
+        for (agent, tests) in agents.into_iter().zip(tests_by_agent) {
+            if !tests.is_empty() {
+                requests.entry(agent).or_default().push(Request::Run {
+                    debugger: kind.to_string(),
+                    tests,
+                });
+            }
+        }
+    }
+
+    requests
+}
+
+/// Runs `test_definitions` on `agents` (given as `host:port`) and collects the results
+pub fn run_on_agents(
+    agents: &[String],
+    test_definitions: &[&TestDefinition],
+) -> anyhow::Result<Vec<TestResult>> {
+    let mut agent_debuggers = Vec::with_capacity(agents.len());
+
+    for agent in agents {
+        let debuggers = request(agent, &Request::Debuggers)?.debuggers;
+
+        println!(
+            "Agent {} has {}",
+            agent,
+            debuggers
+                .iter()
+                .map(|debugger| format!("{} {}", debugger.kind, debugger.version))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+
+        agent_debuggers.push(debuggers);
+    }
+
+    let source_paths: HashMap<&str, &PathBuf> = test_definitions
+        .iter()
+        .map(|test_definition| {
+            (
+                &test_definition.name[..],
+                &test_definition.absolute_source_path,
+            )
+        })
+        .collect();
+
+    let tests: Vec<Arc<str>> = test_definitions
+        .iter()
+        .map(|test_definition| test_definition.name.clone())
+        .collect();
+
+    let requests = assign(&tests, &agent_debuggers);
+
+    // Agents run one request at a time, but in parallel with each other
+    let responses: Vec<anyhow::Result<Vec<Response>>> = std::thread::scope(|scope| {
+        let handles: Vec<_> = requests
+            .iter()
+            .map(|(&agent, requests)| {
+                let agent = &agents[agent];
+                scope.spawn(move || {
+                    requests
+                        .iter()
+                        .map(|run| request(agent, run))
+                        .collect::<anyhow::Result<Vec<_>>>()
+                })
+            })
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect()
+    });
+
+    let mut test_results = vec![];
+
+    for response in responses {
+        for result in response?.into_iter().flat_map(|response| response.results) {
+            let source_path = match source_paths.get(&result.test_name[..]) {
+                Some(&source_path) => source_path.clone(),
+                None => bail!("An agent ran unknown test `{}`", result.test_name),
+            };
+
+            test_results.push(result.into_test_result(source_path)?);
+        }
+    }
+
+    Ok(test_results)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        net::{Shutdown, TcpListener, TcpStream},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use super::{RemoteDebugger, RemoteStatus, RemoteTestResult, Request, Response};
+    use crate::debugger::{DebuggerCrash, DebuggerExitStatus};
+
+    fn debugger(kind: &str) -> RemoteDebugger {
+        RemoteDebugger {
+            kind: kind.to_string(),
+            version: "1.0".to_string(),
+        }
+    }
+
+    #[test]
+    fn assign() {
+        let tests: Vec<Arc<str>> = vec!["a".into(), "b".into(), "c".into()];
+        let agent_debuggers = vec![
+            vec![debugger("cdb")],
+            vec![debugger("gdb"), debugger("lldb")],
+            vec![debugger("cdb"), debugger("cdb")],
+        ];
+
+        let requests = super::assign(&tests, &agent_debuggers);
+
+        let run = |debugger: &str, tests: &[&str]| Request::Run {
+            debugger: debugger.to_string(),
+            tests: tests.iter().map(|test| test.to_string()).collect(),
+        };
+
+        assert_eq!(requests[&0], vec![run("cdb", &["a", "c"])]);
+        assert_eq!(
+            requests[&1],
+            vec![run("gdb", &["a", "b", "c"]), run("lldb", &["a", "b", "c"])]
+        );
+        assert_eq!(requests[&2], vec![run("cdb", &["b"])]);
+    }
+
+    #[test]
+    fn receive_limit() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let receive = |message: &str, limit: u64| {
+            let mut coordinator = TcpStream::connect(address).unwrap();
+            coordinator.write_all(message.as_bytes()).unwrap();
+            coordinator.shutdown(Shutdown::Write).unwrap();
+
+            let (mut stream, _) = listener.accept().unwrap();
+            super::receive::<Request>(&mut stream, limit)
+        };
+
+        let message = toml::to_string(&Request::Debuggers).unwrap();
+        let size = message.len() as u64;
+
+        assert_eq!(receive(&message, size).unwrap(), Request::Debuggers);
+        assert_eq!(
+            receive(&message, size - 1).unwrap_err().to_string(),
+            format!("Message is larger than {} bytes", size - 1)
+        );
+    }
+
+    #[test]
+    fn messages_roundtrip() {
+        let request = Request::Run {
+            debugger: "gdb".to_string(),
+            tests: vec!["ws/t1/src/main.rs".to_string()],
+        };
+        let toml = toml::to_string(&request).unwrap();
+        assert_eq!(toml::from_str::<Request>(&toml).unwrap(), request);
+
+        let toml = toml::to_string(&Response {
+            debuggers: vec![debugger("gdb")],
+            ..Response::default()
+        })
+        .unwrap();
+        assert_eq!(
+            toml::from_str::<Response>(&toml).unwrap().debuggers.len(),
+            1
+        );
+
+        let toml = toml::to_string(&Request::Debuggers).unwrap();
+        assert_eq!(
+            toml::from_str::<Request>(&toml).unwrap(),
+            Request::Debuggers
+        );
+
+        let response = Response {
+            error: None,
+            debuggers: vec![debugger("gdb")],
+            results: vec![
+                RemoteTestResult {
+                    test_name: "ws/t1/src/main.rs".to_string(),
+                    phase: "live".to_string(),
+                    debugger_kind: "gdb".to_string(),
+                    debugger_version: "15.1".to_string(),
+                    cargo_profile: "debug".to_string(),
+                    toolchain: None,
+                    target: Some("x86_64-unknown-linux-gnu".to_string()),
+                    diagnostics: vec!["Missing separate debuginfos".to_string()],
+                    build_output: String::new(),
+                    debugger_duration: None,
+                    status: RemoteStatus::Failed {
+                        message: "check failed".to_string(),
+                        exit_status: DebuggerExitStatus::Success,
+                        stdout: "$1 = 2\n".to_string(),
+                        stderr: String::new(),
+                    },
+                },
+                RemoteTestResult {
+                    test_name: "ws/t2/src/main.rs".to_string(),
+                    phase: "live".to_string(),
+                    debugger_kind: "gdb".to_string(),
+                    debugger_version: "15.1".to_string(),
+                    cargo_profile: "debug".to_string(),
+                    toolchain: None,
+                    target: None,
+                    diagnostics: vec![],
+                    build_output: String::new(),
+                    debugger_duration: Some(Duration::from_millis(1200)),
+                    status: RemoteStatus::DebuggerCrashed {
+                        exit_status: DebuggerExitStatus::Crashed(DebuggerCrash::Signal(11)),
+                        stdout: "Breakpoint 1, main\n".to_string(),
+                        stderr: "Fatal signal: Segmentation fault\n".to_string(),
+                    },
+                },
+            ],
+        };
+        let toml = toml::to_string(&response).unwrap();
+        let roundtripped = toml::from_str::<Response>(&toml).unwrap();
+        assert_eq!(roundtripped, response);
+
+        for (index, source_path) in ["/ws/t1/src/main.rs", "/ws/t2/src/main.rs"]
+            .into_iter()
+            .enumerate()
+        {
+            let test_result = roundtripped.results[index]
+                .clone()
+                .into_test_result(source_path.into())
+                .unwrap();
+            assert_eq!(RemoteTestResult::new(&test_result), response.results[index]);
+        }
+    }
+}
@@ -0,0 +1,255 @@
+//! Running GDB tests against embedded targets (`--gdb-server`). For each test, DBT starts a
+//! GDB server for the probe (OpenOCD or probe-rs), and GDB connects to it via
+//! `target extended-remote` and flashes the debuggee via `load` before running the test's
+//! script.
+
+use std::{
+    ffi::OsString,
+    net::{Ipv4Addr, SocketAddr, TcpStream},
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+
+/// Defined (as `@embedded`) when running tests against an embedded target, e.g. for using
+/// `continue` instead of `run`
+pub const DEFINE: &str = "embedded";
+
+/// How long to wait for a GDB server to accept connections
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum GdbServerKind {
+    /// `openocd`, configured via `--openocd-config`
+    Openocd,
+    /// `probe-rs gdb`, configured via `--chip`
+    ProbeRs,
+}
+
+#[derive(Debug, Clone)]
+pub struct GdbServer {
+    pub kind: GdbServerKind,
+    /// The executable to run, if not the default one for `kind`
+    pub command: Option<OsString>,
+    /// The config files passed to OpenOCD, like `interface/stlink.cfg`
+    pub openocd_configs: Vec<PathBuf>,
+    /// The chip passed to probe-rs, like `STM32F411RETx`
+    pub chip: Option<String>,
+    pub port: u16,
+}
+
+impl GdbServer {
+    fn command(&self) -> anyhow::Result<Command> {
+        let default_command = match self.kind {
+            GdbServerKind::Openocd => "openocd",
+            GdbServerKind::ProbeRs => "probe-rs",
+        };
+
+        let mut command = Command::new(self.command.as_deref().unwrap_or(default_command.as_ref()));
+
+        match self.kind {
+            GdbServerKind::Openocd => {
+                if self.openocd_configs.is_empty() {
+                    bail!("--gdb-server openocd requires at least one --openocd-config");
+                }
+
+                for config in &self.openocd_configs {
+                    command.arg("-f").arg(config);
+                }
+
+                command.arg("-c").arg(format!("gdb_port {}", self.port));
+            }
+            GdbServerKind::ProbeRs => {
+                let chip = match &self.chip {
+                    Some(chip) => chip,
+                    None => bail!("--gdb-server probe-rs requires --chip"),
+                };
+
+                command
+                    .arg("gdb")
+                    .arg("--chip")
+                    .arg(chip)
+                    .arg("--gdb-connection-string")
+                    .arg(format!("127.0.0.1:{}", self.port));
+            }
+        }
+
+        Ok(command)
+    }
+
+    /// The GDB commands that connect to the server, flash the debuggee and reset the target,
+    /// so that the test's script starts with the target halted at its reset vector
+    pub fn gdb_prelude(&self) -> Vec<String> {
+        let reset = match self.kind {
+            GdbServerKind::Openocd => "monitor reset halt",
+            GdbServerKind::ProbeRs => "monitor reset",
+        };
+
+        vec![
+            format!("target extended-remote 127.0.0.1:{}", self.port),
+            "load".to_string(),
+            reset.to_string(),
+        ]
+    }
+
+    /// Starts the server and waits until it accepts connections
+    pub fn start(&self) -> anyhow::Result<RunningGdbServer> {
+        let mut command = self.command()?;
+
+        let child = command
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .with_context(|| {
+                format!(
+                    "Could not start GDB server `{}`",
+                    command.get_program().to_string_lossy()
+                )
+            })?;
+
+        // Kills the server if it doesn't come up
+        let mut server = RunningGdbServer { child };
+
+        wait_for_port(self.port, STARTUP_TIMEOUT, &mut || {
+            server.child.try_wait().map(|status| status.is_some())
+        })?;
+
+        Ok(server)
+    }
+}
+
+/// A GDB server process that is killed when dropped
+pub struct RunningGdbServer {
+    child: Child,
+}
+
+impl Drop for RunningGdbServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// Waits until something accepts connections on `port`. `exited` reports whether the
+/// process that should do so has exited already.
+fn wait_for_port(
+    port: u16,
+    timeout: Duration,
+    exited: &mut dyn FnMut() -> std::io::Result<bool>,
+) -> anyhow::Result<()> {
+    let address = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let deadline = Instant::now() + timeout;
+
+    loop {
+        if TcpStream::connect_timeout(&address, Duration::from_millis(100)).is_ok() {
+            return Ok(());
+        }
+
+        if exited()? {
+            bail!(
+                "The GDB server exited before accepting connections on port {}",
+                port
+            );
+        }
+
+        if Instant::now() >= deadline {
+            bail!(
+                "The GDB server did not accept connections on port {} within {} seconds",
+                port,
+                timeout.as_secs()
+            );
+        }
+
+        std::thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{net::TcpListener, path::PathBuf, time::Duration};
+
+    use super::{GdbServer, GdbServerKind};
+
+    #[test]
+    fn server_command() {
+        let openocd = GdbServer {
+            kind: GdbServerKind::Openocd,
+            command: None,
+            openocd_configs: vec![
+                PathBuf::from("interface/stlink.cfg"),
+                PathBuf::from("target/stm32f4x.cfg"),
+            ],
+            chip: None,
+            port: 3333,
+        };
+
+        let command = openocd.command().unwrap();
+        assert_eq!(command.get_program(), "openocd");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            vec![
+                "-f",
+                "interface/stlink.cfg",
+                "-f",
+                "target/stm32f4x.cfg",
+                "-c",
+                "gdb_port 3333"
+            ]
+        );
+
+        let probe_rs = GdbServer {
+            kind: GdbServerKind::ProbeRs,
+            chip: Some("STM32F411RETx".to_string()),
+            ..openocd
+        };
+
+        assert_eq!(
+            probe_rs.command().unwrap().get_args().collect::<Vec<_>>(),
+            vec![
+                "gdb",
+                "--chip",
+                "STM32F411RETx",
+                "--gdb-connection-string",
+                "127.0.0.1:3333"
+            ]
+        );
+        assert_eq!(
+            probe_rs.gdb_prelude(),
+            vec![
+                "target extended-remote 127.0.0.1:3333",
+                "load",
+                "monitor reset"
+            ]
+        );
+
+        let without_chip = GdbServer {
+            chip: None,
+            ..probe_rs
+        };
+        assert!(without_chip.command().is_err());
+    }
+
+    #[test]
+    fn wait_for_port() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+
+        assert!(super::wait_for_port(port, Duration::from_secs(5), &mut || Ok(false)).is_ok());
+
+        drop(listener);
+
+        let error = super::wait_for_port(port, Duration::from_secs(5), &mut || Ok(true))
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            error,
+            format!(
+                "The GDB server exited before accepting connections on port {}",
+                port
+            )
+        );
+    }
+}
@@ -0,0 +1,340 @@
+//! Formatting of test scripts (`dbt fmt`). Nested blocks are indented by two spaces per
+//! level, trailing whitespace is removed, directives are spelled with single spaces between
+//! their arguments, and the arguments of consecutive checks are aligned. Lines are never added
+//! or removed, so line numbers in diagnostics stay valid, and the formatted script has to parse
+//! to the same statements as the original one.
+
+use std::path::Path;
+
+use anyhow::bail;
+
+use crate::script::{
+    instantiate_params, parse_script, same_meaning, Statement, TOKEN_CHECK, TOKEN_CHECK_EXACT,
+    TOKEN_CHECK_LOCALS, TOKEN_CHECK_NOCASE, TOKEN_CHECK_NOT_BETWEEN, TOKEN_CHECK_SAME,
+    TOKEN_CHECK_TYPE, TOKEN_CHECK_UNORDERED, TOKEN_COMMENT, TOKEN_EACH_HIT, TOKEN_GROUP, TOKEN_IF,
+    TOKEN_SCRIPT_END, TOKEN_SCRIPT_START,
+};
+
+const INDENT: &str = "  ";
+
+/// The checks whose specification is matched against the debugger output. Only the whitespace
+/// after the keyword is normalized for them, as they are often aligned like the debugger
+/// output they match, and the specifications of consecutive checks start in the same column.
+const CHECKS: [&str; 5] = [
+    TOKEN_CHECK,
+    TOKEN_CHECK_NOCASE,
+    TOKEN_CHECK_EXACT,
+    TOKEN_CHECK_SAME,
+    TOKEN_CHECK_NOT_BETWEEN,
+];
+
+/// The directives with nested blocks
+const BLOCKS: [&str; 6] = [
+    TOKEN_IF,
+    TOKEN_GROUP,
+    TOKEN_EACH_HIT,
+    TOKEN_CHECK_UNORDERED,
+    TOKEN_CHECK_LOCALS,
+    TOKEN_CHECK_TYPE,
+];
+
+/// A line of a script while it is formatted
+struct FormattedLine<'a> {
+    level: usize,
+    code: String,
+    /// The trailing comment, with the whitespace in front of it
+    comment: Option<(&'a str, &'a str)>,
+    line_ending: &'a str,
+}
+
+/// Returns `source` with its script formatted. Fails if the script does not parse.
+pub fn format_source(source: &str, path_for_diagnostics: Option<&Path>) -> anyhow::Result<String> {
+    let original = parse_instantiations(source, path_for_diagnostics)?;
+
+    let lines: Vec<&str> = source.split_inclusive('\n').collect();
+
+    let start = match lines
+        .iter()
+        .position(|line| line.contains(TOKEN_SCRIPT_START))
+    {
+        Some(start) => start,
+        None => return Ok(source.to_string()),
+    };
+    let end = match lines[start + 1..]
+        .iter()
+        .position(|line| line.contains(TOKEN_SCRIPT_END))
+    {
+        Some(end) => start + 1 + end,
+        None => return Ok(source.to_string()),
+    };
+
+    let mut formatted = String::with_capacity(source.len());
+    lines[..=start]
+        .iter()
+        .for_each(|line| formatted.push_str(line));
+    formatted.push_str(&format_lines(&lines[start + 1..end]));
+    lines[end..]
+        .iter()
+        .for_each(|line| formatted.push_str(line));
+
+    if parse_instantiations(&formatted, path_for_diagnostics).ok() != Some(original) {
+        bail!("Formatting would change the meaning of the script, please report this as a bug");
+    }
+
+    Ok(formatted)
+}
+
+/// The statements of each instantiation of the script. Their source lines are left out, as
+/// formatting changes them.
+fn parse_instantiations(
+    source: &str,
+    path_for_diagnostics: Option<&Path>,
+) -> anyhow::Result<Vec<Vec<Statement>>> {
+    instantiate_params(source)?
+        .iter()
+        .map(|instantiation| {
+            parse_script(&instantiation.source, path_for_diagnostics)
+                .map(|script| script.statements)
+        })
+        .collect()
+}
+
+/// Formats the lines between the start and the end of a script, which keep their line
+/// endings
+fn format_lines(lines: &[&str]) -> String {
+    // The original indentation of each open block, the outermost first
+    let mut indents: Vec<usize> = vec![];
+    let mut opens_block = false;
+    let mut formatted_lines = vec![];
+
+    for line in lines {
+        let content = line.trim_end_matches(['\r', '\n']);
+        let line_ending = &line[content.len()..];
+        let indent = content.len() - content.trim_start_matches(' ').len();
+
+        let (code, comment) = match comment_start(content) {
+            Some(index) => (&content[..index], Some(content[index..].trim_end())),
+            None => (content, None),
+        };
+
+        let level = if code.trim().is_empty() {
+            match comment {
+                // A comment is indented like code at its place would be
+                Some(_) if indents.last().is_some_and(|last| indent > *last) && opens_block => {
+                    indents.len()
+                }
+                Some(_) => indents
+                    .iter()
+                    .filter(|block_indent| **block_indent <= indent)
+                    .count()
+                    .saturating_sub(1),
+                None => 0,
+            }
+        } else {
+            while indents.last().is_some_and(|last| *last > indent) {
+                indents.pop();
+            }
+            if indents.last().is_none_or(|last| *last < indent) {
+                indents.push(indent);
+            }
+
+            opens_block = BLOCKS.contains(&keyword(code));
+            indents.len() - 1
+        };
+
+        let code_end = code.trim_end();
+
+        formatted_lines.push(FormattedLine {
+            level,
+            code: normalize(code_end.trim_start()),
+            // Keep the gap before a trailing comment, which may align it with others
+            comment: comment.map(|comment| (&code[code_end.len()..], comment)),
+            line_ending,
+        });
+    }
+
+    align_checks(&mut formatted_lines);
+
+    let mut formatted = String::new();
+
+    for line in formatted_lines {
+        if !line.code.is_empty() || line.comment.is_some() {
+            for _ in 0..line.level {
+                formatted.push_str(INDENT);
+            }
+        }
+
+        formatted.push_str(&line.code);
+
+        if let Some((gap, comment)) = line.comment {
+            if !line.code.is_empty() {
+                formatted.push_str(gap);
+            }
+            formatted.push_str(comment);
+        }
+
+        formatted.push_str(line.line_ending);
+    }
+
+    formatted
+}
+
+/// Where the trailing comment of a line starts, if it has one. A `//` within a quoted string,
+/// like a URL, doesn't start a comment.
+fn comment_start(content: &str) -> Option<usize> {
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for (index, c) in content.char_indices() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                '\\' => escaped = true,
+                '"' => in_string = false,
+                _ => {}
+            }
+        } else if c == '"' {
+            in_string = true;
+        } else if content[index..].starts_with(TOKEN_COMMENT) {
+            return Some(index);
+        }
+    }
+
+    None
+}
+
+fn keyword(code: &str) -> &str {
+    code.split_whitespace().next().unwrap_or("")
+}
+
+fn normalize(code: &str) -> String {
+    let keyword = keyword(code);
+
+    if CHECKS.contains(&keyword) {
+        let arguments = code[keyword.len()..].trim_start();
+
+        if arguments.is_empty() {
+            keyword.to_string()
+        } else {
+            format!("{} {}", keyword, arguments)
+        }
+    } else if keyword.starts_with('#') {
+        // Directives whose arguments are split at whitespace when parsing are spelled with
+        // single spaces, the others (e.g. the expression of `#print`) are kept as they are
+        let canonical = code.split_whitespace().collect::<Vec<_>>().join(" ");
+
+        if canonical != code && same_meaning(code, &canonical) {
+            canonical
+        } else {
+            code.to_string()
+        }
+    } else {
+        code.to_string()
+    }
+}
+
+/// Aligns the specifications of consecutive checks at the same level, e.g. a `#check` that
+/// follows a `#check-nocase`
+fn align_checks(lines: &mut [FormattedLine]) {
+    let is_check = |line: &FormattedLine| CHECKS.contains(&keyword(&line.code));
+    let mut start = 0;
+
+    while start < lines.len() {
+        let level = lines[start].level;
+        let end = start
+            + lines[start..]
+                .iter()
+                .take_while(|line| is_check(line) && line.level == level)
+                .count();
+
+        if end == start {
+            start += 1;
+            continue;
+        }
+
+        let width = lines[start..end]
+            .iter()
+            .map(|line| keyword(&line.code).len())
+            .max()
+            .unwrap();
+
+        for line in &mut lines[start..end] {
+            let keyword = keyword(&line.code);
+
+            if keyword.len() < line.code.len() {
+                line.code = format!(
+                    "{:width$} {}",
+                    keyword,
+                    line.code[keyword.len()..].trim_start(),
+                    width = width
+                );
+            }
+        }
+
+        start = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn format_source() {
+        let source = "\
+/***
+#if   @gdb    &&  @version >= 12
+    // Breaks in main
+    break main.rs:10   // the only breakpoint
+    run
+    #check-unordered
+         a = 1
+         b = 2
+
+    #check   Breakpoint  1$
+  // Back to the top
+#if @cdb
+ g
+#frame   1
+#check-nocase  frame 1   // the caller
+#check  main
+#check-exact   }
+
+#check  done
+#ignore-test   \"flaky, see https://example.com/issue\"
+***/
+
+fn main() {}
+"
+        .replace('$', "   ");
+
+        assert_eq!(
+            super::format_source(&source, None).unwrap(),
+            "\
+/***
+#if @gdb && @version >= 12
+  // Breaks in main
+  break main.rs:10   // the only breakpoint
+  run
+  #check-unordered
+    a = 1
+    b = 2
+
+  #check Breakpoint  1
+// Back to the top
+#if @cdb
+  g
+#frame 1
+#check-nocase frame 1   // the caller
+#check        main
+#check-exact  }
+
+#check done
+#ignore-test \"flaky, see https://example.com/issue\"
+***/
+
+fn main() {}
+"
+        );
+
+        assert!(super::format_source("/***\n#if\n***/\n", None).is_err());
+    }
+}
@@ -0,0 +1,239 @@
+//! Why a debugger that was killed for not finishing in time hung. A debuggee that never
+//! stops at the next breakpoint (e.g. because it is stuck in a loop) calls for a different
+//! fix than a debugger that got stuck itself (e.g. printing a cyclic structure), so the
+//! processes are inspected before the debugger is killed. This is only supported on Linux,
+//! where the state of the processes is read from `/proc`.
+
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Hang {
+    /// The processes could not be inspected
+    Unknown,
+    /// The debuggee was running rather than stopped by the debugger
+    DebuggeeRunning,
+    /// The debuggee was not running, and the debugger was using the CPU
+    DebuggerBusy,
+    /// The debuggee was not running, and the debugger was blocked, e.g. waiting for input
+    DebuggerWaiting,
+}
+
+impl Hang {
+    /// The name used when the exit status of a debugger is serialized, see
+    /// [crate::debugger::DebuggerExitStatus]
+    pub fn name(self) -> &'static str {
+        match self {
+            Hang::Unknown => "unknown",
+            Hang::DebuggeeRunning => "debuggee-running",
+            Hang::DebuggerBusy => "debugger-busy",
+            Hang::DebuggerWaiting => "debugger-waiting",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Hang> {
+        [
+            Hang::Unknown,
+            Hang::DebuggeeRunning,
+            Hang::DebuggerBusy,
+            Hang::DebuggerWaiting,
+        ]
+        .into_iter()
+        .find(|hang| hang.name() == name)
+    }
+
+    /// What the hang means for the test, for the message of a timed out test. `stdout` is
+    /// the output of the debugger, whose last line is the prompt it was waiting at, if any.
+    pub fn description(self, stdout: &str) -> Option<String> {
+        match self {
+            Hang::Unknown => None,
+            Hang::DebuggeeRunning => Some(
+                "The debuggee was still running, so it probably never got to the next \
+                 breakpoint, e.g. because it is stuck in a loop or waiting for input."
+                    .to_string(),
+            ),
+            Hang::DebuggerBusy => Some(
+                "The debuggee was not running, but the debugger was busy, e.g. printing a \
+                 huge or cyclic value."
+                    .to_string(),
+            ),
+            Hang::DebuggerWaiting => {
+                let mut description = "The debuggee was not running, and the debugger was \
+                                       idle, e.g. waiting for input"
+                    .to_string();
+
+                match stdout.rsplit('\n').next().map(str::trim) {
+                    Some(prompt) if !prompt.is_empty() => {
+                        description.push_str(&format!(" at the prompt `{}`.", prompt))
+                    }
+                    _ => description.push('.'),
+                }
+
+                Some(description)
+            }
+        }
+    }
+}
+
+/// Determines why the debugger with the process ID `debugger_pid` hung, going by whether
+/// the debuggee at `debuggee` is still running among its descendants, or else what the
+/// debugger is doing
+#[cfg(target_os = "linux")]
+pub fn inspect(debugger_pid: u32, debuggee: &Path) -> Hang {
+    let processes = match processes() {
+        Ok(processes) => processes,
+        Err(_) => return Hang::Unknown,
+    };
+
+    let debugger_state = match processes.iter().find(|process| process.pid == debugger_pid) {
+        Some(debugger) => debugger.state,
+        None => return Hang::Unknown,
+    };
+
+    let debuggee = debuggee.canonicalize().ok();
+    let mut parent_pids = vec![debugger_pid];
+    let mut debuggee_running = false;
+
+    while let Some(parent_pid) = parent_pids.pop() {
+        for process in processes
+            .iter()
+            .filter(|process| process.parent_pid == parent_pid)
+        {
+            parent_pids.push(process.pid);
+
+            let exe = std::fs::read_link(format!("/proc/{}/exe", process.pid)).ok();
+
+            if debuggee.is_some() && exe == debuggee && is_running(process.state) {
+                debuggee_running = true;
+            }
+        }
+    }
+
+    if debuggee_running {
+        Hang::DebuggeeRunning
+    } else if debugger_state == 'R' {
+        Hang::DebuggerBusy
+    } else {
+        Hang::DebuggerWaiting
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn inspect(_debugger_pid: u32, _debuggee: &Path) -> Hang {
+    Hang::Unknown
+}
+
+#[cfg(target_os = "linux")]
+struct Process {
+    pid: u32,
+    parent_pid: u32,
+    state: char,
+}
+
+#[cfg(target_os = "linux")]
+fn processes() -> std::io::Result<Vec<Process>> {
+    let mut processes = vec![];
+
+    for entry in std::fs::read_dir("/proc")? {
+        let entry = entry?;
+
+        let pid = match entry.file_name().to_str().map(str::parse) {
+            Some(Ok(pid)) => pid,
+            _ => continue,
+        };
+
+        // Processes can end while they are listed
+        let stat = match std::fs::read_to_string(entry.path().join("stat")) {
+            Ok(stat) => stat,
+            Err(_) => continue,
+        };
+
+        if let Some((state, parent_pid)) = parse_stat(&stat) {
+            processes.push(Process {
+                pid,
+                parent_pid,
+                state,
+            });
+        }
+    }
+
+    Ok(processes)
+}
+
+/// The state and parent process ID in the contents of `/proc/<pid>/stat`, which look like
+/// `1234 (name) S 1 ...`. The name can contain spaces and parentheses itself.
+#[cfg(target_os = "linux")]
+fn parse_stat(stat: &str) -> Option<(char, u32)> {
+    let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace();
+    let state = fields.next()?.chars().next()?;
+    let parent_pid = fields.next()?.parse().ok()?;
+
+    Some((state, parent_pid))
+}
+
+/// Whether a process in `state` is running, as opposed to stopped by a debugger (`t`, or
+/// `T` for older kernels) or ended (`Z`, `X`)
+#[cfg(target_os = "linux")]
+fn is_running(state: char) -> bool {
+    !matches!(state, 't' | 'T' | 'Z' | 'X')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Hang;
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn parse_stat() {
+        assert_eq!(
+            super::parse_stat("1234 (my (weird) name) t 1200 1234 0 -1"),
+            Some(('t', 1200))
+        );
+        assert_eq!(super::parse_stat("1234 (gdb"), None);
+    }
+
+    #[test]
+    fn description() {
+        assert_eq!(Hang::from_name("debugger-busy"), Some(Hang::DebuggerBusy));
+        assert_eq!(Hang::Unknown.description(""), None);
+        assert_eq!(
+            Hang::DebuggerWaiting
+                .description("$1 = 1\n(y or n) ")
+                .unwrap(),
+            "The debuggee was not running, and the debugger was idle, e.g. waiting for input \
+             at the prompt `(y or n)`."
+        );
+        assert!(Hang::DebuggerWaiting
+            .description("$1 = 1\n")
+            .unwrap()
+            .ends_with("waiting for input."));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn inspect() {
+        use std::{path::Path, process::Command, time::Duration};
+
+        let inspect = |script: &str, debuggee: &str| {
+            let mut child = Command::new("sh").arg("-c").arg(script).spawn().unwrap();
+            std::thread::sleep(Duration::from_millis(200));
+            let hang = super::inspect(child.id(), Path::new(debuggee));
+            child.kill().unwrap();
+            child.wait().unwrap();
+            hang
+        };
+
+        // `; true` keeps the shell from replacing itself with `sleep`
+        assert_eq!(
+            inspect("sleep 2; true", "/bin/sleep"),
+            Hang::DebuggeeRunning
+        );
+        assert_eq!(
+            inspect("while :; do :; done", "/bin/sleep"),
+            Hang::DebuggerBusy
+        );
+        assert_eq!(
+            inspect("sleep 2; true", "/nonexistent"),
+            Hang::DebuggerWaiting
+        );
+    }
+}
@@ -0,0 +1,179 @@
+//! The results of previous runs, persisted via `--history`. These are used to detect
+//! flaky tests, i.e. tests whose status changes between runs although they did not change.
+
+use std::{collections::HashMap, path::Path};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{prettify_path, stable_hash, test_result::TestResult};
+
+/// The number of runs kept in the history
+const MAX_RUNS: usize = 20;
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct History {
+    #[serde(default)]
+    pub runs: Vec<HistoryRun>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryRun {
+    #[serde(default)]
+    pub results: Vec<HistoryEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct HistoryEntry {
+    /// See [TestResult::label_with_debugger]
+    pub test: String,
+    /// The [stable_hash] of the test's source file
+    pub source_hash: String,
+    pub passed: bool,
+}
+
+impl History {
+    /// Loads the history from `path`, returning an empty history if it does not exist yet.
+    pub fn load(path: &Path) -> anyhow::Result<History> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(History::default()),
+            Err(e) => bail!(e),
+        };
+
+        toml::from_str(&contents)
+            .with_context(|| format!("while reading test history {}", prettify_path(path)))
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, toml::to_string(self)?)?;
+        Ok(())
+    }
+
+    /// Adds the results of a run, dropping the oldest run if there are too many.
+    pub fn add_run(&mut self, test_results: &[TestResult]) -> anyhow::Result<()> {
+        let mut source_hashes = HashMap::new();
+        let mut results = vec![];
+
+        for test_result in test_results {
+            let passed = match test_result.status.passed() {
+                Some(passed) => passed,
+                None => continue,
+            };
+
+            if !source_hashes.contains_key(&test_result.source_path) {
+                let source = std::fs::read(&test_result.source_path)?;
+                source_hashes.insert(test_result.source_path.clone(), stable_hash(&source));
+            }
+
+            results.push(HistoryEntry {
+                test: test_result.label_with_debugger(),
+                source_hash: source_hashes[&test_result.source_path].clone(),
+                passed,
+            });
+        }
+
+        self.runs.push(HistoryRun { results });
+
+        if self.runs.len() > MAX_RUNS {
+            self.runs.drain(..self.runs.len() - MAX_RUNS);
+        }
+
+        Ok(())
+    }
+
+    /// Returns the tests of the latest run that both passed and failed in the runs since
+    /// their source last changed.
+    pub fn flaky_tests(&self) -> Vec<String> {
+        let latest = match self.runs.last() {
+            Some(latest) => latest,
+            None => return vec![],
+        };
+
+        let mut flaky = vec![];
+
+        for entry in &latest.results {
+            let (mut passed, mut failed) = (false, false);
+
+            let previous_entries = self.runs.iter().rev().filter_map(|run| {
+                run.results
+                    .iter()
+                    .find(|previous| previous.test == entry.test)
+            });
+
+            for previous in previous_entries {
+                if previous.source_hash != entry.source_hash {
+                    break;
+                }
+
+                passed |= previous.passed;
+                failed |= !previous.passed;
+            }
+
+            if passed && failed {
+                flaky.push(entry.test.clone());
+            }
+        }
+
+        flaky.sort();
+        flaky
+    }
+}
+
+pub fn print_flaky_tests(flaky_tests: &[String]) {
+    if flaky_tests.is_empty() {
+        return;
+    }
+
+    println!();
+    println!("Flaky tests (status changed in recent runs without the test changing):");
+    println!();
+
+    for test in flaky_tests {
+        println!("  {}", test);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{History, HistoryEntry, HistoryRun};
+
+    fn run(results: &[(&str, &str, bool)]) -> HistoryRun {
+        HistoryRun {
+            results: results
+                .iter()
+                .map(|&(test, source_hash, passed)| HistoryEntry {
+                    test: test.to_string(),
+                    source_hash: source_hash.to_string(),
+                    passed,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn flaky_tests() {
+        let history = History {
+            runs: vec![
+                run(&[("a", "1", false), ("b", "1", true), ("c", "1", true)]),
+                // `a` was changed and passed since
+                run(&[("a", "2", true), ("b", "1", false), ("c", "1", true)]),
+                run(&[("c", "1", false)]),
+                run(&[("a", "2", true), ("b", "1", true), ("c", "1", true)]),
+            ],
+        };
+
+        assert_eq!(history.flaky_tests(), vec!["b", "c"]);
+        assert_eq!(History::default().flaky_tests(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn round_trip() {
+        let history = History {
+            runs: vec![run(&[("a (live)", "1", false)]), run(&[])],
+        };
+
+        let toml = toml::to_string(&history).unwrap();
+        assert_eq!(toml::from_str::<History>(&toml).unwrap(), history);
+    }
+}
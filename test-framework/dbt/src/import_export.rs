@@ -1,13 +1,17 @@
 use std::{
+    collections::BTreeMap,
     fs::File,
-    path::{Path, PathBuf},
+    io::Read,
+    path::{Component, Path, PathBuf},
 };
 
+use anyhow::bail;
 use flate2::{read::GzDecoder, write::GzEncoder, Compression};
-use log::debug;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
 use tar;
 
-use crate::prettify_path;
+use crate::{prettify_path, stable_hash};
 
 pub fn import_crashdumps(output_dir: &Path, tar_path: &Path) -> anyhow::Result<()> {
     debug!("Importing crashdumps from `{}`", prettify_path(tar_path));
@@ -18,6 +22,211 @@ pub fn import_crashdumps(output_dir: &Path, tar_path: &Path) -> anyhow::Result<(
     Ok(())
 }
 
+/// The first entry of cache archives, which holds the [SourceFingerprint] of the workspaces
+/// the cache was built from. The other entries are the contents of the Cargo target directory.
+const CACHE_FINGERPRINT: &str = "dbt-cache.toml";
+
+/// The hashes of the sources of the Cargo workspaces a cache was built from, for telling
+/// whether an imported cache is up to date with the sources
+#[derive(Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+struct SourceFingerprint {
+    /// The hash of each file (see [stable_hash]), by its path below the `--cargo-workspace`
+    /// it is in, e.g. `plugin-tests/src/main.rs`
+    files: BTreeMap<String, String>,
+}
+
+impl SourceFingerprint {
+    /// Hashes the files in `cargo_workspaces`, apart from hidden ones and build output
+    fn new(
+        cargo_workspaces: &[PathBuf],
+        cargo_target_directory: &Path,
+    ) -> anyhow::Result<SourceFingerprint> {
+        let mut fingerprint = SourceFingerprint::default();
+        let cargo_target_directory = cargo_target_directory.canonicalize().ok();
+
+        for cargo_workspace in cargo_workspaces {
+            fingerprint.add_files(
+                cargo_workspace,
+                &cargo_workspace.to_string_lossy().replace('\\', "/"),
+                cargo_target_directory.as_deref(),
+            )?;
+        }
+
+        Ok(fingerprint)
+    }
+
+    fn add_files(
+        &mut self,
+        directory: &Path,
+        key: &str,
+        cargo_target_directory: Option<&Path>,
+    ) -> anyhow::Result<()> {
+        for entry in std::fs::read_dir(directory)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let path = entry.path();
+
+            if name.starts_with('.') || name == "target" {
+                continue;
+            }
+
+            let key = format!("{}/{}", key.trim_end_matches('/'), name);
+            let file_type = entry.file_type()?;
+
+            if file_type.is_dir() {
+                if cargo_target_directory != path.canonicalize().ok().as_deref() {
+                    self.add_files(&path, &key, cargo_target_directory)?;
+                }
+            } else if file_type.is_file() {
+                self.files.insert(key, stable_hash(&std::fs::read(&path)?));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The files that differ between the sources the cache was built from and `current`
+    fn changed_files<'a>(&'a self, current: &'a SourceFingerprint) -> Vec<&'a str> {
+        let mut changed: Vec<&str> = self
+            .files
+            .iter()
+            .filter(|&(path, hash)| current.files.get(path) != Some(hash))
+            .map(|(path, _)| &path[..])
+            .chain(
+                current
+                    .files
+                    .keys()
+                    .filter(|path| !self.files.contains_key(*path))
+                    .map(|path| &path[..]),
+            )
+            .collect();
+
+        changed.sort_unstable();
+        changed
+    }
+}
+
+/// Bundles the state that saves work in later runs (see `dbt cache export`), i.e. the
+/// compiled test cases in `cargo_target_directory`, into a `.tar.gz` at `tar_path`, along
+/// with the fingerprint of the sources in `cargo_workspaces`
+pub fn export_cache(
+    cargo_workspaces: &[PathBuf],
+    cargo_target_directory: &Path,
+    tar_path: &Path,
+) -> anyhow::Result<()> {
+    debug!(
+        "Exporting {} to `{}`",
+        prettify_path(cargo_target_directory),
+        prettify_path(tar_path)
+    );
+
+    let fingerprint = toml::to_string(&SourceFingerprint::new(
+        cargo_workspaces,
+        cargo_target_directory,
+    )?)?;
+
+    let file = File::create(tar_path)?;
+    let mut tar_builder = tar::Builder::new(GzEncoder::new(file, Compression::default()));
+
+    let mut header = tar::Header::new_gnu();
+    header.set_size(fingerprint.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    tar_builder.append_data(&mut header, CACHE_FINGERPRINT, fingerprint.as_bytes())?;
+
+    tar_builder.follow_symlinks(false);
+
+    for entry in std::fs::read_dir(cargo_target_directory)? {
+        let entry = entry?;
+
+        if entry.file_type()?.is_dir() {
+            tar_builder.append_dir_all(entry.file_name(), entry.path())?;
+        } else {
+            tar_builder.append_path_with_name(entry.path(), entry.file_name())?;
+        }
+    }
+
+    tar_builder.into_inner()?.finish()?;
+
+    Ok(())
+}
+
+/// Restores the state bundled by [export_cache], overwriting files that already exist.
+/// Warns if the sources in `cargo_workspaces` differ from the ones the cache was built from.
+pub fn import_cache(
+    cargo_workspaces: &[PathBuf],
+    cargo_target_directory: &Path,
+    tar_path: &Path,
+) -> anyhow::Result<()> {
+    debug!(
+        "Importing `{}` into {}",
+        prettify_path(tar_path),
+        prettify_path(cargo_target_directory)
+    );
+
+    let file = File::open(tar_path)?;
+    let mut archive = tar::Archive::new(GzDecoder::new(file));
+    let mut entries = archive.entries()?;
+
+    let exported_fingerprint: SourceFingerprint = match entries.next() {
+        Some(entry) => {
+            let mut entry = entry?;
+
+            if entry.path()?.as_ref() != Path::new(CACHE_FINGERPRINT) {
+                bail!(
+                    "`{}` is not a cache exported by dbt: unexpected entry `{}`",
+                    prettify_path(tar_path),
+                    entry.path()?.display()
+                );
+            }
+
+            let mut contents = String::new();
+            entry.read_to_string(&mut contents)?;
+            toml::from_str(&contents)?
+        }
+        None => bail!("`{}` is empty", prettify_path(tar_path)),
+    };
+
+    std::fs::create_dir_all(cargo_target_directory)?;
+
+    for entry in entries {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+
+        if path.has_root()
+            || path
+                .components()
+                .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+        {
+            bail!(
+                "`{}` contains an entry outside of the target directory: `{}`",
+                prettify_path(tar_path),
+                path.display()
+            );
+        }
+
+        entry.unpack_in(cargo_target_directory)?;
+    }
+
+    if cargo_workspaces.is_empty() {
+        return Ok(());
+    }
+
+    let current_fingerprint = SourceFingerprint::new(cargo_workspaces, cargo_target_directory)?;
+    let changed_files = exported_fingerprint.changed_files(&current_fingerprint);
+
+    if let Some(changed_file) = changed_files.first() {
+        warn!(
+            "The cache was built from other sources ({} files differ, e.g. `{}`), so the \
+             affected tests are rebuilt, or run stale with --skip-rebuild",
+            changed_files.len(),
+            changed_file
+        );
+    }
+
+    Ok(())
+}
+
 // Paths are absolute
 #[derive(Debug)]
 pub struct GeneratedCrashDump {
@@ -110,8 +319,83 @@ fn to_tar_paths<'a>(
 
 #[cfg(test)]
 mod tests {
-    use super::GeneratedCrashDump;
-    use std::path::Path;
+    use super::{GeneratedCrashDump, SourceFingerprint};
+    use flate2::{write::GzEncoder, Compression};
+    use std::{fs::File, path::Path};
+
+    #[test]
+    fn export_and_import_cache() {
+        let root = std::env::temp_dir().join(format!("dbt-cache-test-{}", std::process::id()));
+        let workspace = root.join("tests");
+        let target = root.join("target");
+        let restored = root.join("restored");
+        let archive = root.join("cache.tar.gz");
+
+        std::fs::create_dir_all(workspace.join("src")).unwrap();
+        std::fs::write(workspace.join("src/main.rs"), "fn main() {}").unwrap();
+        std::fs::create_dir_all(target.join("debug/deps")).unwrap();
+        std::fs::write(target.join("debug/my-test"), "executable").unwrap();
+        std::fs::write(target.join("debug/deps/my-test.d"), "deps").unwrap();
+
+        let workspaces = [workspace.clone()];
+        super::export_cache(&workspaces, &target, &archive).unwrap();
+        super::import_cache(&workspaces, &restored, &archive).unwrap();
+
+        assert_eq!(
+            std::fs::read_to_string(restored.join("debug/my-test")).unwrap(),
+            "executable"
+        );
+        assert_eq!(
+            std::fs::read_to_string(restored.join("debug/deps/my-test.d")).unwrap(),
+            "deps"
+        );
+
+        // Entries that would end up outside of the target directory are rejected
+        let mut header = tar::Header::new_gnu();
+        header.as_old_mut().name[..9].copy_from_slice(b"../escape");
+        header.set_size(0);
+        header.set_cksum();
+
+        let mut tar_builder = tar::Builder::new(GzEncoder::new(
+            File::create(&archive).unwrap(),
+            Compression::default(),
+        ));
+        let mut fingerprint = tar::Header::new_gnu();
+        fingerprint.set_size(0);
+        fingerprint.set_cksum();
+        tar_builder
+            .append_data(&mut fingerprint, super::CACHE_FINGERPRINT, &[][..])
+            .unwrap();
+        tar_builder.append(&header, &[][..]).unwrap();
+        tar_builder.into_inner().unwrap().finish().unwrap();
+
+        assert!(super::import_cache(&[], &restored, &archive).is_err());
+        assert!(!root.join("escape").exists());
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn changed_files() {
+        let fingerprint = |files: &[(&str, &str)]| SourceFingerprint {
+            files: files
+                .iter()
+                .map(|(path, hash)| (path.to_string(), hash.to_string()))
+                .collect(),
+        };
+
+        let exported = fingerprint(&[("tests/a.rs", "1"), ("tests/b.rs", "2")]);
+
+        assert!(exported.changed_files(&exported).is_empty());
+        assert_eq!(
+            exported.changed_files(&fingerprint(&[("tests/a.rs", "1"), ("tests/c.rs", "3")])),
+            ["tests/b.rs", "tests/c.rs"]
+        );
+        assert_eq!(
+            exported.changed_files(&fingerprint(&[("tests/a.rs", "4"), ("tests/b.rs", "2")])),
+            ["tests/a.rs"]
+        );
+    }
 
     #[test]
     fn to_tar_paths() {
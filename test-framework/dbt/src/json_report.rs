@@ -0,0 +1,250 @@
+//! The results of a run as JSON (`--json-report`), for tools that present them, like IDE
+//! extensions. Besides the status of each test, it lists which line of the debugger's stdout
+//! each check matched, so that the matched output can be shown next to the test script.
+//!
+//! The format is versioned via [SCHEMA_VERSION]. Within a version, fields and statuses are
+//! only ever added, so consumers should ignore fields they don't know and treat statuses
+//! they don't know as "did not pass". Removing, renaming or changing the meaning of a field
+//! bumps the version. The types here can be used to read reports, see [JsonReport::load].
+
+use std::{collections::BTreeMap, path::Path, time::Duration};
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    metadata::RunMetadata,
+    prettify_path,
+    test_result::{self, Status, TestResult},
+};
+
+/// The version of the format written by [JsonReport::save], see the module docs for what
+/// changes within a version
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JsonReport {
+    pub schema_version: u32,
+    pub tests: Vec<JsonTestResult>,
+    #[serde(default)]
+    pub time_breakdown: JsonTimeBreakdown,
+    /// The environment of the run, see [RunMetadata]. `None` for reports of `recheck`, which
+    /// doesn't run any debuggers.
+    #[serde(default)]
+    pub metadata: Option<RunMetadata>,
+}
+
+/// See [test_result::TimeBreakdown]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JsonTimeBreakdown {
+    /// `None` if the tests weren't compiled by the run
+    pub build_ms: Option<u64>,
+    /// By debugger kind, summed over all tests
+    pub debugger_ms: BTreeMap<String, u64>,
+}
+
+/// Fields added after the first release of a schema version are missing from older reports,
+/// and default to empty
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct JsonTestResult {
+    pub test: String,
+    pub source_path: String,
+    pub phase: String,
+    pub debugger_kind: String,
+    pub debugger_version: String,
+    pub cargo_profile: String,
+    pub toolchain: Option<String>,
+    pub target: Option<String>,
+    /// `passed`, `failed`, `errored`, `timed-out`, `debugger-crashed`, `dependency-failed`,
+    /// `build-failed`, `ignored` or `skipped`
+    pub status: String,
+    /// Why the test didn't pass, as printed in the report
+    pub message: Option<String>,
+    /// The file the debugger's stdout was saved to, which `output_line` refers to
+    pub stdout_path: Option<String>,
+    pub matched_checks: Vec<JsonCheckMatch>,
+    /// Warnings the debugger printed about the environment, see [crate::diagnostics]
+    pub diagnostics: Vec<String>,
+    /// The compiler's diagnostics for the package of the test, e.g. warnings
+    pub build_output: String,
+    /// How long the debugger ran, `None` if it wasn't run (e.g. for skipped tests)
+    pub debugger_duration_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JsonCheckMatch {
+    /// The line of the check in the source file, counting from 1
+    pub check_line: u32,
+    /// The line of the debugger's stdout the check matched, counting from 1
+    pub output_line: usize,
+    pub output: String,
+}
+
+impl JsonReport {
+    /// `build_duration` is how long compiling the tests took, if they were compiled
+    pub fn new(
+        test_results: &[TestResult],
+        build_duration: Option<Duration>,
+        metadata: Option<RunMetadata>,
+    ) -> JsonReport {
+        let time_breakdown = test_result::time_breakdown(test_results, build_duration);
+
+        JsonReport {
+            schema_version: SCHEMA_VERSION,
+            tests: test_results.iter().map(json_test_result).collect(),
+            time_breakdown: JsonTimeBreakdown {
+                build_ms: time_breakdown.build.map(millis),
+                debugger_ms: time_breakdown
+                    .debuggers
+                    .into_iter()
+                    .map(|(debugger_kind, duration)| (debugger_kind.to_string(), millis(duration)))
+                    .collect(),
+            },
+            metadata,
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// Reads a report written by [JsonReport::save], failing if it has a schema version this
+    /// version of dbt doesn't know
+    pub fn load(path: &Path) -> anyhow::Result<JsonReport> {
+        JsonReport::from_json(&std::fs::read_to_string(path)?)
+            .map_err(|e| e.context(prettify_path(path).into_owned()))
+    }
+
+    fn from_json(json: &str) -> anyhow::Result<JsonReport> {
+        let report: JsonReport = serde_json::from_str(json)?;
+
+        if report.schema_version != SCHEMA_VERSION {
+            bail!(
+                "The JSON report has schema version {}, but only version {} is supported",
+                report.schema_version,
+                SCHEMA_VERSION
+            );
+        }
+
+        Ok(report)
+    }
+}
+
+impl JsonTestResult {
+    /// Whether the test didn't pass, not counting tests that were ignored or skipped
+    pub fn failed(&self) -> bool {
+        !matches!(&self.status[..], "passed" | "ignored" | "skipped")
+    }
+}
+
+fn json_test_result(test_result: &TestResult) -> JsonTestResult {
+    let (status, message) = match &*test_result.status {
+        Status::Passed => ("passed", None),
+        Status::Failed(message, _) => ("failed", Some(message.clone())),
+        Status::Errored(message) => ("errored", Some(message.clone())),
+        Status::TimedOut(message) => ("timed-out", Some(message.clone())),
+        Status::DebuggerCrashed(crash, _) => ("debugger-crashed", Some(crash.to_string())),
+        Status::DependencyFailed(dependency) => ("dependency-failed", Some(dependency.clone())),
+        Status::Ignored(reason) => ("ignored", reason.clone()),
+        Status::Skipped(reason) => ("skipped", Some(reason.clone())),
+        Status::BuildFailed(output) => ("build-failed", Some(output.clone())),
+    };
+
+    JsonTestResult {
+        test: test_result.test_name.to_string(),
+        source_path: test_result.source_path.to_string_lossy().into_owned(),
+        phase: test_result.phase.to_string(),
+        debugger_kind: test_result.debugger_kind.name().to_string(),
+        debugger_version: test_result.debugger_version.to_string(),
+        cargo_profile: test_result.cargo_profile.to_string(),
+        toolchain: test_result.build.toolchain.as_deref().map(str::to_string),
+        target: test_result.build.target.as_deref().map(str::to_string),
+        status: status.to_string(),
+        message,
+        stdout_path: test_result
+            .debugger_output_stdout_path
+            .as_ref()
+            .map(|path| path.to_string_lossy().into_owned()),
+        matched_checks: test_result
+            .matched_checks
+            .iter()
+            .map(|check_match| JsonCheckMatch {
+                check_line: check_match.check_line.0,
+                output_line: check_match.output_line,
+                output: check_match.output.clone(),
+            })
+            .collect(),
+        diagnostics: test_result.diagnostics.clone(),
+        build_output: test_result.build_output.clone(),
+        debugger_duration_ms: test_result.debugger_duration.map(millis),
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    duration.as_millis() as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JsonCheckMatch, JsonReport, JsonTestResult, JsonTimeBreakdown, SCHEMA_VERSION};
+    use crate::metadata::{DebuggerMetadata, RunMetadata, ToolchainMetadata};
+
+    #[test]
+    fn from_json() {
+        let report = JsonReport {
+            schema_version: SCHEMA_VERSION,
+            tests: vec![JsonTestResult {
+                test: "my-tests/src/main.rs".to_string(),
+                status: "passed".to_string(),
+                matched_checks: vec![JsonCheckMatch {
+                    check_line: 4,
+                    output_line: 7,
+                    output: "$1 = 1".to_string(),
+                }],
+                ..JsonTestResult::default()
+            }],
+            time_breakdown: JsonTimeBreakdown {
+                build_ms: Some(1500),
+                debugger_ms: [("gdb".to_string(), 300)].into_iter().collect(),
+            },
+            metadata: Some(RunMetadata {
+                dbt_version: "0.1.0".to_string(),
+                host_os: "linux".to_string(),
+                host_arch: "x86_64".to_string(),
+                command_line: vec!["dbt".to_string(), "-d".to_string(), "gdb".to_string()],
+                cargo_profiles: vec!["debug".to_string()],
+                targets: vec![],
+                toolchains: vec![ToolchainMetadata {
+                    toolchain: None,
+                    rustc_version: Some("rustc 1.80.0 (051478957 2024-07-21)".to_string()),
+                }],
+                debuggers: vec![DebuggerMetadata {
+                    kind: "gdb".to_string(),
+                    version: "15.1".to_string(),
+                    command: "gdb".to_string(),
+                }],
+            }),
+        };
+        let json = serde_json::to_string(&report).unwrap();
+        assert_eq!(JsonReport::from_json(&json).unwrap(), report);
+
+        // Unknown fields are ignored, and missing ones default to empty
+        let report = JsonReport::from_json(
+            r#"{"schema_version": 1, "tests": [{"test": "t1", "status": "new", "extra": 1}]}"#,
+        )
+        .unwrap();
+        assert_eq!(report.tests[0].status, "new");
+        assert!(report.tests[0].matched_checks.is_empty());
+        assert_eq!(report.metadata, None);
+
+        assert_eq!(
+            JsonReport::from_json(r#"{"schema_version": 2, "tests": []}"#)
+                .unwrap_err()
+                .to_string(),
+            "The JSON report has schema version 2, but only version 1 is supported"
+        );
+    }
+}
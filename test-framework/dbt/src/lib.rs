@@ -1,20 +1,68 @@
 use std::{borrow::Cow, path::Path};
 
+pub mod baseline;
+pub mod benchmark;
+pub mod bisect;
 pub mod breakpoints;
+pub mod build_variants;
+pub mod bundle;
+pub mod cargo_metadata;
+pub mod cargo_profiles;
 pub mod cargo_test_directory;
+pub mod ci;
+pub mod codesign;
+pub mod consistency;
+pub mod coverage;
+pub mod dbgeng;
 pub mod debugger;
+pub mod diagnostics;
+pub mod distributed;
+pub mod embedded;
+pub mod formatter;
+pub mod hang;
+pub mod history;
 pub mod import_export;
+pub mod json_report;
+pub mod library_runner;
+pub mod lint;
+pub mod manifest;
+pub mod metadata;
+pub mod minimize;
+pub mod notify;
+pub mod recording;
 pub mod regex_check;
+pub mod reproducible;
+pub mod scaffold;
 pub mod script;
+pub mod static_checks;
+pub mod support_crate;
+pub mod test_config;
 pub mod test_result;
+pub mod toolchains;
+pub mod ttd;
+pub mod wine;
 pub mod workflow;
 
+/// The version of dbt, as checked by `#min-dbt-version`
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Returns a hash of `data` as a hex string. Unlike `DefaultHasher`, the hash is guaranteed
+/// to be the same across runs and versions of dbt, so it can be persisted.
+pub fn stable_hash(data: &[u8]) -> String {
+    // 64-bit FNV-1a
+    let hash = data.iter().fold(0xcbf29ce484222325u64, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    });
+
+    format!("{:016x}", hash)
+}
+
 pub fn prettify_path(path: &Path) -> Cow<'_, str> {
     let string = path.to_string_lossy();
     const PREFIX: &str = r"\\?\";
-    if string.starts_with(PREFIX) {
-        if Path::new(&string[PREFIX.len()..]).exists() {
-            return Cow::from(string[PREFIX.len()..].to_string());
+    if let Some(stripped) = string.strip_prefix(PREFIX) {
+        if Path::new(stripped).exists() {
+            return Cow::from(stripped.to_string());
         }
     }
 
@@ -0,0 +1,167 @@
+//! Tests of dynamic libraries: a package whose `src/lib.rs` contains a test script and
+//! whose Cargo.toml has `crate-type = ["cdylib"]` (or `dylib`) is compiled to a library,
+//! which is loaded at runtime by the library runner (`dbt-runner` in this repository). The
+//! debugger starts the runner, which gets the path of the library via `DBT_LIBRARY`.
+
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+
+use crate::{cargo_test_directory::BuildConfig, prettify_path};
+
+/// The environment variable that passes the path of the library to the runner
+pub const LIBRARY_VARIABLE: &str = "DBT_LIBRARY";
+
+const SOURCE: &str = include_str!("../../dbt-runner/src/main.rs");
+
+const RUNNER_NAME: &str = "dbt-runner";
+
+/// The library a package is compiled to, if it is a `cdylib` or `dylib` crate
+pub fn library_file_name(package_directory: &Path) -> anyhow::Result<Option<OsString>> {
+    #[derive(Deserialize)]
+    struct Manifest {
+        lib: Option<Lib>,
+    }
+
+    #[derive(Deserialize)]
+    struct Lib {
+        name: Option<String>,
+        #[serde(rename = "crate-type", default)]
+        crate_type: Vec<String>,
+    }
+
+    let manifest_path = package_directory.join("Cargo.toml");
+    let manifest: Manifest = match toml::from_str(&std::fs::read_to_string(&manifest_path)?) {
+        Ok(manifest) => manifest,
+        Err(e) => bail!("{}: {}", prettify_path(&manifest_path), e),
+    };
+
+    let lib = match manifest.lib {
+        Some(lib) if lib.crate_type.iter().any(|t| t == "cdylib" || t == "dylib") => lib,
+        _ => return Ok(None),
+    };
+
+    let crate_name = match lib.name {
+        Some(name) => name,
+        None => package_directory
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .replace('-', "_"),
+    };
+
+    let file_name = if cfg!(target_os = "windows") {
+        format!("{}.dll", crate_name)
+    } else if cfg!(target_os = "macos") {
+        format!("lib{}.dylib", crate_name)
+    } else {
+        format!("lib{}.so", crate_name)
+    };
+
+    Ok(Some(file_name.into()))
+}
+
+/// The path of the runner compiled into `directory` by [compile]
+pub fn runner_path(directory: &Path) -> PathBuf {
+    let path = directory.join(RUNNER_NAME);
+
+    if cfg!(target_os = "windows") {
+        path.with_extension("exe")
+    } else {
+        path
+    }
+}
+
+/// Compiles the runner for `build` into `directory`, unless it is already there, returning
+/// its path. Like the support crate, it is compiled with the toolchain Cargo uses for the
+/// test workspace, by running `rustc` in `workspace_dir`.
+pub fn compile(
+    workspace_dir: &Path,
+    directory: &Path,
+    build: &BuildConfig,
+) -> anyhow::Result<PathBuf> {
+    std::fs::create_dir_all(directory)?;
+
+    let source_path = directory.join("main.rs");
+    let runner_path = runner_path(directory);
+
+    let up_to_date = runner_path.exists()
+        && std::fs::read_to_string(&source_path).is_ok_and(|source| source == SOURCE);
+
+    if up_to_date {
+        return Ok(runner_path);
+    }
+
+    std::fs::write(&source_path, SOURCE)?;
+
+    let mut command = Command::new("rustc");
+
+    if let Some(toolchain) = &build.toolchain {
+        command.arg(format!("+{}", toolchain));
+    }
+
+    command
+        .arg("--crate-name")
+        .arg(RUNNER_NAME.replace('-', "_"))
+        .arg("--edition=2021")
+        .arg("-Cdebuginfo=2")
+        .arg("-o")
+        .arg(&runner_path);
+
+    if let Some(target) = &build.target {
+        command.arg("--target").arg(&target[..]);
+    }
+
+    let output = command
+        .arg(&source_path)
+        .current_dir(workspace_dir)
+        .output()
+        .context("Could not run `rustc` to compile the library runner")?;
+
+    if !output.status.success() {
+        bail!(
+            "Could not compile the library runner at {}:\n{}",
+            prettify_path(&source_path),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(runner_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    #[test]
+    fn library_file_name() {
+        let directory = std::env::temp_dir().join("dbt-library-runner-test/my-plugin");
+        std::fs::create_dir_all(&directory).unwrap();
+
+        let manifest = directory.join("Cargo.toml");
+        let file_name = |text: &str| {
+            std::fs::write(&manifest, text).unwrap();
+            super::library_file_name(&directory).unwrap()
+        };
+
+        assert_eq!(file_name("[package]\nname = \"my-plugin\"\n"), None);
+        assert_eq!(file_name("[lib]\ncrate-type = [\"rlib\"]\n"), None);
+
+        let expected = if cfg!(target_os = "windows") {
+            "my_plugin.dll"
+        } else if cfg!(target_os = "macos") {
+            "libmy_plugin.dylib"
+        } else {
+            "libmy_plugin.so"
+        };
+        assert_eq!(
+            file_name("[lib]\ncrate-type = [\"cdylib\"]\n").unwrap(),
+            Path::new(expected).as_os_str()
+        );
+    }
+}
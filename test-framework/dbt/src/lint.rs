@@ -0,0 +1,328 @@
+//! Static analysis of test scripts (`dbt lint`). The scripts are evaluated against every
+//! debugger given via `-d` and every Cargo profile, without compiling or running anything,
+//! to find `#if` branches that never apply and checks that are never run.
+
+use std::{
+    collections::{BTreeMap, HashSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use crate::{
+    cargo_test_directory::TestDefinition,
+    debugger::Debugger,
+    prettify_path,
+    script::{EvaluationContext, LineNumber, PhaseConfig, Script, Statement},
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Warning {
+    pub source_path: PathBuf,
+    /// `None` for warnings about the test as a whole
+    pub line_number: Option<LineNumber>,
+    pub message: String,
+}
+
+/// Analyzes the scripts of the given tests. The instantiations of a test with `#params`
+/// directives are analyzed together, so a branch is only reported if it never applies to
+/// any of them.
+pub fn lint(
+    test_definitions: &[&TestDefinition],
+    debuggers: &[Debugger],
+    cargo_profiles: &[Arc<str>],
+) -> Vec<Warning> {
+    let mut by_source_path: BTreeMap<&Path, Vec<&TestDefinition>> = BTreeMap::new();
+
+    for test_definition in test_definitions {
+        by_source_path
+            .entry(&test_definition.absolute_source_path)
+            .or_default()
+            .push(test_definition);
+    }
+
+    let mut warnings = vec![];
+
+    for (source_path, test_definitions) in by_source_path {
+        let analyzed: Vec<(&Script, Vec<EvaluationContext>)> = test_definitions
+            .into_iter()
+            .map(|test_definition| {
+                (
+                    &test_definition.script,
+                    contexts(test_definition, debuggers, cargo_profiles),
+                )
+            })
+            .collect();
+
+        for (line_number, message) in dead_code(&analyzed) {
+            warnings.push(Warning {
+                source_path: source_path.to_owned(),
+                line_number,
+                message,
+            });
+        }
+    }
+
+    warnings
+}
+
+/// The contexts the script of a test is evaluated in: one per debugger, Cargo profile and phase
+fn contexts(
+    test_definition: &TestDefinition,
+    debuggers: &[Debugger],
+    cargo_profiles: &[Arc<str>],
+) -> Vec<EvaluationContext> {
+    let mut contexts = vec![];
+
+    for debugger in debuggers {
+        for cargo_profile in cargo_profiles {
+            let live_context = debugger.evaluation_context(
+                &test_definition.config,
+                cargo_profile,
+                &PhaseConfig::Live,
+            );
+
+            for phase in test_definition.script.phases(&live_context) {
+                contexts.push(debugger.evaluation_context(
+                    &test_definition.config,
+                    cargo_profile,
+                    &phase,
+                ));
+            }
+        }
+    }
+
+    contexts
+}
+
+/// The `#if` blocks and checks that apply in at least one context, by line number
+#[derive(Default)]
+struct Reached {
+    if_blocks: HashSet<u32>,
+    checks: HashSet<u32>,
+}
+
+impl Reached {
+    /// Checks are only counted if the test is not ignored in `context`, but `#if` blocks
+    /// are, as they may be what makes the test ignored.
+    fn visit(&mut self, statements: &[Statement], context: &EvaluationContext, ignored: bool) {
+        for statement in statements {
+            match statement {
+                Statement::IfBlock(condition, nested, line_number) if condition.eval(context) => {
+                    self.if_blocks.insert(line_number.0);
+                    self.visit(nested, context, ignored);
+                }
+                Statement::Group(_, nested, _) | Statement::EachHit(_, _, nested, _) => {
+                    self.visit(nested, context, ignored)
+                }
+                statement if is_check(statement) && !ignored => {
+                    self.checks.insert(statement.line_number().0);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn is_check(statement: &Statement) -> bool {
+    matches!(
+        statement,
+        Statement::Check(..) | Statement::CheckUnorderedBlock(..) | Statement::CommandCheck(..)
+    )
+}
+
+fn count_checks(statements: &[Statement]) -> usize {
+    statements
+        .iter()
+        .map(|statement| match statement {
+            Statement::IfBlock(_, nested, _)
+            | Statement::Group(_, nested, _)
+            | Statement::EachHit(_, _, nested, _) => count_checks(nested),
+            statement if is_check(statement) => 1,
+            _ => 0,
+        })
+        .sum()
+}
+
+/// Returns the `#if` branches that don't apply to any of the contexts and the checks that
+/// are not run in any of them, for the given instantiations of one test. Contexts in which
+/// the test is ignored via `#ignore-test` don't run any checks.
+fn dead_code(analyzed: &[(&Script, Vec<EvaluationContext>)]) -> Vec<(Option<LineNumber>, String)> {
+    let mut reached = Reached::default();
+    let mut any_context = false;
+
+    for (script, contexts) in analyzed {
+        for context in contexts {
+            let ignored = script.ignore_test(context).is_some();
+            reached.visit(&script.statements, context, ignored);
+            any_context |= !ignored;
+        }
+    }
+
+    if !any_context {
+        return vec![(
+            None,
+            "the test is ignored for all configured debuggers and Cargo profiles".to_string(),
+        )];
+    }
+
+    fn report(
+        statements: &[Statement],
+        reached: &Reached,
+        warnings: &mut Vec<(Option<LineNumber>, String)>,
+    ) {
+        for statement in statements {
+            match statement {
+                Statement::IfBlock(_, nested, line_number) => {
+                    if reached.if_blocks.contains(&line_number.0) {
+                        report(nested, reached, warnings);
+                    } else {
+                        let message = match count_checks(nested) {
+                            0 => "`#if` branch never applies".to_string(),
+                            1 => {
+                                "`#if` branch never applies, so its check is never run".to_string()
+                            }
+                            count => format!(
+                                "`#if` branch never applies, so its {} checks are never run",
+                                count
+                            ),
+                        };
+                        warnings.push((Some(*line_number), message));
+                    }
+                }
+                Statement::Group(_, nested, _) | Statement::EachHit(_, _, nested, _) => {
+                    report(nested, reached, warnings)
+                }
+                statement if is_check(statement) => {
+                    let line_number = statement.line_number();
+
+                    // `#each-hit` repeats its checks, they are only reported once
+                    let reported = warnings.iter().any(|(reported, _)| {
+                        reported.is_some_and(|reported| reported.0 == line_number.0)
+                    });
+
+                    if !reached.checks.contains(&line_number.0) && !reported {
+                        warnings.push((Some(line_number), "check is never run".to_string()));
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    // All instantiations have the same statements on the same lines, only the values
+    // substituted for the parameters differ
+    let mut warnings = vec![];
+    report(&analyzed[0].0.statements, &reached, &mut warnings);
+    warnings
+}
+
+pub fn print_warnings(warnings: &[Warning]) {
+    for warning in warnings {
+        match warning.line_number {
+            Some(line_number) => println!(
+                "{}:{}: warning: {}",
+                prettify_path(&warning.source_path),
+                line_number.0,
+                warning.message
+            ),
+            None => println!(
+                "{}: warning: {}",
+                prettify_path(&warning.source_path),
+                warning.message
+            ),
+        }
+    }
+
+    println!();
+    println!("{} warnings", warnings.len());
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::script::{parse_script, EvaluationContext, LineNumber};
+
+    fn context(values: &[(&str, &str)]) -> EvaluationContext {
+        EvaluationContext {
+            values: values
+                .iter()
+                .map(|(name, value)| (name.to_string(), (*value).into()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn dead_code() {
+        let script = parse_script(
+            r#"
+/***
+#if @gdb
+  print a
+  #check a
+#if @lldb
+  v a
+  #check a
+  #check b
+#if @gdb
+  #if @version < 10
+    #check c
+  #if @version >= 12
+    #check d
+***/
+"#,
+            None,
+        )
+        .unwrap();
+
+        let contexts = vec![
+            context(&[("@gdb", "true"), ("@version", "12.1")]),
+            context(&[("@cdb", "true"), ("@version", "10.0")]),
+        ];
+
+        let warnings: Vec<(u32, String)> = super::dead_code(&[(&script, contexts)])
+            .into_iter()
+            .map(|(line_number, message)| (line_number.map_or(0, |l| l.0), message))
+            .collect();
+
+        assert_eq!(
+            warnings,
+            vec![
+                (
+                    6,
+                    "`#if` branch never applies, so its 2 checks are never run".to_string()
+                ),
+                (
+                    11,
+                    "`#if` branch never applies, so its check is never run".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn ignored_everywhere() {
+        let script = parse_script(
+            r#"
+/***
+#if @gdb
+  #ignore-test
+print a
+#check a
+#if @gdb
+  #check g
+***/
+"#,
+            None,
+        )
+        .unwrap();
+
+        let warnings = super::dead_code(&[(&script, vec![context(&[("@gdb", "true")])])]);
+        assert_eq!(warnings.len(), 1);
+        assert_eq!(warnings[0].0, None::<LineNumber>);
+
+        let contexts = vec![context(&[("@gdb", "true")]), context(&[("@lldb", "true")])];
+        assert_eq!(
+            super::dead_code(&[(&script, contexts)]),
+            vec![(Some(LineNumber(8)), "check is never run".to_string())]
+        );
+    }
+}
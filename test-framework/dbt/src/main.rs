@@ -1,13 +1,42 @@
-use dbt::{import_export, workflow};
+use anyhow::bail;
+use dbt::{
+    benchmark::Benchmark,
+    bisect::{self, Outcome},
+    bundle, cargo_profiles,
+    cargo_test_directory::{BuildConfig, CargoWorkspace, TestDefinition},
+    codesign,
+    debugger::{Debugger, DebuggerKind, DebuggerSelector, LldbScriptMode, SchedulerLocking},
+    distributed::{self, RemoteDebugger, RemoteTestResult, Request, Response},
+    embedded::{self, GdbServer, GdbServerKind},
+    history::History,
+    import_export::{self, GeneratedCrashDump},
+    json_report::JsonReport,
+    metadata::RunMetadata,
+    notify::{self, RunSummary},
+    prettify_path, reproducible,
+    static_checks::StaticTools,
+    test_result::{Status, TestResult},
+    toolchains, workflow,
+};
 use regex::Regex;
-use std::{ffi::OsString, path::PathBuf};
+use std::{
+    collections::BTreeSet,
+    ffi::OsString,
+    net::TcpListener,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
-use clap::Parser;
+use clap::{Parser, Subcommand};
 
 #[derive(Debug, Parser)]
 #[command(name = "DBT", about = "A tool for testing debugger extensions.")]
 struct Opt {
-    #[arg(long = "cargo-workspace")]
+    #[command(subcommand)]
+    command: Option<SubCommand>,
+
+    #[arg(long = "cargo-workspace", global = true)]
     cargo_workspace: Vec<PathBuf>,
 
     #[arg(
@@ -20,23 +49,42 @@ struct Opt {
     #[arg(short = 'd', long = "debugger")]
     debuggers: Vec<PathBuf>,
 
+    #[arg(
+        long = "only-debugger",
+        value_name = "KIND[@VERSION]",
+        help = "only run tests with the debuggers given via -d that match, e.g. `gdb` or \
+                `lldb@18`"
+    )]
+    only_debuggers: Vec<String>,
+
+    #[arg(
+        long = "skip-debugger",
+        value_name = "KIND[@VERSION]",
+        help = "don't run tests with the debuggers given via -d that match, e.g. `cdb` or \
+                `gdb@12`"
+    )]
+    skip_debuggers: Vec<String>,
+
     #[arg(
         short = 'p',
         long = "debugger-prelude",
-        help = "a string of the form <debugger-kind>:<debugger command to \
-                execute a beginning of each test script>"
+        help = "a string of the form <debugger-kind>[@<version>][/<placement>]:<debugger \
+                command to execute a beginning of each test script>, where <placement> is \
+                `before-generated`, `after-breakpoints` or `before-run`"
     )]
     debugger_prelude: Vec<OsString>,
 
     #[arg(
         long = "debugger-arg",
-        help = "commandline argument to be passed to the debugger"
+        help = "a string of the form <debugger-kind>[@<version>]:<argument>, passing the argument \
+                on the commandline of every debugger of that kind (and version), e.g. \
+                `gdb:--data-directory=/opt/gdb/share` or `gdb@14:-nx`"
     )]
     debugger_commandline_args: Vec<OsString>,
 
     #[arg(
         long = "debugger-env",
-        help = "a string of the form <debugger-kind>:<env-var-name>=<env-var-value>"
+        help = "a string of the form <debugger-kind>[@<version>]:<env-var-name>=<env-var-value>"
     )]
     debugger_env: Vec<OsString>,
 
@@ -50,13 +98,25 @@ struct Opt {
 
     #[arg(
         long = "cargo-profile",
-        help = "the Cargo profile(s) to be used for compiling test cases"
+        visible_alias = "profile",
+        value_parser = parse_cargo_profile,
+        help = "the Cargo profile(s) to be used for compiling test cases: `debug` (or `dev`), \
+                `release`, one of the build variants `line-tables-only`, `strip-debuginfo` and \
+                `strip-symbols`, or a profile declared in the test workspace"
     )]
     cargo_profiles: Vec<String>,
 
     #[arg(long)]
     verbose: bool,
 
+    #[arg(
+        long = "verbose-test",
+        value_name = "TEST",
+        help = "stream the debugger output of the given test (its name as printed when running \
+                it) to the console while it runs"
+    )]
+    verbose_test: Option<String>,
+
     #[arg(
         long = "export-crashdumps",
         help = "export generated crashdumps to `<output>/exported_crashdumps.tar.gz`"
@@ -72,6 +132,7 @@ struct Opt {
     #[arg(
         short = 'D',
         long = "define",
+        global = true,
         help = "define a value `xyz` that will be available as `@xyz` in test scripts"
     )]
     defines: Vec<String>,
@@ -79,6 +140,7 @@ struct Opt {
     #[arg(
         short = 't',
         long = "test-pattern",
+        global = true,
         help = "only run tests that match the given pattern"
     )]
     test_pattern: Option<String>,
@@ -95,6 +157,389 @@ struct Opt {
         help = "skip rebuilding test cases before running tests"
     )]
     skip_rebuild: bool,
+
+    #[arg(
+        long = "support-crate",
+        help = "make the support crate available to test programs as `dbt_support`, which \
+                replaces the rustflags of .cargo/config.toml files"
+    )]
+    support_crate: bool,
+
+    #[arg(
+        long = "reproducible",
+        help = "compile the tests with their paths remapped via --remap-path-prefix and with \
+                SOURCE_DATE_EPOCH pinned, and tell the debuggers where to find the sources"
+    )]
+    reproducible: bool,
+
+    #[arg(
+        long = "no-default-settings",
+        help = "don't begin debugger scripts with dbt's default settings, like `set print pretty on` \
+                for GDB"
+    )]
+    no_default_settings: bool,
+
+    #[arg(
+        long = "no-fault-dumps",
+        help = "don't have the debuggers dump the debuggee when it faults during a live test"
+    )]
+    no_fault_dumps: bool,
+
+    #[arg(
+        long = "no-forced-locale",
+        help = "run the debuggers with the locale of the system instead of `C.UTF-8`, which \
+                keeps their messages in English"
+    )]
+    no_forced_locale: bool,
+
+    #[arg(
+        long = "lldb-script-mode",
+        value_name = "[OS:]MODE",
+        help = "how LLDB is given the debugger script: `source` (via `--source`, the default), \
+                `one-liners` (a `-o` per command) or `command-source` (via `-o \"command \
+                source\"`), optionally only on the given OS, e.g. `macos:one-liners`"
+    )]
+    lldb_script_modes: Vec<String>,
+
+    #[arg(
+        long = "gdb-non-stop",
+        help = "run the debuggees in GDB's non-stop mode, where only the thread that stopped is \
+                stopped, for tests that don't set `gdb-non-stop` in their dbt-test.toml"
+    )]
+    gdb_non_stop: bool,
+
+    #[arg(
+        long = "gdb-scheduler-locking",
+        value_name = "MODE",
+        help = "set GDB's `scheduler-locking` to `off`, `on`, `step` or `replay`, for tests that \
+                don't set `gdb-scheduler-locking` in their dbt-test.toml"
+    )]
+    gdb_scheduler_locking: Option<String>,
+
+    #[arg(
+        long = "check-filter",
+        value_name = "SUBSTRING",
+        help = "only evaluate the checks whose line in the test's source contains SUBSTRING, \
+                running the rest of the script as usual; tests without such checks are skipped"
+    )]
+    check_filter: Option<String>,
+
+    #[arg(
+        long = "record",
+        help = "save the output of each debugger session to the given directory, for use with `--replay`"
+    )]
+    record: Option<PathBuf>,
+
+    #[arg(
+        long = "replay",
+        conflicts_with = "record",
+        help = "replay the debugger sessions recorded via `--record` in the given directory \
+                instead of running the debuggers"
+    )]
+    replay: Option<PathBuf>,
+
+    #[arg(
+        long = "timeout",
+        help = "the number of seconds after which a debugger running a test is killed"
+    )]
+    timeout: Option<u64>,
+
+    #[arg(
+        long = "command-timeout",
+        help = "the number of seconds after which a debugger still running the same command \
+                (or block of commands) of a test is killed"
+    )]
+    command_timeout: Option<u64>,
+
+    #[arg(
+        long = "dwarfdump",
+        default_value = "llvm-dwarfdump",
+        help = "the llvm-dwarfdump executable to run `#dwarfdump-check` statements with"
+    )]
+    dwarfdump: OsString,
+
+    #[arg(
+        long = "pdbutil",
+        default_value = "llvm-pdbutil",
+        help = "the llvm-pdbutil (or cvdump) executable to run `#pdb-check` statements with"
+    )]
+    pdbutil: OsString,
+
+    #[arg(
+        long = "ttd",
+        help = "the TTD.exe (or tttracer.exe) executable to record traces for `#phase ttd` with [default: TTD.exe]"
+    )]
+    ttd: Option<OsString>,
+
+    #[arg(
+        long = "gdb-server",
+        help = "run GDB tests against an embedded target by starting the given GDB server for \
+                each test, and flashing the test program via `target extended-remote`"
+    )]
+    gdb_server: Option<GdbServerKind>,
+
+    #[arg(
+        long = "codesign",
+        num_args = 0..=1,
+        default_missing_value = codesign::AD_HOC_IDENTITY,
+        value_name = "IDENTITY",
+        help = "sign the test programs (ad-hoc, unless an identity is given) with the \
+                `get-task-allow` entitlement before running them, so LLDB may debug them on macOS"
+    )]
+    codesign: Option<String>,
+
+    #[arg(
+        long = "wine",
+        num_args = 0..=1,
+        default_missing_value = "wine",
+        value_name = "WINE",
+        help = "run debuggers given as `.exe` (i.e. CDB) under Wine, defining `@wine` for them \
+                (experimental)"
+    )]
+    wine: Option<OsString>,
+
+    #[arg(
+        long = "gdb-server-command",
+        requires = "gdb_server",
+        help = "the executable to run for `--gdb-server` [default: openocd or probe-rs]"
+    )]
+    gdb_server_command: Option<OsString>,
+
+    #[arg(
+        long = "gdb-server-port",
+        default_value = "3333",
+        help = "the port the GDB server requested via `--gdb-server` listens on"
+    )]
+    gdb_server_port: u16,
+
+    #[arg(
+        long = "openocd-config",
+        help = "a config file for `--gdb-server openocd`, like `interface/stlink.cfg`"
+    )]
+    openocd_configs: Vec<PathBuf>,
+
+    #[arg(
+        long = "chip",
+        help = "the chip for `--gdb-server probe-rs`, like `STM32F411RETx`"
+    )]
+    chip: Option<String>,
+
+    #[arg(
+        long = "stress",
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "run each test the given number of times and report how often it passed"
+    )]
+    stress: Option<u64>,
+
+    #[arg(
+        long = "stress-parallel",
+        requires = "stress",
+        help = "allow the runs of a test requested via `--stress` to happen in parallel"
+    )]
+    stress_parallel: bool,
+
+    #[arg(
+        long = "toolchain",
+        help = "the Rust toolchain(s) to compile test cases with, e.g. `nightly` (requires rustup)"
+    )]
+    toolchains: Vec<String>,
+
+    #[arg(
+        long = "install-toolchains",
+        help = "install the toolchains given via `--toolchain` via rustup if they are missing, \
+                instead of failing, and add the targets given via `--target` to them"
+    )]
+    install_toolchains: bool,
+
+    #[arg(
+        long = "target",
+        help = "the target triple(s) to compile test cases for"
+    )]
+    targets: Vec<String>,
+
+    #[arg(
+        long = "only-target",
+        help = "only compile and run test cases for the given target triple(s) out of the ones \
+                given via --target"
+    )]
+    only_targets: Vec<String>,
+
+    #[arg(
+        long = "matrix",
+        help = "print the status of each test for every combination of debugger, \
+                Cargo profile, toolchain and target it ran with"
+    )]
+    matrix: bool,
+
+    #[arg(
+        long = "coverage",
+        help = "report which variables declared in the test programs are never inspected by \
+                the test scripts"
+    )]
+    coverage: bool,
+
+    #[arg(
+        long = "consistency",
+        help = "report values printed via `#print` that are rendered differently by \
+                different debuggers"
+    )]
+    consistency: bool,
+
+    #[arg(
+        long = "benchmark",
+        help = "a file in which the time each debugger command took is kept across runs, for \
+                reporting commands that are much slower than usual"
+    )]
+    benchmark: Option<PathBuf>,
+
+    #[arg(
+        long = "history",
+        help = "a file in which the results of recent runs are kept, for detecting flaky tests"
+    )]
+    history: Option<PathBuf>,
+
+    #[arg(
+        long = "agent",
+        value_name = "HOST:PORT",
+        help = "also run the tests on the machine where `dbt agent` listens at the given address, \
+                with the debuggers available there"
+    )]
+    agents: Vec<String>,
+
+    #[arg(
+        long = "emit-metadata",
+        value_name = "FILE",
+        help = "write the environment of the run (host, rustc and debugger versions, flags) to \
+                the given JSON file, and print it along with the report"
+    )]
+    emit_metadata: Option<PathBuf>,
+
+    #[arg(
+        long = "json-report",
+        value_name = "FILE",
+        help = "write the results to the given JSON file, including which line of the debugger \
+                output each check matched"
+    )]
+    json_report: Option<PathBuf>,
+
+    #[arg(
+        long = "notify-url",
+        value_name = "URL",
+        help = "POST a summary of the results as JSON to the given URL when the run finishes \
+                (via `curl`)"
+    )]
+    notify_url: Option<String>,
+
+    #[arg(
+        long = "notify-command",
+        value_name = "COMMAND",
+        help = "run the given shell command with a summary of the results as JSON on its stdin \
+                when the run finishes"
+    )]
+    notify_command: Option<String>,
+}
+
+#[derive(Debug, Subcommand)]
+enum SubCommand {
+    /// Re-run the checks of the tests against the debugger output captured by a previous
+    /// run, without rebuilding the tests or running any debuggers
+    Recheck {
+        /// the output directory of the previous run
+        artifacts_dir: PathBuf,
+    },
+
+    /// Reduce the script of a failing test to a minimal one that still fails in the same
+    /// way, and print it
+    Minimize {
+        /// the name of the test, as printed when running it
+        test: String,
+
+        /// the kind of debugger (e.g. `gdb`, or `gdb@14` for a specific version) to use if more
+        /// than one is given via `-d`
+        #[arg(long = "debugger")]
+        debugger_kind: Option<String>,
+    },
+
+    /// Run the commands of a test and insert `#check` statements matching their output
+    /// into the test's script
+    ScaffoldChecks {
+        /// the name of the test, as printed when running it
+        test: String,
+
+        /// the kind of debugger (e.g. `gdb`, or `gdb@14` for a specific version) to use if more
+        /// than one is given via `-d`
+        #[arg(long = "debugger")]
+        debugger_kind: Option<String>,
+    },
+
+    /// Find the first nightly toolchain with which a test fails, installing toolchains
+    /// via rustup as needed
+    Bisect {
+        /// the name of the test, as printed when running it
+        #[arg(long)]
+        test: String,
+
+        /// the last known good toolchain, e.g. `nightly-2024-01-01`
+        #[arg(long)]
+        from: String,
+
+        /// the first known bad toolchain, e.g. `nightly-2024-06-01`
+        #[arg(long)]
+        to: String,
+    },
+
+    /// Check the test scripts for `#if` branches that never apply and checks that are never
+    /// run with any of the debuggers given via `-d` and the Cargo profiles, without compiling
+    /// or running the tests
+    Lint,
+
+    /// Format the test scripts: indent nested blocks by two spaces per level and normalize
+    /// the whitespace in directives
+    Fmt {
+        /// list the test programs whose scripts are not formatted and fail, instead of
+        /// formatting them, e.g. for CI
+        #[arg(long)]
+        check: bool,
+    },
+
+    /// Save or restore the compiled test cases (in `--cargo-target-directory`), e.g. for
+    /// caching them between CI runs
+    Cache {
+        #[command(subcommand)]
+        action: CacheAction,
+    },
+
+    /// Package the scripts, outputs and binaries of the tests that failed in a run into a
+    /// `.tar.zst` archive, e.g. for uploading them as a CI artifact
+    BundleFailures {
+        /// the archive to write
+        archive: PathBuf,
+
+        /// the JSON report of the run (see `--json-report`, which is used if not given)
+        #[arg(long)]
+        report: Option<PathBuf>,
+
+        /// files larger than this (in MiB), e.g. core files, are left out of the archive
+        #[arg(long, default_value_t = 256)]
+        max_file_size: u64,
+    },
+
+    /// Compile the tests and run them with the debuggers given via `-d` whenever a
+    /// coordinator (a run given `--agent`) asks for it
+    Agent {
+        /// the address to listen at, e.g. `0.0.0.0:7878`
+        #[arg(long)]
+        listen: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+enum CacheAction {
+    /// Bundle the cached state into a `.tar.gz` archive
+    Export { archive: PathBuf },
+
+    /// Restore the cached state from an archive created via `dbt cache export`
+    Import { archive: PathBuf },
 }
 
 fn main() -> anyhow::Result<()> {
@@ -112,29 +557,279 @@ fn main() -> anyhow::Result<()> {
         })
     });
 
+    let gdb_server = opt.gdb_server.map(|kind| GdbServer {
+        kind,
+        command: opt.gdb_server_command.clone(),
+        openocd_configs: opt.openocd_configs.clone(),
+        chip: opt.chip.clone(),
+        port: opt.gdb_server_port,
+    });
+
+    if let Some(SubCommand::Recheck { artifacts_dir }) = &opt.command {
+        // The scripts are generated like for a run, so that they match the saved ones
+        let (debugger_prelude, defines) = debugger_prelude_and_defines(&opt, gdb_server.as_ref());
+        let make_debugger = |kind, version| {
+            let mut debugger = Debugger::offline(kind, version, &debugger_prelude, &defines)?;
+            configure_debuggers(
+                &opt,
+                gdb_server.as_ref(),
+                std::slice::from_mut(&mut debugger),
+            )?;
+            Ok(debugger)
+        };
+
+        let mut test_results = Vec::new();
+
+        for cargo_test_directory in &opt.cargo_workspace {
+            test_results.extend(workflow::recheck(
+                artifacts_dir,
+                &CargoWorkspace::load(cargo_test_directory)?,
+                test_pattern.as_ref(),
+                &make_debugger,
+            )?);
+        }
+
+        if let Some(json_report_path) = &opt.json_report {
+            JsonReport::new(&test_results, None, None).save(json_report_path)?;
+        }
+
+        notify_run_finished(&opt, &RunSummary::new(&test_results));
+
+        let time_breakdown = dbt::test_result::time_breakdown(&test_results, None);
+
+        if !dbt::test_result::print_report(test_results, &time_breakdown) {
+            std::process::exit(1);
+        }
+        return Ok(());
+    }
+
+    if let Some(SubCommand::Cache { action }) = &opt.command {
+        return match action {
+            CacheAction::Export { archive } => import_export::export_cache(
+                &opt.cargo_workspace,
+                &opt.cargo_target_directory,
+                archive,
+            ),
+            CacheAction::Import { archive } => import_export::import_cache(
+                &opt.cargo_workspace,
+                &opt.cargo_target_directory,
+                archive,
+            ),
+        };
+    }
+
+    if let Some(SubCommand::Fmt { check }) = &opt.command {
+        return fmt(&opt, test_pattern.as_ref(), *check);
+    }
+
+    if let Some(SubCommand::BundleFailures {
+        archive,
+        report,
+        max_file_size,
+    }) = &opt.command
+    {
+        let report_path = match report.as_ref().or(opt.json_report.as_ref()) {
+            Some(report_path) => report_path,
+            None => bail!("bundle-failures needs the JSON report of the run, via --report"),
+        };
+
+        let cargo_workspaces = opt
+            .cargo_workspace
+            .iter()
+            .map(|path| CargoWorkspace::load(path))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+
+        let summary = bundle::bundle_failures(
+            &JsonReport::load(report_path)?,
+            &cargo_workspaces,
+            &bundle::RunDirectories {
+                output_dir: &opt.output_dir,
+                cargo_target_directory: &opt.cargo_target_directory,
+            },
+            archive,
+            max_file_size * 1024 * 1024,
+        )?;
+
+        println!(
+            "Bundled {} failing test result(s) into {}",
+            summary.failures,
+            prettify_path(archive)
+        );
+        for path in &summary.omitted {
+            println!("Left out {} for exceeding --max-file-size", path.display());
+        }
+        return Ok(());
+    }
+
     let output_dir = opt.output_dir.canonicalize()?;
 
     if let Some(import_crashdumps) = &opt.import_crashdumps {
         import_export::import_crashdumps(&output_dir, import_crashdumps)?;
     }
 
-    let debuggers = dbt::debugger::init_debuggers(
+    let (debugger_prelude, defines) = debugger_prelude_and_defines(&opt, gdb_server.as_ref());
+
+    let parse_selectors = |selectors: &[String]| -> anyhow::Result<Vec<DebuggerSelector>> {
+        selectors
+            .iter()
+            .map(|selector| DebuggerSelector::parse(selector))
+            .collect()
+    };
+    let only_debuggers = parse_selectors(&opt.only_debuggers)?;
+    let skip_debuggers = parse_selectors(&opt.skip_debuggers)?;
+
+    let probed_debuggers = dbt::debugger::probe_debuggers(
         &opt.debuggers,
-        &opt.debugger_prelude,
+        opt.wine.as_deref(),
+        &only_debuggers,
+        &skip_debuggers,
+    )?;
+    let debuggers = dbt::debugger::init_debuggers(
+        &probed_debuggers,
+        &debugger_prelude,
         &opt.debugger_commandline_args,
         &opt.debugger_env,
-        &opt.defines,
+        &defines,
+        opt.replay.as_deref(),
     )?;
+    // The recorded debuggers are only known now
+    let mut debuggers =
+        dbt::debugger::select_debuggers(debuggers, &only_debuggers, &skip_debuggers)?;
+    configure_debuggers(&opt, gdb_server.as_ref(), &mut debuggers)?;
+
+    if let Some(SubCommand::Lint) = &opt.command {
+        return lint(&opt, test_pattern.as_ref(), &debuggers);
+    }
+
+    let run_options = workflow::RunOptions {
+        verbose: opt.verbose,
+        verbose_test: opt.verbose_test.clone(),
+        record_directory: match &opt.record {
+            Some(record_directory) => {
+                std::fs::create_dir_all(record_directory)?;
+                Some(record_directory.canonicalize()?)
+            }
+            None => None,
+        },
+        timeout: opt.timeout.map(Duration::from_secs),
+        command_timeout: opt.command_timeout.map(Duration::from_secs),
+        stress_runs: opt.stress.map(|runs| runs as usize),
+        stress_parallel: opt.stress_parallel,
+        static_tools: StaticTools {
+            dwarfdump: opt.dwarfdump.clone(),
+            pdbutil: opt.pdbutil.clone(),
+        },
+        ttd: opt.ttd.clone(),
+        gdb_server,
+    };
+
+    if let Some(SubCommand::Bisect { test, from, to }) = &opt.command {
+        return bisect(&opt, test, from, to, &debuggers, &output_dir, &run_options);
+    }
+
+    if let Some(SubCommand::Minimize {
+        test,
+        debugger_kind,
+    }) = &opt.command
+    {
+        return minimize(
+            &opt,
+            test,
+            debugger_kind.as_deref(),
+            &debuggers,
+            &output_dir,
+            &run_options,
+        );
+    }
+
+    if let Some(SubCommand::ScaffoldChecks {
+        test,
+        debugger_kind,
+    }) = &opt.command
+    {
+        return scaffold_checks(
+            &opt,
+            test,
+            debugger_kind.as_deref(),
+            &debuggers,
+            &output_dir,
+            &run_options,
+        );
+    }
+
+    if let Some(target) = opt
+        .only_targets
+        .iter()
+        .find(|target| !opt.targets.contains(target))
+    {
+        bail!(
+            "--only-target {} is not one of the targets given via --target",
+            target
+        );
+    }
+
+    let targets: Vec<String> = opt
+        .targets
+        .iter()
+        .filter(|target| opt.only_targets.is_empty() || opt.only_targets.contains(target))
+        .cloned()
+        .collect();
+
+    let mut build_configs = Vec::new();
+
+    for toolchain in optional_values(&opt.toolchains) {
+        for target in optional_values(&targets) {
+            build_configs.push(BuildConfig {
+                toolchain: toolchain.clone(),
+                target: target.clone(),
+            });
+        }
+    }
+
+    if let Some(verbose_test) = &opt.verbose_test {
+        check_test_exists(&opt, verbose_test, "--verbose-test")?;
+    }
+
+    toolchains::ensure_installed(&opt.toolchains, &targets, opt.install_toolchains)?;
 
     let mut compiled_test_cases = Vec::new();
+    let build_start = Instant::now();
 
-    for cargo_test_directory in opt.cargo_workspace {
-        compiled_test_cases.push(workflow::compile_cargo_tests(
-            &cargo_test_directory,
-            &opt.cargo_target_directory,
+    for cargo_test_directory in &opt.cargo_workspace {
+        for build_config in &build_configs {
+            compiled_test_cases.push(workflow::compile_cargo_tests(
+                cargo_test_directory,
+                &opt.cargo_target_directory,
+                &opt.cargo_profiles,
+                build_config,
+                opt.skip_rebuild,
+                opt.support_crate,
+                opt.reproducible,
+            )?);
+        }
+    }
+
+    let build_duration = build_start.elapsed();
+
+    if let Some(identity) = &opt.codesign {
+        for compiled_test_cases in &compiled_test_cases {
+            workflow::sign_debuggees(compiled_test_cases, identity)?;
+        }
+    }
+
+    // Part of the JSON report as well
+    let metadata = (opt.emit_metadata.is_some() || opt.json_report.is_some()).then(|| {
+        RunMetadata::collect(
             &opt.cargo_profiles,
-            opt.skip_rebuild,
-        )?);
+            &opt.toolchains,
+            &targets,
+            &debuggers,
+            opt.cargo_workspace.first().map(PathBuf::as_path),
+        )
+    });
+
+    if let (Some(metadata_path), Some(metadata)) = (&opt.emit_metadata, &metadata) {
+        metadata.save(metadata_path)?;
     }
 
     let mut test_results = Vec::new();
@@ -147,31 +842,688 @@ fn main() -> anyhow::Result<()> {
         None
     };
 
-    for debugger in &debuggers {
+    if let Some(SubCommand::Agent { listen }) = &opt.command {
+        return agent(
+            listen,
+            &compiled_test_cases,
+            &debuggers,
+            &output_dir,
+            opt.test_threads,
+            &run_options,
+        );
+    }
+
+    let (results, generated_crashdumps) = run_tests(
+        &debuggers.iter().collect::<Vec<_>>(),
+        &compiled_test_cases,
+        &output_dir,
+        test_pattern.as_ref(),
+        opt.test_threads,
+        &run_options,
+    )?;
+
+    if let Some(ref mut crashdump_exporter) = crashdump_exporter {
+        for crashdump in generated_crashdumps {
+            crashdump_exporter.add_crashdump(crashdump)?;
+        }
+    }
+
+    test_results.extend(results);
+
+    drop(crashdump_exporter);
+
+    if !opt.agents.is_empty() {
+        let test_definitions: Vec<&TestDefinition> = compiled_test_cases
+            .iter()
+            .flat_map(|compiled_test_cases| {
+                compiled_test_cases.cargo_workspace.cargo_packages.iter()
+            })
+            .flat_map(|package| package.test_definitions.iter())
+            .filter(|test_definition| test_definition.matches(test_pattern.as_ref()))
+            .collect();
+
+        test_results.extend(distributed::run_on_agents(&opt.agents, &test_definitions)?);
+    }
+
+    if run_options.stress_runs.is_some() {
+        dbt::test_result::print_stress_report(&test_results);
+    }
+
+    if opt.coverage {
+        let mut coverage_entries = Vec::new();
+
         for compiled_test_cases in &compiled_test_cases {
-            let (results, generated_crashdumps) = workflow::run_cargo_tests(
+            let test_definitions: Vec<&TestDefinition> = compiled_test_cases
+                .cargo_workspace
+                .cargo_packages
+                .iter()
+                .flat_map(|package| package.test_definitions.iter())
+                .filter(|test_definition| test_definition.matches(test_pattern.as_ref()))
+                .collect();
+
+            coverage_entries.extend(dbt::coverage::coverage(
+                &test_definitions,
+                &debuggers,
+                &compiled_test_cases.cargo_profiles,
+            )?);
+        }
+
+        dbt::coverage::print_coverage_report(&dbt::coverage::deduplicate(coverage_entries));
+    }
+
+    if opt.matrix {
+        dbt::test_result::print_matrix_report(&test_results);
+    }
+
+    if opt.consistency {
+        dbt::consistency::print_consistency_report(&dbt::consistency::mismatches(&test_results));
+    }
+
+    let baselines: Vec<(&str, &str)> = compiled_test_cases
+        .iter()
+        .flat_map(|compiled_test_cases| compiled_test_cases.cargo_workspace.cargo_packages.iter())
+        .flat_map(|package| package.test_definitions.iter())
+        .filter_map(|test_definition| {
+            let rust_test_name = test_definition.baseline_of.as_ref()?;
+            Some((&test_definition.name[..], &rust_test_name[..]))
+        })
+        .collect();
+
+    if !baselines.is_empty() {
+        dbt::baseline::print_baseline_report(&dbt::baseline::differences(
+            &test_results,
+            &baselines,
+        ));
+    }
+
+    if let Some(benchmark_path) = &opt.benchmark {
+        let mut benchmark = Benchmark::load(benchmark_path)?;
+        let slow_commands = benchmark.add_results(&test_results);
+        benchmark.save(benchmark_path)?;
+
+        dbt::benchmark::print_slow_commands(&slow_commands);
+    }
+
+    if let Some(history_path) = &opt.history {
+        let mut history = History::load(history_path)?;
+        history.add_run(&test_results)?;
+        history.save(history_path)?;
+
+        dbt::history::print_flaky_tests(&history.flaky_tests());
+    }
+
+    if let Some(json_report_path) = &opt.json_report {
+        JsonReport::new(&test_results, Some(build_duration), metadata.clone())
+            .save(json_report_path)?;
+    }
+
+    if let (Some(_), Some(metadata)) = (&opt.emit_metadata, &metadata) {
+        println!();
+        print!("{}", metadata.render());
+    }
+
+    notify_run_finished(&opt, &RunSummary::new(&test_results));
+
+    let time_breakdown = dbt::test_result::time_breakdown(&test_results, Some(build_duration));
+
+    if !dbt::test_result::print_report(test_results, &time_breakdown) {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// The `--debugger-prelude` commands and `--define` values, along with the ones that other
+/// options imply
+fn debugger_prelude_and_defines(
+    opt: &Opt,
+    gdb_server: Option<&GdbServer>,
+) -> (Vec<OsString>, Vec<String>) {
+    let mut debugger_prelude = opt.debugger_prelude.clone();
+    let mut defines = opt.defines.clone();
+
+    if let Some(gdb_server) = gdb_server {
+        debugger_prelude.extend(
+            gdb_server
+                .gdb_prelude()
+                .into_iter()
+                .map(|command| OsString::from(format!("gdb:{}", command))),
+        );
+    }
+
+    if opt.reproducible {
+        defines.push(reproducible::DEFINE.to_string());
+    }
+
+    (debugger_prelude, defines)
+}
+
+/// Applies the options that change the debugger scripts or how the output is checked. Used
+/// for runs as well as for `dbt recheck`, whose scripts have to match the saved ones.
+fn configure_debuggers(
+    opt: &Opt,
+    gdb_server: Option<&GdbServer>,
+    debuggers: &mut [Debugger],
+) -> anyhow::Result<()> {
+    let lldb_script_mode = LldbScriptMode::for_host(&opt.lldb_script_modes)?;
+    let gdb_scheduler_locking = opt
+        .gdb_scheduler_locking
+        .as_deref()
+        .map(SchedulerLocking::parse)
+        .transpose()?;
+
+    for debugger in debuggers {
+        // Only GDB is attached to the GDB server, the other debuggers run as usual
+        if gdb_server.is_some() && debugger.kind == DebuggerKind::Gdb {
+            debugger.add_define(embedded::DEFINE);
+        }
+
+        if opt.no_default_settings {
+            debugger.disable_default_settings();
+        }
+
+        if opt.no_fault_dumps {
+            debugger.disable_fault_dumps();
+        }
+
+        if opt.no_forced_locale {
+            debugger.disable_forced_locale();
+        }
+
+        debugger.set_lldb_script_mode(lldb_script_mode);
+
+        if opt.gdb_non_stop {
+            debugger.enable_gdb_non_stop();
+        }
+
+        if let Some(scheduler_locking) = gdb_scheduler_locking {
+            debugger.set_gdb_scheduler_locking(scheduler_locking);
+        }
+
+        if let Some(check_filter) = &opt.check_filter {
+            debugger.set_check_filter(check_filter.as_str().into());
+        }
+
+        if opt.reproducible {
+            for cargo_workspace in &opt.cargo_workspace {
+                debugger.add_remapped_workspace(cargo_workspace.canonicalize()?);
+            }
+        }
+
+        if opt.benchmark.is_some() {
+            debugger.enable_command_fences();
+        }
+    }
+
+    Ok(())
+}
+
+/// Fails if none of the workspaces has a test named `test`, which `option` refers to, as
+/// that's most likely a typo.
+fn check_test_exists(opt: &Opt, test: &str, option: &str) -> anyhow::Result<()> {
+    for cargo_test_directory in &opt.cargo_workspace {
+        let has_test = CargoWorkspace::load(cargo_test_directory)?
+            .cargo_packages
+            .iter()
+            .flat_map(|package| package.test_definitions.iter())
+            .any(|test_definition| &test_definition.name[..] == test);
+
+        if has_test {
+            return Ok(());
+        }
+    }
+
+    bail!(
+        "{} {} does not match any test, expected a name like `my-tests/src/main.rs`",
+        option,
+        test
+    )
+}
+
+/// Sends the notifications requested via `--notify-url` and `--notify-command`. Failing to
+/// do so is reported, but doesn't fail the run.
+fn notify_run_finished(opt: &Opt, summary: &RunSummary) {
+    if let Some(url) = &opt.notify_url {
+        if let Err(e) = notify::post(summary, url) {
+            eprintln!("Could not notify {}: {:#}", url, e);
+        }
+    }
+
+    if let Some(command) = &opt.notify_command {
+        if let Err(e) = notify::run_command(summary, command) {
+            eprintln!("Could not notify via `{}`: {:#}", command, e);
+        }
+    }
+}
+
+/// The given values, or just `None` if there are none
+fn optional_values(values: &[String]) -> Vec<Option<Arc<str>>> {
+    if values.is_empty() {
+        vec![None]
+    } else {
+        values
+            .iter()
+            .map(|value| Some(Arc::from(&value[..])))
+            .collect()
+    }
+}
+
+/// The output directory for tests compiled with a toolchain or target other than the default,
+/// keeping their output apart
+fn output_dir_for_build(
+    output_dir: &Path,
+    compiled_test_cases: &workflow::CompiledTestCases,
+) -> anyhow::Result<PathBuf> {
+    if compiled_test_cases.build.is_default() {
+        Ok(output_dir.to_path_buf())
+    } else {
+        let output_dir = output_dir.join(compiled_test_cases.build.dir_name());
+        std::fs::create_dir_all(&output_dir)?;
+        Ok(output_dir)
+    }
+}
+
+/// Runs the tests matching `test_pattern` of each build with each of `debuggers`, for a run
+/// of its own as well as for a coordinator
+fn run_tests(
+    debuggers: &[&Debugger],
+    compiled_test_cases: &[workflow::CompiledTestCases],
+    output_dir: &Path,
+    test_pattern: Option<&Regex>,
+    test_threads: Option<usize>,
+    run_options: &workflow::RunOptions,
+) -> anyhow::Result<(Vec<TestResult>, Vec<GeneratedCrashDump>)> {
+    let mut test_results = vec![];
+    let mut generated_crashdumps = vec![];
+
+    for debugger in debuggers {
+        for compiled_test_cases in compiled_test_cases {
+            let output_dir = output_dir_for_build(output_dir, compiled_test_cases)?;
+
+            let (results, crashdumps) = workflow::run_cargo_tests(
                 compiled_test_cases,
                 debugger,
                 &output_dir,
-                test_pattern.as_ref(),
-                opt.test_threads,
-                opt.verbose,
+                test_pattern,
+                test_threads,
+                run_options,
+            )?;
+
+            test_results.extend(results);
+            generated_crashdumps.extend(crashdumps);
+        }
+    }
+
+    Ok((test_results, generated_crashdumps))
+}
+
+fn agent(
+    listen: &str,
+    compiled_test_cases: &[workflow::CompiledTestCases],
+    debuggers: &[Debugger],
+    output_dir: &Path,
+    test_threads: Option<usize>,
+    run_options: &workflow::RunOptions,
+) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(listen)?;
+    println!("Listening for coordinators at {}", listener.local_addr()?);
+
+    distributed::serve(listener, &mut |request| match request {
+        Request::Debuggers => Ok(Response {
+            debuggers: debuggers
+                .iter()
+                .map(|debugger| RemoteDebugger {
+                    kind: debugger.kind.name().to_string(),
+                    version: debugger.version.to_string(),
+                })
+                .collect(),
+            ..Response::default()
+        }),
+        Request::Run { debugger, tests } => {
+            let selector = DebuggerSelector::parse(&debugger)?;
+            let test_pattern = Regex::new(&format!(
+                "^(?:{})$",
+                tests
+                    .iter()
+                    .map(|test| regex::escape(test))
+                    .collect::<Vec<_>>()
+                    .join("|")
+            ))?;
+
+            let debuggers: Vec<&Debugger> = debuggers
+                .iter()
+                .filter(|debugger| selector.matches(debugger.kind, &debugger.version))
+                .collect();
+
+            let (test_results, _) = run_tests(
+                &debuggers,
+                compiled_test_cases,
+                output_dir,
+                Some(&test_pattern),
+                test_threads,
+                run_options,
             )?;
 
-            if let Some(ref mut crashdump_exporter) = crashdump_exporter {
-                for crashdump in generated_crashdumps {
-                    crashdump_exporter.add_crashdump(crashdump)?;
+            // Dependencies (#depends-on) are run as well, but reported by whichever agent they
+            // were assigned to
+            let results = test_results
+                .iter()
+                .filter(|test_result| tests.iter().any(|test| **test == *test_result.test_name))
+                .map(RemoteTestResult::new)
+                .collect();
+
+            Ok(Response {
+                results,
+                ..Response::default()
+            })
+        }
+    })
+}
+
+fn bisect(
+    opt: &Opt,
+    test: &str,
+    from: &str,
+    to: &str,
+    debuggers: &[Debugger],
+    output_dir: &Path,
+    run_options: &workflow::RunOptions,
+) -> anyhow::Result<()> {
+    let toolchains = bisect::nightly_toolchains(from, to)?;
+    let test_pattern = Regex::new(&format!("^{}$", regex::escape(test)))?;
+
+    let (last_good, first_bad) = bisect::bisect(&toolchains, |toolchain| {
+        if !toolchains::install(toolchain)? {
+            println!("Skipping toolchain `{}`: could not install it", toolchain);
+            return Ok(Outcome::Unusable);
+        }
+
+        let build_config = BuildConfig {
+            toolchain: Some(toolchain.into()),
+            target: None,
+        };
+
+        let output_dir = output_dir.join(build_config.dir_name());
+        std::fs::create_dir_all(&output_dir)?;
+
+        let mut test_results = Vec::new();
+
+        for cargo_test_directory in &opt.cargo_workspace {
+            let compiled_test_cases = match workflow::compile_cargo_tests(
+                cargo_test_directory,
+                &opt.cargo_target_directory,
+                &opt.cargo_profiles,
+                &build_config,
+                false,
+                opt.support_crate,
+                opt.reproducible,
+            ) {
+                Ok(compiled_test_cases) => compiled_test_cases,
+                Err(e) => {
+                    println!("Skipping toolchain `{}`: {}", toolchain, e);
+                    return Ok(Outcome::Unusable);
                 }
+            };
+
+            for debugger in debuggers {
+                let (results, _) = workflow::run_cargo_tests(
+                    &compiled_test_cases,
+                    debugger,
+                    &output_dir,
+                    Some(&test_pattern),
+                    opt.test_threads,
+                    run_options,
+                )?;
+
+                test_results.extend(results);
             }
+        }
 
-            test_results.extend_from_slice(&results);
+        if let Some(test_result) = test_results
+            .iter()
+            .find(|test_result| matches!(*test_result.status, Status::BuildFailed(_)))
+        {
+            println!(
+                "Skipping toolchain `{}`: could not compile {}",
+                toolchain, test_result.test_name
+            );
+            return Ok(Outcome::Unusable);
         }
+
+        let outcomes: Vec<bool> = test_results
+            .iter()
+            .filter_map(|test_result| test_result.status.passed())
+            .collect();
+
+        if outcomes.is_empty() {
+            bail!("No test named `{}` was run", test);
+        }
+
+        Ok(if outcomes.iter().all(|&passed| passed) {
+            Outcome::Passed
+        } else {
+            Outcome::Failed
+        })
+    })?;
+
+    println!();
+    println!(
+        "`{}` passes with `{}` and first fails with `{}`",
+        test, last_good, first_bad
+    );
+
+    Ok(())
+}
+
+fn parse_cargo_profile(cargo_profile: &str) -> Result<String, String> {
+    Ok(cargo_profiles::normalize(cargo_profile))
+}
+
+fn lint(opt: &Opt, test_pattern: Option<&Regex>, debuggers: &[Debugger]) -> anyhow::Result<()> {
+    if debuggers.is_empty() {
+        bail!("`dbt lint` needs at least one debugger given via `-d`");
     }
 
-    drop(crashdump_exporter);
+    let cargo_profiles: Vec<Arc<str>> = opt
+        .cargo_profiles
+        .iter()
+        .map(|cargo_profile| cargo_profile[..].into())
+        .collect();
+
+    let mut warnings = Vec::new();
+
+    for cargo_test_directory in &opt.cargo_workspace {
+        let cargo_workspace = CargoWorkspace::load(cargo_test_directory)?;
+        cargo_profiles::declare(&cargo_workspace.root_path, &opt.cargo_profiles)?;
+
+        let test_definitions: Vec<&TestDefinition> = cargo_workspace
+            .cargo_packages
+            .iter()
+            .flat_map(|package| package.test_definitions.iter())
+            .filter(|test_definition| test_definition.matches(test_pattern))
+            .collect();
+
+        warnings.extend(dbt::lint::lint(
+            &test_definitions,
+            debuggers,
+            &cargo_profiles,
+        ));
+    }
+
+    dbt::lint::print_warnings(&warnings);
+
+    if !warnings.is_empty() {
+        std::process::exit(1);
+    }
+
+    Ok(())
+}
+
+fn fmt(opt: &Opt, test_pattern: Option<&Regex>, check: bool) -> anyhow::Result<()> {
+    let mut source_paths = BTreeSet::new();
+
+    for cargo_test_directory in &opt.cargo_workspace {
+        let cargo_workspace = CargoWorkspace::load(cargo_test_directory)?;
+
+        source_paths.extend(
+            cargo_workspace
+                .cargo_packages
+                .iter()
+                .flat_map(|package| package.test_definitions.iter())
+                .filter(|test_definition| test_definition.matches(test_pattern))
+                .map(|test_definition| test_definition.absolute_source_path.clone()),
+        );
+    }
+
+    let mut unformatted = 0;
+    let mut failed = false;
+
+    for source_path in &source_paths {
+        let source = std::fs::read_to_string(source_path)?;
+        let formatted = match dbt::formatter::format_source(&source, Some(source_path)) {
+            Ok(formatted) => formatted,
+            Err(e) => {
+                eprintln!("{}: {:#}", prettify_path(source_path), e);
+                failed = true;
+                continue;
+            }
+        };
+
+        if formatted == source {
+            continue;
+        }
+
+        unformatted += 1;
 
-    if !dbt::test_result::print_report(test_results) {
+        if check {
+            println!("{} is not formatted", prettify_path(source_path));
+        } else {
+            std::fs::write(source_path, formatted)?;
+            println!("Formatted {}", prettify_path(source_path));
+        }
+    }
+
+    if failed || (check && unformatted > 0) {
         std::process::exit(1);
     }
+
     Ok(())
 }
+
+fn minimize(
+    opt: &Opt,
+    test: &str,
+    debugger_kind: Option<&str>,
+    debuggers: &[Debugger],
+    output_dir: &Path,
+    run_options: &workflow::RunOptions,
+) -> anyhow::Result<()> {
+    let debugger = select_debugger(debuggers, debugger_kind)?;
+    let compiled_test_cases = compile_test_cases_containing(opt, test)?;
+
+    println!("Minimizing the script of `{}` ...", test);
+
+    let script_lines = workflow::minimize_test(
+        &compiled_test_cases,
+        test,
+        debugger,
+        output_dir,
+        run_options,
+    )?;
+
+    println!();
+    println!("/***");
+    for line in script_lines {
+        println!("{}", line);
+    }
+    println!("***/");
+
+    Ok(())
+}
+
+fn scaffold_checks(
+    opt: &Opt,
+    test: &str,
+    debugger_kind: Option<&str>,
+    debuggers: &[Debugger],
+    output_dir: &Path,
+    run_options: &workflow::RunOptions,
+) -> anyhow::Result<()> {
+    let debugger = select_debugger(debuggers, debugger_kind)?;
+    let compiled_test_cases = compile_test_cases_containing(opt, test)?;
+
+    let (source_path, inserted) = workflow::scaffold_checks(
+        &compiled_test_cases,
+        test,
+        debugger,
+        output_dir,
+        run_options,
+    )?;
+
+    println!(
+        "Inserted {} suggested checks into {}",
+        inserted,
+        prettify_path(&source_path)
+    );
+
+    Ok(())
+}
+
+/// The debugger of the given kind, or the only one given via `-d` if no kind is given
+fn select_debugger<'d>(
+    debuggers: &'d [Debugger],
+    debugger_kind: Option<&str>,
+) -> anyhow::Result<&'d Debugger> {
+    match debugger_kind {
+        Some(selector) => {
+            let selector = DebuggerSelector::parse(selector)?;
+            let mut matching = debuggers
+                .iter()
+                .filter(|debugger| selector.matches(debugger.kind, &debugger.version));
+
+            match (matching.next(), matching.next()) {
+                (Some(debugger), None) => Ok(debugger),
+                (Some(_), Some(_)) => bail!(
+                    "Several {} debuggers given via -d, use <kind>@<version> to choose one",
+                    selector.kind.name()
+                ),
+                (None, _) => bail!("No matching {} debugger given via -d", selector.kind.name()),
+            }
+        }
+        None => match debuggers {
+            [debugger] => Ok(debugger),
+            _ => bail!("Use --debugger to choose one of the debuggers given via -d"),
+        },
+    }
+}
+
+/// Compiles the Cargo workspace (given via `--cargo-workspace`) containing the test named
+/// `test`
+fn compile_test_cases_containing(
+    opt: &Opt,
+    test: &str,
+) -> anyhow::Result<workflow::CompiledTestCases> {
+    for cargo_test_directory in &opt.cargo_workspace {
+        let compiled_test_cases = workflow::compile_cargo_tests(
+            cargo_test_directory,
+            &opt.cargo_target_directory,
+            &opt.cargo_profiles,
+            &BuildConfig::default(),
+            opt.skip_rebuild,
+            opt.support_crate,
+            opt.reproducible,
+        )?;
+
+        let has_test = compiled_test_cases
+            .cargo_workspace
+            .cargo_packages
+            .iter()
+            .flat_map(|package| package.test_definitions.iter())
+            .any(|test_definition| &test_definition.name[..] == test);
+
+        if has_test {
+            return Ok(compiled_test_cases);
+        }
+    }
+
+    bail!("There is no test named `{}`", test)
+}
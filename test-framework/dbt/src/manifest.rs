@@ -0,0 +1,310 @@
+//! Keeps the Cargo.toml of each test package in sync with the crates its tests declare via
+//! `#dependencies`, so that tests can use third-party types without the manifests having
+//! to be maintained by hand.
+
+use std::{path::Path, process::Command};
+
+use anyhow::bail;
+
+use crate::{
+    cargo_test_directory::CargoPackage,
+    prettify_path,
+    script::{CrateDependency, LineNumber},
+};
+
+/// Adds the crates declared via `#dependencies` in the tests of `package` to the
+/// `[dependencies]` of its Cargo.toml, unless they are already there. Returns the names of
+/// the added crates.
+pub fn add_dependencies(package: &CargoPackage) -> anyhow::Result<Vec<String>> {
+    // The instantiations of a test with #params declare the same dependencies
+    let mut declared: Vec<(CrateDependency, &Path, LineNumber)> = vec![];
+
+    for test_definition in &package.test_definitions {
+        let source_path = test_definition.absolute_source_path.as_path();
+
+        for (dependency, line_number) in test_definition.script.crate_dependencies() {
+            match declared.iter().find(|(d, _, _)| d.name == dependency.name) {
+                Some((other, other_path, other_line_number)) => {
+                    if parse_spec(&other.spec)? != parse_spec(&dependency.spec)? {
+                        bail!(
+                            "{}:{}: `#dependencies {}` conflicts with `#dependencies {}` at {}:{}",
+                            prettify_path(source_path),
+                            line_number.0,
+                            dependency,
+                            other,
+                            prettify_path(other_path),
+                            other_line_number.0
+                        );
+                    }
+                }
+                None => declared.push((dependency, source_path, line_number)),
+            }
+        }
+    }
+
+    if declared.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let manifest_path = package.root_path.join("Cargo.toml");
+    let manifest = std::fs::read_to_string(&manifest_path)?;
+    let existing = match toml::from_str::<toml::Value>(&manifest) {
+        Ok(value) => value.get("dependencies").cloned(),
+        Err(e) => bail!("{}: {}", prettify_path(&manifest_path), e),
+    };
+
+    let mut missing = vec![];
+
+    for (dependency, source_path, line_number) in declared {
+        match existing
+            .as_ref()
+            .and_then(|dependencies| dependencies.get(&dependency.name[..]))
+        {
+            Some(existing) => {
+                if !satisfies(existing, &parse_spec(&dependency.spec)?) {
+                    bail!(
+                        "{}:{}: `#dependencies {}` conflicts with `{} = {}` in {}",
+                        prettify_path(source_path),
+                        line_number.0,
+                        dependency,
+                        dependency.name,
+                        existing,
+                        prettify_path(&manifest_path)
+                    );
+                }
+            }
+            None => missing.push(dependency),
+        }
+    }
+
+    // Cargo edits the manifest without losing its formatting and comments
+    for dependency in &missing {
+        let output = cargo_add_command(&manifest_path, dependency)?.output()?;
+
+        if !output.status.success() {
+            bail!(
+                "`cargo add` failed for `#dependencies {}`:\n{}",
+                dependency,
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+    }
+
+    // `cargo add` writes Unix line endings only
+    if !missing.is_empty() && manifest.contains("\r\n") {
+        let edited = std::fs::read_to_string(&manifest_path)?;
+        std::fs::write(&manifest_path, with_crlf_line_endings(&edited))?;
+    }
+
+    Ok(missing
+        .into_iter()
+        .map(|dependency| dependency.name.to_string())
+        .collect())
+}
+
+/// Parses the specification of a dependency, with `{ version = "1" }` normalized to `"1"`,
+/// which is how `cargo add` writes it
+fn parse_spec(spec: &str) -> anyhow::Result<toml::Value> {
+    let table = toml::from_str::<toml::Value>(&format!("spec = {}", spec))?;
+
+    Ok(match &table["spec"] {
+        toml::Value::Table(table) if table.len() == 1 && table.contains_key("version") => {
+            table["version"].clone()
+        }
+        spec => spec.clone(),
+    })
+}
+
+/// Whether the specification of a dependency in a Cargo.toml has everything `declared` has.
+/// It may have more, e.g. the version `cargo add` adds to path dependencies.
+fn satisfies(existing: &toml::Value, declared: &toml::Value) -> bool {
+    let as_table = |spec: &toml::Value| match spec {
+        toml::Value::String(version) => {
+            toml::map::Map::from_iter([("version".to_string(), version.clone().into())])
+        }
+        toml::Value::Table(table) => table.clone(),
+        _ => toml::map::Map::new(),
+    };
+
+    let existing = as_table(existing);
+
+    as_table(declared)
+        .iter()
+        .all(|(key, value)| existing.get(key) == Some(value))
+}
+
+fn with_crlf_line_endings(manifest: &str) -> String {
+    manifest.replace("\r\n", "\n").replace('\n', "\r\n")
+}
+
+/// The `cargo add` command that adds `dependency` to the manifest at `manifest_path`
+fn cargo_add_command(
+    manifest_path: &Path,
+    dependency: &CrateDependency,
+) -> anyhow::Result<Command> {
+    let mut command = Command::new("cargo");
+    command.arg("add").arg("--manifest-path").arg(manifest_path);
+
+    let table = match parse_spec(&dependency.spec)? {
+        toml::Value::String(version) => {
+            command.arg(format!("{}@{}", dependency.name, version));
+            return Ok(command);
+        }
+        toml::Value::Table(table) => table,
+        _ => bail!("`#dependencies {}` is not a valid dependency", dependency),
+    };
+
+    // `name = { package = "crate" }` depends on `crate` under another name
+    let crate_name = match table.get("package") {
+        Some(toml::Value::String(package)) => {
+            command.arg("--rename").arg(&dependency.name[..]);
+            &package[..]
+        }
+        _ => &dependency.name[..],
+    };
+
+    match table.get("version") {
+        Some(toml::Value::String(version)) => command.arg(format!("{}@{}", crate_name, version)),
+        _ => command.arg(crate_name),
+    };
+
+    for (key, value) in &table {
+        match (&key[..], value) {
+            ("version" | "package", toml::Value::String(_)) => {}
+            (
+                "path" | "git" | "branch" | "tag" | "rev" | "registry",
+                toml::Value::String(value),
+            ) => {
+                command.arg(format!("--{}", key)).arg(value);
+            }
+            ("features", toml::Value::Array(features)) => {
+                for feature in features {
+                    match feature {
+                        toml::Value::String(feature) => command.arg("--features").arg(feature),
+                        _ => bail!("`#dependencies {}`: features must be strings", dependency),
+                    };
+                }
+            }
+            ("default-features", toml::Value::Boolean(false)) => {
+                command.arg("--no-default-features");
+            }
+            ("optional", toml::Value::Boolean(true)) => {
+                command.arg("--optional");
+            }
+            ("default-features", toml::Value::Boolean(true))
+            | ("optional", toml::Value::Boolean(false)) => {}
+            _ => bail!(
+                "`#dependencies {}`: `{}` is not supported, add the crate to the Cargo.toml \
+                 by hand instead",
+                dependency,
+                key
+            ),
+        }
+    }
+
+    Ok(command)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::script::CrateDependency;
+
+    fn dependency(name: &str, spec: &str) -> CrateDependency {
+        CrateDependency {
+            name: name.into(),
+            spec: spec.into(),
+        }
+    }
+
+    #[test]
+    fn cargo_add_command() {
+        let args = |name: &str, spec: &str| {
+            let command =
+                super::cargo_add_command(Path::new("Cargo.toml"), &dependency(name, spec))?;
+
+            anyhow::Ok(
+                command
+                    .get_args()
+                    .skip(3)
+                    .map(|arg| arg.to_string_lossy().into_owned())
+                    .collect::<Vec<_>>(),
+            )
+        };
+
+        assert_eq!(args("smallvec", r#""1""#).unwrap(), ["smallvec@1"]);
+        assert_eq!(
+            args("smallvec", r#"{ version = "1" }"#).unwrap(),
+            ["smallvec@1"]
+        );
+        assert_eq!(
+            args(
+                "serde",
+                r#"{ version = "1", features = ["derive", "rc"], default-features = false }"#
+            )
+            .unwrap(),
+            [
+                "serde@1",
+                "--no-default-features",
+                "--features",
+                "derive",
+                "--features",
+                "rc"
+            ]
+        );
+        assert_eq!(
+            args("json", r#"{ package = "serde_json", version = "1" }"#).unwrap(),
+            ["--rename", "json", "serde_json@1"]
+        );
+        assert_eq!(
+            args("local", r#"{ path = "../local" }"#).unwrap(),
+            ["local", "--path", "../local"]
+        );
+        assert!(args("serde", r#"{ version = "1", target = "x" }"#).is_err());
+        assert!(args("serde", "1").is_err());
+    }
+
+    #[test]
+    fn satisfies() {
+        let spec = |spec: &str| super::parse_spec(spec).unwrap();
+
+        assert!(super::satisfies(
+            &spec(r#""1""#),
+            &spec(r#"{ version = "1" }"#)
+        ));
+        assert!(super::satisfies(
+            &spec(r#"{ version = "0.1.0", path = "../local" }"#),
+            &spec(r#"{ path = "../local" }"#)
+        ));
+        assert!(!super::satisfies(&spec(r#""1""#), &spec(r#""2""#)));
+        assert!(!super::satisfies(
+            &spec(r#""1""#),
+            &spec(r#"{ version = "1", features = ["derive"] }"#)
+        ));
+    }
+
+    #[test]
+    fn with_crlf_line_endings() {
+        assert_eq!(
+            super::with_crlf_line_endings("[package]\r\nname = \"t1\"\n\n"),
+            "[package]\r\nname = \"t1\"\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn parse_spec() {
+        assert_eq!(
+            super::parse_spec(r#"{ version = "1" }"#).unwrap(),
+            super::parse_spec(r#"{version="1"}"#).unwrap()
+        );
+        assert_ne!(
+            super::parse_spec(r#""1""#).unwrap(),
+            super::parse_spec(r#""2""#).unwrap()
+        );
+        assert_eq!(
+            super::parse_spec(r#"{ version = "1" }"#).unwrap(),
+            super::parse_spec(r#""1""#).unwrap()
+        );
+    }
+}
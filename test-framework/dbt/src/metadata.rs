@@ -0,0 +1,195 @@
+//! The environment a run happened in (`--emit-metadata`): the host, the toolchains, targets
+//! and debuggers used, and how dbt was invoked. This is written to a JSON file and also
+//! printed along with the report, so that results can be reproduced (or audited) later. It is
+//! part of the JSON report (`--json-report`) as well.
+
+use std::{fmt::Write, path::Path, process::Command};
+
+use serde::{Deserialize, Serialize};
+
+use crate::debugger::Debugger;
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunMetadata {
+    pub dbt_version: String,
+    pub host_os: String,
+    pub host_arch: String,
+    pub command_line: Vec<String>,
+    pub cargo_profiles: Vec<String>,
+    /// The target triples given via `--target`, empty for the host target
+    pub targets: Vec<String>,
+    pub toolchains: Vec<ToolchainMetadata>,
+    pub debuggers: Vec<DebuggerMetadata>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ToolchainMetadata {
+    /// The toolchain given via `--toolchain`, or `None` for the default one
+    pub toolchain: Option<String>,
+    /// The output of `rustc --version`, or `None` if it could not be run
+    pub rustc_version: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DebuggerMetadata {
+    pub kind: String,
+    pub version: String,
+    pub command: String,
+}
+
+impl RunMetadata {
+    /// Collects the metadata of the current run. `rustc` is run in `workspace_dir`, so that
+    /// toolchain overrides for the test workspace apply.
+    pub fn collect(
+        cargo_profiles: &[String],
+        toolchains: &[String],
+        targets: &[String],
+        debuggers: &[Debugger],
+        workspace_dir: Option<&Path>,
+    ) -> RunMetadata {
+        let toolchains = if toolchains.is_empty() {
+            vec![None]
+        } else {
+            toolchains.iter().map(Some).collect()
+        };
+
+        RunMetadata {
+            dbt_version: crate::VERSION.to_string(),
+            host_os: std::env::consts::OS.to_string(),
+            host_arch: std::env::consts::ARCH.to_string(),
+            command_line: std::env::args().collect(),
+            cargo_profiles: cargo_profiles.to_vec(),
+            targets: targets.to_vec(),
+            toolchains: toolchains
+                .into_iter()
+                .map(|toolchain| ToolchainMetadata {
+                    toolchain: toolchain.cloned(),
+                    rustc_version: rustc_version(toolchain.map(|t| &t[..]), workspace_dir),
+                })
+                .collect(),
+            debuggers: debuggers
+                .iter()
+                .map(|debugger| DebuggerMetadata {
+                    kind: debugger.kind.name().to_string(),
+                    version: debugger.version.to_string(),
+                    command: debugger.command.to_string_lossy().into_owned(),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    /// The block printed along with the report
+    pub fn render(&self) -> String {
+        let mut block = String::new();
+
+        writeln!(block, "Run environment:").unwrap();
+        writeln!(block, "  dbt {}", self.dbt_version).unwrap();
+        writeln!(block, "  host: {} {}", self.host_os, self.host_arch).unwrap();
+        writeln!(block, "  command line: {}", self.command_line.join(" ")).unwrap();
+        writeln!(
+            block,
+            "  Cargo profiles: {}",
+            self.cargo_profiles.join(", ")
+        )
+        .unwrap();
+
+        if self.targets.is_empty() {
+            writeln!(block, "  target: host").unwrap();
+        } else {
+            writeln!(block, "  targets: {}", self.targets.join(", ")).unwrap();
+        }
+
+        for toolchain in &self.toolchains {
+            writeln!(
+                block,
+                "  toolchain {}: {}",
+                toolchain.toolchain.as_deref().unwrap_or("default"),
+                toolchain
+                    .rustc_version
+                    .as_deref()
+                    .unwrap_or("unknown rustc version")
+            )
+            .unwrap();
+        }
+
+        for debugger in &self.debuggers {
+            writeln!(
+                block,
+                "  debugger: {} {} ({})",
+                debugger.kind, debugger.version, debugger.command
+            )
+            .unwrap();
+        }
+
+        block
+    }
+}
+
+fn rustc_version(toolchain: Option<&str>, workspace_dir: Option<&Path>) -> Option<String> {
+    let mut command = Command::new("rustc");
+
+    if let Some(toolchain) = toolchain {
+        command.arg(format!("+{}", toolchain));
+    }
+
+    command.arg("--version");
+
+    if let Some(workspace_dir) = workspace_dir {
+        command.current_dir(workspace_dir);
+    }
+
+    match command.output() {
+        Ok(output) if output.status.success() => {
+            Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DebuggerMetadata, RunMetadata, ToolchainMetadata};
+
+    #[test]
+    fn render_and_serialize() {
+        let metadata = RunMetadata {
+            dbt_version: "0.1.0".to_string(),
+            host_os: "linux".to_string(),
+            host_arch: "x86_64".to_string(),
+            command_line: vec!["dbt".to_string(), "-d".to_string(), "gdb".to_string()],
+            cargo_profiles: vec!["debug".to_string()],
+            targets: vec![],
+            toolchains: vec![ToolchainMetadata {
+                toolchain: None,
+                rustc_version: Some("rustc 1.80.0 (051478957 2024-07-21)".to_string()),
+            }],
+            debuggers: vec![DebuggerMetadata {
+                kind: "gdb".to_string(),
+                version: "15.1".to_string(),
+                command: "gdb".to_string(),
+            }],
+        };
+
+        assert_eq!(
+            metadata.render(),
+            "Run environment:
+  dbt 0.1.0
+  host: linux x86_64
+  command line: dbt -d gdb
+  Cargo profiles: debug
+  target: host
+  toolchain default: rustc 1.80.0 (051478957 2024-07-21)
+  debugger: gdb 15.1 (gdb)
+"
+        );
+
+        let json: serde_json::Value = serde_json::to_value(&metadata).unwrap();
+        assert_eq!(json["debuggers"][0]["version"], "15.1");
+        assert_eq!(json["toolchains"][0]["toolchain"], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,221 @@
+//! Reducing the script of a failing test to a minimal one that still fails (`dbt minimize`).
+
+use anyhow::bail;
+
+use crate::test_result::Status;
+
+/// A source file split into the lines of its test script and everything around them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SplitSource {
+    /// Everything up to and including the `/***` line
+    pub before: String,
+    pub script_lines: Vec<String>,
+    /// Everything from the `***/` line on
+    pub after: String,
+}
+
+impl SplitSource {
+    /// Returns the source file with its script replaced by `script_lines`
+    pub fn with_script_lines(&self, script_lines: &[String]) -> String {
+        let mut source = self.before.clone();
+
+        for line in script_lines {
+            source.push_str(line);
+            source.push('\n');
+        }
+
+        source.push_str(&self.after);
+        source
+    }
+}
+
+/// Splits `source` into the script and the rest. The script markers have to be on lines of
+/// their own.
+pub fn split_source(source: &str) -> anyhow::Result<SplitSource> {
+    let mut before = String::new();
+    let mut script_lines = vec![];
+    let mut lines = source.split_inclusive('\n');
+
+    for line in lines.by_ref() {
+        before.push_str(line);
+
+        if line.trim() == "/***" {
+            break;
+        }
+    }
+
+    for line in lines.by_ref() {
+        if line.trim() == "***/" {
+            let mut after = line.to_string();
+            after.extend(lines);
+
+            return Ok(SplitSource {
+                before,
+                script_lines,
+                after,
+            });
+        }
+
+        script_lines.push(line.trim_end_matches(['\r', '\n']).to_string());
+    }
+
+    bail!("Could not find a test script delimited by `/***` and `***/` lines of their own")
+}
+
+/// Removes as many lines as possible while `reproduces` still returns true, trying to
+/// remove large chunks first. Blank lines are always removed.
+pub fn minimize_lines(
+    lines: &[String],
+    mut reproduces: impl FnMut(&[String]) -> anyhow::Result<bool>,
+) -> anyhow::Result<Vec<String>> {
+    let mut lines: Vec<String> = lines
+        .iter()
+        .filter(|line| !line.trim().is_empty())
+        .cloned()
+        .collect();
+
+    let mut chunk_size = lines.len().div_ceil(2).max(1);
+
+    loop {
+        let mut removed_any = false;
+        let mut start = 0;
+
+        while start < lines.len() {
+            let end = (start + chunk_size).min(lines.len());
+
+            let mut candidate = lines[..start].to_vec();
+            candidate.extend_from_slice(&lines[end..]);
+
+            if reproduces(&candidate)? {
+                lines = candidate;
+                removed_any = true;
+            } else {
+                start = end;
+            }
+        }
+
+        if !removed_any {
+            if chunk_size == 1 {
+                return Ok(lines);
+            }
+
+            chunk_size = chunk_size.div_ceil(2);
+        }
+    }
+}
+
+/// What has to stay the same about the failure of a test while its script is minimized: the
+/// kind of failure and, for failed checks, the message, which names the check and the output
+/// it was matched against. The parts of the message that refer to line numbers are left out,
+/// as they change when lines are removed.
+pub fn failure_signature(status: &Status) -> String {
+    match status {
+        Status::Failed(message, _) => message
+            .lines()
+            .filter(|line| {
+                let refers_to_line_numbers = line.starts_with("Check failed at: ")
+                    || line.starts_with("The checks before it in the same block matched:")
+                    || (line.starts_with("  '") && line.contains(" of stdout: "));
+
+                !refers_to_line_numbers
+            })
+            .map(|line| format!("{}\n", line))
+            .collect(),
+        status => status.short_description().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SplitSource;
+    use crate::{
+        debugger::{DebuggerExitStatus, DebuggerOutput},
+        test_result::Status,
+    };
+
+    fn strings(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn split_source() {
+        let source = "// header\n/***\nprint a\n  #check a\n***/\nfn main() {}\n";
+        let split = super::split_source(source).unwrap();
+
+        assert_eq!(
+            split,
+            SplitSource {
+                before: "// header\n/***\n".to_string(),
+                script_lines: strings(&["print a", "  #check a"]),
+                after: "***/\nfn main() {}\n".to_string(),
+            }
+        );
+
+        assert_eq!(split.with_script_lines(&split.script_lines), source);
+        assert_eq!(
+            split.with_script_lines(&strings(&["x"])),
+            "// header\n/***\nx\n***/\nfn main() {}\n"
+        );
+
+        assert!(super::split_source("/*** print a ***/").is_err());
+    }
+
+    #[test]
+    fn minimize_lines() {
+        let lines = strings(&["a", "b", "", "c", "d", "e", "f", "g"]);
+
+        let mut runs = 0;
+        let minimized = super::minimize_lines(&lines, |candidate| {
+            runs += 1;
+            Ok(candidate.contains(&"b".to_string()) && candidate.contains(&"f".to_string()))
+        })
+        .unwrap();
+
+        assert_eq!(minimized, strings(&["b", "f"]));
+        assert!(runs < 20);
+
+        let minimized = super::minimize_lines(&lines, |_| Ok(false)).unwrap();
+        assert_eq!(minimized, strings(&["a", "b", "c", "d", "e", "f", "g"]));
+    }
+
+    #[test]
+    fn failure_signature() {
+        let failed = |message: &str| {
+            Status::Failed(
+                message.to_string(),
+                DebuggerOutput {
+                    stdout: String::new(),
+                    stderr: String::new(),
+                    exit_status: DebuggerExitStatus::Success,
+                    program_output: None,
+                },
+            )
+        };
+        let message = |output: &str, line: u32| {
+            format!(
+                "Could not find 'a = 1' in debugger output. Expected to find it within the \
+                 following lines:\n\n> {}\n\nThe checks before it in the same block \
+                 matched:\n\n  'x' (line {}) at line 3 of stdout: x\n\nCheck failed at: \
+                 src/main.rs:{}\n",
+                output,
+                line,
+                line + 1
+            )
+        };
+
+        // Removing lines moves the check
+        assert_eq!(
+            super::failure_signature(&failed(&message("a = 2", 4))),
+            super::failure_signature(&failed(&message("a = 2", 2)))
+        );
+        // but it has to fail on the same output
+        assert_ne!(
+            super::failure_signature(&failed(&message("a = 2", 4))),
+            super::failure_signature(&failed(&message("The program is not being run.", 4)))
+        );
+        assert_eq!(
+            super::failure_signature(&Status::TimedOut("at line 4".to_string())),
+            "TIMEOUT"
+        );
+    }
+}
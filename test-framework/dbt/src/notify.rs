@@ -0,0 +1,197 @@
+//! Notifications about finished runs (`--notify-url`, `--notify-command`), for wiring long
+//! runs into chat or issue automation. The notification is a summary of the run as JSON,
+//! which is POSTed to a URL via `curl` (so that dbt doesn't need an HTTP client of its own),
+//! or passed on stdin to a command run via the shell.
+
+use std::{
+    io::Write,
+    process::{Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use serde::Serialize;
+
+use crate::test_result::{Status, TestResult};
+
+/// How long sending the notification may take before the notifier is killed, so that a hung
+/// webhook or command doesn't keep dbt from exiting
+const TIMEOUT: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RunSummary {
+    pub passed: usize,
+    pub failed: usize,
+    /// Tests that errored, timed out, crashed the debugger, or could not be built or run
+    /// because of a failed dependency, like in the report
+    pub errored: usize,
+    pub ignored: usize,
+    pub skipped: usize,
+    /// The tests that failed or errored, see [TestResult::label_with_debugger]
+    pub failures: Vec<String>,
+}
+
+impl RunSummary {
+    pub fn new(test_results: &[TestResult]) -> RunSummary {
+        let mut summary = RunSummary::default();
+
+        for test_result in test_results {
+            match &*test_result.status {
+                Status::Passed => summary.passed += 1,
+                Status::Ignored(_) => summary.ignored += 1,
+                Status::Skipped(_) => summary.skipped += 1,
+                Status::Failed(..) => summary.failed += 1,
+                Status::Errored(_)
+                | Status::TimedOut(_)
+                | Status::DebuggerCrashed(..)
+                | Status::DependencyFailed(_)
+                | Status::BuildFailed(_) => summary.errored += 1,
+            }
+
+            if test_result.status.passed() == Some(false) {
+                summary.failures.push(test_result.label_with_debugger());
+            }
+        }
+
+        summary
+    }
+}
+
+/// POSTs `summary` to `url`
+pub fn post(summary: &RunSummary, url: &str) -> anyhow::Result<()> {
+    let mut command = Command::new("curl");
+    command.args([
+        "--silent",
+        "--show-error",
+        "--fail",
+        "--header",
+        "Content-Type: application/json",
+        "--data-binary",
+        "@-",
+        "--max-time",
+        &TIMEOUT.as_secs().to_string(),
+        // Keeps a URL starting with `-` from being taken for an option
+        "--url",
+        url,
+    ]);
+
+    run(command, summary, TIMEOUT).context("Could not POST the run summary (is `curl` installed?)")
+}
+
+/// Runs `shell_command` via `sh -c` (`cmd /C` on Windows) with `summary` on its stdin
+pub fn run_command(summary: &RunSummary, shell_command: &str) -> anyhow::Result<()> {
+    let mut command = if cfg!(windows) {
+        let mut command = Command::new("cmd");
+        command.arg("/C");
+        command
+    } else {
+        let mut command = Command::new("sh");
+        command.arg("-c");
+        command
+    };
+    command.arg(shell_command);
+
+    run(command, summary, TIMEOUT).with_context(|| format!("Could not run `{}`", shell_command))
+}
+
+/// Runs the notifier with `summary` on its stdin, killing it after `timeout`
+fn run(mut command: Command, summary: &RunSummary, timeout: Duration) -> anyhow::Result<()> {
+    let mut child = command.stdin(Stdio::piped()).spawn()?;
+
+    // Written on a thread of its own, as a command that doesn't read its stdin would block
+    // the write once the pipe is full. Writing fails if the command doesn't read its stdin,
+    // which is up to the command.
+    let mut stdin = child.stdin.take().unwrap();
+    let summary = serde_json::to_string(summary)?;
+    std::thread::spawn(move || {
+        let _ = stdin.write_all(summary.as_bytes());
+    });
+
+    let deadline = Instant::now() + timeout;
+
+    let status = loop {
+        if let Some(status) = child.try_wait()? {
+            break status;
+        }
+
+        if Instant::now() >= deadline {
+            let _ = child.kill();
+            let _ = child.wait();
+            bail!("The notifier did not finish within {:?}", timeout);
+        }
+
+        std::thread::sleep(Duration::from_millis(100));
+    };
+
+    if !status.success() {
+        bail!("The notifier exited with {}", status);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{process::Command, sync::Arc, time::Duration};
+
+    use super::RunSummary;
+    use crate::{
+        cargo_test_directory::BuildConfig,
+        debugger::DebuggerKind,
+        script::PhaseConfig,
+        test_result::{Status, TestResult},
+    };
+
+    #[cfg(unix)]
+    #[test]
+    fn run_times_out() {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg("sleep 5");
+
+        let error =
+            super::run(command, &RunSummary::default(), Duration::from_millis(200)).unwrap_err();
+        assert!(error.to_string().contains("did not finish within"));
+    }
+
+    #[test]
+    fn run_summary() {
+        let test_result = |test_name: &str, status: Status| TestResult {
+            test_name: test_name.into(),
+            phase: PhaseConfig::Live,
+            debugger_kind: DebuggerKind::Gdb,
+            debugger_version: "12.1".into(),
+            cargo_profile: Arc::from("debug"),
+            build: BuildConfig::default(),
+            status: Box::new(status),
+            source_path: test_name.into(),
+            debugger_output_stdout_path: None,
+            debugger_output_stderr_path: None,
+            command_timings: vec![],
+            resource_usage: None,
+            debugger_duration: None,
+            printed_values: vec![],
+            matched_checks: vec![],
+            diagnostics: vec![],
+            build_output: String::new(),
+        };
+
+        let summary = RunSummary::new(&[
+            test_result("a", Status::Passed),
+            test_result("b", Status::Errored("oops".to_string())),
+            test_result("c", Status::Skipped("unmet requirement".to_string())),
+            test_result("d", Status::Passed),
+        ]);
+
+        assert_eq!(summary.passed, 2);
+        assert_eq!(summary.errored, 1);
+        assert_eq!(summary.skipped, 1);
+        assert_eq!(summary.failures.len(), 1);
+        assert!(summary.failures[0].starts_with('b'));
+
+        #[cfg(unix)]
+        {
+            assert!(super::run_command(&summary, "grep -q '\"passed\":2'").is_ok());
+            assert!(super::run_command(&summary, "grep -q '\"passed\":3'").is_err());
+        }
+    }
+}
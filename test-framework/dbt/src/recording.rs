@@ -0,0 +1,322 @@
+//! Recording of real debugger sessions (via `--record`) and replaying them later without
+//! a debugger being installed (via `--replay`). Recordings are stored as one TOML file per
+//! test phase in `<dir>/<debugger-kind>-<version>/<build>/<test>@<cargo-profile>/<phase>.toml`,
+//! where `<build>` is the [BuildConfig::dir_name]. The runs of a test with `--stress` are
+//! recorded separately, in `run-<N>` directories next to where `<phase>.toml` would be.
+
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    cargo_test_directory::{BuildConfig, TestDefinition},
+    debugger::{Debugger, DebuggerExitStatus, DebuggerKind, DebuggerOutput},
+    prettify_path,
+    script::PhaseConfig,
+    stable_hash,
+    test_result::Status,
+};
+
+/// The stand-in for the test's output directory when hashing debugger scripts, so that
+/// recordings can be replayed with a different `--output` directory.
+const OUTPUT_DIR_PLACEHOLDER: &str = "<output>";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Recording {
+    /// The [script_hash] of the debugger script that produced this output
+    pub script_hash: String,
+    pub exit_status: DebuggerExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+    /// See [DebuggerOutput::program_output]. Missing in recordings made before the output of
+    /// the debuggee was captured separately.
+    #[serde(default)]
+    pub program_output: Option<String>,
+}
+
+impl Recording {
+    pub fn new(script_hash: String, debugger_output: &DebuggerOutput) -> Recording {
+        Recording {
+            script_hash,
+            exit_status: debugger_output.exit_status,
+            stdout: debugger_output.stdout.clone(),
+            stderr: debugger_output.stderr.clone(),
+            program_output: debugger_output.program_output.clone(),
+        }
+    }
+
+    pub fn into_debugger_output(self) -> DebuggerOutput {
+        DebuggerOutput {
+            stdout: self.stdout,
+            stderr: self.stderr,
+            exit_status: self.exit_status,
+            program_output: self.program_output,
+        }
+    }
+}
+
+/// Returns a stable hash of `script` with all occurrences of `output_dir_for_test` replaced
+/// by a placeholder. A recording is only replayed if the script generated for it has the
+/// same hash, i.e. if neither the test nor dbt changed in a way that affects the script.
+pub fn script_hash(script: &str, output_dir_for_test: &Path) -> String {
+    let script = script.replace(
+        &output_dir_for_test.to_string_lossy()[..],
+        OUTPUT_DIR_PLACEHOLDER,
+    );
+
+    stable_hash(script.as_bytes())
+}
+
+fn debugger_directory_name(kind: DebuggerKind, version: &str) -> String {
+    format!("{}-{}", kind.name(), version)
+}
+
+/// A debugger session of a test, i.e. what a recording is of
+#[derive(Debug, Clone, Copy)]
+pub struct Session<'a> {
+    pub debugger: &'a Debugger,
+    pub test_definition: &'a TestDefinition,
+    pub build: &'a BuildConfig,
+    pub cargo_profile: &'a Arc<str>,
+    pub phase: &'a PhaseConfig,
+    /// The run of the test with `--stress`
+    pub stress_run: Option<usize>,
+}
+
+pub fn recording_path(recordings_dir: &Path, session: &Session) -> PathBuf {
+    let mut path = recordings_dir
+        .join(debugger_directory_name(
+            session.debugger.kind,
+            &session.debugger.version,
+        ))
+        .join(session.build.dir_name())
+        .join(format!(
+            "{}@{}",
+            session.test_definition.flat_name(),
+            session.cargo_profile
+        ));
+
+    if let Some(stress_run) = session.stress_run {
+        path.push(format!("run-{}", stress_run));
+    }
+
+    path.join(format!("{}.toml", session.phase))
+}
+
+pub fn save(path: &Path, recording: &Recording) -> anyhow::Result<()> {
+    std::fs::create_dir_all(path.parent().unwrap()).with_context(|| {
+        format!(
+            "while trying to create recordings directory: {}",
+            prettify_path(path.parent().unwrap())
+        )
+    })?;
+
+    std::fs::write(path, toml::to_string(recording)?)?;
+    Ok(())
+}
+
+/// Loads the recording at `path`, returning `None` if it does not exist.
+pub fn load(path: &Path) -> anyhow::Result<Option<Recording>> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => bail!(e),
+    };
+
+    let recording = toml::from_str(&contents)
+        .with_context(|| format!("while reading recording {}", prettify_path(path)))?;
+
+    Ok(Some(recording))
+}
+
+/// Returns the recorded output for a test phase, or the [Status] to report if it cannot
+/// be replayed.
+pub fn replay(
+    recordings_dir: &Path,
+    session: &Session,
+    script_hash: &str,
+) -> anyhow::Result<Result<DebuggerOutput, Status>> {
+    let path = recording_path(recordings_dir, session);
+
+    Ok(match load(&path)? {
+        None => Err(Status::Skipped(format!(
+            "no recording at {}",
+            prettify_path(&path)
+        ))),
+        Some(recording) if recording.script_hash != script_hash => Err(Status::Errored(format!(
+            "The recording at {} was made with a different debugger script. \
+             Re-record it via --record.\n",
+            prettify_path(&path)
+        ))),
+        Some(recording) => Ok(recording.into_debugger_output()),
+    })
+}
+
+/// Returns the kind and version of each debugger that has recordings in `recordings_dir`.
+pub fn recorded_debuggers(recordings_dir: &Path) -> anyhow::Result<Vec<(DebuggerKind, Arc<str>)>> {
+    let mut debuggers = vec![];
+
+    for dir_entry in std::fs::read_dir(recordings_dir)
+        .with_context(|| format!("while reading {}", prettify_path(recordings_dir)))?
+    {
+        let dir_entry = dir_entry?;
+
+        if !dir_entry.file_type()?.is_dir() {
+            continue;
+        }
+
+        let directory_name = dir_entry.file_name().to_string_lossy().to_string();
+        debuggers.push(parse_debugger_directory_name(&directory_name)?);
+    }
+
+    debuggers.sort();
+
+    Ok(debuggers)
+}
+
+fn parse_debugger_directory_name(directory_name: &str) -> anyhow::Result<(DebuggerKind, Arc<str>)> {
+    match directory_name.split_once('-') {
+        Some((kind, version)) if !version.is_empty() => {
+            Ok((DebuggerKind::try_from(kind)?, version.into()))
+        }
+        _ => bail!(
+            "Expected recordings directory of the form `<debugger>-<version>`, found `{}`",
+            directory_name
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::{Path, PathBuf},
+        sync::Arc,
+    };
+
+    use crate::{
+        cargo_test_directory::{BuildConfig, TestDefinition},
+        debugger::{Debugger, DebuggerCrash, DebuggerExitStatus, DebuggerKind, DebuggerOutput},
+        script::{parse_script, PhaseConfig},
+    };
+
+    use super::{Recording, Session};
+
+    #[test]
+    fn script_hash() {
+        let output_dir = Path::new("/out/test@debug");
+
+        assert_eq!(super::script_hash("", output_dir), "cbf29ce484222325");
+        assert_eq!(
+            super::script_hash(
+                "generate-core-file /out/test@debug/crashdumps/a",
+                output_dir
+            ),
+            super::script_hash(
+                "generate-core-file /elsewhere/test@debug/crashdumps/a",
+                Path::new("/elsewhere/test@debug")
+            )
+        );
+        assert_ne!(
+            super::script_hash("print a", output_dir),
+            super::script_hash("print b", output_dir)
+        );
+    }
+
+    #[test]
+    fn recording_round_trip() {
+        for exit_status in [
+            DebuggerExitStatus::Failure,
+            DebuggerExitStatus::Crashed(DebuggerCrash::Signal(11)),
+            DebuggerExitStatus::Crashed(DebuggerCrash::Exception(0xC000_0005)),
+        ] {
+            let debugger_output = DebuggerOutput {
+                stdout: "Breakpoint 1, main\n$1 = \"quoted\"\n".to_string(),
+                stderr: String::new(),
+                exit_status,
+                program_output: Some("Hello, world!\n".to_string()),
+            };
+
+            let recording = Recording::new("0123456789abcdef".to_string(), &debugger_output);
+            let toml = toml::to_string(&recording).unwrap();
+
+            assert_eq!(
+                toml::from_str::<Recording>(&toml)
+                    .unwrap()
+                    .into_debugger_output(),
+                debugger_output
+            );
+        }
+    }
+
+    #[test]
+    fn recording_path() {
+        let debugger = Debugger::mock();
+        let test_definition = TestDefinition::new(
+            Path::new("src/main.rs"),
+            &std::env::temp_dir().join("src/main.rs"),
+            "tests",
+            "tests".into(),
+            parse_script("/***\nrun\n***/", None).unwrap(),
+            vec![],
+        );
+        let build = BuildConfig {
+            toolchain: Some("nightly".into()),
+            target: None,
+        };
+        let session = Session {
+            debugger: &debugger,
+            test_definition: &test_definition,
+            build: &build,
+            cargo_profile: &Arc::from("debug"),
+            phase: &PhaseConfig::Live,
+            stress_run: None,
+        };
+        let version_dir = format!("mock-{}", debugger.version);
+
+        assert_eq!(
+            super::recording_path(Path::new("rec"), &session),
+            PathBuf::from_iter([
+                "rec",
+                &version_dir,
+                "nightly-host",
+                "tests~src~main.rs@debug",
+                "live.toml"
+            ])
+        );
+        assert_eq!(
+            super::recording_path(
+                Path::new("rec"),
+                &Session {
+                    stress_run: Some(2),
+                    ..session
+                }
+            ),
+            PathBuf::from_iter([
+                "rec",
+                &version_dir,
+                "nightly-host",
+                "tests~src~main.rs@debug",
+                "run-2",
+                "live.toml"
+            ])
+        );
+    }
+
+    #[test]
+    fn parse_debugger_directory_name() {
+        assert_eq!(
+            super::parse_debugger_directory_name("gdb-12.1").unwrap(),
+            (DebuggerKind::Gdb, "12.1".into())
+        );
+        assert_eq!(
+            super::parse_debugger_directory_name("cdb-10.0.22621.1").unwrap(),
+            (DebuggerKind::Cdb, "10.0.22621.1".into())
+        );
+        assert!(super::parse_debugger_directory_name("gdb").is_err());
+        assert!(super::parse_debugger_directory_name("foo-1.0").is_err());
+    }
+}
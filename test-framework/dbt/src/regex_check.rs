@@ -1,12 +1,13 @@
 use std::{
     borrow::Cow,
     collections::HashMap,
+    fmt::Display,
     sync::{Arc, Mutex},
 };
 
 use anyhow::bail;
 use lazy_static::lazy_static;
-use regex::Regex;
+use regex::{Regex, RegexBuilder};
 
 /// A type for handling `#check` commands. It takes check specifications of the form
 ///
@@ -19,6 +20,124 @@
 pub struct RegexCheck {
     regex: Arc<Regex>,
     pub source: Arc<str>,
+    pub options: CheckOptions,
+    pointers: Arc<[PointerPlaceholder]>,
+}
+
+/// A `{{ptr}}`, `{{ptr:name}}` or `{{ptr!name}}` placeholder in a check specification.
+#[derive(Debug, Clone)]
+struct PointerPlaceholder {
+    group: String,
+    binding: Option<PointerBinding>,
+}
+
+#[derive(Debug, Clone)]
+enum PointerBinding {
+    /// `{{ptr:name}}` - binds the address to `name`, or requires it to be the same
+    /// as the address already bound to `name`
+    Same(Arc<str>),
+    /// `{{ptr!name}}` - requires the address to differ from the one bound to `name`
+    Different(Arc<str>),
+}
+
+/// Addresses bound by `{{ptr:name}}` placeholders while checking the output of a test.
+pub type PointerBindings = HashMap<Arc<str>, u128>;
+
+/// Modifiers that change how a check specification is matched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct CheckOptions {
+    /// Ignore case when matching, e.g. for `#check-nocase`
+    pub case_insensitive: bool,
+    /// Require the specification to match the entire (whitespace-normalized) line
+    /// instead of just a part of it, e.g. for `#check-exact`
+    pub anchored: bool,
+    /// Extract the number at the `{}` placeholder and compare it, e.g. for `#check-num`
+    pub numeric: Option<NumericConstraint>,
+    /// The specification must *not* be found between the surrounding checks,
+    /// e.g. for `#check-not-between`
+    pub negated: bool,
+    /// The specification must match the same output line as the preceding check,
+    /// e.g. for `#check-same`
+    pub same_line: bool,
+}
+
+/// A relation that a number extracted from debugger output must satisfy,
+/// e.g. `> 0` in `#check-num "len = {}" > 0`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct NumericConstraint {
+    pub op: NumericComparison,
+    pub value: i128,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum NumericComparison {
+    Eq,
+    NotEq,
+    LessThan,
+    LessThanOrEq,
+    GreaterThan,
+    GreaterThanOrEq,
+}
+
+impl NumericComparison {
+    pub fn symbol(self) -> &'static str {
+        match self {
+            NumericComparison::Eq => "==",
+            NumericComparison::NotEq => "!=",
+            NumericComparison::LessThan => "<",
+            NumericComparison::LessThanOrEq => "<=",
+            NumericComparison::GreaterThan => ">",
+            NumericComparison::GreaterThanOrEq => ">=",
+        }
+    }
+
+    pub fn from_symbol(symbol: &str) -> Option<Self> {
+        match symbol {
+            "==" => Some(NumericComparison::Eq),
+            "!=" => Some(NumericComparison::NotEq),
+            "<" => Some(NumericComparison::LessThan),
+            "<=" => Some(NumericComparison::LessThanOrEq),
+            ">" => Some(NumericComparison::GreaterThan),
+            ">=" => Some(NumericComparison::GreaterThanOrEq),
+            _ => None,
+        }
+    }
+}
+
+impl NumericConstraint {
+    pub fn is_satisfied_by(&self, value: i128) -> bool {
+        match self.op {
+            NumericComparison::Eq => value == self.value,
+            NumericComparison::NotEq => value != self.value,
+            NumericComparison::LessThan => value < self.value,
+            NumericComparison::LessThanOrEq => value <= self.value,
+            NumericComparison::GreaterThan => value > self.value,
+            NumericComparison::GreaterThanOrEq => value >= self.value,
+        }
+    }
+}
+
+/// The placeholder in `#check-num` specifications that marks the number to extract
+pub const NUMBER_PLACEHOLDER: &str = "{}";
+const NUMBER_GROUP: &str = "__number__";
+
+/// Parses decimal and `0x`-prefixed hexadecimal integers, as printed by debuggers.
+pub fn parse_number(s: &str) -> Option<i128> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(digits) => (true, digits),
+        None => (false, s),
+    };
+
+    let value = if let Some(hex) = digits
+        .strip_prefix("0x")
+        .or_else(|| digits.strip_prefix("0X"))
+    {
+        i128::from_str_radix(hex, 16).ok()?
+    } else {
+        digits.parse().ok()?
+    };
+
+    Some(if negative { -value } else { value })
 }
 
 impl From<&str> for RegexCheck {
@@ -29,7 +148,7 @@ fn from(s: &str) -> Self {
 
 impl PartialEq for RegexCheck {
     fn eq(&self, other: &Self) -> bool {
-        self.source == other.source
+        self.source == other.source && self.options == other.options
     }
 }
 
@@ -38,52 +157,157 @@ impl Eq for RegexCheck {}
 impl std::hash::Hash for RegexCheck {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         let Self {
-            // We only hash the source to keep this in line with Eq.
+            // We only hash the source and options to keep this in line with Eq.
             regex: _,
             ref source,
+            options,
+            pointers: _,
         } = *self;
 
         source.hash(state);
+        options.hash(state);
     }
 }
 
 impl RegexCheck {
     pub fn new(source: &str) -> anyhow::Result<Self> {
+        Self::with_options(source, CheckOptions::default())
+    }
+
+    pub fn with_options(source: &str, options: CheckOptions) -> anyhow::Result<Self> {
         let ws_normalized = normalize_whitespace(source);
 
         lazy_static! {
-            static ref INTERNER: Mutex<HashMap<Arc<str>, RegexCheck>> =
+            static ref INTERNER: Mutex<HashMap<(Arc<str>, CheckOptions), RegexCheck>> =
                 Mutex::new(HashMap::default());
         }
 
+        let key: Arc<str> = ws_normalized.clone().into_owned().into();
+
         {
             let interner = INTERNER.lock().unwrap();
 
-            if let Some(interned) = interner.get(&ws_normalized[..]) {
+            if let Some(interned) = interner.get(&(key.clone(), options)) {
                 return Ok(interned.clone());
             }
         }
 
-        let pre_expanded = expand_predefined_regexes(ws_normalized.clone());
-        let regex_str = escape_and_insert_regex_sections(&pre_expanded)?;
+        let mut pre_expanded = expand_predefined_regexes(ws_normalized);
+
+        if options.numeric.is_some() {
+            if pre_expanded.matches(NUMBER_PLACEHOLDER).count() != 1 {
+                bail!(
+                    "Numeric check specification must contain exactly one `{}` placeholder",
+                    NUMBER_PLACEHOLDER
+                );
+            }
+
+            pre_expanded = Cow::from(pre_expanded.replace(
+                NUMBER_PLACEHOLDER,
+                &format!("@{{ (?P<{}>-?(0[xX][0-9a-fA-F]+|[0-9]+)) }}@", NUMBER_GROUP),
+            ));
+        }
+
+        let pointers = extract_pointer_placeholders(&mut pre_expanded)?;
+
+        let mut regex_str = escape_and_insert_regex_sections(&pre_expanded)?;
+
+        if options.anchored {
+            regex_str = format!("^(?:{})$", regex_str);
+        }
 
-        let key: Arc<str> = ws_normalized.into_owned().into();
+        let regex = RegexBuilder::new(&regex_str)
+            .case_insensitive(options.case_insensitive)
+            .build()?;
 
         let regex_check = Self {
-            regex: Arc::new(Regex::new(&regex_str)?),
+            regex: Arc::new(regex),
             source: key.clone(),
+            options,
+            pointers: pointers.into(),
         };
 
         {
             let mut interner = INTERNER.lock().unwrap();
-            interner.insert(key, regex_check.clone());
+            interner.insert((key, options), regex_check.clone());
         }
 
         Ok(regex_check)
     }
 
     pub fn check(&self, text: &str) -> bool {
-        self.regex.is_match(&normalize_whitespace(text))
+        self.check_with_bindings(text, &mut PointerBindings::default())
+    }
+
+    /// Like [RegexCheck::check] but resolves named `{{ptr}}` placeholders against
+    /// `bindings`, adding new bindings if the check matches.
+    pub fn check_with_bindings(&self, text: &str, bindings: &mut PointerBindings) -> bool {
+        let text = normalize_whitespace(text);
+
+        if self.options.numeric.is_none() && self.pointers.is_empty() {
+            return self.regex.is_match(&text);
+        }
+
+        self.regex.captures_iter(&text).any(|captures| {
+            if let Some(constraint) = self.options.numeric {
+                let value = captures
+                    .name(NUMBER_GROUP)
+                    .and_then(|number| parse_number(number.as_str()));
+
+                if !value.is_some_and(|value| constraint.is_satisfied_by(value)) {
+                    return false;
+                }
+            }
+
+            let mut new_bindings = PointerBindings::default();
+
+            for pointer in self.pointers.iter() {
+                let address = match captures
+                    .name(&pointer.group)
+                    .and_then(|address| parse_address(address.as_str()))
+                {
+                    Some(address) => address,
+                    None => return false,
+                };
+
+                let lookup = |name: &Arc<str>| {
+                    new_bindings
+                        .get(name)
+                        .or_else(|| bindings.get(name))
+                        .copied()
+                };
+
+                match &pointer.binding {
+                    None => {}
+                    Some(PointerBinding::Same(name)) => match lookup(name) {
+                        Some(bound) if bound != address => return false,
+                        Some(_) => {}
+                        None => {
+                            new_bindings.insert(name.clone(), address);
+                        }
+                    },
+                    Some(PointerBinding::Different(name)) => match lookup(name) {
+                        Some(bound) if bound != address => {}
+                        _ => return false,
+                    },
+                }
+            }
+
+            bindings.extend(new_bindings);
+            true
+        })
+    }
+}
+
+impl Display for RegexCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.source)?;
+
+        if let Some(constraint) = self.options.numeric {
+            write!(f, " {} {}", constraint.op.symbol(), constraint.value)?;
+        }
+
+        Ok(())
     }
 }
 
@@ -159,7 +383,75 @@ fn expand_predefined_regexes(s: Cow<'_, str>) -> Cow<'_, str> {
     Cow::from(result)
 }
 
-fn normalize_whitespace(s: &str) -> Cow<str> {
+const PTR_PLACEHOLDER_START: &str = "{{ptr";
+const PTR_PLACEHOLDER_END: &str = "}}";
+
+/// Matches addresses as printed by GDB and LLDB (`0x7ffe5b2c`) and by CDB
+/// (`00007ffe`5b2c1234` or `0x00007ffe`5b2c1234`).
+const PTR_REGEX: &str = "(0x)?[0-9a-fA-F]{8}`[0-9a-fA-F]{8}|0x[0-9a-fA-F]+";
+
+/// Replaces `{{ptr}}` placeholders with named regex sections and returns a description
+/// of each placeholder, in order.
+fn extract_pointer_placeholders(s: &mut Cow<'_, str>) -> anyhow::Result<Vec<PointerPlaceholder>> {
+    let mut pointers = Vec::new();
+
+    if !s.contains(PTR_PLACEHOLDER_START) {
+        return Ok(pointers);
+    }
+
+    let mut result = String::new();
+    let mut rest = &s[..];
+
+    while let Some(start) = rest.find(PTR_PLACEHOLDER_START) {
+        result.push_str(&rest[..start]);
+        rest = &rest[start + PTR_PLACEHOLDER_START.len()..];
+
+        let end = match rest.find(PTR_PLACEHOLDER_END) {
+            Some(end) => end,
+            None => bail!("Unclosed {{{{ptr}}}} placeholder in check statement"),
+        };
+
+        let binding = match rest[..end].trim() {
+            "" => None,
+            name if name.starts_with(':') => Some(PointerBinding::Same(name[1..].trim().into())),
+            name if name.starts_with('!') => {
+                Some(PointerBinding::Different(name[1..].trim().into()))
+            }
+            other => bail!("Invalid pointer placeholder `{{{{ptr{}}}}}`", other),
+        };
+
+        if let Some(PointerBinding::Same(name) | PointerBinding::Different(name)) = &binding {
+            if name.is_empty() {
+                bail!("Missing name in pointer placeholder");
+            }
+        }
+
+        let group = format!("__ptr{}__", pointers.len());
+        result.push_str(&format!("@{{ (?P<{}>{}) }}@", group, PTR_REGEX));
+        pointers.push(PointerPlaceholder { group, binding });
+
+        rest = &rest[end + PTR_PLACEHOLDER_END.len()..];
+    }
+
+    result.push_str(rest);
+    *s = Cow::from(result);
+
+    Ok(pointers)
+}
+
+/// Parses an address captured by [PTR_REGEX].
+fn parse_address(s: &str) -> Option<u128> {
+    let digits: String = s
+        .strip_prefix("0x")
+        .unwrap_or(s)
+        .chars()
+        .filter(|&c| c != '`')
+        .collect();
+
+    u128::from_str_radix(&digits, 16).ok()
+}
+
+fn normalize_whitespace(s: &str) -> Cow<'_, str> {
     let s = s.trim();
 
     lazy_static! {
@@ -217,7 +509,7 @@ mod tests {
         PRE_ADDR, PRE_ADDR_REGEX, PRE_ANY, PRE_ANY_REGEX, PRE_U32, PRE_U32_REGEX,
     };
 
-    use super::RegexCheck;
+    use super::{CheckOptions, NumericComparison, NumericConstraint, PointerBindings, RegexCheck};
 
     #[test]
     fn check_simple_string() {
@@ -309,6 +601,121 @@ fn check_regex_with_escape() {
         assert!(!check.check("a[bc dex}"));
     }
 
+    #[test]
+    fn check_case_insensitive() {
+        let options = CheckOptions {
+            case_insensitive: true,
+            ..Default::default()
+        };
+        let check = RegexCheck::with_options("Vec<u8> @{ [a-z]+ }@", options).unwrap();
+
+        assert!(check.check("alloc::vec::vec<U8> ABC"));
+        assert!(check.check("Vec<u8> abc"));
+        assert!(!check.check("Vec<u16> abc"));
+
+        // Interning must not mix up checks with different options
+        assert!(!RegexCheck::new("Vec<u8> @{ [a-z]+ }@")
+            .unwrap()
+            .check("vec<u8> abc"));
+        assert_ne!(check, RegexCheck::new("Vec<u8> @{ [a-z]+ }@").unwrap());
+    }
+
+    #[test]
+    fn check_anchored() {
+        let options = CheckOptions {
+            anchored: true,
+            ..Default::default()
+        };
+        let check = RegexCheck::with_options("x = @{ [0-9] }@", options).unwrap();
+
+        assert!(check.check("x = 1"));
+        assert!(check.check("   x =  1  "));
+        assert!(!check.check("max = 1"));
+        assert!(!check.check("x = 12"));
+        assert!(!check.check("x = 1, y = 2"));
+    }
+
+    #[test]
+    fn check_numeric() {
+        let check = |spec: &str, op, value| {
+            RegexCheck::with_options(
+                spec,
+                CheckOptions {
+                    numeric: Some(NumericConstraint { op, value }),
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+
+        let len_positive = check("len = {}", NumericComparison::GreaterThan, 0);
+        assert!(len_positive.check("Vec(size=3) {len = 3, cap = 4}"));
+        assert!(!len_positive.check("Vec(size=0) {len = 0, cap = 4}"));
+        assert!(!len_positive.check("cap = 4"));
+
+        let addr = check("ptr: {}", NumericComparison::NotEq, 0);
+        assert!(addr.check("ptr: 0x7ffe1234"));
+        assert!(!addr.check("ptr: 0x0"));
+
+        let tid = check("Thread {} (", NumericComparison::LessThanOrEq, 2);
+        assert!(!tid.check("* 2 Thread 12 (LWP 99)"));
+        assert!(tid.check("Thread 2 (LWP 99)"));
+
+        assert!(RegexCheck::with_options(
+            "no placeholder",
+            CheckOptions {
+                numeric: Some(NumericConstraint {
+                    op: NumericComparison::Eq,
+                    value: 1
+                }),
+                ..Default::default()
+            }
+        )
+        .is_err());
+
+        assert_eq!(len_positive.to_string(), "len = {} > 0");
+    }
+
+    #[test]
+    fn check_pointer() {
+        let check = RegexCheck::new("p = {{ptr}}").unwrap();
+        assert!(check.check("p = 0x7ffe5b2c"));
+        assert!(check.check("p = 0x00007ffe`5b2c1234"));
+        assert!(check.check("p = 00007ffe`5b2c1234"));
+        assert!(!check.check("p = 1234"));
+
+        let mut bindings = PointerBindings::default();
+        let bind = RegexCheck::new("a = {{ptr:x}}").unwrap();
+        let same = RegexCheck::new("b = {{ptr:x}}").unwrap();
+        let different = RegexCheck::new("c = {{ptr!x}}").unwrap();
+
+        // `{{ptr!x}}` cannot match before `x` is bound
+        assert!(!different.check_with_bindings("c = 0x20", &mut bindings));
+
+        assert!(bind.check_with_bindings("a = 0x10", &mut bindings));
+        assert!(same.check_with_bindings("b = 0x0010", &mut bindings));
+        assert!(!same.check_with_bindings("b = 0x20", &mut bindings));
+        assert!(different.check_with_bindings("c = 0x20", &mut bindings));
+        assert!(!different.check_with_bindings("c = 0x10", &mut bindings));
+
+        // Both occurrences within a single line must agree, too
+        let pair = RegexCheck::new("({{ptr:y}}, {{ptr:y}})").unwrap();
+        assert!(pair.check("(0x10, 0x10)"));
+        assert!(!pair.check("(0x10, 0x20)"));
+
+        assert!(RegexCheck::new("{{ptr:}}").is_err());
+        assert!(RegexCheck::new("{{ptr").is_err());
+        assert!(RegexCheck::new("{{ptrx}}").is_err());
+    }
+
+    #[test]
+    fn parse_number() {
+        assert_eq!(super::parse_number("123"), Some(123));
+        assert_eq!(super::parse_number("-7"), Some(-7));
+        assert_eq!(super::parse_number("0x1F"), Some(31));
+        assert_eq!(super::parse_number("abc"), None);
+    }
+
     #[test]
     fn replace_predefined() {
         assert_eq!(super::expand_predefined_regexes("@".into()), "@");
@@ -0,0 +1,106 @@
+//! Reproducible builds (`--reproducible`): the test programs are compiled with the path of the
+//! test workspace remapped via `--remap-path-prefix` and with `SOURCE_DATE_EPOCH` pinned, like
+//! distributions build their packages. The paths in the debuginfo then don't exist on the
+//! machine, so the debuggers are told where to find the sources instead, which is what the
+//! tests verify.
+
+use std::{ffi::OsString, path::Path};
+
+use crate::{debugger::DebuggerKind, stable_hash};
+
+/// Defined (as `@reproducible`) when running with `--reproducible`, e.g. for checking the
+/// remapped paths
+pub const DEFINE: &str = "reproducible";
+
+/// The value of `SOURCE_DATE_EPOCH` for the Cargo commands
+pub const SOURCE_DATE_EPOCH: &str = "0";
+
+/// The directory the test workspaces are remapped into
+const REMAPPED_ROOT: &str = "/dbt-reproducible";
+
+/// The path the sources of the workspace at `root_path` have in the debuginfo, e.g.
+/// `/dbt-reproducible/0123456789abcdef/tests` for a workspace in a `tests` directory. The
+/// hash of the canonical path keeps workspaces in directories of the same name apart.
+pub fn remapped_path(root_path: &Path) -> String {
+    let canonical_path = root_path
+        .canonicalize()
+        .unwrap_or_else(|_| root_path.to_path_buf());
+    let hash = stable_hash(canonical_path.to_string_lossy().as_bytes());
+
+    match root_path.file_name() {
+        Some(name) => format!("{}/{}/{}", REMAPPED_ROOT, hash, name.to_string_lossy()),
+        None => format!("{}/{}", REMAPPED_ROOT, hash),
+    }
+}
+
+/// Adds the flag remapping the workspace at `root_path` to `flags`, which are encoded like
+/// `CARGO_ENCODED_RUSTFLAGS`
+pub fn with_remapped_paths(mut flags: OsString, root_path: &Path) -> OsString {
+    if !flags.is_empty() {
+        flags.push("\x1f");
+    }
+
+    flags.push("--remap-path-prefix=");
+    flags.push(root_path);
+    flags.push(format!("={}", remapped_path(root_path)));
+
+    flags
+}
+
+/// The command that makes a debugger of `kind` find the sources of the workspace at
+/// `root_path` under their remapped path, if it needs one
+pub fn source_map_command(kind: DebuggerKind, root_path: &Path) -> Option<String> {
+    let remapped_path = remapped_path(root_path);
+    let root_path = root_path.to_string_lossy();
+
+    match kind {
+        DebuggerKind::Gdb => Some(format!(
+            "set substitute-path {} {}",
+            remapped_path, root_path
+        )),
+        DebuggerKind::Lldb => Some(format!(
+            "settings append target.source-map {} {}",
+            remapped_path, root_path
+        )),
+        // CDB tries the trailing parts of a source path in each directory of the source path
+        DebuggerKind::Cdb => Some(format!(".srcpath+ {}", root_path)),
+        DebuggerKind::Mock => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsString, path::Path};
+
+    use crate::debugger::DebuggerKind;
+
+    #[test]
+    fn remapping() {
+        let root_path = Path::new("/home/ci/rust-dbg-ext/tests");
+
+        assert_eq!(
+            super::with_remapped_paths(OsString::from("-Cforce-frame-pointers"), root_path),
+            "-Cforce-frame-pointers\x1f--remap-path-prefix=/home/ci/rust-dbg-ext/tests=\
+             /dbt-reproducible/dbea8e7949ed1f67/tests"
+        );
+        assert_eq!(
+            super::source_map_command(DebuggerKind::Gdb, root_path).unwrap(),
+            "set substitute-path /dbt-reproducible/dbea8e7949ed1f67/tests \
+             /home/ci/rust-dbg-ext/tests"
+        );
+        assert_eq!(
+            super::source_map_command(DebuggerKind::Lldb, root_path).unwrap(),
+            "settings append target.source-map /dbt-reproducible/dbea8e7949ed1f67/tests \
+             /home/ci/rust-dbg-ext/tests"
+        );
+        // Workspaces in directories of the same name don't share a remapped path
+        assert_ne!(
+            super::remapped_path(root_path),
+            super::remapped_path(Path::new("/home/ci/other/tests"))
+        );
+        assert_eq!(
+            super::source_map_command(DebuggerKind::Mock, root_path),
+            None
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! Suggesting `#check` statements for a test from the actual output of its commands
+//! (`dbt scaffold-checks`).
+
+use std::collections::BTreeMap;
+
+use lazy_static::lazy_static;
+use regex::Regex;
+
+/// Commands with more output than this only get checks for the first lines of it
+const MAX_CHECKS_PER_COMMAND: usize = 20;
+
+/// Turns a line of debugger output into a check specification matching it, with addresses
+/// replaced by `{{ptr}}` placeholders. Returns `None` for blank lines.
+pub fn check_specification(output_line: &str) -> Option<String> {
+    lazy_static! {
+        // The address formats matched by `{{ptr}}`. Short hex numbers are more likely to be
+        // values than addresses, so they are kept.
+        static ref ADDRESS: Regex =
+            Regex::new(r"\b(?:0x)?[0-9a-fA-F]{8}`[0-9a-fA-F]{8}\b|\b0x[0-9a-fA-F]{6,}\b").unwrap();
+    }
+
+    let normalized = output_line.split_whitespace().collect::<Vec<_>>().join(" ");
+
+    if normalized.is_empty() {
+        return None;
+    }
+
+    let specification = ADDRESS.replace_all(&normalized, "{{ptr}}");
+
+    // `//` would start a comment in the test script
+    Some(specification.replace("//", "@{ /{2} }@"))
+}
+
+/// Inserts checks for the output of each command in `source` (keyed by the command's line
+/// number) after the command. Commands that are already followed by a check are left
+/// alone. Returns the new source and the number of checks inserted.
+pub fn insert_checks(
+    source: &str,
+    output_by_command: &BTreeMap<u32, Vec<&str>>,
+) -> (String, usize) {
+    let lines: Vec<&str> = source.split_inclusive('\n').collect();
+
+    let mut result = String::new();
+    let mut inserted = 0;
+
+    for (index, line) in lines.iter().enumerate() {
+        result.push_str(line);
+
+        let output = match output_by_command.get(&(index as u32 + 1)) {
+            Some(output) => output,
+            None => continue,
+        };
+
+        if is_followed_by_check(&lines[index + 1..]) {
+            continue;
+        }
+
+        if !line.ends_with('\n') {
+            result.push('\n');
+        }
+
+        let indent = &line[..line.len() - line.trim_start().len()];
+        let specifications: Vec<String> = output
+            .iter()
+            .filter_map(|output_line| check_specification(output_line))
+            .collect();
+
+        for specification in specifications.iter().take(MAX_CHECKS_PER_COMMAND) {
+            result.push_str(&format!("{}#check {}\n", indent, specification));
+            inserted += 1;
+        }
+
+        if specifications.len() > MAX_CHECKS_PER_COMMAND {
+            result.push_str(&format!(
+                "{}// ... {} more lines of output\n",
+                indent,
+                specifications.len() - MAX_CHECKS_PER_COMMAND
+            ));
+        }
+    }
+
+    (result, inserted)
+}
+
+/// Whether the next line that isn't blank or a comment is a check of any kind
+fn is_followed_by_check(lines: &[&str]) -> bool {
+    lines
+        .iter()
+        .map(|line| line.trim())
+        .find(|line| !line.is_empty() && !line.starts_with("//"))
+        .is_some_and(|line| line.starts_with("#check"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    #[test]
+    fn check_specification() {
+        assert_eq!(
+            super::check_specification("  $1 =   (*mut u8) 0x5555555592a0 "),
+            Some("$1 = (*mut u8) {{ptr}}".to_string())
+        );
+        assert_eq!(
+            super::check_specification("0x1f at 00007ff6`1a2b3c4d"),
+            Some("0x1f at {{ptr}}".to_string())
+        );
+        assert_eq!(
+            super::check_specification("url = \"https://example.com\""),
+            Some("url = \"https:@{ /{2} }@example.com\"".to_string())
+        );
+        assert_eq!(super::check_specification("   "), None);
+    }
+
+    #[test]
+    fn insert_checks() {
+        let source = "/***\nprint a\n#if @gdb\n    print b\nprint c\n// comment\n#check 3\n***/\nfn main() {}\n";
+
+        let output = BTreeMap::from([
+            (2, vec!["$1 = 1", "", "$2 = 0x7ffe5b2c1234"]),
+            (4, vec!["$3 = 2"]),
+            (5, vec!["$4 = 3"]),
+        ]);
+
+        assert_eq!(
+            super::insert_checks(source, &output),
+            (
+                "/***\nprint a\n#check $1 = 1\n#check $2 = {{ptr}}\n#if @gdb\n    print b\n    #check $3 = 2\nprint c\n// comment\n#check 3\n***/\nfn main() {}\n".to_string(),
+                3
+            )
+        );
+    }
+}
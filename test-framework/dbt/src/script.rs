@@ -1,4 +1,4 @@
-use anyhow::{bail, Context};
+use anyhow::bail;
 use lazy_static::lazy_static;
 use log::warn;
 use regex::Regex;
@@ -7,11 +7,16 @@
     convert::TryInto,
     fmt::Display,
     iter::Peekable,
-    path::Path,
+    ops::Range,
+    path::{Path, PathBuf},
     sync::{Arc, Mutex},
 };
 
-use crate::{prettify_path, regex_check::RegexCheck};
+use crate::{
+    breakpoints::BreakPoint,
+    prettify_path,
+    regex_check::{parse_number, CheckOptions, NumericComparison, NumericConstraint, RegexCheck},
+};
 
 /// The AST of a test script. It is used for
 ///
@@ -20,6 +25,9 @@
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Script {
     pub statements: Vec<Statement>,
+    /// The lines of the script without their comments, by line number, e.g. for selecting
+    /// checks via `--check-filter`. Scripts built with [ScriptBuilder] have none.
+    pub source_lines: HashMap<u32, Arc<str>>,
 }
 
 /// Defines an environment for evaluating #if directives. It maps names like `version` to
@@ -41,6 +49,7 @@ impl Script {
     pub fn new_empty() -> Self {
         Self {
             statements: Vec::new(),
+            source_lines: HashMap::new(),
         }
     }
 
@@ -71,14 +80,15 @@ pub fn phases(&self, context: &EvaluationContext) -> Vec<PhaseConfig> {
         phases
     }
 
-    /// Returns true if this test should be ignored because and #ignore-test statement
-    /// is encountered for the given evaluation context.
-    pub fn ignore_test(&self, context: &EvaluationContext) -> bool {
-        let mut ignore_test = false;
+    /// Returns `Some` if this test should be ignored because an #ignore-test statement
+    /// is encountered for the given evaluation context. The inner value is the optional
+    /// reason given in that statement.
+    pub fn ignore_test(&self, context: &EvaluationContext) -> Option<Option<Arc<str>>> {
+        let mut ignore_test = None;
 
         self.walk_applicable_leaves(context, &mut |statement| {
-            if matches!(statement, Statement::IgnoreTest(_)) {
-                ignore_test = true;
+            if let Statement::IgnoreTest(reason, _) = statement {
+                ignore_test = Some(reason.clone());
                 false
             } else {
                 true
@@ -88,6 +98,239 @@ pub fn ignore_test(&self, context: &EvaluationContext) -> bool {
         ignore_test
     }
 
+    /// Returns all requirements declared via #require statements that apply to the
+    /// given evaluation context.
+    pub fn requirements(&self, context: &EvaluationContext) -> Vec<Requirement> {
+        let mut requirements = vec![];
+
+        self.walk_applicable_leaves(context, &mut |statement| {
+            if let Statement::Require(requirement, _) = statement {
+                if !requirements.contains(requirement) {
+                    requirements.push(*requirement);
+                }
+            }
+
+            true
+        });
+
+        requirements
+    }
+
+    /// Returns the smallest memory limit in bytes set via #max-memory that applies to the
+    /// given evaluation context, if any.
+    pub fn max_memory(&self, context: &EvaluationContext) -> Option<u64> {
+        let mut max_memory: Option<u64> = None;
+
+        self.walk_applicable_leaves(context, &mut |statement| {
+            if let Statement::MaxMemory(bytes, _) = statement {
+                max_memory = Some(max_memory.map_or(*bytes, |max| max.min(*bytes)));
+            }
+
+            true
+        });
+
+        max_memory
+    }
+
+    /// Returns the names of the tests declared via #depends-on that apply to the given
+    /// evaluation context, in the order they are declared.
+    pub fn dependencies(&self, context: &EvaluationContext) -> Vec<Arc<str>> {
+        let mut dependencies: Vec<Arc<str>> = vec![];
+
+        self.walk_applicable_leaves(context, &mut |statement| {
+            if let Statement::DependsOn(test_name, _) = statement {
+                if !dependencies.contains(test_name) {
+                    dependencies.push(test_name.clone());
+                }
+            }
+
+            true
+        });
+
+        dependencies
+    }
+
+    /// Returns the command line arguments for the test program given via #args statements
+    /// that apply to the given evaluation context, in the order they are declared.
+    pub fn debuggee_args(&self, context: &EvaluationContext) -> Vec<String> {
+        let mut debuggee_args = vec![];
+
+        self.walk_applicable_leaves(context, &mut |statement| {
+            if let Statement::Args(args, _) = statement {
+                debuggee_args.extend(args.iter().map(|arg| arg.to_string()));
+            }
+
+            true
+        });
+
+        debuggee_args
+    }
+
+    /// Returns the crates declared via #dependencies, including the ones in `#if` blocks, as
+    /// the test program is compiled once for all debuggers.
+    pub fn crate_dependencies(&self) -> Vec<(CrateDependency, LineNumber)> {
+        fn collect(
+            statements: &[Statement],
+            dependencies: &mut Vec<(CrateDependency, LineNumber)>,
+        ) {
+            for statement in statements {
+                match statement {
+                    Statement::IfBlock(_, statements, _)
+                    | Statement::Group(_, statements, _)
+                    | Statement::EachHit(_, _, statements, _) => collect(statements, dependencies),
+                    Statement::Dependency(dependency, line_number) => {
+                        dependencies.push((dependency.clone(), *line_number))
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let mut dependencies = vec![];
+        collect(&self.statements, &mut dependencies);
+        dependencies
+    }
+
+    /// Returns the compiler flags given via #rustc-flags, including the ones in `#if` blocks,
+    /// as the test program is compiled once for all debuggers.
+    pub fn rustc_flags(&self) -> Vec<Arc<str>> {
+        fn collect(statements: &[Statement], flags: &mut Vec<Arc<str>>) {
+            for statement in statements {
+                match statement {
+                    Statement::IfBlock(_, statements, _)
+                    | Statement::Group(_, statements, _)
+                    | Statement::EachHit(_, _, statements, _) => collect(statements, flags),
+                    Statement::RustcFlags(rustc_flags, _) => {
+                        flags.extend(rustc_flags.iter().cloned())
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let mut flags = vec![];
+        collect(&self.statements, &mut flags);
+        flags
+    }
+
+    /// Returns the target triples given via #targets, or `None` if the test doesn't restrict
+    /// them. Like #rustc-flags, they are needed before compiling, so #if blocks don't
+    /// affect them.
+    pub fn targets(&self) -> Option<Vec<Arc<str>>> {
+        fn collect(statements: &[Statement], targets: &mut Option<Vec<Arc<str>>>) {
+            for statement in statements {
+                match statement {
+                    Statement::IfBlock(_, statements, _)
+                    | Statement::Group(_, statements, _)
+                    | Statement::EachHit(_, _, statements, _) => collect(statements, targets),
+                    Statement::Targets(triples, _) => targets
+                        .get_or_insert_with(Vec::new)
+                        .extend(triples.iter().cloned()),
+                    _ => {}
+                }
+            }
+        }
+
+        let mut targets = None;
+        collect(&self.statements, &mut targets);
+        targets
+    }
+
+    /// Returns the target given via #debuggee-target, if any. Like #targets, it is needed
+    /// before compiling, so #if blocks don't affect it.
+    pub fn debuggee_target(&self) -> Option<&DebuggeeTarget> {
+        fn find(statements: &[Statement]) -> Option<&DebuggeeTarget> {
+            statements.iter().find_map(|statement| match statement {
+                Statement::IfBlock(_, statements, _)
+                | Statement::Group(_, statements, _)
+                | Statement::EachHit(_, _, statements, _) => find(statements),
+                Statement::DebuggeeTarget(debuggee_target, _) => Some(debuggee_target),
+                _ => None,
+            })
+        }
+
+        find(&self.statements)
+    }
+
+    /// Whether the test opts into running the C program next to it as its baseline via
+    /// #c-baseline. Like #targets, it is needed before compiling, so #if blocks don't
+    /// affect it.
+    pub fn has_c_baseline(&self) -> bool {
+        fn find(statements: &[Statement]) -> bool {
+            statements.iter().any(|statement| match statement {
+                Statement::IfBlock(_, statements, _)
+                | Statement::Group(_, statements, _)
+                | Statement::EachHit(_, _, statements, _) => find(statements),
+                Statement::CBaseline(_) => true,
+                _ => false,
+            })
+        }
+
+        find(&self.statements)
+    }
+
+    /// Returns the names of all breakpoints referenced via `#break name`, including
+    /// the ones in `#if` blocks that are not applicable to the current debugger.
+    pub fn breakpoint_references(&self) -> Vec<(Arc<str>, LineNumber)> {
+        fn collect(statements: &[Statement], references: &mut Vec<(Arc<str>, LineNumber)>) {
+            for statement in statements {
+                match statement {
+                    Statement::IfBlock(_, statements, _)
+                    | Statement::Group(_, statements, _)
+                    | Statement::EachHit(_, _, statements, _) => collect(statements, references),
+                    Statement::SetBreakpoint(name, _, line_number) => {
+                        references.push((name.clone(), *line_number))
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        let mut references = vec![];
+        collect(&self.statements, &mut references);
+        references
+    }
+
+    /// Returns the label of the innermost #group containing the statement at the
+    /// given line, if any.
+    pub fn group_label(&self, line_number: LineNumber) -> Option<Arc<str>> {
+        self.find_group_label(&|statement| statement.line_number().0 == line_number.0)
+    }
+
+    /// Like [Script::group_label] but looks up the given statement instead of its line.
+    /// This distinguishes statements on the same line, e.g. the iterations of an
+    /// `#each-hit` block, as long as they have correlation IDs assigned.
+    pub fn group_label_of(&self, statement: &Statement) -> Option<Arc<str>> {
+        self.find_group_label(&|candidate| candidate == statement)
+    }
+
+    fn find_group_label(&self, predicate: &dyn Fn(&Statement) -> bool) -> Option<Arc<str>> {
+        fn find<'a>(
+            statements: &'a [Statement],
+            predicate: &dyn Fn(&Statement) -> bool,
+            current_label: Option<&'a Arc<str>>,
+        ) -> Option<Option<&'a Arc<str>>> {
+            for statement in statements {
+                let found = match statement {
+                    Statement::IfBlock(_, nested, _) | Statement::EachHit(_, _, nested, _) => {
+                        find(nested, predicate, current_label)
+                    }
+                    Statement::Group(label, nested, _) => find(nested, predicate, Some(label)),
+                    statement if predicate(statement) => Some(current_label),
+                    _ => None,
+                };
+
+                if found.is_some() {
+                    return found;
+                }
+            }
+
+            None
+        }
+
+        find(&self.statements, predicate, None).flatten().cloned()
+    }
+
     pub fn active_crashdump_tags(&self, context: &EvaluationContext) -> Vec<Arc<str>> {
         let mut tags = vec![];
 
@@ -102,13 +345,69 @@ pub fn active_crashdump_tags(&self, context: &EvaluationContext) -> Vec<Arc<str>
         tags
     }
 
+    /// Returns the `#check-section` and `#check-symbol` statements applicable in `context`
+    pub fn object_checks(&self, context: &EvaluationContext) -> Vec<(ObjectCheck, LineNumber)> {
+        let mut object_checks = vec![];
+
+        self.walk_applicable_leaves(context, &mut |statement| {
+            if let Statement::ObjectCheck(object_check, line_number) = statement {
+                object_checks.push((object_check.clone(), *line_number));
+            }
+
+            true
+        });
+
+        object_checks
+    }
+
+    /// Returns the `#check-program-output` statements applicable in `context`
+    pub fn program_output_checks(
+        &self,
+        context: &EvaluationContext,
+    ) -> Vec<(RegexCheck, LineNumber)> {
+        let mut checks = vec![];
+
+        self.walk_applicable_leaves(context, &mut |statement| {
+            if let Statement::ProgramOutputCheck(check, line_number) = statement {
+                checks.push((check.clone(), *line_number));
+            }
+
+            true
+        });
+
+        checks
+    }
+
+    /// Returns the `#dwarfdump-check` and `#pdb-check` statements applicable in `context`
+    pub fn static_checks(&self, context: &EvaluationContext) -> Vec<(StaticCheck, LineNumber)> {
+        let mut static_checks = vec![];
+
+        self.walk_applicable_leaves(context, &mut |statement| {
+            if let Statement::StaticCheck(static_check, line_number) = statement {
+                static_checks.push((static_check.clone(), *line_number));
+            }
+
+            true
+        });
+
+        static_checks
+    }
+
+    /// Whether there are any checks applicable in `context` that don't need a debugger
+    pub fn has_static_checks(&self, context: &EvaluationContext) -> bool {
+        !self.static_checks(context).is_empty() || !self.object_checks(context).is_empty()
+    }
+
     pub fn has_active_checks(&self, context: &EvaluationContext) -> bool {
         let mut result = false;
 
         self.walk_applicable_leaves(context, &mut |statement| {
             if matches!(
                 statement,
-                Statement::Check(..) | Statement::CheckUnorderedBlock(..)
+                Statement::Check(..)
+                    | Statement::CheckUnorderedBlock(..)
+                    | Statement::CommandCheck(..)
+                    | Statement::ProgramOutputCheck(..)
             ) {
                 result = true;
                 false
@@ -149,6 +448,262 @@ pub fn walk_applicable_leaves_mut(
             }
         }
     }
+
+    /// Returns a copy of the script with a `#sequence` before each command, so that the
+    /// output of every command ends up in a correlation block of its own.
+    pub fn with_sequence_points(&self) -> Script {
+        Script {
+            statements: with_sequence_points(&self.statements),
+            source_lines: self.source_lines.clone(),
+        }
+    }
+
+    /// Returns the script without its checks, and without the directives that only apply
+    /// to compiling the Rust test program, for running it against a C baseline
+    pub fn without_checks(&self) -> Script {
+        fn filter(statements: &[Statement]) -> Vec<Statement> {
+            statements
+                .iter()
+                .filter_map(|statement| match statement {
+                    Statement::IfBlock(condition, statements, line_number) => Some(
+                        Statement::IfBlock(condition.clone(), filter(statements), *line_number),
+                    ),
+                    Statement::Group(label, statements, line_number) => Some(Statement::Group(
+                        label.clone(),
+                        filter(statements),
+                        *line_number,
+                    )),
+                    Statement::EachHit(name, count, statements, line_number) => Some(
+                        Statement::EachHit(name.clone(), *count, filter(statements), *line_number),
+                    ),
+                    Statement::Check(..)
+                    | Statement::CheckUnorderedBlock(..)
+                    | Statement::CommandCheck(..)
+                    | Statement::StaticCheck(..)
+                    | Statement::ObjectCheck(..)
+                    | Statement::ProgramOutputCheck(..)
+                    | Statement::Dependency(..)
+                    | Statement::RustcFlags(..)
+                    | Statement::CBaseline(..) => None,
+                    statement => Some(statement.clone()),
+                })
+                .collect()
+        }
+
+        Script {
+            statements: filter(&self.statements),
+            source_lines: self.source_lines.clone(),
+        }
+    }
+}
+
+/// Builds a [Script] in Rust code, e.g. for generating tests from a table of types, as an
+/// alternative to writing a script in the comments of the test program. Specifications
+/// and conditions use the same syntax as in scripts; errors in them are reported by
+/// [ScriptBuilder::build].
+///
+/// ```
+/// # use dbt::script::ScriptBuilder;
+/// let script = ScriptBuilder::new()
+///     .exec("break main.rs:10")
+///     .exec("run")
+///     .if_block("@gdb", |script| script.exec("print x").check("$1 = 5"))
+///     .build()
+///     .unwrap();
+///
+/// assert_eq!(script.statements.len(), 3);
+/// ```
+///
+/// Statements get consecutive line numbers starting at 1, as there are no source lines to
+/// refer to, so diagnostics about the script point at the n-th statement.
+#[derive(Debug)]
+pub struct ScriptBuilder {
+    statements: Vec<Statement>,
+    next_line_number: u32,
+    error: Option<anyhow::Error>,
+}
+
+impl Default for ScriptBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ScriptBuilder {
+    pub fn new() -> Self {
+        Self {
+            statements: vec![],
+            next_line_number: 1,
+            error: None,
+        }
+    }
+
+    /// A debugger command that is passed through as is
+    pub fn exec(self, command: &str) -> Self {
+        self.push(|line_number| Ok(Statement::Exec(command.to_string(), None, line_number)))
+    }
+
+    /// `#check <spec>`
+    pub fn check(self, spec: &str) -> Self {
+        self.check_with_options(spec, CheckOptions::default())
+    }
+
+    /// `#check <spec>` with modifiers, e.g. `#check-exact` via [CheckOptions::anchored]
+    pub fn check_with_options(self, spec: &str, options: CheckOptions) -> Self {
+        let follows_check = matches!(
+            self.statements.last(),
+            Some(Statement::Check(check, ..)) if !check.options.negated
+        );
+
+        self.push(|line_number| {
+            if options.same_line && !follows_check {
+                bail!(
+                    "{} must follow a {} statement",
+                    TOKEN_CHECK_SAME,
+                    TOKEN_CHECK
+                );
+            }
+
+            Ok(Statement::Check(
+                RegexCheck::with_options(spec, options)?,
+                None,
+                line_number,
+            ))
+        })
+    }
+
+    /// `#check-unordered` followed by the given specifications
+    pub fn check_unordered(self, specs: &[&str]) -> Self {
+        self.push(|line_number| {
+            for spec in specs {
+                RegexCheck::new(spec)?;
+            }
+
+            Ok(Statement::CheckUnorderedBlock(
+                specs.iter().map(|spec| spec.to_string()).collect(),
+                None,
+                line_number,
+            ))
+        })
+    }
+
+    /// `#break <name>`
+    pub fn set_breakpoint(self, name: &str) -> Self {
+        self.push(|line_number| Ok(Statement::SetBreakpoint(name.into(), None, line_number)))
+    }
+
+    /// A debugger-agnostic command, e.g. `#thread 2`
+    pub fn command(self, command: DebuggerCommand) -> Self {
+        self.push(|line_number| Ok(Statement::Command(command, None, line_number)))
+    }
+
+    /// A check that issues its own command, e.g. `#check-thread-count 3`
+    pub fn command_check(self, check: CommandCheck) -> Self {
+        self.push(|line_number| Ok(Statement::CommandCheck(check, None, line_number)))
+    }
+
+    /// `#sequence`
+    pub fn sequence(self) -> Self {
+        self.push(|line_number| Ok(Statement::Sequence(line_number)))
+    }
+
+    /// `#ignore-test [reason]`
+    pub fn ignore_test(self, reason: Option<&str>) -> Self {
+        self.push(|line_number| Ok(Statement::IgnoreTest(reason.map(Into::into), line_number)))
+    }
+
+    /// `#require <requirement>`
+    pub fn require(self, requirement: Requirement) -> Self {
+        self.push(|line_number| Ok(Statement::Require(requirement, line_number)))
+    }
+
+    /// `#phase ...`, see [PhaseConfig]
+    pub fn phase(self, phase_config: PhaseConfig) -> Self {
+        self.push(|line_number| Ok(Statement::Phase(phase_config, line_number)))
+    }
+
+    /// `#if <condition>` with the statements added by `body` nested under it
+    pub fn if_block(self, condition: &str, body: impl FnOnce(Self) -> Self) -> Self {
+        let condition = parse_condition(&mut tokenize(condition).peekable());
+
+        self.nest(body, |statements, line_number| {
+            Ok(Statement::IfBlock(condition?, statements, line_number))
+        })
+    }
+
+    /// `#group "<label>"` with the statements added by `body` nested under it
+    pub fn group(self, label: &str, body: impl FnOnce(Self) -> Self) -> Self {
+        self.nest(body, |statements, line_number| {
+            Ok(Statement::Group(label.into(), statements, line_number))
+        })
+    }
+
+    /// Adds a statement that has no dedicated method. Its line number is replaced.
+    pub fn statement(self, statement: Statement) -> Self {
+        self.push(|line_number| {
+            let mut statement = statement;
+            *statement.line_number_mut() = line_number;
+            Ok(statement)
+        })
+    }
+
+    /// The script, or the first error in a specification or condition
+    pub fn build(self) -> anyhow::Result<Script> {
+        match self.error {
+            Some(error) => Err(error),
+            None => Ok(Script {
+                statements: self.statements,
+                source_lines: HashMap::new(),
+            }),
+        }
+    }
+
+    fn push(mut self, statement: impl FnOnce(LineNumber) -> anyhow::Result<Statement>) -> Self {
+        let line_number = LineNumber(self.next_line_number);
+        self.next_line_number += 1;
+
+        if self.error.is_none() {
+            match statement(line_number) {
+                Ok(statement) => self.statements.push(statement),
+                Err(error) => {
+                    self.error = Some(error.context(format!("statement {}", line_number.0)))
+                }
+            }
+        }
+
+        self
+    }
+
+    fn nest(
+        mut self,
+        body: impl FnOnce(Self) -> Self,
+        statement: impl FnOnce(Vec<Statement>, LineNumber) -> anyhow::Result<Statement>,
+    ) -> Self {
+        let line_number = LineNumber(self.next_line_number);
+
+        let nested = body(Self {
+            statements: vec![],
+            next_line_number: line_number.0 + 1,
+            error: None,
+        });
+
+        self.next_line_number = nested.next_line_number;
+
+        if self.error.is_none() {
+            self.error = nested.error;
+        }
+
+        if self.error.is_none() {
+            match statement(nested.statements, line_number) {
+                Ok(statement) => self.statements.push(statement),
+                Err(error) => {
+                    self.error = Some(error.context(format!("statement {}", line_number.0)))
+                }
+            }
+        }
+
+        self
+    }
 }
 
 #[derive(Debug, Eq, Clone)]
@@ -267,84 +822,525 @@ fn eq(&self, other: &Self) -> bool {
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Statement {
     IfBlock(Condition, Vec<Statement>, LineNumber),
+    /// `#group "label"` -- labels the nested statements for better diagnostics
+    Group(/* label */ Arc<str>, Vec<Statement>, LineNumber),
     CheckUnorderedBlock(Vec<String>, Option<CorrelationId>, LineNumber),
     Exec(String, Option<CorrelationId>, LineNumber),
     Check(RegexCheck, Option<CorrelationId>, LineNumber),
-    IgnoreTest(LineNumber),
+    IgnoreTest(/* reason */ Option<Arc<str>>, LineNumber),
+    Require(Requirement, LineNumber),
+    /// `#max-memory 512M` -- fails the test if the debugger uses more memory (in bytes)
+    MaxMemory(u64, LineNumber),
+    /// `#depends-on ws/setup/src/main.rs` -- the test only runs after the named test passed
+    DependsOn(/* test name */ Arc<str>, LineNumber),
+    /// `#dependencies serde = "1"` -- the test program needs the given crate
+    Dependency(CrateDependency, LineNumber),
+    /// `#args my_test --exact` -- command line arguments for the test program
+    Args(Vec<Arc<str>>, LineNumber),
+    /// `#rustc-flags -Zdebug-info-for-profiling` -- additional flags for compiling the test
+    RustcFlags(Vec<Arc<str>>, LineNumber),
+    /// `#targets x86_64-pc-windows-msvc` -- the test is only compiled and run for these targets
+    Targets(Vec<Arc<str>>, LineNumber),
+    /// `#debuggee-target example:foo` -- debug this target of the package instead of the
+    /// test program
+    DebuggeeTarget(DebuggeeTarget, LineNumber),
+    /// `#c-baseline` -- run the C program next to the test (e.g. `src/main.c`) as its
+    /// baseline, see [crate::baseline]
+    CBaseline(LineNumber),
+    /// `#sequence` -- forces the next command to start a new correlation block
+    Sequence(LineNumber),
     Phase(PhaseConfig, LineNumber),
     GenerateCrashDump(/* tag */ Arc<str>, Option<CorrelationId>, LineNumber),
+    /// A debugger-agnostic command like `#thread 2`, translated for each debugger
+    Command(DebuggerCommand, Option<CorrelationId>, LineNumber),
+    /// A check that issues its own debugger command, e.g. `#check-thread-count 3`
+    CommandCheck(CommandCheck, Option<CorrelationId>, LineNumber),
+    /// `#break name` -- sets a breakpoint at the `// #break: name` marker in the source file
+    SetBreakpoint(/* name */ Arc<str>, Option<CorrelationId>, LineNumber),
+    /// `#each-hit name N` -- the nested statements are expanded at parse time into a
+    /// `#break name` followed by `N` groups of `continue` plus the nested statements.
+    EachHit(
+        /* breakpoint name */ Arc<str>,
+        /* count */ u32,
+        /* expanded */ Vec<Statement>,
+        LineNumber,
+    ),
+    /// `#dwarfdump-check <args>` or `#pdb-check <args>` -- checks the output of a tool that
+    /// inspects the debuggee's debuginfo without running it, see [StaticCheck]
+    StaticCheck(StaticCheck, LineNumber),
+    /// `#check-section` or `#check-symbol` -- checks the sections or symbols of the debuggee,
+    /// read from the executable itself, see [ObjectCheck]
+    ObjectCheck(ObjectCheck, LineNumber),
+    /// `#check-program-output <spec>` -- checks what the debuggee printed to its stdout,
+    /// which is captured apart from the debugger's output where the debugger supports it
+    ProgramOutputCheck(RegexCheck, LineNumber),
 }
 
-impl Statement {
-    pub fn line_number(&self) -> LineNumber {
-        match *self {
-            Statement::IfBlock(_, _, line_number)
-            | Statement::CheckUnorderedBlock(_, _, line_number)
-            | Statement::Exec(_, _, line_number)
-            | Statement::Check(_, _, line_number)
-            | Statement::IgnoreTest(line_number)
-            | Statement::Phase(_, line_number)
-            | Statement::GenerateCrashDump(_, _, line_number) => line_number,
-        }
-    }
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ObjectCheck {
+    /// `#check-section .debug_info compressed` -- the section exists and, if given, is
+    /// (un)compressed
+    Section(/* name */ Arc<str>, Option<SectionCompression>),
+    /// `#check-symbol <spec>` -- some symbol's name, mangled or demangled, matches the whole
+    /// specification
+    Symbol(RegexCheck),
+}
 
-    fn walk_applicable_leaves<'a>(
-        &'a self,
-        context: &EvaluationContext,
-        f: &mut dyn FnMut(&'a Statement) -> bool,
-    ) -> bool {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SectionCompression {
+    Compressed,
+    Uncompressed,
+}
+
+impl Display for SectionCompression {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
-            Statement::IfBlock(condition, statements, _) => {
-                if condition.eval(context) {
-                    for statement in statements {
-                        if !statement.walk_applicable_leaves(context, f) {
-                            return false;
-                        }
-                    }
-                }
-                true
-            }
-            _ => f(self),
+            SectionCompression::Compressed => write!(f, "{}", TOKEN_COMPRESSED),
+            SectionCompression::Uncompressed => write!(f, "{}", TOKEN_UNCOMPRESSED),
         }
     }
+}
 
-    fn walk_applicable_leaves_mut<'a>(
-        &'a mut self,
-        context: &EvaluationContext,
-        f: &mut dyn FnMut(&'a mut Statement) -> bool,
-    ) -> bool {
+/// Runs a tool like `llvm-dwarfdump` with the given arguments (followed by the path of the
+/// debuggee or its PDB) and matches the check specifications against its output, in order.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StaticCheck {
+    pub tool: StaticTool,
+    pub args: Vec<Arc<str>>,
+    pub checks: Vec<RegexCheck>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum StaticTool {
+    /// `#dwarfdump-check`
+    Dwarfdump,
+    /// `#pdb-check`, which inspects the PDB instead of the debuggee itself
+    Pdbutil,
+}
+
+impl StaticTool {
+    pub fn keyword(self) -> &'static str {
         match self {
-            Statement::IfBlock(condition, statements, _) => {
-                if condition.eval(context) {
-                    for statement in statements {
-                        if !statement.walk_applicable_leaves_mut(context, f) {
-                            return false;
-                        }
-                    }
-                }
-                true
-            }
-            _ => f(self),
+            StaticTool::Dwarfdump => TOKEN_DWARFDUMP_CHECK,
+            StaticTool::Pdbutil => TOKEN_PDB_CHECK,
         }
     }
 }
 
+/// Commands that are spelled differently by each debugger. See `Debugger::emit_command()`
+/// for how they are translated.
 #[derive(Debug, Clone, PartialEq, Eq)]
-struct Line {
-    kind: LineKind,
-    indent: isize,
-    line_number: LineNumber,
+pub enum DebuggerCommand {
+    /// `#thread 2` -- makes the thread with the given (1-based) number the current one
+    SelectThread(u32),
+    /// Lists all threads of the debuggee, emitted for `#check-thread-count`
+    ListThreads,
+    /// Lists the local variables of the current frame, emitted for `#check-locals`
+    ListLocals,
+    /// Prints the fields of a type with their offsets and sizes, emitted for `#check-type`
+    DescribeType(Arc<str>),
+    /// `#frame 1` -- selects the stack frame with the given number, 0 being the innermost
+    SelectFrame(u32),
+    /// `#up` -- selects the frame of the caller of the current frame
+    FrameUp,
+    /// `#down` -- selects the frame called by the current frame
+    FrameDown,
+    /// Prints the value of a register, emitted for `#check-register`
+    ReadRegister(Arc<str>),
+    /// Dumps the given number of bytes at an address, emitted for `#check-memory`
+    ReadMemory(/* expression */ Arc<str>, /* length */ u32),
+    /// `#follow-fork child` -- which process to debug after the debuggee forks or
+    /// spawns a child process
+    FollowFork(ForkMode),
+    /// `#inferior 2` -- makes the process with the given (1-based) number the current one
+    SelectInferior(u32),
+    /// Resumes execution until the next breakpoint, emitted for `#each-hit`
+    Continue,
+    /// Resumes execution and reports how the debuggee exited, emitted for
+    /// `#expect-clean-exit`
+    ContinueToExit,
+    /// `#print v` -- prints the value of an expression. Its output always forms a
+    /// correlation block of its own, so it can be compared across debuggers.
+    Print(/* expression */ Arc<str>),
+    /// `#reverse-step` -- steps backwards over the previous line, e.g. when replaying a
+    /// TTD trace
+    ReverseStep,
+    /// `#reverse-step-into` -- steps backwards into the previous line's calls
+    ReverseStepInto,
+    /// `#reverse-continue` -- runs backwards until the previous breakpoint
+    ReverseContinue,
+    /// `#reverse-finish` -- runs backwards to where the current function was called
+    ReverseFinish,
+    /// `#watch write 4 counter` -- sets a hardware watchpoint, see [Watchpoint]
+    Watch(Watchpoint),
 }
 
+/// A data breakpoint, set via `#watch [read|write|access] [1|2|4|8] <place>`
 #[derive(Debug, Clone, PartialEq, Eq)]
-enum LineKind {
-    If { condition: Condition },
-    Check { check: RegexCheck },
-    CheckUnordered,
-    Raw { text: String },
-    IgnoreTest,
-    Phase { phase_config: PhaseConfig },
-    GenerateCrashDump { tag: Arc<str> },
+pub struct Watchpoint {
+    pub access: WatchAccess,
+    /// The number of bytes to watch, if given. GDB watches the whole value of the place and
+    /// CDB watches 4 bytes if no width is given.
+    pub width: Option<u8>,
+    /// What to watch, e.g. a variable or a field
+    pub place: Arc<str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchAccess {
+    Read,
+    Write,
+    /// Reads and writes
+    Access,
+}
+
+impl WatchAccess {
+    fn from_keyword(keyword: &str) -> Option<WatchAccess> {
+        match keyword {
+            TOKEN_WATCH_READ => Some(WatchAccess::Read),
+            TOKEN_WATCH_WRITE => Some(WatchAccess::Write),
+            TOKEN_WATCH_ACCESS => Some(WatchAccess::Access),
+            _ => None,
+        }
+    }
+
+    pub fn keyword(self) -> &'static str {
+        match self {
+            WatchAccess::Read => TOKEN_WATCH_READ,
+            WatchAccess::Write => TOKEN_WATCH_WRITE,
+            WatchAccess::Access => TOKEN_WATCH_ACCESS,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ForkMode {
+    Parent,
+    Child,
+}
+
+impl Display for ForkMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ForkMode::Parent => write!(f, "{}", TOKEN_FORK_MODE_PARENT),
+            ForkMode::Child => write!(f, "{}", TOKEN_FORK_MODE_CHILD),
+        }
+    }
+}
+
+/// Checks that are evaluated against the output of a [DebuggerCommand] they issue themselves.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandCheck {
+    /// `#check-thread-count 3`
+    ThreadCount(u32),
+    /// `#check-register rax == 5`
+    Register(Arc<str>, NumericConstraint),
+    /// `#check-memory &x 4 01 00 ?? 00` -- `None` stands for a `??` wildcard byte
+    Memory(/* expression */ Arc<str>, Vec<Option<u8>>),
+    /// `#expect-clean-exit` -- continuing the debuggee has to lead to it exiting normally,
+    /// without hitting another breakpoint or faulting
+    CleanExit,
+    /// `#check-locals [exact]` followed by a block of `name` or `name = value` lines -- the
+    /// local variables of the current frame have to include the given ones, or be exactly
+    /// them
+    Locals(Vec<ExpectedLocal>, /* exact */ bool),
+    /// `#check-type Point` followed by a block of `size N` and `field x offset N size N`
+    /// lines -- the layout of a type as the debugger sees it
+    TypeLayout(TypeLayout),
+    /// `#check-variant opt == Some(42)` -- prints the enum value and checks its active
+    /// variant and, if given, its payload
+    Variant(
+        /* expression */ Arc<str>,
+        /* variant */ Arc<str>,
+        /* payload */ Option<Arc<str>>,
+    ),
+    /// `#check-string s == "hello"` -- prints a `&str`, `String`, `OsString` or the like and
+    /// checks its contents, whatever quoting and annotations the debugger adds
+    Str(/* expression */ Arc<str>, /* contents */ Arc<str>),
+}
+
+/// The expected layout of a type, see [CommandCheck::TypeLayout]. Unset sizes and offsets
+/// aren't checked.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeLayout {
+    pub type_name: Arc<str>,
+    pub size: Option<u64>,
+    pub fields: Vec<FieldLayout>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldLayout {
+    pub name: Arc<str>,
+    pub offset: Option<u64>,
+    pub size: Option<u64>,
+}
+
+/// A variable listed in a `#check-locals` block, with the check its value has to pass, if
+/// any
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExpectedLocal {
+    pub name: Arc<str>,
+    pub value: Option<RegexCheck>,
+}
+
+impl CommandCheck {
+    pub fn command(&self) -> DebuggerCommand {
+        match self {
+            CommandCheck::ThreadCount(_) => DebuggerCommand::ListThreads,
+            CommandCheck::Register(name, _) => DebuggerCommand::ReadRegister(name.clone()),
+            CommandCheck::Memory(expression, bytes) => {
+                DebuggerCommand::ReadMemory(expression.clone(), bytes.len() as u32)
+            }
+            CommandCheck::CleanExit => DebuggerCommand::ContinueToExit,
+            CommandCheck::Locals(..) => DebuggerCommand::ListLocals,
+            CommandCheck::TypeLayout(layout) => {
+                DebuggerCommand::DescribeType(layout.type_name.clone())
+            }
+            CommandCheck::Variant(expression, ..) | CommandCheck::Str(expression, _) => {
+                DebuggerCommand::Print(expression.clone())
+            }
+        }
+    }
+}
+
+impl Statement {
+    pub fn line_number(&self) -> LineNumber {
+        match *self {
+            Statement::IfBlock(_, _, line_number)
+            | Statement::Group(_, _, line_number)
+            | Statement::CheckUnorderedBlock(_, _, line_number)
+            | Statement::Exec(_, _, line_number)
+            | Statement::Check(_, _, line_number)
+            | Statement::IgnoreTest(_, line_number)
+            | Statement::Require(_, line_number)
+            | Statement::MaxMemory(_, line_number)
+            | Statement::DependsOn(_, line_number)
+            | Statement::Dependency(_, line_number)
+            | Statement::Args(_, line_number)
+            | Statement::RustcFlags(_, line_number)
+            | Statement::Targets(_, line_number)
+            | Statement::DebuggeeTarget(_, line_number)
+            | Statement::CBaseline(line_number)
+            | Statement::Sequence(line_number)
+            | Statement::Phase(_, line_number)
+            | Statement::GenerateCrashDump(_, _, line_number)
+            | Statement::Command(_, _, line_number)
+            | Statement::CommandCheck(_, _, line_number)
+            | Statement::SetBreakpoint(_, _, line_number)
+            | Statement::EachHit(_, _, _, line_number)
+            | Statement::StaticCheck(_, line_number)
+            | Statement::ObjectCheck(_, line_number)
+            | Statement::ProgramOutputCheck(_, line_number) => line_number,
+        }
+    }
+
+    fn line_number_mut(&mut self) -> &mut LineNumber {
+        match self {
+            Statement::IfBlock(_, _, line_number)
+            | Statement::Group(_, _, line_number)
+            | Statement::CheckUnorderedBlock(_, _, line_number)
+            | Statement::Exec(_, _, line_number)
+            | Statement::Check(_, _, line_number)
+            | Statement::IgnoreTest(_, line_number)
+            | Statement::Require(_, line_number)
+            | Statement::MaxMemory(_, line_number)
+            | Statement::DependsOn(_, line_number)
+            | Statement::Dependency(_, line_number)
+            | Statement::Args(_, line_number)
+            | Statement::RustcFlags(_, line_number)
+            | Statement::Targets(_, line_number)
+            | Statement::DebuggeeTarget(_, line_number)
+            | Statement::CBaseline(line_number)
+            | Statement::Sequence(line_number)
+            | Statement::Phase(_, line_number)
+            | Statement::GenerateCrashDump(_, _, line_number)
+            | Statement::Command(_, _, line_number)
+            | Statement::CommandCheck(_, _, line_number)
+            | Statement::SetBreakpoint(_, _, line_number)
+            | Statement::EachHit(_, _, _, line_number)
+            | Statement::StaticCheck(_, line_number)
+            | Statement::ObjectCheck(_, line_number)
+            | Statement::ProgramOutputCheck(_, line_number) => line_number,
+        }
+    }
+
+    fn walk_applicable_leaves<'a>(
+        &'a self,
+        context: &EvaluationContext,
+        f: &mut dyn FnMut(&'a Statement) -> bool,
+    ) -> bool {
+        match self {
+            Statement::IfBlock(condition, statements, _) => {
+                if condition.eval(context) {
+                    for statement in statements {
+                        if !statement.walk_applicable_leaves(context, f) {
+                            return false;
+                        }
+                    }
+                }
+                true
+            }
+            Statement::Group(_, statements, _) | Statement::EachHit(_, _, statements, _) => {
+                for statement in statements {
+                    if !statement.walk_applicable_leaves(context, f) {
+                        return false;
+                    }
+                }
+                true
+            }
+            _ => f(self),
+        }
+    }
+
+    fn walk_applicable_leaves_mut<'a>(
+        &'a mut self,
+        context: &EvaluationContext,
+        f: &mut dyn FnMut(&'a mut Statement) -> bool,
+    ) -> bool {
+        match self {
+            Statement::IfBlock(condition, statements, _) => {
+                if condition.eval(context) {
+                    for statement in statements {
+                        if !statement.walk_applicable_leaves_mut(context, f) {
+                            return false;
+                        }
+                    }
+                }
+                true
+            }
+            Statement::Group(_, statements, _) | Statement::EachHit(_, _, statements, _) => {
+                for statement in statements {
+                    if !statement.walk_applicable_leaves_mut(context, f) {
+                        return false;
+                    }
+                }
+                true
+            }
+            _ => f(self),
+        }
+    }
+}
+
+fn with_sequence_points(statements: &[Statement]) -> Vec<Statement> {
+    let mut result = vec![];
+
+    for statement in statements {
+        let statement = match statement {
+            Statement::IfBlock(condition, statements, line_number) => Statement::IfBlock(
+                condition.clone(),
+                with_sequence_points(statements),
+                *line_number,
+            ),
+            Statement::Group(label, statements, line_number) => Statement::Group(
+                label.clone(),
+                with_sequence_points(statements),
+                *line_number,
+            ),
+            Statement::EachHit(name, count, statements, line_number) => Statement::EachHit(
+                name.clone(),
+                *count,
+                with_sequence_points(statements),
+                *line_number,
+            ),
+            Statement::Exec(..) | Statement::Command(..) | Statement::SetBreakpoint(..) => {
+                result.push(Statement::Sequence(statement.line_number()));
+                statement.clone()
+            }
+            statement => statement.clone(),
+        };
+
+        result.push(statement);
+    }
+
+    result
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Line {
+    kind: LineKind,
+    indent: isize,
+    line_number: LineNumber,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum LineKind {
+    If {
+        condition: Condition,
+    },
+    Group {
+        label: Arc<str>,
+    },
+    Check {
+        check: RegexCheck,
+    },
+    CheckUnordered,
+    CheckLocals {
+        exact: bool,
+    },
+    CheckType {
+        type_name: Arc<str>,
+    },
+    Raw {
+        text: String,
+    },
+    IgnoreTest {
+        reason: Option<Arc<str>>,
+    },
+    Require {
+        requirement: Requirement,
+    },
+    MaxMemory {
+        bytes: u64,
+    },
+    DependsOn {
+        test_name: Arc<str>,
+    },
+    Dependency {
+        dependency: CrateDependency,
+    },
+    Args {
+        args: Vec<Arc<str>>,
+    },
+    RustcFlags {
+        flags: Vec<Arc<str>>,
+    },
+    Targets {
+        targets: Vec<Arc<str>>,
+    },
+    DebuggeeTarget {
+        debuggee_target: DebuggeeTarget,
+    },
+    CBaseline,
+    Sequence,
+    Phase {
+        phase_config: PhaseConfig,
+    },
+    GenerateCrashDump {
+        tag: Arc<str>,
+    },
+    Command {
+        command: DebuggerCommand,
+    },
+    CommandCheck {
+        check: CommandCheck,
+    },
+    SetBreakpoint {
+        name: Arc<str>,
+    },
+    EachHit {
+        name: Arc<str>,
+        count: u32,
+    },
+    StaticCheck {
+        tool: StaticTool,
+        args: Vec<Arc<str>>,
+    },
+    ObjectCheck {
+        check: ObjectCheck,
+    },
+    ProgramOutputCheck {
+        check: RegexCheck,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -416,10 +1412,97 @@ pub fn eval(&self, context: &EvaluationContext) -> bool {
     }
 }
 
+/// A crate the test program uses, declared via `#dependencies`, which dbt adds to the
+/// `[dependencies]` of the package's Cargo.toml
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct CrateDependency {
+    pub name: Arc<str>,
+    /// The TOML value, e.g. `"1"` or `{ version = "1", features = ["derive"] }`
+    pub spec: Arc<str>,
+}
+
+impl Display for CrateDependency {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} = {}", self.name, self.spec)
+    }
+}
+
+/// A binary, example or benchmark of the test's package that is debugged instead of the
+/// test program, selected via `#debuggee-target`
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DebuggeeTarget {
+    pub kind: DebuggeeTargetKind,
+    pub name: Arc<str>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DebuggeeTargetKind {
+    Bin,
+    Example,
+    Bench,
+}
+
+impl DebuggeeTargetKind {
+    /// The kind as Cargo calls it, both in `cargo metadata` and in flags like `--example`
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DebuggeeTargetKind::Bin => "bin",
+            DebuggeeTargetKind::Example => "example",
+            DebuggeeTargetKind::Bench => "bench",
+        }
+    }
+}
+
+impl DebuggeeTarget {
+    /// The arguments selecting the target for `cargo rustc`, e.g. `--example foo`
+    pub fn cargo_target_args(&self) -> Vec<String> {
+        vec![format!("--{}", self.kind.as_str()), self.name.to_string()]
+    }
+}
+
+impl Display for DebuggeeTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.kind.as_str(), self.name)
+    }
+}
+
+/// An external prerequisite of a test, declared via `#require`. Tests with unmet
+/// requirements are skipped instead of failing somewhere in the middle of the script.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Requirement {
+    /// `#require python` -- the debugger has Python scripting support
+    Python,
+    /// `#require natvis` -- the debugger supports Natvis visualizers
+    Natvis,
+    /// `#require symbol-server` -- the debugger is configured to use a symbol server
+    SymbolServer,
+}
+
+impl Requirement {
+    pub fn name(self) -> &'static str {
+        match self {
+            Requirement::Python => TOKEN_REQUIREMENT_PYTHON,
+            Requirement::Natvis => TOKEN_REQUIREMENT_NATVIS,
+            Requirement::SymbolServer => TOKEN_REQUIREMENT_SYMBOL_SERVER,
+        }
+    }
+}
+
+impl Display for Requirement {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum PhaseConfig {
     Live,
-    CrashDump { tag: Arc<str> },
+    CrashDump {
+        tag: Arc<str>,
+    },
+    /// Replays a Time Travel Debugging trace of the debuggee, recorded by running it with
+    /// `TTD.exe`. Only supported by CDB.
+    Ttd,
 }
 
 impl PhaseConfig {
@@ -427,15 +1510,38 @@ pub fn kind(&self) -> &'static str {
         match self {
             PhaseConfig::Live => "live",
             PhaseConfig::CrashDump { .. } => "crashdump",
+            PhaseConfig::Ttd => "ttd",
+        }
+    }
+
+    /// Whether the debuggee's code runs during this phase, so that breakpoints can be hit
+    pub fn executes_debuggee(&self) -> bool {
+        match self {
+            PhaseConfig::Live | PhaseConfig::Ttd => true,
+            PhaseConfig::CrashDump { .. } => false,
         }
     }
 
     pub fn to_variable_value(&self) -> Value {
         match self {
-            PhaseConfig::Live => self.kind().into(),
+            PhaseConfig::Live | PhaseConfig::Ttd => self.kind().into(),
             PhaseConfig::CrashDump { tag } => format!("{}.{}", self.kind(), tag).into(),
         }
     }
+
+    /// Parses the [Display] representation of a phase, as used in file names.
+    pub fn from_display_string(s: &str) -> Option<PhaseConfig> {
+        if s == "live" {
+            return Some(PhaseConfig::Live);
+        }
+
+        if s == "ttd" {
+            return Some(PhaseConfig::Ttd);
+        }
+
+        let tag = s.strip_prefix("crashdump[")?.strip_suffix(']')?;
+        Some(PhaseConfig::CrashDump { tag: tag.into() })
+    }
 }
 
 impl Display for PhaseConfig {
@@ -443,30 +1549,388 @@ fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             PhaseConfig::Live => write!(f, "live"),
             PhaseConfig::CrashDump { tag } => write!(f, "crashdump[{}]", tag),
+            PhaseConfig::Ttd => write!(f, "ttd"),
+        }
+    }
+}
+
+/// An error that occurred while parsing a test script. It points to the offending
+/// location and its [Display] implementation renders a source snippet with a caret,
+/// similar to rustc diagnostics.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub file_path: Option<PathBuf>,
+    /// The 1-based line number within the file containing the script
+    pub line_number: LineNumber,
+    /// The 1-based column of the offending token
+    pub column: usize,
+    /// The number of characters to underline, starting at `column`
+    pub width: usize,
+    /// The full text of the offending line
+    pub source_line: String,
+    pub message: String,
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let line_number = self.line_number.0.to_string();
+        let gutter = " ".repeat(line_number.len());
+
+        writeln!(f, "error: {}", self.message)?;
+
+        match &self.file_path {
+            Some(path) => writeln!(
+                f,
+                "{}--> {}:{}:{}",
+                gutter,
+                prettify_path(path),
+                line_number,
+                self.column
+            )?,
+            None => writeln!(f, "{}--> line {}:{}", gutter, line_number, self.column)?,
+        }
+
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", line_number, self.source_line)?;
+        writeln!(
+            f,
+            "{} | {}{}",
+            gutter,
+            " ".repeat(self.column - 1),
+            "^".repeat(self.width.max(1))
+        )
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// A syntax error within a single script line. `span` is a byte range within that line.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct SyntaxError {
+    line_number: LineNumber,
+    span: Range<usize>,
+    message: String,
+}
+
+impl SyntaxError {
+    fn into_parse_error(self, source_line: &str, file_path: Option<&Path>) -> ParseError {
+        let start = self.span.start.min(source_line.len());
+        let end = self.span.end.clamp(start, source_line.len());
+
+        ParseError {
+            file_path: file_path.map(Path::to_path_buf),
+            line_number: self.line_number,
+            column: source_line[..start].chars().count() + 1,
+            width: source_line[start..end].chars().count(),
+            source_line: source_line.to_string(),
+            message: self.message,
         }
     }
 }
 
-fn parse_line(line: &str, line_number: LineNumber) -> anyhow::Result<Line> {
+/// An error that refers to a specific token. The token is identified by its address,
+/// which allows mapping it back to a column in the line it was sliced from.
+#[derive(Debug)]
+struct TokenError {
+    address: usize,
+    len: usize,
+    message: String,
+}
+
+impl Display for TokenError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for TokenError {}
+
+/// Creates an error pointing at `token`, which must be a sub-slice of the line being parsed.
+fn token_error(token: &str, message: String) -> anyhow::Error {
+    anyhow::Error::new(TokenError {
+        address: token.as_ptr() as usize,
+        len: token.len(),
+        message,
+    })
+}
+
+/// Returns the byte range within `line` of the token at `address`, if the token is a
+/// sub-slice of `line`.
+fn span_within(line: &str, address: usize, len: usize) -> Option<Range<usize>> {
+    let start = address.checked_sub(line.as_ptr() as usize)?;
+
+    if start + len <= line.len() {
+        Some(start..start + len)
+    } else {
+        None
+    }
+}
+
+fn parse_line(line: &str, line_number: LineNumber) -> Result<Line, SyntaxError> {
+    parse_line_inner(line, line_number).map_err(|error| to_syntax_error(error, line, line_number))
+}
+
+fn to_syntax_error(error: anyhow::Error, line: &str, line_number: LineNumber) -> SyntaxError {
+    // Point at the offending token if we know it, otherwise at the directive
+    let span = error
+        .downcast_ref::<TokenError>()
+        .and_then(|token_error| span_within(line, token_error.address, token_error.len))
+        .unwrap_or_else(|| {
+            let start = line.len() - line.trim_start().len();
+            let len = tokenize(line).next().map_or(0, str::len);
+            start..start + len
+        });
+
+    SyntaxError {
+        line_number,
+        span,
+        message: error.to_string(),
+    }
+}
+
+/// Whether two lines of a script parse to the same directive or command, regardless of
+/// their indentation. Used by `dbt fmt` to tell which whitespace is insignificant.
+pub fn same_meaning(line: &str, other_line: &str) -> bool {
+    match (
+        parse_line(line, LineNumber::ANY),
+        parse_line(other_line, LineNumber::ANY),
+    ) {
+        (Ok(line), Ok(other_line)) => line.kind == other_line.kind,
+        _ => false,
+    }
+}
+
+/// Checks a `#min-dbt-version` line against the version of this dbt binary.
+fn check_min_dbt_version(line: &str, line_number: LineNumber) -> Result<(), SyntaxError> {
+    parse_min_dbt_version(line).map_err(|error| to_syntax_error(error, line, line_number))
+}
+
+fn parse_min_dbt_version(line: &str) -> anyhow::Result<()> {
+    let (line, indent) = trim_indent(line)?;
+    let mut tokens = tokenize(line);
+    let keyword = expect(&mut tokens, &TOKEN_MIN_DBT_VERSION)?;
+
+    if indent != 0 {
+        return Err(token_error(
+            keyword,
+            format!("{} must not be nested in a block", TOKEN_MIN_DBT_VERSION),
+        ));
+    }
+
+    let version_token = match tokens.next() {
+        Some(token) => token,
+        None => bail!("expected version, found nothing"),
+    };
+
+    let required = Value::new(version_token);
+
+    if required.version.is_none() {
+        return Err(token_error(
+            version_token,
+            format!(
+                "expected a version number like `0.5`, found `{}`",
+                version_token
+            ),
+        ));
+    }
+
+    if required > Value::new(crate::VERSION) {
+        return Err(token_error(
+            version_token,
+            format!(
+                "this test requires dbt {} or newer, but this is dbt {} -- please upgrade dbt",
+                version_token,
+                crate::VERSION
+            ),
+        ));
+    }
+
+    Ok(())
+}
+
+fn parse_line_inner(line: &str, line_number: LineNumber) -> anyhow::Result<Line> {
     let (line, indent) = trim_indent(line)?;
 
     let kind = if line.starts_with(TOKEN_IF) {
         parse_if(line)?
+    } else if line.starts_with(TOKEN_GROUP) {
+        parse_group(line)?
+    } else if line.starts_with(TOKEN_EACH_HIT) {
+        parse_each_hit(line)?
+    } else if line.starts_with(TOKEN_DWARFDUMP_CHECK) {
+        parse_static_check(line, StaticTool::Dwarfdump)?
+    } else if line.starts_with(TOKEN_PDB_CHECK) {
+        parse_static_check(line, StaticTool::Pdbutil)?
     } else if line.starts_with(TOKEN_CHECK_UNORDERED) {
         parse_check_unordered(line)?
+    } else if line.starts_with(TOKEN_CHECK_LOCALS) {
+        parse_check_locals(line)?
+    } else if line.starts_with(TOKEN_CHECK_STRING) {
+        parse_check_string(line)?
+    } else if line.starts_with(TOKEN_CHECK_VARIANT) {
+        parse_check_variant(line)?
+    } else if line.starts_with(TOKEN_CHECK_TYPE) {
+        let mut tokens = tokenize(line);
+        let keyword = expect(&mut tokens, &TOKEN_CHECK_TYPE)?;
+        let type_name = line[keyword.len()..].trim();
+
+        if type_name.is_empty() {
+            bail!("expected a type name, found nothing");
+        }
+
+        LineKind::CheckType {
+            type_name: type_name.into(),
+        }
+    } else if line.starts_with(TOKEN_CHECK_THREAD_COUNT) {
+        LineKind::CommandCheck {
+            check: CommandCheck::ThreadCount(parse_count_directive(
+                line,
+                TOKEN_CHECK_THREAD_COUNT,
+                1,
+            )?),
+        }
+    } else if line.starts_with(TOKEN_EXPECT_CLEAN_EXIT) {
+        parse_no_argument_directive(line, TOKEN_EXPECT_CLEAN_EXIT)?;
+        LineKind::CommandCheck {
+            check: CommandCheck::CleanExit,
+        }
+    } else if line.starts_with(TOKEN_CHECK_REGISTER) {
+        parse_check_register(line)?
+    } else if line.starts_with(TOKEN_CHECK_MEMORY) {
+        parse_check_memory(line)?
+    } else if line.starts_with(TOKEN_CHECK_SECTION) {
+        parse_check_section(line)?
+    } else if line.starts_with(TOKEN_CHECK_PROGRAM_OUTPUT) {
+        parse_check_program_output(line)?
+    } else if line.starts_with(TOKEN_CHECK_SYMBOL) {
+        parse_check_symbol(line)?
+    } else if line.starts_with(TOKEN_CHECK_NOCASE) {
+        parse_check(
+            line,
+            TOKEN_CHECK_NOCASE,
+            CheckOptions {
+                case_insensitive: true,
+                ..Default::default()
+            },
+        )?
+    } else if line.starts_with(TOKEN_CHECK_SAME) {
+        parse_check(
+            line,
+            TOKEN_CHECK_SAME,
+            CheckOptions {
+                same_line: true,
+                ..Default::default()
+            },
+        )?
+    } else if line.starts_with(TOKEN_CHECK_NOT_BETWEEN) {
+        parse_check(
+            line,
+            TOKEN_CHECK_NOT_BETWEEN,
+            CheckOptions {
+                negated: true,
+                ..Default::default()
+            },
+        )?
+    } else if line.starts_with(TOKEN_CHECK_NUM) {
+        parse_check_num(line)?
+    } else if line.starts_with(TOKEN_CHECK_EXACT) {
+        parse_check(
+            line,
+            TOKEN_CHECK_EXACT,
+            CheckOptions {
+                anchored: true,
+                ..Default::default()
+            },
+        )?
     } else if line.starts_with(TOKEN_CHECK) {
-        parse_check(line)?
+        parse_check(line, TOKEN_CHECK, CheckOptions::default())?
     } else if line.starts_with(TOKEN_IGNORE_TEST) {
         parse_ignore(line)?
+    } else if line.starts_with(TOKEN_REQUIRE) {
+        parse_require(line)?
+    } else if line.starts_with(TOKEN_MAX_MEMORY) {
+        parse_max_memory(line)?
+    } else if line.starts_with(TOKEN_DEPENDS_ON) {
+        parse_depends_on(line)?
+    } else if line.starts_with(TOKEN_DEPENDENCIES) {
+        parse_dependencies(line)?
+    } else if line.starts_with(TOKEN_ARGS) {
+        parse_args(line)?
+    } else if line.starts_with(TOKEN_RUSTC_FLAGS) {
+        parse_rustc_flags(line)?
+    } else if line.starts_with(TOKEN_TARGETS) {
+        parse_targets(line)?
+    } else if line.starts_with(TOKEN_DEBUGGEE_TARGET) {
+        parse_debuggee_target(line)?
+    } else if line.starts_with(TOKEN_C_BASELINE) {
+        parse_no_argument_directive(line, TOKEN_C_BASELINE)?;
+        LineKind::CBaseline
+    } else if line.starts_with(TOKEN_SEQUENCE) {
+        parse_sequence(line)?
+    } else if line.starts_with(TOKEN_PRINT) {
+        parse_print(line)?
     } else if line.starts_with(TOKEN_PHASE) {
         parse_phase(line)?
     } else if line.starts_with(TOKEN_GENERATE_CRASHDUMP) {
         parse_generate_crashdump(line)?
+    } else if line.starts_with(TOKEN_THREAD) {
+        LineKind::Command {
+            command: DebuggerCommand::SelectThread(parse_count_directive(line, TOKEN_THREAD, 1)?),
+        }
+    } else if line.starts_with(TOKEN_FOLLOW_FORK) {
+        parse_follow_fork(line)?
+    } else if line.starts_with(TOKEN_INFERIOR) {
+        LineKind::Command {
+            command: DebuggerCommand::SelectInferior(parse_count_directive(
+                line,
+                TOKEN_INFERIOR,
+                1,
+            )?),
+        }
+    } else if line.starts_with(TOKEN_BREAK) {
+        parse_break(line)?
+    } else if line.starts_with(TOKEN_FRAME) {
+        LineKind::Command {
+            command: DebuggerCommand::SelectFrame(parse_count_directive(line, TOKEN_FRAME, 0)?),
+        }
+    } else if line.starts_with(TOKEN_UP) {
+        parse_no_argument_directive(line, TOKEN_UP)?;
+        LineKind::Command {
+            command: DebuggerCommand::FrameUp,
+        }
+    } else if line.starts_with(TOKEN_DOWN) {
+        parse_no_argument_directive(line, TOKEN_DOWN)?;
+        LineKind::Command {
+            command: DebuggerCommand::FrameDown,
+        }
+    } else if line.starts_with(TOKEN_REVERSE_STEP_INTO) {
+        parse_no_argument_directive(line, TOKEN_REVERSE_STEP_INTO)?;
+        LineKind::Command {
+            command: DebuggerCommand::ReverseStepInto,
+        }
+    } else if line.starts_with(TOKEN_REVERSE_STEP) {
+        parse_no_argument_directive(line, TOKEN_REVERSE_STEP)?;
+        LineKind::Command {
+            command: DebuggerCommand::ReverseStep,
+        }
+    } else if line.starts_with(TOKEN_WATCH) {
+        parse_watch(line)?
+    } else if line.starts_with(TOKEN_REVERSE_FINISH) {
+        parse_no_argument_directive(line, TOKEN_REVERSE_FINISH)?;
+        LineKind::Command {
+            command: DebuggerCommand::ReverseFinish,
+        }
+    } else if line.starts_with(TOKEN_REVERSE_CONTINUE) {
+        parse_no_argument_directive(line, TOKEN_REVERSE_CONTINUE)?;
+        LineKind::Command {
+            command: DebuggerCommand::ReverseContinue,
+        }
     } else if line.starts_with('#') {
-        bail!(
-            "Encountered unknown keyword `{}`",
-            tokenize(line).next().unwrap()
-        )
+        let keyword = tokenize(line).next().unwrap();
+        return Err(token_error(
+            keyword,
+            format!("Encountered unknown keyword `{}`", keyword),
+        ));
     } else {
         LineKind::Raw {
             text: line.trim().to_string(),
@@ -480,15 +1944,61 @@ fn parse_line(line: &str, line_number: LineNumber) -> anyhow::Result<Line> {
     })
 }
 
-const TOKEN_IF: &str = "#if";
-const TOKEN_CHECK: &str = "#check";
-const TOKEN_CHECK_UNORDERED: &str = "#check-unordered";
+pub const TOKEN_IF: &str = "#if";
+pub const TOKEN_GROUP: &str = "#group";
+pub const TOKEN_CHECK: &str = "#check";
+pub const TOKEN_CHECK_UNORDERED: &str = "#check-unordered";
+pub const TOKEN_CHECK_NOCASE: &str = "#check-nocase";
+pub const TOKEN_CHECK_EXACT: &str = "#check-exact";
+const TOKEN_CHECK_NUM: &str = "#check-num";
+pub const TOKEN_CHECK_NOT_BETWEEN: &str = "#check-not-between";
+pub const TOKEN_CHECK_SAME: &str = "#check-same";
+const TOKEN_CHECK_THREAD_COUNT: &str = "#check-thread-count";
+const TOKEN_CHECK_REGISTER: &str = "#check-register";
+const TOKEN_CHECK_MEMORY: &str = "#check-memory";
+const TOKEN_CHECK_SECTION: &str = "#check-section";
+const TOKEN_CHECK_SYMBOL: &str = "#check-symbol";
+const TOKEN_CHECK_PROGRAM_OUTPUT: &str = "#check-program-output";
+const TOKEN_EXPECT_CLEAN_EXIT: &str = "#expect-clean-exit";
+pub const TOKEN_CHECK_LOCALS: &str = "#check-locals";
+pub const TOKEN_CHECK_TYPE: &str = "#check-type";
+const TOKEN_CHECK_VARIANT: &str = "#check-variant";
+const TOKEN_CHECK_STRING: &str = "#check-string";
+const TOKEN_EXACT: &str = "exact";
+const TOKEN_THREAD: &str = "#thread";
+const TOKEN_FRAME: &str = "#frame";
+const TOKEN_UP: &str = "#up";
+const TOKEN_DOWN: &str = "#down";
+pub const TOKEN_REVERSE_STEP: &str = "#reverse-step";
+pub const TOKEN_REVERSE_STEP_INTO: &str = "#reverse-step-into";
+const TOKEN_REVERSE_CONTINUE: &str = "#reverse-continue";
+pub const TOKEN_REVERSE_FINISH: &str = "#reverse-finish";
+const TOKEN_WATCH: &str = "#watch";
+const TOKEN_FOLLOW_FORK: &str = "#follow-fork";
+const TOKEN_BREAK: &str = "#break";
+pub const TOKEN_EACH_HIT: &str = "#each-hit";
+const TOKEN_DWARFDUMP_CHECK: &str = "#dwarfdump-check";
+const TOKEN_PDB_CHECK: &str = "#pdb-check";
+const TOKEN_INFERIOR: &str = "#inferior";
 const TOKEN_IGNORE_TEST: &str = "#ignore-test";
+const TOKEN_REQUIRE: &str = "#require";
+const TOKEN_MAX_MEMORY: &str = "#max-memory";
+const TOKEN_DEPENDS_ON: &str = "#depends-on";
+const TOKEN_DEPENDENCIES: &str = "#dependencies";
+const TOKEN_ARGS: &str = "#args";
+const TOKEN_RUSTC_FLAGS: &str = "#rustc-flags";
+const TOKEN_TARGETS: &str = "#targets";
+const TOKEN_DEBUGGEE_TARGET: &str = "#debuggee-target";
+const TOKEN_C_BASELINE: &str = "#c-baseline";
+const TOKEN_PARAMS: &str = "#params";
+const TOKEN_MIN_DBT_VERSION: &str = "#min-dbt-version";
+const TOKEN_SEQUENCE: &str = "#sequence";
 const TOKEN_PHASE: &str = "#phase";
+const TOKEN_PRINT: &str = "#print";
 pub const TOKEN_GENERATE_CRASHDUMP: &str = "#generate-crashdump";
-const TOKEN_SCRIPT_START: &str = "/***";
-const TOKEN_SCRIPT_END: &str = "***/";
-const TOKEN_COMMENT: &str = "//";
+pub const TOKEN_SCRIPT_START: &str = "/***";
+pub const TOKEN_SCRIPT_END: &str = "***/";
+pub const TOKEN_COMMENT: &str = "//";
 const TOKEN_AND: &str = "&&";
 const TOKEN_OR: &str = "||";
 const TOKEN_NOT: &str = "not";
@@ -503,6 +2013,21 @@ fn parse_line(line: &str, line_number: LineNumber) -> anyhow::Result<Line> {
 
 const TOKEN_PHASE_KIND_LIVE: &str = "live";
 const TOKEN_PHASE_KIND_CRASHDUMP: &str = "crashdump";
+const TOKEN_PHASE_KIND_TTD: &str = "ttd";
+
+const TOKEN_FORK_MODE_PARENT: &str = "parent";
+const TOKEN_FORK_MODE_CHILD: &str = "child";
+
+const TOKEN_WATCH_READ: &str = "read";
+const TOKEN_WATCH_WRITE: &str = "write";
+const TOKEN_WATCH_ACCESS: &str = "access";
+
+const TOKEN_COMPRESSED: &str = "compressed";
+const TOKEN_UNCOMPRESSED: &str = "uncompressed";
+
+const TOKEN_REQUIREMENT_PYTHON: &str = "python";
+const TOKEN_REQUIREMENT_NATVIS: &str = "natvis";
+const TOKEN_REQUIREMENT_SYMBOL_SERVER: &str = "symbol-server";
 
 fn parse_if(line: &str) -> anyhow::Result<LineKind> {
     let mut tokens = tokenize(line);
@@ -514,13 +2039,62 @@ fn parse_if(line: &str) -> anyhow::Result<LineKind> {
     })
 }
 
-fn parse_check(line: &str) -> anyhow::Result<LineKind> {
+fn parse_group(line: &str) -> anyhow::Result<LineKind> {
     let mut tokens = tokenize(line);
+    let keyword = expect(&mut tokens, &TOKEN_GROUP)?;
+
+    match parse_string_argument(&line[keyword.len()..])? {
+        Some(label) => Ok(LineKind::Group { label }),
+        None => bail!("expected group label, found nothing"),
+    }
+}
+
+fn parse_check(line: &str, token: &str, options: CheckOptions) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+
+    expect(&mut tokens, &token)?;
+
+    Ok(LineKind::Check {
+        check: RegexCheck::with_options(&concat(tokens), options)?,
+    })
+}
+
+/// Parses `#check-num "<pattern with {}>" <op> <value>`.
+fn parse_check_num(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    let keyword = expect(&mut tokens, &TOKEN_CHECK_NUM)?;
+    let rest = line[keyword.len()..].trim();
+
+    let quoted = match rest.strip_prefix('"') {
+        Some(quoted) => quoted,
+        None => {
+            return Err(token_error(
+                tokenize(rest).next().unwrap_or(keyword),
+                format!("{} expects a quoted pattern", TOKEN_CHECK_NUM),
+            ))
+        }
+    };
+
+    let (pattern, constraint) = match quoted.rfind('"') {
+        Some(end) => (&quoted[..end], &quoted[end + 1..]),
+        None => return Err(token_error(rest, "unterminated string".to_string())),
+    };
+
+    let mut constraint_tokens = tokenize(constraint);
+    let constraint = parse_numeric_constraint(&mut constraint_tokens)?;
+
+    if let Some(token) = constraint_tokens.next() {
+        return Err(token_error(token, format!("unexpected token `{}`", token)));
+    }
 
-    expect(&mut tokens, &TOKEN_CHECK)?;
-
     Ok(LineKind::Check {
-        check: RegexCheck::new(&concat(tokens))?,
+        check: RegexCheck::with_options(
+            pattern,
+            CheckOptions {
+                numeric: Some(constraint),
+                ..Default::default()
+            },
+        )?,
     })
 }
 
@@ -532,783 +2106,3394 @@ fn parse_check_unordered(line: &str) -> anyhow::Result<LineKind> {
     Ok(LineKind::CheckUnordered)
 }
 
-fn parse_ignore(line: &str) -> anyhow::Result<LineKind> {
+fn parse_check_locals(line: &str) -> anyhow::Result<LineKind> {
     let mut tokens = tokenize(line);
-    expect(&mut tokens, &TOKEN_IGNORE_TEST)?;
-    Ok(LineKind::IgnoreTest)
+    expect(&mut tokens, &TOKEN_CHECK_LOCALS)?;
+
+    let exact = match tokens.next() {
+        None => false,
+        Some(TOKEN_EXACT) => true,
+        Some(token) => {
+            return Err(token_error(
+                token,
+                format!("expected `{}` or nothing, found `{}`", TOKEN_EXACT, token),
+            ))
+        }
+    };
+
+    if let Some(token) = tokens.next() {
+        return Err(token_error(token, format!("unexpected `{}`", token)));
+    }
+
+    Ok(LineKind::CheckLocals { exact })
 }
 
-fn parse_phase(line: &str) -> anyhow::Result<LineKind> {
+fn parse_ignore(line: &str) -> anyhow::Result<LineKind> {
     let mut tokens = tokenize(line);
-    expect(&mut tokens, &TOKEN_PHASE)?;
+    let keyword = expect(&mut tokens, &TOKEN_IGNORE_TEST)?;
+    let rest = &line[keyword.len()..];
 
-    let phase_kind = expect(
-        &mut tokens,
-        &[TOKEN_PHASE_KIND_LIVE, TOKEN_PHASE_KIND_CRASHDUMP],
-    )?;
+    // Comments are kept in the reason, which may contain URLs, but a comment in place of
+    // the reason is no reason
+    if rest.trim_start().starts_with(TOKEN_COMMENT) {
+        return Ok(LineKind::IgnoreTest { reason: None });
+    }
 
-    Ok(match phase_kind {
-        TOKEN_PHASE_KIND_LIVE => LineKind::Phase {
-            phase_config: PhaseConfig::Live,
-        },
-        TOKEN_PHASE_KIND_CRASHDUMP => LineKind::Phase {
-            phase_config: PhaseConfig::CrashDump {
-                tag: tokens.next().unwrap_or("default").into(),
-            },
-        },
-        _ => unreachable!(),
+    Ok(LineKind::IgnoreTest {
+        reason: parse_string_argument(rest)?,
     })
 }
 
-fn parse_generate_crashdump(line: &str) -> anyhow::Result<LineKind> {
-    let mut tokens = tokenize(line);
-    expect(&mut tokens, &TOKEN_GENERATE_CRASHDUMP)?;
-    let tag = tokens.next().unwrap_or("default").into();
-    Ok(LineKind::GenerateCrashDump { tag })
+/// Parses the rest of a line as a single string argument, which can optionally be
+/// enclosed in double quotes. Returns `None` if there is no argument.
+fn parse_string_argument(rest: &str) -> anyhow::Result<Option<Arc<str>>> {
+    let rest = rest.trim();
+
+    if rest.is_empty() {
+        Ok(None)
+    } else if let Some(quoted) = rest.strip_prefix('"') {
+        match quoted.strip_suffix('"') {
+            Some(string) => Ok(Some(string.trim().into())),
+            None => Err(token_error(rest, "unterminated string".to_string())),
+        }
+    } else {
+        Ok(Some(rest.into()))
+    }
 }
 
-fn tokenize(line: &str) -> impl Iterator<Item = &str> {
-    line.split(char::is_whitespace).filter(|x| !x.is_empty())
+fn parse_sequence(line: &str) -> anyhow::Result<LineKind> {
+    parse_no_argument_directive(line, TOKEN_SEQUENCE)?;
+    Ok(LineKind::Sequence)
 }
 
-fn concat<'a>(it: impl Iterator<Item = &'a str>) -> String {
-    let mut string = String::new();
-    for piece in it {
-        string.push_str(piece);
-        string.push(' ');
+fn parse_no_argument_directive(line: &str, keyword: &str) -> anyhow::Result<()> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &keyword)?;
+
+    if let Some(token) = tokens.next() {
+        return Err(token_error(
+            token,
+            format!("{} does not take arguments", keyword),
+        ));
     }
 
-    string.pop();
-    string
+    Ok(())
 }
 
-trait StrPattern {
-    fn check(&self, s: &str) -> bool;
-    fn display(&self) -> String;
-}
+fn parse_require(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &TOKEN_REQUIRE)?;
 
-impl StrPattern for &str {
-    fn check(&self, s: &str) -> bool {
-        *self == s
-    }
+    let requirement = match expect(
+        &mut tokens,
+        &[
+            TOKEN_REQUIREMENT_PYTHON,
+            TOKEN_REQUIREMENT_NATVIS,
+            TOKEN_REQUIREMENT_SYMBOL_SERVER,
+        ],
+    )? {
+        TOKEN_REQUIREMENT_PYTHON => Requirement::Python,
+        TOKEN_REQUIREMENT_NATVIS => Requirement::Natvis,
+        TOKEN_REQUIREMENT_SYMBOL_SERVER => Requirement::SymbolServer,
+        _ => unreachable!(),
+    };
 
-    fn display(&self) -> String {
-        self.to_string()
-    }
+    Ok(LineKind::Require { requirement })
 }
 
-impl<const LEN: usize> StrPattern for [&str; LEN] {
-    fn check(&self, s: &str) -> bool {
-        self.iter().any(|x| *x == s)
-    }
+/// `#max-memory 512M`, with an optional `K`, `M` or `G` suffix (powers of 1024)
+fn parse_max_memory(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &TOKEN_MAX_MEMORY)?;
 
-    fn display(&self) -> String {
-        self.join(" or ")
-    }
-}
+    let token = match tokens.next() {
+        Some(token) => token,
+        None => bail!("expected memory size like `512M`, found nothing"),
+    };
 
-fn expect<'a>(
-    tokens: &mut impl Iterator<Item = &'a str>,
-    expected: &dyn StrPattern,
-) -> anyhow::Result<&'a str> {
-    let token = tokens.next();
+    let (number, multiplier) = match token.char_indices().last() {
+        Some((index, 'K')) => (&token[..index], 1 << 10),
+        Some((index, 'M')) => (&token[..index], 1 << 20),
+        Some((index, 'G')) => (&token[..index], 1 << 30),
+        _ => (token, 1),
+    };
 
-    match token {
-        None => bail!("expected `{}`, found nothing", expected.display()),
-        Some(token) => {
-            if expected.check(token) {
-                Ok(token)
-            } else {
-                bail!("expected `{}`, found `{}`", expected.display(), token);
-            }
+    let bytes = match number.parse::<u64>() {
+        Ok(number) if number > 0 => number.checked_mul(multiplier),
+        _ => None,
+    };
+
+    let bytes = match bytes {
+        Some(bytes) => bytes,
+        None => {
+            return Err(token_error(
+                token,
+                format!("expected memory size like `512M`, found `{}`", token),
+            ))
         }
-    }
-}
+    };
 
-fn to_comparsion_op(s: &str) -> Option<Comparison> {
-    match s {
-        TOKEN_EQ => Some(Comparison::Eq),
-        TOKEN_CONTAINS => Some(Comparison::Contains),
-        TOKEN_MATCHES => Some(Comparison::Matches),
-        TOKEN_NEQ => Some(Comparison::NotEq),
-        TOKEN_GT => Some(Comparison::GreaterThan),
-        TOKEN_GT_EQ => Some(Comparison::GreaterThanOrEq),
-        TOKEN_LT => Some(Comparison::LessThan),
-        TOKEN_LT_EQ => Some(Comparison::LessThanOrEq),
-        _ => None,
+    if let Some(token) = tokens.next() {
+        return Err(token_error(token, format!("unexpected token `{}`", token)));
     }
+
+    Ok(LineKind::MaxMemory { bytes })
 }
 
-fn trim_indent(line: &str) -> anyhow::Result<(&str, isize)> {
-    let first_non_whitespace = line.bytes().position(|x| x != b' ').unwrap();
-    if line.as_bytes()[first_non_whitespace].is_ascii_whitespace() {
-        bail!("only spaces allow for indentation")
+fn parse_depends_on(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    let keyword = expect(&mut tokens, &TOKEN_DEPENDS_ON)?;
+
+    match parse_string_argument(&line[keyword.len()..])? {
+        Some(test_name) => Ok(LineKind::DependsOn { test_name }),
+        None => bail!("expected the name of a test, found nothing"),
     }
-    Ok((
-        &line[first_non_whitespace..],
-        first_non_whitespace.try_into().unwrap(),
-    ))
 }
 
-fn parse_condition<'a>(
-    tokens: &mut Peekable<impl Iterator<Item = &'a str>>,
-) -> anyhow::Result<Condition> {
-    let term = parse_condition_term(tokens)?;
+fn parse_dependencies(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    let keyword = expect(&mut tokens, &TOKEN_DEPENDENCIES)?;
+    let rest = line[keyword.len()..].trim();
+
+    let (name, spec) = match rest.split_once('=') {
+        Some((name, spec)) => (name.trim(), spec.trim()),
+        None => bail!(
+            "expected a dependency like `serde = \"1\"`, found `{}`",
+            rest
+        ),
+    };
 
-    match tokens.next() {
-        Some(op) => {
-            let rhs = parse_condition(tokens)?;
+    let valid_name = !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
 
-            if op == TOKEN_OR {
-                Ok(Condition::Or(Box::new(term), Box::new(rhs)))
-            } else if op == TOKEN_AND {
-                Ok(Condition::And(Box::new(term), Box::new(rhs)))
-            } else {
-                bail!("unknown op")
-            }
-        }
-        None => Ok(term),
+    if !valid_name {
+        return Err(token_error(
+            name,
+            format!("expected the name of a crate, found `{}`", name),
+        ));
     }
-}
 
-fn parse_condition_term<'a>(
-    tokens: &mut Peekable<impl Iterator<Item = &'a str>>,
-) -> anyhow::Result<Condition> {
-    match tokens.next() {
-        Some(lhs) => {
-            if lhs == TOKEN_NOT {
-                let inner = parse_condition_term(tokens)?;
-                return Ok(Condition::Not(Box::new(inner)));
-            }
+    if let Err(e) = toml::from_str::<toml::Value>(&format!("{} = {}", name, spec)) {
+        return Err(token_error(
+            spec,
+            format!(
+                "expected a dependency specification as in Cargo.toml: {}",
+                e
+            ),
+        ));
+    }
 
-            if !lhs.starts_with('@') {
-                bail!("expected variable name -- did you mean `@{}`?", lhs);
-            }
+    Ok(LineKind::Dependency {
+        dependency: CrateDependency {
+            name: name.into(),
+            spec: spec.into(),
+        },
+    })
+}
 
-            if let Some(&peek) = tokens.peek() {
-                if let Some(comparison_op) = to_comparsion_op(peek) {
-                    // Eat operator token
-                    let _ = tokens.next();
+fn parse_args(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &TOKEN_ARGS)?;
 
-                    if let Some(rhs) = tokens.next() {
-                        if to_comparsion_op(rhs).is_some() {
-                            bail!("rhs is operator")
-                        }
+    let args: Vec<Arc<str>> = tokens.map(Arc::from).collect();
 
-                        let rhs = Value::new(rhs);
+    if args.is_empty() {
+        bail!("expected command line arguments, found nothing");
+    }
 
-                        if comparison_op == Comparison::Matches {
-                            // Validate that the RHS is a valid regular expression
-                            get_regex(&rhs.string)?;
-                        }
+    Ok(LineKind::Args { args })
+}
 
-                        return Ok(Condition::Comparison(lhs.into(), comparison_op, rhs));
-                    }
-                }
-            }
+fn parse_rustc_flags(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &TOKEN_RUSTC_FLAGS)?;
 
-            Ok(Condition::DefinitionExists(lhs.into()))
-        }
-        None => {
-            bail!("");
+    let flags: Vec<&str> = tokens.collect();
+
+    // Flags may take their value as a separate argument, like `--cfg foo`
+    match flags.first() {
+        Some(flag) if !flag.starts_with('-') => {
+            return Err(token_error(
+                flag,
+                format!(
+                    "expected compiler flags like `-Zdebug-info-for-profiling`, found `{}`",
+                    flag
+                ),
+            ))
         }
+        Some(_) => {}
+        None => bail!("expected compiler flags, found nothing"),
     }
-}
 
-fn parse_statement_list(
-    lines: &mut Peekable<impl Iterator<Item = Line>>,
-    parent_indent: isize,
-    token: &str,
-) -> anyhow::Result<Vec<Statement>> {
-    parse_nested_block(lines, parent_indent, token, |line, lines| match line {
-        Line {
-            kind: LineKind::Raw { text },
-            line_number,
-            ..
-        } => Ok(Statement::Exec(text, None, line_number)),
-        Line {
-            kind: LineKind::IgnoreTest,
-            line_number,
-            ..
-        } => Ok(Statement::IgnoreTest(line_number)),
-        Line {
-            kind: LineKind::Check { check, .. },
-            line_number,
-            ..
-        } => Ok(Statement::Check(check, None, line_number)),
-        Line {
-            kind: LineKind::CheckUnordered,
-            line_number,
-            indent,
-        } => parse_check_unordered_body(lines, indent, line_number),
-        Line {
-            kind: LineKind::If { condition },
-            indent,
-            line_number,
-        } => {
-            let nested_body = parse_statement_list(lines, indent, TOKEN_IF)?;
-            Ok(Statement::IfBlock(condition, nested_body, line_number))
-        }
-        Line {
-            kind: LineKind::Phase { phase_config },
-            line_number,
-            ..
-        } => Ok(Statement::Phase(phase_config, line_number)),
-        Line {
-            kind: LineKind::GenerateCrashDump { tag },
-            line_number,
-            ..
-        } => Ok(Statement::GenerateCrashDump(tag, None, line_number)),
+    Ok(LineKind::RustcFlags {
+        flags: flags.into_iter().map(Arc::from).collect(),
     })
 }
 
-fn parse_check_unordered_body(
-    lines: &mut Peekable<impl Iterator<Item = Line>>,
-    parent_indent: isize,
-    line_number: LineNumber,
-) -> anyhow::Result<Statement> {
-    let checks =
-        parse_nested_block(
-            lines,
-            parent_indent,
-            TOKEN_CHECK_UNORDERED,
-            |line, _| match line {
-                Line {
-                    kind: LineKind::Raw { text },
-                    ..
-                } => Ok(text),
-                _ => bail!("{} cannot have nested statements", TOKEN_CHECK_UNORDERED),
-            },
-        )?;
+fn parse_targets(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &TOKEN_TARGETS)?;
+
+    let targets: Vec<&str> = tokens.collect();
+
+    if targets.is_empty() {
+        bail!("expected target triples like `x86_64-pc-windows-msvc`, found nothing");
+    }
 
-    Ok(Statement::CheckUnorderedBlock(checks, None, line_number))
+    Ok(LineKind::Targets {
+        targets: targets.into_iter().map(Arc::from).collect(),
+    })
 }
 
-fn parse_nested_block<T, I: Iterator<Item = Line>>(
-    lines: &mut Peekable<I>,
-    parent_indent: isize,
-    token: &str,
-    process_line: fn(Line, &mut Peekable<I>) -> anyhow::Result<T>,
-) -> anyhow::Result<Vec<T>> {
-    let mut checks = vec![];
-    let first_indent = lines.peek().map_or(-1, |line| line.indent);
+fn parse_debuggee_target(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &TOKEN_DEBUGGEE_TARGET)?;
 
-    if first_indent <= parent_indent {
-        bail!("Empty {} block", token)
-    }
+    let token = match tokens.next() {
+        Some(token) => token,
+        None => bail!("expected a target like `example:foo`, found nothing"),
+    };
 
-    loop {
-        if lines.peek().map_or(true, |line| line.indent < first_indent) {
-            return Ok(checks);
+    let (kind, name) = match token.split_once(':') {
+        Some(("bin", name)) => (DebuggeeTargetKind::Bin, name),
+        Some(("example", name)) => (DebuggeeTargetKind::Example, name),
+        Some(("bench", name)) => (DebuggeeTargetKind::Bench, name),
+        _ => {
+            return Err(token_error(
+                token,
+                format!(
+                    "expected `bin:<name>`, `example:<name>` or `bench:<name>`, found `{}`",
+                    token
+                ),
+            ))
         }
+    };
 
-        let line = lines.next().unwrap();
-        checks.push(process_line(line, lines)?);
+    if name.is_empty() {
+        return Err(token_error(
+            token,
+            format!("expected the name of the target after `{}`", token),
+        ));
     }
+
+    if let Some(token) = tokens.next() {
+        return Err(token_error(token, format!("unexpected token `{}`", token)));
+    }
+
+    Ok(LineKind::DebuggeeTarget {
+        debuggee_target: DebuggeeTarget {
+            kind,
+            name: name.into(),
+        },
+    })
 }
 
-pub fn parse_script(
-    script: &str,
-    file_path_for_diagnostics: Option<&Path>,
-) -> anyhow::Result<Script> {
+fn parse_phase(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &TOKEN_PHASE)?;
+
+    let phase_kind = expect(
+        &mut tokens,
+        &[
+            TOKEN_PHASE_KIND_LIVE,
+            TOKEN_PHASE_KIND_CRASHDUMP,
+            TOKEN_PHASE_KIND_TTD,
+        ],
+    )?;
+
+    Ok(match phase_kind {
+        TOKEN_PHASE_KIND_LIVE => LineKind::Phase {
+            phase_config: PhaseConfig::Live,
+        },
+        TOKEN_PHASE_KIND_CRASHDUMP => LineKind::Phase {
+            phase_config: PhaseConfig::CrashDump {
+                tag: tokens.next().unwrap_or("default").into(),
+            },
+        },
+        TOKEN_PHASE_KIND_TTD => LineKind::Phase {
+            phase_config: PhaseConfig::Ttd,
+        },
+        _ => unreachable!(),
+    })
+}
+
+fn parse_break(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &TOKEN_BREAK)?;
+
+    let name = match tokens.next() {
+        Some(name) => name,
+        None => bail!("expected breakpoint name, found nothing"),
+    };
+
+    if let Some(token) = tokens.next() {
+        return Err(token_error(token, format!("unexpected token `{}`", token)));
+    }
+
+    Ok(LineKind::SetBreakpoint { name: name.into() })
+}
+
+fn parse_each_hit(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &TOKEN_EACH_HIT)?;
+
+    let name = match tokens.next() {
+        Some(name) => name,
+        None => bail!("expected breakpoint name, found nothing"),
+    };
+
+    let count = match tokens.next() {
+        Some(token) => match token.parse() {
+            Ok(count) if count > 0 => count,
+            _ => {
+                return Err(token_error(
+                    token,
+                    format!("expected positive number, found `{}`", token),
+                ))
+            }
+        },
+        None => bail!("expected positive number, found nothing"),
+    };
+
+    if let Some(token) = tokens.next() {
+        return Err(token_error(token, format!("unexpected token `{}`", token)));
+    }
+
+    Ok(LineKind::EachHit {
+        name: name.into(),
+        count,
+    })
+}
+
+fn parse_follow_fork(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &TOKEN_FOLLOW_FORK)?;
+
+    let mode = match expect(
+        &mut tokens,
+        &[TOKEN_FORK_MODE_PARENT, TOKEN_FORK_MODE_CHILD],
+    )? {
+        TOKEN_FORK_MODE_PARENT => ForkMode::Parent,
+        TOKEN_FORK_MODE_CHILD => ForkMode::Child,
+        _ => unreachable!(),
+    };
+
+    if let Some(token) = tokens.next() {
+        return Err(token_error(token, format!("unexpected token `{}`", token)));
+    }
+
+    Ok(LineKind::Command {
+        command: DebuggerCommand::FollowFork(mode),
+    })
+}
+
+/// Parses `#print <expression>`, where the expression is the rest of the line
+fn parse_watch(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &TOKEN_WATCH)?;
+
+    let mut place = line.trim_start()[TOKEN_WATCH.len()..].trim();
+    let mut access = WatchAccess::Write;
+    let mut width = None;
+
+    // The access and the width are optional, but the place always comes last
+    if let Some((keyword, rest)) = place.split_once(char::is_whitespace) {
+        if let Some(keyword_access) = WatchAccess::from_keyword(keyword) {
+            access = keyword_access;
+            place = rest.trim_start();
+        }
+    }
+
+    if let Some((number, rest)) = place.split_once(char::is_whitespace) {
+        if number.bytes().all(|byte| byte.is_ascii_digit()) {
+            width = match number {
+                "1" | "2" | "4" | "8" => Some(number.parse().unwrap()),
+                _ => {
+                    return Err(token_error(
+                        number,
+                        format!("expected a width of 1, 2, 4 or 8 bytes, found `{}`", number),
+                    ))
+                }
+            };
+            place = rest.trim_start();
+        }
+    }
+
+    if place.is_empty() {
+        bail!("expected a place to watch, found nothing");
+    }
+
+    Ok(LineKind::Command {
+        command: DebuggerCommand::Watch(Watchpoint {
+            access,
+            width,
+            place: place.into(),
+        }),
+    })
+}
+
+fn parse_print(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &TOKEN_PRINT)?;
+
+    let expression = line.trim_start()[TOKEN_PRINT.len()..].trim();
+
+    if expression.is_empty() {
+        bail!("expected an expression to print, found nothing");
+    }
+
+    Ok(LineKind::Command {
+        command: DebuggerCommand::Print(expression.into()),
+    })
+}
+
+/// Parses `#check-string <expression> == "<contents>"`, where the contents can contain the
+/// escapes `\"`, `\\`, `\n` and `\t`
+fn parse_check_string(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &TOKEN_CHECK_STRING)?;
+
     lazy_static! {
-        static ref START_FINDER: memchr::memmem::Finder<'static> =
-            memchr::memmem::Finder::new(TOKEN_SCRIPT_START);
-        static ref END_FINDER: memchr::memmem::Finder<'static> =
-            memchr::memmem::Finder::new(TOKEN_SCRIPT_END);
-        static ref COMMENT_FINDER: memchr::memmem::Finder<'static> =
-            memchr::memmem::Finder::new(TOKEN_COMMENT);
+        static ref EQUALS_QUOTE: Regex = Regex::new(r#"==\s*""#).unwrap();
     }
 
-    let script_start = if let Some(script_start) = START_FINDER.find(script.as_bytes()) {
-        script_start
-    } else {
-        bail!("Could not find start of debugger script")
+    let rest = line.trim_start()[TOKEN_CHECK_STRING.len()..].trim();
+
+    let (expression, quoted) = match EQUALS_QUOTE.find(rest) {
+        Some(m) if !rest[..m.start()].trim().is_empty() => {
+            (rest[..m.start()].trim(), &rest[m.end()..])
+        }
+        _ => bail!("expected `<expression> == \"<string>\"`, found `{}`", rest),
+    };
+
+    let mut contents = String::new();
+    let mut chars = quoted.char_indices();
+
+    loop {
+        match chars.next() {
+            Some((_, '\\')) => match chars.next() {
+                Some((_, 'n')) => contents.push('\n'),
+                Some((_, 't')) => contents.push('\t'),
+                Some((_, c @ ('"' | '\\'))) => contents.push(c),
+                Some((index, c)) => {
+                    return Err(token_error(
+                        &quoted[index - 1..index + c.len_utf8()],
+                        "unknown escape".to_string(),
+                    ))
+                }
+                None => return Err(token_error(quoted, "unterminated string".to_string())),
+            },
+            Some((index, '"')) => {
+                let trailing = quoted[index + 1..].trim();
+
+                if !trailing.is_empty() {
+                    return Err(token_error(trailing, format!("unexpected `{}`", trailing)));
+                }
+
+                break;
+            }
+            Some((_, c)) => contents.push(c),
+            None => return Err(token_error(quoted, "unterminated string".to_string())),
+        }
+    }
+
+    Ok(LineKind::CommandCheck {
+        check: CommandCheck::Str(expression.into(), contents.into()),
+    })
+}
+
+/// Parses `#check-variant <expression> == <variant>[(<payload>)]`, where the variant can be
+/// given as a path like `Option::Some`
+fn parse_check_variant(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &TOKEN_CHECK_VARIANT)?;
+
+    let rest = line.trim_start()[TOKEN_CHECK_VARIANT.len()..].trim();
+
+    let (expression, expected) = match rest.rsplit_once("==") {
+        Some((expression, expected)) if !expression.trim().is_empty() => {
+            (expression.trim(), expected.trim())
+        }
+        _ => bail!("expected `<expression> == <variant>`, found `{}`", rest),
+    };
+
+    let (variant, payload) = match expected.split_once('(') {
+        Some((variant, payload)) => match payload.strip_suffix(')') {
+            Some(payload) => (variant.trim(), Some(payload.trim().into())),
+            None => return Err(token_error(expected, "unbalanced parentheses".to_string())),
+        },
+        None => (expected, None),
+    };
+
+    if variant.is_empty()
+        || !variant
+            .chars()
+            .all(|c| c.is_alphanumeric() || c == '_' || c == ':')
+    {
+        return Err(token_error(
+            expected,
+            format!("expected an enum variant, found `{}`", expected),
+        ));
+    }
+
+    Ok(LineKind::CommandCheck {
+        check: CommandCheck::Variant(expression.into(), variant.into(), payload),
+    })
+}
+
+/// Parses the line opening a static check block, e.g. `#dwarfdump-check --name=Foo`
+fn parse_static_check(line: &str, tool: StaticTool) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &tool.keyword())?;
+
+    Ok(LineKind::StaticCheck {
+        tool,
+        args: tokens.map(|token| token.into()).collect(),
+    })
+}
+
+fn parse_generate_crashdump(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &TOKEN_GENERATE_CRASHDUMP)?;
+    let tag = tokens.next().unwrap_or("default").into();
+    Ok(LineKind::GenerateCrashDump { tag })
+}
+
+/// Parses `#check-register <name> <op> <value>`.
+fn parse_check_register(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &TOKEN_CHECK_REGISTER)?;
+
+    let name = match tokens.next() {
+        Some(name) => name,
+        None => bail!("expected register name, found nothing"),
+    };
+
+    let constraint = parse_numeric_constraint(&mut tokens)?;
+
+    if let Some(token) = tokens.next() {
+        return Err(token_error(token, format!("unexpected token `{}`", token)));
+    }
+
+    Ok(LineKind::CommandCheck {
+        check: CommandCheck::Register(name.to_ascii_lowercase().into(), constraint),
+    })
+}
+
+/// Parses `#check-memory <expression> <length> <bytes>`, e.g. `#check-memory &x 2 01 ??`.
+fn parse_check_memory(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    let keyword = expect(&mut tokens, &TOKEN_CHECK_MEMORY)?;
+
+    let expression = match tokens.next() {
+        Some(expression) => expression,
+        None => bail!("expected expression, found nothing"),
+    };
+
+    let len_token = match tokens.next() {
+        Some(token) => token,
+        None => bail!("expected length, found nothing"),
+    };
+
+    let len: usize = match len_token.parse() {
+        Ok(len) if len > 0 => len,
+        _ => {
+            return Err(token_error(
+                len_token,
+                format!("expected positive number, found `{}`", len_token),
+            ))
+        }
+    };
+
+    let mut bytes = vec![];
+
+    for token in tokens {
+        if token == "??" {
+            bytes.push(None);
+        } else if token.len() == 2 {
+            match u8::from_str_radix(token, 16) {
+                Ok(byte) => bytes.push(Some(byte)),
+                Err(_) => {
+                    return Err(token_error(
+                        token,
+                        format!("expected hex byte, found `{}`", token),
+                    ))
+                }
+            }
+        } else {
+            return Err(token_error(
+                token,
+                format!("expected hex byte, found `{}`", token),
+            ));
+        }
+    }
+
+    if bytes.len() != len {
+        return Err(token_error(
+            keyword,
+            format!(
+                "{} expects {} bytes, found {}",
+                TOKEN_CHECK_MEMORY,
+                len,
+                bytes.len()
+            ),
+        ));
+    }
+
+    Ok(LineKind::CommandCheck {
+        check: CommandCheck::Memory(expression.into(), bytes),
+    })
+}
+
+/// Parses `#check-section <name> [compressed|uncompressed]`
+fn parse_check_section(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &TOKEN_CHECK_SECTION)?;
+
+    let name = match tokens.next() {
+        Some(name) => name,
+        None => bail!("expected section name, found nothing"),
+    };
+
+    let compression = match tokens.next() {
+        Some(TOKEN_COMPRESSED) => Some(SectionCompression::Compressed),
+        Some(TOKEN_UNCOMPRESSED) => Some(SectionCompression::Uncompressed),
+        Some(token) => bail!(
+            "expected `{}` or `{}`, found `{}`",
+            TOKEN_COMPRESSED,
+            TOKEN_UNCOMPRESSED,
+            token
+        ),
+        None => None,
+    };
+
+    if let Some(token) = tokens.next() {
+        bail!("unexpected token `{}`", token);
+    }
+
+    Ok(LineKind::ObjectCheck {
+        check: ObjectCheck::Section(name.into(), compression),
+    })
+}
+
+/// Parses `#check-symbol <spec>`, where the spec is the rest of the line
+fn parse_check_symbol(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &TOKEN_CHECK_SYMBOL)?;
+
+    let spec = concat(tokens);
+
+    if spec.is_empty() {
+        bail!("expected symbol specification, found nothing");
+    }
+
+    let check = RegexCheck::with_options(
+        &spec,
+        CheckOptions {
+            anchored: true,
+            ..Default::default()
+        },
+    )?;
+
+    Ok(LineKind::ObjectCheck {
+        check: ObjectCheck::Symbol(check),
+    })
+}
+
+/// Parses `#check-program-output <spec>`, where the spec is the rest of the line
+fn parse_check_program_output(line: &str) -> anyhow::Result<LineKind> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &TOKEN_CHECK_PROGRAM_OUTPUT)?;
+
+    let spec = concat(tokens);
+
+    if spec.is_empty() {
+        bail!("expected output specification, found nothing");
+    }
+
+    Ok(LineKind::ProgramOutputCheck {
+        check: RegexCheck::with_options(&spec, CheckOptions::default())?,
+    })
+}
+
+/// Parses `<op> <value>`, as in `#check-num "len = {}" > 0`.
+fn parse_numeric_constraint<'a>(
+    tokens: &mut impl Iterator<Item = &'a str>,
+) -> anyhow::Result<NumericConstraint> {
+    let op = match tokens.next() {
+        Some(token) => NumericComparison::from_symbol(token).ok_or_else(|| {
+            token_error(
+                token,
+                format!("expected comparison operator, found `{}`", token),
+            )
+        })?,
+        None => bail!("expected comparison operator, found nothing"),
+    };
+
+    let value = match tokens.next() {
+        Some(token) => parse_number(token)
+            .ok_or_else(|| token_error(token, format!("expected number, found `{}`", token)))?,
+        None => bail!("expected number, found nothing"),
+    };
+
+    Ok(NumericConstraint { op, value })
+}
+
+/// Parses directives of the form `#keyword N` where `N` is an integer `>= min`.
+fn parse_count_directive(line: &str, keyword: &str, min: u32) -> anyhow::Result<u32> {
+    let mut tokens = tokenize(line);
+    expect(&mut tokens, &keyword)?;
+
+    let expected = if min > 0 { "positive number" } else { "number" };
+
+    let count = match tokens.next() {
+        Some(token) => match token.parse() {
+            Ok(count) if count >= min => count,
+            _ => {
+                return Err(token_error(
+                    token,
+                    format!("expected {}, found `{}`", expected, token),
+                ))
+            }
+        },
+        None => bail!("expected {}, found nothing", expected),
     };
 
-    let script_len = if let Some(script_end) = END_FINDER.find(&script.as_bytes()[script_start..]) {
-        script_end + TOKEN_SCRIPT_END.len()
-    } else {
-        bail!("Could not find end of debugger script")
-    };
+    if let Some(token) = tokens.next() {
+        return Err(token_error(token, format!("unexpected token `{}`", token)));
+    }
+
+    Ok(count)
+}
+
+fn tokenize(line: &str) -> impl Iterator<Item = &str> {
+    line.split(char::is_whitespace).filter(|x| !x.is_empty())
+}
+
+fn concat<'a>(it: impl Iterator<Item = &'a str>) -> String {
+    let mut string = String::new();
+    for piece in it {
+        string.push_str(piece);
+        string.push(' ');
+    }
+
+    string.pop();
+    string
+}
+
+trait StrPattern {
+    fn check(&self, s: &str) -> bool;
+    fn display(&self) -> String;
+}
+
+impl StrPattern for &str {
+    fn check(&self, s: &str) -> bool {
+        *self == s
+    }
+
+    fn display(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl<const LEN: usize> StrPattern for [&str; LEN] {
+    fn check(&self, s: &str) -> bool {
+        self.contains(&s)
+    }
+
+    fn display(&self) -> String {
+        self.join(" or ")
+    }
+}
+
+fn expect<'a>(
+    tokens: &mut impl Iterator<Item = &'a str>,
+    expected: &dyn StrPattern,
+) -> anyhow::Result<&'a str> {
+    let token = tokens.next();
+
+    match token {
+        None => bail!("expected `{}`, found nothing", expected.display()),
+        Some(token) => {
+            if expected.check(token) {
+                Ok(token)
+            } else {
+                Err(token_error(
+                    token,
+                    format!("expected `{}`, found `{}`", expected.display(), token),
+                ))
+            }
+        }
+    }
+}
+
+fn to_comparsion_op(s: &str) -> Option<Comparison> {
+    match s {
+        TOKEN_EQ => Some(Comparison::Eq),
+        TOKEN_CONTAINS => Some(Comparison::Contains),
+        TOKEN_MATCHES => Some(Comparison::Matches),
+        TOKEN_NEQ => Some(Comparison::NotEq),
+        TOKEN_GT => Some(Comparison::GreaterThan),
+        TOKEN_GT_EQ => Some(Comparison::GreaterThanOrEq),
+        TOKEN_LT => Some(Comparison::LessThan),
+        TOKEN_LT_EQ => Some(Comparison::LessThanOrEq),
+        _ => None,
+    }
+}
+
+fn trim_indent(line: &str) -> anyhow::Result<(&str, isize)> {
+    let first_non_whitespace = line.bytes().position(|x| x != b' ').unwrap();
+    if line.as_bytes()[first_non_whitespace].is_ascii_whitespace() {
+        return Err(token_error(
+            &line[first_non_whitespace..first_non_whitespace + 1],
+            "only spaces allowed for indentation".to_string(),
+        ));
+    }
+    Ok((
+        &line[first_non_whitespace..],
+        first_non_whitespace.try_into().unwrap(),
+    ))
+}
+
+fn parse_condition<'a>(
+    tokens: &mut Peekable<impl Iterator<Item = &'a str>>,
+) -> anyhow::Result<Condition> {
+    let term = parse_condition_term(tokens)?;
+
+    match tokens.next() {
+        Some(op) => {
+            let rhs = parse_condition(tokens)?;
+
+            if op == TOKEN_OR {
+                Ok(Condition::Or(Box::new(term), Box::new(rhs)))
+            } else if op == TOKEN_AND {
+                Ok(Condition::And(Box::new(term), Box::new(rhs)))
+            } else {
+                Err(token_error(
+                    op,
+                    format!(
+                        "unknown operator `{}`, expected `{}` or `{}`",
+                        op, TOKEN_AND, TOKEN_OR
+                    ),
+                ))
+            }
+        }
+        None => Ok(term),
+    }
+}
+
+fn parse_condition_term<'a>(
+    tokens: &mut Peekable<impl Iterator<Item = &'a str>>,
+) -> anyhow::Result<Condition> {
+    match tokens.next() {
+        Some(lhs) => {
+            if lhs == TOKEN_NOT {
+                let inner = parse_condition_term(tokens)?;
+                return Ok(Condition::Not(Box::new(inner)));
+            }
+
+            if !lhs.starts_with('@') {
+                return Err(token_error(
+                    lhs,
+                    format!("expected variable name -- did you mean `@{}`?", lhs),
+                ));
+            }
+
+            if let Some(&peek) = tokens.peek() {
+                if let Some(comparison_op) = to_comparsion_op(peek) {
+                    // Eat operator token
+                    let _ = tokens.next();
+
+                    if let Some(rhs) = tokens.next() {
+                        if to_comparsion_op(rhs).is_some() {
+                            return Err(token_error(
+                                rhs,
+                                format!("expected a value, found operator `{}`", rhs),
+                            ));
+                        }
+
+                        let rhs_token = rhs;
+                        let rhs = Value::new(rhs);
+
+                        if comparison_op == Comparison::Matches {
+                            // Validate that the RHS is a valid regular expression
+                            if let Err(e) = get_regex(&rhs.string) {
+                                return Err(token_error(
+                                    rhs_token,
+                                    format!("invalid regular expression: {}", e),
+                                ));
+                            }
+                        }
+
+                        return Ok(Condition::Comparison(lhs.into(), comparison_op, rhs));
+                    }
+                }
+            }
+
+            Ok(Condition::DefinitionExists(lhs.into()))
+        }
+        None => {
+            bail!("expected condition, found nothing");
+        }
+    }
+}
+
+fn parse_statement_list(
+    lines: &mut Peekable<impl Iterator<Item = Line>>,
+    parent: Option<(&Line, &str)>,
+) -> Result<Vec<Statement>, SyntaxError> {
+    let mut follows_check = false;
+
+    parse_nested_block(lines, parent, |line, lines| {
+        if let LineKind::Check { check } = &line.kind {
+            if check.options.same_line && !follows_check {
+                let start = line.indent as usize;
+                return Err(SyntaxError {
+                    line_number: line.line_number,
+                    span: start..start + TOKEN_CHECK_SAME.len(),
+                    message: format!(
+                        "{} must follow a {} statement",
+                        TOKEN_CHECK_SAME, TOKEN_CHECK
+                    ),
+                });
+            }
+        }
+
+        follows_check = matches!(&line.kind, LineKind::Check { check } if !check.options.negated);
+
+        statement_from_line(line, lines)
+    })
+}
+
+fn statement_from_line(
+    line: Line,
+    lines: &mut Peekable<impl Iterator<Item = Line>>,
+) -> Result<Statement, SyntaxError> {
+    match line {
+        Line {
+            kind: LineKind::Raw { text },
+            line_number,
+            ..
+        } => Ok(Statement::Exec(text, None, line_number)),
+        Line {
+            kind: LineKind::IgnoreTest { reason },
+            line_number,
+            ..
+        } => Ok(Statement::IgnoreTest(reason, line_number)),
+        Line {
+            kind: LineKind::Require { requirement },
+            line_number,
+            ..
+        } => Ok(Statement::Require(requirement, line_number)),
+        Line {
+            kind: LineKind::MaxMemory { bytes },
+            line_number,
+            ..
+        } => Ok(Statement::MaxMemory(bytes, line_number)),
+        Line {
+            kind: LineKind::DependsOn { test_name },
+            line_number,
+            ..
+        } => Ok(Statement::DependsOn(test_name, line_number)),
+        Line {
+            kind: LineKind::Dependency { dependency },
+            line_number,
+            ..
+        } => Ok(Statement::Dependency(dependency, line_number)),
+        Line {
+            kind: LineKind::Args { args },
+            line_number,
+            ..
+        } => Ok(Statement::Args(args, line_number)),
+        Line {
+            kind: LineKind::RustcFlags { flags },
+            line_number,
+            ..
+        } => Ok(Statement::RustcFlags(flags, line_number)),
+        Line {
+            kind: LineKind::Targets { targets },
+            line_number,
+            ..
+        } => Ok(Statement::Targets(targets, line_number)),
+        Line {
+            kind: LineKind::DebuggeeTarget { debuggee_target },
+            line_number,
+            ..
+        } => Ok(Statement::DebuggeeTarget(debuggee_target, line_number)),
+        Line {
+            kind: LineKind::CBaseline,
+            line_number,
+            ..
+        } => Ok(Statement::CBaseline(line_number)),
+        Line {
+            kind: LineKind::Sequence,
+            line_number,
+            ..
+        } => Ok(Statement::Sequence(line_number)),
+        Line {
+            kind: LineKind::Check { check, .. },
+            line_number,
+            ..
+        } => Ok(Statement::Check(check, None, line_number)),
+        Line {
+            kind: LineKind::CheckUnordered,
+            ..
+        } => parse_check_unordered_body(lines, &line),
+        Line {
+            kind: LineKind::CheckLocals { exact },
+            ..
+        } => parse_check_locals_body(lines, &line, exact),
+        Line {
+            kind: LineKind::CheckType { ref type_name },
+            ..
+        } => parse_check_type_body(lines, &line, type_name.clone()),
+        Line {
+            kind: LineKind::StaticCheck { tool, ref args },
+            ..
+        } => parse_static_check_body(lines, &line, tool, args.clone()),
+        Line {
+            kind: LineKind::ObjectCheck { ref check },
+            line_number,
+            ..
+        } => Ok(Statement::ObjectCheck(check.clone(), line_number)),
+        Line {
+            kind: LineKind::ProgramOutputCheck { ref check },
+            line_number,
+            ..
+        } => Ok(Statement::ProgramOutputCheck(check.clone(), line_number)),
+        Line {
+            kind: LineKind::If { ref condition },
+            line_number,
+            ..
+        } => {
+            let nested_body = parse_statement_list(lines, Some((&line, TOKEN_IF)))?;
+            Ok(Statement::IfBlock(
+                condition.clone(),
+                nested_body,
+                line_number,
+            ))
+        }
+        Line {
+            kind: LineKind::Group { ref label },
+            line_number,
+            ..
+        } => {
+            let nested_body = parse_statement_list(lines, Some((&line, TOKEN_GROUP)))?;
+            Ok(Statement::Group(label.clone(), nested_body, line_number))
+        }
+        Line {
+            kind: LineKind::Phase { phase_config },
+            line_number,
+            ..
+        } => Ok(Statement::Phase(phase_config, line_number)),
+        Line {
+            kind: LineKind::GenerateCrashDump { tag },
+            line_number,
+            ..
+        } => Ok(Statement::GenerateCrashDump(tag, None, line_number)),
+        Line {
+            kind: LineKind::Command { command },
+            line_number,
+            ..
+        } => Ok(Statement::Command(command, None, line_number)),
+        Line {
+            kind: LineKind::CommandCheck { check },
+            line_number,
+            ..
+        } => Ok(Statement::CommandCheck(check, None, line_number)),
+        Line {
+            kind: LineKind::SetBreakpoint { name },
+            line_number,
+            ..
+        } => Ok(Statement::SetBreakpoint(name, None, line_number)),
+        Line {
+            kind: LineKind::EachHit { ref name, count },
+            line_number,
+            ..
+        } => {
+            let nested_body = parse_statement_list(lines, Some((&line, TOKEN_EACH_HIT)))?;
+            Ok(expand_each_hit(
+                name.clone(),
+                count,
+                nested_body,
+                line_number,
+            ))
+        }
+    }
+}
+
+/// Expands the body of `#each-hit name N` into a `#break name` followed by one `#group`
+/// per hit, each of which continues to the breakpoint before running the body. Each hit
+/// starts a correlation block of its own (like `#sequence`), so that its checks can't match
+/// the output of the commands before it, not even for the first hit.
+fn expand_each_hit(
+    name: Arc<str>,
+    count: u32,
+    body: Vec<Statement>,
+    line_number: LineNumber,
+) -> Statement {
+    let mut expanded = Vec::with_capacity(count as usize + 1);
+    expanded.push(Statement::SetBreakpoint(name.clone(), None, line_number));
+
+    for hit in 1..=count {
+        let mut statements = Vec::with_capacity(body.len() + 2);
+        statements.push(Statement::Sequence(line_number));
+        statements.push(Statement::Command(
+            DebuggerCommand::Continue,
+            None,
+            line_number,
+        ));
+        statements.extend(body.iter().cloned());
+
+        expanded.push(Statement::Group(
+            format!("{} hit {}", name, hit).into(),
+            statements,
+            line_number,
+        ));
+    }
+
+    Statement::EachHit(name, count, expanded, line_number)
+}
+
+fn parse_static_check_body(
+    lines: &mut Peekable<impl Iterator<Item = Line>>,
+    parent: &Line,
+    tool: StaticTool,
+    args: Vec<Arc<str>>,
+) -> Result<Statement, SyntaxError> {
+    let checks = parse_nested_block(lines, Some((parent, tool.keyword())), |line, _| {
+        let span = line.indent as usize..usize::MAX;
+
+        match line {
+            Line {
+                kind: LineKind::Raw { text },
+                line_number,
+                ..
+            } => RegexCheck::new(&text).map_err(|e| SyntaxError {
+                line_number,
+                span,
+                message: e.to_string(),
+            }),
+            Line { line_number, .. } => Err(SyntaxError {
+                line_number,
+                span,
+                message: format!("{} cannot have nested statements", tool.keyword()),
+            }),
+        }
+    })?;
+
+    Ok(Statement::StaticCheck(
+        StaticCheck { tool, args, checks },
+        parent.line_number,
+    ))
+}
+
+/// Parses the `name` and `name = value` lines of a `#check-locals` block, where `value` is a
+/// check specification like the one of `#check`
+fn parse_check_locals_body(
+    lines: &mut Peekable<impl Iterator<Item = Line>>,
+    parent: &Line,
+    exact: bool,
+) -> Result<Statement, SyntaxError> {
+    let locals = parse_nested_block(lines, Some((parent, TOKEN_CHECK_LOCALS)), |line, _| {
+        let span = line.indent as usize..usize::MAX;
+
+        let (text, line_number) = match line {
+            Line {
+                kind: LineKind::Raw { text },
+                line_number,
+                ..
+            } => (text, line_number),
+            Line { line_number, .. } => {
+                return Err(SyntaxError {
+                    line_number,
+                    span,
+                    message: format!("{} cannot have nested statements", TOKEN_CHECK_LOCALS),
+                })
+            }
+        };
+
+        let (name, value) = match text.split_once('=') {
+            Some((name, value)) => (name.trim(), Some(value.trim())),
+            None => (text.trim(), None),
+        };
+
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(SyntaxError {
+                line_number,
+                span,
+                message: format!("expected a variable name, found `{}`", name),
+            });
+        }
+
+        let value = match value {
+            Some(value) => Some(RegexCheck::new(value).map_err(|e| SyntaxError {
+                line_number,
+                span,
+                message: e.to_string(),
+            })?),
+            None => None,
+        };
+
+        Ok(ExpectedLocal {
+            name: name.into(),
+            value,
+        })
+    })?;
+
+    Ok(Statement::CommandCheck(
+        CommandCheck::Locals(locals, exact),
+        None,
+        parent.line_number,
+    ))
+}
+
+/// Parses the `size N` and `field <name> [offset N] [size N]` lines of a `#check-type`
+/// block
+fn parse_check_type_body(
+    lines: &mut Peekable<impl Iterator<Item = Line>>,
+    parent: &Line,
+    type_name: Arc<str>,
+) -> Result<Statement, SyntaxError> {
+    let mut size = None;
+
+    let fields = parse_nested_block(lines, Some((parent, TOKEN_CHECK_TYPE)), |line, _| {
+        let span = line.indent as usize..usize::MAX;
+        let line_number = line.line_number;
+        let error = |message: String| SyntaxError {
+            line_number,
+            span: span.clone(),
+            message,
+        };
+
+        let text = match line.kind {
+            LineKind::Raw { text } => text,
+            _ => {
+                return Err(error(format!(
+                    "{} cannot have nested statements",
+                    TOKEN_CHECK_TYPE
+                )))
+            }
+        };
+
+        let mut tokens = text.split_whitespace();
+
+        match tokens.next() {
+            Some("size") => {
+                let number = tokens
+                    .next()
+                    .and_then(parse_number)
+                    .filter(|size| *size >= 0);
+
+                match (number, tokens.next()) {
+                    (Some(number), None) => {
+                        size = Some(number as u64);
+                        Ok(None)
+                    }
+                    _ => Err(error(format!("expected `size <number>`, found `{}`", text))),
+                }
+            }
+            Some("field") => {
+                let mut field = match tokens.next() {
+                    Some(name) => FieldLayout {
+                        name: name.into(),
+                        offset: None,
+                        size: None,
+                    },
+                    None => return Err(error("expected a field name, found nothing".to_string())),
+                };
+
+                while let Some(token) = tokens.next() {
+                    let value = tokens
+                        .next()
+                        .and_then(parse_number)
+                        .filter(|value| *value >= 0)
+                        .map(|value| value as u64);
+
+                    match (token, value) {
+                        ("offset", Some(offset)) => field.offset = Some(offset),
+                        ("size", Some(size)) => field.size = Some(size),
+                        ("offset" | "size", None) => {
+                            return Err(error(format!("expected a number after `{}`", token)))
+                        }
+                        _ => {
+                            return Err(error(format!(
+                                "expected `offset` or `size`, found `{}`",
+                                token
+                            )))
+                        }
+                    }
+                }
+
+                Ok(Some(field))
+            }
+            _ => Err(error(format!(
+                "expected `size` or `field`, found `{}`",
+                text
+            ))),
+        }
+    })?;
+
+    Ok(Statement::CommandCheck(
+        CommandCheck::TypeLayout(TypeLayout {
+            type_name,
+            size,
+            fields: fields.into_iter().flatten().collect(),
+        }),
+        None,
+        parent.line_number,
+    ))
+}
+
+fn parse_check_unordered_body(
+    lines: &mut Peekable<impl Iterator<Item = Line>>,
+    parent: &Line,
+) -> Result<Statement, SyntaxError> {
+    let checks =
+        parse_nested_block(
+            lines,
+            Some((parent, TOKEN_CHECK_UNORDERED)),
+            |line, _| match line {
+                Line {
+                    kind: LineKind::Raw { text },
+                    ..
+                } => Ok(text),
+                Line {
+                    indent,
+                    line_number,
+                    ..
+                } => Err(SyntaxError {
+                    line_number,
+                    span: indent as usize..usize::MAX,
+                    message: format!("{} cannot have nested statements", TOKEN_CHECK_UNORDERED),
+                }),
+            },
+        )?;
+
+    Ok(Statement::CheckUnorderedBlock(
+        checks,
+        None,
+        parent.line_number,
+    ))
+}
+
+/// Parses the block nested under `parent`, which is the line that opened the block
+/// together with its keyword. `parent` is `None` for the top-level block.
+fn parse_nested_block<T, I: Iterator<Item = Line>>(
+    lines: &mut Peekable<I>,
+    parent: Option<(&Line, &str)>,
+    mut process_line: impl FnMut(Line, &mut Peekable<I>) -> Result<T, SyntaxError>,
+) -> Result<Vec<T>, SyntaxError> {
+    let mut checks = vec![];
+    let first_indent = lines.peek().map_or(-1, |line| line.indent);
+
+    if let Some((parent, token)) = parent {
+        if first_indent <= parent.indent {
+            let start = parent.indent as usize;
+            return Err(SyntaxError {
+                line_number: parent.line_number,
+                span: start..start + token.len(),
+                message: format!("Empty {} block", token),
+            });
+        }
+    }
+
+    loop {
+        if lines.peek().is_none_or(|line| line.indent < first_indent) {
+            return Ok(checks);
+        }
+
+        let line = lines.next().unwrap();
+        checks.push(process_line(line, lines)?);
+    }
+}
+
+/// One instantiation of a test with `#params` directives: the values of all parameters,
+/// and the test's source with them substituted into the script.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instantiation {
+    pub params: Vec<(String, String)>,
+    pub source: String,
+}
+
+/// Expands the `#params name = value1, value2, ...` directives in the script of `source`
+/// into one instantiation per combination of values, with `{{name}}` replaced by the value
+/// in the script. The `#params` lines themselves are blanked out, so that line numbers stay
+/// the same. Without `#params`, this is just `source`. Fails on a `{{name}}` without a
+/// `#params` directive for it.
+pub fn instantiate_params(source: &str) -> anyhow::Result<Vec<Instantiation>> {
+    let (script_start, script_end) = match source.find(TOKEN_SCRIPT_START) {
+        Some(script_start) => match source[script_start..].find(TOKEN_SCRIPT_END) {
+            Some(script_len) => (script_start, script_start + script_len),
+            None => (script_start, source.len()),
+        },
+        None => (source.len(), source.len()),
+    };
+
+    let mut params: Vec<(String, Vec<String>)> = vec![];
+    let mut script = String::with_capacity(script_end - script_start);
+
+    for line in source[script_start..script_end].split_inclusive('\n') {
+        let directive = match line.trim().strip_prefix(TOKEN_PARAMS) {
+            Some(directive) if directive.is_empty() || directive.starts_with(' ') => directive,
+            _ => {
+                script.push_str(line);
+                continue;
+            }
+        };
+
+        let (name, values) = match directive.split_once('=') {
+            Some((name, values)) => (name.trim(), values),
+            None => bail!(
+                "expected `#params <name> = <value>, ...`, found `{}`",
+                line.trim()
+            ),
+        };
+
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            bail!("invalid parameter name `{}` in `{}`", name, line.trim());
+        }
+
+        if params.iter().any(|(other, _)| other == name) {
+            bail!("parameter `{}` is defined more than once", name);
+        }
+
+        let values: Vec<String> = values.split(',').map(|v| v.trim().to_string()).collect();
+
+        if values.iter().any(String::is_empty) {
+            bail!("empty value for parameter `{}` in `{}`", name, line.trim());
+        }
+
+        params.push((name.to_string(), values));
+        script.push_str(if line.ends_with('\n') { "\n" } else { "" });
+    }
+
+    lazy_static! {
+        static ref PLACEHOLDER: Regex = Regex::new(r"\{\{([A-Za-z0-9_]+)\}\}").unwrap();
+    }
+
+    // `{{ptr}}` is a placeholder of checks rather than a parameter, see [RegexCheck]
+    for line in script.lines() {
+        if let Some(placeholder) = PLACEHOLDER.captures_iter(line).find(|captures| {
+            &captures[1] != "ptr" && !params.iter().any(|(name, _)| *name == captures[1])
+        }) {
+            bail!(
+                "undefined parameter `{}` in `{}`, parameters are defined via `{} {} = ...`",
+                &placeholder[1],
+                line.trim(),
+                TOKEN_PARAMS,
+                &placeholder[1]
+            );
+        }
+    }
+
+    if params.is_empty() {
+        return Ok(vec![Instantiation {
+            params: vec![],
+            source: source.to_string(),
+        }]);
+    }
+
+    // The cartesian product of all parameter values, varying the last parameter fastest
+    let mut combinations: Vec<Vec<(String, String)>> = vec![vec![]];
+
+    for (name, values) in &params {
+        combinations = combinations
+            .into_iter()
+            .flat_map(|combination| {
+                values.iter().map(move |value| {
+                    let mut combination = combination.clone();
+                    combination.push((name.clone(), value.clone()));
+                    combination
+                })
+            })
+            .collect();
+    }
+
+    Ok(combinations
+        .into_iter()
+        .map(|params| {
+            let mut instantiated_script = script.clone();
+
+            for (name, value) in &params {
+                instantiated_script =
+                    instantiated_script.replace(&format!("{{{{{}}}}}", name), value);
+            }
+
+            Instantiation {
+                source: format!(
+                    "{}{}{}",
+                    &source[..script_start],
+                    instantiated_script,
+                    &source[script_end..]
+                ),
+                params,
+            }
+        })
+        .collect())
+}
+
+pub fn parse_script(
+    script: &str,
+    file_path_for_diagnostics: Option<&Path>,
+) -> anyhow::Result<Script> {
+    lazy_static! {
+        static ref START_FINDER: memchr::memmem::Finder<'static> =
+            memchr::memmem::Finder::new(TOKEN_SCRIPT_START);
+        static ref END_FINDER: memchr::memmem::Finder<'static> =
+            memchr::memmem::Finder::new(TOKEN_SCRIPT_END);
+        static ref COMMENT_FINDER: memchr::memmem::Finder<'static> =
+            memchr::memmem::Finder::new(TOKEN_COMMENT);
+    }
+
+    let script_start = if let Some(script_start) = START_FINDER.find(script.as_bytes()) {
+        script_start
+    } else {
+        bail!("Could not find start of debugger script")
+    };
+
+    let script_len = if let Some(script_end) = END_FINDER.find(&script.as_bytes()[script_start..]) {
+        script_end + TOKEN_SCRIPT_END.len()
+    } else {
+        bail!("Could not find end of debugger script")
+    };
+
+    // Line numbers are reported relative to the containing file
+    let first_line_index = memchr::memchr_iter(b'\n', &script.as_bytes()[..script_start]).count();
+
+    let mut source_lines = HashMap::new();
+    let mut script_lines = vec![];
+    let mut code_lines = HashMap::new();
+
+    for (line_index, full_line) in script[script_start..script_start + script_len]
+        .lines()
+        .enumerate()
+    {
+        // Remove comments, except from the reason of #ignore-test, which may contain URLs
+        let line = if full_line.trim_start().starts_with(TOKEN_IGNORE_TEST) {
+            full_line
+        } else {
+            COMMENT_FINDER
+                .find(full_line.as_bytes())
+                .map(|index| &full_line[..index])
+                .unwrap_or(full_line)
+        };
+
+        let trimmed = line.trim();
+
+        if trimmed.is_empty() || trimmed == TOKEN_SCRIPT_START || trimmed == TOKEN_SCRIPT_END {
+            continue;
+        }
+
+        let line_number = LineNumber((first_line_index + line_index) as u32 + 1);
+        source_lines.insert(line_number.0, (full_line, line.trim_end().len()));
+        code_lines.insert(line_number.0, trimmed.into());
+        script_lines.push((line_number, full_line, line));
+    }
+
+    // Check #min-dbt-version before anything else, so that scripts using newer
+    // directives don't fail with confusing parse errors on older versions of dbt.
+    for &(line_number, full_line, line) in &script_lines {
+        if line.trim_start().starts_with(TOKEN_MIN_DBT_VERSION) {
+            check_min_dbt_version(line, line_number)
+                .map_err(|e| e.into_parse_error(full_line, file_path_for_diagnostics))?;
+        }
+    }
+
+    let mut lines = vec![];
+
+    for (line_number, full_line, line) in script_lines {
+        if line.trim_start().starts_with(TOKEN_MIN_DBT_VERSION) {
+            continue;
+        }
+
+        let line = parse_line(line, line_number)
+            .map_err(|e| e.into_parse_error(full_line, file_path_for_diagnostics))?;
+
+        lines.push(line);
+    }
+
+    let to_parse_error = |mut error: SyntaxError| {
+        let (full_line, code_len) = source_lines[&error.line_number.0];
+        // Don't underline trailing comments
+        error.span.end = error.span.end.min(code_len);
+        error.into_parse_error(full_line, file_path_for_diagnostics)
+    };
+
+    let mut lines = lines.into_iter().peekable();
+
+    let statements = if lines.peek().is_none() {
+        vec![]
+    } else {
+        parse_statement_list(&mut lines, None).map_err(to_parse_error)?
+    };
+
+    // All lines must have been consumed by the top-level block. If not, a line is
+    // indented less than the first line of the script.
+    if let Some(line) = lines.next() {
+        let start = line.indent as usize;
+        return Err(to_parse_error(SyntaxError {
+            line_number: line.line_number,
+            span: start..usize::MAX,
+            message:
+                "Unexpected indentation: line is indented less than the first line of the script"
+                    .to_string(),
+        })
+        .into());
+    }
+
+    Ok(Script {
+        statements,
+        source_lines: code_lines,
+    })
+}
+
+/// Builds the script of a test that has no script section from the directives in the
+/// comments right below its unnamed `// #break` markers, like `// #check = 5`. The script
+/// starts the debuggee and continues to each breakpoint in turn, so the breakpoints have to
+/// be hit in the order they appear in the source. Returns `None` for tests with a script
+/// section or without such directives.
+pub fn parse_inline_script(
+    source: &str,
+    breakpoints: &[BreakPoint],
+    file_path_for_diagnostics: Option<&Path>,
+) -> anyhow::Result<Option<Script>> {
+    if source.contains(TOKEN_SCRIPT_START) {
+        return Ok(None);
+    }
+
+    let source_lines: Vec<&str> = source.lines().collect();
+    let mut lines = vec![];
+    let mut directive_lines = HashMap::new();
+    let mut has_directives = false;
+
+    for (index, breakpoint) in breakpoints
+        .iter()
+        .filter(|breakpoint| breakpoint.name.is_none())
+        .enumerate()
+    {
+        let line_number = LineNumber(breakpoint.line_index as u32 + 1);
+        let raw = |text: &str, indent| Line {
+            kind: LineKind::Raw {
+                text: text.to_string(),
+            },
+            indent,
+            line_number,
+        };
+
+        if index == 0 {
+            // CDB starts out stopped at the initial breakpoint, and programs on embedded
+            // targets are already running
+            for (condition, command) in [
+                ("#if @embedded", "continue"),
+                ("#if @cdb", "g"),
+                ("#if @gdb && not @embedded", "run"),
+                ("#if @lldb || @mock", "run"),
+            ] {
+                lines.push(parse_line(condition, line_number).unwrap());
+                lines.push(raw(command, 2));
+            }
+        } else {
+            lines.push(Line {
+                kind: LineKind::Command {
+                    command: DebuggerCommand::Continue,
+                },
+                indent: 0,
+                line_number,
+            });
+        }
+
+        for (line_index, full_line) in source_lines
+            .iter()
+            .enumerate()
+            .skip(breakpoint.line_index + 1)
+        {
+            if breakpoints
+                .iter()
+                .any(|breakpoint| breakpoint.line_index == line_index)
+            {
+                break;
+            }
+
+            let directive = match full_line.trim_start().strip_prefix(TOKEN_COMMENT) {
+                Some(comment) if comment.trim_start().starts_with('#') => comment.trim(),
+                _ => break,
+            };
+
+            let line_number = LineNumber(line_index as u32 + 1);
+
+            let line = parse_line(directive, line_number).map_err(|mut error| {
+                // Point into the full source line rather than the comment
+                let offset = directive.as_ptr() as usize - full_line.as_ptr() as usize;
+                error.span = error.span.start + offset..error.span.end.saturating_add(offset);
+                error.into_parse_error(full_line, file_path_for_diagnostics)
+            })?;
+
+            lines.push(line);
+            directive_lines.insert(line_number.0, directive.into());
+            has_directives = true;
+        }
+    }
+
+    if !has_directives {
+        return Ok(None);
+    }
+
+    let statements = parse_statement_list(&mut lines.into_iter().peekable(), None).map_err(
+        |error: SyntaxError| {
+            let full_line = source_lines[error.line_number.0 as usize - 1];
+            error.into_parse_error(full_line, file_path_for_diagnostics)
+        },
+    )?;
+
+    Ok(Some(Script {
+        statements,
+        source_lines: directive_lines,
+    }))
+}
+
+fn get_regex(regex_str: &Arc<str>) -> anyhow::Result<Arc<Regex>> {
+    lazy_static! {
+        static ref CACHE: Mutex<HashMap<Arc<str>, Arc<Regex>>> = Mutex::new(HashMap::default());
+    }
+
+    {
+        let mut cache = CACHE.lock().unwrap();
+
+        if let Some(cached) = cache.get(&regex_str[..]) {
+            return Ok(cached.clone());
+        }
+
+        let regex = Arc::new(Regex::new(regex_str)?);
+
+        cache.insert(regex_str.clone(), regex.clone());
+
+        Ok(regex)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::regex_check::{CheckOptions, NumericComparison, NumericConstraint, RegexCheck};
+    use crate::script::{
+        parse_script, parse_statement_list, CommandCheck, Comparison, CrateDependency,
+        DebuggeeTarget, DebuggeeTargetKind, DebuggerCommand, FieldLayout, ForkMode, LineKind,
+        LineNumber, ObjectCheck, PhaseConfig, Requirement, SectionCompression, Statement,
+        StaticCheck, StaticTool, TypeLayout, Value, WatchAccess, Watchpoint, TOKEN_SCRIPT_END,
+        TOKEN_SCRIPT_START,
+    };
+    use std::{fmt::Write, sync::Arc};
+
+    use super::{tokenize, Condition, EvaluationContext, Line, Script, ScriptBuilder};
+
+    #[test]
+    fn parse_line() {
+        use super::parse_line;
+
+        assert_eq!(
+            parse_line("#if @cdb", LineNumber(123)).unwrap(),
+            Line {
+                kind: LineKind::If {
+                    condition: Condition::DefinitionExists("@cdb".into()),
+                },
+                indent: 0,
+                line_number: LineNumber(123),
+            }
+        );
+
+        assert_eq!(
+            parse_line("  #check abc def", LineNumber(123)).unwrap(),
+            Line {
+                kind: LineKind::Check {
+                    check: "abc def".into(),
+                },
+                indent: 2,
+                line_number: LineNumber(123),
+            }
+        );
+
+        assert_eq!(
+            parse_line("    foo bar quux", LineNumber(123)).unwrap(),
+            Line {
+                kind: LineKind::Raw {
+                    text: "foo bar quux".into(),
+                },
+                indent: 4,
+                line_number: LineNumber(123),
+            }
+        );
+
+        assert_eq!(
+            parse_line("  #check-nocase abc def", LineNumber(123)).unwrap(),
+            Line {
+                kind: LineKind::Check {
+                    check: RegexCheck::with_options(
+                        "abc def",
+                        CheckOptions {
+                            case_insensitive: true,
+                            ..Default::default()
+                        }
+                    )
+                    .unwrap(),
+                },
+                indent: 2,
+                line_number: LineNumber(123),
+            }
+        );
+
+        assert_eq!(
+            parse_line("  #check-unordered", LineNumber(123)).unwrap(),
+            Line {
+                kind: LineKind::CheckUnordered,
+                indent: 2,
+                line_number: LineNumber(123),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_condition() {
+        use super::parse_condition;
+
+        assert_eq!(
+            parse_condition(&mut tokenize("@cdb").peekable()).unwrap(),
+            Condition::DefinitionExists("@cdb".into())
+        );
+        assert_eq!(
+            parse_condition(&mut tokenize("@cdb && @gdb").peekable()).unwrap(),
+            Condition::And(
+                Box::new(Condition::DefinitionExists("@cdb".into())),
+                Box::new(Condition::DefinitionExists("@gdb".into()))
+            )
+        );
+        assert_eq!(
+            parse_condition(&mut tokenize("@cdb && @version == 1.3.4").peekable()).unwrap(),
+            Condition::And(
+                Box::new(Condition::DefinitionExists("@cdb".into())),
+                Box::new(Condition::Comparison(
+                    "@version".into(),
+                    Comparison::Eq,
+                    "1.3.4".into()
+                ))
+            )
+        );
+
+        assert_eq!(
+            parse_condition(&mut tokenize("@version == 1.3.4 || @abc ~= 3.5").peekable()).unwrap(),
+            Condition::Or(
+                Box::new(Condition::Comparison(
+                    "@version".into(),
+                    Comparison::Eq,
+                    "1.3.4".into()
+                )),
+                Box::new(Condition::Comparison(
+                    "@abc".into(),
+                    Comparison::Matches,
+                    "3.5".into()
+                ))
+            )
+        );
+
+        assert_eq!(
+            parse_condition(&mut tokenize("@version == 1.3.4 && @gdb || @abc ~= 3.5").peekable())
+                .unwrap(),
+            Condition::And(
+                Box::new(Condition::Comparison(
+                    "@version".into(),
+                    Comparison::Eq,
+                    "1.3.4".into()
+                )),
+                Box::new(Condition::Or(
+                    Box::new(Condition::DefinitionExists("@gdb".into())),
+                    Box::new(Condition::Comparison(
+                        "@abc".into(),
+                        Comparison::Matches,
+                        "3.5".into()
+                    ))
+                ))
+            )
+        );
+    }
+
+    fn script_from_lines(lines: &[&str]) -> Script {
+        let mut script = String::new();
+
+        writeln!(&mut script, "{}", TOKEN_SCRIPT_START).unwrap();
+
+        for line in lines {
+            writeln!(&mut script, "{}", line).unwrap();
+        }
+
+        writeln!(&mut script, "{}", TOKEN_SCRIPT_END).unwrap();
+
+        parse_script(&script, None).unwrap()
+    }
+
+    #[test]
+    fn parse_program_body() {
+        use super::parse_line as line;
+
+        let lines = vec![
+            line("execute something", LineNumber(1)).unwrap(),
+            line("execute something else", LineNumber(2)).unwrap(),
+            line("#if @gdb", LineNumber(3)).unwrap(),
+            line("  execute gdb 1", LineNumber(4)).unwrap(),
+            line("  #if @version == 1", LineNumber(5)).unwrap(),
+            line("    #check abc", LineNumber(6)).unwrap(),
+            line("    #check def", LineNumber(7)).unwrap(),
+            line("    execute gdb 2", LineNumber(8)).unwrap(),
+            line("    #check ghi", LineNumber(9)).unwrap(),
+            line("    #check-unordered", LineNumber(10)).unwrap(),
+            line("      foo", LineNumber(11)).unwrap(),
+            line("      bar", LineNumber(12)).unwrap(),
+            line("    #check quux", LineNumber(13)).unwrap(),
+            line("  #if @version == 2", LineNumber(14)).unwrap(),
+            line("    execute gdb 3", LineNumber(15)).unwrap(),
+            line("    #check xyz", LineNumber(16)).unwrap(),
+        ];
+
+        let parsed = parse_statement_list(&mut lines.into_iter().peekable(), None).unwrap();
+
+        assert_eq!(
+            parsed,
+            vec![
+                Statement::Exec("execute something".into(), None, LineNumber(1)),
+                Statement::Exec("execute something else".into(), None, LineNumber(2)),
+                Statement::IfBlock(
+                    Condition::DefinitionExists("@gdb".into()),
+                    vec![
+                        Statement::Exec("execute gdb 1".into(), None, LineNumber(4)),
+                        Statement::IfBlock(
+                            Condition::Comparison("@version".into(), Comparison::Eq, "1".into()),
+                            vec![
+                                Statement::Check("abc".into(), None, LineNumber(6)),
+                                Statement::Check("def".into(), None, LineNumber(7)),
+                                Statement::Exec("execute gdb 2".into(), None, LineNumber(8)),
+                                Statement::Check("ghi".into(), None, LineNumber(9)),
+                                Statement::CheckUnorderedBlock(
+                                    vec!["foo".into(), "bar".into(),],
+                                    None,
+                                    LineNumber(10)
+                                ),
+                                Statement::Check("quux".into(), None, LineNumber(13)),
+                            ],
+                            LineNumber(5)
+                        ),
+                        Statement::IfBlock(
+                            Condition::Comparison("@version".into(), Comparison::Eq, "2".into()),
+                            vec![
+                                Statement::Exec("execute gdb 3".into(), None, LineNumber(15)),
+                                Statement::Check("xyz".into(), None, LineNumber(16)),
+                            ],
+                            LineNumber(14)
+                        ),
+                    ],
+                    LineNumber(3)
+                ),
+            ]
+        );
+    }
+
+    fn context_from(values: &[(&str, &str)]) -> EvaluationContext {
+        EvaluationContext {
+            values: values
+                .iter()
+                .map(|(k, v)| (k.to_string(), (*v).into()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn walk_applicable_leaves() {
+        let script = script_from_lines(&[
+            "#if @cdb",
+            "  cdb x",
+            "  #if @version == 1.0",
+            "    cdb 1.0",
+            "  #if @version == 2.0",
+            "    cdb 2.0",
+            "#if @gdb",
+            "  gdb x",
+            "  #if @version == 1.0",
+            "    gdb 1.0",
+            "  #if @version == 2.0",
+            "    gdb 2.0",
+            "    #ignore-test",
+            "#if not @gdb",
+            "  not-gdb",
+        ]);
+
+        let collect_for_context = |ctx| {
+            let mut output = String::new();
+
+            script.walk_applicable_leaves(&ctx, &mut |statement| {
+                if let Statement::Exec(command, _, _) = statement {
+                    write!(&mut output, "{};", command).unwrap();
+                }
+                if let Statement::IgnoreTest(..) = statement {
+                    write!(&mut output, "#ignore-test;").unwrap();
+                }
+                true
+            });
+
+            output
+        };
+
+        assert_eq!(
+            collect_for_context(context_from(&[("@cdb", "true"), ("@version", "")])),
+            "cdb x;not-gdb;"
+        );
+        assert_eq!(
+            collect_for_context(context_from(&[("@cdb", "true"), ("@version", "1.0")])),
+            "cdb x;cdb 1.0;not-gdb;"
+        );
+        assert_eq!(
+            collect_for_context(context_from(&[("@cdb", "true"), ("@version", "2.0")])),
+            "cdb x;cdb 2.0;not-gdb;"
+        );
+
+        assert_eq!(
+            collect_for_context(context_from(&[("@gdb", "true"), ("@version", "")])),
+            "gdb x;"
+        );
+        assert_eq!(
+            collect_for_context(context_from(&[("@gdb", "true"), ("@version", "1.0")])),
+            "gdb x;gdb 1.0;"
+        );
+        assert_eq!(
+            collect_for_context(context_from(&[("@gdb", "true"), ("@version", "2.0")])),
+            "gdb x;gdb 2.0;#ignore-test;"
+        );
+    }
+
+    #[test]
+    fn comparisons() {
+        let ctx = &context_from(&[("x", "17")]);
+        assert!(Condition::Comparison("x".into(), Comparison::Eq, "17".into()).eval(ctx));
+        assert!(!Condition::Comparison("x".into(), Comparison::Eq, "0".into()).eval(ctx));
+
+        assert!(!Condition::Comparison("x".into(), Comparison::NotEq, "17".into()).eval(ctx));
+        assert!(Condition::Comparison("x".into(), Comparison::NotEq, "0".into()).eval(ctx));
+
+        assert!(!Condition::Comparison("x".into(), Comparison::LessThan, "17".into()).eval(ctx));
+        assert!(Condition::Comparison("x".into(), Comparison::LessThan, "18".into()).eval(ctx));
+
+        assert!(
+            !Condition::Comparison("x".into(), Comparison::LessThanOrEq, "16".into()).eval(ctx)
+        );
+        assert!(Condition::Comparison("x".into(), Comparison::LessThanOrEq, "17".into()).eval(ctx));
+        assert!(Condition::Comparison("x".into(), Comparison::LessThanOrEq, "18".into()).eval(ctx));
+
+        assert!(!Condition::Comparison("x".into(), Comparison::GreaterThan, "17".into()).eval(ctx));
+        assert!(Condition::Comparison("x".into(), Comparison::GreaterThan, "16".into()).eval(ctx));
+
+        assert!(
+            !Condition::Comparison("x".into(), Comparison::GreaterThanOrEq, "18".into()).eval(ctx)
+        );
+        assert!(
+            Condition::Comparison("x".into(), Comparison::GreaterThanOrEq, "17".into()).eval(ctx)
+        );
+        assert!(
+            Condition::Comparison("x".into(), Comparison::GreaterThanOrEq, "16".into()).eval(ctx)
+        );
+
+        assert!(Condition::Comparison("x".into(), Comparison::Contains, "1".into()).eval(ctx));
+        assert!(Condition::Comparison("x".into(), Comparison::Contains, "7".into()).eval(ctx));
+        assert!(Condition::Comparison("x".into(), Comparison::Contains, "17".into()).eval(ctx));
+        assert!(!Condition::Comparison("x".into(), Comparison::Contains, "2".into()).eval(ctx));
+
+        assert!(Condition::Comparison("x".into(), Comparison::Matches, r"\d\d".into()).eval(ctx));
+        assert!(Condition::Comparison("x".into(), Comparison::Matches, r"1.".into()).eval(ctx));
+        assert!(Condition::Comparison("x".into(), Comparison::Matches, r".7".into()).eval(ctx));
+        assert!(!Condition::Comparison("x".into(), Comparison::Matches, r"\s".into()).eval(ctx));
+    }
+
+    #[test]
+    fn value_new() {
+        assert_eq!(
+            Value::new("abc"),
+            Value {
+                string: "abc".into(),
+                version: None,
+            }
+        );
+
+        assert_eq!(
+            Value::new("123"),
+            Value {
+                string: "123".into(),
+                version: Some(vec![123].into()),
+            }
+        );
+
+        assert_eq!(
+            Value::new("123."),
+            Value {
+                string: "123.".into(),
+                version: None,
+            }
+        );
+
+        assert_eq!(
+            Value::new("1.2.3"),
+            Value {
+                string: "1.2.3".into(),
+                version: Some(vec![1, 2, 3].into()),
+            }
+        );
+
+        assert_eq!(
+            Value::new("01.0002.003"),
+            Value {
+                string: "01.0002.003".into(),
+                version: Some(vec![1, 2, 3].into()),
+            }
+        );
+
+        assert_eq!(
+            Value::new("1.x.3"),
+            Value {
+                string: "1.x.3".into(),
+                version: None,
+            }
+        );
+
+        assert_eq!(Value::new("1.0.0"), Value::new("1.0"),);
+
+        assert_eq!(Value::new("1.0.0"), Value::new("1"),);
+    }
+
+    #[test]
+    fn value_compare() {
+        assert!(Value::new("01") == Value::new("1"));
+        assert!(Value::new("_02") < Value::new("_1"));
+        assert!(Value::new("2.0") > Value::new("1.1"));
+        assert!(Value::new("1.2.3") > Value::new("1.01.3"));
+        assert!(Value::new("1.0.1") >= Value::new("1.0.0"));
+    }
+
+    #[test]
+    fn parse_phase() {
+        assert_eq!(
+            super::parse_line("#phase live", LineNumber(77)).unwrap(),
+            Line {
+                kind: LineKind::Phase {
+                    phase_config: PhaseConfig::Live
+                },
+                indent: 0,
+                line_number: LineNumber(77),
+            }
+        );
+
+        assert_eq!(
+            super::parse_line("#phase crashdump", LineNumber(77)).unwrap(),
+            Line {
+                kind: LineKind::Phase {
+                    phase_config: PhaseConfig::CrashDump {
+                        tag: "default".into()
+                    }
+                },
+                indent: 0,
+                line_number: LineNumber(77)
+            }
+        );
+
+        assert_eq!(
+            super::parse_line("#phase crashdump xyz", LineNumber(77)).unwrap(),
+            Line {
+                kind: LineKind::Phase {
+                    phase_config: PhaseConfig::CrashDump { tag: "xyz".into() }
+                },
+                indent: 0,
+                line_number: LineNumber(77),
+            }
+        );
+    }
+
+    #[test]
+    fn parse_ignore() {
+        let ignore_reason = |line| match super::parse_line(line, LineNumber(1)).unwrap().kind {
+            LineKind::IgnoreTest { reason } => reason,
+            kind => panic!("unexpected {:?}", kind),
+        };
+
+        assert_eq!(ignore_reason("#ignore-test"), None);
+        assert_eq!(ignore_reason("#ignore-test // see #123"), None);
+        assert_eq!(
+            ignore_reason("#ignore-test flaky // see #123"),
+            Some("flaky // see #123".into())
+        );
+        assert_eq!(
+            ignore_reason("#ignore-test \"broken until gdb 15, see #123\""),
+            Some("broken until gdb 15, see #123".into())
+        );
+        assert_eq!(
+            ignore_reason("  #ignore-test flaky on CI"),
+            Some("flaky on CI".into())
+        );
+        assert!(super::parse_line("#ignore-test \"unterminated", LineNumber(1)).is_err());
+
+        let script = script_from_lines(&["#if @gdb", "  #ignore-test \"no reason\"", "run"]);
+        assert_eq!(
+            script.ignore_test(&context_from(&[("@gdb", "true")])),
+            Some(Some("no reason".into()))
+        );
+        assert_eq!(script.ignore_test(&context_from(&[("@cdb", "true")])), None);
+
+        let script = script_from_lines(&[
+            "#ignore-test see https://github.com/rust-lang/rust/issues/123",
+            "run // comment",
+        ]);
+        assert_eq!(
+            script.ignore_test(&context_from(&[])),
+            Some(Some(
+                "see https://github.com/rust-lang/rust/issues/123".into()
+            ))
+        );
+    }
+
+    #[test]
+    fn group_label() {
+        let script = script_from_lines(&[
+            "run",
+            "#check abc",
+            "#group \"Vec header\"",
+            "  print v",
+            "  #check len",
+            "  #if @gdb",
+            "    #check capacity",
+            "  #group inner",
+            "    #check buf",
+            "#check xyz",
+        ]);
+
+        assert_eq!(script.group_label(LineNumber(3)), None);
+        assert_eq!(script.group_label(LineNumber(6)), Some("Vec header".into()));
+        assert_eq!(script.group_label(LineNumber(8)), Some("Vec header".into()));
+        assert_eq!(script.group_label(LineNumber(10)), Some("inner".into()));
+        assert_eq!(script.group_label(LineNumber(11)), None);
+
+        assert!(super::parse_line("#group", LineNumber(1)).is_err());
+    }
+
+    #[test]
+    fn parse_require() {
+        assert_eq!(
+            super::parse_line("  #require python", LineNumber(5)).unwrap(),
+            Line {
+                kind: LineKind::Require {
+                    requirement: Requirement::Python
+                },
+                indent: 2,
+                line_number: LineNumber(5),
+            }
+        );
+
+        assert!(super::parse_line("#require", LineNumber(5)).is_err());
+        assert!(super::parse_line("#require xyz", LineNumber(5)).is_err());
+
+        let script = script_from_lines(&[
+            "#require symbol-server",
+            "#if @cdb",
+            "  #require natvis",
+            "  #require symbol-server",
+            "#if @gdb",
+            "  #require python",
+        ]);
+
+        assert_eq!(
+            script.requirements(&context_from(&[("@cdb", "true")])),
+            vec![Requirement::SymbolServer, Requirement::Natvis]
+        );
+        assert_eq!(
+            script.requirements(&context_from(&[("@gdb", "true")])),
+            vec![Requirement::SymbolServer, Requirement::Python]
+        );
+    }
+
+    #[test]
+    fn parse_max_memory() {
+        assert_eq!(
+            super::parse_line("#max-memory 512M", LineNumber(3)).unwrap(),
+            Line {
+                kind: LineKind::MaxMemory { bytes: 512 << 20 },
+                indent: 0,
+                line_number: LineNumber(3),
+            }
+        );
+
+        assert!(super::parse_line("#max-memory", LineNumber(3)).is_err());
+        assert!(super::parse_line("#max-memory 0", LineNumber(3)).is_err());
+        assert!(super::parse_line("#max-memory 12X", LineNumber(3)).is_err());
+        assert!(super::parse_line("#max-memory 1G 2G", LineNumber(3)).is_err());
+
+        let script = script_from_lines(&[
+            "#max-memory 2G",
+            "#if @gdb",
+            "  #max-memory 100K",
+            "#max-memory 4096",
+        ]);
+
+        assert_eq!(script.max_memory(&context_from(&[])), Some(4096));
+        assert_eq!(
+            script.max_memory(&context_from(&[("@gdb", "true")])),
+            Some(4096)
+        );
+
+        let script = script_from_lines(&["#if @gdb", "  #max-memory 100K", "run"]);
+
+        assert_eq!(script.max_memory(&context_from(&[])), None);
+        assert_eq!(
+            script.max_memory(&context_from(&[("@gdb", "true")])),
+            Some(100 << 10)
+        );
+    }
+
+    #[test]
+    fn parse_dependencies() {
+        assert_eq!(
+            super::parse_line(
+                r#"#dependencies serde = { version = "1", features = ["derive"] }"#,
+                LineNumber(3)
+            )
+            .unwrap()
+            .kind,
+            LineKind::Dependency {
+                dependency: CrateDependency {
+                    name: "serde".into(),
+                    spec: r#"{ version = "1", features = ["derive"] }"#.into(),
+                }
+            }
+        );
+
+        assert!(super::parse_line("#dependencies serde", LineNumber(3)).is_err());
+        assert!(super::parse_line("#dependencies serde = 1.0.0", LineNumber(3)).is_err());
+        assert!(super::parse_line("#dependencies ser de = \"1\"", LineNumber(3)).is_err());
+
+        let script = script_from_lines(&[
+            "#dependencies smallvec = \"1\"",
+            "#if @gdb",
+            "  #dependencies serde = \"1\"",
+            "run",
+        ]);
+
+        assert_eq!(
+            script
+                .crate_dependencies()
+                .iter()
+                .map(|(dependency, _)| dependency.to_string())
+                .collect::<Vec<_>>(),
+            vec!["smallvec = \"1\"", "serde = \"1\""]
+        );
+    }
+
+    #[test]
+    fn has_c_baseline() {
+        assert!(script_from_lines(&["#if @gdb", "  #c-baseline"]).has_c_baseline());
+        assert!(!script_from_lines(&["print v"]).has_c_baseline());
+        assert!(super::parse_line("#c-baseline main.c", LineNumber(1)).is_err());
+    }
+
+    #[test]
+    fn without_checks() {
+        let script = script_from_lines(&[
+            "#rustc-flags -Zshare-generics",
+            "#c-baseline",
+            "print v",
+            "#check v",
+            "#if @gdb",
+            "  #print w",
+            "  #check w",
+        ])
+        .without_checks();
+
+        assert_eq!(script.statements.len(), 2);
+        assert!(matches!(script.statements[0], Statement::Exec(..)));
+
+        match &script.statements[1] {
+            Statement::IfBlock(_, statements, _) => assert!(matches!(
+                statements[..],
+                [Statement::Command(DebuggerCommand::Print(_), _, _)]
+            )),
+            other => panic!("expected an `#if` block, found {:?}", other),
+        }
+    }
+
+    #[test]
+    fn parse_rustc_flags() {
+        assert_eq!(
+            super::parse_line(
+                "#rustc-flags -Zdebug-info-for-profiling -Zcodegen-backend=cranelift",
+                LineNumber(3)
+            )
+            .unwrap()
+            .kind,
+            LineKind::RustcFlags {
+                flags: vec![
+                    "-Zdebug-info-for-profiling".into(),
+                    "-Zcodegen-backend=cranelift".into()
+                ]
+            }
+        );
+
+        assert!(super::parse_line("#rustc-flags", LineNumber(3)).is_err());
+        assert!(super::parse_line("#rustc-flags foo", LineNumber(3)).is_err());
+
+        assert_eq!(
+            super::parse_line(
+                "#targets x86_64-pc-windows-msvc i686-pc-windows-msvc",
+                LineNumber(3)
+            )
+            .unwrap()
+            .kind,
+            LineKind::Targets {
+                targets: vec![
+                    "x86_64-pc-windows-msvc".into(),
+                    "i686-pc-windows-msvc".into()
+                ]
+            }
+        );
+        assert!(super::parse_line("#targets", LineNumber(3)).is_err());
+
+        assert_eq!(
+            super::parse_line("#debuggee-target example:show-vec", LineNumber(3))
+                .unwrap()
+                .kind,
+            LineKind::DebuggeeTarget {
+                debuggee_target: DebuggeeTarget {
+                    kind: DebuggeeTargetKind::Example,
+                    name: "show-vec".into()
+                }
+            }
+        );
+        assert!(super::parse_line("#debuggee-target", LineNumber(3)).is_err());
+        assert!(super::parse_line("#debuggee-target test:foo", LineNumber(3)).is_err());
+        assert!(super::parse_line("#debuggee-target bench:", LineNumber(3)).is_err());
+        assert!(super::parse_line("#debuggee-target bin:a bin:b", LineNumber(3)).is_err());
+
+        let script = script_from_lines(&[
+            "#rustc-flags -Zshare-generics",
+            "#if @gdb",
+            "  #rustc-flags -Zdwarf-version=5",
+            "run",
+        ]);
+
+        assert_eq!(
+            script.rustc_flags(),
+            vec![
+                Arc::from("-Zshare-generics"),
+                Arc::from("-Zdwarf-version=5")
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_args() {
+        assert_eq!(
+            super::parse_line("#args  my_test --exact", LineNumber(3))
+                .unwrap()
+                .kind,
+            LineKind::Args {
+                args: vec!["my_test".into(), "--exact".into()]
+            }
+        );
+
+        assert!(super::parse_line("#args", LineNumber(3)).is_err());
+
+        let script = script_from_lines(&[
+            "#args my_test --exact",
+            "#if @gdb",
+            "  #args --nocapture",
+            "run",
+        ]);
+
+        assert_eq!(
+            script.debuggee_args(&context_from(&[])),
+            vec!["my_test", "--exact"]
+        );
+        assert_eq!(
+            script.debuggee_args(&context_from(&[("@gdb", "true")])),
+            vec!["my_test", "--exact", "--nocapture"]
+        );
+    }
+
+    #[test]
+    fn parse_depends_on() {
+        assert_eq!(
+            super::parse_line("#depends-on ws/setup/src/main.rs", LineNumber(3)).unwrap(),
+            Line {
+                kind: LineKind::DependsOn {
+                    test_name: "ws/setup/src/main.rs".into()
+                },
+                indent: 0,
+                line_number: LineNumber(3),
+            }
+        );
+
+        assert!(super::parse_line("#depends-on", LineNumber(3)).is_err());
+
+        let script = script_from_lines(&[
+            "#depends-on \"ws/setup/src/main.rs\"",
+            "#if @gdb",
+            "  #depends-on ws/record/src/main.rs",
+            "  #depends-on ws/setup/src/main.rs",
+            "run",
+        ]);
+
+        assert_eq!(
+            script.dependencies(&context_from(&[])),
+            vec![Arc::from("ws/setup/src/main.rs")]
+        );
+        assert_eq!(
+            script.dependencies(&context_from(&[("@gdb", "true")])),
+            vec![
+                Arc::from("ws/setup/src/main.rs"),
+                Arc::from("ws/record/src/main.rs")
+            ]
+        );
+    }
+
+    #[test]
+    fn instantiate_params() {
+        let source = "fn main() {\n    println!(\"{{size}}\");\n}\n\n/***\n#params size = 0, 10\n\
+                      #params kind = Some\nprint v\n#check v = {{kind}}(len={{size}})\n***/\n";
 
-    let mut lines = vec![];
+        let instantiations = super::instantiate_params(source).unwrap();
 
-    for (line_index, line) in script[script_start..script_start + script_len]
-        .lines()
-        .enumerate()
-    {
-        // Remove comments
-        let line = COMMENT_FINDER
-            .find(line.as_bytes())
-            .map(|index| &line[..index])
-            .unwrap_or(line);
+        let params = |size: &str| {
+            vec![
+                ("size".to_string(), size.to_string()),
+                ("kind".to_string(), "Some".to_string()),
+            ]
+        };
+        assert_eq!(instantiations.len(), 2);
+        assert_eq!(instantiations[0].params, params("0"));
+        assert_eq!(instantiations[1].params, params("10"));
 
-        let trimmed = line.trim();
+        // Only the script is instantiated, and line numbers don't change
+        assert_eq!(
+            instantiations[1].source,
+            "fn main() {\n    println!(\"{{size}}\");\n}\n\n/***\n\n\nprint v\n#check v = Some(len=10)\n***/\n"
+        );
 
-        if trimmed.is_empty() || trimmed == TOKEN_SCRIPT_START || trimmed == TOKEN_SCRIPT_END {
-            continue;
-        }
+        let script = parse_script(&instantiations[0].source, None).unwrap();
+        assert_eq!(script.statements.len(), 2);
 
-        let line_number = LineNumber(line_index as u32 + 1);
+        assert_eq!(
+            super::instantiate_params("/***\nprint v\n***/").unwrap(),
+            vec![super::Instantiation {
+                params: vec![],
+                source: "/***\nprint v\n***/".to_string(),
+            }]
+        );
 
-        let line = parse_line(line, line_number).with_context(|| {
-            if let Some(path) = file_path_for_diagnostics {
-                format!(
-                    "Parsing error at `{}:{}`.",
-                    prettify_path(path),
-                    line_number.0
-                )
-            } else {
-                format!("Parsing error at line {}.", line_number.0)
-            }
-        })?;
+        assert!(super::instantiate_params("/***\n#params size\n***/").is_err());
+        assert!(super::instantiate_params("/***\n#params a b = 1\n***/").is_err());
+        assert!(super::instantiate_params("/***\n#params a = 1,\n***/").is_err());
+        assert!(super::instantiate_params("/***\n#params a = 1\n#params a = 2\n***/").is_err());
 
-        lines.push(line);
+        assert_eq!(
+            super::instantiate_params("/***\n#params size = 1\n#check v = {{sise}}\n***/")
+                .unwrap_err()
+                .to_string(),
+            "undefined parameter `sise` in `#check v = {{sise}}`, parameters are defined via \
+             `#params sise = ...`"
+        );
+        assert!(super::instantiate_params("/***\n#check v = {{size}}\n***/").is_err());
+        // `{{ptr}}` is left to the check
+        assert!(super::instantiate_params("/***\n#check p = {{ptr:a}} {{ptr}}\n***/").is_ok());
     }
 
-    let statements = if lines.is_empty() {
-        vec![]
-    } else {
-        parse_statement_list(&mut lines.into_iter().peekable(), -1, "")?
-    };
+    #[test]
+    fn min_dbt_version() {
+        script_from_lines(&["#min-dbt-version 0.1", "run"]);
+        script_from_lines(&[&format!("#min-dbt-version {}", crate::VERSION), "run"]);
 
-    Ok(Script { statements })
-}
+        // The version check must take precedence over unknown directives
+        let error = parse_error(&["#future-directive", "#min-dbt-version 9999.0"]);
+        assert_eq!(error.line_number, LineNumber(4));
+        assert_eq!(error.column, 18);
+        assert!(error.message.contains("please upgrade dbt"));
 
-fn get_regex(regex_str: &Arc<str>) -> anyhow::Result<Arc<Regex>> {
-    lazy_static! {
-        static ref CACHE: Mutex<HashMap<Arc<str>, Arc<Regex>>> = Mutex::new(HashMap::default());
+        let error = parse_error(&["#min-dbt-version latest"]);
+        assert_eq!(error.column, 18);
+
+        let error = parse_error(&["#if @gdb", "  #min-dbt-version 0.1"]);
+        assert_eq!(error.column, 3);
     }
 
-    {
-        let mut cache = CACHE.lock().unwrap();
+    fn parse_error(lines: &[&str]) -> super::ParseError {
+        let mut script = String::new();
 
-        if let Some(cached) = cache.get(&regex_str[..]) {
-            return Ok(cached.clone());
-        }
+        writeln!(&mut script, "fn foo() {{}}").unwrap();
+        writeln!(&mut script, "{}", TOKEN_SCRIPT_START).unwrap();
 
-        let regex = Arc::new(Regex::new(regex_str)?);
+        for line in lines {
+            writeln!(&mut script, "{}", line).unwrap();
+        }
 
-        cache.insert(regex_str.clone(), regex.clone());
+        writeln!(&mut script, "{}", TOKEN_SCRIPT_END).unwrap();
 
-        Ok(regex)
+        parse_script(&script, Some(std::path::Path::new("src/main.rs")))
+            .unwrap_err()
+            .downcast::<super::ParseError>()
+            .unwrap()
     }
-}
-
-#[cfg(test)]
-mod tests {
-    use crate::script::{
-        parse_script, parse_statement_list, Comparison, LineKind, LineNumber, PhaseConfig,
-        Statement, Value, TOKEN_SCRIPT_END, TOKEN_SCRIPT_START,
-    };
-    use std::fmt::Write;
 
-    use super::{tokenize, Condition, EvaluationContext, Line, Script};
+    #[test]
+    fn parse_error_location() {
+        let error = parse_error(&["#if @gdb", "  #phase foo"]);
+        assert_eq!(error.line_number, LineNumber(4));
+        assert_eq!(error.column, 10);
+        assert_eq!(error.width, 3);
+        assert_eq!(error.source_line, "  #phase foo");
+
+        let error = parse_error(&["run", "#chekc abc // typo"]);
+        assert_eq!(error.line_number, LineNumber(4));
+        assert_eq!(error.column, 1);
+        assert_eq!(error.width, 6);
+        assert_eq!(error.message, "Encountered unknown keyword `#chekc`");
+
+        let error = parse_error(&["#if @gdb && version == 1", "  run"]);
+        assert_eq!(error.line_number, LineNumber(3));
+        assert_eq!(error.column, 13);
+        assert_eq!(error.width, 7);
+
+        let error = parse_error(&["#if @gdb ^^ @cdb", "  run"]);
+        assert_eq!(error.column, 10);
+        assert_eq!(error.width, 2);
+    }
 
     #[test]
-    fn parse_line() {
+    fn parse_check_num() {
         use super::parse_line;
+        use crate::regex_check::{NumericComparison, NumericConstraint};
 
         assert_eq!(
-            parse_line("#if @cdb", LineNumber(123)).unwrap(),
+            parse_line(r#"#check-num "len = {}" >= 0x10"#, LineNumber(1)).unwrap(),
             Line {
-                kind: LineKind::If {
-                    condition: Condition::DefinitionExists("@cdb".into()),
+                kind: LineKind::Check {
+                    check: RegexCheck::with_options(
+                        "len = {}",
+                        CheckOptions {
+                            numeric: Some(NumericConstraint {
+                                op: NumericComparison::GreaterThanOrEq,
+                                value: 16,
+                            }),
+                            ..Default::default()
+                        }
+                    )
+                    .unwrap(),
                 },
                 indent: 0,
-                line_number: LineNumber(123),
+                line_number: LineNumber(1),
             }
         );
 
-        assert_eq!(
-            parse_line("  #check abc def", LineNumber(123)).unwrap(),
-            Line {
-                kind: LineKind::Check {
-                    check: "abc def".into(),
-                },
-                indent: 2,
-                line_number: LineNumber(123),
-            }
-        );
+        let error = parse_error(&[r#"#check-num "len = {}" => 0"#]);
+        assert_eq!(error.column, 23);
+        assert_eq!(error.width, 2);
+        assert_eq!(error.message, "expected comparison operator, found `=>`");
 
-        assert_eq!(
-            parse_line("    foo bar quux", LineNumber(123)).unwrap(),
-            Line {
-                kind: LineKind::Raw {
-                    text: "foo bar quux".into(),
-                },
-                indent: 4,
-                line_number: LineNumber(123),
-            }
-        );
+        let error = parse_error(&[r#"#check-num "len = {}" > zero"#]);
+        assert_eq!(error.column, 25);
+        assert_eq!(error.message, "expected number, found `zero`");
 
-        assert_eq!(
-            parse_line("  #check-unordered", LineNumber(123)).unwrap(),
-            Line {
-                kind: LineKind::CheckUnordered,
-                indent: 2,
-                line_number: LineNumber(123),
-            }
-        );
+        let error = parse_error(&["#check-num len = {} > 0"]);
+        assert_eq!(error.column, 12);
     }
 
     #[test]
-    fn parse_condition() {
-        use super::parse_condition;
+    fn check_same_must_follow_check() {
+        // Parses fine after a regular check
+        script_from_lines(&["print x", "#check x", "#check-same 1"]);
+
+        let error = parse_error(&["print x", "#check-same 1"]);
+        assert_eq!(error.message, "#check-same must follow a #check statement");
+        assert_eq!(error.line_number, LineNumber(4));
+        assert_eq!(error.column, 1);
+        assert_eq!(error.width, 11);
+
+        let error = parse_error(&[
+            "print x",
+            "#check x",
+            "#check-not-between y",
+            "#check-same 1",
+        ]);
+        assert_eq!(error.line_number, LineNumber(6));
+    }
+
+    #[test]
+    fn parse_thread_directives() {
+        let script = script_from_lines(&["#thread 2", "#check-thread-count 3"]);
 
         assert_eq!(
-            parse_condition(&mut tokenize("@cdb").peekable()).unwrap(),
-            Condition::DefinitionExists("@cdb".into())
+            script.statements,
+            vec![
+                Statement::Command(DebuggerCommand::SelectThread(2), None, LineNumber::ANY),
+                Statement::CommandCheck(CommandCheck::ThreadCount(3), None, LineNumber::ANY),
+            ]
         );
+
+        let error = parse_error(&["#thread 0"]);
+        assert_eq!(error.message, "expected positive number, found `0`");
+        assert_eq!(error.column, 9);
+
+        let error = parse_error(&["#check-thread-count"]);
+        assert_eq!(error.message, "expected positive number, found nothing");
+
+        let script = script_from_lines(&["continue", "#expect-clean-exit"]);
         assert_eq!(
-            parse_condition(&mut tokenize("@cdb && @gdb").peekable()).unwrap(),
-            Condition::And(
-                Box::new(Condition::DefinitionExists("@cdb".into())),
-                Box::new(Condition::DefinitionExists("@gdb".into()))
-            )
+            script.statements[1],
+            Statement::CommandCheck(CommandCheck::CleanExit, None, LineNumber::ANY)
         );
+
+        let error = parse_error(&["#expect-clean-exit 0"]);
+        assert_eq!(error.message, "#expect-clean-exit does not take arguments");
+    }
+
+    #[test]
+    fn parse_check_locals() {
+        let script = script_from_lines(&["#check-locals exact", "  v", "  len = 3"]);
+
+        match &script.statements[0] {
+            Statement::CommandCheck(CommandCheck::Locals(locals, true), None, _) => {
+                assert_eq!(locals.len(), 2);
+                assert_eq!(&*locals[0].name, "v");
+                assert!(locals[0].value.is_none());
+                assert_eq!(&*locals[1].name, "len");
+                assert!(locals[1].value.as_ref().unwrap().check("0n3"));
+            }
+            statement => panic!("unexpected statement {:?}", statement),
+        }
+
+        let error = parse_error(&["#check-locals all", "  v"]);
+        assert_eq!(error.message, "expected `exact` or nothing, found `all`");
+
+        let error = parse_error(&["#check-locals", "  my var = 1"]);
+        assert_eq!(error.message, "expected a variable name, found `my var`");
+
+        let error = parse_error(&["#check-locals", "run"]);
+        assert_eq!(error.message, "Empty #check-locals block");
+    }
+
+    #[test]
+    fn parse_inline_script() {
+        let source = "\
+fn main() {
+    let x = 5;
+    zzz(); // #break
+    // #print x
+    // #check 5
+
+    zzz(); // #break: named
+    // #check ignored
+    let y = 7;
+    zzz(); // #break
+    // plain comment
+    // #check y
+    // #break
+}";
+        let breakpoints = crate::breakpoints::find(source);
+        let script = super::parse_inline_script(source, &breakpoints, None)
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(script.statements.len(), 8);
+        assert!(matches!(
+            &script.statements[4],
+            Statement::Command(DebuggerCommand::Print(expression), None, LineNumber(4))
+                if &**expression == "x"
+        ));
+        assert_eq!(script.statements[5].line_number(), LineNumber(5));
         assert_eq!(
-            parse_condition(&mut tokenize("@cdb && @version == 1.3.4").peekable()).unwrap(),
-            Condition::And(
-                Box::new(Condition::DefinitionExists("@cdb".into())),
-                Box::new(Condition::Comparison(
-                    "@version".into(),
-                    Comparison::Eq,
-                    "1.3.4".into()
-                ))
-            )
+            script.statements[6],
+            Statement::Command(DebuggerCommand::Continue, None, LineNumber(10))
         );
 
+        // The program is started with the command of each debugger
+        let start_commands = |values: &[(&str, &str)]| {
+            let mut commands = vec![];
+            script.walk_applicable_leaves(&context_from(values), &mut |statement| {
+                if let Statement::Exec(command, _, _) = statement {
+                    commands.push(command.to_string());
+                }
+                true
+            });
+            commands
+        };
+        assert_eq!(start_commands(&[("@cdb", "true")]), vec!["g"]);
+        assert_eq!(start_commands(&[("@gdb", "true")]), vec!["run"]);
         assert_eq!(
-            parse_condition(&mut tokenize("@version == 1.3.4 || @abc ~= 3.5").peekable()).unwrap(),
-            Condition::Or(
-                Box::new(Condition::Comparison(
-                    "@version".into(),
-                    Comparison::Eq,
-                    "1.3.4".into()
-                )),
-                Box::new(Condition::Comparison(
-                    "@abc".into(),
-                    Comparison::Matches,
-                    "3.5".into()
-                ))
-            )
+            start_commands(&[("@gdb", "true"), ("@embedded", "true")]),
+            vec!["continue"]
         );
 
-        assert_eq!(
-            parse_condition(&mut tokenize("@version == 1.3.4 && @gdb || @abc ~= 3.5").peekable())
-                .unwrap(),
-            Condition::And(
-                Box::new(Condition::Comparison(
-                    "@version".into(),
-                    Comparison::Eq,
-                    "1.3.4".into()
-                )),
-                Box::new(Condition::Or(
-                    Box::new(Condition::DefinitionExists("@gdb".into())),
-                    Box::new(Condition::Comparison(
-                        "@abc".into(),
-                        Comparison::Matches,
-                        "3.5".into()
-                    ))
-                ))
-            )
+        // Neither for tests with a script section nor without directives
+        let with_section = format!("/***\nrun\n***/\n{}", source);
+        assert!(
+            super::parse_inline_script(&with_section, &breakpoints, None)
+                .unwrap()
+                .is_none()
         );
+        let parse = |source: &str| {
+            super::parse_inline_script(source, &crate::breakpoints::find(source), None)
+        };
+
+        assert!(parse("fn main() {} // #break").unwrap().is_none());
+
+        let error = parse("zzz(); // #break\n  // #check-num x")
+            .unwrap_err()
+            .downcast::<super::ParseError>()
+            .unwrap();
+        assert_eq!(error.line_number, LineNumber(2));
+        assert_eq!(error.column, 17);
     }
 
-    fn script_from_lines(lines: &[&str]) -> Script {
-        let mut script = String::new();
+    #[test]
+    fn parse_check_variant() {
+        let script = script_from_lines(&[
+            "#check-variant opt == Option::Some(42)",
+            "#check-variant a == b == None",
+        ]);
 
-        writeln!(&mut script, "{}", TOKEN_SCRIPT_START).unwrap();
+        assert_eq!(
+            script.statements,
+            vec![
+                Statement::CommandCheck(
+                    CommandCheck::Variant("opt".into(), "Option::Some".into(), Some("42".into())),
+                    None,
+                    LineNumber::ANY
+                ),
+                Statement::CommandCheck(
+                    CommandCheck::Variant("a == b".into(), "None".into(), None),
+                    None,
+                    LineNumber::ANY
+                ),
+            ]
+        );
 
-        for line in lines {
-            writeln!(&mut script, "{}", line).unwrap();
-        }
+        let error = parse_error(&["#check-variant opt"]);
+        assert_eq!(
+            error.message,
+            "expected `<expression> == <variant>`, found `opt`"
+        );
 
-        writeln!(&mut script, "{}", TOKEN_SCRIPT_END).unwrap();
+        let error = parse_error(&["#check-variant opt == Some(42"]);
+        assert_eq!(error.message, "unbalanced parentheses");
 
-        parse_script(&script, None).unwrap()
+        let error = parse_error(&["#check-variant opt == 42 + 1"]);
+        assert_eq!(error.message, "expected an enum variant, found `42 + 1`");
     }
 
     #[test]
-    fn parse_program_body() {
-        use super::parse_line as line;
-
-        let lines = vec![
-            line("execute something", LineNumber(1)).unwrap(),
-            line("execute something else", LineNumber(2)).unwrap(),
-            line("#if @gdb", LineNumber(3)).unwrap(),
-            line("  execute gdb 1", LineNumber(4)).unwrap(),
-            line("  #if @version == 1", LineNumber(5)).unwrap(),
-            line("    #check abc", LineNumber(6)).unwrap(),
-            line("    #check def", LineNumber(7)).unwrap(),
-            line("    execute gdb 2", LineNumber(8)).unwrap(),
-            line("    #check ghi", LineNumber(9)).unwrap(),
-            line("    #check-unordered", LineNumber(10)).unwrap(),
-            line("      foo", LineNumber(11)).unwrap(),
-            line("      bar", LineNumber(12)).unwrap(),
-            line("    #check quux", LineNumber(13)).unwrap(),
-            line("  #if @version == 2", LineNumber(14)).unwrap(),
-            line("    execute gdb 3", LineNumber(15)).unwrap(),
-            line("    #check xyz", LineNumber(16)).unwrap(),
-        ];
+    fn parse_check_string() {
+        let script = script_from_lines(&[r#"#check-string s.as_str() == "say \"hi\"\n""#]);
 
-        let parsed = parse_statement_list(&mut lines.into_iter().peekable(), -1, "").unwrap();
+        assert_eq!(
+            script.statements[0],
+            Statement::CommandCheck(
+                CommandCheck::Str("s.as_str()".into(), "say \"hi\"\n".into()),
+                None,
+                LineNumber::ANY
+            )
+        );
 
+        let error = parse_error(&["#check-string s == hi"]);
         assert_eq!(
-            parsed,
-            vec![
-                Statement::Exec("execute something".into(), None, LineNumber(1)),
-                Statement::Exec("execute something else".into(), None, LineNumber(2)),
-                Statement::IfBlock(
-                    Condition::DefinitionExists("@gdb".into()),
-                    vec![
-                        Statement::Exec("execute gdb 1".into(), None, LineNumber(4)),
-                        Statement::IfBlock(
-                            Condition::Comparison("@version".into(), Comparison::Eq, "1".into()),
-                            vec![
-                                Statement::Check("abc".into(), None, LineNumber(6)),
-                                Statement::Check("def".into(), None, LineNumber(7)),
-                                Statement::Exec("execute gdb 2".into(), None, LineNumber(8)),
-                                Statement::Check("ghi".into(), None, LineNumber(9)),
-                                Statement::CheckUnorderedBlock(
-                                    vec!["foo".into(), "bar".into(),],
-                                    None,
-                                    LineNumber(10)
-                                ),
-                                Statement::Check("quux".into(), None, LineNumber(13)),
-                            ],
-                            LineNumber(5)
-                        ),
-                        Statement::IfBlock(
-                            Condition::Comparison("@version".into(), Comparison::Eq, "2".into()),
-                            vec![
-                                Statement::Exec("execute gdb 3".into(), None, LineNumber(15)),
-                                Statement::Check("xyz".into(), None, LineNumber(16)),
-                            ],
-                            LineNumber(14)
-                        ),
-                    ],
-                    LineNumber(3)
-                ),
-            ]
+            error.message,
+            "expected `<expression> == \"<string>\"`, found `s == hi`"
         );
-    }
 
-    fn context_from(values: &[(&str, &str)]) -> EvaluationContext {
-        EvaluationContext {
-            values: values
-                .into_iter()
-                .map(|(k, v)| (k.to_string(), (*v).into()))
-                .collect(),
+        let error = parse_error(&[r#"#check-string s == "hi"#]);
+        assert_eq!(error.message, "unterminated string");
+
+        let error = parse_error(&[r#"#check-string s == "h\i""#]);
+        assert_eq!(error.message, "unknown escape");
+        assert_eq!(error.column, 22);
+
+        let error = parse_error(&[r#"#check-string s == "h\é""#]);
+        assert_eq!(error.message, "unknown escape");
+        assert_eq!((error.column, error.width), (22, 2));
+
+        let script =
+            script_from_lines(&["#check-string s==\t\"hi\"", "#check-string s  ==   \"hi\""]);
+        for statement in &script.statements {
+            assert!(matches!(
+                statement,
+                Statement::CommandCheck(CommandCheck::Str(expression, contents), _, _)
+                    if &expression[..] == "s" && &contents[..] == "hi"
+            ));
         }
     }
 
     #[test]
-    fn walk_applicable_leaves() {
+    fn parse_check_type() {
         let script = script_from_lines(&[
-            "#if @cdb",
-            "  cdb x",
-            "  #if @version == 1.0",
-            "    cdb 1.0",
-            "  #if @version == 2.0",
-            "    cdb 2.0",
-            "#if @gdb",
-            "  gdb x",
-            "  #if @version == 1.0",
-            "    gdb 1.0",
-            "  #if @version == 2.0",
-            "    gdb 2.0",
-            "    #ignore-test",
-            "#if not @gdb",
-            "  not-gdb",
+            "#check-type Option<(i32, u8)>",
+            "  size 0x8",
+            "  field x offset 0 size 4",
+            "  field y",
         ]);
 
-        let collect_for_context = |ctx| {
-            let mut output = String::new();
+        assert_eq!(
+            script.statements[0],
+            Statement::CommandCheck(
+                CommandCheck::TypeLayout(TypeLayout {
+                    type_name: "Option<(i32, u8)>".into(),
+                    size: Some(8),
+                    fields: vec![
+                        FieldLayout {
+                            name: "x".into(),
+                            offset: Some(0),
+                            size: Some(4),
+                        },
+                        FieldLayout {
+                            name: "y".into(),
+                            offset: None,
+                            size: None,
+                        },
+                    ],
+                }),
+                None,
+                LineNumber::ANY
+            )
+        );
 
-            script.walk_applicable_leaves(&ctx, &mut |statement| {
-                if let Statement::Exec(command, _, _) = statement {
-                    write!(&mut output, "{};", command).unwrap();
-                }
-                if let Statement::IgnoreTest(_) = statement {
-                    write!(&mut output, "#ignore-test;").unwrap();
-                }
-                true
-            });
+        let error = parse_error(&["#check-type"]);
+        assert_eq!(error.message, "expected a type name, found nothing");
 
-            output
-        };
+        let error = parse_error(&["#check-type Point", "  field x offset"]);
+        assert_eq!(error.message, "expected a number after `offset`");
+
+        let error = parse_error(&["#check-type Point", "  x 0"]);
+        assert_eq!(error.message, "expected `size` or `field`, found `x 0`");
+    }
+
+    #[test]
+    fn parse_register_and_memory_checks() {
+        let script =
+            script_from_lines(&["#check-register RAX == 0x5", "#check-memory &x 3 01 ?? ff"]);
 
         assert_eq!(
-            collect_for_context(context_from(&[("@cdb", "true"), ("@version", "")])),
-            "cdb x;not-gdb;"
+            script.statements,
+            vec![
+                Statement::CommandCheck(
+                    CommandCheck::Register(
+                        "rax".into(),
+                        NumericConstraint {
+                            op: NumericComparison::Eq,
+                            value: 5
+                        }
+                    ),
+                    None,
+                    LineNumber::ANY
+                ),
+                Statement::CommandCheck(
+                    CommandCheck::Memory("&x".into(), vec![Some(0x01), None, Some(0xff)]),
+                    None,
+                    LineNumber::ANY
+                ),
+            ]
         );
+
+        let error = parse_error(&["#check-memory &x 2 01"]);
+        assert_eq!(error.message, "#check-memory expects 2 bytes, found 1");
+
+        let error = parse_error(&["#check-memory &x 1 0x1"]);
+        assert_eq!(error.message, "expected hex byte, found `0x1`");
+        assert_eq!(error.column, 20);
+    }
+
+    #[test]
+    fn parse_multi_process_directives() {
+        let script = script_from_lines(&["#follow-fork child", "run", "#inferior 2"]);
+
         assert_eq!(
-            collect_for_context(context_from(&[("@cdb", "true"), ("@version", "1.0")])),
-            "cdb x;cdb 1.0;not-gdb;"
+            script.statements,
+            vec![
+                Statement::Command(
+                    DebuggerCommand::FollowFork(ForkMode::Child),
+                    None,
+                    LineNumber::ANY
+                ),
+                Statement::Exec("run".into(), None, LineNumber::ANY),
+                Statement::Command(DebuggerCommand::SelectInferior(2), None, LineNumber::ANY),
+            ]
         );
+
+        let error = parse_error(&["#follow-fork both"]);
+        assert_eq!(error.message, "expected `parent or child`, found `both`");
+    }
+
+    #[test]
+    fn breakpoint_references() {
+        let script = script_from_lines(&["#break start", "#if @gdb", "  #break loop-body", "run"]);
+
         assert_eq!(
-            collect_for_context(context_from(&[("@cdb", "true"), ("@version", "2.0")])),
-            "cdb x;cdb 2.0;not-gdb;"
+            script.breakpoint_references(),
+            vec![
+                ("start".into(), LineNumber(2)),
+                ("loop-body".into(), LineNumber(4))
+            ]
         );
+    }
+
+    #[test]
+    fn parse_each_hit() {
+        let script = script_from_lines(&["#each-hit loop-body 2", "  print i", "  #check i"]);
+
+        let iteration = |hit| {
+            Statement::Group(
+                format!("loop-body hit {}", hit).into(),
+                vec![
+                    Statement::Sequence(LineNumber::ANY),
+                    Statement::Command(DebuggerCommand::Continue, None, LineNumber::ANY),
+                    Statement::Exec("print i".into(), None, LineNumber::ANY),
+                    Statement::Check(RegexCheck::new("i").unwrap(), None, LineNumber::ANY),
+                ],
+                LineNumber::ANY,
+            )
+        };
 
         assert_eq!(
-            collect_for_context(context_from(&[("@gdb", "true"), ("@version", "")])),
-            "gdb x;"
+            script.statements,
+            vec![Statement::EachHit(
+                "loop-body".into(),
+                2,
+                vec![
+                    Statement::SetBreakpoint("loop-body".into(), None, LineNumber::ANY),
+                    iteration(1),
+                    iteration(2),
+                ],
+                LineNumber::ANY,
+            )]
         );
+
         assert_eq!(
-            collect_for_context(context_from(&[("@gdb", "true"), ("@version", "1.0")])),
-            "gdb x;gdb 1.0;"
+            script.breakpoint_references(),
+            vec![("loop-body".into(), LineNumber(2))]
         );
+
+        let error = parse_error(&["#each-hit loop-body 0", "  print i"]);
+        assert_eq!(error.message, "expected positive number, found `0`");
+
+        let error = parse_error(&["#each-hit loop-body 3"]);
+        assert_eq!(error.message, "Empty #each-hit block");
+    }
+
+    #[test]
+    fn parse_frame_directives() {
+        let script = script_from_lines(&["#frame 0", "#up", "#down"]);
+
         assert_eq!(
-            collect_for_context(context_from(&[("@gdb", "true"), ("@version", "2.0")])),
-            "gdb x;gdb 2.0;#ignore-test;"
+            script.statements,
+            vec![
+                Statement::Command(DebuggerCommand::SelectFrame(0), None, LineNumber::ANY),
+                Statement::Command(DebuggerCommand::FrameUp, None, LineNumber::ANY),
+                Statement::Command(DebuggerCommand::FrameDown, None, LineNumber::ANY),
+            ]
         );
+
+        let error = parse_error(&["#frame -1"]);
+        assert_eq!(error.message, "expected number, found `-1`");
+
+        let error = parse_error(&["#up 2"]);
+        assert_eq!(error.message, "#up does not take arguments");
+        assert_eq!(error.column, 5);
     }
 
     #[test]
-    fn comparisons() {
-        let ctx = &context_from(&[("x", "17")]);
-        assert!(Condition::Comparison("x".into(), Comparison::Eq, "17".into()).eval(ctx));
-        assert!(!Condition::Comparison("x".into(), Comparison::Eq, "0".into()).eval(ctx));
+    fn parse_reverse_directives() {
+        let script = script_from_lines(&[
+            "#phase ttd",
+            "#reverse-step",
+            "#reverse-step-into",
+            "#reverse-continue",
+            "#reverse-finish",
+        ]);
 
-        assert!(!Condition::Comparison("x".into(), Comparison::NotEq, "17".into()).eval(ctx));
-        assert!(Condition::Comparison("x".into(), Comparison::NotEq, "0".into()).eval(ctx));
+        assert_eq!(
+            script.statements,
+            vec![
+                Statement::Phase(PhaseConfig::Ttd, LineNumber::ANY),
+                Statement::Command(DebuggerCommand::ReverseStep, None, LineNumber::ANY),
+                Statement::Command(DebuggerCommand::ReverseStepInto, None, LineNumber::ANY),
+                Statement::Command(DebuggerCommand::ReverseContinue, None, LineNumber::ANY),
+                Statement::Command(DebuggerCommand::ReverseFinish, None, LineNumber::ANY),
+            ]
+        );
 
-        assert!(!Condition::Comparison("x".into(), Comparison::LessThan, "17".into()).eval(ctx));
-        assert!(Condition::Comparison("x".into(), Comparison::LessThan, "18".into()).eval(ctx));
+        let error = parse_error(&["#reverse-continue 2"]);
+        assert_eq!(error.message, "#reverse-continue does not take arguments");
+    }
 
-        assert!(
-            !Condition::Comparison("x".into(), Comparison::LessThanOrEq, "16".into()).eval(ctx)
-        );
-        assert!(Condition::Comparison("x".into(), Comparison::LessThanOrEq, "17".into()).eval(ctx));
-        assert!(Condition::Comparison("x".into(), Comparison::LessThanOrEq, "18".into()).eval(ctx));
+    #[test]
+    fn parse_watch() {
+        let script = script_from_lines(&[
+            "#watch counter",
+            "#watch read 4 state.counter",
+            "#watch access self.buf[2]",
+        ]);
 
-        assert!(!Condition::Comparison("x".into(), Comparison::GreaterThan, "17".into()).eval(ctx));
-        assert!(Condition::Comparison("x".into(), Comparison::GreaterThan, "16".into()).eval(ctx));
+        let watch = |access, width, place: &str| {
+            Statement::Command(
+                DebuggerCommand::Watch(Watchpoint {
+                    access,
+                    width,
+                    place: place.into(),
+                }),
+                None,
+                LineNumber::ANY,
+            )
+        };
 
-        assert!(
-            !Condition::Comparison("x".into(), Comparison::GreaterThanOrEq, "18".into()).eval(ctx)
-        );
-        assert!(
-            Condition::Comparison("x".into(), Comparison::GreaterThanOrEq, "17".into()).eval(ctx)
+        assert_eq!(
+            script.statements,
+            vec![
+                watch(WatchAccess::Write, None, "counter"),
+                watch(WatchAccess::Read, Some(4), "state.counter"),
+                watch(WatchAccess::Access, None, "self.buf[2]"),
+            ]
         );
-        assert!(
-            Condition::Comparison("x".into(), Comparison::GreaterThanOrEq, "16".into()).eval(ctx)
+
+        let error = parse_error(&["#watch write 3 counter"]);
+        assert_eq!(
+            error.message,
+            "expected a width of 1, 2, 4 or 8 bytes, found `3`"
         );
+        assert_eq!(error.column, 14);
 
-        assert!(Condition::Comparison("x".into(), Comparison::Contains, "1".into()).eval(ctx));
-        assert!(Condition::Comparison("x".into(), Comparison::Contains, "7".into()).eval(ctx));
-        assert!(Condition::Comparison("x".into(), Comparison::Contains, "17".into()).eval(ctx));
-        assert!(!Condition::Comparison("x".into(), Comparison::Contains, "2".into()).eval(ctx));
+        let error = parse_error(&["#watch"]);
+        assert_eq!(error.message, "expected a place to watch, found nothing");
+    }
 
-        assert!(Condition::Comparison("x".into(), Comparison::Matches, r"\d\d".into()).eval(ctx));
-        assert!(Condition::Comparison("x".into(), Comparison::Matches, r"1.".into()).eval(ctx));
-        assert!(Condition::Comparison("x".into(), Comparison::Matches, r".7".into()).eval(ctx));
-        assert!(!Condition::Comparison("x".into(), Comparison::Matches, r"\s".into()).eval(ctx));
+    #[test]
+    fn parse_print() {
+        let script = script_from_lines(&["#print v.len() // comment", "#check 3"]);
+
+        assert_eq!(
+            script.statements[0],
+            Statement::Command(
+                DebuggerCommand::Print("v.len()".into()),
+                None,
+                LineNumber::ANY
+            )
+        );
+
+        let error = parse_error(&["#print"]);
+        assert_eq!(
+            error.message,
+            "expected an expression to print, found nothing"
+        );
     }
 
     #[test]
-    fn value_new() {
+    fn parse_check_program_output() {
+        let script = script_from_lines(&[
+            "run",
+            "#check-program-output Hello, @{ \\w+ }@!",
+            "#if @gdb",
+            "  #check-program-output done",
+        ]);
+
+        let checks = script.program_output_checks(&context_from(&[]));
+        assert_eq!(checks.len(), 1);
+        assert_eq!(checks[0].1, LineNumber(3));
+        assert!(checks[0].0.check("Hello, world!"));
+        assert!(script.has_active_checks(&context_from(&[])));
+
         assert_eq!(
-            Value::new("abc"),
-            Value {
-                string: "abc".into(),
-                version: None,
-            }
+            script
+                .program_output_checks(&context_from(&[("@gdb", "true")]))
+                .len(),
+            2
         );
 
+        let error = parse_error(&["#check-program-output"]);
         assert_eq!(
-            Value::new("123"),
-            Value {
-                string: "123".into(),
-                version: Some(vec![123].into()),
-            }
+            error.message,
+            "expected output specification, found nothing"
         );
+    }
+
+    #[test]
+    fn parse_static_checks() {
+        let script = script_from_lines(&[
+            "#dwarfdump-check --name=Foo --show-children",
+            "  DW_TAG_structure_type",
+            "  DW_AT_byte_size @{ .* }@",
+        ]);
 
         assert_eq!(
-            Value::new("123."),
-            Value {
-                string: "123.".into(),
-                version: None,
-            }
+            script.statements,
+            vec![Statement::StaticCheck(
+                StaticCheck {
+                    tool: StaticTool::Dwarfdump,
+                    args: vec!["--name=Foo".into(), "--show-children".into()],
+                    checks: vec![
+                        RegexCheck::new("DW_TAG_structure_type").unwrap(),
+                        RegexCheck::new("DW_AT_byte_size @{ .* }@").unwrap(),
+                    ],
+                },
+                LineNumber::ANY
+            )]
         );
 
+        let error = parse_error(&["#dwarfdump-check --name=Foo"]);
+        assert_eq!(error.message, "Empty #dwarfdump-check block");
+
+        let script = script_from_lines(&["#pdb-check dump --types", "  LF_STRUCTURE"]);
+        assert!(matches!(
+            &script.statements[0],
+            Statement::StaticCheck(
+                StaticCheck {
+                    tool: StaticTool::Pdbutil,
+                    ..
+                },
+                _
+            )
+        ));
+
+        let error = parse_error(&["#dwarfdump-check", "  #check x"]);
         assert_eq!(
-            Value::new("1.2.3"),
-            Value {
-                string: "1.2.3".into(),
-                version: Some(vec![1, 2, 3].into()),
-            }
+            error.message,
+            "#dwarfdump-check cannot have nested statements"
         );
+    }
 
+    #[test]
+    fn parse_object_checks() {
         assert_eq!(
-            Value::new("01.0002.003"),
-            Value {
-                string: "01.0002.003".into(),
-                version: Some(vec![1, 2, 3].into()),
+            super::parse_line("#check-section .debug_info compressed", LineNumber(1))
+                .unwrap()
+                .kind,
+            LineKind::ObjectCheck {
+                check: ObjectCheck::Section(
+                    ".debug_info".into(),
+                    Some(SectionCompression::Compressed)
+                )
             }
         );
-
         assert_eq!(
-            Value::new("1.x.3"),
-            Value {
-                string: "1.x.3".into(),
-                version: None,
+            super::parse_line("#check-section .debug_line", LineNumber(1))
+                .unwrap()
+                .kind,
+            LineKind::ObjectCheck {
+                check: ObjectCheck::Section(".debug_line".into(), None)
+            }
+        );
+        assert_eq!(
+            super::parse_line("#check-symbol my_crate::@{ .* }@::foo", LineNumber(1))
+                .unwrap()
+                .kind,
+            LineKind::ObjectCheck {
+                check: ObjectCheck::Symbol(
+                    RegexCheck::with_options(
+                        "my_crate::@{ .* }@::foo",
+                        CheckOptions {
+                            anchored: true,
+                            ..Default::default()
+                        }
+                    )
+                    .unwrap()
+                )
             }
         );
 
-        assert_eq!(Value::new("1.0.0"), Value::new("1.0"),);
-
-        assert_eq!(Value::new("1.0.0"), Value::new("1"),);
+        assert!(super::parse_line("#check-section", LineNumber(1)).is_err());
+        assert!(super::parse_line("#check-section .debug_info zlib", LineNumber(1)).is_err());
+        assert!(super::parse_line("#check-symbol", LineNumber(1)).is_err());
     }
 
     #[test]
-    fn value_compare() {
-        assert!(Value::new("01") == Value::new("1"));
-        assert!(Value::new("_02") < Value::new("_1"));
-        assert!(Value::new("2.0") > Value::new("1.1"));
-        assert!(Value::new("1.2.3") > Value::new("1.01.3"));
-        assert!(Value::new("1.0.1") >= Value::new("1.0.0"));
+    fn parse_error_block_structure() {
+        let error = parse_error(&["  #if @gdb", "  run"]);
+        assert_eq!(error.message, "Empty #if block");
+        assert_eq!(error.line_number, LineNumber(3));
+        assert_eq!(error.column, 3);
+        assert_eq!(error.width, 3);
+
+        let error = parse_error(&["#check-unordered", "  #check abc // comment"]);
+        assert_eq!(error.line_number, LineNumber(4));
+        assert_eq!(error.column, 3);
+        assert_eq!(error.width, 10);
+
+        let error = parse_error(&["  run", "print x"]);
+        assert_eq!(error.line_number, LineNumber(4));
+        assert_eq!(error.column, 1);
     }
 
     #[test]
-    fn parse_phase() {
-        assert_eq!(
-            super::parse_line("#phase live", LineNumber(77)).unwrap(),
-            Line {
-                kind: LineKind::Phase {
-                    phase_config: PhaseConfig::Live
-                },
-                indent: 0,
-                line_number: LineNumber(77),
-            }
-        );
+    fn parse_error_display() {
+        let error = parse_error(&["#if @gdb", "  #phase foo"]);
 
         assert_eq!(
-            super::parse_line("#phase crashdump", LineNumber(77)).unwrap(),
-            Line {
-                kind: LineKind::Phase {
-                    phase_config: PhaseConfig::CrashDump {
-                        tag: "default".into()
-                    }
-                },
-                indent: 0,
-                line_number: LineNumber(77)
-            }
+            error.to_string(),
+            "error: expected `live or crashdump or ttd`, found `foo`\n \
+             --> src/main.rs:4:10\n  \
+             |\n\
+             4 |   #phase foo\n  \
+             |          ^^^\n"
         );
+    }
+
+    #[test]
+    fn script_builder() {
+        let script = ScriptBuilder::new()
+            .exec("break main.rs:10")
+            .if_block("@gdb && @version >= 12", |script| {
+                script.exec("print x").check("$1 = 5")
+            })
+            .group("tail", |script| script.sequence())
+            .command(DebuggerCommand::Continue)
+            .build()
+            .unwrap();
 
         assert_eq!(
-            super::parse_line("#phase crashdump xyz", LineNumber(77)).unwrap(),
-            Line {
-                kind: LineKind::Phase {
-                    phase_config: PhaseConfig::CrashDump { tag: "xyz".into() }
-                },
-                indent: 0,
-                line_number: LineNumber(77),
-            }
+            script.statements,
+            vec![
+                Statement::Exec("break main.rs:10".to_string(), None, LineNumber(1)),
+                Statement::IfBlock(
+                    Condition::And(
+                        Box::new(Condition::DefinitionExists("@gdb".to_string())),
+                        Box::new(Condition::Comparison(
+                            "@version".to_string(),
+                            Comparison::GreaterThanOrEq,
+                            Value::from("12"),
+                        )),
+                    ),
+                    vec![
+                        Statement::Exec("print x".to_string(), None, LineNumber(3)),
+                        Statement::Check(RegexCheck::new("$1 = 5").unwrap(), None, LineNumber(4)),
+                    ],
+                    LineNumber(2),
+                ),
+                Statement::Group(
+                    "tail".into(),
+                    vec![Statement::Sequence(LineNumber(6))],
+                    LineNumber(5)
+                ),
+                Statement::Command(DebuggerCommand::Continue, None, LineNumber(7)),
+            ]
         );
+
+        let error = ScriptBuilder::new()
+            .exec("run")
+            .if_block("@gdb", |script| script.check("x @{ ( }@"))
+            .build()
+            .unwrap_err();
+        assert!(error.to_string().starts_with("statement 3"), "{:?}", error);
+
+        let same_line = CheckOptions {
+            same_line: true,
+            ..Default::default()
+        };
+        assert!(ScriptBuilder::new()
+            .check_with_options("x", same_line)
+            .build()
+            .is_err());
     }
 }
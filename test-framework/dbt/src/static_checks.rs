@@ -0,0 +1,390 @@
+//! Checks against the output of tools that inspect the debuginfo of the debuggee without
+//! running it (`#dwarfdump-check`, `#pdb-check`), and against the sections and symbols of the
+//! debuggee itself (`#check-section`, `#check-symbol`). These don't need a debugger at all,
+//! which makes them a good fit for structural properties like DW_AT entries, CodeView records,
+//! type layouts or whether the debuginfo survived stripping and compression.
+
+use std::{
+    collections::HashMap,
+    ffi::{OsStr, OsString},
+    fmt::Write,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::Arc,
+};
+
+use object::{CompressionFormat, Object, ObjectSection, ObjectSymbol};
+
+use crate::{
+    cargo_test_directory::TestDefinition,
+    debugger::{DebuggerExitStatus, DebuggerOutput},
+    prettify_path,
+    regex_check::RegexCheck,
+    script::{EvaluationContext, ObjectCheck, SectionCompression, StaticCheck, StaticTool},
+    test_result::Status,
+};
+
+/// The commands used to run each tool
+#[derive(Debug, Clone)]
+pub struct StaticTools {
+    /// `--dwarfdump`
+    pub dwarfdump: OsString,
+    /// `--pdbutil`, which can also be `cvdump`
+    pub pdbutil: OsString,
+}
+
+impl Default for StaticTools {
+    fn default() -> Self {
+        StaticTools {
+            dwarfdump: "llvm-dwarfdump".into(),
+            pdbutil: "llvm-pdbutil".into(),
+        }
+    }
+}
+
+impl StaticTools {
+    fn command(&self, tool: StaticTool) -> &OsStr {
+        match tool {
+            StaticTool::Dwarfdump => &self.dwarfdump,
+            StaticTool::Pdbutil => &self.pdbutil,
+        }
+    }
+}
+
+/// The file `tool` inspects for `debuggee`
+fn tool_input(tool: StaticTool, debuggee: &Path) -> PathBuf {
+    match tool {
+        StaticTool::Dwarfdump => debuggee.to_path_buf(),
+        StaticTool::Pdbutil => pdb_path(debuggee),
+    }
+}
+
+/// Cargo names the PDB after the crate, i.e. with underscores instead of dashes
+fn pdb_path(debuggee: &Path) -> PathBuf {
+    let pdb = debuggee.with_extension("pdb");
+
+    if pdb.exists() {
+        return pdb;
+    }
+
+    match debuggee.file_stem() {
+        Some(stem) => {
+            debuggee.with_file_name(format!("{}.pdb", stem.to_string_lossy().replace('-', "_")))
+        }
+        None => pdb,
+    }
+}
+
+/// Runs the static checks of `test_definition` applicable in `context` against `debuggee`
+/// (or its PDB).
+/// Returns the status of the first one that doesn't pass, if any.
+pub fn run_static_checks(
+    test_definition: &TestDefinition,
+    context: &EvaluationContext,
+    debuggee: &Path,
+    tools: &StaticTools,
+) -> Option<Status> {
+    // Several checks often inspect the output of the same invocation
+    let mut outputs: HashMap<(StaticTool, Vec<Arc<str>>), DebuggerOutput> = HashMap::new();
+
+    for (static_check, line_number) in test_definition.script.static_checks(context) {
+        let command = tools.command(static_check.tool);
+        let input = tool_input(static_check.tool, debuggee);
+
+        let output = match outputs.get(&(static_check.tool, static_check.args.clone())) {
+            Some(output) => output.clone(),
+            None => {
+                let output = match Command::new(command)
+                    .args(static_check.args.iter().map(|arg| &arg[..]))
+                    .arg(&input)
+                    .output()
+                {
+                    Ok(output) => DebuggerOutput {
+                        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+                        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+                        exit_status: if output.status.success() {
+                            DebuggerExitStatus::Success
+                        } else {
+                            DebuggerExitStatus::Failure
+                        },
+                        program_output: None,
+                    },
+                    Err(e) => {
+                        return Some(Status::Errored(format!(
+                            "Could not run `{}` for {}: {}\n",
+                            command.to_string_lossy(),
+                            static_check.tool.keyword(),
+                            e
+                        )));
+                    }
+                };
+
+                outputs.insert(
+                    (static_check.tool, static_check.args.clone()),
+                    output.clone(),
+                );
+                output
+            }
+        };
+
+        let invocation = format!(
+            "{} {}",
+            command.to_string_lossy(),
+            static_check
+                .args
+                .iter()
+                .map(|arg| &arg[..])
+                .chain([&prettify_path(&input)[..]])
+                .collect::<Vec<_>>()
+                .join(" ")
+        );
+
+        if !output.exit_status.success() {
+            return Some(Status::Errored(format!(
+                "`{}` failed:\n{}",
+                invocation, output.stderr
+            )));
+        }
+
+        if let Some(check) = first_unmatched_check(&static_check, &output.stdout) {
+            let mut message = String::new();
+            writeln!(
+                message,
+                "Check failed at: {}:{}",
+                prettify_path(&test_definition.absolute_source_path),
+                line_number.0
+            )
+            .unwrap();
+            writeln!(
+                message,
+                "Could not find `{}` in the output of `{}`",
+                check.source, invocation
+            )
+            .unwrap();
+
+            return Some(Status::Failed(message, output));
+        }
+    }
+
+    run_object_checks(test_definition, context, debuggee)
+}
+
+/// Runs the `#check-section` and `#check-symbol` statements applicable in `context` against
+/// `debuggee`. Returns the status of the first one that doesn't pass, if any.
+fn run_object_checks(
+    test_definition: &TestDefinition,
+    context: &EvaluationContext,
+    debuggee: &Path,
+) -> Option<Status> {
+    let object_checks = test_definition.script.object_checks(context);
+
+    if object_checks.is_empty() {
+        return None;
+    }
+
+    let data = match std::fs::read(debuggee) {
+        Ok(data) => data,
+        Err(e) => {
+            return Some(Status::Errored(format!(
+                "Could not read `{}`: {}\n",
+                prettify_path(debuggee),
+                e
+            )))
+        }
+    };
+
+    let file = match object::File::parse(&*data) {
+        Ok(file) => file,
+        Err(e) => {
+            return Some(Status::Errored(format!(
+                "Could not parse `{}`: {}\n",
+                prettify_path(debuggee),
+                e
+            )))
+        }
+    };
+
+    for (object_check, line_number) in object_checks {
+        if let Some(failure) = check_object(&file, &object_check) {
+            let mut message = String::new();
+            writeln!(
+                message,
+                "Check failed at: {}:{}",
+                prettify_path(&test_definition.absolute_source_path),
+                line_number.0
+            )
+            .unwrap();
+            writeln!(message, "{}", failure).unwrap();
+            writeln!(message, "Debuggee: {}", prettify_path(debuggee)).unwrap();
+
+            return Some(Status::Failed(
+                message,
+                DebuggerOutput {
+                    stdout: String::new(),
+                    stderr: String::new(),
+                    exit_status: DebuggerExitStatus::Success,
+                    program_output: None,
+                },
+            ));
+        }
+    }
+
+    None
+}
+
+/// Returns why `object_check` doesn't hold for `file`, if it doesn't
+fn check_object(file: &object::File, object_check: &ObjectCheck) -> Option<String> {
+    match object_check {
+        ObjectCheck::Section(name, compression) => {
+            // For Mach-O, `object` maps e.g. `.debug_info` to `__debug_info`
+            let section = match file.section_by_name(name) {
+                Some(section) => section,
+                None => return Some(format!("Could not find section `{}`", name)),
+            };
+
+            let expected = match compression {
+                Some(expected) => *expected,
+                None => return None,
+            };
+
+            let actual = match section.compressed_file_range() {
+                Ok(range) if range.format != CompressionFormat::None => {
+                    SectionCompression::Compressed
+                }
+                Ok(_) => SectionCompression::Uncompressed,
+                Err(e) => return Some(format!("Could not read section `{}`: {}", name, e)),
+            };
+
+            if actual != expected {
+                return Some(format!(
+                    "Section `{}` is {}, expected it to be {}",
+                    name, actual, expected
+                ));
+            }
+
+            None
+        }
+        ObjectCheck::Symbol(check) => {
+            let found = file
+                .symbols()
+                .chain(file.dynamic_symbols())
+                .filter_map(|symbol| symbol.name().ok())
+                .any(|name| symbol_matches(check, name));
+
+            if found {
+                None
+            } else {
+                Some(format!(
+                    "Could not find a symbol matching `{}`",
+                    check.source
+                ))
+            }
+        }
+    }
+}
+
+/// Whether `check` matches the mangled or demangled (without hash) name of a symbol
+fn symbol_matches(check: &RegexCheck, name: &str) -> bool {
+    check.check(name) || check.check(&format!("{:#}", rustc_demangle::demangle(name)))
+}
+
+/// Matches the checks against the lines of `output` in order and returns the first one that
+/// could not be found
+fn first_unmatched_check<'c>(
+    static_check: &'c StaticCheck,
+    output: &str,
+) -> Option<&'c RegexCheck> {
+    // llvm-dwarfdump separates attributes from their values with single tabs, which regular
+    // checks don't treat as a space
+    let mut lines = output.lines().map(|line| line.replace('\t', " "));
+
+    static_check
+        .checks
+        .iter()
+        .find(|check| !lines.any(|line| check.check(&line)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::{
+        regex_check::{CheckOptions, RegexCheck},
+        script::{ObjectCheck, StaticCheck, StaticTool},
+    };
+
+    #[test]
+    fn pdb_path() {
+        assert_eq!(
+            super::pdb_path(Path::new("target/debug/my-tests.exe")),
+            Path::new("target/debug/my_tests.pdb")
+        );
+    }
+
+    #[test]
+    fn checks_are_matched_in_order() {
+        let static_check = StaticCheck {
+            tool: StaticTool::Dwarfdump,
+            args: vec!["--name=Foo".into()],
+            checks: vec![
+                RegexCheck::new("DW_TAG_structure_type").unwrap(),
+                RegexCheck::new("DW_AT_byte_size (0x10)").unwrap(),
+            ],
+        };
+
+        let output = "0x0000002a:   DW_TAG_structure_type\n                DW_AT_name (\"Foo\")\n                DW_AT_byte_size (0x10)\n";
+        assert_eq!(super::first_unmatched_check(&static_check, output), None);
+
+        let output =
+            "                DW_AT_byte_size (0x10)\n0x0000002a:   DW_TAG_structure_type\n";
+        assert_eq!(
+            super::first_unmatched_check(&static_check, output),
+            Some(&static_check.checks[1])
+        );
+
+        let output = "0x0000002a:\tDW_TAG_structure_type\n\tDW_AT_byte_size\t(0x10)\n";
+        assert_eq!(super::first_unmatched_check(&static_check, output), None);
+    }
+
+    #[test]
+    fn symbol_matches() {
+        let check = |spec: &str| {
+            RegexCheck::with_options(
+                spec,
+                CheckOptions {
+                    anchored: true,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+        };
+
+        let mangled = "_ZN7my_test3foo17h0123456789abcdefE";
+
+        assert!(super::symbol_matches(&check("my_test::foo"), mangled));
+        assert!(super::symbol_matches(&check(mangled), mangled));
+        assert!(!super::symbol_matches(&check("foo"), mangled));
+        assert!(!super::symbol_matches(
+            &check("my_test::foo"),
+            "my_test::foo_bar"
+        ));
+    }
+
+    #[test]
+    fn check_sections_of_own_executable() {
+        let data = std::fs::read(std::env::current_exe().unwrap()).unwrap();
+        let file = object::File::parse(&*data).unwrap();
+
+        assert_eq!(
+            super::check_object(&file, &ObjectCheck::Section(".text".into(), None)),
+            None
+        );
+        assert_eq!(
+            super::check_object(
+                &file,
+                &ObjectCheck::Section(".no_such_section".into(), None)
+            ),
+            Some("Could not find section `.no_such_section`".to_string())
+        );
+    }
+}
@@ -0,0 +1,248 @@
+//! The support crate (`dbt-support` in this repository), which test programs can use as
+//! `dbt_support` for common helpers like breakpoint anchors and types to inspect, if dbt is
+//! given `--support-crate`. Cargo has no way of adding a dependency to a package from the
+//! outside, so dbt compiles the crate with `rustc` itself and passes it to the compilation of
+//! the test workspace via `--extern dbt_support=...` in the rustflags. As that replaces the
+//! rustflags of `.cargo/config.toml` files, it is opt-in.
+
+use std::{
+    ffi::{OsStr, OsString},
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{bail, Context};
+
+use crate::{build_variants, cargo_test_directory::BuildConfig, prettify_path};
+
+pub const CRATE_NAME: &str = "dbt_support";
+
+const SOURCE: &str = include_str!("../../dbt-support/src/lib.rs");
+
+/// Compiles the support crate for `build` and `cargo_profile`, whose `opt-level` is
+/// `opt_level`, into `directory`, unless it is already there, returning the path of the rlib. `rustc` is run in `workspace_dir`, so
+/// that it is the same toolchain Cargo uses for the test workspace.
+pub fn compile(
+    workspace_dir: &Path,
+    directory: &Path,
+    build: &BuildConfig,
+    cargo_profile: &str,
+    opt_level: &str,
+) -> anyhow::Result<PathBuf> {
+    std::fs::create_dir_all(directory)?;
+
+    let source_path = directory.join("lib.rs");
+    let rlib_path = directory.join(format!("lib{}.rlib", CRATE_NAME));
+    let stamp_path = directory.join("stamp");
+
+    let mut command = compile_command(
+        &source_path,
+        directory,
+        build,
+        cargo_profile,
+        opt_level,
+        &environment_rustflags(),
+    );
+    command.current_dir(workspace_dir);
+
+    // A different toolchain or different flags need a rebuild, or the rlib can't be linked
+    let stamp = stamp(workspace_dir, build, &command)?;
+
+    let up_to_date = rlib_path.exists()
+        && std::fs::read_to_string(&source_path).is_ok_and(|source| source == SOURCE)
+        && std::fs::read_to_string(&stamp_path).is_ok_and(|old_stamp| old_stamp == stamp);
+
+    if up_to_date {
+        return Ok(rlib_path);
+    }
+
+    std::fs::write(&source_path, SOURCE)?;
+
+    let output = command
+        .output()
+        .context("Could not run `rustc` to compile the support crate")?;
+
+    if !output.status.success() {
+        bail!(
+            "Could not compile the support crate at {}:\n{}",
+            prettify_path(&source_path),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    std::fs::write(&stamp_path, stamp)?;
+
+    Ok(rlib_path)
+}
+
+/// What the rlib depends on besides the source: the output of `rustc -vV` and the arguments
+/// it is compiled with
+fn stamp(workspace_dir: &Path, build: &BuildConfig, command: &Command) -> anyhow::Result<String> {
+    let mut version_command = Command::new("rustc");
+
+    if let Some(toolchain) = &build.toolchain {
+        version_command.arg(format!("+{}", toolchain));
+    }
+
+    let output = version_command
+        .arg("-vV")
+        .current_dir(workspace_dir)
+        .output()
+        .context("Could not run `rustc -vV`")?;
+
+    if !output.status.success() {
+        bail!(
+            "`rustc -vV` failed:\n{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    let mut stamp = String::from_utf8_lossy(&output.stdout).into_owned();
+
+    for arg in command.get_args() {
+        stamp.push_str(&arg.to_string_lossy());
+        stamp.push('\n');
+    }
+
+    Ok(stamp)
+}
+
+/// The `rustc` command compiling the support crate. `rustflags` (encoded like
+/// `CARGO_ENCODED_RUSTFLAGS`) are the ones the test programs get, so that the rlib is
+/// compatible with them.
+fn compile_command(
+    source_path: &Path,
+    out_dir: &Path,
+    build: &BuildConfig,
+    cargo_profile: &str,
+    opt_level: &str,
+    rustflags: &OsStr,
+) -> Command {
+    let mut command = Command::new("rustc");
+
+    if let Some(toolchain) = &build.toolchain {
+        command.arg(format!("+{}", toolchain));
+    }
+
+    command
+        .arg("--crate-name")
+        .arg(CRATE_NAME)
+        .arg("--crate-type=rlib")
+        .arg("--edition=2021")
+        .arg(format!(
+            "-Cdebuginfo={}",
+            build_variants::rustc_debuginfo(cargo_profile)
+        ));
+
+    if opt_level != "0" {
+        command.arg(format!("-Copt-level={}", opt_level));
+    }
+
+    if let Some(target) = &build.target {
+        command.arg("--target").arg(&target[..]);
+    }
+
+    if !rustflags.is_empty() {
+        command.args(rustflags.to_string_lossy().split('\x1f'));
+    }
+
+    command.arg("--out-dir").arg(out_dir).arg(source_path);
+
+    command
+}
+
+/// The value for `CARGO_ENCODED_RUSTFLAGS` that makes the support crate at `rlib_path`
+/// available to the test programs, in addition to the flags given via the environment
+pub fn encoded_rustflags(rlib_path: &Path) -> OsString {
+    with_extern(environment_rustflags(), rlib_path)
+}
+
+/// The flags given via the environment, encoded like `CARGO_ENCODED_RUSTFLAGS`, to which
+/// dbt adds its own
+pub fn environment_rustflags() -> OsString {
+    // Cargo only looks at `RUSTFLAGS` if `CARGO_ENCODED_RUSTFLAGS` is not set
+    match std::env::var_os("CARGO_ENCODED_RUSTFLAGS") {
+        Some(flags) => flags,
+        None => encode_rustflags(&std::env::var("RUSTFLAGS").unwrap_or_default()),
+    }
+}
+
+/// Encodes whitespace-separated `flags` like `CARGO_ENCODED_RUSTFLAGS`
+fn encode_rustflags(flags: &str) -> OsString {
+    flags
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("\x1f")
+        .into()
+}
+
+fn with_extern(mut flags: OsString, rlib_path: &Path) -> OsString {
+    if !flags.is_empty() {
+        flags.push("\x1f");
+    }
+
+    flags.push(format!("--extern\x1f{}=", CRATE_NAME));
+    flags.push(rlib_path);
+
+    flags
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        ffi::{OsStr, OsString},
+        path::Path,
+    };
+
+    use crate::cargo_test_directory::BuildConfig;
+
+    #[test]
+    fn compile_command() {
+        let command = super::compile_command(
+            Path::new("target/dbt-support/release/lib.rs"),
+            Path::new("target/dbt-support/release"),
+            &BuildConfig {
+                toolchain: Some("nightly".into()),
+                target: Some("i686-pc-windows-msvc".into()),
+            },
+            "release",
+            "3",
+            OsStr::new("-Ctarget-cpu=native\x1f--cfg\x1ftesting"),
+        );
+
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            vec![
+                "+nightly",
+                "--crate-name",
+                "dbt_support",
+                "--crate-type=rlib",
+                "--edition=2021",
+                "-Cdebuginfo=2",
+                "-Copt-level=3",
+                "--target",
+                "i686-pc-windows-msvc",
+                "-Ctarget-cpu=native",
+                "--cfg",
+                "testing",
+                "--out-dir",
+                "target/dbt-support/release",
+                "target/dbt-support/release/lib.rs"
+            ]
+        );
+    }
+
+    #[test]
+    fn encoded_rustflags() {
+        let rlib = Path::new("/t/libdbt_support.rlib");
+
+        assert_eq!(
+            super::with_extern(OsString::new(), rlib),
+            "--extern\x1fdbt_support=/t/libdbt_support.rlib"
+        );
+        assert_eq!(
+            super::with_extern(super::encode_rustflags(" -C  force-frame-pointers "), rlib),
+            "-C\x1fforce-frame-pointers\x1f--extern\x1fdbt_support=/t/libdbt_support.rlib"
+        );
+    }
+}
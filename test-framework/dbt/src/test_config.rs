@@ -0,0 +1,440 @@
+//! Settings for the tests of a Cargo package that don't fit in script comments, read from
+//! an optional `dbt-test.toml` next to the package's `Cargo.toml`:
+//!
+//! ```toml
+//! # Kill the debugger after this many seconds, instead of the `--timeout`
+//! timeout = 60
+//! # Cargo features of the package to enable
+//! features = ["nightly-types"]
+//! # Only compile and run the tests for these targets
+//! targets = ["x86_64-pc-windows-msvc", "i686-pc-windows-msvc"]
+//! # Additional arguments for `cargo build`
+//! cargo-args = ["--locked"]
+//! # Environment variables for compiling the package (not for running the tests)
+//! build-env = { RUSTC_BOOTSTRAP = "1" }
+//! # Labels for selecting the tests via `-t tag:<tag>`
+//! tags = ["collections"]
+//! # Debugger commands run before each script, like `--debugger-prelude`
+//! prelude = ["gdb:set print pretty on", "gdb/before-run:set env RUST_BACKTRACE=1"]
+//! # GDB's non-stop mode and `scheduler-locking`, instead of `--gdb-non-stop` and
+//! # `--gdb-scheduler-locking`
+//! gdb-non-stop = true
+//! gdb-scheduler-locking = "step"
+//! ```
+//!
+//! A `dbt-test.toml` in a directory above the package (a suite of packages) declares
+//! defaults for all packages beneath it, see [TestConfig::inherit].
+
+use std::{collections::BTreeMap, path::Path, process::Command, sync::Arc, time::Duration};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+
+use crate::{
+    cargo_profiles,
+    debugger::{self, DebuggerKind, PreludePlacement, SchedulerLocking},
+    prettify_path,
+};
+
+pub const FILE_NAME: &str = "dbt-test.toml";
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+pub struct TestConfig {
+    /// In seconds
+    pub timeout: Option<u64>,
+    #[serde(default)]
+    pub features: Vec<String>,
+    /// `None` means that all targets are allowed
+    pub targets: Option<Vec<String>>,
+    #[serde(default)]
+    pub cargo_args: Vec<String>,
+    /// Set for the Cargo commands compiling the package, but not for the test programs
+    #[serde(default)]
+    pub build_env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    /// Of the form `<debugger-kind>[@<version>][/<placement>]:<command>`, see
+    /// [debugger::parse_prelude]
+    #[serde(default)]
+    pub prelude: Vec<String>,
+    /// `None` means that `--gdb-non-stop` decides
+    pub gdb_non_stop: Option<bool>,
+    /// One of the modes of [SchedulerLocking], `None` means that `--gdb-scheduler-locking`
+    /// decides
+    pub gdb_scheduler_locking: Option<SchedulerLocking>,
+    /// The `opt-level` of the Cargo profiles of the workspace (see
+    /// [cargo_profiles::opt_levels]), which is not read from the file but from the
+    /// workspace's Cargo.toml
+    #[serde(skip)]
+    pub opt_levels: Arc<BTreeMap<String, String>>,
+}
+
+impl TestConfig {
+    /// Loads the settings of the package in `package_directory` from the `dbt-test.toml`
+    /// files in it and in the directories above it, up to `workspace_directory`, along with
+    /// the `opt-level` of the workspace's Cargo profiles
+    pub fn load(
+        workspace_directory: &Path,
+        package_directory: &Path,
+    ) -> anyhow::Result<TestConfig> {
+        let mut config = TestConfig::load_file(workspace_directory)?;
+        let mut directory = workspace_directory.to_path_buf();
+
+        if let Ok(relative_path) = package_directory.strip_prefix(workspace_directory) {
+            for component in relative_path.iter() {
+                directory.push(component);
+                config = TestConfig::load_file(&directory)?.inherit(&config);
+            }
+        }
+
+        config.opt_levels = Arc::new(cargo_profiles::opt_levels(workspace_directory)?);
+
+        Ok(config)
+    }
+
+    /// Loads the `dbt-test.toml` in `directory`, or returns the default settings if there
+    /// is none
+    fn load_file(directory: &Path) -> anyhow::Result<TestConfig> {
+        let path = directory.join(FILE_NAME);
+
+        if !path.exists() {
+            return Ok(TestConfig::default());
+        }
+
+        let text = std::fs::read_to_string(&path)
+            .with_context(|| format!("Could not read {}", prettify_path(&path)))?;
+
+        match TestConfig::parse(&text) {
+            Ok(config) => Ok(config),
+            Err(e) => bail!("{}: {}", prettify_path(&path), e),
+        }
+    }
+
+    fn parse(text: &str) -> anyhow::Result<TestConfig> {
+        let config: TestConfig = toml::from_str(text)?;
+
+        if config.timeout == Some(0) {
+            bail!("`timeout` must be at least 1 second");
+        }
+
+        for feature in &config.features {
+            if feature.is_empty() || feature.contains([',', ' ']) {
+                bail!(
+                    "`features` must list one feature per string, found `{}`",
+                    feature
+                );
+            }
+        }
+
+        if let Some(targets) = &config.targets {
+            if targets.is_empty() {
+                bail!("`targets` must not be empty, remove it to allow all targets");
+            }
+
+            if let Some(target) = targets.iter().find(|target| target.contains(' ')) {
+                bail!("`targets` must list target triples, found `{}`", target);
+            }
+        }
+
+        if let Some(tag) = config
+            .tags
+            .iter()
+            .find(|tag| tag.is_empty() || tag.contains(char::is_whitespace))
+        {
+            bail!(
+                "`tags` must not be empty or contain whitespace, found `{}`",
+                tag
+            );
+        }
+
+        for command in &config.prelude {
+            if debugger::parse_prelude(command).is_err() {
+                bail!(
+                    "`prelude` must list commands like `gdb:set print pretty on` or \
+                     `gdb/before-run:set env X=1`, found `{}`",
+                    command
+                );
+            }
+        }
+
+        if let Some(arg) = config
+            .cargo_args
+            .iter()
+            .find(|arg| ["--release", "--target", "--target-dir"].contains(&arg.as_str()))
+        {
+            bail!(
+                "`cargo-args` must not contain `{}`, it is set by dbt for each build",
+                arg
+            );
+        }
+
+        if let Some(name) = config.build_env.keys().find(|name| {
+            name.is_empty()
+                || name.contains('=')
+                || [
+                    "CARGO_TARGET_DIR",
+                    "CARGO_BUILD_TARGET",
+                    "CARGO_BUILD_TARGET_DIR",
+                ]
+                .contains(&name.as_str())
+        }) {
+            bail!(
+                "`build-env` must not set `{}`, which is either invalid or set by dbt for \
+                 each build",
+                name
+            );
+        }
+
+        // dbt passes its own flags to the compiler (see [crate::support_crate]), and takes
+        // `@opt_level` from the profiles in the workspace's Cargo.toml
+        if let Some(name) = config.build_env.keys().find(|name| {
+            [
+                "RUSTFLAGS",
+                "CARGO_ENCODED_RUSTFLAGS",
+                "CARGO_BUILD_RUSTFLAGS",
+            ]
+            .contains(&name.as_str())
+                || (name.starts_with("CARGO_PROFILE_") && name.ends_with("_OPT_LEVEL"))
+        }) {
+            bail!(
+                "`build-env` must not set `{}`, use #rustc-flags or a profile in the \
+                 workspace's Cargo.toml instead",
+                name
+            );
+        }
+
+        Ok(config)
+    }
+
+    /// Adds the defaults from the `dbt-test.toml` of an enclosing directory to these
+    /// settings: `timeout`, `targets` and the GDB settings are only taken from `outer` if they
+    /// aren't set here, the lists are combined, and so are the `build-env` variables, with the
+    /// ones set here taking precedence.
+    pub fn inherit(self, outer: &TestConfig) -> TestConfig {
+        fn combine(outer: &[String], inner: Vec<String>) -> Vec<String> {
+            let mut combined = outer.to_vec();
+
+            for value in inner {
+                if !combined.contains(&value) {
+                    combined.push(value);
+                }
+            }
+
+            combined
+        }
+
+        TestConfig {
+            timeout: self.timeout.or(outer.timeout),
+            features: combine(&outer.features, self.features),
+            targets: self.targets.or_else(|| outer.targets.clone()),
+            cargo_args: combine(&outer.cargo_args, self.cargo_args),
+            build_env: outer
+                .build_env
+                .clone()
+                .into_iter()
+                .chain(self.build_env)
+                .collect(),
+            tags: combine(&outer.tags, self.tags),
+            prelude: combine(&outer.prelude, self.prelude),
+            gdb_non_stop: self.gdb_non_stop.or(outer.gdb_non_stop),
+            gdb_scheduler_locking: self.gdb_scheduler_locking.or(outer.gdb_scheduler_locking),
+            opt_levels: outer.opt_levels.clone(),
+        }
+    }
+
+    /// The `opt-level` the package is compiled with for `cargo_profile`, i.e. the value of
+    /// `@opt_level`
+    pub fn opt_level(&self, cargo_profile: &str) -> String {
+        cargo_profiles::opt_level(&self.opt_levels, cargo_profile)
+    }
+
+    /// The commands of `prelude` for the given debugger, with where they go in the script
+    pub fn prelude_for(&self, kind: DebuggerKind, version: &str) -> Vec<(PreludePlacement, &str)> {
+        self.prelude
+            .iter()
+            .filter_map(|prelude| {
+                let (selector, (placement, _)) = debugger::parse_prelude(prelude).ok()?;
+                let (_, command) = prelude.split_once(':')?;
+
+                selector
+                    .matches(kind, version)
+                    .then_some((placement, command.trim()))
+            })
+            .collect()
+    }
+
+    /// The `gdb-scheduler-locking` of the tests, if it is set
+    pub fn gdb_scheduler_locking(&self) -> Option<SchedulerLocking> {
+        self.gdb_scheduler_locking
+    }
+
+    /// Whether the package has to be compiled with its own `cargo build` invocation
+    pub fn needs_separate_build(&self) -> bool {
+        !self.features.is_empty() || !self.cargo_args.is_empty() || !self.build_env.is_empty()
+    }
+
+    /// Whether the tests are compiled and run for `target_triple`
+    pub fn allows_target(&self, target_triple: &str) -> bool {
+        match &self.targets {
+            Some(targets) => targets.iter().any(|target| target == target_triple),
+            None => true,
+        }
+    }
+
+    /// The timeout for the tests, falling back to `default` (given via `--timeout`)
+    pub fn timeout(&self, default: Option<Duration>) -> Option<Duration> {
+        self.timeout.map(Duration::from_secs).or(default)
+    }
+}
+
+/// The host target triple of the (given or default) toolchain, as reported by `rustc -vV`
+pub fn host_target(toolchain: Option<&str>) -> anyhow::Result<String> {
+    let mut command = Command::new("rustc");
+
+    if let Some(toolchain) = toolchain {
+        command.arg(format!("+{}", toolchain));
+    }
+
+    let output = command
+        .arg("-vV")
+        .output()
+        .context("Could not run `rustc -vV` to determine the host target")?;
+
+    match String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("host: "))
+    {
+        Some(host) => Ok(host.trim().to_string()),
+        None => bail!("Could not determine the host target from the output of `rustc -vV`"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::TestConfig;
+    use crate::debugger::{DebuggerKind, PreludePlacement, SchedulerLocking};
+
+    #[test]
+    fn parse() {
+        let config = TestConfig::parse(
+            r#"
+timeout = 60
+features = ["a", "b"]
+targets = ["x86_64-pc-windows-msvc"]
+cargo-args = ["--locked"]
+build-env = { RUSTC_BOOTSTRAP = "1" }
+tags = ["slow"]
+prelude = ["gdb@12:set print pretty on"]
+gdb-non-stop = true
+gdb-scheduler-locking = "step"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config,
+            TestConfig {
+                timeout: Some(60),
+                features: vec!["a".into(), "b".into()],
+                targets: Some(vec!["x86_64-pc-windows-msvc".into()]),
+                cargo_args: vec!["--locked".into()],
+                build_env: [("RUSTC_BOOTSTRAP".into(), "1".into())].into(),
+                tags: vec!["slow".into()],
+                prelude: vec!["gdb@12:set print pretty on".into()],
+                gdb_non_stop: Some(true),
+                gdb_scheduler_locking: Some(SchedulerLocking::Step),
+                opt_levels: Default::default(),
+            }
+        );
+        assert_eq!(config.gdb_scheduler_locking(), Some(SchedulerLocking::Step));
+        assert!(config.needs_separate_build());
+        assert!(config.allows_target("x86_64-pc-windows-msvc"));
+        assert!(!config.allows_target("x86_64-unknown-linux-gnu"));
+        assert_eq!(config.timeout(None), Some(Duration::from_secs(60)));
+        assert_eq!(
+            config.prelude_for(DebuggerKind::Gdb, "12.1"),
+            vec![(PreludePlacement::AfterGenerated, "set print pretty on")]
+        );
+        assert!(config.prelude_for(DebuggerKind::Gdb, "13.1").is_empty());
+
+        assert_eq!(TestConfig::parse("").unwrap(), TestConfig::default());
+    }
+
+    #[test]
+    fn parse_errors() {
+        let error = |text: &str| TestConfig::parse(text).unwrap_err().to_string();
+
+        assert!(error("timout = 60").contains("unknown field `timout`"));
+        assert!(error("timeout = \"60\"").contains("invalid type: string \"60\""));
+        assert_eq!(error("timeout = 0"), "`timeout` must be at least 1 second");
+        assert_eq!(
+            error("features = [\"a,b\"]"),
+            "`features` must list one feature per string, found `a,b`"
+        );
+        assert_eq!(
+            error("targets = []"),
+            "`targets` must not be empty, remove it to allow all targets"
+        );
+        assert_eq!(
+            error("prelude = [\"set print pretty on\"]"),
+            "`prelude` must list commands like `gdb:set print pretty on` or \
+             `gdb/before-run:set env X=1`, found `set print pretty on`"
+        );
+        assert!(error("gdb-scheduler-locking = \"always\"").contains(
+            "Unknown scheduler-locking mode `always`, expected `off`, `on`, `step` or `replay` \
+             for key `gdb-scheduler-locking`"
+        ));
+        assert_eq!(
+            error("cargo-args = [\"--release\"]"),
+            "`cargo-args` must not contain `--release`, it is set by dbt for each build"
+        );
+        assert_eq!(
+            error("build-env = { CARGO_TARGET_DIR = \"x\" }"),
+            "`build-env` must not set `CARGO_TARGET_DIR`, which is either invalid or set by dbt \
+             for each build"
+        );
+        assert_eq!(
+            error("build-env = { CARGO_PROFILE_DEV_OPT_LEVEL = \"1\" }"),
+            "`build-env` must not set `CARGO_PROFILE_DEV_OPT_LEVEL`, use #rustc-flags or a profile \
+             in the workspace's Cargo.toml instead"
+        );
+    }
+
+    #[test]
+    fn inherit() {
+        let suite = TestConfig::parse(
+            r#"
+timeout = 30
+targets = ["x86_64-unknown-linux-gnu"]
+tags = ["collections"]
+prelude = ["gdb:set print pretty on"]
+build-env = { RUSTC_BOOTSTRAP = "1", CARGO_PROFILE_DEV_OVERFLOW_CHECKS = "true" }
+"#,
+        )
+        .unwrap();
+        let package = TestConfig::parse(
+            "timeout = 60\ntags = [\"slow\", \"collections\"]\n\
+             build-env = { CARGO_PROFILE_DEV_OVERFLOW_CHECKS = \"false\" }",
+        )
+        .unwrap();
+
+        assert_eq!(
+            package.inherit(&suite),
+            TestConfig {
+                timeout: Some(60),
+                targets: Some(vec!["x86_64-unknown-linux-gnu".into()]),
+                tags: vec!["collections".into(), "slow".into()],
+                prelude: vec!["gdb:set print pretty on".into()],
+                build_env: [
+                    ("RUSTC_BOOTSTRAP".into(), "1".into()),
+                    ("CARGO_PROFILE_DEV_OVERFLOW_CHECKS".into(), "false".into())
+                ]
+                .into(),
+                ..TestConfig::default()
+            }
+        );
+    }
+}
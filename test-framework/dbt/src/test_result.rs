@@ -1,8 +1,11 @@
-use std::{path::PathBuf, sync::Arc};
+use std::{collections::BTreeMap, fmt::Write, path::PathBuf, sync::Arc, time::Duration};
 
 use crate::{
-    cargo_test_directory::TestDefinition,
-    debugger::{Debugger, DebuggerKind, DebuggerOutput},
+    cargo_test_directory::{BuildConfig, TestDefinition},
+    debugger::{
+        CheckMatch, CommandTiming, Debugger, DebuggerCrash, DebuggerKind, DebuggerOutput,
+        PrintedValue, ResourceUsage,
+    },
     prettify_path,
     script::PhaseConfig,
 };
@@ -12,7 +15,18 @@ pub enum Status {
     Passed,
     Failed(String, DebuggerOutput),
     Errored(String),
-    Ignored,
+    /// The debugger did not finish within `--timeout`
+    TimedOut(String),
+    /// The debugger itself crashed, with the output it produced up to that point
+    DebuggerCrashed(DebuggerCrash, DebuggerOutput),
+    /// The test was not run because the named test it depends on (#depends-on) did not pass
+    DependencyFailed(String),
+    /// The test was ignored via #ignore-test, optionally giving a reason
+    Ignored(Option<String>),
+    /// The test could not be run, e.g. because of an unmet #require
+    Skipped(String),
+    /// The package of the test could not be compiled, with the compiler's diagnostics
+    BuildFailed(String),
 }
 
 impl Status {
@@ -21,7 +35,26 @@ pub fn short_description(&self) -> &'static str {
             Status::Passed => "OK",
             Status::Failed(..) => "failed",
             Status::Errored(..) => "ERROR",
-            Status::Ignored => "ignored",
+            Status::TimedOut(..) => "TIMEOUT",
+            Status::DebuggerCrashed(..) => "CRASHED",
+            Status::DependencyFailed(..) => "DEPENDENCY FAILED",
+            Status::Ignored(..) => "ignored",
+            Status::Skipped(..) => "skipped",
+            Status::BuildFailed(..) => "BUILD FAILED",
+        }
+    }
+
+    /// Whether the test passed, or `None` if it was not actually run (ignored or skipped).
+    pub fn passed(&self) -> Option<bool> {
+        match *self {
+            Status::Passed => Some(true),
+            Status::Failed(..)
+            | Status::Errored(..)
+            | Status::TimedOut(..)
+            | Status::DebuggerCrashed(..)
+            | Status::DependencyFailed(..)
+            | Status::BuildFailed(..) => Some(false),
+            Status::Ignored(..) | Status::Skipped(..) => None,
         }
     }
 }
@@ -33,9 +66,29 @@ pub struct TestResult {
     pub debugger_kind: DebuggerKind,
     pub debugger_version: Arc<str>,
     pub cargo_profile: Arc<str>,
+    /// The toolchain and target the test was compiled with
+    pub build: BuildConfig,
     pub status: Box<Status>,
+    /// The source file containing the test
+    pub source_path: PathBuf,
     pub debugger_output_stdout_path: Option<PathBuf>,
     pub debugger_output_stderr_path: Option<PathBuf>,
+    /// How long the debugger took for each of the test's commands. Empty unless the commands
+    /// were fenced (see `--benchmark`) and the debugger's output was captured live.
+    pub command_timings: Vec<CommandTiming>,
+    /// The resources used by the debugger, if measured
+    pub resource_usage: Option<ResourceUsage>,
+    /// How long the debugger ran, if it was run, see [time_breakdown]
+    pub debugger_duration: Option<Duration>,
+    /// The output of the test's `#print` statements
+    pub printed_values: Vec<PrintedValue>,
+    /// The checks that matched the debugger's output, in the order they matched
+    pub matched_checks: Vec<CheckMatch>,
+    /// Warnings the debugger printed about the environment, see [crate::diagnostics]
+    pub diagnostics: Vec<String>,
+    /// The compiler's diagnostics for the package of the test, e.g. warnings about
+    /// deprecated APIs it uses. Empty if there were none.
+    pub build_output: String,
 }
 
 impl TestResult {
@@ -50,25 +103,313 @@ pub fn new(
             debugger_kind: debugger.kind,
             debugger_version: debugger.version.clone(),
             cargo_profile: cargo_profile.clone(),
+            build: BuildConfig::default(),
             status: Box::new(status),
             test_name: test_definition.name.clone(),
             phase: phase.clone(),
+            source_path: test_definition.absolute_source_path.clone(),
             debugger_output_stdout_path: None,
             debugger_output_stderr_path: None,
+            command_timings: vec![],
+            resource_usage: None,
+            debugger_duration: None,
+            printed_values: vec![],
+            matched_checks: vec![],
+            diagnostics: vec![],
+            build_output: String::new(),
+        }
+    }
+
+    pub fn test_label(&self) -> String {
+        if self.build.is_default() {
+            format!(
+                "{} ({}) - Cargo profile `{}`",
+                self.test_name, self.phase, self.cargo_profile
+            )
+        } else {
+            format!(
+                "{} ({}) - Cargo profile `{}`, {}",
+                self.test_name, self.phase, self.cargo_profile, self.build
+            )
+        }
+    }
+
+    /// Like [TestResult::test_label] but also identifies the debugger
+    pub fn label_with_debugger(&self) -> String {
+        format!(
+            "{} - {} {}",
+            self.test_label(),
+            self.debugger_kind.name(),
+            self.debugger_version
+        )
+    }
+}
+
+/// How often a test passed when running it repeatedly via `--stress`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StressStatistic {
+    pub label: String,
+    pub passed: usize,
+    pub runs: usize,
+}
+
+impl StressStatistic {
+    /// A test is flaky if it passed some of the time, but not always
+    pub fn is_flaky(&self) -> bool {
+        self.passed > 0 && self.passed < self.runs
+    }
+}
+
+/// Aggregates the results of the runs of each test. Ignored and skipped runs are not
+/// counted.
+pub fn stress_statistics(test_results: &[TestResult]) -> Vec<StressStatistic> {
+    let mut by_label: BTreeMap<String, (usize, usize)> = BTreeMap::new();
+
+    for test_result in test_results {
+        let passed = match test_result.status.passed() {
+            Some(passed) => passed,
+            None => continue,
+        };
+
+        let (passed_count, runs) = by_label
+            .entry(test_result.label_with_debugger())
+            .or_default();
+        *passed_count += passed as usize;
+        *runs += 1;
+    }
+
+    by_label
+        .into_iter()
+        .map(|(label, (passed, runs))| StressStatistic {
+            label,
+            passed,
+            runs,
+        })
+        .collect()
+}
+
+pub fn print_stress_report(test_results: &[TestResult]) {
+    println!();
+    println!("Pass rates:");
+    println!();
+
+    for statistic in stress_statistics(test_results) {
+        println!(
+            "{:>3}% ({}/{}) {}{}",
+            statistic.passed * 100 / statistic.runs,
+            statistic.passed,
+            statistic.runs,
+            statistic.label,
+            if statistic.is_flaky() {
+                " -- FLAKY"
+            } else {
+                ""
+            }
+        );
+    }
+}
+
+/// The status of each test across all debuggers, profiles, toolchains and targets it ran
+/// with (`--matrix`). Rows are tests, columns are configurations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Matrix {
+    pub columns: Vec<String>,
+    /// Each test with the short status description for each column, if it ran with it
+    pub rows: Vec<(String, Vec<Option<&'static str>>)>,
+}
+
+pub fn matrix(test_results: &[TestResult]) -> Matrix {
+    let mut columns: Vec<String> = vec![];
+    let mut cells: BTreeMap<String, BTreeMap<usize, &'static str>> = BTreeMap::new();
+
+    for test_result in test_results {
+        let mut column = format!(
+            "{} {}, {}",
+            test_result.debugger_kind.name(),
+            test_result.debugger_version,
+            test_result.cargo_profile
+        );
+
+        if !test_result.build.is_default() {
+            write!(column, ", {}", test_result.build).unwrap();
         }
+
+        let column_index = match columns.iter().position(|c| *c == column) {
+            Some(index) => index,
+            None => {
+                columns.push(column);
+                columns.len() - 1
+            }
+        };
+
+        let row = format!("{} ({})", test_result.test_name, test_result.phase);
+        let status = test_result.status.short_description();
+
+        // With --stress a test runs several times per configuration
+        cells
+            .entry(row)
+            .or_default()
+            .entry(column_index)
+            .and_modify(|cell| {
+                if *cell != status {
+                    *cell = "FLAKY";
+                }
+            })
+            .or_insert(status);
+    }
+
+    let rows = cells
+        .into_iter()
+        .map(|(row, cells)| {
+            let statuses = (0..columns.len()).map(|i| cells.get(&i).copied()).collect();
+            (row, statuses)
+        })
+        .collect();
+
+    Matrix { columns, rows }
+}
+
+pub fn print_matrix_report(test_results: &[TestResult]) {
+    let matrix = matrix(test_results);
+
+    println!();
+    println!("Test matrix:");
+    println!();
+
+    for (index, column) in matrix.columns.iter().enumerate() {
+        println!("  [{}] {}", index + 1, column);
+    }
+
+    println!();
+
+    const CELL_WIDTH: usize = 9;
+
+    for index in 0..matrix.columns.len() {
+        print!("{:<CELL_WIDTH$}", format!("[{}]", index + 1));
+    }
+    println!("test");
+
+    for (row, statuses) in &matrix.rows {
+        for status in statuses {
+            print!("{:<CELL_WIDTH$}", status.unwrap_or("-"));
+        }
+        println!("{}", row);
     }
+}
+
+/// The number of lines of the debugger's output shown when it crashed
+const CRASH_OUTPUT_LINES: usize = 10;
 
-    fn test_label(&self) -> String {
+fn crash_message(crash: &DebuggerCrash, debugger_output: &DebuggerOutput) -> String {
+    let mut message = if crash.is_fault() {
+        format!(
+            "The debugger was {}. This is a bug in the debugger, not a failed check.\n",
+            crash
+        )
+    } else {
         format!(
-            "{} ({}) - Cargo profile `{}`",
-            self.test_name, self.phase, self.cargo_profile
+            "The debugger was {}, most likely from outside dbt (e.g. by the OOM killer or a \
+             cancelled job). This is not a failed check.\n",
+            crash
         )
+    };
+
+    for (name, output) in [
+        ("stdout", &debugger_output.stdout),
+        ("stderr", &debugger_output.stderr),
+    ] {
+        let lines: Vec<&str> = output.lines().collect();
+
+        if lines.is_empty() {
+            continue;
+        }
+
+        writeln!(message, "Last lines of the debugger's {}:", name).unwrap();
+
+        for line in &lines[lines.len().saturating_sub(CRASH_OUTPUT_LINES)..] {
+            writeln!(message, "  {}", line).unwrap();
+        }
+    }
+
+    message
+}
+
+/// The first line of each warning in the compiler's diagnostics, e.g. `warning: use of
+/// deprecated function `foo``, each distinct one once
+fn compiler_warnings(build_output: &str) -> Vec<&str> {
+    let mut warnings = vec![];
+
+    for line in build_output.lines() {
+        if line.starts_with("warning") && !warnings.contains(&line) {
+            warnings.push(line);
+        }
     }
+
+    warnings
+}
+
+/// Where the time of a run went, to tell whether compiling the tests or running the
+/// debuggers is what makes a run slow
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TimeBreakdown {
+    /// Compiling the tests, `None` if they weren't compiled by this run
+    pub build: Option<Duration>,
+    /// Running the debuggers, by kind. As tests run in parallel, this adds up to more than
+    /// the wall-clock time.
+    pub debuggers: BTreeMap<&'static str, Duration>,
 }
 
-pub fn print_report(test_results: Vec<TestResult>) -> bool {
+impl TimeBreakdown {
+    /// `None` if nothing was measured, e.g. when re-checking captured output
+    pub fn render(&self) -> Option<String> {
+        let mut parts = vec![];
+
+        if let Some(build) = self.build {
+            parts.push(format!("compiling {:.1}s", build.as_secs_f64()));
+        }
+
+        for (debugger_kind, duration) in &self.debuggers {
+            parts.push(format!(
+                "running {} {:.1}s",
+                debugger_kind,
+                duration.as_secs_f64()
+            ));
+        }
+
+        if parts.is_empty() {
+            None
+        } else {
+            Some(format!("Time spent {}", parts.join(", ")))
+        }
+    }
+}
+
+/// Adds up the time the debuggers ran for `test_results`, by debugger kind. `build_duration`
+/// is how long compiling the tests took, if they were compiled.
+pub fn time_breakdown(
+    test_results: &[TestResult],
+    build_duration: Option<Duration>,
+) -> TimeBreakdown {
+    let mut debuggers: BTreeMap<&'static str, Duration> = BTreeMap::new();
+
+    for test_result in test_results {
+        if let Some(duration) = test_result.debugger_duration {
+            *debuggers
+                .entry(test_result.debugger_kind.name())
+                .or_default() += duration;
+        }
+    }
+
+    TimeBreakdown {
+        build: build_duration,
+        debuggers,
+    }
+}
+
+pub fn print_report(test_results: Vec<TestResult>, time_breakdown: &TimeBreakdown) -> bool {
     let mut errored = 0;
     let mut ignored = 0;
+    let mut skipped = 0;
     let mut failed = 0;
     let mut passed = 0;
 
@@ -76,26 +417,128 @@ pub fn print_report(test_results: Vec<TestResult>) -> bool {
 
     for test_result in test_results {
         let print_output_paths = match &*test_result.status {
-            Status::Ignored => {
+            Status::Ignored(reason) => {
                 ignored += 1;
+                if let Some(reason) = reason {
+                    println!(
+                        "Test {} ignored: {}",
+                        test_result.label_with_debugger(),
+                        reason
+                    );
+                }
+                false
+            }
+            Status::Skipped(reason) => {
+                skipped += 1;
+                println!(
+                    "Test {} skipped: {}",
+                    test_result.label_with_debugger(),
+                    reason
+                );
                 false
             }
             Status::Errored(msg) => {
                 errored += 1;
-                print!("Test {} errored:\n{}", test_result.test_label(), msg);
+                print!(
+                    "Test {} errored:\n{}",
+                    test_result.label_with_debugger(),
+                    msg
+                );
+                true
+            }
+            Status::TimedOut(msg) => {
+                errored += 1;
+                print!(
+                    "Test {} timed out:\n{}",
+                    test_result.label_with_debugger(),
+                    msg
+                );
+                true
+            }
+            Status::DebuggerCrashed(crash, debugger_output) => {
+                errored += 1;
+                print!(
+                    "Test {} {}:\n{}",
+                    test_result.label_with_debugger(),
+                    if crash.is_fault() {
+                        "crashed the debugger"
+                    } else {
+                        "lost its debugger"
+                    },
+                    crash_message(crash, debugger_output)
+                );
                 true
             }
+            Status::DependencyFailed(dependency) => {
+                errored += 1;
+                println!(
+                    "Test {} not run: its dependency {} did not pass",
+                    test_result.label_with_debugger(),
+                    dependency
+                );
+                false
+            }
+            Status::BuildFailed(output) => {
+                errored += 1;
+                println!(
+                    "Test {} not run: its package could not be compiled:",
+                    test_result.label_with_debugger()
+                );
+
+                for line in output.lines() {
+                    println!("  {}", line);
+                }
+
+                println!();
+                false
+            }
             Status::Passed => {
                 passed += 1;
                 false
             }
             Status::Failed(msg, _) => {
                 failed += 1;
-                print!("Test {} failed:\n{}", test_result.test_label(), msg);
+                print!(
+                    "Test {} failed:\n{}",
+                    test_result.label_with_debugger(),
+                    msg
+                );
                 true
             }
         };
 
+        if !test_result.diagnostics.is_empty() {
+            println!(
+                "Test {} printed debugger warnings:",
+                test_result.label_with_debugger()
+            );
+
+            for warning in &test_result.diagnostics {
+                println!("  {}", warning);
+            }
+
+            if !print_output_paths {
+                println!();
+            }
+        }
+
+        let compiler_warnings = compiler_warnings(&test_result.build_output);
+
+        if !compiler_warnings.is_empty() {
+            println!(
+                "Test {} was compiled with warnings:",
+                test_result.label_with_debugger()
+            );
+
+            for warning in compiler_warnings {
+                println!("  {}", warning);
+            }
+
+            if !print_output_paths {
+                println!();
+            }
+        }
+
         if print_output_paths {
             if let (Some(stdout_path), Some(stderr_path)) = (
                 &test_result.debugger_output_stdout_path,
@@ -110,10 +553,14 @@ pub fn print_report(test_results: Vec<TestResult>) -> bool {
     }
 
     println!(
-        "{} passed, {} failed, {} errored, {} ignored",
-        passed, failed, errored, ignored
+        "{} passed, {} failed, {} errored, {} ignored, {} skipped",
+        passed, failed, errored, ignored, skipped
     );
 
+    if let Some(time_breakdown) = time_breakdown.render() {
+        println!("{}", time_breakdown);
+    }
+
     println!();
 
     if failed + errored == 0 {
@@ -123,3 +570,190 @@ pub fn print_report(test_results: Vec<TestResult>) -> bool {
         false
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use crate::{
+        cargo_test_directory::BuildConfig,
+        debugger::{DebuggerCrash, DebuggerExitStatus, DebuggerKind, DebuggerOutput},
+        script::PhaseConfig,
+    };
+
+    use super::{crash_message, Status, StressStatistic, TestResult};
+
+    fn test_result(test_name: &str, status: Status) -> TestResult {
+        TestResult {
+            test_name: test_name.into(),
+            phase: PhaseConfig::Live,
+            debugger_kind: DebuggerKind::Gdb,
+            debugger_version: "12.1".into(),
+            cargo_profile: Arc::from("debug"),
+            build: BuildConfig::default(),
+            status: Box::new(status),
+            source_path: "src/main.rs".into(),
+            debugger_output_stdout_path: None,
+            debugger_output_stderr_path: None,
+            command_timings: vec![],
+            resource_usage: None,
+            debugger_duration: None,
+            printed_values: vec![],
+            matched_checks: vec![],
+            diagnostics: vec![],
+            build_output: String::new(),
+        }
+    }
+
+    #[test]
+    fn stress_statistics() {
+        let results = [
+            test_result("a", Status::Passed),
+            test_result("b", Status::Errored("oops".to_string())),
+            test_result("a", Status::TimedOut("slow".to_string())),
+            test_result("b", Status::Errored("oops".to_string())),
+            test_result("c", Status::Skipped("unmet requirement".to_string())),
+            test_result("a", Status::Passed),
+        ];
+
+        let statistics = super::stress_statistics(&results);
+
+        assert_eq!(
+            statistics,
+            vec![
+                StressStatistic {
+                    label: "a (live) - Cargo profile `debug` - gdb 12.1".to_string(),
+                    passed: 2,
+                    runs: 3,
+                },
+                StressStatistic {
+                    label: "b (live) - Cargo profile `debug` - gdb 12.1".to_string(),
+                    passed: 0,
+                    runs: 2,
+                },
+            ]
+        );
+
+        assert!(statistics[0].is_flaky());
+        assert!(!statistics[1].is_flaky());
+    }
+
+    #[test]
+    fn matrix() {
+        let release = |test_result: TestResult| TestResult {
+            cargo_profile: Arc::from("release"),
+            ..test_result
+        };
+        let nightly = |test_result: TestResult| TestResult {
+            build: BuildConfig {
+                toolchain: Some("nightly".into()),
+                target: None,
+            },
+            ..test_result
+        };
+
+        let results = [
+            test_result("b", Status::Passed),
+            test_result("a", Status::Errored("oops".to_string())),
+            release(test_result(
+                "b",
+                Status::Skipped("unmet requirement".to_string()),
+            )),
+            nightly(test_result("a", Status::Passed)),
+            nightly(test_result("a", Status::Errored("oops".to_string()))),
+        ];
+
+        assert_eq!(
+            super::matrix(&results),
+            super::Matrix {
+                columns: vec![
+                    "gdb 12.1, debug".to_string(),
+                    "gdb 12.1, release".to_string(),
+                    "gdb 12.1, debug, toolchain `nightly`".to_string(),
+                ],
+                rows: vec![
+                    (
+                        "a (live)".to_string(),
+                        vec![Some("ERROR"), None, Some("FLAKY")]
+                    ),
+                    (
+                        "b (live)".to_string(),
+                        vec![Some("OK"), Some("skipped"), None]
+                    ),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn time_breakdown() {
+        let with_duration = |debugger_kind: DebuggerKind, millis: u64| TestResult {
+            debugger_kind,
+            debugger_duration: Some(Duration::from_millis(millis)),
+            ..test_result("a", Status::Passed)
+        };
+
+        let time_breakdown = super::time_breakdown(
+            &[
+                with_duration(DebuggerKind::Gdb, 1500),
+                with_duration(DebuggerKind::Lldb, 2000),
+                with_duration(DebuggerKind::Gdb, 1000),
+                test_result("b", Status::Skipped("unmet requirement".to_string())),
+            ],
+            Some(Duration::from_secs(30)),
+        );
+
+        assert_eq!(
+            time_breakdown.render().unwrap(),
+            "Time spent compiling 30.0s, running gdb 2.5s, running lldb 2.0s"
+        );
+        assert_eq!(super::time_breakdown(&[], None).render(), None);
+    }
+
+    #[test]
+    fn compiler_warnings() {
+        let build_output = "warning: use of deprecated function `std::mem::uninitialized`\n \
+                            --> src/main.rs:3:13\n  |\n\
+                            warning: unused variable: `x`\n  |\n\
+                            warning: use of deprecated function `std::mem::uninitialized`\n";
+
+        assert_eq!(
+            super::compiler_warnings(build_output),
+            [
+                "warning: use of deprecated function `std::mem::uninitialized`",
+                "warning: unused variable: `x`",
+            ]
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn crash_messages() {
+        let output = |signal| DebuggerOutput {
+            stdout: "(gdb) print x\n".into(),
+            stderr: String::new(),
+            exit_status: DebuggerExitStatus::Crashed(DebuggerCrash::Signal(signal)),
+            program_output: None,
+        };
+
+        assert_eq!(
+            crash_message(
+                &DebuggerCrash::Signal(libc::SIGSEGV),
+                &output(libc::SIGSEGV)
+            ),
+            "The debugger was killed by signal 11 (SIGSEGV). This is a bug in the debugger, not \
+             a failed check.\nLast lines of the debugger's stdout:\n  (gdb) print x\n"
+        );
+        assert_eq!(
+            crash_message(
+                &DebuggerCrash::Signal(libc::SIGKILL),
+                &output(libc::SIGKILL)
+            ),
+            "The debugger was killed by signal 9 (SIGKILL), most likely from outside dbt (e.g. by \
+             the OOM killer or a cancelled job). This is not a failed check.\nLast lines of the \
+             debugger's stdout:\n  (gdb) print x\n"
+        );
+        assert!(!DebuggerCrash::Signal(libc::SIGTERM).is_fault());
+        assert!(DebuggerCrash::Exception(0xC000_0005).is_fault());
+    }
+}
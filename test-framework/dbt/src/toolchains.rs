@@ -0,0 +1,143 @@
+//! The Rust toolchains given via `--toolchain`, which are managed by rustup. They are checked
+//! before anything is built, so that a missing toolchain fails the run right away rather than
+//! after the tests for the other toolchains were built, or is installed if
+//! `--install-toolchains` is given, along with the standard library of each `--target`.
+
+use std::process::{Command, Stdio};
+
+use anyhow::{bail, Context};
+
+/// Whether `toolchain` is installed, without letting rustup install it on the fly
+pub fn is_installed(toolchain: &str) -> anyhow::Result<bool> {
+    let status = Command::new("rustup")
+        .args(["which", "--toolchain", toolchain, "rustc"])
+        // Newer versions of rustup install missing toolchains by default
+        .env("RUSTUP_AUTO_INSTALL", "0")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .context("Could not run rustup, which is needed for `--toolchain`")?;
+
+    Ok(status.success())
+}
+
+/// Installs `toolchain` via rustup if it isn't installed yet. Returns false if that
+/// failed, e.g. because there is no nightly for that day.
+pub fn install(toolchain: &str) -> anyhow::Result<bool> {
+    println!("Installing toolchain `{}`", toolchain);
+
+    let status = Command::new("rustup")
+        .args(["toolchain", "install", toolchain, "--profile", "minimal"])
+        .stdout(Stdio::null())
+        .status()?;
+
+    Ok(status.success())
+}
+
+/// The command adding the standard library for `target` to `toolchain`, or to the default
+/// toolchain if none is given
+fn target_add_command(toolchain: Option<&str>, target: &str) -> Command {
+    let mut command = Command::new("rustup");
+    command.args(["target", "add"]);
+
+    if let Some(toolchain) = toolchain {
+        command.args(["--toolchain", toolchain]);
+    }
+
+    command.arg(target);
+    command
+}
+
+/// Adds the standard library for each of `targets` to each of `toolchains`, or to the
+/// default toolchain if there are none. rustup skips the ones that are already installed.
+fn add_targets(toolchains: &[String], targets: &[String]) -> anyhow::Result<()> {
+    let toolchains: Vec<Option<&str>> = if toolchains.is_empty() {
+        vec![None]
+    } else {
+        toolchains
+            .iter()
+            .map(|toolchain| Some(&toolchain[..]))
+            .collect()
+    };
+
+    for toolchain in toolchains {
+        for target in targets {
+            let status = target_add_command(toolchain, target)
+                .stdout(Stdio::null())
+                .status()
+                .context("Could not run rustup, which is needed for `--install-toolchains`")?;
+
+            if !status.success() {
+                bail!(
+                    "Could not add target `{}` to toolchain `{}`",
+                    target,
+                    toolchain.unwrap_or("default")
+                );
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Makes sure all of `toolchains` are installed, installing the missing ones and the
+/// standard library for each of `targets` if `install` is set, or else failing with a list
+/// of the missing toolchains
+pub fn ensure_installed(
+    toolchains: &[String],
+    targets: &[String],
+    install: bool,
+) -> anyhow::Result<()> {
+    let mut missing = vec![];
+
+    for toolchain in toolchains {
+        if is_installed(toolchain)? {
+            continue;
+        }
+
+        if !install {
+            missing.push(format!("`{}`", toolchain));
+        } else if !self::install(toolchain)? {
+            bail!("Could not install toolchain `{}`", toolchain);
+        }
+    }
+
+    if !missing.is_empty() {
+        bail!(
+            "The toolchain(s) {} are not installed. Install them via `rustup toolchain \
+             install`, or pass `--install-toolchains` to have dbt do so.",
+            missing.join(", ")
+        );
+    }
+
+    if install {
+        add_targets(toolchains, targets)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn target_add_command() {
+        let args = |toolchain| {
+            super::target_add_command(toolchain, "i686-unknown-linux-gnu")
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned())
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            args(Some("nightly-2024-01-01")),
+            [
+                "target",
+                "add",
+                "--toolchain",
+                "nightly-2024-01-01",
+                "i686-unknown-linux-gnu"
+            ]
+        );
+        assert_eq!(args(None), ["target", "add", "i686-unknown-linux-gnu"]);
+    }
+}
@@ -0,0 +1,104 @@
+//! Recording Time Travel Debugging traces for the `ttd` phase. CDB opens a trace like a
+//! crashdump, except that the recorded execution can be replayed in both directions, so
+//! breakpoints, stepping and `#reverse-*` directives all work on it.
+
+use std::{
+    ffi::OsStr,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use anyhow::{bail, Context};
+
+use crate::prettify_path;
+
+/// The directory the trace of a test is recorded to
+pub fn trace_directory(output_dir_for_test: &Path) -> PathBuf {
+    output_dir_for_test.join("ttd")
+}
+
+/// Records a trace of `debuggee` into `trace_dir` by running it with `ttd` (i.e. `TTD.exe`
+/// or `tttracer.exe`). Returns the path of the trace file.
+pub fn record_trace(
+    ttd: &OsStr,
+    debuggee: &Path,
+    debuggee_args: &[String],
+    trace_dir: &Path,
+    env_vars: &[(String, String)],
+) -> anyhow::Result<PathBuf> {
+    // TTD numbers traces instead of overwriting them, so start from scratch
+    if trace_dir.exists() {
+        std::fs::remove_dir_all(trace_dir)?;
+    }
+
+    std::fs::create_dir_all(trace_dir)?;
+
+    // The exit code is the debuggee's, which may fail on purpose, so only the trace counts
+    let output = record_command(ttd, debuggee, trace_dir)
+        .args(debuggee_args)
+        .envs(env_vars.iter().map(|(name, value)| (name, value)))
+        .output()
+        .with_context(|| format!("Could not run `{}`", ttd.to_string_lossy()))?;
+
+    match find_trace(trace_dir)? {
+        Some(trace) => Ok(trace),
+        None => bail!(
+            "`{}` did not record a trace of {} to {}:\n{}{}",
+            ttd.to_string_lossy(),
+            prettify_path(debuggee),
+            prettify_path(trace_dir),
+            String::from_utf8_lossy(&output.stdout),
+            String::from_utf8_lossy(&output.stderr)
+        ),
+    }
+}
+
+fn record_command(ttd: &OsStr, debuggee: &Path, trace_dir: &Path) -> Command {
+    let mut command = Command::new(ttd);
+
+    command
+        .arg("-accepteula")
+        .arg("-out")
+        .arg(trace_dir)
+        .arg(debuggee);
+
+    command
+}
+
+/// TTD names the trace after the debuggee, e.g. `my-test01.run`
+fn find_trace(trace_dir: &Path) -> anyhow::Result<Option<PathBuf>> {
+    for entry in std::fs::read_dir(trace_dir)? {
+        let path = entry?.path();
+
+        if path.extension() == Some(OsStr::new("run")) {
+            return Ok(Some(path));
+        }
+    }
+
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{ffi::OsStr, path::Path};
+
+    #[test]
+    fn record_command() {
+        let command = super::record_command(
+            OsStr::new("TTD.exe"),
+            Path::new("target/debug/my-test.exe"),
+            Path::new("out/my-test@debug/ttd"),
+        );
+
+        assert_eq!(command.get_program(), "TTD.exe");
+        assert_eq!(
+            command.get_args().collect::<Vec<_>>(),
+            vec![
+                "-accepteula",
+                "-out",
+                "out/my-test@debug/ttd",
+                "target/debug/my-test.exe"
+            ]
+        );
+    }
+}
@@ -0,0 +1,111 @@
+//! Running CDB under Wine (`--wine`), so that tests for Windows can get some coverage on hosts
+//! without Windows. Every debugger given as an `.exe` is run under Wine, and the paths passed
+//! to it are translated to the `Z:` drive, which Wine maps to the root of the host's file
+//! system. Scripts can tell via `@wine`.
+
+use std::{
+    ffi::{OsStr, OsString},
+    path::{Component, Path, PathBuf},
+    process::Command,
+};
+
+/// The variable that is defined for debuggers running under Wine
+pub const VARIABLE: &str = "@wine";
+
+/// Whether the debugger `command` has to run under Wine
+pub fn runs_under_wine(command: &Path) -> bool {
+    command
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("exe"))
+}
+
+/// Translates an absolute host path into the path Windows programs see it at under Wine,
+/// e.g. `/tmp/out/script.txt` into `Z:\tmp\out\script.txt`. Relative paths keep working as
+/// they are, apart from the separators.
+pub fn windows_path(path: &Path) -> PathBuf {
+    let mut windows_path = OsString::new();
+
+    for component in path.components() {
+        match component {
+            Component::RootDir => windows_path.push("Z:"),
+            Component::Normal(name) => {
+                if !windows_path.is_empty() {
+                    windows_path.push("\\");
+                }
+                windows_path.push(name);
+            }
+            Component::CurDir => {}
+            Component::ParentDir => {
+                if !windows_path.is_empty() {
+                    windows_path.push("\\");
+                }
+                windows_path.push("..");
+            }
+            Component::Prefix(prefix) => windows_path.push(prefix.as_os_str()),
+        }
+    }
+
+    PathBuf::from(windows_path)
+}
+
+/// Turns `command` into one running the same program with the same arguments and environment
+/// under `wine`
+pub fn wrap(command: &Command, wine: &OsStr) -> Command {
+    let mut wrapped = Command::new(wine);
+
+    // Wine's own diagnostics would end up in the debugger's stderr
+    wrapped.env("WINEDEBUG", "-all");
+
+    for (name, value) in command.get_envs() {
+        match value {
+            Some(value) => wrapped.env(name, value),
+            None => wrapped.env_remove(name),
+        };
+    }
+
+    wrapped.arg(command.get_program()).args(command.get_args());
+
+    wrapped
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::{Path, PathBuf},
+        process::Command,
+    };
+
+    #[test]
+    fn windows_path() {
+        assert_eq!(
+            super::windows_path(Path::new("/tmp/out/my-test@debug/cdb-live.txt")),
+            PathBuf::from("Z:\\tmp\\out\\my-test@debug\\cdb-live.txt")
+        );
+        assert_eq!(
+            super::windows_path(Path::new("./target/debug/my-test.exe")),
+            PathBuf::from("target\\debug\\my-test.exe")
+        );
+    }
+
+    #[test]
+    fn wrap() {
+        let mut command = Command::new("/opt/windbg/cdb.exe");
+        command
+            .arg("-cf")
+            .arg("Z:\\tmp\\script.txt")
+            .env("FOO", "1");
+
+        let wrapped = super::wrap(&command, "wine64".as_ref());
+
+        assert_eq!(wrapped.get_program(), "wine64");
+        assert_eq!(
+            wrapped.get_args().collect::<Vec<_>>(),
+            vec!["/opt/windbg/cdb.exe", "-cf", "Z:\\tmp\\script.txt"]
+        );
+        assert!(wrapped
+            .get_envs()
+            .any(|(name, value)| name == "FOO" && value == Some("1".as_ref())));
+        assert!(super::runs_under_wine(Path::new("/opt/windbg/cdb.exe")));
+        assert!(!super::runs_under_wine(Path::new("/usr/bin/gdb")));
+    }
+}
@@ -1,129 +1,1220 @@
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashMap, HashSet},
+    ffi::{OsStr, OsString},
+    fmt::Write,
+    io::IsTerminal,
     path::{Path, PathBuf},
-    process::Command,
+    process::{Command, Output, Stdio},
     sync::Arc,
+    time::Duration,
 };
 
 use anyhow::{bail, Context};
-use log::debug;
-use rayon::{prelude::*, ThreadPoolBuilder};
+use log::{debug, warn};
+use rayon::{prelude::*, ThreadPool, ThreadPoolBuilder};
 use regex::Regex;
 
 use crate::{
-    cargo_test_directory::{CargoWorkspace, TestDefinition},
-    debugger::{self, Debugger, DebuggerOutput},
+    baseline, breakpoints,
+    cargo_metadata::{self, CargoMetadata},
+    cargo_profiles,
+    cargo_test_directory::{BuildConfig, CargoPackage, CargoWorkspace, TestDefinition},
+    codesign,
+    debugger::{
+        self, Debugger, DebuggerExitStatus, DebuggerKind, DebuggerOutput, ResourceUsage,
+        RunMeasurements, SessionOptions,
+    },
+    diagnostics,
+    embedded::GdbServer,
     import_export::GeneratedCrashDump,
-    prettify_path,
-    script::PhaseConfig,
+    library_runner, manifest, minimize, prettify_path, recording, reproducible, scaffold,
+    script::{instantiate_params, parse_script, EvaluationContext, PhaseConfig, Script, Statement},
+    static_checks::{self, StaticTools},
+    support_crate, test_config,
     test_result::{Status, TestResult},
+    ttd,
 };
 
 pub struct CompiledTestCases {
-    /// The target directory that Cargo used when compiling the workspace
+    /// The directory containing an executable directory for each Cargo profile, i.e. the
+    /// target directory Cargo used when compiling the workspace, or the target triple's
+    /// subdirectory of it when compiling for a specific target
     pub cargo_target_directory: PathBuf,
 
+    /// The toolchain and target the workspace was compiled with
+    pub build: BuildConfig,
+
     /// The Cargo workspace containing all test projects
     pub cargo_workspace: Arc<CargoWorkspace>,
 
     pub cargo_profiles: Vec<Arc<str>>,
+
+    /// The root paths of the packages that were not compiled because the `targets` in their
+    /// `dbt-test.toml`, or the #targets of all their tests, don't include the target
+    pub skipped_packages: Vec<PathBuf>,
+
+    /// The names of the tests that are not run because their #targets don't include the
+    /// target
+    pub skipped_tests: Vec<Arc<str>>,
+
+    /// The packages that could not be compiled, whose tests are reported as
+    /// [Status::BuildFailed] instead of being run
+    pub build_failures: Vec<BuildOutput>,
+
+    /// The compiler's diagnostics for the packages that were compiled, if there were any
+    /// (e.g. warnings), which are attached to the results of their tests
+    pub build_outputs: Vec<BuildOutput>,
+
+    /// The test programs Cargo reported for each Cargo profile
+    pub test_programs: HashMap<String, TestPrograms>,
+}
+
+/// The test programs of a Cargo profile where Cargo reported them, by the executable name of
+/// their tests. They are saved to [TEST_PROGRAMS_FILE_NAME] in the executable directory of
+/// the profile for `--skip-rebuild`.
+pub type TestPrograms = BTreeMap<String, PathBuf>;
+
+/// The file in the executable directory of a Cargo profile that [TestPrograms] are saved to
+pub const TEST_PROGRAMS_FILE_NAME: &str = "dbt-test-programs.json";
+
+/// What the compiler reported for a package compiled for a Cargo profile
+#[derive(Debug, Clone)]
+pub struct BuildOutput {
+    pub package_root_path: PathBuf,
+    pub cargo_profile: String,
+    /// The compiler's diagnostics as rendered by Cargo, or why the package could not be
+    /// compiled otherwise
+    pub output: String,
+}
+
+impl CompiledTestCases {
+    /// Whether the test is not run for the target, see [CompiledTestCases::skipped_packages]
+    /// and [CompiledTestCases::skipped_tests]
+    pub fn is_skipped(&self, test_definition: &TestDefinition) -> bool {
+        self.skipped_tests.contains(&test_definition.name)
+            || self
+                .skipped_packages
+                .iter()
+                .any(|root_path| test_definition.absolute_source_path.starts_with(root_path))
+    }
+
+    /// The failure to compile the package of `test_definition` for `cargo_profile`, if any
+    pub fn build_failure(
+        &self,
+        test_definition: &TestDefinition,
+        cargo_profile: &str,
+    ) -> Option<&BuildOutput> {
+        find_build_output(&self.build_failures, test_definition, cargo_profile)
+    }
+
+    /// The compiler's diagnostics for the package of `test_definition`, empty if there were
+    /// none
+    pub fn build_output(&self, test_definition: &TestDefinition, cargo_profile: &str) -> &str {
+        match find_build_output(&self.build_outputs, test_definition, cargo_profile) {
+            Some(build_output) => &build_output.output,
+            None => "",
+        }
+    }
+
+    /// The test program of `test_definition` for `cargo_profile`, see [test_program_path]
+    pub fn test_program(&self, cargo_profile: &str, test_definition: &TestDefinition) -> PathBuf {
+        test_program_path(
+            &self.cargo_target_directory.join(cargo_profile),
+            self.test_programs.get(cargo_profile),
+            test_definition,
+        )
+    }
+}
+
+/// The test program of `test_definition` where Cargo reported it among `test_programs`, or
+/// where dbt puts it itself in `executable_directory` otherwise (e.g. the C baselines)
+pub fn test_program_path(
+    executable_directory: &Path,
+    test_programs: Option<&TestPrograms>,
+    test_definition: &TestDefinition,
+) -> PathBuf {
+    let executable_name = test_definition.executable_name.to_string_lossy();
+
+    if let Some(path) =
+        test_programs.and_then(|test_programs| test_programs.get(&executable_name[..]))
+    {
+        return path.clone();
+    }
+
+    let path = executable_directory.join(&test_definition.executable_name);
+
+    // Executables cross-compiled for Windows (e.g. for running CDB under Wine) end in `.exe`
+    let exe_path = path.with_extension("exe");
+
+    if !path.exists() && exe_path.exists() {
+        exe_path
+    } else {
+        path
+    }
+}
+
+/// Loads the [TestPrograms] saved by [compile_cargo_tests] to `executable_directory`, none if
+/// the tests were not compiled by this version of dbt
+pub fn load_test_programs(executable_directory: &Path) -> anyhow::Result<TestPrograms> {
+    let path = executable_directory.join(TEST_PROGRAMS_FILE_NAME);
+
+    if !path.exists() {
+        return Ok(TestPrograms::new());
+    }
+
+    let test_programs: TestPrograms = serde_json::from_str(&std::fs::read_to_string(&path)?)
+        .with_context(|| format!("Could not parse {}", prettify_path(&path)))?;
+
+    // Relative paths keep working for an imported cache, see [save_test_programs]
+    Ok(test_programs
+        .into_iter()
+        .map(|(executable_name, path)| (executable_name, executable_directory.join(path)))
+        .collect())
+}
+
+/// Saves `test_programs` to `executable_directory`, with the paths relative to it where they
+/// are inside of it
+fn save_test_programs(
+    executable_directory: &Path,
+    test_programs: &TestPrograms,
+) -> anyhow::Result<()> {
+    let relative: TestPrograms = test_programs
+        .iter()
+        .map(|(executable_name, path)| {
+            let path = path.strip_prefix(executable_directory).unwrap_or(path);
+            (executable_name.clone(), path.to_path_buf())
+        })
+        .collect();
+
+    std::fs::create_dir_all(executable_directory)?;
+    std::fs::write(
+        executable_directory.join(TEST_PROGRAMS_FILE_NAME),
+        serde_json::to_string_pretty(&relative)?,
+    )?;
+
+    Ok(())
+}
+
+fn find_build_output<'a>(
+    build_outputs: &'a [BuildOutput],
+    test_definition: &TestDefinition,
+    cargo_profile: &str,
+) -> Option<&'a BuildOutput> {
+    build_outputs.iter().find(|build_output| {
+        build_output.cargo_profile == cargo_profile
+            && test_definition
+                .absolute_source_path
+                .starts_with(&build_output.package_root_path)
+    })
+}
+
+/// Options that apply to every test run by [run_cargo_tests].
+#[derive(Debug, Clone, Default)]
+pub struct RunOptions {
+    /// Print the debugger output of failed tests
+    pub verbose: bool,
+    /// Stream the debugger output of the test with this name to the console (`--verbose-test`)
+    pub verbose_test: Option<String>,
+    /// Save the output of each debugger session to this directory (`--record`)
+    pub record_directory: Option<PathBuf>,
+    /// Kill debuggers that take longer than this to run a test (`--timeout`)
+    pub timeout: Option<Duration>,
+    /// Kill debuggers that take longer than this for a single block of commands
+    /// (`--command-timeout`)
+    pub command_timeout: Option<Duration>,
+    /// Run each test this many times (`--stress`)
+    pub stress_runs: Option<usize>,
+    /// Allow the runs of a test to happen in parallel with each other (`--stress-parallel`)
+    pub stress_parallel: bool,
+    /// The tools used for `#dwarfdump-check` and `#pdb-check`
+    pub static_tools: StaticTools,
+    /// The executable recording traces for the `ttd` phase (`--ttd`), `TTD.exe` if not given
+    pub ttd: Option<OsString>,
+    /// The GDB server GDB tests are run against (`--gdb-server`), for embedded targets
+    pub gdb_server: Option<GdbServer>,
+}
+
+impl RunOptions {
+    /// How the debugger is run for `test_definition`
+    fn session_options(
+        &self,
+        test_definition: &TestDefinition,
+        debugger: &Debugger,
+    ) -> SessionOptions {
+        let stream_output = self.verbose_test.as_deref() == Some(&test_definition.name[..]);
+
+        if stream_output {
+            println!(
+                "Streaming the output of {} {} for {}:",
+                debugger.kind.name(),
+                debugger.version,
+                test_definition.name
+            );
+        }
+
+        SessionOptions {
+            timeout: test_definition.config.timeout(self.timeout),
+            command_timeout: self.command_timeout,
+            stream_output,
+        }
+    }
 }
 
 pub fn compile_cargo_tests(
     cargo_test_directory: &Path,
     cargo_target_directory: &Path,
     cargo_profiles: &Vec<String>,
+    build: &BuildConfig,
     skip_rebuild: bool,
+    support_crate: bool,
+    reproducible: bool,
 ) -> anyhow::Result<CompiledTestCases> {
-    // For now just allow debug and release Cargo profiles
-    for cargo_profile in cargo_profiles {
-        match &cargo_profile[..] {
-            "debug" | "release" => {
-                // OK
-            }
-            other => {
-                bail!(
-                    "Cargo profile `{}` is not supported. Use `debug` or `release` instead.",
-                    other
-                )
-            }
-        }
+    let mut cargo_workspace = CargoWorkspace::load(cargo_test_directory)?;
+
+    // Packages and test programs are passed to Cargo by the names it knows them by
+    if !skip_rebuild {
+        CargoMetadata::load(&cargo_workspace.root_path, build)?.apply(&mut cargo_workspace)?;
     }
 
-    let cargo_test_directory = Arc::new(CargoWorkspace::load(cargo_test_directory)?);
-    let cargo_target_directory = cargo_target_directory.canonicalize()?;
+    let cargo_test_directory = Arc::new(cargo_workspace);
+    cargo_profiles::declare(&cargo_test_directory.root_path, cargo_profiles)?;
+    let cargo_target_directory =
+        build_target_directory(&cargo_target_directory.canonicalize()?, build);
+    let executables_directory = executables_directory(&cargo_target_directory, build);
+
+    let (skipped_packages, skipped_tests) = skipped_for_target(&cargo_test_directory, build)?;
+    let mut build_failures = vec![];
+    let mut build_outputs = vec![];
+    let mut test_programs = HashMap::new();
 
     if skip_rebuild {
         println!(
             "Skipping compilation of test cases in {} due to --no-rebuild flag.",
             prettify_path(&cargo_test_directory.root_path)
+        );
+
+        for cargo_profile in cargo_profiles {
+            test_programs.insert(
+                cargo_profile.clone(),
+                load_test_programs(&executables_directory.join(cargo_profile))?,
+            );
+        }
+    } else if skipped_packages.len() == cargo_test_directory.cargo_packages.len() {
+        println!(
+            "Skipping compilation of test cases in {} as none of them are for the target",
+            prettify_path(&cargo_test_directory.root_path)
         )
     } else {
+        for package in &cargo_test_directory.cargo_packages {
+            let added = manifest::add_dependencies(package)?;
+
+            if !added.is_empty() {
+                println!(
+                    "Added {} to the dependencies of {} (via #dependencies)",
+                    added.join(", "),
+                    prettify_path(&package.root_path.join("Cargo.toml"))
+                );
+            }
+        }
+
+        let has_library_tests = cargo_test_directory
+            .cargo_packages
+            .iter()
+            .flat_map(|package| package.test_definitions.iter())
+            .any(|test_definition| test_definition.is_library());
+
+        if has_library_tests {
+            library_runner::compile(
+                &cargo_test_directory.root_path,
+                &executables_directory.join("dbt-runner"),
+                build,
+            )?;
+        }
+
+        let opt_levels = cargo_profiles::opt_levels(&cargo_test_directory.root_path)?;
+
         for cargo_profile in cargo_profiles {
-            let executable_directory = cargo_target_directory.join(cargo_profile);
+            // Where dbt puts its own files for the profile, like the C baselines. Cargo puts
+            // the test programs of `test` and `bench` elsewhere, see `TestPrograms`.
+            let executable_directory = executables_directory.join(cargo_profile);
+            std::fs::create_dir_all(&executable_directory)?;
+
+            if build.is_default() {
+                println!(
+                    "Compiling cargo test packages in {} for Cargo profile `{}`",
+                    prettify_path(&cargo_test_directory.root_path),
+                    cargo_profile
+                );
+            } else {
+                println!(
+                    "Compiling cargo test packages in {} for Cargo profile `{}` ({})",
+                    prettify_path(&cargo_test_directory.root_path),
+                    cargo_profile,
+                    build
+                );
+            }
 
-            println!(
-                "Compiling cargo test packages in {} for Cargo profile `{}`",
-                prettify_path(&cargo_test_directory.root_path),
-                cargo_profile
-            );
+            let mut cargo_env = CargoEnv {
+                rustflags: None,
+                reproducible,
+            };
+
+            if support_crate {
+                let rlib_path = support_crate::compile(
+                    &cargo_test_directory.root_path,
+                    &executables_directory
+                        .join("dbt-support")
+                        .join(cargo_profile),
+                    build,
+                    cargo_profile,
+                    &cargo_profiles::opt_level(&opt_levels, cargo_profile),
+                )?;
+
+                cargo_env.rustflags = Some(support_crate::encoded_rustflags(&rlib_path));
+            }
 
-            let mut cargo_command = Command::new("cargo");
+            if reproducible {
+                cargo_env.rustflags = Some(reproducible::with_remapped_paths(
+                    cargo_env
+                        .rustflags
+                        .unwrap_or_else(support_crate::environment_rustflags),
+                    &cargo_test_directory.root_path,
+                ));
+            }
 
-            cargo_command.arg("build");
+            // Packages with features or extra arguments in their dbt-test.toml get a
+            // `cargo build` of their own, packages with #rustc-flags or #debuggee-target are
+            // compiled by `compile_with_rustc_flags`, and all others are compiled together
+            let (separate_packages, shared_packages): (Vec<&CargoPackage>, Vec<&CargoPackage>) =
+                cargo_test_directory
+                    .cargo_packages
+                    .iter()
+                    .filter(|package| {
+                        !skipped_packages.contains(&package.root_path)
+                            && !package.builds_tests_separately()
+                    })
+                    .partition(|package| package.config.needs_separate_build());
+
+            // Each command with the packages it compiles
+            let mut cargo_commands: Vec<(Vec<&CargoPackage>, Command)> = vec![];
+
+            if !shared_packages.is_empty() {
+                let mut cargo_command = cargo_build_command(
+                    &cargo_test_directory.root_path,
+                    &cargo_target_directory,
+                    build,
+                    cargo_profile,
+                    &cargo_env,
+                );
+
+                if shared_packages.len() != cargo_test_directory.cargo_packages.len() {
+                    cargo_command.arg("--workspace");
+
+                    for package in &cargo_test_directory.cargo_packages {
+                        if !shared_packages.contains(&package) {
+                            cargo_command
+                                .arg("--exclude")
+                                .arg(&package.package_name()[..]);
+                        }
+                    }
+                }
 
-            if cargo_profile != "debug" {
-                assert_eq!(cargo_profile, "release");
-                cargo_command.arg("--release");
+                cargo_commands.push((shared_packages.clone(), cargo_command));
             }
 
-            cargo_command
-                .arg("--target-dir")
-                .arg(&cargo_target_directory);
+            for package in separate_packages {
+                let mut cargo_command = cargo_build_command(
+                    &cargo_test_directory.root_path,
+                    &cargo_target_directory,
+                    build,
+                    cargo_profile,
+                    &cargo_env,
+                );
+
+                cargo_command.arg("-p").arg(&package.package_name()[..]);
+
+                if !package.config.features.is_empty() {
+                    cargo_command
+                        .arg("--features")
+                        .arg(package.config.features.join(","));
+                }
 
-            cargo_command.current_dir(&cargo_test_directory.root_path);
+                cargo_command
+                    .args(&package.config.cargo_args)
+                    .envs(&package.config.build_env);
 
-            cargo_command.env("CARGO_INCREMENTAL", "0");
+                cargo_commands.push((vec![package], cargo_command));
+            }
 
-            debug!("Cargo command: {:?}", cargo_command);
+            // The packages of this profile that could not be compiled, with the reason
+            let mut failed_packages: Vec<(&CargoPackage, String)> = vec![];
+            let mut compiler_messages = BTreeMap::new();
 
-            let exit_status = cargo_command.status()?;
+            // The test programs Cargo reported, by the path of their source
+            let mut artifacts = HashMap::new();
+            let mut profile_test_programs = TestPrograms::new();
 
-            if exit_status.success() {
-                for test_project_def in &cargo_test_directory.cargo_packages {
-                    for test_def in &test_project_def.test_definitions {
-                        let expected_executable =
-                            executable_directory.join(&test_def.executable_name);
-                        if !expected_executable.exists() {
-                            bail!(
-                                "Expected test executable at {} but it does not exist.",
-                                prettify_path(&expected_executable)
-                            )
-                        }
+            for (packages, mut cargo_command) in cargo_commands {
+                let error = match run_compilation(&mut cargo_command, &mut compiler_messages) {
+                    Ok(output) => {
+                        artifacts.extend(built_artifacts(&String::from_utf8_lossy(&output.stdout)));
+                        continue;
                     }
+                    Err(e) => e,
+                };
+
+                if packages.len() == 1 {
+                    failed_packages.push((packages[0], format!("{:#}", error)));
+                    continue;
+                }
+
+                // Compile the packages one by one to find out which of them failed
+                for package in packages {
+                    let mut cargo_command = cargo_build_command(
+                        &cargo_test_directory.root_path,
+                        &cargo_target_directory,
+                        build,
+                        cargo_profile,
+                        &cargo_env,
+                    );
+                    cargo_command.arg("-p").arg(&package.package_name()[..]);
+
+                    match run_compilation(&mut cargo_command, &mut compiler_messages) {
+                        Ok(output) => artifacts
+                            .extend(built_artifacts(&String::from_utf8_lossy(&output.stdout))),
+                        Err(e) => failed_packages.push((package, format!("{:#}", e))),
+                    }
+                }
+            }
+
+            for package in &cargo_test_directory.cargo_packages {
+                if skipped_packages.contains(&package.root_path)
+                    || failed_packages
+                        .iter()
+                        .any(|(failed, _)| failed.root_path == package.root_path)
+                {
+                    continue;
+                }
+
+                record_artifacts(package, &artifacts, &mut profile_test_programs);
+
+                let compiled = compile_harnesses(
+                    package,
+                    &cargo_target_directory,
+                    &mut profile_test_programs,
+                    build,
+                    cargo_profile,
+                    &cargo_env,
+                    &mut compiler_messages,
+                )
+                .and_then(|()| {
+                    compile_with_rustc_flags(
+                        package,
+                        &cargo_target_directory,
+                        &mut profile_test_programs,
+                        build,
+                        cargo_profile,
+                        &cargo_env,
+                        &mut compiler_messages,
+                    )
+                })
+                .and_then(|()| {
+                    compile_baselines(package, &executable_directory, build, cargo_profile)
+                });
+
+                if let Err(e) = compiled {
+                    failed_packages.push((package, format!("{:#}", e)));
+                }
+            }
+
+            for (root_path, messages) in compiler_messages {
+                if !failed_packages
+                    .iter()
+                    .any(|(failed, _)| failed.root_path == root_path)
+                {
+                    build_outputs.push(BuildOutput {
+                        package_root_path: root_path,
+                        cargo_profile: cargo_profile.clone(),
+                        output: messages.concat(),
+                    });
                 }
             }
 
-            if !exit_status.success() {
-                bail!("test case compilation failed");
+            for (package, output) in failed_packages {
+                println!(
+                    "Could not compile {} for Cargo profile `{}`, its tests are reported as \
+                     failed builds",
+                    prettify_path(&package.root_path),
+                    cargo_profile
+                );
+
+                build_failures.push(BuildOutput {
+                    package_root_path: package.root_path.clone(),
+                    cargo_profile: cargo_profile.clone(),
+                    output,
+                });
+            }
+
+            for test_project_def in &cargo_test_directory.cargo_packages {
+                if skipped_packages.contains(&test_project_def.root_path)
+                    || build_failures.iter().any(|failure| {
+                        failure.package_root_path == test_project_def.root_path
+                            && failure.cargo_profile == *cargo_profile
+                    })
+                {
+                    continue;
+                }
+
+                for test_def in &test_project_def.test_definitions {
+                    let expected_executable = test_program_path(
+                        &executable_directory,
+                        Some(&profile_test_programs),
+                        test_def,
+                    );
+                    if !expected_executable.exists() {
+                        bail!(
+                            "Expected test executable at {} but it does not exist.",
+                            prettify_path(&expected_executable)
+                        )
+                    }
+                }
             }
+
+            save_test_programs(&executable_directory, &profile_test_programs)?;
+            test_programs.insert(cargo_profile.clone(), profile_test_programs);
         }
     }
 
     Ok(CompiledTestCases {
-        cargo_target_directory,
+        cargo_target_directory: executables_directory,
+        build: build.clone(),
         cargo_workspace: cargo_test_directory,
         cargo_profiles: cargo_profiles.iter().map(|p| Arc::from(p.trim())).collect(),
+        skipped_packages,
+        skipped_tests,
+        build_failures,
+        build_outputs,
+        test_programs,
     })
 }
 
+/// The target directory Cargo compiles the tests of `build` into
+pub fn build_target_directory(cargo_target_directory: &Path, build: &BuildConfig) -> PathBuf {
+    // Different toolchains write their executables to the same place, so give each one a
+    // separate target directory
+    match &build.toolchain {
+        Some(toolchain) => cargo_target_directory.join(format!("toolchain-{}", toolchain)),
+        None => cargo_target_directory.to_path_buf(),
+    }
+}
+
+/// The directory containing an executable directory for each Cargo profile for the tests of
+/// `build`, see [CompiledTestCases::cargo_target_directory]
+pub fn executables_directory(cargo_target_directory: &Path, build: &BuildConfig) -> PathBuf {
+    // Cargo puts the executables for an explicitly given target into a subdirectory
+    match &build.target {
+        Some(target) => cargo_target_directory.join(&target[..]),
+        None => cargo_target_directory.to_path_buf(),
+    }
+}
+
+/// The root paths of the packages that are not compiled for the target of `build`, and the
+/// names of the tests that are not run for it, see [CompiledTestCases::skipped_packages]
+fn skipped_for_target(
+    cargo_workspace: &CargoWorkspace,
+    build: &BuildConfig,
+) -> anyhow::Result<(Vec<PathBuf>, Vec<Arc<str>>)> {
+    if cargo_workspace.cargo_packages.iter().all(|package| {
+        package.config.targets.is_none()
+            && package
+                .test_definitions
+                .iter()
+                .all(|test_definition| test_definition.script.targets().is_none())
+    }) {
+        return Ok((vec![], vec![]));
+    }
+
+    let target_triple = match &build.target {
+        Some(target) => target.to_string(),
+        None => test_config::host_target(build.toolchain.as_deref())?,
+    };
+
+    let mut skipped_packages = vec![];
+    let mut skipped_tests = vec![];
+
+    for package in &cargo_workspace.cargo_packages {
+        if !package.config.allows_target(&target_triple) {
+            println!(
+                "Skipping {} as the `targets` in its {} don't include `{}`",
+                prettify_path(&package.root_path),
+                test_config::FILE_NAME,
+                target_triple
+            );
+            skipped_packages.push(package.root_path.clone());
+            continue;
+        }
+
+        let excluded: Vec<&TestDefinition> = package
+            .test_definitions
+            .iter()
+            .filter(|test_definition| !test_definition.allows_target(&target_triple))
+            .collect();
+
+        if !excluded.is_empty() && excluded.len() == package.test_definitions.len() {
+            println!(
+                "Skipping {} as the #targets of its tests don't include `{}`",
+                prettify_path(&package.root_path),
+                target_triple
+            );
+            skipped_packages.push(package.root_path.clone());
+        } else {
+            skipped_tests.extend(
+                excluded
+                    .into_iter()
+                    .map(|test_definition| test_definition.name.clone()),
+            );
+        }
+    }
+
+    Ok((skipped_packages, skipped_tests))
+}
+
+/// Builds the `#[test]` harnesses of the package's integration tests that are test
+/// programs via `cargo test --no-run`, and records them in `test_programs`, since Cargo only
+/// puts them into its `deps` directory, under a name with a hash.
+fn compile_harnesses(
+    package: &CargoPackage,
+    cargo_target_directory: &Path,
+    test_programs: &mut TestPrograms,
+    build: &BuildConfig,
+    cargo_profile: &str,
+    cargo_env: &CargoEnv,
+    compiler_messages: &mut CompilerMessages,
+) -> anyhow::Result<()> {
+    let mut harness_tests: Vec<(String, &OsStr)> = vec![];
+
+    for test_definition in &package.test_definitions {
+        // Harnesses with #rustc-flags are compiled by `compile_with_rustc_flags`
+        if !test_definition.script.rustc_flags().is_empty() {
+            continue;
+        }
+
+        if let Some(test_name) = test_definition.harness_target_name() {
+            // The instantiations of a test with #params share the harness
+            if !harness_tests.iter().any(|(name, _)| *name == test_name) {
+                harness_tests.push((test_name.into_owned(), &test_definition.executable_name));
+            }
+        }
+    }
+
+    if harness_tests.is_empty() {
+        return Ok(());
+    }
+
+    let mut cargo_command = cargo_command(
+        "test",
+        &package.root_path,
+        cargo_target_directory,
+        build,
+        cargo_profile,
+        cargo_env,
+    );
+
+    cargo_command
+        .arg("--no-run")
+        .arg("--message-format=json")
+        .arg("-p")
+        .arg(&package.package_name()[..]);
+
+    for (test_name, _) in &harness_tests {
+        cargo_command.arg("--test").arg(test_name);
+    }
+
+    if !package.config.features.is_empty() {
+        cargo_command
+            .arg("--features")
+            .arg(package.config.features.join(","));
+    }
+
+    cargo_command
+        .args(&package.config.cargo_args)
+        .envs(&package.config.build_env);
+
+    let output = run_compilation(&mut cargo_command, compiler_messages)?;
+
+    let harnesses = harness_executables(&String::from_utf8_lossy(&output.stdout));
+
+    for (test_name, executable_name) in harness_tests {
+        match harnesses.get(&test_name) {
+            Some(harness) => {
+                test_programs.insert(
+                    executable_name.to_string_lossy().into_owned(),
+                    harness.clone(),
+                );
+            }
+            None => bail!(
+                "`cargo test --no-run` did not report a harness for `{}` in {}",
+                test_name,
+                prettify_path(&package.root_path)
+            ),
+        }
+    }
+
+    Ok(())
+}
+
+/// Compiles the C baselines of the tests of a package, see [baseline]
+fn compile_baselines(
+    package: &CargoPackage,
+    executable_directory: &Path,
+    build: &BuildConfig,
+    cargo_profile: &str,
+) -> anyhow::Result<()> {
+    let mut compiled: Vec<&Path> = vec![];
+
+    for test_definition in &package.test_definitions {
+        let source_path = test_definition.absolute_source_path.as_path();
+
+        if !test_definition.is_baseline() || compiled.contains(&source_path) {
+            continue;
+        }
+
+        compiled.push(source_path);
+
+        baseline::compile(
+            source_path,
+            &executable_directory.join(&test_definition.executable_name),
+            build,
+            &test_definition.config.opt_level(cargo_profile),
+        )?;
+    }
+
+    Ok(())
+}
+
+/// Compiles the test programs of a package with `#rustc-flags` one by one via `cargo rustc`,
+/// which passes the flags to the compilation of the test program only, not to its
+/// dependencies. Harnesses without flags are left to `compile_harnesses`. The targets given
+/// via `#debuggee-target` are compiled the same way.
+fn compile_with_rustc_flags(
+    package: &CargoPackage,
+    cargo_target_directory: &Path,
+    test_programs: &mut TestPrograms,
+    build: &BuildConfig,
+    cargo_profile: &str,
+    cargo_env: &CargoEnv,
+    compiler_messages: &mut CompilerMessages,
+) -> anyhow::Result<()> {
+    if !package.builds_tests_separately() {
+        return Ok(());
+    }
+
+    // Compiling an integration test compiles the package's binaries as well, without the
+    // flags, so the harnesses go first
+    let mut test_definitions: Vec<&TestDefinition> = package.test_definitions.iter().collect();
+    test_definitions.sort_by_key(|test_definition| test_definition.harness_test_name().is_none());
+
+    let mut compiled: Vec<&OsStr> = vec![];
+
+    for test_definition in test_definitions {
+        let source_path = test_definition.absolute_source_path.as_path();
+        let executable_name = test_definition.executable_name.as_os_str();
+        let rustc_flags = test_definition.script.rustc_flags();
+        let harness_test_name = test_definition.harness_target_name();
+
+        // The instantiations of a test with #params share the test program
+        if compiled.contains(&executable_name)
+            || test_definition.is_baseline()
+            || (rustc_flags.is_empty() && harness_test_name.is_some())
+        {
+            continue;
+        }
+
+        let differing_instantiation = package.test_definitions.iter().find(|other| {
+            other.executable_name == executable_name && other.script.rustc_flags() != rustc_flags
+        });
+
+        if let Some(other) = differing_instantiation {
+            bail!(
+                "{}: `{}` and `{}` have different #rustc-flags, but share a test program",
+                prettify_path(source_path),
+                test_definition.name,
+                other.name
+            );
+        }
+
+        compiled.push(executable_name);
+
+        let mut cargo_command = cargo_command(
+            "rustc",
+            &package.root_path,
+            cargo_target_directory,
+            build,
+            cargo_profile,
+            cargo_env,
+        );
+
+        cargo_command
+            .arg("-p")
+            .arg(&package.package_name()[..])
+            .args(test_definition.cargo_target_args());
+
+        if !package.config.features.is_empty() {
+            cargo_command
+                .arg("--features")
+                .arg(package.config.features.join(","));
+        }
+
+        cargo_command
+            .args(&package.config.cargo_args)
+            .envs(&package.config.build_env)
+            .arg("--message-format=json");
+
+        if !rustc_flags.is_empty() {
+            cargo_command
+                .arg("--")
+                .args(rustc_flags.iter().map(|flag| &flag[..]));
+        }
+
+        let output = run_compilation(&mut cargo_command, compiler_messages)?;
+
+        record_artifacts(
+            package,
+            &built_artifacts(&String::from_utf8_lossy(&output.stdout)),
+            test_programs,
+        );
+
+        if let Some(test_name) = harness_test_name {
+            match harness_executables(&String::from_utf8_lossy(&output.stdout)).get(&test_name[..])
+            {
+                Some(harness) => {
+                    test_programs.insert(
+                        executable_name.to_string_lossy().into_owned(),
+                        harness.clone(),
+                    );
+                }
+                None => bail!(
+                    "`cargo rustc` did not report a harness for `{}` in {}",
+                    test_name,
+                    prettify_path(&package.root_path)
+                ),
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// The diagnostics of the compiler as rendered by Cargo, by the root path of the package
+/// they are about
+type CompilerMessages = BTreeMap<PathBuf, Vec<String>>;
+
+/// Runs a Cargo command compiling test programs with `--message-format=json`, adding the
+/// compiler's diagnostics to `compiler_messages` and failing with them if the compilation
+/// fails. They are passed on to the console either way, and Cargo's progress on stderr as
+/// it arrives.
+fn run_compilation(
+    cargo_command: &mut Command,
+    compiler_messages: &mut CompilerMessages,
+) -> anyhow::Result<Output> {
+    debug!("Cargo command: {:?}", cargo_command);
+
+    let mut child = cargo_command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // Read stderr on a separate thread so that Cargo doesn't block on a full pipe
+    let mut stderr_pipe = child.stderr.take().unwrap();
+    let stderr_thread = std::thread::spawn(move || -> std::io::Result<Vec<u8>> {
+        let mut stderr = vec![];
+        let mut buffer = [0; 4096];
+
+        loop {
+            match std::io::Read::read(&mut stderr_pipe, &mut buffer) {
+                Ok(0) => return Ok(stderr),
+                Ok(len) => {
+                    // The console going away must not fail the compilation
+                    let mut console = std::io::stderr();
+                    let _ = std::io::Write::write_all(&mut console, &buffer[..len]);
+                    let _ = std::io::Write::flush(&mut console);
+
+                    stderr.extend_from_slice(&buffer[..len]);
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(e) => return Err(e),
+            }
+        }
+    });
+
+    let mut stdout = vec![];
+    std::io::Read::read_to_end(&mut child.stdout.take().unwrap(), &mut stdout)?;
+
+    let output = Output {
+        status: child.wait()?,
+        stdout,
+        stderr: stderr_thread.join().unwrap()?,
+    };
+    let mut rendered_messages = String::new();
+
+    for (root_path, rendered) in
+        rendered_compiler_messages(&String::from_utf8_lossy(&output.stdout))
+    {
+        rendered_messages.push_str(&rendered);
+
+        // Cargo repeats the diagnostics of packages that are already compiled
+        let messages = compiler_messages.entry(root_path).or_default();
+        if !messages.contains(&rendered) {
+            messages.push(rendered);
+        }
+    }
+
+    eprint!("{}", rendered_messages);
+
+    if !output.status.success() {
+        // The failure ends up in the reports, which must not contain the console's colors
+        let color = Regex::new("\x1b\\[[0-9;]*m").unwrap();
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!(
+            "{}{}",
+            rendered_messages,
+            color.replace_all(stderr.trim_end(), "")
+        );
+    }
+
+    Ok(output)
+}
+
+/// The rendered diagnostics in the output of a Cargo command with `--message-format=json`,
+/// with the root path of the package each is about
+fn rendered_compiler_messages(cargo_output: &str) -> Vec<(PathBuf, String)> {
+    let mut messages = vec![];
+
+    for line in cargo_output.lines() {
+        let message = match serde_json::from_str::<serde_json::Value>(line) {
+            Ok(message) => message,
+            Err(_) => continue,
+        };
+
+        if message["reason"] != "compiler-message" {
+            continue;
+        }
+
+        if let (Some(manifest_path), Some(rendered)) = (
+            message["manifest_path"].as_str(),
+            message["message"]["rendered"].as_str(),
+        ) {
+            if let Some(root_path) = Path::new(manifest_path).parent() {
+                messages.push((root_path.to_path_buf(), rendered.to_string()));
+            }
+        }
+    }
+
+    messages
+}
+
+/// The executables of the integration test harnesses, by test name, from the output of
+/// `cargo test --no-run --message-format=json`
+fn harness_executables(cargo_output: &str) -> HashMap<String, PathBuf> {
+    let mut harnesses = HashMap::new();
+
+    for line in cargo_output.lines() {
+        let message = match serde_json::from_str::<serde_json::Value>(line) {
+            Ok(message) => message,
+            Err(_) => continue,
+        };
+
+        let is_test_target = message["target"]["kind"]
+            .as_array()
+            .is_some_and(|kinds| kinds.iter().any(|kind| kind == "test"));
+
+        if message["reason"] == "compiler-artifact" && is_test_target {
+            if let (Some(name), Some(executable)) = (
+                message["target"]["name"].as_str(),
+                message["executable"].as_str(),
+            ) {
+                harnesses.insert(name.to_string(), PathBuf::from(executable));
+            }
+        }
+    }
+
+    harnesses
+}
+
+/// The test programs (executables and dynamic libraries) in the output of a Cargo command
+/// with `--message-format=json`, by the path of their crate root
+fn built_artifacts(cargo_output: &str) -> HashMap<PathBuf, PathBuf> {
+    let mut artifacts = HashMap::new();
+
+    for line in cargo_output.lines() {
+        let message = match serde_json::from_str::<serde_json::Value>(line) {
+            Ok(message) => message,
+            Err(_) => continue,
+        };
+
+        if message["reason"] != "compiler-artifact" {
+            continue;
+        }
+
+        let src_path = match message["target"]["src_path"].as_str() {
+            Some(src_path) => cargo_metadata::canonical(Path::new(src_path)),
+            None => continue,
+        };
+
+        let library = message["filenames"].as_array().and_then(|filenames| {
+            filenames
+                .iter()
+                .filter_map(|filename| filename.as_str())
+                .find(|filename| {
+                    Path::new(filename).extension().is_some_and(|extension| {
+                        ["so", "dylib", "dll"].iter().any(|e| extension == *e)
+                    })
+                })
+        });
+
+        if let Some(artifact) = message["executable"].as_str().or(library) {
+            artifacts.insert(src_path, PathBuf::from(artifact));
+        }
+    }
+
+    artifacts
+}
+
+/// Records the test programs of `package` among `artifacts` (see [built_artifacts]) in
+/// `test_programs`. The debuggers are started on them where Cargo put them, so the files with
+/// their debuginfo (`.dwp`, `.pdb`, `.dSYM`) stay next to them, and targets with a name other
+/// than the one dbt derives from their source path are found.
+fn record_artifacts(
+    package: &CargoPackage,
+    artifacts: &HashMap<PathBuf, PathBuf>,
+    test_programs: &mut TestPrograms,
+) {
+    for test_definition in &package.test_definitions {
+        // The target of a test with #debuggee-target has a source of its own
+        let source_path = match &test_definition.cargo_target {
+            Some(target) => cargo_metadata::canonical(&target.src_path),
+            None => cargo_metadata::canonical(&test_definition.absolute_source_path),
+        };
+
+        if let Some(artifact) = artifacts.get(&source_path) {
+            test_programs.insert(
+                test_definition
+                    .executable_name
+                    .to_string_lossy()
+                    .into_owned(),
+                artifact.clone(),
+            );
+        }
+    }
+}
+
+/// The environment dbt sets for the Cargo commands compiling the test programs of a
+/// workspace, in addition to the `build-env` of the packages
+struct CargoEnv {
+    /// For `CARGO_ENCODED_RUSTFLAGS`, e.g. making the support crate available
+    rustflags: Option<OsString>,
+    /// Whether `SOURCE_DATE_EPOCH` is pinned, see [reproducible]
+    reproducible: bool,
+}
+
+/// `cargo build` for the workspace at `root_path`, with the settings of `build` and
+/// `cargo_profile`
+fn cargo_build_command(
+    root_path: &Path,
+    cargo_target_directory: &Path,
+    build: &BuildConfig,
+    cargo_profile: &str,
+    cargo_env: &CargoEnv,
+) -> Command {
+    let mut cargo_command = cargo_command(
+        "build",
+        root_path,
+        cargo_target_directory,
+        build,
+        cargo_profile,
+        cargo_env,
+    );
+
+    cargo_command.arg("--message-format=json");
+    cargo_command
+}
+
+fn cargo_command(
+    subcommand: &str,
+    root_path: &Path,
+    cargo_target_directory: &Path,
+    build: &BuildConfig,
+    cargo_profile: &str,
+    cargo_env: &CargoEnv,
+) -> Command {
+    let mut cargo_command = Command::new("cargo");
+
+    if let Some(toolchain) = &build.toolchain {
+        cargo_command.arg(format!("+{}", toolchain));
+    }
+
+    cargo_command.arg(subcommand);
+
+    cargo_command.args(cargo_profiles::cargo_args(cargo_profile));
+
+    // Cargo's stderr is piped through dbt, see [run_compilation]
+    let color = if std::io::stderr().is_terminal() {
+        "always"
+    } else {
+        "never"
+    };
+    cargo_command.arg("--color").arg(color);
+
+    if let Some(target) = &build.target {
+        cargo_command.arg("--target").arg(&target[..]);
+    }
+
+    cargo_command
+        .arg("--target-dir")
+        .arg(cargo_target_directory);
+
+    cargo_command.current_dir(root_path);
+
+    cargo_command.env("CARGO_INCREMENTAL", "0");
+
+    if let Some(rustflags) = &cargo_env.rustflags {
+        cargo_command.env("CARGO_ENCODED_RUSTFLAGS", rustflags);
+    }
+
+    if cargo_env.reproducible {
+        cargo_command.env("SOURCE_DATE_EPOCH", reproducible::SOURCE_DATE_EPOCH);
+    }
+
+    cargo_command
+}
+
+/// Signs all test executables so that LLDB may debug them (`--codesign`)
+pub fn sign_debuggees(test_cases: &CompiledTestCases, identity: &str) -> anyhow::Result<()> {
+    let entitlements = codesign::write_entitlements(&test_cases.cargo_target_directory)?;
+
+    for cargo_profile in &test_cases.cargo_profiles {
+        for cargo_package in &test_cases.cargo_workspace.cargo_packages {
+            for test_definition in &cargo_package.test_definitions {
+                codesign::sign(
+                    &debuggee_launch(test_cases, cargo_profile, test_definition).0,
+                    identity,
+                    &entitlements,
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
 pub fn run_cargo_tests(
     test_cases: &CompiledTestCases,
     debugger: &Debugger,
     output_dir: &Path,
     test_pattern: Option<&Regex>,
     test_threads: Option<usize>,
-    verbose: bool,
+    options: &RunOptions,
 ) -> anyhow::Result<(Vec<TestResult>, Vec<GeneratedCrashDump>)> {
     assert_eq!(output_dir, output_dir.canonicalize()?);
 
@@ -132,45 +1223,70 @@ pub fn run_cargo_tests(
     let mut generated_crashdumps = vec![];
 
     for cargo_profile in &test_cases.cargo_profiles {
-        let phases_evaluation_context =
-            debugger.evaluation_context(cargo_profile, &PhaseConfig::Live);
+        let phases_evaluation_context = |test_definition: &TestDefinition| {
+            debugger.evaluation_context(&test_definition.config, cargo_profile, &PhaseConfig::Live)
+        };
 
-        let test_count: usize = test_cases
+        // Tests that are not for the target are left out rather than reported as skipped,
+        // unless another test depends on them
+        let (skipped_for_target, tests_to_run): (Vec<_>, Vec<_>) = test_cases
             .cargo_workspace
             .cargo_packages
             .iter()
-            .map(|p| {
-                p.test_definitions
+            .flat_map(|test_project_def| {
+                test_project_def
+                    .test_definitions
                     .iter()
-                    .filter(|test_def| test_def.matches(test_pattern))
-                    .map(|test_def| test_def.script.phases(&phases_evaluation_context).len())
-                    .sum::<usize>()
+                    .filter(|td| td.matches(test_pattern))
+            })
+            .partition(|td| test_cases.is_skipped(td));
+
+        let layers = dependency_layers(test_cases, tests_to_run, &phases_evaluation_context)?;
+
+        let test_count: usize = layers
+            .iter()
+            .flatten()
+            .map(|test_def| {
+                test_def
+                    .script
+                    .phases(&phases_evaluation_context(test_def))
+                    .len()
             })
             .sum();
 
-        println!();
-        println!(
-            "{} ({}) -- running {} tests for Cargo profile `{}`",
+        let mut header = format!(
+            "{} ({}) -- running {} tests",
             debugger.kind.name(),
             debugger.version,
-            test_count,
-            cargo_profile,
+            test_count
         );
+
+        if let Some(runs) = options.stress_runs {
+            write!(header, " {} times each", runs).unwrap();
+        }
+
+        write!(header, " for Cargo profile `{}`", cargo_profile).unwrap();
+
+        if !test_cases.build.is_default() {
+            write!(header, " ({})", test_cases.build).unwrap();
+        }
+
         println!();
+        println!("{}", header);
 
-        let tests_to_run = test_cases
-            .cargo_workspace
-            .cargo_packages
-            .iter()
-            .flat_map(|test_project_def| {
-                test_project_def
-                    .test_definitions
-                    .iter()
-                    .filter(|td| td.matches(test_pattern))
-            })
-            .collect::<Vec<_>>();
+        if !skipped_for_target.is_empty() {
+            println!(
+                "Leaving out {} tests that are not for the target",
+                skipped_for_target.len()
+            );
+        }
 
-        let num_threads = if let Some(num_threads) = test_threads {
+        println!();
+
+        // There is only one embedded target, so its tests can't run in parallel
+        let num_threads = if options.gdb_server.is_some() && debugger.kind == DebuggerKind::Gdb {
+            1
+        } else if let Some(num_threads) = test_threads {
             num_threads.clamp(1, rayon::max_num_threads())
         } else {
             0
@@ -181,45 +1297,258 @@ pub fn run_cargo_tests(
             .num_threads(num_threads)
             .build()?;
 
-        let (test_results0, generated_crashdumps0, mut errors) = thread_pool.install(move || {
-            tests_to_run
-                .par_iter()
-                .map(|test_definition| {
+        // Whether all runs of a test passed, for deciding whether its dependents can run
+        let mut passed_by_test: HashMap<Arc<str>, bool> = HashMap::new();
+
+        for layer in layers {
+            let mut tests_to_run = vec![];
+            let mut layer_results = vec![];
+
+            for test_definition in layer {
+                let failed_dependency = test_definition
+                    .script
+                    .dependencies(&phases_evaluation_context(test_definition))
+                    .into_iter()
+                    .find(|dependency| !passed_by_test.get(dependency).copied().unwrap_or(false));
+
+                let not_run_status = if test_cases.is_skipped(test_definition) {
+                    Some(Status::Skipped(format!(
+                        "the target is not listed in the `targets` of {} or its #targets",
+                        test_config::FILE_NAME
+                    )))
+                } else if let Some(failure) =
+                    test_cases.build_failure(test_definition, cargo_profile)
+                {
+                    Some(Status::BuildFailed(failure.output.clone()))
+                } else {
+                    failed_dependency
+                        .map(|dependency| Status::DependencyFailed(dependency.to_string()))
+                };
+
+                match not_run_status {
+                    Some(status) => layer_results.extend(not_run(
+                        debugger,
+                        test_cases,
+                        test_definition,
+                        cargo_profile,
+                        status,
+                    )),
+                    None => tests_to_run.push(test_definition),
+                }
+            }
+
+            let (test_results0, generated_crashdumps0) = run_batches(
+                debugger,
+                test_cases,
+                &tests_to_run,
+                cargo_profile,
+                output_dir,
+                options,
+                &thread_pool,
+            );
+
+            layer_results.extend(test_results0);
+
+            for test_result in &layer_results {
+                *passed_by_test
+                    .entry(test_result.test_name.clone())
+                    .or_insert(true) &= test_result.status.passed() == Some(true);
+            }
+
+            test_results.extend(layer_results);
+            generated_crashdumps.extend(generated_crashdumps0);
+        }
+    }
+
+    Ok((test_results, generated_crashdumps))
+}
+
+/// Runs `tests_to_run` on `thread_pool`, each of them `--stress` times if given. A test that
+/// can't be run is reported as errored, without stopping the others.
+fn run_batches(
+    debugger: &Debugger,
+    test_cases: &CompiledTestCases,
+    tests_to_run: &[&TestDefinition],
+    cargo_profile: &Arc<str>,
+    output_dir: &Path,
+    options: &RunOptions,
+    thread_pool: &ThreadPool,
+) -> (Vec<TestResult>, Vec<GeneratedCrashDump>) {
+    // Each batch is run sequentially. Runs of the same test only go into separate
+    // batches (and thus may run in parallel) if explicitly requested.
+    let batches: Vec<Vec<(&TestDefinition, Option<usize>)>> = match options.stress_runs {
+        None => tests_to_run.iter().map(|&td| vec![(td, None)]).collect(),
+        Some(runs) if options.stress_parallel => tests_to_run
+            .iter()
+            .flat_map(|&td| (1..=runs).map(move |run| vec![(td, Some(run))]))
+            .collect(),
+        Some(runs) => tests_to_run
+            .iter()
+            .map(|&td| (1..=runs).map(|run| (td, Some(run))).collect())
+            .collect(),
+    };
+
+    thread_pool.install(move || {
+        batches
+            .par_iter()
+            .flat_map_iter(|batch| {
+                batch.iter().map(|&(test_definition, stress_run)| {
                     run_all_test_phases(
                         debugger,
                         test_cases,
                         test_definition,
                         cargo_profile,
                         output_dir,
-                        verbose,
+                        options,
+                        stress_run,
                     )
+                    .unwrap_or_else(|e| {
+                        eprintln!("Could not run {}: {:#}", test_definition.name, e);
+
+                        let status = Status::Errored(format!("{:#}", e));
+                        let test_results =
+                            not_run(debugger, test_cases, test_definition, cargo_profile, status);
+
+                        (test_results, vec![])
+                    })
                 })
-                .map(|result| match result {
-                    Ok((test_results, generated_crash_dumps)) => {
-                        (test_results, generated_crash_dumps, vec![])
-                    }
-                    Err(e) => (vec![], vec![], vec![e]),
-                })
-                .reduce(
-                    || (vec![], vec![], vec![]),
-                    |mut acc, results| {
-                        acc.0.extend(results.0);
-                        acc.1.extend(results.1);
-                        acc.2.extend(results.2);
-                        acc
-                    },
+            })
+            .reduce(
+                || (vec![], vec![]),
+                |mut acc, results| {
+                    acc.0.extend(results.0);
+                    acc.1.extend(results.1);
+                    acc
+                },
+            )
+    })
+}
+
+/// Adds the tests that `tests` depend on via `#depends-on` (transitively), even if they are
+/// not selected by the test pattern, and orders them into layers so that each test only
+/// depends on tests in earlier layers. Within a layer, tests keep their order.
+fn dependency_layers<'t>(
+    test_cases: &'t CompiledTestCases,
+    mut tests: Vec<&'t TestDefinition>,
+    context: &dyn Fn(&TestDefinition) -> EvaluationContext,
+) -> anyhow::Result<Vec<Vec<&'t TestDefinition>>> {
+    let all_tests: HashMap<&str, &TestDefinition> = test_cases
+        .cargo_workspace
+        .cargo_packages
+        .iter()
+        .flat_map(|package| &package.test_definitions)
+        .map(|test_definition| (&test_definition.name[..], test_definition))
+        .collect();
+
+    let mut index = 0;
+
+    while index < tests.len() {
+        for dependency in tests[index].script.dependencies(&context(tests[index])) {
+            let test_definition = match all_tests.get(&dependency[..]) {
+                Some(&test_definition) => test_definition,
+                None => bail!(
+                    "{} depends on unknown test `{}`",
+                    tests[index].name,
+                    dependency
+                ),
+            };
+
+            if !tests
+                .iter()
+                .any(|test| Arc::ptr_eq(&test.name, &test_definition.name))
+            {
+                tests.push(test_definition);
+            }
+        }
+
+        index += 1;
+    }
+
+    let mut layers = vec![];
+    let mut scheduled: HashSet<Arc<str>> = HashSet::new();
+
+    while !tests.is_empty() {
+        let (layer, rest): (Vec<_>, Vec<_>) = tests.into_iter().partition(|test| {
+            test.script
+                .dependencies(&context(test))
+                .iter()
+                .all(|dependency| scheduled.contains(dependency))
+        });
+
+        if layer.is_empty() {
+            bail!(
+                "The dependencies (#depends-on) of these tests form a cycle: {}",
+                rest.iter()
+                    .map(|test| &test.name[..])
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+
+        scheduled.extend(layer.iter().map(|test| test.name.clone()));
+        layers.push(layer);
+        tests = rest;
+    }
+
+    Ok(layers)
+}
+
+/// The results for a test that is not run, e.g. because one of its dependencies did not pass
+fn not_run(
+    debugger: &Debugger,
+    test_cases: &CompiledTestCases,
+    test_definition: &TestDefinition,
+    cargo_profile: &Arc<str>,
+    status: Status,
+) -> Vec<TestResult> {
+    let phases = test_definition.script.phases(&debugger.evaluation_context(
+        &test_definition.config,
+        cargo_profile,
+        &PhaseConfig::Live,
+    ));
+
+    phases
+        .iter()
+        .map(|phase| {
+            let test_result = TestResult {
+                build: test_cases.build.clone(),
+                ..TestResult::new(
+                    test_definition,
+                    debugger,
+                    cargo_profile,
+                    phase,
+                    status.clone(),
                 )
-        });
+            };
 
-        if !errors.is_empty() {
-            return Err(errors.pop().unwrap());
-        }
+            println!(
+                "test {} .. {}",
+                test_status_label(test_definition, phase, phases.len(), None),
+                test_result.status.short_description()
+            );
+
+            test_result
+        })
+        .collect()
+}
+
+fn test_status_label(
+    test_definition: &TestDefinition,
+    phase: &PhaseConfig,
+    phase_count: usize,
+    stress_run: Option<usize>,
+) -> String {
+    let mut label = test_definition.name.to_string();
 
-        test_results.extend(test_results0);
-        generated_crashdumps.extend(generated_crashdumps0);
+    if *phase != PhaseConfig::Live || phase_count != 1 {
+        write!(label, " ({})", phase).unwrap();
     }
 
-    Ok((test_results, generated_crashdumps))
+    if let Some(stress_run) = stress_run {
+        write!(label, " [run {}]", stress_run).unwrap();
+    }
+
+    label
 }
 
 fn run_all_test_phases(
@@ -228,42 +1557,54 @@ fn run_all_test_phases(
     test_definition: &TestDefinition,
     cargo_profile: &Arc<str>,
     output_dir: &Path,
-    verbose: bool,
+    options: &RunOptions,
+    stress_run: Option<usize>,
 ) -> anyhow::Result<(Vec<TestResult>, Vec<GeneratedCrashDump>)> {
-    let phases = test_definition
-        .script
-        .phases(&debugger.evaluation_context(cargo_profile, &PhaseConfig::Live));
+    let phases = test_definition.script.phases(&debugger.evaluation_context(
+        &test_definition.config,
+        cargo_profile,
+        &PhaseConfig::Live,
+    ));
+
+    let mut output_dir_for_test = output_dir_for_test(test_definition, cargo_profile, output_dir)?;
 
-    let output_dir_for_test = output_dir_for_test(test_definition, cargo_profile, output_dir)?;
+    // Each run of a stress test gets its own directory so that runs don't interfere
+    if let Some(stress_run) = stress_run {
+        output_dir_for_test.push(format!("run-{}", stress_run));
+        std::fs::create_dir_all(&output_dir_for_test)?;
+    }
 
     let mut test_results = vec![];
     let mut generated_crashdumps = vec![];
 
     for phase in &phases {
         let (test_result, crashdumps_generated_by_test) = run_test(
-            debugger,
-            test_definition,
-            &test_cases.cargo_target_directory,
-            cargo_profile,
-            phase,
+            &recording::Session {
+                debugger,
+                test_definition,
+                build: &test_cases.build,
+                cargo_profile,
+                phase,
+                stress_run,
+            },
+            test_cases,
             &output_dir_for_test,
-            verbose,
+            options,
         )?;
 
-        if *phase == PhaseConfig::Live && phases.len() == 1 {
-            println!(
-                "test {} .. {}",
-                test_definition.name,
-                test_result.status.short_description()
-            );
-        } else {
-            println!(
-                "test {} ({}) .. {}",
-                test_definition.name,
-                phase,
-                test_result.status.short_description()
-            );
-        }
+        let test_result = TestResult {
+            build: test_cases.build.clone(),
+            build_output: test_cases
+                .build_output(test_definition, cargo_profile)
+                .to_string(),
+            ..test_result
+        };
+
+        println!(
+            "test {} .. {}",
+            test_status_label(test_definition, phase, phases.len(), stress_run),
+            test_result.status.short_description()
+        );
 
         test_results.push(test_result);
         generated_crashdumps.extend(crashdumps_generated_by_test);
@@ -273,22 +1614,127 @@ fn run_all_test_phases(
 }
 
 fn run_test(
-    debugger: &Debugger,
-    test_definition: &TestDefinition,
-    cargo_target_directory: &Path,
-    cargo_profile: &Arc<str>,
-    phase: &PhaseConfig,
+    session: &recording::Session,
+    test_cases: &CompiledTestCases,
     output_dir_for_test: &Path,
-    verbose: bool,
+    options: &RunOptions,
 ) -> anyhow::Result<(TestResult, Vec<GeneratedCrashDump>)> {
-    if debugger.ignore_test(test_definition, cargo_profile, phase) {
+    let recording::Session {
+        debugger,
+        test_definition,
+        cargo_profile,
+        phase,
+        ..
+    } = *session;
+
+    if let Some(reason) = debugger.ignore_test(test_definition, cargo_profile, phase) {
+        return Ok((
+            TestResult::new(
+                test_definition,
+                debugger,
+                cargo_profile,
+                phase,
+                Status::Ignored(reason.map(|reason| reason.to_string())),
+            ),
+            vec![],
+        ));
+    }
+
+    if let Some(directive) = debugger.unsupported_directive(test_definition, cargo_profile, phase) {
+        return Ok((
+            TestResult::new(
+                test_definition,
+                debugger,
+                cargo_profile,
+                phase,
+                Status::Skipped(format!(
+                    "`{}` is not supported by {}",
+                    directive,
+                    debugger.kind.name()
+                )),
+            ),
+            vec![],
+        ));
+    }
+
+    let unmet_requirements = debugger.unmet_requirements(test_definition, cargo_profile, phase);
+
+    if !unmet_requirements.is_empty() {
+        let requirements = unmet_requirements
+            .iter()
+            .map(|requirement| format!("`{}`", requirement))
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        return Ok((
+            TestResult::new(
+                test_definition,
+                debugger,
+                cargo_profile,
+                phase,
+                Status::Skipped(format!("unmet requirement {}", requirements)),
+            ),
+            vec![],
+        ));
+    }
+
+    if *phase == PhaseConfig::Ttd && debugger.kind != DebuggerKind::Cdb {
         return Ok((
             TestResult::new(
                 test_definition,
                 debugger,
                 cargo_profile,
                 phase,
-                Status::Ignored,
+                Status::Skipped("the ttd phase is only supported by CDB".to_string()),
+            ),
+            vec![],
+        ));
+    }
+
+    // Static checks don't depend on the phase, so they only run in the live one
+    if *phase == PhaseConfig::Live {
+        let evaluation_context =
+            debugger.evaluation_context(&test_definition.config, cargo_profile, phase);
+
+        if let Some(status) = static_checks::run_static_checks(
+            test_definition,
+            &evaluation_context,
+            &test_cases.test_program(cargo_profile, test_definition),
+            &options.static_tools,
+        ) {
+            return Ok((
+                TestResult::new(test_definition, debugger, cargo_profile, phase, status),
+                vec![],
+            ));
+        }
+
+        // No need to run the debugger for tests consisting of static checks only
+        if !debugger.has_active_checks(test_definition, cargo_profile, phase)
+            && test_definition
+                .script
+                .has_static_checks(&evaluation_context)
+        {
+            return Ok((
+                TestResult::new(
+                    test_definition,
+                    debugger,
+                    cargo_profile,
+                    phase,
+                    Status::Passed,
+                ),
+                vec![],
+            ));
+        }
+    }
+
+    if let Some(reason) = debugger.skipped_by_check_filter(test_definition, cargo_profile, phase) {
+        return Ok((
+            TestResult::new(
+                test_definition,
+                debugger,
+                cargo_profile,
+                phase,
+                Status::Skipped(reason),
             ),
             vec![],
         ));
@@ -309,13 +1755,16 @@ fn run_test(
                 debugger,
                 cargo_profile,
                 phase,
-                Status::Ignored,
+                Status::Ignored(None),
             ),
             vec![],
         ));
     }
 
-    if !debugger.has_active_checks(test_definition, cargo_profile, phase) {
+    // C baselines only print values for comparison, see [baseline]
+    if !test_definition.is_baseline()
+        && !debugger.has_active_checks(test_definition, cargo_profile, phase)
+    {
         return Ok((
             TestResult::new(
                 test_definition,
@@ -330,91 +1779,538 @@ fn run_test(
         ));
     }
 
+    let script_hash = recording::script_hash(&debugger_script, output_dir_for_test);
+
     let debugger_script_path =
         save_debugger_script(debugger, debugger_script, output_dir_for_test, phase)?;
 
-    let (debugger_output, generated_crashdumps) = match phase {
-        PhaseConfig::Live => {
-            let debuggee_path =
-                local_debuggee_path(cargo_target_directory, cargo_profile, test_definition);
-
-            // Find the paths of all crashdump files this test is going to generate
-            let generated_crashdump_paths: Vec<_> = debugger
-                .active_crashdump_tags(test_definition, cargo_profile)
-                .into_iter()
-                .map(|tag| crashdump_path(output_dir_for_test, &tag))
-                .collect();
-
-            // Delete any crashdump files that may already exist
-            for crashdump_path in &generated_crashdump_paths {
-                if let Err(e) = std::fs::remove_file(crashdump_path) {
-                    if e.kind() != std::io::ErrorKind::NotFound {
-                        bail!(e);
-                    }
-                }
-            }
+    let debuggee_args = test_definition
+        .script
+        .debuggee_args(&debugger.evaluation_context(&test_definition.config, cargo_profile, phase));
+
+    let ((debugger_output, measurements), generated_crashdumps) = if let Some(replay_directory) =
+        debugger.replay_directory()
+    {
+        match recording::replay(replay_directory, session, &script_hash)? {
+            // Recordings have no arrival times or resource usage
+            Ok(debugger_output) => ((debugger_output, RunMeasurements::default()), vec![]),
+            Err(status) => {
+                return Ok((
+                    TestResult::new(test_definition, debugger, cargo_profile, phase, status),
+                    vec![],
+                ))
+            }
+        }
+    } else {
+        match phase {
+            PhaseConfig::Live => {
+                let (debuggee_path, env_vars) =
+                    debuggee_launch(test_cases, cargo_profile, test_definition);
+
+                // Find the paths of all crashdump files this test is going to generate
+                let generated_crashdump_paths: Vec<_> = debugger
+                    .active_crashdump_tags(test_definition, cargo_profile)
+                    .into_iter()
+                    .map(|tag| crashdump_path(output_dir_for_test, &tag))
+                    .collect();
+
+                let program_output_path = program_output_path(debugger, output_dir_for_test, phase);
+                let fault_dump_path = fault_dump_path(debugger, output_dir_for_test);
+
+                // Delete any crashdump files, fault dump and program output that may already
+                // exist
+                for stale_path in generated_crashdump_paths
+                    .iter()
+                    .chain([&program_output_path, &fault_dump_path])
+                {
+                    if let Err(e) = std::fs::remove_file(stale_path) {
+                        if e.kind() != std::io::ErrorKind::NotFound {
+                            bail!(e);
+                        }
+                    }
+                }
+
+                // Stopped once the debugger is done with the target
+                let _gdb_server = match &options.gdb_server {
+                    Some(gdb_server) if debugger.kind == DebuggerKind::Gdb => {
+                        Some(gdb_server.start()?)
+                    }
+                    _ => None,
+                };
+
+                let (mut debugger_output, measurements) = debugger.run(
+                    &debugger_script_path,
+                    &debuggee_path,
+                    &debuggee_args,
+                    None,
+                    &env_vars,
+                    options.session_options(test_definition, debugger),
+                )?;
+
+                // No file means the debuggee printed nothing, or never started
+                if debugger
+                    .program_output_redirect(&program_output_path, &debuggee_args)
+                    .is_some()
+                {
+                    debugger_output.program_output =
+                        Some(std::fs::read_to_string(&program_output_path).unwrap_or_default());
+                }
+
+                let mut generated_crashdumps = vec![];
+
+                // A debugger that timed out or crashed might not have gotten to generating the
+                // crashdumps
+                let generated_crashdump_paths = if matches!(
+                    debugger_output.exit_status,
+                    DebuggerExitStatus::TimedOut(_) | DebuggerExitStatus::Crashed(_)
+                ) {
+                    vec![]
+                } else {
+                    generated_crashdump_paths
+                };
+
+                // Collect information about generated crashdumps so they can be exported.
+                for crashdump_path in generated_crashdump_paths {
+                    if !crashdump_path.exists() {
+                        bail!(
+                            "Could not find expected crashdump file at: {}",
+                            prettify_path(&crashdump_path)
+                        );
+                    }
+
+                    let pdb_file = debuggee_path.with_extension("pdb");
+
+                    let extra_symbols = if pdb_file.exists() {
+                        Some(pdb_file)
+                    } else {
+                        None
+                    };
+
+                    generated_crashdumps.push(GeneratedCrashDump {
+                        crashdump_path,
+                        debuggee_path: debuggee_path.clone(),
+                        extra_symbols,
+                    });
+                }
+
+                ((debugger_output, measurements), generated_crashdumps)
+            }
+            PhaseConfig::CrashDump { tag } => {
+                let DebuggeePaths {
+                    crashdump,
+                    executable,
+                } = debuggee_paths(
+                    test_definition,
+                    cargo_profile,
+                    output_dir_for_test,
+                    test_cases,
+                    tag,
+                )?;
+
+                (
+                    debugger.run(
+                        &debugger_script_path,
+                        &executable,
+                        &[],
+                        Some(&crashdump),
+                        &test_definition.param_env_vars(),
+                        options.session_options(test_definition, debugger),
+                    )?,
+                    vec![],
+                )
+            }
+            PhaseConfig::Ttd => {
+                let (debuggee_path, env_vars) =
+                    debuggee_launch(test_cases, cargo_profile, test_definition);
+
+                let trace = ttd::record_trace(
+                    options.ttd.as_deref().unwrap_or(OsStr::new("TTD.exe")),
+                    &debuggee_path,
+                    &debuggee_args,
+                    &ttd::trace_directory(output_dir_for_test),
+                    &env_vars,
+                )?;
+
+                // CDB opens traces the same way as crashdumps
+                (
+                    debugger.run(
+                        &debugger_script_path,
+                        &debuggee_path,
+                        &debuggee_args,
+                        Some(&trace),
+                        &env_vars,
+                        options.session_options(test_definition, debugger),
+                    )?,
+                    vec![],
+                )
+            }
+        }
+    };
+
+    if let Some(record_directory) = &options.record_directory {
+        recording::save(
+            &recording::recording_path(record_directory, session),
+            &recording::Recording::new(script_hash, &debugger_output),
+        )?;
+    }
+
+    let command_timings = debugger::command_timings(
+        debugger,
+        test_definition,
+        cargo_profile,
+        phase,
+        &debugger_output.stdout,
+        &measurements.stdout_timestamps,
+    );
+
+    // Recordings have no resource usage to check the limit against
+    let max_memory = test_definition
+        .script
+        .max_memory(&debugger.evaluation_context(&test_definition.config, cargo_profile, phase))
+        .filter(|_| debugger.replay_directory().is_none());
+    let max_memory_status =
+        check_max_memory(max_memory, measurements.resource_usage, &debugger_output);
+
+    let mut test_result = process_debugger_output(
+        debugger,
+        test_definition,
+        cargo_profile,
+        phase,
+        debugger_output,
+        &measurements,
+        output_dir_for_test,
+    )?;
+
+    if options.verbose {
+        if let Status::Failed(_, ref debugger_output) = *test_result.status {
+            println!("debugger stdout:\n{}\n\n", &debugger_output.stdout);
+            println!("debugger stderr:\n{}\n\n", &debugger_output.stderr);
+        }
+    }
+
+    if let (Status::Passed, Some(max_memory_status)) = (&*test_result.status, max_memory_status) {
+        test_result.status = Box::new(max_memory_status);
+    }
+
+    if *phase == PhaseConfig::Live {
+        report_fault_dump(
+            &mut test_result.status,
+            &fault_dump_path(debugger, output_dir_for_test),
+        )?;
+    }
+
+    Ok((
+        TestResult {
+            command_timings,
+            resource_usage: measurements.resource_usage,
+            debugger_duration: measurements.duration,
+            ..test_result
+        },
+        generated_crashdumps,
+    ))
+}
+
+/// Points the message of a failed test at the dump of the debuggee at `fault_dump_path`,
+/// if it faulted. The dump of a test that passed is deleted, as the fault was expected.
+fn report_fault_dump(status: &mut Status, fault_dump_path: &Path) -> anyhow::Result<()> {
+    if !fault_dump_path.exists() {
+        return Ok(());
+    }
+
+    let note = format!(
+        "The debuggee faulted, it was dumped to {} for offline analysis.\n",
+        prettify_path(fault_dump_path)
+    );
+
+    match status {
+        Status::Failed(message, _) | Status::Errored(message) | Status::TimedOut(message) => {
+            if !message.ends_with('\n') {
+                message.push('\n');
+            }
+
+            message.push_str(&note);
+        }
+        Status::Passed => std::fs::remove_file(fault_dump_path)?,
+        _ => {}
+    }
+
+    Ok(())
+}
+
+/// Returns the status of a test that exceeded the limit set via `#max-memory`, if any.
+/// Errors if there's a limit but the debugger's memory usage was not measured.
+fn check_max_memory(
+    max_memory: Option<u64>,
+    resource_usage: Option<ResourceUsage>,
+    debugger_output: &DebuggerOutput,
+) -> Option<Status> {
+    match (max_memory, resource_usage) {
+        (Some(max_memory), Some(resource_usage))
+            if resource_usage.peak_memory_bytes > max_memory =>
+        {
+            Some(Status::Failed(
+                format!(
+                    "The debugger used {} bytes of memory, more than the {} bytes allowed by #max-memory.\n",
+                    resource_usage.peak_memory_bytes, max_memory
+                ),
+                debugger_output.clone(),
+            ))
+        }
+        // Not enforcing the limit would silently pass a test that may well exceed it
+        (Some(_), None) => Some(Status::Errored(
+            "#max-memory can't be enforced, as the debugger's memory usage can't be measured on \
+             this platform or with this debugger.\n"
+                .to_owned(),
+        )),
+        _ => None,
+    }
+}
+
+/// Finds a minimal version of the script of the test named `test_name` that still fails in
+/// the same way with `debugger` (see `dbt minimize`). Returns the lines of that script.
+pub fn minimize_test(
+    test_cases: &CompiledTestCases,
+    test_name: &str,
+    debugger: &Debugger,
+    output_dir: &Path,
+    options: &RunOptions,
+) -> anyhow::Result<Vec<String>> {
+    let (test_definition, cargo_profile) = find_test_definition(test_cases, test_name)?;
+
+    if let Some(rust_test_name) = &test_definition.baseline_of {
+        bail!(
+            "{} is the C baseline of {}, whose script it runs, so minimize that instead",
+            test_name,
+            rust_test_name
+        );
+    }
+
+    let output_dir_for_test =
+        output_dir_for_test(test_definition, cargo_profile, output_dir)?.join("minimize");
+    std::fs::create_dir_all(&output_dir_for_test)?;
+
+    // Reduced scripts must not overwrite recordings of the real one
+    let options = RunOptions {
+        record_directory: None,
+        ..options.clone()
+    };
+
+    let source = std::fs::read_to_string(&test_definition.absolute_source_path)?;
+
+    // A test with #params is minimized as the instantiation that failed
+    let source = match instantiate_params(&source)?
+        .into_iter()
+        .find(|instantiation| instantiation.params == test_definition.params)
+    {
+        Some(instantiation) => instantiation.source,
+        None => bail!("{} changed since it was compiled", test_name),
+    };
+    let split_source = minimize::split_source(&source)?;
+
+    // Returns the failure signature of the first unsuccessful phase, if any. Scripts that
+    // don't parse or can't be run don't reproduce anything.
+    let failure = |script_lines: &[String]| -> Option<String> {
+        let source = split_source.with_script_lines(script_lines);
+        let script = parse_script(&source, Some(&test_definition.absolute_source_path)).ok()?;
+
+        let candidate = TestDefinition {
+            name: test_definition.name.clone(),
+            executable_name: test_definition.executable_name.clone(),
+            absolute_source_path: test_definition.absolute_source_path.clone(),
+            script,
+            breakpoints: breakpoints::find(&source),
+            params: test_definition.params.clone(),
+            config: test_definition.config.clone(),
+            baseline_of: test_definition.baseline_of.clone(),
+            cargo_target: test_definition.cargo_target.clone(),
+        };
+
+        let phases = candidate.script.phases(&debugger.evaluation_context(
+            &test_definition.config,
+            cargo_profile,
+            &PhaseConfig::Live,
+        ));
+
+        for phase in &phases {
+            let context =
+                debugger.evaluation_context(&test_definition.config, cargo_profile, phase);
+            if has_check_before_command(&candidate.script, &context) {
+                return None;
+            }
+
+            let (test_result, _) = run_test(
+                &recording::Session {
+                    debugger,
+                    test_definition: &candidate,
+                    build: &test_cases.build,
+                    cargo_profile,
+                    phase,
+                    stress_run: None,
+                },
+                test_cases,
+                &output_dir_for_test,
+                &options,
+            )
+            .ok()?;
+
+            if test_result.status.passed() == Some(false) {
+                return Some(minimize::failure_signature(&test_result.status));
+            }
+        }
+
+        None
+    };
+
+    let original_failure = match failure(&split_source.script_lines) {
+        Some(original_failure) => original_failure,
+        None => bail!(
+            "Test `{}` does not fail with {} {}",
+            test_name,
+            debugger.kind.name(),
+            debugger.version
+        ),
+    };
+
+    minimize::minimize_lines(&split_source.script_lines, |candidate| {
+        Ok(failure(candidate).as_ref() == Some(&original_failure))
+    })
+}
+
+/// Runs the commands of the test named `test_name` with `debugger` and inserts checks
+/// matching their output into the test's source file (see `dbt scaffold-checks`). Returns
+/// the path of the source file and the number of checks inserted.
+pub fn scaffold_checks(
+    test_cases: &CompiledTestCases,
+    test_name: &str,
+    debugger: &Debugger,
+    output_dir: &Path,
+    options: &RunOptions,
+) -> anyhow::Result<(PathBuf, usize)> {
+    let (test_definition, cargo_profile) = find_test_definition(test_cases, test_name)?;
+
+    let output_dir_for_test =
+        output_dir_for_test(test_definition, cargo_profile, output_dir)?.join("scaffold");
+    std::fs::create_dir_all(&output_dir_for_test)?;
+
+    // Give each command its own correlation block so its output can be told apart
+    let test_definition_with_sequence_points = TestDefinition {
+        name: test_definition.name.clone(),
+        executable_name: test_definition.executable_name.clone(),
+        absolute_source_path: test_definition.absolute_source_path.clone(),
+        script: test_definition.script.with_sequence_points(),
+        breakpoints: test_definition.breakpoints.clone(),
+        params: test_definition.params.clone(),
+        config: test_definition.config.clone(),
+        baseline_of: test_definition.baseline_of.clone(),
+        cargo_target: test_definition.cargo_target.clone(),
+    };
+
+    let debugger_script = generate_debugger_script(
+        &test_definition_with_sequence_points,
+        debugger,
+        cargo_profile,
+        &PhaseConfig::Live,
+        &output_dir_for_test,
+    )?;
+
+    let debugger_script_path = save_debugger_script(
+        debugger,
+        debugger_script,
+        &output_dir_for_test,
+        &PhaseConfig::Live,
+    )?;
+
+    let (debuggee_path, env_vars) = debuggee_launch(test_cases, cargo_profile, test_definition);
+
+    let debuggee_args = test_definition
+        .script
+        .debuggee_args(&debugger.evaluation_context(
+            &test_definition.config,
+            cargo_profile,
+            &PhaseConfig::Live,
+        ));
+
+    let (debugger_output, _) = debugger.run(
+        &debugger_script_path,
+        &debuggee_path,
+        &debuggee_args,
+        None,
+        &env_vars,
+        options.session_options(test_definition, debugger),
+    )?;
+
+    if let DebuggerExitStatus::TimedOut(hang) = debugger_output.exit_status {
+        match hang.description(&debugger_output.stdout) {
+            Some(description) => bail!(
+                "The debugger did not finish within the timeout. {}",
+                description
+            ),
+            None => bail!("The debugger did not finish within the timeout"),
+        }
+    }
+
+    if let DebuggerExitStatus::Crashed(crash) = debugger_output.exit_status {
+        bail!("The debugger was {}", crash);
+    }
 
-            let debugger_output = debugger.run(&debugger_script_path, &debuggee_path, None)?;
+    let output_by_command = match debugger::output_by_command(
+        debugger,
+        &test_definition_with_sequence_points,
+        cargo_profile,
+        &PhaseConfig::Live,
+        &debugger_output,
+    ) {
+        Ok(output_by_command) => output_by_command,
+        Err(message) => bail!(message),
+    };
 
-            let mut generated_crashdumps = vec![];
+    let source = std::fs::read_to_string(&test_definition.absolute_source_path)?;
+    let (source, inserted) = scaffold::insert_checks(&source, &output_by_command);
+    std::fs::write(&test_definition.absolute_source_path, source)?;
 
-            // Collect information about generated crashdumps so they can be exported.
-            for crashdump_path in generated_crashdump_paths {
-                if !crashdump_path.exists() {
-                    bail!(
-                        "Could not find expected crashdump file at: {}",
-                        prettify_path(&crashdump_path)
-                    );
-                }
+    Ok((test_definition.absolute_source_path.clone(), inserted))
+}
 
-                let pdb_file = debuggee_path.with_extension("pdb");
+/// Finds the test named `test_name` and the Cargo profile to run it with, which is the
+/// first one given.
+fn find_test_definition<'t>(
+    test_cases: &'t CompiledTestCases,
+    test_name: &str,
+) -> anyhow::Result<(&'t TestDefinition, &'t Arc<str>)> {
+    let test_definition = match test_cases
+        .cargo_workspace
+        .cargo_packages
+        .iter()
+        .flat_map(|package| package.test_definitions.iter())
+        .find(|test_definition| &test_definition.name[..] == test_name)
+    {
+        Some(test_definition) => test_definition,
+        None => bail!("There is no test named `{}`", test_name),
+    };
 
-                let extra_symbols = if pdb_file.exists() {
-                    Some(pdb_file)
-                } else {
-                    None
-                };
+    let cargo_profile = match test_cases.cargo_profiles.first() {
+        Some(cargo_profile) => cargo_profile,
+        None => bail!("No Cargo profile given via --cargo-profile"),
+    };
 
-                generated_crashdumps.push(GeneratedCrashDump {
-                    crashdump_path,
-                    debuggee_path: debuggee_path.clone(),
-                    extra_symbols,
-                });
-            }
+    Ok((test_definition, cargo_profile))
+}
 
-            (debugger_output, generated_crashdumps)
-        }
-        PhaseConfig::CrashDump { tag } => {
-            let DebuggeePaths {
-                crashdump,
-                executable,
-            } = debuggee_paths(
-                test_definition,
-                cargo_profile,
-                output_dir_for_test,
-                cargo_target_directory,
-                tag,
-            )?;
+/// Whether a check comes before the first command, i.e. there is no output to check yet.
+/// Such scripts are produced while minimizing but are never valid.
+fn has_check_before_command(script: &Script, context: &EvaluationContext) -> bool {
+    let mut check_before_command = false;
 
-            (
-                debugger.run(&debugger_script_path, &executable, Some(&crashdump))?,
-                vec![],
-            )
+    script.walk_applicable_leaves(context, &mut |statement| match statement {
+        Statement::Exec(..) | Statement::Command(..) | Statement::SetBreakpoint(..) => false,
+        Statement::Check(..) | Statement::CheckUnorderedBlock(..) | Statement::CommandCheck(..) => {
+            check_before_command = true;
+            false
         }
-    };
+        _ => true,
+    });
 
-    Ok((
-        process_debugger_output(
-            debugger,
-            test_definition,
-            cargo_profile,
-            phase,
-            debugger_output,
-            output_dir_for_test,
-            verbose,
-        )?,
-        generated_crashdumps,
-    ))
+    check_before_command
 }
 
 struct DebuggeePaths {
@@ -422,27 +2318,42 @@ struct DebuggeePaths {
     crashdump: PathBuf,
 }
 
-// outdir/target/{cargo_profile}/{executable_name}
-fn local_debuggee_path(
-    cargo_target_directory: &Path,
-    cargo_profile: &Arc<str>,
-    test_definition: &TestDefinition,
-) -> PathBuf {
-    cargo_target_directory
-        .join(&cargo_profile[..])
-        .join(&test_definition.executable_name)
-}
-
 fn crashdump_path(output_dir_for_test: &Path, tag: &str) -> PathBuf {
     let crashdump_dir = output_dir_for_test.join("crashdumps").join(tag);
     crashdump_dir.join("crashdump.dmp")
 }
 
+/// The program the debugger starts for the test, along with the environment variables to
+/// start it with. For tests of dynamic libraries, that is the library runner, which loads
+/// the test's library.
+fn debuggee_launch(
+    test_cases: &CompiledTestCases,
+    cargo_profile: &Arc<str>,
+    test_definition: &TestDefinition,
+) -> (PathBuf, Vec<(String, String)>) {
+    let debuggee_path = test_cases.test_program(cargo_profile, test_definition);
+    let mut env_vars = test_definition.param_env_vars();
+
+    if !test_definition.is_library() {
+        return (debuggee_path, env_vars);
+    }
+
+    env_vars.push((
+        library_runner::LIBRARY_VARIABLE.to_string(),
+        debuggee_path.to_string_lossy().into_owned(),
+    ));
+
+    (
+        library_runner::runner_path(&test_cases.cargo_target_directory.join("dbt-runner")),
+        env_vars,
+    )
+}
+
 fn debuggee_paths(
     test_definition: &TestDefinition,
     cargo_profile: &Arc<str>,
     output_dir_for_test: &Path,
-    cargo_target_directory: &Path,
+    test_cases: &CompiledTestCases,
     tag: &Arc<str>,
 ) -> anyhow::Result<DebuggeePaths> {
     let crashdump_file_path = crashdump_path(output_dir_for_test, tag);
@@ -463,9 +2374,8 @@ fn debuggee_paths(
         }
     }
 
-    let executable = found_executable.unwrap_or_else(|| {
-        local_debuggee_path(cargo_target_directory, cargo_profile, test_definition)
-    });
+    let executable = found_executable
+        .unwrap_or_else(|| debuggee_launch(test_cases, cargo_profile, test_definition).0);
 
     Ok(DebuggeePaths {
         crashdump: crashdump_file_path,
@@ -473,6 +2383,172 @@ fn debuggee_paths(
     })
 }
 
+/// Re-runs the checks of all matching tests against the debugger output captured in
+/// `artifacts_dir` (the `--output` directory of a previous run), without building the
+/// tests or running any debuggers.
+pub fn recheck(
+    artifacts_dir: &Path,
+    cargo_workspace: &CargoWorkspace,
+    test_pattern: Option<&Regex>,
+    make_debugger: &dyn Fn(DebuggerKind, Arc<str>) -> anyhow::Result<Debugger>,
+) -> anyhow::Result<Vec<TestResult>> {
+    let mut test_results = vec![];
+
+    let mut test_dirs = vec![];
+    for dir_entry in std::fs::read_dir(artifacts_dir)
+        .with_context(|| format!("while reading {}", prettify_path(artifacts_dir)))?
+    {
+        let dir_entry = dir_entry?;
+        if dir_entry.file_type()?.is_dir() {
+            test_dirs.push(dir_entry);
+        }
+    }
+    test_dirs.sort_by_key(|dir_entry| dir_entry.file_name());
+
+    let test_definitions = cargo_workspace
+        .cargo_packages
+        .iter()
+        .flat_map(|package| &package.test_definitions)
+        .filter(|test_definition| test_definition.matches(test_pattern));
+
+    for test_definition in test_definitions {
+        let prefix = format!("{}@", test_definition.flat_name());
+
+        for test_dir in &test_dirs {
+            let dir_name = test_dir.file_name().to_string_lossy().to_string();
+
+            let cargo_profile: Arc<str> = match dir_name.strip_prefix(&prefix) {
+                Some(cargo_profile) => cargo_profile.into(),
+                None => continue,
+            };
+
+            let mut stdout_paths = vec![];
+            for dir_entry in std::fs::read_dir(test_dir.path())? {
+                let path = dir_entry?.path();
+                if path.extension() == Some("stdout".as_ref()) {
+                    stdout_paths.push(path);
+                }
+            }
+            stdout_paths.sort();
+
+            for stdout_path in stdout_paths {
+                let file_stem = stdout_path.file_stem().unwrap().to_string_lossy();
+
+                let (debugger_kind, version, phase) = match parse_artifact_name(&file_stem) {
+                    Some(parsed) => parsed,
+                    None => {
+                        warn!("Skipping unrecognized file {}", prettify_path(&stdout_path));
+                        continue;
+                    }
+                };
+
+                let debugger = make_debugger(debugger_kind, version)?;
+
+                test_results.push(recheck_test(
+                    &debugger,
+                    test_definition,
+                    &cargo_profile,
+                    &phase,
+                    &test_dir.path(),
+                    stdout_path,
+                )?);
+            }
+        }
+    }
+
+    Ok(test_results)
+}
+
+fn recheck_test(
+    debugger: &Debugger,
+    test_definition: &TestDefinition,
+    cargo_profile: &Arc<str>,
+    phase: &PhaseConfig,
+    output_dir_for_test: &Path,
+    stdout_path: PathBuf,
+) -> anyhow::Result<TestResult> {
+    let stderr_path = stdout_path.with_extension("stderr");
+    let script_path = stdout_path.with_extension("dbgscript");
+    let exit_status_path = stdout_path.with_extension(EXIT_STATUS_EXTENSION);
+
+    let script = debugger::generate_debugger_script(
+        debugger,
+        test_definition,
+        cargo_profile,
+        phase,
+        &mut |tag| crashdump_path(output_dir_for_test, tag),
+    );
+
+    // Checks can only be matched up with the output if the commands are still the same
+    let mut test_result = if std::fs::read_to_string(&script_path).ok() != Some(script) {
+        TestResult::new(
+            test_definition,
+            debugger,
+            cargo_profile,
+            phase,
+            Status::Errored(
+                "The debugger commands in the test changed since its output was captured. \
+                 Run the test again instead.\n"
+                    .to_string(),
+            ),
+        )
+    } else {
+        // Output captured before the exit status was saved is assumed to be from a successful
+        // run
+        let exit_status = match std::fs::read_to_string(&exit_status_path) {
+            Ok(exit_status) => match DebuggerExitStatus::try_from(exit_status.trim().to_string()) {
+                Ok(exit_status) => exit_status,
+                Err(e) => bail!("{}: {}", prettify_path(&exit_status_path), e),
+            },
+            Err(_) => DebuggerExitStatus::Success,
+        };
+
+        let debugger_output = DebuggerOutput {
+            stdout: std::fs::read_to_string(&stdout_path)?,
+            stderr: std::fs::read_to_string(&stderr_path).unwrap_or_default(),
+            exit_status,
+            program_output: None,
+        };
+
+        let diagnostics = diagnostics::debugger_warnings(&debugger_output);
+
+        // The arrival times of the output weren't saved, so stderr can't be correlated
+        TestResult {
+            diagnostics,
+            ..debugger::process_debugger_output(
+                debugger,
+                test_definition,
+                debugger_output,
+                &BTreeMap::new(),
+                cargo_profile,
+                phase,
+            )
+        }
+    };
+
+    test_result.debugger_output_stdout_path = Some(stdout_path);
+    test_result.debugger_output_stderr_path = Some(stderr_path);
+
+    Ok(test_result)
+}
+
+/// Parses file names of the form `<debugger-kind>-<version>-<phase>` as written by
+/// [save_debugger_script] and [process_debugger_output].
+fn parse_artifact_name(name: &str) -> Option<(DebuggerKind, Arc<str>, PhaseConfig)> {
+    let (kind, rest) = name.split_once('-')?;
+    let (version, phase) = rest.split_once('-')?;
+
+    Some((
+        DebuggerKind::try_from(kind).ok()?,
+        version.into(),
+        PhaseConfig::from_display_string(phase)?,
+    ))
+}
+
+/// The extension of the file the exit status of a debugger is saved to, next to its output,
+/// for `dbt recheck`
+const EXIT_STATUS_EXTENSION: &str = "exit-status";
+
 fn save_debugger_script(
     debugger: &Debugger,
     script_contents: String,
@@ -490,6 +2566,36 @@ fn save_debugger_script(
     Ok(path)
 }
 
+/// The file the output of the debuggee is redirected to, see
+/// [Debugger::program_output_redirect]
+fn program_output_path(
+    debugger: &Debugger,
+    output_dir_for_test: &Path,
+    phase: &PhaseConfig,
+) -> PathBuf {
+    output_dir_for_test.join(format!(
+        "{}-{}-{}.program-output",
+        debugger.kind.name(),
+        debugger.version,
+        phase
+    ))
+}
+
+/// Where the debugger dumps the debuggee if it faults, see [Debugger::fault_dump_command]
+fn fault_dump_path(debugger: &Debugger, output_dir_for_test: &Path) -> PathBuf {
+    let extension = match debugger.kind {
+        DebuggerKind::Cdb => "dmp",
+        _ => "core",
+    };
+
+    output_dir_for_test.join(format!(
+        "{}-{}.fault.{}",
+        debugger.kind.name(),
+        debugger.version,
+        extension
+    ))
+}
+
 fn generate_debugger_script(
     test_definition: &TestDefinition,
     debugger: &Debugger,
@@ -499,7 +2605,7 @@ fn generate_debugger_script(
 ) -> anyhow::Result<String> {
     let mut crashdump_paths_generated: HashSet<PathBuf> = Default::default();
 
-    let script = debugger::generate_debugger_script(
+    let mut script = debugger::generate_debugger_script(
         debugger,
         test_definition,
         cargo_profile,
@@ -511,6 +2617,31 @@ fn generate_debugger_script(
         },
     );
 
+    // Keeps the output of the debuggee out of the debugger's stdout, where it could satisfy
+    // checks by accident
+    if matches!(phase, PhaseConfig::Live) && !script.is_empty() {
+        let debuggee_args = test_definition
+            .script
+            .debuggee_args(&debugger.evaluation_context(
+                &test_definition.config,
+                cargo_profile,
+                phase,
+            ));
+
+        if let Some(redirect) = debugger.program_output_redirect(
+            &program_output_path(debugger, output_dir_for_test, phase),
+            &debuggee_args,
+        ) {
+            script = format!("{}\n{}", redirect, script);
+        }
+
+        if let Some(command) =
+            debugger.fault_dump_command(&fault_dump_path(debugger, output_dir_for_test))
+        {
+            script = format!("{}\n{}", command, script);
+        }
+    }
+
     for path in crashdump_paths_generated {
         let directory = path.parent().unwrap();
         std::fs::create_dir_all(directory).with_context(|| {
@@ -530,8 +2661,8 @@ fn process_debugger_output(
     cargo_profile: &Arc<str>,
     phase: &PhaseConfig,
     debugger_output: DebuggerOutput,
+    measurements: &RunMeasurements,
     output_dir_for_test: &Path,
-    verbose: bool,
 ) -> anyhow::Result<TestResult> {
     let stdout_path = output_dir_for_test.join(format!(
         "{}-{}-{}.stdout",
@@ -543,25 +2674,37 @@ fn process_debugger_output(
 
     std::fs::write(&stdout_path, &debugger_output.stdout)?;
     std::fs::write(&stderr_path, &debugger_output.stderr)?;
+    std::fs::write(
+        stdout_path.with_extension(EXIT_STATUS_EXTENSION),
+        String::from(debugger_output.exit_status),
+    )?;
+
+    let printed_values = debugger::printed_values(
+        debugger,
+        test_definition,
+        cargo_profile,
+        phase,
+        &debugger_output,
+    );
+
+    let stderr_by_correlation_id =
+        debugger::stderr_by_correlation_id(&debugger_output, measurements);
+    let diagnostics = diagnostics::debugger_warnings(&debugger_output);
 
     let mut test_result = debugger::process_debugger_output(
         debugger,
         test_definition,
         debugger_output,
+        &stderr_by_correlation_id,
         cargo_profile,
         phase,
     );
 
+    test_result.printed_values = printed_values;
+    test_result.diagnostics = diagnostics;
     test_result.debugger_output_stdout_path = Some(stdout_path);
     test_result.debugger_output_stderr_path = Some(stderr_path);
 
-    if verbose {
-        if let Status::Failed(_, ref debugger_output) = *test_result.status {
-            println!("debugger stdout:\n{}\n\n", &debugger_output.stdout);
-            println!("debugger stderr:\n{}\n\n", &debugger_output.stderr);
-        }
-    }
-
     Ok(test_result)
 }
 
@@ -584,3 +2727,406 @@ fn output_dir_for_test(
 
     Ok(path)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        path::{Path, PathBuf},
+        sync::Arc,
+        time::Duration,
+    };
+
+    use crate::{
+        cargo_test_directory::{BuildConfig, CargoPackage, CargoWorkspace, TestDefinition},
+        debugger::{Debugger, DebuggerExitStatus, DebuggerKind, DebuggerOutput, ResourceUsage},
+        script::{parse_script, EvaluationContext, PhaseConfig, Script},
+        test_result::Status,
+    };
+
+    use super::CompiledTestCases;
+
+    /// Test cases named `ws/<name>` with the given dependencies
+    fn test_cases_with_dependencies(tests: &[(&str, &[&str])]) -> CompiledTestCases {
+        let test_definitions = tests
+            .iter()
+            .map(|(name, dependencies)| {
+                let mut script = String::from("/***\n");
+                for dependency in *dependencies {
+                    script.push_str(&format!("#depends-on ws/{}\n", dependency));
+                }
+                script.push_str("run\n***/\n");
+
+                TestDefinition::new(
+                    Path::new(name),
+                    &std::env::temp_dir().join(name),
+                    "ws",
+                    name.into(),
+                    parse_script(&script, None).unwrap(),
+                    vec![],
+                )
+            })
+            .collect();
+
+        CompiledTestCases {
+            cargo_target_directory: "target".into(),
+            build: BuildConfig::default(),
+            cargo_workspace: Arc::new(CargoWorkspace {
+                root_path: "ws".into(),
+                cargo_packages: vec![CargoPackage {
+                    root_path: "ws".into(),
+                    name: None,
+                    config: Default::default(),
+                    test_definitions,
+                }],
+            }),
+            cargo_profiles: vec![Arc::from("debug")],
+            skipped_packages: vec![],
+            skipped_tests: vec![],
+            build_failures: vec![],
+            build_outputs: vec![],
+            test_programs: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_programs() {
+        let executable_directory =
+            std::env::temp_dir().join(format!("dbt-test-programs-{}", std::process::id()));
+        let test_programs: super::TestPrograms = [
+            ("t1".to_string(), executable_directory.join("deps/t1-1a2b")),
+            ("t2".to_string(), PathBuf::from("/elsewhere/t2")),
+        ]
+        .into_iter()
+        .collect();
+
+        super::save_test_programs(&executable_directory, &test_programs).unwrap();
+
+        // Paths inside the executable directory are saved relative to it
+        let saved: super::TestPrograms = serde_json::from_str(
+            &std::fs::read_to_string(executable_directory.join(super::TEST_PROGRAMS_FILE_NAME))
+                .unwrap(),
+        )
+        .unwrap();
+        assert_eq!(saved["t1"], Path::new("deps").join("t1-1a2b"));
+        assert_eq!(
+            super::load_test_programs(&executable_directory).unwrap(),
+            test_programs
+        );
+
+        let test_definition = TestDefinition::new(
+            Path::new("src/main.rs"),
+            Path::new("/ws/src/main.rs"),
+            "ws",
+            "t3".into(),
+            Script::new_empty(),
+            vec![],
+        );
+        assert_eq!(
+            super::test_program_path(
+                &executable_directory,
+                Some(&test_programs),
+                &test_definition
+            ),
+            executable_directory.join("t3")
+        );
+
+        std::fs::remove_dir_all(&executable_directory).unwrap();
+    }
+
+    #[test]
+    fn harness_executables() {
+        let output = r#"{"reason":"compiler-artifact","target":{"kind":["bin"],"name":"t1"},"executable":"/t/debug/t1"}
+{"reason":"compiler-artifact","target":{"kind":["test"],"name":"vectors"},"executable":"/t/debug/deps/vectors-0123abcd"}
+{"reason":"compiler-artifact","target":{"kind":["lib"],"name":"t1"},"executable":null}
+{"reason":"build-finished","success":true}"#;
+
+        let harnesses = super::harness_executables(output);
+
+        assert_eq!(harnesses.len(), 1);
+        assert_eq!(
+            harnesses["vectors"],
+            Path::new("/t/debug/deps/vectors-0123abcd")
+        );
+    }
+
+    #[test]
+    fn built_artifacts() {
+        let output = r#"{"reason":"compiler-artifact","target":{"kind":["bin"],"name":"show-vec","src_path":"/ws/t1/src/bin/a.rs"},"filenames":["/t/debug/show-vec"],"executable":"/t/debug/show-vec"}
+{"reason":"compiler-artifact","target":{"kind":["cdylib"],"name":"t2","src_path":"/ws/t2/src/lib.rs"},"filenames":["/t/debug/libt2.so"],"executable":null}
+{"reason":"compiler-artifact","target":{"kind":["lib"],"name":"dep","src_path":"/ws/dep/src/lib.rs"},"filenames":["/t/debug/deps/libdep-0123abcd.rlib"],"executable":null}
+{"reason":"build-finished","success":true}"#;
+
+        let artifacts = super::built_artifacts(output);
+
+        assert_eq!(artifacts.len(), 2);
+        assert_eq!(
+            artifacts[Path::new("/ws/t1/src/bin/a.rs")],
+            Path::new("/t/debug/show-vec")
+        );
+        assert_eq!(
+            artifacts[Path::new("/ws/t2/src/lib.rs")],
+            Path::new("/t/debug/libt2.so")
+        );
+    }
+
+    #[test]
+    fn build_failure() {
+        let mut test_cases = test_cases_with_dependencies(&[("a", &[])]);
+        test_cases.build_failures.push(super::BuildOutput {
+            package_root_path: std::env::temp_dir(),
+            cargo_profile: "debug".to_string(),
+            output: "error[E0308]: mismatched types".to_string(),
+        });
+        test_cases.build_outputs.push(super::BuildOutput {
+            package_root_path: std::env::temp_dir(),
+            cargo_profile: "release".to_string(),
+            output: "warning: unused variable: `x`".to_string(),
+        });
+        let test = &test_cases.cargo_workspace.cargo_packages[0].test_definitions[0];
+
+        assert_eq!(
+            test_cases.build_failure(test, "debug").unwrap().output,
+            "error[E0308]: mismatched types"
+        );
+        assert!(test_cases.build_failure(test, "release").is_none());
+        assert_eq!(
+            test_cases.build_output(test, "release"),
+            "warning: unused variable: `x`"
+        );
+        assert_eq!(test_cases.build_output(test, "debug"), "");
+    }
+
+    #[test]
+    fn rendered_compiler_messages() {
+        let output = r#"{"reason":"compiler-message","manifest_path":"/ws/t1/Cargo.toml","message":{"level":"warning","rendered":"warning: unused variable: `x`\n"}}
+{"reason":"compiler-artifact","manifest_path":"/ws/t1/Cargo.toml","target":{"kind":["bin"],"name":"t1"},"executable":"/t/debug/t1"}
+{"reason":"build-finished","success":true}"#;
+
+        assert_eq!(
+            super::rendered_compiler_messages(output),
+            [(
+                Path::new("/ws/t1").to_path_buf(),
+                "warning: unused variable: `x`\n".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn report_fault_dump() {
+        let output = DebuggerOutput {
+            stdout: String::new(),
+            stderr: String::new(),
+            exit_status: DebuggerExitStatus::Success,
+            program_output: None,
+        };
+        let dump_path = std::env::temp_dir().join("dbt-report-fault-dump.core");
+
+        let mut status = Status::Failed("Check failed\n".to_string(), output.clone());
+        super::report_fault_dump(&mut status, &dump_path).unwrap();
+        assert_eq!(status, Status::Failed("Check failed\n".to_string(), output));
+
+        std::fs::write(&dump_path, "").unwrap();
+
+        super::report_fault_dump(&mut status, &dump_path).unwrap();
+        match status {
+            Status::Failed(message, _) => {
+                assert!(message.starts_with("Check failed\nThe debuggee faulted"))
+            }
+            status => panic!("unexpected status {:?}", status),
+        }
+        assert!(dump_path.exists());
+
+        super::report_fault_dump(&mut Status::Passed, &dump_path).unwrap();
+        assert!(!dump_path.exists());
+    }
+
+    #[test]
+    fn check_max_memory() {
+        let output = DebuggerOutput {
+            stdout: String::new(),
+            stderr: String::new(),
+            exit_status: DebuggerExitStatus::Success,
+            program_output: None,
+        };
+
+        let usage = Some(ResourceUsage {
+            peak_memory_bytes: 2048,
+            cpu_time: Duration::from_millis(5),
+        });
+
+        assert!(matches!(
+            super::check_max_memory(Some(1024), usage, &output),
+            Some(Status::Failed(..))
+        ));
+        assert_eq!(super::check_max_memory(Some(2048), usage, &output), None);
+        assert_eq!(super::check_max_memory(None, usage, &output), None);
+        assert!(matches!(
+            super::check_max_memory(Some(1024), None, &output),
+            Some(Status::Errored(_))
+        ));
+        assert_eq!(super::check_max_memory(None, None, &output), None);
+    }
+
+    #[test]
+    fn dependency_layers() {
+        let context = |_: &TestDefinition| EvaluationContext {
+            values: HashMap::new(),
+        };
+        let test_cases = test_cases_with_dependencies(&[
+            ("a", &["b"]),
+            ("b", &["c"]),
+            ("c", &[]),
+            ("d", &[]),
+            ("e", &["c", "d"]),
+        ]);
+        let tests = &test_cases.cargo_workspace.cargo_packages[0].test_definitions;
+
+        let layers = super::dependency_layers(&test_cases, vec![&tests[0], &tests[3]], &context)
+            .unwrap()
+            .iter()
+            .map(|layer| layer.iter().map(|test| &test.name[..]).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+
+        // `b` and `c` are run even though they were not selected
+        assert_eq!(
+            layers,
+            vec![vec!["ws/d", "ws/c"], vec!["ws/b"], vec!["ws/a"]]
+        );
+
+        let test_cases = test_cases_with_dependencies(&[("a", &["b"]), ("b", &["a"])]);
+        let tests = &test_cases.cargo_workspace.cargo_packages[0].test_definitions;
+        let error = super::dependency_layers(&test_cases, vec![&tests[0]], &context).unwrap_err();
+        assert!(error.to_string().contains("form a cycle: ws/a, ws/b"));
+
+        let test_cases = test_cases_with_dependencies(&[("a", &["x"])]);
+        let tests = &test_cases.cargo_workspace.cargo_packages[0].test_definitions;
+        let error = super::dependency_layers(&test_cases, vec![&tests[0]], &context).unwrap_err();
+        assert_eq!(error.to_string(), "ws/a depends on unknown test `ws/x`");
+    }
+
+    #[test]
+    fn recheck_test() {
+        let output_dir =
+            std::env::temp_dir().join(format!("dbt-recheck-test-{}", std::process::id()));
+        std::fs::create_dir_all(&output_dir).unwrap();
+
+        let test_definition = TestDefinition::new(
+            Path::new("main.rs"),
+            &output_dir.join("main.rs"),
+            "ws",
+            "main".into(),
+            parse_script("/***\nprint a\n#check a = 1\n***/\n", None).unwrap(),
+            vec![],
+        );
+        let cargo_profile = Arc::from("debug");
+
+        // The prelude of the run is part of the saved script
+        let make_debugger = |preludes: &[&str]| {
+            let preludes: Vec<_> = preludes.iter().map(Into::into).collect();
+            Debugger::offline(DebuggerKind::Mock, "1.0".into(), &preludes, &[]).unwrap()
+        };
+        let debugger = make_debugger(&["mock:settings"]);
+        let script = crate::debugger::generate_debugger_script(
+            &debugger,
+            &test_definition,
+            &cargo_profile,
+            &PhaseConfig::Live,
+            &mut |tag| output_dir.join(tag),
+        );
+
+        let stdout_path = output_dir.join("mock-1.0-live.stdout");
+        std::fs::write(stdout_path.with_extension("dbgscript"), script).unwrap();
+        std::fs::write(&stdout_path, "a = 1\n").unwrap();
+        std::fs::write(stdout_path.with_extension("exit-status"), "timed-out").unwrap();
+
+        let recheck = |debugger: &Debugger| {
+            super::recheck_test(
+                debugger,
+                &test_definition,
+                &cargo_profile,
+                &PhaseConfig::Live,
+                &output_dir,
+                stdout_path.clone(),
+            )
+            .unwrap()
+            .status
+        };
+
+        assert!(matches!(*recheck(&debugger), Status::TimedOut(..)));
+        assert!(matches!(
+            *recheck(&make_debugger(&[])),
+            Status::Errored(message) if message.contains("debugger commands in the test changed")
+        ));
+
+        std::fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[test]
+    fn minimize_test() {
+        let root = std::env::temp_dir().join(format!("dbt-minimize-test-{}", std::process::id()));
+        let output_dir = root.join("output");
+        std::fs::create_dir_all(&output_dir).unwrap();
+
+        // The mock debugger prints the commands, so dropping `run` changes the output the
+        // failing check is matched against, while the checks after it don't matter
+        let source_path = root.join("main.rs");
+        std::fs::write(
+            &source_path,
+            "/***\nrun\nprint a\n#check a = 1\nprint b\n#check b\n***/\nfn main() {}\n",
+        )
+        .unwrap();
+
+        let mut test_cases = test_cases_with_dependencies(&[]);
+        let test_definition = TestDefinition::new(
+            Path::new("main.rs"),
+            &source_path,
+            "ws",
+            "main".into(),
+            parse_script(&std::fs::read_to_string(&source_path).unwrap(), None).unwrap(),
+            vec![],
+        );
+        Arc::get_mut(&mut test_cases.cargo_workspace)
+            .unwrap()
+            .cargo_packages[0]
+            .test_definitions
+            .push(test_definition);
+
+        let minimized = super::minimize_test(
+            &test_cases,
+            "ws/main.rs",
+            &Debugger::mock(),
+            &output_dir,
+            &super::RunOptions::default(),
+        )
+        .unwrap();
+
+        assert_eq!(minimized, ["run", "print a", "#check a = 1"]);
+
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn parse_artifact_name() {
+        assert_eq!(
+            super::parse_artifact_name("gdb-12.1-live"),
+            Some((DebuggerKind::Gdb, "12.1".into(), PhaseConfig::Live))
+        );
+        assert_eq!(
+            super::parse_artifact_name("cdb-10.0.22621.1-crashdump[first-dump]"),
+            Some((
+                DebuggerKind::Cdb,
+                "10.0.22621.1".into(),
+                PhaseConfig::CrashDump {
+                    tag: "first-dump".into()
+                }
+            ))
+        );
+        assert_eq!(
+            super::parse_artifact_name("cdb-10.0.22621.1-ttd"),
+            Some((DebuggerKind::Cdb, "10.0.22621.1".into(), PhaseConfig::Ttd))
+        );
+        assert_eq!(super::parse_artifact_name("gdb-12.1-later"), None);
+        assert_eq!(super::parse_artifact_name("notes"), None);
+    }
+}
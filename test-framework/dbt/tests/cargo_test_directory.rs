@@ -1,7 +1,8 @@
-use std::{ffi::OsString, path::PathBuf};
+use std::{ffi::OsString, path::PathBuf, sync::Arc};
 
 use dbt::cargo_test_directory::*;
 use dbt::script::Script;
+use dbt::{cargo_profiles, test_config::TestConfig};
 
 fn executable_name(name: &str) -> OsString {
     let mut name: OsString = name.into();
@@ -19,6 +20,10 @@ fn find_cargo_test_directories() {
 
     let cargo_test_directory = CargoWorkspace::load(&root_path).unwrap();
     let root_path = root_path.canonicalize().unwrap();
+    let config = Arc::new(TestConfig {
+        opt_levels: Arc::new(cargo_profiles::opt_levels(&root_path).unwrap()),
+        ..Default::default()
+    });
 
     assert_eq!(
         cargo_test_directory,
@@ -27,24 +32,34 @@ fn find_cargo_test_directories() {
             cargo_packages: vec![
                 CargoPackage {
                     root_path: root_path.join("testcase1"),
+                    name: None,
+                    config: config.clone(),
                     test_definitions: vec![TestDefinition {
                         executable_name: executable_name("testcase1"),
                         name: "cargo-test-discovery-sample/testcase1/src/main.rs".into(),
-                        absolute_source_path: root_path.join("testcase1/src/main.rs").into(),
+                        absolute_source_path: root_path.join("testcase1/src/main.rs"),
                         script: Script::new_empty(),
                         breakpoints: vec![],
+                        params: vec![],
+                        config: config.clone(),
+                        baseline_of: None,
+                        cargo_target: None,
                     }]
                 },
                 CargoPackage {
                     root_path: root_path.join("testcase2"),
+                    name: None,
+                    config: config.clone(),
                     test_definitions: vec![TestDefinition {
                         executable_name: executable_name("some_exe"),
                         name: "cargo-test-discovery-sample/testcase2/src/bin/some_exe.rs".into(),
-                        absolute_source_path: root_path
-                            .join("testcase2/src/bin/some_exe.rs")
-                            .into(),
+                        absolute_source_path: root_path.join("testcase2/src/bin/some_exe.rs"),
                         script: Script::new_empty(),
                         breakpoints: vec![],
+                        params: vec![],
+                        config: config.clone(),
+                        baseline_of: None,
+                        cargo_target: None,
                     }]
                 }
             ],